@@ -0,0 +1,78 @@
+use std::process::Command;
+
+fn main() {
+    // Release builds from a source tarball have no .git; they report "unknown".
+    let git_hash = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_string())
+        .filter(|hash| !hash.is_empty())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=LB_GIT_HASH={}", git_hash);
+    println!(
+        "cargo:rustc-env=LB_BUILD_PROFILE={}",
+        std::env::var("PROFILE").unwrap_or_else(|_| "unknown".to_string())
+    );
+    println!("cargo:rerun-if-changed=../.git/HEAD");
+    println!("cargo:rerun-if-changed=../.git/refs/heads");
+    #[cfg(feature = "grpc")]
+    grpc::generate();
+}
+
+/// Service stubs for `src/grpc.rs`, declared here instead of compiled from
+/// `proto/lazy_blacktea.proto` so the build needs no `protoc`; keep the two in sync.
+#[cfg(feature = "grpc")]
+mod grpc {
+    use tonic_build::manual::{Builder, Method, Service};
+
+    /// `(rust name, proto name, request, response, server streaming)`.
+    type Rpc = (&'static str, &'static str, &'static str, &'static str, bool);
+
+    fn service(name: &str, rpcs: &[Rpc]) -> Service {
+        let mut service = Service::builder().name(name).package("lazy_blacktea.v1");
+        for &(method, route, input, output, streaming) in rpcs {
+            let mut builder = Method::builder()
+                .name(method)
+                .route_name(route)
+                .input_type(format!("crate::grpc::{}", input))
+                .output_type(format!("crate::grpc::{}", output))
+                .codec_path("tonic::codec::ProstCodec");
+            if streaming {
+                builder = builder.server_streaming();
+            }
+            service = service.method(builder.build());
+        }
+        service.build()
+    }
+
+    pub fn generate() {
+        let services = [
+            service(
+                "DeviceService",
+                &[
+                    ("list", "List", "ListDevicesRequest", "ListDevicesResponse", false),
+                    ("track", "Track", "TrackDevicesRequest", "DeviceEvent", true),
+                ],
+            ),
+            service(
+                "CommandService",
+                &[
+                    ("run", "Run", "RunRequest", "RunResponse", false),
+                    ("stream", "Stream", "RunRequest", "OutputLine", true),
+                ],
+            ),
+            service(
+                "MediaService",
+                &[
+                    ("screenshot", "Screenshot", "ScreenshotRequest", "Screenshot", false),
+                    ("start_recording", "StartRecording", "RecordingRequest", "RecordingResponse", false),
+                    ("stop_recording", "StopRecording", "RecordingRequest", "RecordingResponse", false),
+                ],
+            ),
+        ];
+        Builder::new().build_client(false).compile(&services);
+    }
+}
@@ -0,0 +1,249 @@
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use crate::cancel;
+use crate::error::{ErrorCode, LbError};
+use crate::exec::{self, CommandOutput};
+use crate::png;
+use crate::transcript;
+
+const BOOT_POLL_INTERVAL_MS: u64 = 1000;
+const REMOTE_UI_DUMP: &str = "/data/local/tmp/lb_ui_dump.xml";
+
+pub fn adb_argv(serial: Option<&str>, args: &[&str]) -> Vec<String> {
+    let mut argv = vec!["adb".to_string()];
+    if let Some(serial) = serial {
+        argv.push("-s".to_string());
+        argv.push(serial.to_string());
+    }
+    argv.extend(args.iter().map(|arg| arg.to_string()));
+    argv
+}
+
+fn run_argv(serial: Option<&str>, argv: &[String]) -> Result<CommandOutput, LbError> {
+    exec::run_argv(argv).map_err(|err| {
+        let err = err.context("Failed to run adb");
+        match serial {
+            Some(serial) => err.with_serial(serial),
+            None => err,
+        }
+    })
+}
+
+pub fn run_adb(serial: Option<&str>, args: &[&str]) -> Result<CommandOutput, LbError> {
+    run_argv(serial, &adb_argv(serial, args))
+}
+
+/// Maps adb's stderr onto the device-state error codes the host can branch on.
+pub fn classify_failure(detail: &str) -> ErrorCode {
+    let detail = detail.to_ascii_lowercase();
+    if detail.contains("unauthorized") {
+        ErrorCode::DeviceUnauthorized
+    } else if detail.contains("offline")
+        || detail.contains("no devices/emulators")
+        || (detail.contains("device") && detail.contains("not found"))
+    {
+        ErrorCode::DeviceOffline
+    } else if detail.contains("permission denied") || detail.contains("operation not permitted") {
+        ErrorCode::PermissionDenied
+    } else {
+        ErrorCode::CommandFailed
+    }
+}
+
+/// Runs `adb` and returns stdout, turning a non-zero exit into an error carrying stderr.
+pub fn adb_checked(serial: Option<&str>, args: &[&str]) -> Result<String, LbError> {
+    let argv = adb_argv(serial, args);
+    let output = run_argv(serial, &argv)?;
+    if output.success() {
+        return Ok(output.stdout);
+    }
+    let detail = if output.stderr.trim().is_empty() {
+        output.stdout.trim()
+    } else {
+        output.stderr.trim()
+    };
+    let err = LbError::new(
+        classify_failure(detail),
+        format!(
+            "adb {} failed (exit={}): {}",
+            args.first().copied().unwrap_or(""),
+            output.exit_code.unwrap_or(-1),
+            detail
+        ),
+    )
+    .with_command(&argv);
+    Err(match serial {
+        Some(serial) => err.with_serial(serial),
+        None => err,
+    })
+}
+
+/// `adb exec-out <command>` with stdout kept as raw bytes; `exec::run_argv` decodes
+/// output as UTF-8, which corrupts binary data such as screenshots.
+pub fn exec_out(serial: &str, command: &str) -> Result<Vec<u8>, LbError> {
+    let argv = adb_argv(Some(serial), &["exec-out", command]);
+    let started = Instant::now();
+    let output = Command::new(&argv[0])
+        .args(&argv[1..])
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|err| {
+            LbError::new(ErrorCode::SpawnFailed, format!("Failed to spawn {}: {}", argv[0], err))
+                .with_command(&argv)
+                .with_serial(serial)
+        })?;
+    transcript::record_command(&argv, started.elapsed(), output.status.code());
+    if output.status.success() {
+        return Ok(output.stdout);
+    }
+    let detail = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    Err(LbError::new(
+        classify_failure(&detail),
+        format!("adb exec-out {} failed (exit={}): {}", command, output.status.code().unwrap_or(-1), detail),
+    )
+    .with_command(&argv)
+    .with_serial(serial))
+}
+
+/// Full-size screenshot as PNG bytes (`screencap -p`).
+pub fn screencap_png(serial: &str) -> Result<Vec<u8>, LbError> {
+    let data = exec_out(serial, "screencap -p")?;
+    if !data.starts_with(&png::SIGNATURE) {
+        return Err(LbError::parse("screencap did not return a PNG").with_serial(serial));
+    }
+    Ok(data)
+}
+
+/// `uiautomator dump` of the current screen, read back and removed in the same shell.
+pub fn dump_ui_xml(serial: &str) -> Result<Vec<u8>, LbError> {
+    let command = format!("uiautomator dump {0} >/dev/null && cat {0}; rm -f {0}", REMOTE_UI_DUMP);
+    let data = exec_out(serial, &command)?;
+    if !data.windows(10).any(|window| window == b"<hierarchy") {
+        return Err(LbError::parse("uiautomator did not write a dump").with_serial(serial));
+    }
+    Ok(data)
+}
+
+pub fn shell(serial: &str, command: &str) -> Result<String, LbError> {
+    adb_checked(Some(serial), &["shell", command])
+}
+
+/// Quotes one argument for the device shell; plain words pass through unchanged.
+pub fn shell_quote(arg: &str) -> String {
+    let is_safe = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || "-_./:=@%+,".contains(ch));
+    if is_safe {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+pub fn getprop(serial: &str, name: &str) -> Result<String, LbError> {
+    shell(serial, &format!("getprop {}", name)).map(|value| value.trim().to_string())
+}
+
+pub fn get_state(serial: &str) -> Option<String> {
+    run_adb(Some(serial), &["get-state"])
+        .ok()
+        .filter(|output| output.success())
+        .map(|output| output.stdout.trim().to_string())
+}
+
+/// Wireless devices are addressed as `host:port`; emulators use `emulator-NNNN`.
+pub fn is_network_serial(serial: &str) -> bool {
+    serial.contains(':') && !serial.starts_with("emulator-")
+}
+
+/// Polls until the device reports `sys.boot_completed=1` or the timeout elapses.
+pub fn wait_for_boot_completed(serial: &str, timeout: Duration) -> Result<Duration, LbError> {
+    let started = Instant::now();
+    loop {
+        if get_state(serial).as_deref() == Some("device") && getprop(serial, "sys.boot_completed").as_deref() == Ok("1")
+        {
+            return Ok(started.elapsed());
+        }
+        if started.elapsed() >= timeout {
+            return Err(LbError::new(
+                ErrorCode::Timeout,
+                format!("Timed out after {}s waiting for {} to boot", timeout.as_secs(), serial),
+            )
+            .with_serial(serial));
+        }
+        cancel::sleep(Duration::from_millis(BOOT_POLL_INTERVAL_MS))?;
+    }
+}
+
+/// The `mCurrentFocus`/`mFocusedApp` lines of `dumpsys window`: the window and app in front.
+pub fn focused_window(serial: &str) -> Result<String, LbError> {
+    shell(serial, "dumpsys window | grep -E 'mCurrentFocus|mFocusedApp'; true")
+}
+
+/// One `adb devices -l` line: `serial`, its state, and the `key:value` details that follow
+/// (`product`, `model`, `device`, `transport_id`, `usb`).
+pub struct ListedDevice {
+    pub serial: String,
+    pub state: String,
+    pub details: Vec<(String, String)>,
+}
+
+/// Devices adb knows about, in any state. States can be several words (`no permissions
+/// (...); see [...]`), so everything before the first `key:value` detail is the state.
+pub fn list_devices() -> Result<Vec<ListedDevice>, LbError> {
+    let output = adb_checked(None, &["devices", "-l"])?;
+    let is_detail = |token: &str| {
+        token.split_once(':').is_some_and(|(key, value)| {
+            !key.is_empty() && !value.is_empty() && key.bytes().all(|byte| byte.is_ascii_lowercase() || byte == b'_')
+        })
+    };
+    Ok(output
+        .lines()
+        // The daemon's `* daemon started successfully` chatter comes before the header.
+        .skip_while(|line| !line.starts_with("List of devices"))
+        .skip(1)
+        .filter_map(|line| {
+            let mut tokens = line.split_whitespace();
+            let serial = tokens.next()?.to_string();
+            let tokens: Vec<&str> = tokens.collect();
+            let split = tokens.iter().position(|token| is_detail(token)).unwrap_or(tokens.len());
+            let details = tokens[split..]
+                .iter()
+                .filter_map(|token| token.split_once(':'))
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect();
+            Some(ListedDevice { serial, state: tokens[..split].join(" "), details })
+        })
+        .collect())
+}
+
+/// Fails unless adbd runs as root (`adb root`), which writes under `/sys` and `/proc` require.
+pub fn require_root(serial: &str) -> Result<(), LbError> {
+    match shell(serial, "id -u") {
+        Ok(uid) if uid.trim() == "0" => Ok(()),
+        Ok(_) => Err(LbError::new(
+            ErrorCode::PermissionDenied,
+            format!("{} requires root; run `adb root` first", serial),
+        )
+        .with_serial(serial)),
+        Err(err) => Err(err),
+    }
+}
+
+/// Reads `settings get <namespace> <key>`; an unset key comes back as `None`.
+pub fn get_setting(serial: &str, namespace: &str, key: &str) -> Result<Option<String>, LbError> {
+    let value = shell(serial, &format!("settings get {} {}", namespace, key))?;
+    let value = value.trim();
+    Ok(if value == "null" { None } else { Some(value.to_string()) })
+}
+
+/// Writes a setting, or deletes it when `value` is `None` so restores return keys to "unset".
+pub fn put_setting(serial: &str, namespace: &str, key: &str, value: Option<&str>) -> Result<(), LbError> {
+    let command = match value {
+        Some(value) => format!("settings put {} {} {}", namespace, key, shell_quote(value)),
+        None => format!("settings delete {} {}", namespace, key),
+    };
+    shell(serial, &command).map(|_| ())
+}
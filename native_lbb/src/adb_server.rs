@@ -0,0 +1,129 @@
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpStream};
+use std::os::raw::c_char;
+use std::time::Duration;
+
+use crate::adb;
+use crate::error::{ErrorCode, LbError};
+use crate::json::JsonValue;
+use crate::logging::{lb_log, Level};
+use crate::{ffi_guard, status_result, string_result};
+
+const DEFAULT_SERVER_PORT: u16 = 5037;
+const SERVER_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// `ANDROID_ADB_SERVER_PORT` as the adb client reads it, else 5037.
+pub fn server_port() -> u16 {
+    std::env::var("ANDROID_ADB_SERVER_PORT")
+        .ok()
+        .and_then(|port| port.trim().parse().ok())
+        .unwrap_or(DEFAULT_SERVER_PORT)
+}
+
+/// Asks a running server for its protocol version over the smart-socket protocol
+/// (`host:version` -> `OKAY` + 4 hex length + 4 hex version). `None` if nothing answers.
+fn query_server_version(port: u16) -> Option<u32> {
+    let address = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    let mut stream = TcpStream::connect_timeout(&address, SERVER_TIMEOUT).ok()?;
+    stream.set_read_timeout(Some(SERVER_TIMEOUT)).ok()?;
+    stream.set_write_timeout(Some(SERVER_TIMEOUT)).ok()?;
+    let request = "host:version";
+    stream
+        .write_all(format!("{:04x}{}", request.len(), request).as_bytes())
+        .ok()?;
+    let mut reply = [0u8; 12];
+    stream.read_exact(&mut reply).ok()?;
+    let reply = std::str::from_utf8(&reply).ok()?;
+    if !reply.starts_with("OKAY") {
+        return None;
+    }
+    u32::from_str_radix(&reply[8..12], 16).ok()
+}
+
+struct ClientVersion {
+    protocol: Option<u32>,
+    release: Option<String>,
+    path: Option<String>,
+}
+
+/// `Android Debug Bridge version 1.0.41` / `Version 34.0.4-10411341` / `Installed as /path/adb`.
+fn parse_client_version(output: &str) -> ClientVersion {
+    let field = |prefix: &str| {
+        output
+            .lines()
+            .find_map(|line| line.trim().strip_prefix(prefix))
+            .map(|value| value.trim().to_string())
+    };
+    let protocol = field("Android Debug Bridge version ")
+        .and_then(|version| version.rsplit('.').next().and_then(|minor| minor.parse().ok()));
+    ClientVersion {
+        protocol,
+        release: field("Version "),
+        path: field("Installed as "),
+    }
+}
+
+fn server_status() -> Result<JsonValue, LbError> {
+    let port = server_port();
+    let client = parse_client_version(&adb::adb_checked(None, &["version"])?);
+    let server_version = query_server_version(port);
+    let mismatch = matches!((client.protocol, server_version), (Some(client), Some(server)) if client != server);
+    Ok(JsonValue::object(vec![
+        ("running", server_version.is_some().into()),
+        ("port", u32::from(port).into()),
+        ("server_version", server_version.into()),
+        ("client_version", client.protocol.into()),
+        ("client_release", client.release.into()),
+        ("client_path", client.path.into()),
+        ("version_mismatch", mismatch.into()),
+    ]))
+}
+
+fn start_server() -> Result<JsonValue, LbError> {
+    let before = server_status()?;
+    if before.get("version_mismatch").and_then(JsonValue::as_bool) == Some(true) {
+        // Another SDK's server answers on our port; every client call would restart it.
+        lb_log!(Level::Warn, "adb", "adb server version differs from client; restarting it");
+        kill_server()?;
+    }
+    adb::adb_checked(None, &["start-server"])?;
+    let after = server_status()?;
+    if after.get("running").and_then(JsonValue::as_bool) != Some(true) {
+        return Err(LbError::new(
+            ErrorCode::CommandFailed,
+            format!("adb start-server returned but nothing answers on port {}", server_port()),
+        ));
+    }
+    Ok(after)
+}
+
+fn kill_server() -> Result<(), LbError> {
+    if query_server_version(server_port()).is_none() {
+        return Ok(());
+    }
+    adb::adb_checked(None, &["kill-server"]).map(|_| ())
+}
+
+/// Returns `{"running", "port", "server_version", "client_version", "client_release",
+/// "client_path", "version_mismatch"}` without starting a server.
+#[no_mangle]
+pub extern "C" fn lb_adb_server_status() -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        string_result(server_status().map(|status| status.to_string()), "adb server status")
+    })
+}
+
+/// Starts the adb server, first killing one whose version does not match the client,
+/// and returns the resulting status JSON.
+#[no_mangle]
+pub extern "C" fn lb_adb_start_server() -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        string_result(start_server().map(|status| status.to_string()), "adb server status")
+    })
+}
+
+/// Stops the adb server; succeeds if none is running.
+#[no_mangle]
+pub extern "C" fn lb_adb_kill_server() -> i32 {
+    ffi_guard(0, || status_result(kill_server()))
+}
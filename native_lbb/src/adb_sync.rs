@@ -0,0 +1,430 @@
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpStream};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::adb;
+use crate::adb_server;
+use crate::error::{ErrorCode, LbError};
+use crate::logging::{lb_log, Level};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Generous because a device can stall mid-transfer while it flushes storage.
+const IO_TIMEOUT: Duration = Duration::from_secs(60);
+/// Largest `DATA` chunk the sync protocol allows.
+const MAX_CHUNK_BYTES: usize = 64 * 1024;
+
+/// `STAT` / `LST2` reply. Sizes are 32-bit in sync v1, so on a device without `stat_v2`
+/// files past 4 GiB report a wrapped size.
+pub struct RemoteStat {
+    pub mode: u32,
+    pub size: u64,
+    /// Seconds since the epoch.
+    pub mtime: u64,
+}
+
+/// One `DENT` / `DNT2` record from `LIST` / `LIS2`; without `ls_v2`, `stat` has the same
+/// v1 limits as `STAT`.
+pub struct RemoteEntry {
+    pub name: String,
+    pub stat: RemoteStat,
+}
+
+impl RemoteStat {
+    pub fn exists(&self) -> bool {
+        self.mode != 0
+    }
+
+    /// The `sync_stat_v2` fields after the id: error, dev, ino, mode, nlink, uid, gid, size,
+    /// atime, mtime, ctime. A failed lstat (`error` is the errno) reads like a v1 miss.
+    fn from_v2(fields: &[u8]) -> RemoteStat {
+        let u32_at = |offset: usize| u32::from_le_bytes(fields[offset..offset + 4].try_into().unwrap_or_default());
+        let u64_at = |offset: usize| u64::from_le_bytes(fields[offset..offset + 8].try_into().unwrap_or_default());
+        if u32_at(0) != 0 {
+            return RemoteStat { mode: 0, size: 0, mtime: 0 };
+        }
+        RemoteStat {
+            mode: u32_at(20),
+            size: u64_at(36),
+            mtime: (u64_at(52) as i64).max(0) as u64,
+        }
+    }
+}
+
+/// `sync_stat_v2` without its id.
+const STAT_V2_BYTES: usize = 68;
+
+/// One `sync:` session with a device over the adb server's smart socket. Requests are
+/// `ID + u32le length + payload`; the connection stays usable across requests.
+pub struct SyncConnection {
+    serial: String,
+    stream: TcpStream,
+    /// `stat_v2`: `LST2` replaces `STAT`, with 64-bit sizes and times.
+    stat_v2: bool,
+    /// `ls_v2`: `LIS2` replaces `LIST`.
+    ls_v2: bool,
+}
+
+fn connect_server() -> Result<TcpStream, LbError> {
+    let address = SocketAddr::from((Ipv4Addr::LOCALHOST, adb_server::server_port()));
+    let stream = match TcpStream::connect_timeout(&address, CONNECT_TIMEOUT) {
+        Ok(stream) => stream,
+        Err(_) => {
+            // The adb client starts the server on demand; do the same before giving up.
+            lb_log!(Level::Debug, "sync", "No adb server on {}; starting one", address);
+            adb::adb_checked(None, &["start-server"])?;
+            TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)
+                .map_err(|err| LbError::io(format!("Failed to connect to adb server at {}: {}", address, err)))?
+        }
+    };
+    stream
+        .set_read_timeout(Some(IO_TIMEOUT))
+        .and_then(|_| stream.set_write_timeout(Some(IO_TIMEOUT)))
+        .map_err(|err| LbError::io(format!("Failed to configure adb server socket: {}", err)))?;
+    Ok(stream)
+}
+
+/// The transport features adbd advertised (`shell_v2,cmd,stat_v2,...`). Asked on a connection
+/// of its own because the server closes it after replying; empty when the query fails, which
+/// leaves the sync connection on v1.
+fn device_features(serial: &str) -> Vec<String> {
+    let query = || -> Result<Vec<String>, LbError> {
+        let mut connection = SyncConnection {
+            serial: serial.to_string(),
+            stream: connect_server()?,
+            stat_v2: false,
+            ls_v2: false,
+        };
+        connection.host_request(&format!("host-serial:{}:features", serial))?;
+        let features = connection.read_hex_payload("features")?;
+        Ok(features.trim().split(',').map(str::to_string).collect())
+    };
+    query().unwrap_or_else(|err| {
+        lb_log!(Level::Debug, "sync", "No feature list for {}: {}", serial, err.message);
+        Vec::new()
+    })
+}
+
+impl SyncConnection {
+    /// Switches a fresh server connection to `serial`'s transport and enters sync mode.
+    pub fn open(serial: &str) -> Result<SyncConnection, LbError> {
+        let features = device_features(serial);
+        let mut connection = SyncConnection {
+            serial: serial.to_string(),
+            stream: connect_server()?,
+            stat_v2: features.iter().any(|feature| feature == "stat_v2"),
+            ls_v2: features.iter().any(|feature| feature == "ls_v2"),
+        };
+        connection.host_request(&format!("host:transport:{}", serial))?;
+        connection.host_request("sync:")?;
+        Ok(connection)
+    }
+
+    fn io_error(&self, action: &str, err: std::io::Error) -> LbError {
+        let code = if err.kind() == std::io::ErrorKind::WouldBlock || err.kind() == std::io::ErrorKind::TimedOut {
+            ErrorCode::Timeout
+        } else {
+            ErrorCode::DeviceOffline
+        };
+        LbError::new(code, format!("Sync {} failed on {}: {}", action, self.serial, err)).with_serial(&self.serial)
+    }
+
+    fn read_exact(&mut self, buffer: &mut [u8], action: &str) -> Result<(), LbError> {
+        self.stream.read_exact(buffer).map_err(|err| self.io_error(action, err))
+    }
+
+    fn read_u32(&mut self, action: &str) -> Result<u32, LbError> {
+        let mut bytes = [0u8; 4];
+        self.read_exact(&mut bytes, action)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn write_all(&mut self, bytes: &[u8], action: &str) -> Result<(), LbError> {
+        self.stream.write_all(bytes).map_err(|err| self.io_error(action, err))
+    }
+
+    /// Smart-socket request: 4 hex digits of length, the request, then `OKAY` or
+    /// `FAIL` + hex length + message.
+    fn host_request(&mut self, request: &str) -> Result<(), LbError> {
+        self.write_all(format!("{:04x}{}", request.len(), request).as_bytes(), request)?;
+        let mut status = [0u8; 4];
+        self.read_exact(&mut status, request)?;
+        if &status == b"OKAY" {
+            return Ok(());
+        }
+        let message = self.read_hex_payload(request)?;
+        Err(LbError::new(adb::classify_failure(&message), format!("adb server refused {}: {}", request, message))
+            .with_serial(&self.serial))
+    }
+
+    /// 4 hex digits of length, then that many bytes.
+    fn read_hex_payload(&mut self, action: &str) -> Result<String, LbError> {
+        let mut length = [0u8; 4];
+        self.read_exact(&mut length, action)?;
+        let length = std::str::from_utf8(&length)
+            .ok()
+            .and_then(|hex| usize::from_str_radix(hex, 16).ok())
+            .unwrap_or(0);
+        let mut payload = vec![0u8; length];
+        self.read_exact(&mut payload, action)?;
+        Ok(String::from_utf8_lossy(&payload).into_owned())
+    }
+
+    fn send_request(&mut self, id: &[u8; 4], path: &str) -> Result<(), LbError> {
+        let mut request = Vec::with_capacity(8 + path.len());
+        request.extend_from_slice(id);
+        request.extend_from_slice(&(path.len() as u32).to_le_bytes());
+        request.extend_from_slice(path.as_bytes());
+        self.write_all(&request, path)
+    }
+
+    /// Reads the message of a `FAIL` reply, whose header has already been consumed.
+    fn failure(&mut self, path: &str) -> LbError {
+        let message = self.read_u32(path).and_then(|length| {
+            let mut message = vec![0u8; length as usize];
+            self.read_exact(&mut message, path).map(|_| message)
+        });
+        let message = message.map(|bytes| String::from_utf8_lossy(&bytes).into_owned()).unwrap_or_default();
+        let code = if message.contains("No such file") {
+            ErrorCode::NotFound
+        } else if message.contains("Permission denied") {
+            ErrorCode::PermissionDenied
+        } else {
+            ErrorCode::CommandFailed
+        };
+        LbError::new(code, format!("{}: {}", path, message)).with_serial(&self.serial)
+    }
+
+    /// lstat of `path`: a symlink reports itself, not its target.
+    pub fn stat(&mut self, path: &str) -> Result<RemoteStat, LbError> {
+        if self.stat_v2 {
+            self.send_request(b"LST2", path)?;
+            let mut reply = [0u8; 4 + STAT_V2_BYTES];
+            self.read_exact(&mut reply, path)?;
+            if &reply[..4] != b"LST2" {
+                return Err(LbError::new(ErrorCode::CommandFailed, format!("Unexpected sync reply to LST2 {}", path)));
+            }
+            return Ok(RemoteStat::from_v2(&reply[4..]));
+        }
+        self.send_request(b"STAT", path)?;
+        let mut reply = [0u8; 16];
+        self.read_exact(&mut reply, path)?;
+        if &reply[..4] != b"STAT" {
+            return Err(LbError::new(ErrorCode::CommandFailed, format!("Unexpected sync reply to STAT {}", path)));
+        }
+        let field = |offset: usize| u32::from_le_bytes([reply[offset], reply[offset + 1], reply[offset + 2], reply[offset + 3]]);
+        Ok(RemoteStat {
+            mode: field(4),
+            size: u64::from(field(8)),
+            mtime: u64::from(field(12)),
+        })
+    }
+
+    /// Lists a directory, `.` and `..` included. Unreadable or missing directories come
+    /// back empty rather than failing; that is how adbd reports them.
+    pub fn list(&mut self, path: &str) -> Result<Vec<RemoteEntry>, LbError> {
+        if self.ls_v2 {
+            return self.list_v2(path);
+        }
+        self.send_request(b"LIST", path)?;
+        let mut entries = Vec::new();
+        loop {
+            // `DENT` / `DONE`, mode, size, mtime, name length.
+            let mut header = [0u8; 20];
+            self.read_exact(&mut header, path)?;
+            let field = |offset: usize| {
+                u32::from_le_bytes([header[offset], header[offset + 1], header[offset + 2], header[offset + 3]])
+            };
+            match &header[..4] {
+                b"DENT" => {
+                    let mut name = vec![0u8; field(16) as usize];
+                    self.read_exact(&mut name, path)?;
+                    entries.push(RemoteEntry {
+                        name: String::from_utf8_lossy(&name).into_owned(),
+                        stat: RemoteStat {
+                            mode: field(4),
+                            size: u64::from(field(8)),
+                            mtime: u64::from(field(12)),
+                        },
+                    });
+                }
+                b"DONE" => return Ok(entries),
+                _ => return Err(LbError::new(ErrorCode::CommandFailed, format!("Unexpected sync reply to LIST {}", path))),
+            }
+        }
+    }
+
+    /// `LIS2`: `DNT2` records are the id, the rest of a `sync_stat_v2` and the name length;
+    /// the closing `DONE` has the same size.
+    fn list_v2(&mut self, path: &str) -> Result<Vec<RemoteEntry>, LbError> {
+        self.send_request(b"LIS2", path)?;
+        let mut entries = Vec::new();
+        loop {
+            let mut header = [0u8; 4 + STAT_V2_BYTES + 4];
+            self.read_exact(&mut header, path)?;
+            match &header[..4] {
+                b"DNT2" => {
+                    let name_length = u32::from_le_bytes(header[4 + STAT_V2_BYTES..].try_into().unwrap_or_default());
+                    let mut name = vec![0u8; name_length as usize];
+                    self.read_exact(&mut name, path)?;
+                    // adbd sends entries it could not lstat with their errno; v1 skipped them.
+                    if header[4..8] != [0u8; 4] {
+                        continue;
+                    }
+                    entries.push(RemoteEntry {
+                        name: String::from_utf8_lossy(&name).into_owned(),
+                        stat: RemoteStat::from_v2(&header[4..4 + STAT_V2_BYTES]),
+                    });
+                }
+                b"DONE" => return Ok(entries),
+                _ => return Err(LbError::new(ErrorCode::CommandFailed, format!("Unexpected sync reply to LIS2 {}", path))),
+            }
+        }
+    }
+
+    /// Streams `remote` into `writer`, calling `on_progress(bytes_so_far)` after each
+    /// chunk. Returns the number of bytes received.
+    pub fn recv(&mut self, remote: &str, writer: &mut impl Write, mut on_progress: impl FnMut(u64)) -> Result<u64, LbError> {
+        self.send_request(b"RECV", remote)?;
+        let mut received = 0u64;
+        let mut chunk = vec![0u8; MAX_CHUNK_BYTES];
+        loop {
+            let mut id = [0u8; 4];
+            self.read_exact(&mut id, remote)?;
+            match &id {
+                b"DATA" => {
+                    let length = self.read_u32(remote)? as usize;
+                    if length > MAX_CHUNK_BYTES {
+                        return Err(LbError::new(ErrorCode::CommandFailed, format!("Oversized sync chunk for {}", remote)));
+                    }
+                    self.read_exact(&mut chunk[..length], remote)?;
+                    writer
+                        .write_all(&chunk[..length])
+                        .map_err(|err| LbError::io(format!("Failed to write local copy of {}: {}", remote, err)))?;
+                    received += length as u64;
+                    on_progress(received);
+                }
+                b"DONE" => {
+                    self.read_u32(remote)?;
+                    return Ok(received);
+                }
+                b"FAIL" => return Err(self.failure(remote)),
+                _ => return Err(LbError::new(ErrorCode::CommandFailed, format!("Unexpected sync reply to RECV {}", remote))),
+            }
+        }
+    }
+
+    /// Pulls `remote` to `local` through `<local>.part`, so an interrupted transfer never
+    /// leaves a truncated file under the final name, and gives it the remote mtime.
+    /// `on_progress(bytes, total)` follows each chunk. Returns the number of bytes written.
+    pub fn pull(&mut self, remote: &str, local: &Path, mut on_progress: impl FnMut(u64, u64)) -> Result<u64, LbError> {
+        let stat = self.stat(remote)?;
+        if !stat.exists() {
+            return Err(LbError::not_found(format!("{} does not exist on {}", remote, self.serial)).with_serial(&self.serial));
+        }
+        let mut partial = local.as_os_str().to_owned();
+        partial.push(".part");
+        let partial = Path::new(&partial);
+        let file = File::create(partial).map_err(|err| LbError::io(format!("Failed to create {}: {}", partial.display(), err)))?;
+        let mut writer = BufWriter::new(file);
+        let finished = self.recv(remote, &mut writer, |bytes| on_progress(bytes, stat.size)).and_then(|received| {
+            writer
+                .into_inner()
+                .map_err(|err| err.into_error())
+                .and_then(|file| {
+                    file.sync_all()?;
+                    file.set_modified(UNIX_EPOCH + Duration::from_secs(stat.mtime))
+                })
+                .and_then(|_| fs::rename(partial, local))
+                .map(|_| received)
+                .map_err(|err| LbError::io(format!("Failed to save {}: {}", local.display(), err)))
+        });
+        if finished.is_err() {
+            let _ = fs::remove_file(partial);
+        }
+        finished
+    }
+
+    /// Streams `reader` to `remote` as `SEND path,mode` + `DATA` chunks + `DONE mtime`.
+    /// adbd creates missing parent directories and only replaces the file once `DONE`
+    /// arrives. Calls `on_progress(bytes_so_far)` after each chunk; returns bytes sent.
+    pub fn send(
+        &mut self,
+        remote: &str,
+        mode: u32,
+        mtime: u64,
+        reader: &mut impl Read,
+        mut on_progress: impl FnMut(u64),
+    ) -> Result<u64, LbError> {
+        self.send_request(b"SEND", &format!("{},{}", remote, mode))?;
+        let mut sent = 0u64;
+        let mut chunk = vec![0u8; 8 + MAX_CHUNK_BYTES];
+        chunk[..4].copy_from_slice(b"DATA");
+        loop {
+            let length = reader
+                .read(&mut chunk[8..])
+                .map_err(|err| LbError::io(format!("Failed to read local source of {}: {}", remote, err)))?;
+            if length == 0 {
+                break;
+            }
+            chunk[4..8].copy_from_slice(&(length as u32).to_le_bytes());
+            self.write_all(&chunk[..8 + length], remote)?;
+            sent += length as u64;
+            on_progress(sent);
+        }
+        let mut done = Vec::with_capacity(8);
+        done.extend_from_slice(b"DONE");
+        done.extend_from_slice(&(mtime as u32).to_le_bytes());
+        self.write_all(&done, remote)?;
+        let mut status = [0u8; 4];
+        self.read_exact(&mut status, remote)?;
+        match &status {
+            b"OKAY" => {
+                self.read_u32(remote)?;
+                Ok(sent)
+            }
+            b"FAIL" => Err(self.failure(remote)),
+            _ => Err(LbError::new(ErrorCode::CommandFailed, format!("Unexpected sync reply to SEND {}", remote))),
+        }
+    }
+
+    /// Pushes `local` to `remote`, keeping its permission bits and mtime.
+    /// `on_progress(bytes, total)` follows each chunk. Returns the number of bytes sent.
+    pub fn push(&mut self, local: &Path, remote: &str, mut on_progress: impl FnMut(u64, u64)) -> Result<u64, LbError> {
+        let file = File::open(local).map_err(|err| LbError::io(format!("Failed to open {}: {}", local.display(), err)))?;
+        let metadata = file
+            .metadata()
+            .map_err(|err| LbError::io(format!("Failed to stat {}: {}", local.display(), err)))?;
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default())
+            .as_secs();
+        let total = metadata.len();
+        let mut reader = BufReader::new(file);
+        self.send(remote, local_mode(&metadata), mtime, &mut reader, |bytes| on_progress(bytes, total))
+    }
+}
+
+#[cfg(unix)]
+fn local_mode(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    0o100000 | (metadata.permissions().mode() & 0o777)
+}
+
+#[cfg(not(unix))]
+fn local_mode(metadata: &fs::Metadata) -> u32 {
+    if metadata.permissions().readonly() {
+        0o100444
+    } else {
+        0o100644
+    }
+}
+
+impl Drop for SyncConnection {
+    fn drop(&mut self) {
+        let _ = self.stream.write_all(b"QUIT\0\0\0\0");
+    }
+}
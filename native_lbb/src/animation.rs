@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::png::{self, write_chunk};
+
+/// Widest GIF frame; screenshots are box-filtered down by an integer factor to fit.
+const GIF_MAX_WIDTH: u32 = 480;
+/// Browsers replace GIF delays under 20 ms with 100 ms, which would slow playback down.
+const GIF_MIN_DELAY_CS: u64 = 2;
+/// Levels per channel of the fixed GIF palette (6 * 7 * 6 = 252 colors).
+const PALETTE_LEVELS: [u32; 3] = [6, 7, 6];
+const BAYER_4X4: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// An RGBA image, e.g. a frame read from `screencap`.
+pub struct RawFrame {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+/// Packs LZW codes least-significant bit first, as GIF expects.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    pending: u32,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, width: u32) {
+        self.pending |= u32::from(code) << self.bits;
+        self.bits += width;
+        while self.bits >= 8 {
+            self.bytes.push(self.pending as u8);
+            self.pending >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.bytes.push(self.pending as u8);
+        }
+        self.bytes
+    }
+}
+
+/// GIF LZW with 8-bit minimum code size. The decoder adds each table entry one code
+/// later than the encoder, so the width for the next code follows `next - 1`.
+fn lzw_encode(indices: &[u8]) -> Vec<u8> {
+    const CLEAR: u16 = 256;
+    const END: u16 = 257;
+    const FIRST_FREE: u16 = 258;
+    const TABLE_SIZE: u16 = 4096;
+    let width = |next: u16| match next - 1 {
+        0..=511 => 9,
+        512..=1023 => 10,
+        1024..=2047 => 11,
+        _ => 12,
+    };
+    let mut writer = BitWriter::default();
+    let mut table: HashMap<u32, u16> = HashMap::new();
+    let mut next = FIRST_FREE;
+    writer.write(CLEAR, width(next));
+    let Some((&first, rest)) = indices.split_first() else {
+        writer.write(END, width(next));
+        return writer.finish();
+    };
+    let mut prefix = u16::from(first);
+    for &index in rest {
+        let key = (u32::from(prefix) << 8) | u32::from(index);
+        if let Some(&code) = table.get(&key) {
+            prefix = code;
+            continue;
+        }
+        writer.write(prefix, width(next));
+        table.insert(key, next);
+        next += 1;
+        if next == TABLE_SIZE {
+            writer.write(CLEAR, width(next));
+            table.clear();
+            next = FIRST_FREE;
+        }
+        prefix = u16::from(index);
+    }
+    writer.write(prefix, width(next));
+    writer.write(END, width(next));
+    writer.finish()
+}
+
+fn palette() -> Vec<u8> {
+    let [red_levels, green_levels, blue_levels] = PALETTE_LEVELS;
+    let mut colors = Vec::with_capacity(256 * 3);
+    for red in 0..red_levels {
+        for green in 0..green_levels {
+            for blue in 0..blue_levels {
+                colors.push((red * 255 / (red_levels - 1)) as u8);
+                colors.push((green * 255 / (green_levels - 1)) as u8);
+                colors.push((blue * 255 / (blue_levels - 1)) as u8);
+            }
+        }
+    }
+    colors.resize(256 * 3, 0);
+    colors
+}
+
+/// Ordered-dither one channel value onto `levels` evenly spaced steps.
+fn dither(value: u32, levels: u32, threshold: f32) -> u32 {
+    let scaled = value as f32 / 255.0 * (levels - 1) as f32 + threshold;
+    (scaled.floor() as u32).min(levels - 1)
+}
+
+/// Streams a looping GIF89a. The size is fixed by the first frame; later frames of a
+/// different size (the device rotated) are skipped.
+pub struct GifWriter {
+    out: BufWriter<File>,
+    source: (u32, u32),
+    step: u32,
+    width: u32,
+    height: u32,
+}
+
+impl GifWriter {
+    pub fn create(path: &Path, first: &RawFrame) -> io::Result<GifWriter> {
+        let step = first.width.div_ceil(GIF_MAX_WIDTH).max(1);
+        let (width, height) = ((first.width / step).max(1), (first.height / step).max(1));
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(b"GIF89a")?;
+        out.write_all(&(width as u16).to_le_bytes())?;
+        out.write_all(&(height as u16).to_le_bytes())?;
+        // Global 256-entry color table, 8 bits per primary.
+        out.write_all(&[0xf7, 0, 0])?;
+        out.write_all(&palette())?;
+        // NETSCAPE2.0 application extension: loop forever.
+        out.write_all(&[0x21, 0xff, 0x0b])?;
+        out.write_all(b"NETSCAPE2.0")?;
+        out.write_all(&[0x03, 0x01, 0x00, 0x00, 0x00])?;
+        Ok(GifWriter {
+            out,
+            source: (first.width, first.height),
+            step,
+            width,
+            height,
+        })
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Returns false when the frame was skipped because its size changed.
+    pub fn frame(&mut self, frame: &RawFrame, delay_ms: u64) -> io::Result<bool> {
+        if (frame.width, frame.height) != self.source {
+            return Ok(false);
+        }
+        let [red_levels, green_levels, blue_levels] = PALETTE_LEVELS;
+        let samples = self.step * self.step;
+        let mut indices = Vec::with_capacity((self.width * self.height) as usize);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let mut sum = [0u32; 3];
+                for dy in 0..self.step {
+                    let row = ((y * self.step + dy) * frame.width) as usize;
+                    for dx in 0..self.step {
+                        let offset = (row + (x * self.step + dx) as usize) * 4;
+                        for (channel, total) in sum.iter_mut().enumerate() {
+                            *total += u32::from(frame.rgba[offset + channel]);
+                        }
+                    }
+                }
+                let threshold = (f32::from(BAYER_4X4[(y % 4) as usize][(x % 4) as usize]) + 0.5) / 16.0;
+                let red = dither(sum[0] / samples, red_levels, threshold);
+                let green = dither(sum[1] / samples, green_levels, threshold);
+                let blue = dither(sum[2] / samples, blue_levels, threshold);
+                indices.push((red * green_levels * blue_levels + green * blue_levels + blue) as u8);
+            }
+        }
+        let delay = (delay_ms.div_ceil(10)).clamp(GIF_MIN_DELAY_CS, u64::from(u16::MAX)) as u16;
+        // Graphic control extension: no disposal, no transparency.
+        self.out.write_all(&[0x21, 0xf9, 0x04, 0x04])?;
+        self.out.write_all(&delay.to_le_bytes())?;
+        self.out.write_all(&[0x00, 0x00])?;
+        // Image descriptor covering the whole canvas, no local color table.
+        self.out.write_all(&[0x2c, 0, 0, 0, 0])?;
+        self.out.write_all(&(self.width as u16).to_le_bytes())?;
+        self.out.write_all(&(self.height as u16).to_le_bytes())?;
+        self.out.write_all(&[0x00, 0x08])?;
+        for block in lzw_encode(&indices).chunks(255) {
+            self.out.write_all(&[block.len() as u8])?;
+            self.out.write_all(block)?;
+        }
+        self.out.write_all(&[0x00])?;
+        Ok(true)
+    }
+
+    /// Writes the trailer and returns the file size.
+    pub fn finish(mut self) -> io::Result<u64> {
+        self.out.write_all(&[0x3b])?;
+        let mut file = self.out.into_inner().map_err(|err| err.into_error())?;
+        file.flush()?;
+        file.stream_position()
+    }
+}
+
+fn animation_control(frames: u32) -> Vec<u8> {
+    let mut control = frames.to_be_bytes().to_vec();
+    // num_plays 0: loop forever.
+    control.extend_from_slice(&0u32.to_be_bytes());
+    control
+}
+
+/// Streams an APNG by re-wrapping each screenshot's compressed `IDAT` stream as frame
+/// data, so nothing is decoded or re-compressed. The frame count in `acTL` is patched
+/// in by `finish`.
+pub struct ApngWriter {
+    out: BufWriter<File>,
+    header: Vec<u8>,
+    frames: u32,
+    sequence: u32,
+}
+
+/// Signature + IHDR chunk (8 + 12 + 13 bytes) precede `acTL`.
+const ACTL_OFFSET: u64 = 33;
+
+impl ApngWriter {
+    /// Fails with InvalidData when `first` is not a PNG.
+    pub fn create(path: &Path, first: &[u8]) -> io::Result<ApngWriter> {
+        let not_png = || io::Error::new(io::ErrorKind::InvalidData, "screencap did not return a PNG");
+        let (header, _) = png::parts(first).ok_or_else(not_png)?;
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(&png::SIGNATURE)?;
+        write_chunk(&mut out, b"IHDR", &header)?;
+        write_chunk(&mut out, b"acTL", &animation_control(0))?;
+        Ok(ApngWriter {
+            out,
+            header,
+            frames: 0,
+            sequence: 0,
+        })
+    }
+
+    /// Width and height from the first frame's `IHDR`.
+    pub fn size(&self) -> (u32, u32) {
+        let field = |offset: usize| u32::from_be_bytes([
+            self.header[offset],
+            self.header[offset + 1],
+            self.header[offset + 2],
+            self.header[offset + 3],
+        ]);
+        (field(0), field(4))
+    }
+
+    /// Returns false when the frame was skipped because it is not a PNG with the same
+    /// size and pixel format as the first one.
+    pub fn frame(&mut self, png: &[u8], delay_ms: u64) -> io::Result<bool> {
+        let Some((header, data)) = png::parts(png).filter(|(header, _)| *header == self.header) else {
+            return Ok(false);
+        };
+        let mut control = self.sequence.to_be_bytes().to_vec();
+        control.extend_from_slice(&header[..8]);
+        control.extend_from_slice(&[0; 8]);
+        control.extend_from_slice(&(delay_ms.min(u64::from(u16::MAX)) as u16).to_be_bytes());
+        control.extend_from_slice(&1000u16.to_be_bytes());
+        // dispose_op NONE, blend_op SOURCE.
+        control.extend_from_slice(&[0, 0]);
+        write_chunk(&mut self.out, b"fcTL", &control)?;
+        self.sequence += 1;
+        if self.frames == 0 {
+            // The first frame doubles as the default image for non-APNG viewers.
+            write_chunk(&mut self.out, b"IDAT", &data)?;
+        } else {
+            let mut frame_data = self.sequence.to_be_bytes().to_vec();
+            frame_data.extend_from_slice(&data);
+            write_chunk(&mut self.out, b"fdAT", &frame_data)?;
+            self.sequence += 1;
+        }
+        self.frames += 1;
+        Ok(true)
+    }
+
+    /// Writes `IEND`, fills in the frame count, and returns the file size.
+    pub fn finish(mut self) -> io::Result<u64> {
+        write_chunk(&mut self.out, b"IEND", &[])?;
+        let mut file = self.out.into_inner().map_err(|err| err.into_error())?;
+        let size = file.stream_position()?;
+        file.seek(SeekFrom::Start(ACTL_OFFSET))?;
+        write_chunk(&mut file, b"acTL", &animation_control(self.frames))?;
+        file.flush()?;
+        Ok(size)
+    }
+}
@@ -0,0 +1,465 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, BufReader, Read};
+use std::net::{Ipv4Addr, SocketAddr, TcpStream};
+use std::os::raw::c_char;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::adb;
+use crate::cancel;
+use crate::emulator;
+use crate::error::{ErrorCode, LbError};
+use crate::exec;
+use crate::json::{self, JsonValue};
+use crate::logging::{lb_log, Level};
+use crate::transcript;
+use crate::{ffi_guard, now_millis, read_c_str, status_result, string_result};
+
+/// Console ports the emulator accepts; adb uses the odd port above each.
+const FIRST_PORT: u16 = 5554;
+const LAST_PORT: u16 = 5682;
+const DEFAULT_BOOT_TIMEOUT_MS: u64 = 300_000;
+const BOOT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How long `kill` on the console gets before the process is killed outright.
+const STOP_TIMEOUT: Duration = Duration::from_secs(20);
+/// Emulator output kept for startup failure reports.
+const LOG_LINES: usize = 200;
+const LOG_TAIL_LINES: usize = 20;
+
+struct AvdProcess {
+    child: Child,
+    name: String,
+    log: Arc<Mutex<VecDeque<String>>>,
+    readers: Vec<JoinHandle<()>>,
+    started_ms: u64,
+}
+
+impl AvdProcess {
+    /// The last lines the emulator printed, for error messages.
+    fn log_tail(&self) -> String {
+        let Ok(log) = self.log.lock() else {
+            return String::new();
+        };
+        let skip = log.len().saturating_sub(LOG_TAIL_LINES);
+        log.iter().skip(skip).cloned().collect::<Vec<_>>().join("\n")
+    }
+
+    fn finish(mut self) {
+        let _ = self.child.wait();
+        for reader in self.readers.drain(..) {
+            let _ = reader.join();
+        }
+    }
+}
+
+static AVD_PROCESSES: OnceLock<Mutex<HashMap<String, AvdProcess>>> = OnceLock::new();
+
+fn avd_registry() -> &'static Mutex<HashMap<String, AvdProcess>> {
+    AVD_PROCESSES.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn lock_registry() -> Result<std::sync::MutexGuard<'static, HashMap<String, AvdProcess>>, LbError> {
+    avd_registry().lock().map_err(|_| LbError::internal("AVD registry poisoned"))
+}
+
+/// `$ANDROID_HOME/emulator/emulator` (or under `ANDROID_SDK_ROOT`) when present, since the
+/// SDK does not put it on PATH; otherwise `emulator` from PATH.
+fn emulator_binary() -> String {
+    let file = if cfg!(windows) { "emulator.exe" } else { "emulator" };
+    ["ANDROID_HOME", "ANDROID_SDK_ROOT"]
+        .iter()
+        .filter_map(std::env::var_os)
+        .map(|sdk| PathBuf::from(sdk).join("emulator").join(file))
+        .find(|path| path.is_file())
+        .map(|path| path.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "emulator".to_string())
+}
+
+fn validate_name(name: &str) -> Result<(), LbError> {
+    let valid = !name.is_empty() && name.chars().all(|ch| ch.is_ascii_alphanumeric() || "._-".contains(ch));
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("Invalid AVD name: {:?}", name).into())
+    }
+}
+
+/// `emulator -list-avds`; newer emulators mix `INFO    | ...` lines into stdout.
+fn avd_names() -> Result<Vec<String>, LbError> {
+    let argv = vec![emulator_binary(), "-list-avds".to_string()];
+    let output = exec::run_argv(&argv)?;
+    if !output.success() {
+        return Err(LbError::new(
+            ErrorCode::CommandFailed,
+            format!("emulator -list-avds failed: {}", output.stderr.trim()),
+        )
+        .with_command(&argv));
+    }
+    Ok(output
+        .stdout
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.contains(char::is_whitespace) && !line.contains('|'))
+        .map(str::to_string)
+        .collect())
+}
+
+/// `(serial, state)` for every emulator adb knows about.
+fn running_emulators() -> Result<Vec<(String, String)>, LbError> {
+    let output = adb::run_adb(None, &["devices"])?;
+    Ok(output
+        .stdout
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .filter(|(serial, _)| serial.starts_with("emulator-"))
+        .map(|(serial, state)| (serial.to_string(), state.trim().to_string()))
+        .collect())
+}
+
+/// The AVD an emulator runs, from its console; the first line of `avd name`.
+fn running_avd_name(serial: &str) -> Option<String> {
+    emulator::console_command(serial, "avd name")
+        .ok()
+        .and_then(|output| output.lines().next().map(|line| line.trim().to_string()))
+        .filter(|name| !name.is_empty())
+}
+
+fn list_avds() -> Result<String, LbError> {
+    let names = avd_names()?;
+    let managed: Vec<String> = lock_registry()?.keys().cloned().collect();
+    let mut running: HashMap<String, (String, String)> = HashMap::new();
+    for (serial, state) in running_emulators()? {
+        if let Some(name) = running_avd_name(&serial) {
+            running.insert(name, (serial, state));
+        }
+    }
+    let entry = |name: &str, instance: Option<&(String, String)>| {
+        let serial = instance.map(|(serial, _)| serial.clone());
+        JsonValue::object(vec![
+            ("name", name.into()),
+            ("running", instance.is_some().into()),
+            ("serial", serial.clone().into()),
+            ("state", instance.map(|(_, state)| state.clone()).into()),
+            ("managed", serial.is_some_and(|serial| managed.contains(&serial)).into()),
+        ])
+    };
+    let mut avds: Vec<JsonValue> = names.iter().map(|name| entry(name, running.get(name))).collect();
+    // Emulators started from another SDK still show up, just without a local definition.
+    let mut others: Vec<(&String, &(String, String))> =
+        running.iter().filter(|(name, _)| !names.contains(name)).collect();
+    others.sort();
+    avds.extend(others.into_iter().map(|(name, instance)| entry(name, Some(instance))));
+    Ok(JsonValue::object(vec![("emulator", emulator_binary().into()), ("avds", avds.into())]).to_string())
+}
+
+struct StartOptions {
+    port: Option<u16>,
+    no_window: bool,
+    wipe_data: bool,
+    cold_boot: bool,
+    gpu: Option<String>,
+    args: Vec<String>,
+    wait_boot: bool,
+    boot_timeout_ms: u64,
+}
+
+fn parse_options(options_json: Option<&str>) -> Result<StartOptions, LbError> {
+    let options = json::parse(options_json.unwrap_or("{}"))?;
+    if options.as_object().is_none() {
+        return Err("AVD options must be a JSON object".into());
+    }
+    let flag = |name: &str, default: bool| match options.get(name) {
+        None | Some(JsonValue::Null) => Ok(default),
+        Some(value) => value
+            .as_bool()
+            .ok_or_else(|| LbError::from(format!("AVD option '{}' must be a boolean", name))),
+    };
+    let port = match options.get("port") {
+        None | Some(JsonValue::Null) => None,
+        Some(port) => {
+            let port = port
+                .as_u64()
+                .and_then(|port| u16::try_from(port).ok())
+                .filter(|port| (FIRST_PORT..=LAST_PORT).contains(port) && port % 2 == 0)
+                .ok_or_else(|| format!("AVD option 'port' must be an even port from {} to {}", FIRST_PORT, LAST_PORT))?;
+            Some(port)
+        }
+    };
+    let gpu = match options.get("gpu") {
+        None | Some(JsonValue::Null) => None,
+        Some(gpu) => Some(gpu.as_str().ok_or("AVD option 'gpu' must be a string")?.to_string()),
+    };
+    let args = match options.get("args") {
+        None | Some(JsonValue::Null) => Vec::new(),
+        Some(args) => args.as_string_array().ok_or("AVD option 'args' must be an array of strings")?,
+    };
+    let boot_timeout_ms = match options.get("boot_timeout_ms") {
+        None | Some(JsonValue::Null) => DEFAULT_BOOT_TIMEOUT_MS,
+        Some(timeout) => timeout.as_u64().ok_or("AVD option 'boot_timeout_ms' must be a number")?,
+    };
+    Ok(StartOptions {
+        port,
+        no_window: flag("no_window", false)?,
+        wipe_data: flag("wipe_data", false)?,
+        cold_boot: flag("cold_boot", false)?,
+        gpu,
+        args,
+        wait_boot: flag("wait_boot", true)?,
+        boot_timeout_ms,
+    })
+}
+
+/// Nothing answers on the console port or the adb port above it.
+fn port_free(port: u16) -> bool {
+    [port, port + 1].iter().all(|&port| {
+        let address = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+        TcpStream::connect_timeout(&address, Duration::from_millis(200)).is_err()
+    })
+}
+
+fn pick_port(taken: &[String]) -> Result<u16, LbError> {
+    (FIRST_PORT..=LAST_PORT)
+        .step_by(2)
+        .find(|port| !taken.contains(&format!("emulator-{}", port)) && port_free(*port))
+        .ok_or_else(|| LbError::new(ErrorCode::CommandFailed, "No free emulator port between 5554 and 5682"))
+}
+
+/// Keeps the last `LOG_LINES` lines of one emulator output pipe and surfaces errors.
+fn capture<R: Read + Send + 'static>(
+    pipe: Option<R>,
+    serial: &str,
+    log: &Arc<Mutex<VecDeque<String>>>,
+) -> Option<JoinHandle<()>> {
+    let pipe = pipe?;
+    let (serial, log) = (serial.to_string(), Arc::clone(log));
+    Some(thread::spawn(move || {
+        for line in BufReader::new(pipe).lines().map_while(Result::ok) {
+            if line.contains("ERROR") || line.contains("FATAL") || line.contains("PANIC") {
+                lb_log!(Level::Warn, "emulator", "{}: {}", serial, line.trim());
+            }
+            let Ok(mut log) = log.lock() else {
+                return;
+            };
+            if log.len() == LOG_LINES {
+                log.pop_front();
+            }
+            log.push_back(line);
+        }
+    }))
+}
+
+/// Polls until `serial` reports `sys.boot_completed=1`, failing early if the emulator
+/// process exits or is stopped. A timeout or cancellation stops the emulator.
+fn wait_for_boot(serial: &str, timeout: Duration) -> Result<Duration, LbError> {
+    let started = Instant::now();
+    loop {
+        {
+            let mut registry = lock_registry()?;
+            let process = registry.get_mut(serial).ok_or_else(|| {
+                let message = format!("{} was stopped while booting", serial);
+                LbError::new(ErrorCode::CommandFailed, message).with_serial(serial)
+            })?;
+            let exited = process
+                .child
+                .try_wait()
+                .map_err(|err| LbError::io(format!("Failed to wait for the emulator: {}", err)))?;
+            if let Some(status) = exited {
+                let process = registry.remove(serial).expect("entry checked above");
+                drop(registry);
+                let tail = process.log_tail();
+                process.finish();
+                return Err(LbError::new(
+                    ErrorCode::CommandFailed,
+                    format!("Emulator for {} exited during startup ({}):\n{}", serial, status, tail),
+                )
+                .with_serial(serial));
+            }
+        }
+        if adb::get_state(serial).as_deref() == Some("device")
+            && adb::getprop(serial, "sys.boot_completed").as_deref() == Ok("1")
+        {
+            return Ok(started.elapsed());
+        }
+        if started.elapsed() >= timeout {
+            let tail = lock_registry()?.get(serial).map(AvdProcess::log_tail).unwrap_or_default();
+            let _ = stop_avd(serial);
+            return Err(LbError::new(
+                ErrorCode::Timeout,
+                format!(
+                    "{} did not finish booting within {}s; stopped it. Last output:\n{}",
+                    serial,
+                    timeout.as_secs(),
+                    tail
+                ),
+            )
+            .with_serial(serial));
+        }
+        if let Err(err) = cancel::sleep(BOOT_POLL_INTERVAL) {
+            let _ = stop_avd(serial);
+            return Err(err.with_serial(serial));
+        }
+    }
+}
+
+fn start_avd(name: &str, options: StartOptions) -> Result<String, LbError> {
+    validate_name(name)?;
+    if !avd_names()?.iter().any(|known| known == name) {
+        return Err(LbError::not_found(format!("No AVD named {}; see lb_list_avds", name)));
+    }
+    let running = running_emulators()?;
+    let taken: Vec<String> = running.iter().map(|(serial, _)| serial.clone()).collect();
+    if let Some(serial) = taken.iter().find(|serial| running_avd_name(serial).as_deref() == Some(name)) {
+        return Err(format!("AVD {} is already running as {}", name, serial).into());
+    }
+    let port = match options.port {
+        Some(port) if taken.contains(&format!("emulator-{}", port)) || !port_free(port) => {
+            return Err(format!("Emulator port {} is already in use", port).into());
+        }
+        Some(port) => port,
+        None => pick_port(&taken)?,
+    };
+    let serial = format!("emulator-{}", port);
+
+    let mut argv = vec![emulator_binary(), "-avd".to_string(), name.to_string()];
+    argv.extend(["-port".to_string(), port.to_string()]);
+    if options.no_window {
+        argv.push("-no-window".to_string());
+    }
+    if options.wipe_data {
+        argv.push("-wipe-data".to_string());
+    }
+    if options.cold_boot {
+        argv.push("-no-snapshot-load".to_string());
+    }
+    if let Some(gpu) = &options.gpu {
+        argv.extend(["-gpu".to_string(), gpu.clone()]);
+    }
+    argv.extend(options.args.iter().cloned());
+
+    let mut registry = lock_registry()?;
+    // A finished emulator nobody stopped is replaced; dropping a `Child` does not reap it.
+    if let Some(process) = registry.get_mut(&serial) {
+        if process.child.try_wait().ok().flatten().is_none() {
+            return Err(format!("{} is already running", serial).into());
+        }
+        if let Some(process) = registry.remove(&serial) {
+            process.finish();
+        }
+    }
+    let mut child = Command::new(&argv[0])
+        .args(&argv[1..])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| {
+            LbError::new(ErrorCode::SpawnFailed, format!("Failed to spawn {}: {}", argv[0], err)).with_command(&argv)
+        })?;
+    transcript::record_command(&argv, Duration::ZERO, None);
+    let pid = child.id();
+    let log = Arc::new(Mutex::new(VecDeque::new()));
+    let readers = [capture(child.stdout.take(), &serial, &log), capture(child.stderr.take(), &serial, &log)]
+        .into_iter()
+        .flatten()
+        .collect();
+    registry.insert(
+        serial.clone(),
+        AvdProcess {
+            child,
+            name: name.to_string(),
+            log,
+            readers,
+            started_ms: now_millis(),
+        },
+    );
+    drop(registry);
+    lb_log!(Level::Info, "emulator", "Started AVD {} as {} (pid {})", name, serial, pid);
+
+    let boot = if options.wait_boot {
+        Some(wait_for_boot(&serial, Duration::from_millis(options.boot_timeout_ms))?)
+    } else {
+        None
+    };
+    if let Some(boot) = boot {
+        lb_log!(Level::Info, "emulator", "{} booted in {} ms", serial, boot.as_millis());
+    }
+    Ok(JsonValue::object(vec![
+        ("name", name.into()),
+        ("serial", serial.as_str().into()),
+        ("port", u32::from(port).into()),
+        ("pid", pid.into()),
+        ("booted", boot.is_some().into()),
+        ("boot_ms", boot.map(|boot| boot.as_millis() as u64).into()),
+        ("command", JsonValue::from(argv.iter().map(JsonValue::from).collect::<Vec<_>>())),
+    ])
+    .to_string())
+}
+
+/// Asks the emulator to exit through its console, then waits for a process this library
+/// started (killing it after `STOP_TIMEOUT`). Unmanaged emulators only get the console
+/// `kill`.
+fn stop_avd(serial: &str) -> Result<(), LbError> {
+    let process = lock_registry()?.remove(serial);
+    let killed = emulator::console_command(serial, "kill");
+    let Some(mut process) = process else {
+        killed?;
+        lb_log!(Level::Info, "emulator", "Asked {} to exit", serial);
+        return Ok(());
+    };
+    let deadline = Instant::now() + STOP_TIMEOUT;
+    while process.child.try_wait().ok().flatten().is_none() {
+        if Instant::now() >= deadline {
+            lb_log!(Level::Warn, "emulator", "{} ignored the console kill; killing pid {}", serial, process.child.id());
+            let _ = process.child.kill();
+            break;
+        }
+        if cancel::sleep(Duration::from_millis(200)).is_err() {
+            let _ = process.child.kill();
+            break;
+        }
+    }
+    let uptime_ms = now_millis().saturating_sub(process.started_ms);
+    lb_log!(Level::Info, "emulator", "Stopped AVD {} ({}) after {} ms", process.name, serial, uptime_ms);
+    process.finish();
+    Ok(())
+}
+
+/// AVDs from `emulator -list-avds` (`$ANDROID_HOME/emulator` first, then PATH) joined with
+/// the emulators adb sees: `{emulator, avds: [{name, running, serial, state, managed}]}`.
+/// Running emulators are matched by the console's `avd name`; `managed` means this
+/// library started it. Running AVDs without a local definition are listed too.
+#[no_mangle]
+pub extern "C" fn lb_list_avds() -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || string_result(list_avds(), "AVD list"))
+}
+
+/// Starts AVD `name` as a managed child process. `options_json` (may be null) is an object
+/// with `port` (even, 5554-5682; default the first free one), `no_window`, `wipe_data`,
+/// `cold_boot` (skip the snapshot), `gpu` (`-gpu` mode), `args` (extra emulator
+/// arguments), `wait_boot` (default true) and `boot_timeout_ms` (default 300000). Waits
+/// for `sys.boot_completed` unless `wait_boot` is false; an emulator that exits meanwhile
+/// fails with its last output, and one that misses the timeout is stopped. Returns `{name,
+/// serial, port, pid, booted, boot_ms, command}`.
+#[no_mangle]
+pub extern "C" fn lb_start_avd(name_ptr: *const c_char, options_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let options = if options_ptr.is_null() {
+            Ok(None)
+        } else {
+            read_c_str(options_ptr, "AVD options").map(Some)
+        };
+        let result = read_c_str(name_ptr, "AVD name")
+            .and_then(|name| options.and_then(parse_options).and_then(|options| start_avd(name, options)));
+        string_result(result, "AVD start result")
+    })
+}
+
+/// Stops emulator `serial` with the console `kill`, waiting up to 20s for one started by
+/// `lb_start_avd` before killing the process. Returns 1 on success.
+#[no_mangle]
+pub extern "C" fn lb_stop_avd(serial_ptr: *const c_char) -> i32 {
+    ffi_guard(0, || status_result(read_c_str(serial_ptr, "serial").and_then(stop_avd)))
+}
@@ -0,0 +1,295 @@
+use std::ffi::c_void;
+use std::fs;
+use std::io::Read;
+use std::os::raw::c_char;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::adb;
+use crate::cancel;
+use crate::device_lock::ensure_device_unlocked;
+use crate::error::{ErrorCode, LbError};
+use crate::json::{self, JsonValue};
+use crate::logging::{lb_log, Level};
+use crate::serial_lock;
+use crate::packages;
+use crate::stream::{LineCallback, LineSink};
+use crate::transcript;
+use crate::{ffi_guard, read_c_str, string_result};
+
+/// The activity that asks the user to allow a backup or restore.
+const CONFIRM_PACKAGE: &str = "com.android.backupconfirm";
+/// Every `adb backup` file starts with this line.
+const BACKUP_MAGIC: &[u8] = b"ANDROID BACKUP\n";
+/// How long the flow may sit before anything happens: the prompt not yet answered, or the
+/// device not showing it at all.
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(120);
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+fn confirmation_pending(serial: &str) -> bool {
+    adb::focused_window(serial).is_ok_and(|focus| focus.contains(CONFIRM_PACKAGE))
+}
+
+struct Transfer<'a> {
+    serial: &'a str,
+    sink: Option<LineSink>,
+    started: Instant,
+    last: Option<(&'static str, u64)>,
+    /// Set once the flow gets past the prompt: it was seen and went away, or data arrived
+    /// without us catching it.
+    confirmed: bool,
+}
+
+impl Transfer<'_> {
+    fn report(&mut self, stage: &'static str, bytes: u64) {
+        if self.last == Some((stage, bytes)) {
+            return;
+        }
+        self.last = Some((stage, bytes));
+        if let Some(sink) = &self.sink {
+            sink.emit(
+                &JsonValue::object(vec![
+                    ("stage", stage.into()),
+                    ("bytes", bytes.into()),
+                    ("elapsed_ms", (self.started.elapsed().as_millis() as u64).into()),
+                ])
+                .to_string(),
+            );
+        }
+    }
+
+    /// Polls until adb exits, reporting `starting` -> `confirmation_pending` -> `running`.
+    /// `bytes` reads how far the transfer got (the growing backup file; 0 for restores).
+    fn watch(&mut self, argv: &[String], child: &mut Child, bytes: impl Fn() -> u64) -> Result<Option<i32>, LbError> {
+        let mut pending_since: Option<Instant> = None;
+        let mut seen_prompt = false;
+        loop {
+            if let Some(status) = child
+                .try_wait()
+                .map_err(|err| LbError::io(format!("Failed to wait for adb: {}", err)))?
+            {
+                return Ok(status.code());
+            }
+            let pending = confirmation_pending(self.serial);
+            let bytes = bytes();
+            seen_prompt |= pending;
+            let stage = if pending {
+                "confirmation_pending"
+            } else if seen_prompt || bytes > 0 {
+                "running"
+            } else {
+                "starting"
+            };
+            self.confirmed |= stage == "running";
+            self.report(stage, bytes);
+            let waiting = match stage {
+                "confirmation_pending" => pending_since.get_or_insert_with(Instant::now).elapsed(),
+                "starting" => self.started.elapsed(),
+                _ => {
+                    pending_since = None;
+                    Duration::ZERO
+                }
+            };
+            if waiting >= CONFIRM_TIMEOUT {
+                let _ = child.kill();
+                let _ = child.wait();
+                let message = if stage == "starting" {
+                    "The device never showed the confirmation prompt"
+                } else {
+                    "Nobody answered the confirmation prompt on the device"
+                };
+                let message = format!("{} within {}s", message, CONFIRM_TIMEOUT.as_secs());
+                return Err(LbError::new(ErrorCode::Timeout, message)
+                    .with_serial(self.serial)
+                    .with_command(argv));
+            }
+            if let Err(err) = cancel::sleep(POLL_INTERVAL) {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(err.with_serial(self.serial).with_command(argv));
+            }
+        }
+    }
+}
+
+fn read_all(mut stream: impl Read + Send + 'static) -> JoinHandle<String> {
+    thread::spawn(move || {
+        let mut text = String::new();
+        let _ = stream.read_to_string(&mut text);
+        text
+    })
+}
+
+/// Runs `adb <args>` under `Transfer::watch`, failing on a non-zero exit like `run_adb`.
+fn run_watched(transfer: &mut Transfer, args: &[&str], bytes: impl Fn() -> u64) -> Result<(), LbError> {
+    let argv = adb::adb_argv(Some(transfer.serial), args);
+    // Held until adb exits; the confirmation polls run on this thread and pass through.
+    let _device = serial_lock::acquire_for(&argv);
+    let started = Instant::now();
+    let mut child = Command::new(&argv[0])
+        .args(&argv[1..])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| {
+            LbError::new(ErrorCode::SpawnFailed, format!("Failed to spawn {}: {}", argv[0], err))
+                .with_command(&argv)
+                .with_serial(transfer.serial)
+        })?;
+    let stdout = child.stdout.take().map(read_all);
+    let stderr = child.stderr.take().map(read_all);
+    let code = transfer.watch(&argv, &mut child, bytes);
+    transcript::record_command(&argv, started.elapsed(), code.as_ref().ok().copied().flatten());
+    let code = code?;
+    let text: String = [stdout, stderr]
+        .into_iter()
+        .flatten()
+        .filter_map(|reader| reader.join().ok())
+        .collect();
+    if code != Some(0) {
+        let detail = text.trim();
+        return Err(LbError::new(
+            adb::classify_failure(detail),
+            format!("adb {} failed (exit={}): {}", args[0], code.unwrap_or(-1), detail),
+        )
+        .with_serial(transfer.serial)
+        .with_command(&argv));
+    }
+    Ok(())
+}
+
+fn app_backup(serial: &str, packages_json: &str, local_path: &str, sink: Option<LineSink>) -> Result<String, LbError> {
+    let packages = json::parse(packages_json)?
+        .as_string_array()
+        .ok_or("Backup packages must be a JSON array of package names")?;
+    if packages.is_empty() {
+        return Err("No packages to back up".into());
+    }
+    for package in &packages {
+        packages::validate_package(package)?;
+    }
+    ensure_device_unlocked(serial)?;
+    let path = Path::new(local_path);
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)
+            .map_err(|err| LbError::io(format!("Failed to create {}: {}", parent.display(), err)))?;
+    }
+    // A leftover file would make an unconfirmed backup look like progress.
+    let _ = fs::remove_file(path);
+
+    let mut transfer = Transfer {
+        serial,
+        sink,
+        started: Instant::now(),
+        last: None,
+        confirmed: false,
+    };
+    lb_log!(Level::Info, "sync", "Backing up {} package(s) from {} to {}", packages.len(), serial, local_path);
+    let mut args = vec!["backup", "-f", local_path];
+    args.extend(packages.iter().map(String::as_str));
+    let size = || fs::metadata(path).map(|metadata| metadata.len()).unwrap_or(0);
+    run_watched(&mut transfer, &args, size)?;
+    let bytes = size();
+    if bytes == 0 {
+        return Err(LbError::new(
+            ErrorCode::CommandFailed,
+            "The backup was declined on the device or produced no data",
+        )
+        .with_serial(serial));
+    }
+    transfer.report("complete", bytes);
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("path", local_path.into()),
+        ("packages", packages.iter().map(JsonValue::from).collect::<Vec<_>>().into()),
+        ("bytes", bytes.into()),
+        ("confirmed", true.into()),
+        ("duration_ms", (transfer.started.elapsed().as_millis() as u64).into()),
+    ])
+    .to_string())
+}
+
+fn app_restore(serial: &str, local_path: &str, sink: Option<LineSink>) -> Result<String, LbError> {
+    let mut header = [0u8; BACKUP_MAGIC.len()];
+    let bytes = fs::File::open(local_path)
+        .and_then(|mut file| {
+            file.read_exact(&mut header)?;
+            file.metadata()
+        })
+        .map_err(|err| LbError::not_found(format!("Backup {} is not readable: {}", local_path, err)))?
+        .len();
+    if header != BACKUP_MAGIC {
+        return Err(LbError::parse(format!("{} is not an adb backup file", local_path)));
+    }
+    ensure_device_unlocked(serial)?;
+    let mut transfer = Transfer {
+        serial,
+        sink,
+        started: Instant::now(),
+        last: None,
+        confirmed: false,
+    };
+    lb_log!(Level::Info, "sync", "Restoring {} to {}", local_path, serial);
+    run_watched(&mut transfer, &["restore", local_path], || 0)?;
+    transfer.report("complete", bytes);
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("path", local_path.into()),
+        ("bytes", bytes.into()),
+        ("confirmed", transfer.confirmed.into()),
+        ("duration_ms", (transfer.started.elapsed().as_millis() as u64).into()),
+    ])
+    .to_string())
+}
+
+/// Runs `adb backup -f local_path <packages>` (`packages_json` is an array of names) and
+/// blocks until it finishes. The user must allow the backup on the device; while the
+/// prompt is up the callback receives `{stage: "confirmation_pending", bytes, elapsed_ms}`,
+/// then `running` with the file growing, then `complete`. Returns `{serial, path,
+/// packages, bytes, confirmed, duration_ms}`. A prompt left unanswered for two minutes is
+/// a Timeout; a declined backup (empty file) is CommandFailed. Apps targeting Android 12+
+/// only contribute data when they opt in.
+#[no_mangle]
+pub extern "C" fn lb_app_backup(
+    serial_ptr: *const c_char,
+    packages_json_ptr: *const c_char,
+    local_path_ptr: *const c_char,
+    callback: Option<LineCallback>,
+    user_data: *mut c_void,
+) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let sink = callback.map(|callback| LineSink::new(Some(callback), user_data)).transpose();
+        let result = sink.and_then(|sink| {
+            let serial = read_c_str(serial_ptr, "serial")?;
+            let packages_json = read_c_str(packages_json_ptr, "packages JSON")?;
+            let local_path = read_c_str(local_path_ptr, "backup path")?;
+            app_backup(serial, packages_json, local_path, sink)
+        });
+        string_result(result, "backup result")
+    })
+}
+
+/// `adb restore path` with the same confirmation tracking and callback stages as
+/// `lb_app_backup`. Returns `{serial, path, bytes, confirmed, duration_ms}`; adb cannot
+/// tell a declined restore from a finished one, so `confirmed` only says whether the
+/// prompt was seen and then dismissed.
+#[no_mangle]
+pub extern "C" fn lb_app_restore(
+    serial_ptr: *const c_char,
+    path_ptr: *const c_char,
+    callback: Option<LineCallback>,
+    user_data: *mut c_void,
+) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let sink = callback.map(|callback| LineSink::new(Some(callback), user_data)).transpose();
+        let result = sink.and_then(|sink| {
+            let serial = read_c_str(serial_ptr, "serial")?;
+            let path = read_c_str(path_ptr, "backup path")?;
+            app_restore(serial, path, sink)
+        });
+        string_result(result, "restore result")
+    })
+}
@@ -0,0 +1,111 @@
+use std::os::raw::c_char;
+
+use crate::adb;
+use crate::error::LbError;
+use crate::json::JsonValue;
+use crate::{ffi_guard, read_c_str, string_result};
+
+const POWER_SUPPLY_ROOT: &str = "/sys/class/power_supply/battery";
+const SYSFS_FIELDS: [&str; 7] = [
+    "charge_counter",
+    "cycle_count",
+    "charge_full",
+    "charge_full_design",
+    "health",
+    "capacity",
+    "technology",
+];
+/// Below this share of design capacity a battery is flagged for replacement.
+const REPLACE_BELOW_PERCENT: f64 = 80.0;
+
+/// `dumpsys battery` health constants (`BatteryManager.BATTERY_HEALTH_*`).
+pub fn health_name(code: &str) -> &str {
+    match code {
+        "1" => "Unknown",
+        "2" => "Good",
+        "3" => "Overheat",
+        "4" => "Dead",
+        "5" => "Over voltage",
+        "6" => "Unspecified failure",
+        "7" => "Cold",
+        other => other,
+    }
+}
+
+/// `name|value` lines for every readable node; unreadable nodes print an empty value.
+fn sysfs_script() -> String {
+    let names = SYSFS_FIELDS.join(" ");
+    format!(
+        "for f in {}; do echo \"$f|$(cat {}/$f 2>/dev/null)\"; done",
+        names, POWER_SUPPLY_ROOT
+    )
+}
+
+fn field<'a>(lines: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    lines
+        .iter()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.as_str())
+        .filter(|value| !value.is_empty())
+}
+
+fn parse_sysfs(output: &str) -> Vec<(String, String)> {
+    output
+        .lines()
+        .filter_map(|line| line.split_once('|'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect()
+}
+
+/// `  Charge counter: 2840000` style lines from `dumpsys battery`, keyed by lowercase name.
+pub fn parse_dumpsys(output: &str) -> Vec<(String, String)> {
+    output
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+        .collect()
+}
+
+fn get_battery_health(serial: &str) -> Result<String, LbError> {
+    let dumpsys = parse_dumpsys(&adb::shell(serial, "dumpsys battery")?);
+    let sysfs = adb::shell(serial, &sysfs_script())
+        .map(|output| parse_sysfs(&output))
+        .unwrap_or_default();
+    let number = |value: Option<&str>| value.and_then(|value| value.parse::<i64>().ok());
+
+    let charge_counter = number(field(&sysfs, "charge_counter")).or_else(|| number(field(&dumpsys, "charge counter")));
+    let cycle_count = number(field(&sysfs, "cycle_count")).or_else(|| number(field(&dumpsys, "cycle count")));
+    let full_uah = number(field(&sysfs, "charge_full"));
+    let design_uah = number(field(&sysfs, "charge_full_design"));
+    let health_percent = match (full_uah, design_uah) {
+        (Some(full), Some(design)) if design > 0 => Some(full as f64 * 100.0 / design as f64),
+        _ => None,
+    };
+    let health = field(&sysfs, "health").or_else(|| field(&dumpsys, "health").map(health_name));
+    let level = number(field(&sysfs, "capacity")).or_else(|| number(field(&dumpsys, "level")));
+    let needs_replacement = health_percent.is_some_and(|percent| percent < REPLACE_BELOW_PERCENT)
+        || matches!(health, Some("Dead") | Some("Unspecified failure"));
+
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("level", level.into()),
+        ("health", health.into()),
+        ("technology", field(&sysfs, "technology").or_else(|| field(&dumpsys, "technology")).into()),
+        ("charge_counter_uah", charge_counter.into()),
+        ("cycle_count", cycle_count.into()),
+        ("charge_full_uah", full_uah.into()),
+        ("charge_full_design_uah", design_uah.into()),
+        ("health_percent", health_percent.into()),
+        ("needs_replacement", needs_replacement.into()),
+    ])
+    .to_string())
+}
+
+/// Returns battery level, health, charge counter, cycle count, and full vs design capacity
+/// as JSON. Fields the kernel does not expose to the shell user are `null`.
+#[no_mangle]
+pub extern "C" fn lb_get_battery_health(serial_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        string_result(read_c_str(serial_ptr, "serial").and_then(get_battery_health), "battery health")
+    })
+}
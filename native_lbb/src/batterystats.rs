@@ -0,0 +1,232 @@
+use std::collections::BTreeMap;
+use std::os::raw::c_char;
+
+use crate::adb;
+use crate::device_lock::ensure_device_unlocked;
+use crate::error::{ErrorCode, LbError};
+use crate::json::JsonValue;
+use crate::logging::{lb_log, Level};
+use crate::{ffi_guard, read_c_str, status_result, string_result};
+
+/// One app UID's share of the drain since the stats were last reset.
+#[derive(Default)]
+struct UidUsage {
+    packages: Vec<String>,
+    power_mah: f64,
+    screen_mah: f64,
+    cpu_user_ms: u64,
+    cpu_system_ms: u64,
+    /// mobile rx, mobile tx, wifi rx, wifi tx.
+    network_bytes: [u64; 4],
+    wakelock_ms: u64,
+}
+
+/// `dumpsys batterystats -c` lines are `<version>,<uid>,<scope>,<section>,<values...>`;
+/// scope `i` is static info and `l` covers the time since the last reset or charge.
+#[derive(Default)]
+struct Checkin {
+    version: Option<String>,
+    uids: BTreeMap<u32, UidUsage>,
+    /// Drains not attributed to an app: `scrn`, `cell`, `idle`, `wifi`, `over`, ...
+    other: Vec<(String, f64)>,
+    capacity_mah: Option<f64>,
+    computed_mah: Option<f64>,
+    drained_mah: Option<(f64, f64)>,
+    on_battery_ms: Option<u64>,
+}
+
+impl Checkin {
+    fn parse(output: &str) -> Checkin {
+        let mut checkin = Checkin::default();
+        for line in output.lines() {
+            let fields: Vec<&str> = line.trim().split(',').collect();
+            if fields.len() < 5 {
+                continue;
+            }
+            let Ok(uid) = fields[1].parse::<u32>() else {
+                continue;
+            };
+            let values = &fields[4..];
+            let float = |index: usize| values.get(index).and_then(|value| value.parse::<f64>().ok());
+            let int = |index: usize| values.get(index).and_then(|value| value.parse::<u64>().ok());
+            match (fields[2], fields[3]) {
+                ("i", "vers") => checkin.version = values.first().map(|value| value.to_string()),
+                // `uid,<uid>,<package>`: several packages can share one UID.
+                ("i", "uid") => {
+                    let app_uid = values.first().and_then(|value| value.parse::<u32>().ok());
+                    if let (Some(app_uid), Some(package)) = (app_uid, values.get(1)) {
+                        let usage = checkin.uids.entry(app_uid).or_default();
+                        if !usage.packages.iter().any(|known| known == package) {
+                            usage.packages.push(package.to_string());
+                        }
+                    }
+                }
+                ("l", "bt") => checkin.on_battery_ms = int(1),
+                ("l", "pws") => {
+                    checkin.capacity_mah = float(0);
+                    checkin.computed_mah = float(1);
+                    checkin.drained_mah = float(2).zip(float(3));
+                }
+                // `pwi,<label>,<mAh>,<hidden>,<screen mAh>,...`; app drains are labelled `uid`.
+                ("l", "pwi") => {
+                    let (Some(label), Some(power)) = (values.first(), float(1)) else {
+                        continue;
+                    };
+                    if *label == "uid" {
+                        let usage = checkin.uids.entry(uid).or_default();
+                        usage.power_mah += power;
+                        usage.screen_mah += float(3).unwrap_or(0.0);
+                    } else {
+                        checkin.other.push((label.to_string(), power));
+                    }
+                }
+                ("l", "cpu") => {
+                    let usage = checkin.uids.entry(uid).or_default();
+                    usage.cpu_user_ms += int(0).unwrap_or(0);
+                    usage.cpu_system_ms += int(1).unwrap_or(0);
+                }
+                ("l", "nt") => {
+                    let usage = checkin.uids.entry(uid).or_default();
+                    for (index, total) in usage.network_bytes.iter_mut().enumerate() {
+                        *total += int(index).unwrap_or(0);
+                    }
+                }
+                // `wl,<name>,<full ms>,f,<count>,<partial ms>,p,<count>,...`; partial locks
+                // are the ones that keep the CPU awake.
+                ("l", "wl") => {
+                    let usage = checkin.uids.entry(uid).or_default();
+                    usage.wakelock_ms += int(4).unwrap_or(0);
+                }
+                _ => {}
+            }
+        }
+        checkin
+    }
+
+    fn to_json(&self, serial: &str) -> JsonValue {
+        let mut uids: Vec<(&u32, &UidUsage)> = self
+            .uids
+            .iter()
+            .filter(|(_, usage)| usage.power_mah > 0.0 || usage.cpu_user_ms + usage.cpu_system_ms > 0)
+            .collect();
+        uids.sort_by(|a, b| b.1.power_mah.total_cmp(&a.1.power_mah));
+        let uids: Vec<JsonValue> = uids
+            .into_iter()
+            .map(|(uid, usage)| {
+                let packages: Vec<JsonValue> = usage.packages.iter().map(JsonValue::from).collect();
+                let [mobile_rx, mobile_tx, wifi_rx, wifi_tx] = usage.network_bytes;
+                JsonValue::object(vec![
+                    ("uid", (*uid).into()),
+                    ("packages", packages.into()),
+                    ("power_mah", usage.power_mah.into()),
+                    ("screen_mah", usage.screen_mah.into()),
+                    ("cpu_user_ms", usage.cpu_user_ms.into()),
+                    ("cpu_system_ms", usage.cpu_system_ms.into()),
+                    ("mobile_rx_bytes", mobile_rx.into()),
+                    ("mobile_tx_bytes", mobile_tx.into()),
+                    ("wifi_rx_bytes", wifi_rx.into()),
+                    ("wifi_tx_bytes", wifi_tx.into()),
+                    ("wakelock_ms", usage.wakelock_ms.into()),
+                ])
+            })
+            .collect();
+        let other: Vec<JsonValue> = self
+            .other
+            .iter()
+            .map(|(label, power)| JsonValue::object(vec![("label", label.into()), ("power_mah", (*power).into())]))
+            .collect();
+        JsonValue::object(vec![
+            ("serial", serial.into()),
+            ("checkin_version", self.version.as_deref().into()),
+            ("on_battery_ms", self.on_battery_ms.into()),
+            ("capacity_mah", self.capacity_mah.into()),
+            ("computed_mah", self.computed_mah.into()),
+            ("min_drained_mah", self.drained_mah.map(|(min, _)| min).into()),
+            ("max_drained_mah", self.drained_mah.map(|(_, max)| max).into()),
+            ("uids", uids.into()),
+            ("other", other.into()),
+        ])
+    }
+}
+
+fn batterystats_json(serial: &str) -> Result<String, LbError> {
+    let output = adb::shell(serial, "dumpsys batterystats -c")?;
+    let checkin = Checkin::parse(&output);
+    if checkin.version.is_none() {
+        return Err(LbError::parse("dumpsys batterystats -c printed no checkin data").with_serial(serial));
+    }
+    Ok(checkin.to_json(serial).to_string())
+}
+
+fn reset_batterystats(serial: &str) -> Result<(), LbError> {
+    ensure_device_unlocked(serial)?;
+    let output = adb::shell(serial, "dumpsys batterystats --reset")?;
+    if !output.contains("Battery stats reset") {
+        return Err(LbError::new(
+            ErrorCode::CommandFailed,
+            format!("Battery stats reset failed: {}", output.trim()),
+        )
+        .with_serial(serial));
+    }
+    lb_log!(Level::Info, "battery", "Reset battery stats on {}", serial);
+    Ok(())
+}
+
+/// `dumpsys battery` exits 0 for unknown commands and prints the reason instead.
+fn battery_command(serial: &str, command: &str) -> Result<(), LbError> {
+    let output = adb::shell(serial, &format!("dumpsys battery {}", command))?;
+    if !output.trim().is_empty() {
+        return Err(LbError::new(
+            ErrorCode::CommandFailed,
+            format!("dumpsys battery {} failed: {}", command, output.trim()),
+        )
+        .with_serial(serial));
+    }
+    Ok(())
+}
+
+pub fn set_unplugged(serial: &str, unplugged: bool) -> Result<(), LbError> {
+    ensure_device_unlocked(serial)?;
+    if unplugged {
+        // The device keeps charging; the framework only believes it is on battery, which
+        // is what batterystats needs to account drain.
+        for supply in ["ac", "usb", "wireless"] {
+            battery_command(serial, &format!("set {} 0", supply))?;
+        }
+        battery_command(serial, "set status 3")?;
+    } else {
+        battery_command(serial, "reset")?;
+    }
+    let action = if unplugged { "Faked unplugged" } else { "Restored" };
+    lb_log!(Level::Info, "battery", "{} charging state on {}", action, serial);
+    Ok(())
+}
+
+/// Clears the device's battery stats (`dumpsys batterystats --reset`) so a following
+/// `lb_batterystats_dump_json` covers only the flow under test.
+#[no_mangle]
+pub extern "C" fn lb_batterystats_reset(serial_ptr: *const c_char) -> i32 {
+    ffi_guard(0, || status_result(read_c_str(serial_ptr, "serial").and_then(reset_batterystats)))
+}
+
+/// Parses `dumpsys batterystats -c` into `{serial, checkin_version, on_battery_ms,
+/// capacity_mah, computed_mah, min_drained_mah, max_drained_mah, uids, other}`. `uids`
+/// is sorted by estimated drain: `{uid, packages, power_mah, screen_mah, cpu_user_ms,
+/// cpu_system_ms, mobile_rx_bytes, mobile_tx_bytes, wifi_rx_bytes, wifi_tx_bytes,
+/// wakelock_ms}`; `other` holds drains not charged to an app (`{label, power_mah}`).
+#[no_mangle]
+pub extern "C" fn lb_batterystats_dump_json(serial_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        string_result(read_c_str(serial_ptr, "serial").and_then(batterystats_json), "battery stats")
+    })
+}
+
+/// With `unplugged` non-zero, makes the framework report the device as on battery
+/// (`dumpsys battery set ac|usb|wireless 0`, `set status 3`) while USB keeps adb
+/// connected; zero restores the real state with `dumpsys battery reset`.
+#[no_mangle]
+pub extern "C" fn lb_battery_set_unplugged(serial_ptr: *const c_char, unplugged: i32) -> i32 {
+    ffi_guard(0, || {
+        status_result(read_c_str(serial_ptr, "serial").and_then(|serial| set_unplugged(serial, unplugged != 0)))
+    })
+}
@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+use std::os::raw::c_char;
+use std::sync::{Mutex, OnceLock};
+
+use crate::adb;
+use crate::cpu;
+use crate::device_lock::ensure_device_unlocked;
+use crate::error::LbError;
+use crate::failure_capture;
+use crate::json::JsonValue;
+use crate::{ffi_guard, read_c_str, string_result};
+
+const BENCHMARK_BRIGHTNESS: &str = "128";
+pub const ANIMATION_SCALES: [&str; 3] = [
+    "window_animation_scale",
+    "transition_animation_scale",
+    "animator_duration_scale",
+];
+
+/// One reversible modification; restored in reverse order on exit.
+enum Change {
+    Setting {
+        namespace: &'static str,
+        key: &'static str,
+        original: Option<String>,
+    },
+    AirplaneMode,
+    Sysfs(Vec<(String, String)>),
+}
+
+impl Change {
+    fn label(&self) -> String {
+        match self {
+            Change::Setting { namespace, key, .. } => format!("settings {} {}", namespace, key),
+            Change::AirplaneMode => "airplane mode".to_string(),
+            Change::Sysfs(_) => "cpu clocks".to_string(),
+        }
+    }
+
+    fn restore(&self, serial: &str) -> Result<(), LbError> {
+        match self {
+            Change::Setting {
+                namespace,
+                key,
+                original,
+            } => adb::put_setting(serial, namespace, key, original.as_deref()),
+            Change::AirplaneMode => set_airplane_mode(serial, false),
+            Change::Sysfs(originals) => cpu::write_sysfs(serial, originals),
+        }
+    }
+}
+
+/// `None` while `lb_enter_benchmark_mode` is still applying its steps.
+static BENCHMARK_SESSIONS: OnceLock<Mutex<HashMap<String, Option<Vec<Change>>>>> = OnceLock::new();
+
+fn benchmark_registry() -> &'static Mutex<HashMap<String, Option<Vec<Change>>>> {
+    BENCHMARK_SESSIONS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// A serial's slot, claimed before any step runs so concurrent enters cannot both apply
+/// theirs. Released again unless `commit` fills it.
+struct Reservation<'a> {
+    serial: &'a str,
+    committed: bool,
+}
+
+impl<'a> Reservation<'a> {
+    fn claim(serial: &'a str) -> Result<Reservation<'a>, LbError> {
+        let mut registry = benchmark_registry()
+            .lock()
+            .map_err(|_| LbError::internal("Benchmark registry poisoned"))?;
+        if registry.contains_key(serial) {
+            return Err(format!("{} is already in benchmark mode", serial).into());
+        }
+        registry.insert(serial.to_string(), None);
+        Ok(Reservation { serial, committed: false })
+    }
+
+    fn commit(mut self, changes: Vec<Change>) -> Result<(), LbError> {
+        benchmark_registry()
+            .lock()
+            .map_err(|_| LbError::internal("Benchmark registry poisoned"))?
+            .insert(self.serial.to_string(), Some(changes));
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        if !self.committed {
+            if let Ok(mut registry) = benchmark_registry().lock() {
+                registry.remove(self.serial);
+            }
+        }
+    }
+}
+
+fn override_setting(
+    serial: &str,
+    namespace: &'static str,
+    key: &'static str,
+    value: &str,
+    changes: &mut Vec<Change>,
+) -> Result<(), LbError> {
+    let original = adb::get_setting(serial, namespace, key)?;
+    if original.as_deref() == Some(value) {
+        return Ok(());
+    }
+    adb::put_setting(serial, namespace, key, Some(value))?;
+    changes.push(Change::Setting {
+        namespace,
+        key,
+        original,
+    });
+    Ok(())
+}
+
+fn set_airplane_mode(serial: &str, enabled: bool) -> Result<(), LbError> {
+    let action = if enabled { "enable" } else { "disable" };
+    if adb::shell(serial, &format!("cmd connectivity airplane-mode {}", action)).is_ok() {
+        return Ok(());
+    }
+    // Pre-Android 11 fallback; the broadcast is what makes the radios follow the setting.
+    let state = if enabled { "1" } else { "0" };
+    adb::shell(
+        serial,
+        &format!(
+            "settings put global airplane_mode_on {} && am broadcast -a android.intent.action.AIRPLANE_MODE --ez state {}",
+            state, enabled
+        ),
+    )
+    .map(|_| ())
+}
+
+fn disable_animations(serial: &str, changes: &mut Vec<Change>) -> Result<(), LbError> {
+    for key in ANIMATION_SCALES {
+        override_setting(serial, "global", key, "0", changes)?;
+    }
+    Ok(())
+}
+
+fn fix_brightness(serial: &str, changes: &mut Vec<Change>) -> Result<(), LbError> {
+    override_setting(serial, "system", "screen_brightness_mode", "0", changes)?;
+    override_setting(serial, "system", "screen_brightness", BENCHMARK_BRIGHTNESS, changes)
+}
+
+fn airplane_mode_keep_wifi(serial: &str, changes: &mut Vec<Change>) -> Result<(), LbError> {
+    if adb::is_network_serial(serial) {
+        return Err("skipped for wireless adb; toggling radios would drop the connection".into());
+    }
+    let wifi_on = adb::get_setting(serial, "global", "wifi_on")?.is_some_and(|value| value != "0");
+    if adb::get_setting(serial, "global", "airplane_mode_on")?.as_deref() != Some("1") {
+        set_airplane_mode(serial, true)?;
+        // Android re-enables Wi-Fi by itself when airplane mode is turned off again.
+        changes.push(Change::AirplaneMode);
+    }
+    if wifi_on {
+        adb::shell(serial, "svc wifi enable")?;
+    }
+    Ok(())
+}
+
+fn kill_background_apps(serial: &str, _changes: &mut Vec<Change>) -> Result<(), LbError> {
+    adb::shell(serial, "am kill-all").map(|_| ())
+}
+
+fn fix_cpu_clocks(serial: &str, changes: &mut Vec<Change>) -> Result<(), LbError> {
+    adb::require_root(serial)?;
+    changes.push(Change::Sysfs(cpu::pin_stable_clocks(serial)?));
+    Ok(())
+}
+
+type Step = fn(&str, &mut Vec<Change>) -> Result<(), LbError>;
+
+const STEPS: [(&str, Step); 5] = [
+    ("disable_animations", disable_animations),
+    ("fixed_brightness", fix_brightness),
+    ("airplane_mode_keep_wifi", airplane_mode_keep_wifi),
+    ("kill_background_apps", kill_background_apps),
+    ("fixed_cpu_clocks", fix_cpu_clocks),
+];
+
+fn enter_benchmark_mode(serial: &str) -> Result<String, LbError> {
+    ensure_device_unlocked(serial)?;
+    let reservation = Reservation::claim(serial)?;
+    let mut changes = Vec::new();
+    let mut applied = Vec::new();
+    let mut skipped = Vec::new();
+    for (name, step) in STEPS {
+        // A failed step keeps whatever it changed before failing, so exit still restores it.
+        match step(serial, &mut changes) {
+            Ok(()) => applied.push(JsonValue::from(name)),
+            Err(reason) => skipped.push(JsonValue::object(vec![("step", name.into()), ("reason", reason.message.into())])),
+        }
+    }
+    let tracked: Vec<JsonValue> = changes.iter().map(|change| change.label().into()).collect();
+    reservation.commit(changes)?;
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("applied", applied.into()),
+        ("skipped", skipped.into()),
+        ("changes", tracked.into()),
+    ])
+    .to_string())
+}
+
+fn exit_benchmark_mode(serial: &str) -> Result<String, LbError> {
+    let changes = {
+        let mut registry = benchmark_registry()
+            .lock()
+            .map_err(|_| LbError::internal("Benchmark registry poisoned"))?;
+        match registry.get(serial) {
+            None => return Err(LbError::not_found(format!("{} is not in benchmark mode", serial))),
+            Some(None) => return Err(format!("{} is still entering benchmark mode", serial).into()),
+            Some(Some(_)) => registry.remove(serial).flatten().unwrap_or_default(),
+        }
+    };
+    let mut restored = Vec::new();
+    let mut failed = Vec::new();
+    for change in changes.iter().rev() {
+        match change.restore(serial) {
+            Ok(()) => restored.push(JsonValue::from(change.label())),
+            Err(err) => failed.push(JsonValue::object(vec![("change", change.label().into()), ("error", err.message.into())])),
+        }
+    }
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("restored", restored.into()),
+        ("failed", failed.into()),
+    ])
+    .to_string())
+}
+
+/// Stabilizes a device for benchmarking (animations off, fixed brightness, airplane mode
+/// with Wi-Fi kept, background apps killed, median CPU clocks when rooted) and returns a
+/// JSON report of applied and skipped steps. Every change is tracked for the exit call.
+#[no_mangle]
+pub extern "C" fn lb_enter_benchmark_mode(serial_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(serial_ptr, "serial")
+            .and_then(|serial| failure_capture::on_failure(serial, "enter_benchmark_mode", enter_benchmark_mode(serial)));
+        string_result(result, "benchmark report")
+    })
+}
+
+/// Restores everything `lb_enter_benchmark_mode` changed and reports what could not be restored.
+#[no_mangle]
+pub extern "C" fn lb_exit_benchmark_mode(serial_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        string_result(read_c_str(serial_ptr, "serial").and_then(exit_benchmark_mode), "benchmark report")
+    })
+}
@@ -0,0 +1,217 @@
+use std::io;
+use std::os::raw::c_char;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::adb;
+use crate::animation::{ApngWriter, GifWriter, RawFrame};
+use crate::cancel;
+use crate::error::{ErrorCode, LbError};
+use crate::json::JsonValue;
+use crate::logging::{lb_log, Level};
+use crate::{ffi_guard, read_c_str, string_result};
+
+/// Each frame is a full `screencap` round trip, so devices rarely keep up beyond this.
+const MAX_FPS: u32 = 15;
+const MAX_DURATION_MS: u64 = 30_000;
+
+/// `screencap` raw pixel formats (`PIXEL_FORMAT_*`).
+const RGBA_8888: u32 = 1;
+const RGBX_8888: u32 = 2;
+const BGRA_8888: u32 = 5;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Gif,
+    Apng,
+}
+
+fn output_format(path: &str) -> Result<Format, LbError> {
+    let extension = Path::new(path)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("gif") => Ok(Format::Gif),
+        Some("png") | Some("apng") => Ok(Format::Apng),
+        _ => Err(format!("Burst output must end in .gif, .png or .apng, got '{}'", path).into()),
+    }
+}
+
+/// Raw `screencap` output: width, height, and format as u32le, a u32 color space on
+/// Android 9+, then the pixels.
+fn parse_raw(mut data: Vec<u8>) -> Result<RawFrame, LbError> {
+    let field = |offset: usize| {
+        data.get(offset..offset + 4)
+            .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    };
+    let (Some(width), Some(height), Some(format)) = (field(0), field(4), field(8)) else {
+        return Err(LbError::parse("screencap output is too short"));
+    };
+    let pixels = width as usize * height as usize * 4;
+    let header = data.len().checked_sub(pixels).filter(|header| *header == 12 || *header == 16).ok_or_else(|| {
+        LbError::parse(format!("screencap returned {} bytes for a {}x{} frame", data.len(), width, height))
+    })?;
+    if !matches!(format, RGBA_8888 | RGBX_8888 | BGRA_8888) {
+        return Err(LbError::parse(format!("Unsupported screencap pixel format {}", format)));
+    }
+    let mut rgba = data.split_off(header);
+    if format == BGRA_8888 {
+        for pixel in rgba.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+    }
+    Ok(RawFrame { width, height, rgba })
+}
+
+enum Writer {
+    Gif(GifWriter),
+    Apng(ApngWriter),
+}
+
+/// Opens the writer on the first frame (its size fixes the canvas) and appends `data`.
+/// Returns false when the frame had to be skipped.
+fn write_frame(
+    writer: &mut Option<Writer>,
+    path: &Path,
+    format: Format,
+    data: Vec<u8>,
+    delay_ms: u64,
+) -> Result<bool, LbError> {
+    let io_error = |err: io::Error| {
+        if err.kind() == io::ErrorKind::InvalidData {
+            LbError::parse(err.to_string())
+        } else {
+            LbError::io(format!("Failed to write {}: {}", path.display(), err))
+        }
+    };
+    match format {
+        Format::Gif => {
+            let frame = parse_raw(data)?;
+            if writer.is_none() {
+                *writer = Some(Writer::Gif(GifWriter::create(path, &frame).map_err(io_error)?));
+            }
+            match writer {
+                Some(Writer::Gif(gif)) => gif.frame(&frame, delay_ms).map_err(io_error),
+                _ => Err(LbError::internal("Burst writer does not match its format")),
+            }
+        }
+        Format::Apng => {
+            if writer.is_none() {
+                *writer = Some(Writer::Apng(ApngWriter::create(path, &data).map_err(io_error)?));
+            }
+            match writer {
+                Some(Writer::Apng(apng)) => apng.frame(&data, delay_ms).map_err(io_error),
+                _ => Err(LbError::internal("Burst writer does not match its format")),
+            }
+        }
+    }
+}
+
+/// Captures frame after frame for `duration_ms`, pacing to `fps` when the device is fast
+/// enough. Each frame's delay is the real time until the next capture, so playback speed
+/// matches what happened on screen even when the achieved rate is lower.
+fn capture_burst(serial: &str, fps: u32, duration_ms: u64, output_path: &str) -> Result<String, LbError> {
+    if fps == 0 || fps > MAX_FPS {
+        return Err(format!("fps must be between 1 and {}", MAX_FPS).into());
+    }
+    if duration_ms == 0 || duration_ms > MAX_DURATION_MS {
+        return Err(format!("duration_ms must be between 1 and {}", MAX_DURATION_MS).into());
+    }
+    let format = output_format(output_path)?;
+    let path = Path::new(output_path);
+    let command = if format == Format::Gif { "screencap" } else { "screencap -p" };
+    let interval = Duration::from_millis(1000 / u64::from(fps));
+    let deadline = Duration::from_millis(duration_ms);
+    let started = Instant::now();
+
+    let mut writer = None;
+    let mut pending: Option<(Vec<u8>, Duration)> = None;
+    let mut frames = 0u64;
+    let mut skipped = 0u64;
+    loop {
+        let captured_at = started.elapsed();
+        let data = adb::exec_out(serial, command)?;
+        if let Some((previous, previous_at)) = pending.take() {
+            let delay_ms = (captured_at - previous_at).as_millis() as u64;
+            if write_frame(&mut writer, path, format, previous, delay_ms)? {
+                frames += 1;
+            } else {
+                skipped += 1;
+            }
+        }
+        pending = Some((data, captured_at));
+        let next = captured_at + interval;
+        if next >= deadline {
+            break;
+        }
+        cancel::sleep(next.saturating_sub(started.elapsed()))?;
+    }
+    if let Some((last, _)) = pending {
+        if write_frame(&mut writer, path, format, last, interval.as_millis() as u64)? {
+            frames += 1;
+        } else {
+            skipped += 1;
+        }
+    }
+    let elapsed = started.elapsed();
+    let finished = match writer {
+        Some(Writer::Gif(gif)) => {
+            let size = gif.size();
+            gif.finish().map(|bytes| (size, bytes))
+        }
+        Some(Writer::Apng(apng)) => {
+            let size = apng.size();
+            apng.finish().map(|bytes| (size, bytes))
+        }
+        None => return Err(LbError::new(ErrorCode::CommandFailed, "No frames were captured").with_serial(serial)),
+    };
+    let ((width, height), bytes) =
+        finished.map_err(|err| LbError::io(format!("Failed to write {}: {}", output_path, err)))?;
+    if skipped > 0 {
+        lb_log!(Level::Warn, "recording", "Skipped {} burst frame(s) from {} after a size change", skipped, serial);
+    }
+    let achieved_fps = (frames as f64 / elapsed.as_secs_f64().max(0.001) * 10.0).round() / 10.0;
+    lb_log!(
+        Level::Info,
+        "recording",
+        "Burst of {} frame(s) from {} at {} fps -> {}",
+        frames,
+        serial,
+        achieved_fps,
+        output_path
+    );
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("path", output_path.into()),
+        ("format", if format == Format::Gif { "gif" } else { "apng" }.into()),
+        ("frames", frames.into()),
+        ("skipped_frames", skipped.into()),
+        ("width", width.into()),
+        ("height", height.into()),
+        ("fps", achieved_fps.into()),
+        ("duration_ms", (elapsed.as_millis() as u64).into()),
+        ("bytes", bytes.into()),
+    ])
+    .to_string())
+}
+
+/// Takes repeated screenshots for `duration_ms` at up to `fps` (1-15) and assembles them
+/// into a looping animation at `output_path`: `.gif` (downscaled to at most 480 px wide,
+/// 252-color dithered palette) or `.png` / `.apng` (full-resolution APNG built from the
+/// device's PNGs without re-encoding). Returns `{serial, path, format, frames,
+/// skipped_frames, width, height, fps, duration_ms, bytes}`, where `fps` is the achieved
+/// rate; frames after a rotation are skipped.
+#[no_mangle]
+pub extern "C" fn lb_capture_burst(
+    serial_ptr: *const c_char,
+    fps: u32,
+    duration_ms: u64,
+    output_path_ptr: *const c_char,
+) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(serial_ptr, "serial").and_then(|serial| {
+            read_c_str(output_path_ptr, "output path").and_then(|path| capture_burst(serial, fps, duration_ms, path))
+        });
+        string_result(result, "burst result")
+    })
+}
@@ -0,0 +1,385 @@
+//! Cancellation tokens for long operations. Every thread runs under a current token (its
+//! scoped one, or the process-wide root): `exec` kills a running child when the token fires,
+//! and polling loops wait with `cancel::sleep` instead of `thread::sleep`, so one timeout
+//! and cancellation path covers every module instead of per-call ad-hoc deadlines.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::os::raw::c_char;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, OnceLock, PoisonError, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::device_lock;
+use crate::error::{ErrorCode, LbError};
+use crate::json::JsonValue;
+use crate::logging::{lb_log, Level};
+use crate::{ffi_guard, status_result, string_result};
+
+type Hook = Box<dyn FnOnce() + Send>;
+
+#[derive(Default)]
+struct State {
+    /// Set once, when the token fires; what `check` returns from then on.
+    reason: Option<LbError>,
+    hooks: Vec<(u64, Hook)>,
+    /// Every handle to the token is gone; its deadline timer can exit.
+    released: bool,
+}
+
+#[derive(Default)]
+struct Inner {
+    state: Mutex<State>,
+    wake: Condvar,
+}
+
+static NEXT_HOOK_ID: AtomicU64 = AtomicU64::new(1);
+
+impl Inner {
+    fn fire(&self, reason: LbError) {
+        let hooks = match self.state.lock() {
+            Ok(mut state) if state.reason.is_none() => {
+                state.reason = Some(reason);
+                std::mem::take(&mut state.hooks)
+            }
+            _ => return,
+        };
+        self.wake.notify_all();
+        for (_, hook) in hooks {
+            hook();
+        }
+    }
+
+    fn reason(&self) -> Option<LbError> {
+        self.state.lock().ok().and_then(|state| state.reason.clone())
+    }
+
+    fn add_hook(&self, hook: Hook) -> Option<u64> {
+        let mut state = self.state.lock().ok()?;
+        if state.reason.is_some() {
+            drop(state);
+            hook();
+            return None;
+        }
+        let id = NEXT_HOOK_ID.fetch_add(1, Ordering::Relaxed);
+        state.hooks.push((id, hook));
+        Some(id)
+    }
+
+    fn remove_hook(&self, id: u64) {
+        if let Ok(mut state) = self.state.lock() {
+            state.hooks.retain(|(hook_id, _)| *hook_id != id);
+        }
+    }
+}
+
+/// Shared by every clone of a token; dropping the last one unhooks it from its parent and
+/// lets its deadline timer exit.
+struct Handle {
+    inner: Arc<Inner>,
+    parent: Option<(Arc<Inner>, u64)>,
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        if let Some((parent, hook)) = &self.parent {
+            parent.remove_hook(*hook);
+        }
+        if let Ok(mut state) = self.inner.state.lock() {
+            state.released = true;
+        }
+        self.inner.wake.notify_all();
+    }
+}
+
+#[derive(Clone)]
+pub struct CancelToken {
+    handle: Arc<Handle>,
+}
+
+impl CancelToken {
+    fn new() -> CancelToken {
+        CancelToken {
+            handle: Arc::new(Handle {
+                inner: Arc::new(Inner::default()),
+                parent: None,
+            }),
+        }
+    }
+
+    /// A token that fires when this one does, and can also be cancelled on its own.
+    pub fn child(&self) -> CancelToken {
+        let inner = Arc::new(Inner::default());
+        let weak: Weak<Inner> = Arc::downgrade(&inner);
+        let parent = &self.handle.inner;
+        let parent_weak = Arc::downgrade(parent);
+        // A child fired by its parent reports the parent's reason (a Timeout stays a Timeout).
+        let hook = parent.add_hook(Box::new(move || {
+            if let Some(child) = weak.upgrade() {
+                let reason = parent_weak.upgrade().and_then(|parent| parent.reason());
+                child.fire(reason.unwrap_or_else(cancelled));
+            }
+        }));
+        if hook.is_none() {
+            inner.fire(parent.reason().unwrap_or_else(cancelled));
+        }
+        CancelToken {
+            handle: Arc::new(Handle {
+                inner,
+                parent: hook.map(|hook| (Arc::clone(parent), hook)),
+            }),
+        }
+    }
+
+    /// A child token that also fires with a Timeout error once `timeout` has passed. With
+    /// `async-exec` the deadline is a tokio timer, otherwise a thread that waits it out.
+    pub fn child_with_timeout(&self, timeout: Duration) -> CancelToken {
+        let token = self.child();
+        #[cfg(feature = "async-exec")]
+        {
+            let inner = Arc::downgrade(&token.handle.inner);
+            let timer = crate::runtime::spawn(async move {
+                tokio::time::sleep(timeout).await;
+                if let Some(inner) = inner.upgrade() {
+                    inner.fire(timed_out(timeout));
+                }
+            });
+            if timer.is_ok() {
+                return token;
+            }
+        }
+        let inner = Arc::clone(&token.handle.inner);
+        let deadline = Instant::now() + timeout;
+        thread::spawn(move || {
+            let Ok(mut state) = inner.state.lock() else {
+                return;
+            };
+            loop {
+                if state.reason.is_some() || state.released {
+                    return;
+                }
+                let now = Instant::now();
+                if now >= deadline {
+                    break;
+                }
+                state = match inner.wake.wait_timeout(state, deadline - now) {
+                    Ok((state, _)) => state,
+                    Err(_) => return,
+                };
+            }
+            drop(state);
+            inner.fire(timed_out(timeout));
+        });
+        token
+    }
+
+    pub fn cancel(&self) {
+        self.handle.inner.fire(cancelled());
+    }
+
+    /// `Err` with the Cancelled (or Timeout) error once the token has fired.
+    pub fn check(&self) -> Result<(), LbError> {
+        match self.handle.inner.reason() {
+            Some(reason) => Err(reason),
+            None => Ok(()),
+        }
+    }
+
+    /// Waits `duration`, returning early with the token's error if it fires meanwhile.
+    pub fn sleep(&self, duration: Duration) -> Result<(), LbError> {
+        let deadline = Instant::now() + duration;
+        let inner = &self.handle.inner;
+        let mut state = inner.state.lock().map_err(|_| LbError::internal("Cancel token lock poisoned"))?;
+        loop {
+            if let Some(reason) = &state.reason {
+                return Err(reason.clone());
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(());
+            }
+            state = inner
+                .wake
+                .wait_timeout(state, deadline - now)
+                .map_err(|_| LbError::internal("Cancel token lock poisoned"))?
+                .0;
+        }
+    }
+
+    /// Resolves once the token fires, for `select!` in the `async-exec` command driver.
+    #[cfg(feature = "async-exec")]
+    pub async fn cancelled(&self) {
+        let (fired, wait) = tokio::sync::oneshot::channel();
+        let _hook = self.on_cancel(move || {
+            let _ = fired.send(());
+        });
+        // The sender only goes away unfired if the hook could not be registered.
+        if wait.await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+
+    /// Runs `hook` once when the token fires (immediately if it already has) unless the
+    /// returned guard is dropped first.
+    pub fn on_cancel(&self, hook: impl FnOnce() + Send + 'static) -> OnCancel {
+        let id = self.handle.inner.add_hook(Box::new(hook));
+        OnCancel {
+            inner: Arc::downgrade(&self.handle.inner),
+            id,
+        }
+    }
+}
+
+/// Unregisters an `on_cancel` hook when dropped.
+pub struct OnCancel {
+    inner: Weak<Inner>,
+    id: Option<u64>,
+}
+
+impl Drop for OnCancel {
+    fn drop(&mut self) {
+        if let (Some(inner), Some(id)) = (self.inner.upgrade(), self.id) {
+            inner.remove_hook(id);
+        }
+    }
+}
+
+fn timed_out(timeout: Duration) -> LbError {
+    LbError::new(ErrorCode::Timeout, format!("Operation timed out after {}ms", timeout.as_millis()))
+}
+
+fn cancelled() -> LbError {
+    LbError::new(ErrorCode::Cancelled, "Operation cancelled")
+}
+
+static ROOT: Mutex<Option<CancelToken>> = Mutex::new(None);
+
+/// Parent of every token; what threads without a scoped token run under.
+pub fn root() -> CancelToken {
+    let mut root = ROOT.lock().unwrap_or_else(PoisonError::into_inner);
+    root.get_or_insert_with(CancelToken::new).clone()
+}
+
+/// Fires the root, stopping everything running under it or any token derived from it, and
+/// installs a fresh one so calls made afterwards run normally.
+pub fn cancel_all() {
+    let previous = ROOT.lock().unwrap_or_else(PoisonError::into_inner).replace(CancelToken::new());
+    if let Some(previous) = previous {
+        previous.cancel();
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<CancelToken>> = const { RefCell::new(None) };
+}
+
+/// The token long operations on this thread should honor.
+pub fn current() -> CancelToken {
+    CURRENT
+        .with(|current| current.borrow().clone())
+        .unwrap_or_else(root)
+}
+
+/// Runs `body` with `token` as this thread's current token, restoring the previous one after.
+pub fn scope<T>(token: &CancelToken, body: impl FnOnce() -> T) -> T {
+    let previous = CURRENT.with(|current| current.replace(Some(token.clone())));
+    let result = body();
+    CURRENT.with(|current| *current.borrow_mut() = previous);
+    result
+}
+
+/// `thread::spawn` that carries the caller's current token (and the device-lock owner it
+/// acts for) into the new thread, for fan-out work (parallel commands, batches) that must
+/// stop with its caller.
+pub fn spawn<T: Send + 'static>(body: impl FnOnce() -> T + Send + 'static) -> thread::JoinHandle<T> {
+    let token = current();
+    let owner = device_lock::acting_owner();
+    thread::spawn(move || device_lock::act_as(owner, || scope(&token, body)))
+}
+
+/// `current().sleep(duration)`: the polling-loop wait.
+pub fn sleep(duration: Duration) -> Result<(), LbError> {
+    current().sleep(duration)
+}
+
+static NEXT_TOKEN_ID: AtomicU64 = AtomicU64::new(1);
+static TOKENS: OnceLock<Mutex<HashMap<u64, CancelToken>>> = OnceLock::new();
+
+fn token_registry() -> &'static Mutex<HashMap<u64, CancelToken>> {
+    TOKENS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+pub fn token(id: u64) -> Result<CancelToken, LbError> {
+    token_registry()
+        .lock()
+        .map_err(|_| LbError::internal("Cancel token registry is unavailable"))?
+        .get(&id)
+        .cloned()
+        .ok_or_else(|| LbError::not_found(format!("No cancel token {}", id)))
+}
+
+fn create_token(timeout_ms: u64) -> Result<u64, LbError> {
+    let token = if timeout_ms == 0 {
+        root().child()
+    } else {
+        root().child_with_timeout(Duration::from_millis(timeout_ms))
+    };
+    let id = NEXT_TOKEN_ID.fetch_add(1, Ordering::Relaxed);
+    token_registry()
+        .lock()
+        .map_err(|_| LbError::internal("Cancel token registry is unavailable"))?
+        .insert(id, token);
+    Ok(id)
+}
+
+fn set_thread_token(id: u64) -> Result<(), LbError> {
+    let token = if id == 0 { None } else { Some(token(id)?) };
+    CURRENT.with(|current| *current.borrow_mut() = token);
+    Ok(())
+}
+
+/// Creates a cancel token (firing on its own after `timeout_ms`, unless 0) and returns
+/// `{"handle"}`. Pass it to `lb_set_thread_cancel_token` or as `cancel_token` in an
+/// `lb_invoke` request; `lb_cancel_token_cancel` from any thread stops whatever runs under it.
+#[no_mangle]
+pub extern "C" fn lb_cancel_token_new(timeout_ms: u64) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = create_token(timeout_ms).map(|id| JsonValue::object(vec![("handle", id.into())]).to_string());
+        string_result(result, "cancel token")
+    })
+}
+
+/// Fires the token: running adb children under it are killed and waits return Cancelled.
+#[no_mangle]
+pub extern "C" fn lb_cancel_token_cancel(handle: u64) -> i32 {
+    ffi_guard(0, || {
+        status_result(token(handle).map(|token| {
+            lb_log!(Level::Info, "exec", "Cancel token {} fired", handle);
+            token.cancel();
+        }))
+    })
+}
+
+/// Forgets the token; operations already running under it keep their copy.
+#[no_mangle]
+pub extern "C" fn lb_cancel_token_free(handle: u64) -> i32 {
+    ffi_guard(0, || {
+        let removed = token_registry()
+            .lock()
+            .map_err(|_| LbError::internal("Cancel token registry is unavailable"))
+            .and_then(|mut tokens| {
+                tokens
+                    .remove(&handle)
+                    .map(|_| ())
+                    .ok_or_else(|| LbError::not_found(format!("No cancel token {}", handle)))
+            });
+        status_result(removed)
+    })
+}
+
+/// Makes later calls on the calling thread run under token `handle` (0 restores the root).
+#[no_mangle]
+pub extern "C" fn lb_set_thread_cancel_token(handle: u64) -> i32 {
+    ffi_guard(0, || status_result(set_thread_token(handle)))
+}
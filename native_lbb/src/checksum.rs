@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::os::raw::c_char;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+use crate::adb::{self, shell_quote};
+use crate::error::{ErrorCode, LbError};
+use crate::json::JsonValue;
+use crate::logging::{lb_log, Level};
+use crate::remote_fs;
+use crate::{ffi_guard, read_c_str, string_result};
+
+/// Paths per device-side invocation, to stay well under the shell's argument limit.
+const DEVICE_BATCH: usize = 64;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    Md5,
+    Sha256,
+}
+
+impl Algorithm {
+    fn parse(name: &str) -> Result<Algorithm, LbError> {
+        match name {
+            "md5" => Ok(Algorithm::Md5),
+            "sha256" => Ok(Algorithm::Sha256),
+            _ => Err(format!("Unknown checksum algorithm '{}'; expected md5 or sha256", name).into()),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Algorithm::Md5 => "md5",
+            Algorithm::Sha256 => "sha256",
+        }
+    }
+}
+
+/// Per-round left-rotation amounts (RFC 1321).
+const MD5_SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+
+/// `floor(|sin(i + 1)| * 2^32)`; exact in f64 for all 64 entries.
+fn md5_constants() -> &'static [u32; 64] {
+    static TABLE: OnceLock<[u32; 64]> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut table = [0u32; 64];
+        for (index, value) in table.iter_mut().enumerate() {
+            *value = ((index as f64 + 1.0).sin().abs() * 4_294_967_296.0) as u32;
+        }
+        table
+    })
+}
+
+/// FIPS 180-4 round constants.
+const SHA256_CONSTANTS: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// MD5 and SHA-256 both hash 64-byte blocks with the same padding; they differ in the
+/// compression function and byte order. Only used to compare files with the device's
+/// `md5sum` / `sha256sum`, not for anything secret.
+struct Hasher {
+    algorithm: Algorithm,
+    state: [u32; 8],
+    buffer: Vec<u8>,
+    length: u64,
+}
+
+impl Hasher {
+    fn new(algorithm: Algorithm) -> Hasher {
+        let state = match algorithm {
+            Algorithm::Md5 => [0x6745_2301, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476, 0, 0, 0, 0],
+            Algorithm::Sha256 => [
+                0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+            ],
+        };
+        Hasher {
+            algorithm,
+            state,
+            buffer: Vec::with_capacity(64),
+            length: 0,
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.length = self.length.wrapping_add(data.len() as u64);
+        while !data.is_empty() {
+            let take = (64 - self.buffer.len()).min(data.len());
+            self.buffer.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.buffer.len() == 64 {
+                let block = std::mem::take(&mut self.buffer);
+                match self.algorithm {
+                    Algorithm::Md5 => self.md5_block(&block),
+                    Algorithm::Sha256 => self.sha256_block(&block),
+                }
+                self.buffer = block;
+                self.buffer.clear();
+            }
+        }
+    }
+
+    fn md5_block(&mut self, block: &[u8]) {
+        let words: Vec<u32> = block
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+            .collect();
+        let [mut a, mut b, mut c, mut d] = [self.state[0], self.state[1], self.state[2], self.state[3]];
+        for index in 0..64 {
+            let (mix, word) = match index / 16 {
+                0 => ((b & c) | (!b & d), index),
+                1 => ((d & b) | (!d & c), (5 * index + 1) % 16),
+                2 => (b ^ c ^ d, (3 * index + 5) % 16),
+                _ => (c ^ (b | !d), (7 * index) % 16),
+            };
+            let sum = mix
+                .wrapping_add(a)
+                .wrapping_add(md5_constants()[index])
+                .wrapping_add(words[word]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(sum.rotate_left(MD5_SHIFTS[(index / 16) * 4 + index % 4]));
+        }
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+
+    fn sha256_block(&mut self, block: &[u8]) {
+        let mut words = [0u32; 64];
+        for (word, bytes) in words.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        for index in 16..64 {
+            let (early, late) = (words[index - 15], words[index - 2]);
+            let s0 = early.rotate_right(7) ^ early.rotate_right(18) ^ (early >> 3);
+            let s1 = late.rotate_right(17) ^ late.rotate_right(19) ^ (late >> 10);
+            words[index] = words[index - 16]
+                .wrapping_add(s0)
+                .wrapping_add(words[index - 7])
+                .wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for (constant, word) in SHA256_CONSTANTS.iter().zip(words) {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let choice = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(choice)
+                .wrapping_add(*constant)
+                .wrapping_add(word);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let majority = (a & b) ^ (a & c) ^ (b & c);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(s0.wrapping_add(majority));
+        }
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+
+    fn hex_digest(mut self) -> String {
+        let bits = self.length.wrapping_mul(8);
+        let mut padding = vec![0x80u8];
+        padding.resize(1 + (119 - (self.length % 64) as usize) % 64, 0);
+        let length = self.length;
+        self.update(&padding);
+        match self.algorithm {
+            Algorithm::Md5 => self.update(&bits.to_le_bytes()),
+            Algorithm::Sha256 => self.update(&bits.to_be_bytes()),
+        }
+        self.length = length;
+        let bytes: Vec<u8> = match self.algorithm {
+            Algorithm::Md5 => self.state[..4].iter().flat_map(|word| word.to_le_bytes()).collect(),
+            Algorithm::Sha256 => self.state.iter().flat_map(|word| word.to_be_bytes()).collect(),
+        };
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
+
+/// Lowercase hex digest of a local file, as `md5sum` / `sha256sum` print it.
+pub fn local_checksum(path: &Path, algorithm: Algorithm) -> Result<String, LbError> {
+    let mut file = File::open(path).map_err(|err| LbError::io(format!("Failed to open {}: {}", path.display(), err)))?;
+    let mut hasher = Hasher::new(algorithm);
+    let mut chunk = vec![0u8; 64 * 1024];
+    loop {
+        let read = file
+            .read(&mut chunk)
+            .map_err(|err| LbError::io(format!("Failed to read {}: {}", path.display(), err)))?;
+        if read == 0 {
+            return Ok(hasher.hex_digest());
+        }
+        hasher.update(&chunk[..read]);
+    }
+}
+
+/// `(serial, algorithm)` -> command prefix that works on that device, e.g. `toybox md5sum`.
+fn tools() -> &'static Mutex<HashMap<(String, &'static str), String>> {
+    static TOOLS: OnceLock<Mutex<HashMap<(String, &'static str), String>>> = OnceLock::new();
+    TOOLS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Old builds lack the standalone `sha256sum` applet link, and some vendor images only
+/// ship busybox; try each spelling once per device and remember the one that works.
+fn device_tool(serial: &str, algorithm: Algorithm) -> Result<String, LbError> {
+    let key = (serial.to_string(), algorithm.name());
+    if let Some(tool) = tools().lock().ok().and_then(|tools| tools.get(&key).cloned()) {
+        return Ok(tool);
+    }
+    let command = format!("{}sum", algorithm.name());
+    let probe = format!(
+        "for t in '' toybox busybox; do echo | $t {} >/dev/null 2>&1 && {{ echo \"tool:$t\"; exit 0; }}; done; true",
+        command
+    );
+    let output = adb::shell(serial, &probe)?;
+    let prefix = output
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("tool:"))
+        .ok_or_else(|| {
+            LbError::new(
+                ErrorCode::CommandFailed,
+                format!("{} has neither {} nor a toybox/busybox applet for it", serial, command),
+            )
+            .with_serial(serial)
+        })?;
+    let tool = if prefix.is_empty() { command } else { format!("{} {}", prefix, command) };
+    lb_log!(Level::Debug, "adb", "Using '{}' for checksums on {}", tool, serial);
+    if let Ok(mut tools) = tools().lock() {
+        tools.insert(key, tool.clone());
+    }
+    Ok(tool)
+}
+
+/// Device-side digests keyed by path. Unreadable or missing files are simply absent.
+pub fn device_checksums(serial: &str, paths: &[String], algorithm: Algorithm) -> Result<HashMap<String, String>, LbError> {
+    let mut sums = HashMap::new();
+    if paths.is_empty() {
+        return Ok(sums);
+    }
+    let tool = device_tool(serial, algorithm)?;
+    for batch in paths.chunks(DEVICE_BATCH) {
+        let quoted: Vec<String> = batch.iter().map(|path| shell_quote(path)).collect();
+        let output = adb::shell(serial, &format!("{} {} 2>/dev/null; true", tool, quoted.join(" ")))?;
+        for line in output.lines() {
+            if let Some((sum, path)) = line.split_once("  ") {
+                sums.insert(path.to_string(), sum.to_ascii_lowercase());
+            }
+        }
+    }
+    Ok(sums)
+}
+
+pub fn device_checksum(serial: &str, path: &str, algorithm: Algorithm) -> Result<String, LbError> {
+    let tool = device_tool(serial, algorithm)?;
+    let output = adb::shell(serial, &format!("{} {}", tool, shell_quote(path))).map_err(|err| {
+        let code = if err.message.contains("No such file") {
+            ErrorCode::NotFound
+        } else if err.message.contains("Permission denied") || err.message.contains("Is a directory") {
+            ErrorCode::PermissionDenied
+        } else {
+            err.code
+        };
+        LbError::new(code, format!("Cannot checksum {} on {}: {}", path, serial, err.message)).with_serial(serial)
+    })?;
+    output
+        .split_whitespace()
+        .next()
+        .filter(|sum| sum.chars().all(|ch| ch.is_ascii_hexdigit()))
+        .map(str::to_ascii_lowercase)
+        .ok_or_else(|| LbError::parse(format!("Unexpected {} output: {}", tool, output.trim())).with_serial(serial))
+}
+
+/// Compares a local file with its device copy after a transfer; ChecksumMismatch when
+/// the bytes differ.
+pub fn verify_transfer(serial: &str, local: &Path, remote: &str, algorithm: Algorithm) -> Result<(), LbError> {
+    let local_sum = local_checksum(local, algorithm)?;
+    let remote_sum = device_checksum(serial, remote, algorithm)?;
+    if local_sum == remote_sum {
+        return Ok(());
+    }
+    Err(LbError::new(
+        ErrorCode::ChecksumMismatch,
+        format!(
+            "{} mismatch between {} ({}) and {} ({})",
+            algorithm.name(),
+            local.display(),
+            local_sum,
+            remote,
+            remote_sum
+        ),
+    )
+    .with_serial(serial))
+}
+
+fn file_checksum(serial: &str, path: &str, algorithm: Option<&str>) -> Result<String, LbError> {
+    remote_fs::validate_path(path)?;
+    let algorithm = Algorithm::parse(algorithm.unwrap_or("md5"))?;
+    let checksum = device_checksum(serial, path, algorithm)?;
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("path", path.into()),
+        ("algorithm", algorithm.name().into()),
+        ("checksum", checksum.into()),
+    ])
+    .to_string())
+}
+
+/// Hashes a device file with `md5sum` / `sha256sum` (falling back to the toybox or
+/// busybox applet) and returns `{serial, path, algorithm, checksum}`. `algo` is `md5`
+/// (also when null) or `sha256`; the checksum is lowercase hex.
+#[no_mangle]
+pub extern "C" fn lb_file_checksum(serial_ptr: *const c_char, path_ptr: *const c_char, algo_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let algorithm = if algo_ptr.is_null() {
+            Ok(None)
+        } else {
+            read_c_str(algo_ptr, "algorithm").map(Some)
+        };
+        let result = read_c_str(serial_ptr, "serial").and_then(|serial| {
+            read_c_str(path_ptr, "path").and_then(|path| algorithm.and_then(|algorithm| file_checksum(serial, path, algorithm)))
+        });
+        string_result(result, "checksum")
+    })
+}
@@ -0,0 +1,198 @@
+//! `lbb`: the library core as a command-line tool, so CI jobs can script exactly what the
+//! GUI does without loading the shared library. `src/bin/lbb.rs` only forwards `argv` here.
+
+use std::fs;
+use std::io::{self, Write};
+
+use crate::adb;
+use crate::device_meta;
+use crate::error::{ErrorCode, LbError};
+use crate::install;
+use crate::json::{self, JsonValue};
+
+const USAGE: &str = "\
+usage: lbb <command> [args]
+
+  devices [--json]                         attached devices with stored aliases
+  run <serial> <command...>                adb shell command; exits with its status
+  screenshot <serial> <out.png|->          PNG screenshot
+  ui-dump <serial> [out.xml|-]             uiautomator hierarchy (stdout by default)
+  record start <serial> <remote_path>      start screenrecord on the device
+  record stop <serial>                     stop screenrecord and let it finalize the file
+  install <serial> <apk> [-r] [-d] [-g]    install an APK (replace, downgrade, grant)
+  version                                  library version and capabilities
+";
+
+/// Exit statuses: 0 success, 1 library error, 2 usage error; `run` passes the device's own.
+const EXIT_FAILURE: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+
+enum Failure {
+    Usage(String),
+    Error(LbError),
+}
+
+impl From<LbError> for Failure {
+    fn from(err: LbError) -> Self {
+        Failure::Error(err)
+    }
+}
+
+impl From<io::Error> for Failure {
+    fn from(err: io::Error) -> Self {
+        Failure::Error(LbError::io(err.to_string()))
+    }
+}
+
+fn usage(message: &str) -> Failure {
+    Failure::Usage(message.to_string())
+}
+
+fn write_output(target: Option<&str>, data: &[u8]) -> Result<(), Failure> {
+    match target {
+        None | Some("-") => io::stdout().write_all(data)?,
+        Some(path) => fs::write(path, data)
+            .map_err(|err| LbError::io(format!("Failed to write {}: {}", path, err)))?,
+    }
+    Ok(())
+}
+
+fn devices(args: &[String]) -> Result<i32, Failure> {
+    let as_json = match args {
+        [] => false,
+        [flag] if flag == "--json" => true,
+        _ => return Err(usage("devices takes only --json")),
+    };
+    let listed = device_meta::list_devices_json()?;
+    if as_json {
+        println!("{}", listed);
+        return Ok(0);
+    }
+    let listed = json::parse(&listed)?;
+    let field = |device: &JsonValue, name: &str| device.get(name).and_then(JsonValue::as_str).unwrap_or("-").to_string();
+    for device in listed.as_array().into_iter().flatten() {
+        println!(
+            "{}\t{}\t{}\t{}",
+            field(device, "serial"),
+            field(device, "state"),
+            field(device, "model"),
+            field(device, "alias")
+        );
+    }
+    Ok(0)
+}
+
+fn run(args: &[String]) -> Result<i32, Failure> {
+    let [serial, command @ ..] = args else {
+        return Err(usage("run needs <serial> <command...>"));
+    };
+    if command.is_empty() {
+        return Err(usage("run needs a command"));
+    }
+    let command = command.join(" ");
+    let output = adb::run_adb(Some(serial), &["shell", &command])?;
+    print!("{}", output.stdout);
+    eprint!("{}", output.stderr);
+    Ok(output.exit_code.unwrap_or(EXIT_FAILURE))
+}
+
+fn screenshot(args: &[String]) -> Result<i32, Failure> {
+    let [serial, target] = args else {
+        return Err(usage("screenshot needs <serial> <out.png|->"));
+    };
+    write_output(Some(target), &adb::screencap_png(serial)?)?;
+    Ok(0)
+}
+
+fn ui_dump(args: &[String]) -> Result<i32, Failure> {
+    let (serial, target) = match args {
+        [serial] => (serial, None),
+        [serial, target] => (serial, Some(target.as_str())),
+        _ => return Err(usage("ui-dump needs <serial> [out.xml|-]")),
+    };
+    write_output(target, &adb::dump_ui_xml(serial)?)?;
+    Ok(0)
+}
+
+/// `record start` leaves the adb child running after `lbb` exits; `record stop` from a
+/// later invocation finds no tracked child and stops it through `pkill -SIGINT` alone.
+fn record(args: &[String]) -> Result<i32, Failure> {
+    match args {
+        [action, serial, remote_path] if action == "start" => crate::start_screen_record(serial, remote_path)?,
+        [action, serial] if action == "stop" => crate::stop_screen_record(serial)?,
+        _ => return Err(usage("record needs start <serial> <remote_path> or stop <serial>")),
+    }
+    Ok(0)
+}
+
+fn install(args: &[String]) -> Result<i32, Failure> {
+    let [serial, apk, flags @ ..] = args else {
+        return Err(usage("install needs <serial> <apk>"));
+    };
+    let mut options = Vec::new();
+    for flag in flags {
+        let option = match flag.as_str() {
+            "-r" => "replace",
+            "-d" => "downgrade",
+            "-g" => "grant_permissions",
+            _ => return Err(usage(&format!("Unknown install flag '{}'", flag))),
+        };
+        options.push((option, JsonValue::Bool(true)));
+    }
+    let options = JsonValue::object(options).to_string();
+    let result = install::install_apk(serial, apk, Some(&options), None)?;
+    println!("{}", result);
+    Ok(if result.get("success").and_then(JsonValue::as_bool) == Some(true) {
+        0
+    } else {
+        EXIT_FAILURE
+    })
+}
+
+fn version() -> i32 {
+    let capabilities: Vec<JsonValue> = crate::version::capabilities().into_iter().map(JsonValue::from).collect();
+    let mut info = crate::version::version_json();
+    if let JsonValue::Object(fields) = &mut info {
+        fields.push(("capabilities".to_string(), capabilities.into()));
+    }
+    println!("{}", info);
+    0
+}
+
+/// Runs one `lbb` invocation (`args` without the program name) and returns its exit status.
+pub fn main(args: &[String]) -> i32 {
+    let Some((command, rest)) = args.split_first() else {
+        eprint!("{}", USAGE);
+        return EXIT_USAGE;
+    };
+    let outcome = match command.as_str() {
+        "devices" => devices(rest),
+        "run" => run(rest),
+        "screenshot" => screenshot(rest),
+        "ui-dump" => ui_dump(rest),
+        "record" => record(rest),
+        "install" => install(rest),
+        "version" | "--version" => Ok(version()),
+        "help" | "--help" | "-h" => {
+            print!("{}", USAGE);
+            Ok(0)
+        }
+        other => Err(usage(&format!("Unknown command '{}'", other))),
+    };
+    match outcome {
+        Ok(status) => status,
+        Err(Failure::Usage(message)) => {
+            eprintln!("lbb: {}\n\n{}", message, USAGE);
+            EXIT_USAGE
+        }
+        Err(Failure::Error(err)) => {
+            let serial = err.serial.as_deref().map(|serial| format!(" [{}]", serial)).unwrap_or_default();
+            eprintln!("lbb: {}{} ({})", err.message, serial, err.code.name());
+            if err.code == ErrorCode::InvalidArgument {
+                EXIT_USAGE
+            } else {
+                EXIT_FAILURE
+            }
+        }
+    }
+}
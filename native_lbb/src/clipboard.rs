@@ -0,0 +1,114 @@
+use std::os::raw::c_char;
+
+use crate::adb;
+use crate::device_lock::ensure_device_unlocked;
+use crate::error::LbError;
+use crate::json::JsonValue;
+use crate::logging::{lb_log, Level};
+use crate::{ffi_guard, read_c_str, status_result, string_result};
+
+/// Clipper (`ca.zgrs.clipper`) answers these broadcasts on releases without `cmd clipboard`.
+const HELPER_PACKAGE: &str = "ca.zgrs.clipper";
+const HELPER_GET_ACTION: &str = "clipper.get";
+const HELPER_SET_ACTION: &str = "clipper.set";
+
+/// What `cmd` prints when the clipboard service has no shell command handler.
+const UNSUPPORTED_MARKERS: [&str; 3] = ["No shell command implementation", "Unknown command", "Can't find service"];
+
+fn unsupported(output: &str) -> bool {
+    UNSUPPORTED_MARKERS.iter().any(|marker| output.contains(marker))
+}
+
+/// Runs `cmd clipboard <args>`; `Ok(None)` means this release has no clipboard shell command.
+fn clipboard_command(serial: &str, args: &str) -> Result<Option<String>, LbError> {
+    let command = format!("cmd clipboard {}", args);
+    let output = adb::run_adb(Some(serial), &["shell", &command])?;
+    let combined = format!("{}{}", output.stdout, output.stderr);
+    if unsupported(&combined) {
+        return Ok(None);
+    }
+    if !output.success() {
+        return Err(LbError::new(
+            adb::classify_failure(&combined),
+            format!("cmd clipboard failed (exit={}): {}", output.exit_code.unwrap_or(-1), combined.trim()),
+        )
+        .with_serial(serial));
+    }
+    Ok(Some(output.stdout))
+}
+
+/// `Broadcast completed: result=-1, data="copied text"`; result 0 means nobody handled it.
+fn parse_broadcast(output: &str) -> Option<Option<String>> {
+    let line = output.lines().find(|line| line.starts_with("Broadcast completed:"))?;
+    if !line.contains("result=-1") {
+        return None;
+    }
+    let start = output.find("data=\"")? + "data=\"".len();
+    let end = output.rfind('"').filter(|end| *end >= start);
+    Some(end.map(|end| output[start..end].to_string()))
+}
+
+fn helper_broadcast(serial: &str, args: &str) -> Result<Option<String>, LbError> {
+    let output = adb::shell(serial, &format!("am broadcast -p {} {}", HELPER_PACKAGE, args))?;
+    parse_broadcast(&output).ok_or_else(|| {
+        LbError::not_found(format!(
+            "{} has no `cmd clipboard`; install the {} helper app for clipboard access",
+            serial, HELPER_PACKAGE
+        ))
+        .with_serial(serial)
+    })
+}
+
+fn get_clipboard(serial: &str) -> Result<String, LbError> {
+    let (text, method) = match clipboard_command(serial, "get-primary-clip")? {
+        // The shell command ends the clip with a newline of its own.
+        Some(output) => {
+            let text = output.strip_suffix('\n').unwrap_or(&output);
+            ((!text.is_empty()).then(|| text.to_string()), "cmd")
+        }
+        None => (helper_broadcast(serial, &format!("-a {}", HELPER_GET_ACTION))?, "helper"),
+    };
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("text", text.into()),
+        ("method", method.into()),
+    ])
+    .to_string())
+}
+
+fn set_clipboard(serial: &str, text: &str) -> Result<(), LbError> {
+    ensure_device_unlocked(serial)?;
+    // One quoted argument survives the device shell intact, whatever the text holds.
+    let quoted = adb::shell_quote(text);
+    let method = match clipboard_command(serial, &format!("set-primary-clip {}", quoted))? {
+        Some(_) => "cmd",
+        None => {
+            helper_broadcast(serial, &format!("-a {} -e text {}", HELPER_SET_ACTION, quoted))?;
+            "helper"
+        }
+    };
+    lb_log!(Level::Info, "shell", "Set clipboard on {} via {} ({} chars)", serial, method, text.chars().count());
+    Ok(())
+}
+
+/// Returns `{serial, text, method}` for the device's primary clip; `text` is null when the
+/// clipboard is empty and `method` is `cmd` or `helper`. Releases without `cmd clipboard`
+/// need the Clipper helper app installed (NotFound otherwise).
+#[no_mangle]
+pub extern "C" fn lb_get_clipboard(serial_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        string_result(read_c_str(serial_ptr, "serial").and_then(get_clipboard), "clipboard")
+    })
+}
+
+/// Replaces the device's primary clip with `text`, using the same
+/// `cmd clipboard` / helper fallback as `lb_get_clipboard`.
+#[no_mangle]
+pub extern "C" fn lb_set_clipboard(serial_ptr: *const c_char, text_ptr: *const c_char) -> i32 {
+    ffi_guard(0, || {
+        let result = read_c_str(serial_ptr, "serial")
+            .and_then(|serial| read_c_str(text_ptr, "clipboard text").and_then(|text| set_clipboard(serial, text)));
+        status_result(result)
+    })
+}
+
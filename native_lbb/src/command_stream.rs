@@ -0,0 +1,265 @@
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::os::raw::c_char;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::Instant;
+
+use crate::error::{ErrorCode, LbError};
+use crate::exec;
+use crate::history;
+use crate::json::JsonValue;
+use crate::logging::{lb_log, Level};
+use crate::transcript;
+use crate::{ffi_guard, handle_result, read_c_str, status_result, string_result};
+
+/// Unread output kept per pipe; beyond this the oldest bytes are dropped and counted.
+const MAX_BUFFERED_BYTES: usize = 8 * 1024 * 1024;
+const READ_CHUNK_BYTES: usize = 16 * 1024;
+
+#[derive(Default)]
+struct PipeBuffer {
+    data: Vec<u8>,
+    dropped: u64,
+    closed: bool,
+}
+
+impl PipeBuffer {
+    fn push(&mut self, chunk: &[u8]) {
+        self.data.extend_from_slice(chunk);
+        if self.data.len() > MAX_BUFFERED_BYTES {
+            let excess = self.data.len() - MAX_BUFFERED_BYTES;
+            self.data.drain(..excess);
+            self.dropped += excess as u64;
+        }
+    }
+
+    /// Takes everything up to the last complete UTF-8 character; a split character stays
+    /// buffered for the next read unless the pipe is closed.
+    fn drain_text(&mut self) -> String {
+        let end = match std::str::from_utf8(&self.data) {
+            Ok(_) => self.data.len(),
+            Err(err) if err.error_len().is_none() && !self.closed => err.valid_up_to(),
+            Err(_) => self.data.len(),
+        };
+        let taken: Vec<u8> = self.data.drain(..end).collect();
+        String::from_utf8_lossy(&taken).into_owned()
+    }
+}
+
+type SharedBuffer = Arc<Mutex<PipeBuffer>>;
+
+struct CommandStream {
+    argv: Vec<String>,
+    child: Child,
+    /// Open only for `lb_command_start_with_stdin` until `lb_command_close_stdin`.
+    stdin: Option<ChildStdin>,
+    stdout: SharedBuffer,
+    stderr: SharedBuffer,
+    started: Instant,
+    exit_code: Option<i32>,
+    exited: bool,
+}
+
+static NEXT_COMMAND_ID: AtomicU64 = AtomicU64::new(1);
+static COMMANDS: OnceLock<Mutex<HashMap<u64, CommandStream>>> = OnceLock::new();
+
+fn command_registry() -> &'static Mutex<HashMap<u64, CommandStream>> {
+    COMMANDS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn spawn_pump(mut pipe: impl Read + Send + 'static, buffer: SharedBuffer) {
+    thread::spawn(move || {
+        let mut chunk = vec![0u8; READ_CHUNK_BYTES];
+        loop {
+            match pipe.read(&mut chunk) {
+                Ok(0) | Err(_) => break,
+                Ok(read) => match buffer.lock() {
+                    Ok(mut guard) => guard.push(&chunk[..read]),
+                    Err(_) => break,
+                },
+            }
+        }
+        if let Ok(mut guard) = buffer.lock() {
+            guard.closed = true;
+        }
+    });
+}
+
+fn start_command(command: &str, with_stdin: bool) -> Result<u64, LbError> {
+    let argv = exec::shlex_split(command)?;
+    if argv.is_empty() {
+        return Err("Empty command".into());
+    }
+    let mut child = Command::new(&argv[0])
+        .args(&argv[1..])
+        .stdin(if with_stdin { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| LbError::new(ErrorCode::SpawnFailed, format!("Failed to spawn {}: {}", argv[0], err)).with_command(&argv))?;
+    let stdin = child.stdin.take();
+    let stdout = SharedBuffer::default();
+    let stderr = SharedBuffer::default();
+    spawn_pump(child.stdout.take().ok_or("Failed to capture command output")?, stdout.clone());
+    spawn_pump(child.stderr.take().ok_or("Failed to capture command errors")?, stderr.clone());
+    let id = NEXT_COMMAND_ID.fetch_add(1, Ordering::Relaxed);
+    lb_log!(Level::Info, "exec", "Command {} started: {}", id, history::redacted(&argv).0.join(" "));
+    command_registry()
+        .lock()
+        .map_err(|_| LbError::internal("Command registry poisoned"))?
+        .insert(
+            id,
+            CommandStream {
+                argv,
+                child,
+                stdin,
+                stdout,
+                stderr,
+                started: Instant::now(),
+                exit_code: None,
+                exited: false,
+            },
+        );
+    Ok(id)
+}
+
+fn read_command(id: u64) -> Result<String, LbError> {
+    let mut registry = command_registry()
+        .lock()
+        .map_err(|_| LbError::internal("Command registry poisoned"))?;
+    let command = registry
+        .get_mut(&id)
+        .ok_or_else(|| LbError::not_found(format!("No running command with handle {}", id)))?;
+    if !command.exited {
+        if let Ok(Some(status)) = command.child.try_wait() {
+            command.exited = true;
+            command.exit_code = status.code();
+            transcript::record_command(&command.argv, command.started.elapsed(), command.exit_code);
+            lb_log!(Level::Debug, "exec", "Command {} exited ({:?})", id, command.exit_code);
+        }
+    }
+    let drain = |buffer: &SharedBuffer| -> Result<(String, u64, bool), LbError> {
+        let mut guard = buffer.lock().map_err(|_| LbError::internal("Command buffer poisoned"))?;
+        let text = guard.drain_text();
+        let dropped = std::mem::take(&mut guard.dropped);
+        Ok((text, dropped, guard.closed && guard.data.is_empty()))
+    };
+    let (stdout, stdout_dropped, stdout_done) = drain(&command.stdout)?;
+    let (stderr, stderr_dropped, stderr_done) = drain(&command.stderr)?;
+    // Finished only once the process exited and both pipes were read to the end.
+    let finished = command.exited && stdout_done && stderr_done;
+    Ok(JsonValue::object(vec![
+        ("stdout", stdout.into()),
+        ("stderr", stderr.into()),
+        ("dropped_bytes", (stdout_dropped + stderr_dropped).into()),
+        ("finished", finished.into()),
+        ("exit_code", command.exit_code.into()),
+    ])
+    .to_string())
+}
+
+/// Unregisters every command and hands back the processes still running, for `lb_shutdown`.
+pub fn take_all() -> Vec<Child> {
+    command_registry()
+        .lock()
+        .map(|mut guard| {
+            guard
+                .drain()
+                .filter(|(_, command)| !command.exited)
+                .map(|(_, command)| command.child)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn take_stdin(id: u64) -> Result<ChildStdin, LbError> {
+    command_registry()
+        .lock()
+        .map_err(|_| LbError::internal("Command registry poisoned"))?
+        .get_mut(&id)
+        .ok_or_else(|| LbError::not_found(format!("No running command with handle {}", id)))?
+        .stdin
+        .take()
+        .ok_or_else(|| format!("Command {} has no open stdin; start it with lb_command_start_with_stdin", id).into())
+}
+
+fn write_stdin(id: u64, data: &[u8]) -> Result<(), LbError> {
+    // Written outside the registry lock: a child that is slow to read must not stall
+    // every other handle.
+    let mut stdin = take_stdin(id)?;
+    let written = stdin.write_all(data).and_then(|_| stdin.flush());
+    if let Ok(mut registry) = command_registry().lock() {
+        if let Some(command) = registry.get_mut(&id) {
+            command.stdin = Some(stdin);
+        }
+    }
+    written.map_err(|err| LbError::io(format!("Failed to write to command {}: {}", id, err)))
+}
+
+fn stop_command(id: u64) -> Result<(), LbError> {
+    let mut command = command_registry()
+        .lock()
+        .map_err(|_| LbError::internal("Command registry poisoned"))?
+        .remove(&id)
+        .ok_or_else(|| LbError::not_found(format!("No running command with handle {}", id)))?;
+    if !command.exited {
+        let _ = command.child.kill();
+        let exit_code = command.child.wait().ok().and_then(|status| status.code());
+        transcript::record_command(&command.argv, command.started.elapsed(), exit_code);
+        lb_log!(Level::Info, "exec", "Command {} stopped", id);
+    }
+    Ok(())
+}
+
+/// Starts `command` (split like the parallel runner) with output buffered for
+/// `lb_command_read`. Returns a handle, or 0 on error.
+#[no_mangle]
+pub extern "C" fn lb_command_start(command_ptr: *const c_char) -> u64 {
+    ffi_guard(0, || {
+        handle_result(read_c_str(command_ptr, "command").and_then(|command| start_command(command, false)))
+    })
+}
+
+/// `lb_command_start` with stdin kept open for `lb_command_write`; the command sees EOF
+/// after `lb_command_close_stdin` (or `lb_command_stop`).
+#[no_mangle]
+pub extern "C" fn lb_command_start_with_stdin(command_ptr: *const c_char) -> u64 {
+    ffi_guard(0, || {
+        handle_result(read_c_str(command_ptr, "command").and_then(|command| start_command(command, true)))
+    })
+}
+
+/// Writes `len` bytes from `data` to the command's stdin, blocking until the pipe accepts them.
+#[no_mangle]
+pub extern "C" fn lb_command_write(handle: u64, data: *const u8, len: usize) -> i32 {
+    ffi_guard(0, || {
+        if data.is_null() && len > 0 {
+            return status_result(Err(LbError::new(ErrorCode::NullPointer, "Null pointer received for data")));
+        }
+        let bytes = if len == 0 { &[][..] } else { unsafe { std::slice::from_raw_parts(data, len) } };
+        status_result(write_stdin(handle, bytes))
+    })
+}
+
+/// Closes the command's stdin so it sees end of input.
+#[no_mangle]
+pub extern "C" fn lb_command_close_stdin(handle: u64) -> i32 {
+    ffi_guard(0, || status_result(take_stdin(handle).map(drop)))
+}
+
+/// Returns output produced since the previous read without blocking:
+/// `{"stdout", "stderr", "dropped_bytes", "finished", "exit_code"}`. Up to 8 MiB per pipe is
+/// kept between reads; older bytes are dropped and counted. Keep reading until `finished`.
+#[no_mangle]
+pub extern "C" fn lb_command_read(handle: u64) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || string_result(read_command(handle), "command output"))
+}
+
+/// Kills the command if still running and releases the handle; required even after `finished`.
+#[no_mangle]
+pub extern "C" fn lb_command_stop(handle: u64) -> i32 {
+    ffi_guard(0, || status_result(stop_command(handle)))
+}
@@ -0,0 +1,248 @@
+use std::os::raw::c_char;
+
+use crate::adb;
+use crate::device_lock::ensure_device_unlocked;
+use crate::error::LbError;
+use crate::json::JsonValue;
+use crate::{ffi_guard, read_c_str, status_result, string_result};
+
+const CPU_ROOT: &str = "/sys/devices/system/cpu";
+const FIELD_SEPARATOR: char = '|';
+
+// One line per core: `cpuN|online|cur|min|max|governor|available governors|available freqs`.
+// cpu0 usually has no `online` node because it cannot be unplugged; it reads as empty.
+const CPU_INFO_SCRIPT: &str = "for c in /sys/devices/system/cpu/cpu[0-9]*; do \
+f=$c/cpufreq; \
+echo \"${c##*/}|$(cat $c/online 2>/dev/null)|$(cat $f/scaling_cur_freq 2>/dev/null)|\
+$(cat $f/scaling_min_freq 2>/dev/null)|$(cat $f/scaling_max_freq 2>/dev/null)|\
+$(cat $f/scaling_governor 2>/dev/null)|$(cat $f/scaling_available_governors 2>/dev/null)|\
+$(cat $f/scaling_available_frequencies 2>/dev/null)\"; done";
+
+struct CoreInfo {
+    index: u32,
+    online: bool,
+    cur_freq_khz: Option<u64>,
+    min_freq_khz: Option<u64>,
+    max_freq_khz: Option<u64>,
+    governor: Option<String>,
+    available_governors: Vec<String>,
+    available_freqs_khz: Vec<u64>,
+}
+
+impl CoreInfo {
+    fn to_json(&self) -> JsonValue {
+        JsonValue::object(vec![
+            ("cpu", self.index.into()),
+            ("online", self.online.into()),
+            ("cur_freq_khz", self.cur_freq_khz.into()),
+            ("min_freq_khz", self.min_freq_khz.into()),
+            ("max_freq_khz", self.max_freq_khz.into()),
+            ("governor", self.governor.clone().into()),
+            (
+                "available_governors",
+                self.available_governors.iter().map(JsonValue::from).collect::<Vec<_>>().into(),
+            ),
+            (
+                "available_freqs_khz",
+                self.available_freqs_khz.iter().map(|freq| JsonValue::from(*freq)).collect::<Vec<_>>().into(),
+            ),
+        ])
+    }
+}
+
+fn parse_core_line(line: &str) -> Option<CoreInfo> {
+    let fields: Vec<&str> = line.split(FIELD_SEPARATOR).map(str::trim).collect();
+    if fields.len() != 8 {
+        return None;
+    }
+    let index = fields[0].strip_prefix("cpu")?.parse().ok()?;
+    let number = |value: &str| value.parse::<u64>().ok();
+    Some(CoreInfo {
+        index,
+        online: fields[1] != "0",
+        cur_freq_khz: number(fields[2]),
+        min_freq_khz: number(fields[3]),
+        max_freq_khz: number(fields[4]),
+        governor: Some(fields[5].to_string()).filter(|governor| !governor.is_empty()),
+        available_governors: fields[6].split_whitespace().map(str::to_string).collect(),
+        available_freqs_khz: fields[7].split_whitespace().filter_map(number).collect(),
+    })
+}
+
+fn read_cores(serial: &str) -> Result<Vec<CoreInfo>, LbError> {
+    let output = adb::shell(serial, CPU_INFO_SCRIPT)?;
+    let mut cores: Vec<CoreInfo> = output.lines().filter_map(parse_core_line).collect();
+    if cores.is_empty() {
+        return Err(LbError::not_found(format!("No CPU cores found under {} on {}", CPU_ROOT, serial)));
+    }
+    cores.sort_by_key(|core| core.index);
+    Ok(cores)
+}
+
+fn get_cpu_info(serial: &str) -> Result<String, LbError> {
+    let cores = read_cores(serial)?;
+    let online_mask = cores
+        .iter()
+        .filter(|core| core.online)
+        .fold(0u64, |mask, core| mask | (1u64 << core.index.min(63)));
+    let online_list = adb::shell(serial, &format!("cat {}/online", CPU_ROOT))
+        .map(|list| list.trim().to_string())
+        .unwrap_or_default();
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("online", online_list.into()),
+        ("online_mask", online_mask.into()),
+        ("cores", cores.iter().map(CoreInfo::to_json).collect::<Vec<_>>().into()),
+    ])
+    .to_string())
+}
+
+/// Runs a root write against `/sys`, refusing while another process holds the device lock.
+pub fn write_sysfs(serial: &str, writes: &[(String, String)]) -> Result<(), LbError> {
+    ensure_device_unlocked(serial)?;
+    adb::require_root(serial)?;
+    let script: Vec<String> = writes
+        .iter()
+        .map(|(path, value)| format!("echo {} > {}", value, path))
+        .collect();
+    adb::shell(serial, &script.join(" && ")).map(|_| ())
+}
+
+fn set_cpu_governor(serial: &str, governor: &str) -> Result<(), LbError> {
+    let governor = governor.trim();
+    if governor.is_empty() || !governor.chars().all(|ch| ch.is_ascii_alphanumeric() || ch == '_') {
+        return Err(format!("Invalid CPU governor name: {:?}", governor).into());
+    }
+    let cores: Vec<CoreInfo> = read_cores(serial)?.into_iter().filter(|core| core.online).collect();
+    if let Some(core) = cores
+        .iter()
+        .find(|core| !core.available_governors.is_empty() && !core.available_governors.iter().any(|g| g == governor))
+    {
+        return Err(format!(
+            "Governor {} is not available on cpu{} (available: {})",
+            governor,
+            core.index,
+            core.available_governors.join(" ")
+        )
+        .into());
+    }
+    let writes: Vec<(String, String)> = cores
+        .iter()
+        .map(|core| {
+            (
+                format!("{}/cpu{}/cpufreq/scaling_governor", CPU_ROOT, core.index),
+                governor.to_string(),
+            )
+        })
+        .collect();
+    write_sysfs(serial, &writes)
+}
+
+fn set_cpu_online(serial: &str, cpu: u32, online: bool) -> Result<(), LbError> {
+    if cpu == 0 && !online {
+        return Err("cpu0 cannot be taken offline".into());
+    }
+    let path = format!("{}/cpu{}/online", CPU_ROOT, cpu);
+    write_sysfs(serial, &[(path, if online { "1" } else { "0" }.to_string())])
+}
+
+fn pin_writes(core: &CoreInfo, target: u64) -> [(String, String); 2] {
+    let cpufreq = format!("{}/cpu{}/cpufreq", CPU_ROOT, core.index);
+    // Raise max before min (and lower min before max) so the pair never becomes min > max.
+    let (first, second) = match core.max_freq_khz {
+        Some(current_max) if target > current_max => ("scaling_max_freq", "scaling_min_freq"),
+        _ => ("scaling_min_freq", "scaling_max_freq"),
+    };
+    [
+        (format!("{}/{}", cpufreq, first), target.to_string()),
+        (format!("{}/{}", cpufreq, second), target.to_string()),
+    ]
+}
+
+/// Pins every online core to the median of its available frequencies, which stays clear
+/// of thermal throttling. Returns the original `(path, value)` pairs in restore order.
+pub fn pin_stable_clocks(serial: &str) -> Result<Vec<(String, String)>, LbError> {
+    let mut originals = Vec::new();
+    let mut writes = Vec::new();
+    for core in read_cores(serial)?.into_iter().filter(|core| core.online) {
+        let (Some(min), Some(max)) = (core.min_freq_khz, core.max_freq_khz) else {
+            continue;
+        };
+        let mut freqs = core.available_freqs_khz.clone();
+        freqs.sort_unstable();
+        let Some(target) = freqs.get(freqs.len() / 2).copied() else {
+            continue;
+        };
+        // The pinned value lies within the original range, so min-then-max restores cleanly.
+        let cpufreq = format!("{}/cpu{}/cpufreq", CPU_ROOT, core.index);
+        originals.push((format!("{}/scaling_min_freq", cpufreq), min.to_string()));
+        originals.push((format!("{}/scaling_max_freq", cpufreq), max.to_string()));
+        writes.extend(pin_writes(&core, target));
+    }
+    if writes.is_empty() {
+        return Err(LbError::not_found(format!("{} exposes no scalable CPU frequencies", serial)));
+    }
+    write_sysfs(serial, &writes)?;
+    Ok(originals)
+}
+
+/// Pins min and max scaling frequency to `freq_khz` (0 = the core's highest available
+/// frequency) on one core, or on every online core when `cpu` is negative.
+fn pin_cpu_freq(serial: &str, cpu: i32, freq_khz: u64) -> Result<(), LbError> {
+    let cores: Vec<CoreInfo> = read_cores(serial)?
+        .into_iter()
+        .filter(|core| if cpu < 0 { core.online } else { core.index as i32 == cpu })
+        .collect();
+    if cores.is_empty() {
+        return Err(LbError::not_found(format!("cpu{} not found or offline on {}", cpu, serial)));
+    }
+    let mut writes = Vec::new();
+    for core in &cores {
+        let target = if freq_khz == 0 {
+            core.available_freqs_khz
+                .iter()
+                .copied()
+                .max()
+                .or(core.max_freq_khz)
+                .ok_or_else(|| LbError::not_found(format!("cpu{} reports no frequencies", core.index)))?
+        } else {
+            freq_khz
+        };
+        writes.extend(pin_writes(core, target));
+    }
+    write_sysfs(serial, &writes)
+}
+
+/// Returns per-core frequencies, governors, and the online mask as JSON.
+#[no_mangle]
+pub extern "C" fn lb_get_cpu_info(serial_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        string_result(read_c_str(serial_ptr, "serial").and_then(get_cpu_info), "CPU info")
+    })
+}
+
+/// Sets the scaling governor on every online core. Requires `adb root`.
+#[no_mangle]
+pub extern "C" fn lb_set_cpu_governor(serial_ptr: *const c_char, governor_ptr: *const c_char) -> i32 {
+    ffi_guard(0, || {
+        let result = read_c_str(serial_ptr, "serial")
+            .and_then(|serial| read_c_str(governor_ptr, "governor").and_then(|governor| set_cpu_governor(serial, governor)));
+        status_result(result)
+    })
+}
+
+/// Hot-plugs a core on (`online != 0`) or off. Requires `adb root`.
+#[no_mangle]
+pub extern "C" fn lb_set_cpu_online(serial_ptr: *const c_char, cpu: u32, online: i32) -> i32 {
+    ffi_guard(0, || {
+        status_result(read_c_str(serial_ptr, "serial").and_then(|serial| set_cpu_online(serial, cpu, online != 0)))
+    })
+}
+
+/// Locks clocks for stable benchmarks; see `pin_cpu_freq`. Requires `adb root`.
+#[no_mangle]
+pub extern "C" fn lb_pin_cpu_freq(serial_ptr: *const c_char, cpu: i32, freq_khz: u64) -> i32 {
+    ffi_guard(0, || {
+        status_result(read_c_str(serial_ptr, "serial").and_then(|serial| pin_cpu_freq(serial, cpu, freq_khz)))
+    })
+}
@@ -0,0 +1,310 @@
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::os::raw::c_char;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::adb;
+use crate::error::LbError;
+use crate::json::{self, JsonValue};
+use crate::lmk::matches_package;
+use crate::logging::{lb_log, Level};
+use crate::packages;
+use crate::stream::{self, Gate, LineCallback, LineSink};
+use crate::{ffi_guard, handle_result, now_millis, read_c_str};
+
+/// The crash buffer has no end-of-report marker; a report is complete once it goes quiet.
+const QUIET_PERIOD: Duration = Duration::from_millis(500);
+/// `am monitor` and the crash buffer both see Java crashes; the second report is dropped.
+const DUPLICATE_WINDOW: Duration = Duration::from_secs(10);
+/// ANR trace files hold every thread of several processes; keep the watched one's section.
+const MAX_TRACES_BYTES: usize = 512 * 1024;
+
+/// A crash or ANR on its way to the callback.
+struct Report {
+    kind: &'static str,
+    source: &'static str,
+    process: String,
+    pid: Option<u32>,
+    short_msg: Option<String>,
+    stack: Vec<String>,
+}
+
+impl Report {
+    fn new(kind: &'static str, source: &'static str) -> Report {
+        Report {
+            kind,
+            source,
+            process: String::new(),
+            pid: None,
+            short_msg: None,
+            stack: Vec::new(),
+        }
+    }
+}
+
+/// `10-16 10:00:00.123  1234  1234 E AndroidRuntime: message` (`logcat -v threadtime`).
+fn threadtime(line: &str) -> Option<(u32, &str, &str)> {
+    let mut fields = line.split_whitespace();
+    let (date, time) = (fields.next()?, fields.next()?);
+    if !date.contains('-') || !time.contains(':') {
+        return None;
+    }
+    let pid = fields.next()?.parse().ok()?;
+    fields.next()?.parse::<u32>().ok()?;
+    let level = fields.next()?;
+    let rest = &line[line.find(level)? + level.len()..];
+    let (tag, message) = rest.split_once(": ")?;
+    Some((pid, tag.trim(), message))
+}
+
+/// Folds `logcat -b crash` and `am monitor -c` output into reports. Monitor blocks run
+/// from `** ERROR: ...` to a lone `#`; crash-buffer reports are grouped by pid.
+#[derive(Default)]
+struct CrashParser {
+    monitor: Option<Report>,
+    /// Set once a monitor block reaches `stack:` / `processStats:`; later lines are the body.
+    monitor_body: bool,
+    logcat: Option<Report>,
+    monitor_failed: bool,
+}
+
+impl CrashParser {
+    fn line(&mut self, line: &str, done: &mut Vec<Report>) {
+        if let Some((pid, tag, message)) = threadtime(line) {
+            self.logcat_line(pid, tag, message, done);
+            return;
+        }
+        let trimmed = line.trim();
+        if let Some(kind) = trimmed.strip_prefix("** ERROR: ") {
+            let kind = match kind {
+                "PROCESS CRASHED" => "crash",
+                "PROCESS NOT RESPONDING" => "anr",
+                // `EARLY PROCESS NOT RESPONDING` precedes the real ANR report.
+                _ => return,
+            };
+            self.monitor = Some(Report::new(kind, "am_monitor"));
+            self.monitor_body = false;
+            return;
+        }
+        let Some(report) = &mut self.monitor else {
+            if trimmed.starts_with("Error:") && !self.monitor_failed {
+                self.monitor_failed = true;
+                lb_log!(Level::Warn, "crash", "am monitor unavailable ({}); ANRs will not be reported", trimmed);
+            }
+            return;
+        };
+        if trimmed == "#" {
+            if report.kind == "crash" && report.short_msg.as_deref().is_some_and(|msg| msg.starts_with("Native crash")) {
+                report.kind = "native_crash";
+            }
+            done.extend(self.monitor.take());
+        } else if self.monitor_body {
+            if let Some(reason) = trimmed.strip_prefix("Reason:").filter(|_| report.kind == "anr") {
+                report.short_msg.get_or_insert_with(|| reason.trim().to_string());
+            }
+            report.stack.push(line.to_string());
+        } else if trimmed == "stack:" || trimmed == "processStats:" {
+            self.monitor_body = true;
+        } else if let Some((key, value)) = trimmed.split_once(": ") {
+            match key {
+                "processName" => report.process = value.to_string(),
+                "processPid" => report.pid = value.parse().ok(),
+                "shortMsg" => report.short_msg = Some(value.to_string()),
+                _ => {}
+            }
+        }
+    }
+
+    fn logcat_line(&mut self, pid: u32, tag: &str, message: &str, done: &mut Vec<Report>) {
+        let starts = match tag {
+            "AndroidRuntime" if message.starts_with("FATAL EXCEPTION") => Some("crash"),
+            "DEBUG" if message.starts_with("*** *** ***") => Some("native_crash"),
+            _ => None,
+        };
+        if let Some(kind) = starts {
+            done.extend(self.logcat.take());
+            self.logcat = Some(Report::new(kind, "logcat"));
+        }
+        let Some(report) = &mut self.logcat else {
+            return;
+        };
+        // Java crashes log from the crashing process; tombstones from crash_dump, whose
+        // `pid:` line names the victim.
+        if report.kind == "crash" {
+            if report.pid.is_none() {
+                report.pid = Some(pid);
+            }
+            if report.pid != Some(pid) || tag != "AndroidRuntime" {
+                return;
+            }
+            if let Some(process) = message.strip_prefix("Process: ") {
+                report.process = process.split(',').next().unwrap_or(process).trim().to_string();
+            } else if starts.is_none() && report.short_msg.is_none() && !message.trim_start().starts_with("at ") {
+                report.short_msg = Some(message.trim().to_string());
+            }
+        } else {
+            if tag != "DEBUG" {
+                return;
+            }
+            if let Some(fields) = message.strip_prefix("pid: ") {
+                report.pid = fields.split(',').next().and_then(|pid| pid.trim().parse().ok());
+                if let Some((_, name)) = fields.split_once(">>> ") {
+                    report.process = name.split(" <<<").next().unwrap_or(name).trim().to_string();
+                }
+            } else if message.starts_with("signal ") || message.starts_with("Abort message:") {
+                report.short_msg.get_or_insert_with(|| message.trim().to_string());
+            }
+        }
+        report.stack.push(message.to_string());
+    }
+
+    /// The crash-buffer report in progress, once the buffer has been quiet for a while.
+    fn flush(&mut self, done: &mut Vec<Report>) {
+        done.extend(self.logcat.take());
+    }
+}
+
+/// The `----- pid N at ... -----` section of the newest `/data/anr` file that has one.
+/// Shell can read these on userdebug builds or as root; elsewhere this is `(None, None)`.
+fn anr_traces(serial: &str, pid: u32) -> (Option<String>, Option<String>) {
+    let marker = format!("----- pid {} ", pid);
+    let script = format!(
+        "for f in $(ls -t /data/anr/* 2>/dev/null); do grep -q {} \"$f\" 2>/dev/null && echo \"$f\" && cat \"$f\" && break; done",
+        adb::shell_quote(&marker)
+    );
+    let Ok(output) = adb::shell(serial, &script) else {
+        return (None, None);
+    };
+    let Some((path, contents)) = output.split_once('\n') else {
+        return (None, None);
+    };
+    let section = contents.find(&marker).map(|start| {
+        let section = &contents[start..];
+        let end_marker = format!("----- end {} -----", pid);
+        let end = section.find(&end_marker).map_or(section.len(), |end| end + end_marker.len());
+        let mut section = &section[..end];
+        if section.len() > MAX_TRACES_BYTES {
+            let mut cut = MAX_TRACES_BYTES;
+            while !section.is_char_boundary(cut) {
+                cut -= 1;
+            }
+            section = &section[..cut];
+        }
+        section.to_string()
+    });
+    (Some(path.trim().to_string()), section)
+}
+
+fn report_json(serial: &str, report: Report, traces: (Option<String>, Option<String>)) -> JsonValue {
+    let package = report.process.split(':').next().unwrap_or_default().to_string();
+    JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("kind", report.kind.into()),
+        ("source", report.source.into()),
+        ("package", package.into()),
+        ("process", report.process.into()),
+        ("pid", report.pid.into()),
+        ("short_msg", report.short_msg.into()),
+        ("stack", report.stack.join("\n").trim_end().into()),
+        ("traces_path", traces.0.into()),
+        ("traces", traces.1.into()),
+        ("timestamp_ms", now_millis().into()),
+    ])
+}
+
+fn parse_packages(packages_json: Option<&str>) -> Result<Vec<String>, LbError> {
+    let Some(source) = packages_json else {
+        return Ok(Vec::new());
+    };
+    let packages = json::parse(source)?
+        .as_string_array()
+        .ok_or("Watched packages must be a JSON array of strings")?;
+    for package in &packages {
+        packages::validate_package(package)?;
+    }
+    Ok(packages)
+}
+
+fn watch_crashes(serial: &str, packages: Vec<String>, sink: LineSink) -> Result<u64, LbError> {
+    // One shell for both sources so the watch is a single stream handle; `-c` answers
+    // `am monitor`'s crash prompt with "continue" instead of holding the app.
+    let argv = adb::adb_argv(Some(serial), &["shell", "logcat -b crash -v threadtime -T 1 & am monitor -c; wait"]);
+    let (sender, receiver) = mpsc::channel::<String>();
+    let serial = serial.to_string();
+    // Reports leave through the stream's gate, so none follows `lb_stop_stream`.
+    let gate = Arc::new(Gate::default());
+    let reports = Arc::clone(&gate);
+    thread::spawn(move || {
+        let mut parser = CrashParser::default();
+        let mut recent: HashMap<(&'static str, u32), Instant> = HashMap::new();
+        let mut open = true;
+        while open {
+            let mut done = Vec::new();
+            match receiver.recv_timeout(QUIET_PERIOD) {
+                Ok(line) => parser.line(&line, &mut done),
+                Err(RecvTimeoutError::Timeout) => parser.flush(&mut done),
+                Err(RecvTimeoutError::Disconnected) => {
+                    parser.flush(&mut done);
+                    open = false;
+                }
+            }
+            for report in done {
+                let watched = packages.is_empty() || packages.iter().any(|package| matches_package(&report.process, package));
+                if !watched {
+                    continue;
+                }
+                // Native crashes also reach `am monitor` as crashes.
+                let kind = if report.kind == "anr" { "anr" } else { "crash" };
+                if let Some(pid) = report.pid {
+                    recent.retain(|_, seen| seen.elapsed() < DUPLICATE_WINDOW);
+                    if recent.insert((kind, pid), Instant::now()).is_some() {
+                        continue;
+                    }
+                }
+                lb_log!(Level::Warn, "crash", "{} in {} on {}", report.kind, report.process, serial);
+                let traces = match (report.kind, report.pid) {
+                    ("anr", Some(pid)) => anr_traces(&serial, pid),
+                    _ => (None, None),
+                };
+                let report = report_json(&serial, report, traces).to_string();
+                if !reports.deliver(|| sink.emit(&report)) {
+                    return;
+                }
+            }
+        }
+    });
+    stream::spawn_gated_line_stream(&argv, gate, move |line| {
+        let _ = sender.send(line.to_string());
+    })
+}
+
+/// Watches `logcat -b crash` and `am monitor` for Java crashes, native crashes and ANRs in
+/// `packages_json` (a JSON array; null or `[]` watches every process) and calls
+/// `callback(user_data, report_json)` with `{serial, kind: crash | native_crash | anr,
+/// source, package, process, pid, short_msg, stack, traces_path, traces, timestamp_ms}`.
+/// ANR reports carry the process's section of the newest `/data/anr` file when shell can
+/// read it. Returns a handle for `lb_stop_stream`.
+#[no_mangle]
+pub extern "C" fn lb_crash_watch_start(
+    serial_ptr: *const c_char,
+    packages_json_ptr: *const c_char,
+    callback: Option<LineCallback>,
+    user_data: *mut c_void,
+) -> u64 {
+    ffi_guard(0, || {
+        let packages = if packages_json_ptr.is_null() {
+            Ok(None)
+        } else {
+            read_c_str(packages_json_ptr, "packages").map(Some)
+        };
+        let result = LineSink::new(callback, user_data).and_then(|sink| {
+            packages.and_then(parse_packages).and_then(|packages| {
+                read_c_str(serial_ptr, "serial").and_then(|serial| watch_crashes(serial, packages, sink))
+            })
+        });
+        handle_result(result)
+    })
+}
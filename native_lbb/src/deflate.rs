@@ -0,0 +1,324 @@
+use crate::error::LbError;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+    8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+/// Order in which dynamic blocks list the code-length code lengths.
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+const WINDOW: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: u32 = 15;
+
+fn corrupt(what: &str) -> LbError {
+    LbError::parse(format!("Corrupt deflate stream: {}", what))
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, count: u32) -> Result<u32, LbError> {
+        while self.count < count {
+            let byte = *self.data.get(self.position).ok_or_else(|| corrupt("unexpected end"))?;
+            self.position += 1;
+            self.buffer |= u32::from(byte) << self.count;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1u32 << count) - 1);
+        self.buffer >>= count;
+        self.count -= count;
+        Ok(value)
+    }
+
+    fn align(&mut self) {
+        self.buffer = 0;
+        self.count = 0;
+    }
+}
+
+/// Canonical Huffman code, decoded one bit at a time as in zlib's `puff`.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; 16];
+        for &length in lengths {
+            counts[usize::from(length)] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; 16];
+        for length in 1..15 {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[usize::from(offsets[usize::from(length)])] = symbol as u16;
+                offsets[usize::from(length)] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, LbError> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for length in 1..16 {
+            code |= reader.bits(1)? as i32;
+            let count = i32::from(self.counts[length]);
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(corrupt("invalid Huffman code"))
+    }
+}
+
+fn fixed_tables() -> (Huffman, Huffman) {
+    let mut lengths = [8u8; 288];
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    (Huffman::new(&lengths), Huffman::new(&[5u8; 30]))
+}
+
+fn dynamic_tables(reader: &mut BitReader) -> Result<(Huffman, Huffman), LbError> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+    let mut code_lengths = [0u8; 19];
+    for &slot in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_lengths[slot] = reader.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths);
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let (value, repeat) = match code_length_code.decode(reader)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => (*lengths.last().ok_or_else(|| corrupt("repeat without a previous length"))?, 3 + reader.bits(2)?),
+            17 => (0, 3 + reader.bits(3)?),
+            _ => (0, 11 + reader.bits(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(value, repeat as usize));
+    }
+    if lengths.len() > literal_count + distance_count {
+        return Err(corrupt("code lengths overrun"));
+    }
+    Ok((Huffman::new(&lengths[..literal_count]), Huffman::new(&lengths[literal_count..])))
+}
+
+fn inflate_block(reader: &mut BitReader, literals: &Huffman, distances: &Huffman, out: &mut Vec<u8>) -> Result<(), LbError> {
+    loop {
+        let symbol = literals.decode(reader)?;
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let index = usize::from(symbol - 257);
+                let base = *LENGTH_BASE.get(index).ok_or_else(|| corrupt("invalid length symbol"))?;
+                let length = usize::from(base) + reader.bits(u32::from(LENGTH_EXTRA[index]))? as usize;
+                let index = usize::from(distances.decode(reader)?);
+                let base = *DISTANCE_BASE.get(index).ok_or_else(|| corrupt("invalid distance symbol"))?;
+                let distance = usize::from(base) + reader.bits(u32::from(DISTANCE_EXTRA[index]))? as usize;
+                if distance > out.len() {
+                    return Err(corrupt("distance before start of output"));
+                }
+                let start = out.len() - distance;
+                for offset in 0..length {
+                    out.push(out[start + offset]);
+                }
+            }
+        }
+    }
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += u32::from(byte);
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+/// Decompresses a zlib stream (RFC 1950/1951), checking its header and Adler-32 trailer.
+pub fn zlib_decompress(data: &[u8]) -> Result<Vec<u8>, LbError> {
+    if data.len() < 6 || data[0] & 0x0f != 8 || (u16::from(data[0]) << 8 | u16::from(data[1])) % 31 != 0 {
+        return Err(corrupt("bad zlib header"));
+    }
+    if data[1] & 0x20 != 0 {
+        return Err(corrupt("preset dictionaries are not supported"));
+    }
+    let mut reader = BitReader {
+        data: &data[2..],
+        position: 0,
+        buffer: 0,
+        count: 0,
+    };
+    let out = inflate_blocks(&mut reader)?;
+    let trailer = reader.data.get(reader.position..reader.position + 4).ok_or_else(|| corrupt("missing checksum"))?;
+    if u32::from_be_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]) != adler32(&out) {
+        return Err(corrupt("Adler-32 mismatch"));
+    }
+    Ok(out)
+}
+
+/// Decompresses a raw deflate stream (RFC 1951), as stored in zip entries.
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>, LbError> {
+    let mut reader = BitReader {
+        data,
+        position: 0,
+        buffer: 0,
+        count: 0,
+    };
+    inflate_blocks(&mut reader)
+}
+
+fn inflate_blocks(reader: &mut BitReader) -> Result<Vec<u8>, LbError> {
+    let mut out = Vec::new();
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => {
+                reader.align();
+                let header = reader.data.get(reader.position..reader.position + 4).ok_or_else(|| corrupt("truncated stored block"))?;
+                let length = usize::from(u16::from_le_bytes([header[0], header[1]]));
+                if u16::from_le_bytes([header[2], header[3]]) != !(length as u16) {
+                    return Err(corrupt("stored block length check"));
+                }
+                let start = reader.position + 4;
+                let bytes = reader.data.get(start..start + length).ok_or_else(|| corrupt("truncated stored block"))?;
+                out.extend_from_slice(bytes);
+                reader.position = start + length;
+            }
+            1 => {
+                let (literals, distances) = fixed_tables();
+                inflate_block(reader, &literals, &distances, &mut out)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_tables(reader)?;
+                inflate_block(reader, &literals, &distances, &mut out)?;
+            }
+            _ => return Err(corrupt("reserved block type")),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn bits(&mut self, value: u32, count: u32) {
+        self.buffer |= value << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Huffman codes are defined most-significant bit first.
+    fn code(&mut self, code: u32, length: u32) {
+        self.bits(code.reverse_bits() >> (32 - length), length);
+    }
+
+    fn literal(&mut self, symbol: u16) {
+        let symbol = u32::from(symbol);
+        match symbol {
+            0..=143 => self.code(0x30 + symbol, 8),
+            144..=255 => self.code(0x190 + symbol - 144, 9),
+            256..=279 => self.code(symbol - 256, 7),
+            _ => self.code(0xc0 + symbol - 280, 8),
+        }
+    }
+
+    fn matched(&mut self, length: usize, distance: usize) {
+        let index = LENGTH_BASE.iter().rposition(|&base| usize::from(base) <= length).unwrap_or(0);
+        self.literal(257 + index as u16);
+        self.bits((length - usize::from(LENGTH_BASE[index])) as u32, u32::from(LENGTH_EXTRA[index]));
+        let index = DISTANCE_BASE.iter().rposition(|&base| usize::from(base) <= distance).unwrap_or(0);
+        self.code(index as u32, 5);
+        self.bits((distance - usize::from(DISTANCE_BASE[index])) as u32, u32::from(DISTANCE_EXTRA[index]));
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+fn hash(data: &[u8]) -> usize {
+    let value = u32::from(data[0]) << 16 | u32::from(data[1]) << 8 | u32::from(data[2]);
+    (value.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+}
+
+/// Compresses `data` into a zlib stream: one fixed-Huffman block with greedy LZ77 matching.
+/// Fine for thumbnails; nowhere near zlib's ratio on large images.
+pub fn zlib_compress(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::default();
+    // Final block, fixed Huffman codes.
+    writer.bits(1, 1);
+    writer.bits(1, 2);
+    let mut heads = vec![usize::MAX; 1 << HASH_BITS];
+    let mut position = 0;
+    while position < data.len() {
+        let mut best = 0;
+        if position + MIN_MATCH <= data.len() {
+            let slot = hash(&data[position..]);
+            let candidate = heads[slot];
+            heads[slot] = position;
+            if candidate != usize::MAX && position - candidate <= WINDOW {
+                let limit = (data.len() - position).min(MAX_MATCH);
+                best = (0..limit).take_while(|&offset| data[candidate + offset] == data[position + offset]).count();
+                if best >= MIN_MATCH {
+                    writer.matched(best, position - candidate);
+                }
+            }
+        }
+        if best >= MIN_MATCH {
+            // Index the skipped positions too, or long runs would never match again.
+            for skipped in position + 1..(position + best).min(data.len().saturating_sub(MIN_MATCH - 1)) {
+                heads[hash(&data[skipped..])] = skipped;
+            }
+            position += best;
+        } else {
+            writer.literal(u16::from(data[position]));
+            position += 1;
+        }
+    }
+    writer.literal(256);
+    let mut out = vec![0x78, 0x01];
+    out.extend(writer.finish());
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
@@ -0,0 +1,132 @@
+use std::os::raw::c_char;
+
+use crate::adb;
+use crate::device_lock::ensure_device_unlocked;
+use crate::error::{ErrorCode, LbError};
+use crate::json::JsonValue;
+use crate::logging::{lb_log, Level};
+use crate::{ffi_guard, read_c_str, status_result, string_result};
+
+const DEMO_ACTION: &str = "com.android.systemui.demo";
+/// SystemUI ignores the demo broadcasts unless this global setting is 1.
+const DEMO_ALLOWED: &str = "sysui_demo_allowed";
+const DEFAULT_CLOCK: &str = "1200";
+const DEFAULT_BATTERY: i32 = 100;
+const NETWORKS: [&str; 4] = ["wifi", "mobile", "both", "none"];
+
+fn demo_broadcast(command: &str, extras: &str) -> String {
+    let mut line = format!("am broadcast -a {} -e command {}", DEMO_ACTION, command);
+    if !extras.is_empty() {
+        line.push(' ');
+        line.push_str(extras);
+    }
+    line
+}
+
+/// `HHMM`, 24-hour.
+fn validate_clock(clock: &str) -> Result<(), LbError> {
+    let valid = clock.len() == 4
+        && clock.bytes().all(|byte| byte.is_ascii_digit())
+        && clock[..2].parse::<u32>().is_ok_and(|hours| hours < 24)
+        && clock[2..].parse::<u32>().is_ok_and(|minutes| minutes < 60);
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("Clock must be HHMM (24-hour), got {:?}", clock).into())
+    }
+}
+
+fn network_extras(network: &str) -> String {
+    let (wifi, mobile) = match network {
+        "wifi" => ("show", "hide"),
+        "mobile" => ("hide", "show"),
+        "both" => ("show", "show"),
+        _ => ("hide", "hide"),
+    };
+    let mut extras = format!("-e wifi {} -e mobile {}", wifi, mobile);
+    if network != "none" {
+        // Full bars; `level` applies to whichever icons are shown.
+        extras.push_str(" -e level 4 -e fully true");
+    }
+    if mobile == "show" {
+        extras.push_str(" -e datatype none");
+    }
+    extras
+}
+
+fn enable_demo_mode(serial: &str, clock: Option<&str>, battery: i32, network: Option<&str>) -> Result<String, LbError> {
+    let clock = clock.unwrap_or(DEFAULT_CLOCK);
+    validate_clock(clock)?;
+    let battery = if battery < 0 { DEFAULT_BATTERY } else { battery };
+    if battery > 100 {
+        return Err("Battery level must be between 0 and 100".to_string().into());
+    }
+    let network = network.unwrap_or("wifi");
+    if !NETWORKS.contains(&network) {
+        return Err(format!("Network must be one of {}", NETWORKS.join(", ")).into());
+    }
+    ensure_device_unlocked(serial)?;
+    adb::put_setting(serial, "global", DEMO_ALLOWED, Some("1"))?;
+    if adb::get_setting(serial, "global", DEMO_ALLOWED)?.as_deref() != Some("1") {
+        let message = format!("{} did not stick", DEMO_ALLOWED);
+        return Err(LbError::new(ErrorCode::CommandFailed, message).with_serial(serial));
+    }
+    let broadcasts = [
+        demo_broadcast("enter", ""),
+        demo_broadcast("clock", &format!("-e hhmm {}", clock)),
+        demo_broadcast("battery", &format!("-e level {} -e plugged false", battery)),
+        demo_broadcast("network", &network_extras(network)),
+        demo_broadcast("notifications", "-e visible false"),
+    ];
+    adb::shell(serial, &broadcasts.join(" && "))?;
+    lb_log!(Level::Info, "settings", "Entered demo mode on {}", serial);
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("clock", clock.into()),
+        ("battery", battery.into()),
+        ("network", network.into()),
+    ])
+    .to_string())
+}
+
+fn disable_demo_mode(serial: &str) -> Result<(), LbError> {
+    ensure_device_unlocked(serial)?;
+    adb::shell(serial, &demo_broadcast("exit", ""))?;
+    lb_log!(Level::Info, "settings", "Left demo mode on {}", serial);
+    Ok(())
+}
+
+/// Puts SystemUI into demo mode with a fixed status bar: `clock` as `HHMM` (null: 1200),
+/// `battery` level 0-100 and not charging (negative: 100), `network` one of `wifi`,
+/// `mobile`, `both`, `none` (null: wifi) at full signal, and notification icons hidden.
+/// Sets `sysui_demo_allowed` first and leaves it on. Returns `{serial, clock, battery,
+/// network}`; calling it again while in demo mode just updates the icons.
+#[no_mangle]
+pub extern "C" fn lb_demo_mode_enable(
+    serial_ptr: *const c_char,
+    clock_ptr: *const c_char,
+    battery: i32,
+    network_ptr: *const c_char,
+) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let optional = |ptr: *const c_char, name| {
+            if ptr.is_null() {
+                Ok(None)
+            } else {
+                read_c_str(ptr, name).map(Some)
+            }
+        };
+        let result = read_c_str(serial_ptr, "serial").and_then(|serial| {
+            let clock = optional(clock_ptr, "clock")?;
+            let network = optional(network_ptr, "network")?;
+            enable_demo_mode(serial, clock, battery, network)
+        });
+        string_result(result, "demo mode")
+    })
+}
+
+/// Leaves demo mode, restoring the live status bar. Returns 1 on success.
+#[no_mangle]
+pub extern "C" fn lb_demo_mode_disable(serial_ptr: *const c_char) -> i32 {
+    ffi_guard(0, || status_result(read_c_str(serial_ptr, "serial").and_then(disable_demo_mode)))
+}
@@ -0,0 +1,227 @@
+use std::os::raw::c_char;
+
+use crate::adb;
+use crate::benchmark::ANIMATION_SCALES;
+use crate::device_lock::ensure_device_unlocked;
+use crate::error::{ErrorCode, LbError};
+use crate::json::JsonValue;
+use crate::logging::{lb_log, Level};
+use crate::{ffi_guard, read_c_str, string_result};
+
+/// The largest scale the developer options offer.
+const MAX_ANIMATION_SCALE: f64 = 10.0;
+/// `IBinder.SYSPROPS_TRANSACTION`: makes running apps re-read `debug.*` properties, as the
+/// Settings app does after changing one.
+const POKE_SYSPROPS: &str = "service call activity 1599295570 >/dev/null 2>&1; true";
+
+enum Store {
+    Setting(&'static str, &'static str),
+    Prop(&'static str),
+}
+
+/// A developer-options switch and the values the Settings app writes for it.
+struct DevOption {
+    name: &'static str,
+    store: Store,
+    on: &'static str,
+    off: &'static str,
+}
+
+const STAY_AWAKE: DevOption = DevOption {
+    name: "stay_awake",
+    // AC | USB | wireless.
+    store: Store::Setting("global", "stay_on_while_plugged_in"),
+    on: "7",
+    off: "0",
+};
+const POINTER_LOCATION: DevOption = DevOption {
+    name: "pointer_location",
+    store: Store::Setting("system", "pointer_location"),
+    on: "1",
+    off: "0",
+};
+const SHOW_TOUCHES: DevOption = DevOption {
+    name: "show_touches",
+    store: Store::Setting("system", "show_touches"),
+    on: "1",
+    off: "0",
+};
+const GPU_PROFILING: DevOption = DevOption {
+    name: "gpu_profiling",
+    store: Store::Prop("debug.hwui.profile"),
+    on: "visual_bars",
+    off: "false",
+};
+const STRICT_MODE: DevOption = DevOption {
+    name: "strict_mode",
+    store: Store::Prop("persist.sys.strictmode.visual"),
+    on: "1",
+    off: "",
+};
+const OPTIONS: [&DevOption; 5] = [&STAY_AWAKE, &POINTER_LOCATION, &SHOW_TOUCHES, &GPU_PROFILING, &STRICT_MODE];
+
+impl DevOption {
+    fn read(&self, serial: &str) -> Result<Option<String>, LbError> {
+        match self.store {
+            Store::Setting(namespace, key) => adb::get_setting(serial, namespace, key),
+            Store::Prop(name) => adb::getprop(serial, name).map(|value| Some(value).filter(|value| !value.is_empty())),
+        }
+    }
+
+    fn write(&self, serial: &str, value: &str) -> Result<(), LbError> {
+        match self.store {
+            Store::Setting(namespace, key) => adb::put_setting(serial, namespace, key, Some(value)),
+            Store::Prop(name) => {
+                adb::shell(serial, &format!("setprop {} {}", name, adb::shell_quote(value)))?;
+                adb::shell(serial, POKE_SYSPROPS).map(|_| ())
+            }
+        }
+    }
+
+    /// Anything but the off value counts as on, e.g. `debug.hwui.profile=true` (gfxinfo).
+    fn is_enabled(&self, value: Option<&str>) -> bool {
+        value.is_some_and(|value| !value.is_empty() && value != self.off && value != "0" && value != "false")
+    }
+}
+
+fn read_back_error(serial: &str, what: &str, wrote: &str, read: Option<&str>) -> LbError {
+    LbError::new(
+        ErrorCode::CommandFailed,
+        format!("{} reads back as {} after writing {}", what, read.unwrap_or("null"), wrote),
+    )
+    .with_serial(serial)
+}
+
+fn set_option(serial: &str, option: &DevOption, enabled: bool) -> Result<String, LbError> {
+    ensure_device_unlocked(serial)?;
+    let previous = option.read(serial)?;
+    let value = if enabled { option.on } else { option.off };
+    option.write(serial, value)?;
+    let current = option.read(serial)?;
+    if option.is_enabled(current.as_deref()) != enabled {
+        return Err(read_back_error(serial, option.name, value, current.as_deref()));
+    }
+    let action = if enabled { "Enabled" } else { "Disabled" };
+    lb_log!(Level::Info, "settings", "{} {} on {}", action, option.name, serial);
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("option", option.name.into()),
+        ("enabled", enabled.into()),
+        ("value", current.into()),
+        ("previous", previous.into()),
+    ])
+    .to_string())
+}
+
+fn scale_value(scale: f64) -> String {
+    // `1` rather than `1.0`, matching what the Settings app stores.
+    if scale.fract() == 0.0 {
+        format!("{}", scale as u32)
+    } else {
+        scale.to_string()
+    }
+}
+
+fn set_animation_scales(serial: &str, scale: f64) -> Result<String, LbError> {
+    if !(0.0..=MAX_ANIMATION_SCALE).contains(&scale) {
+        return Err(format!("Animation scale must be between 0 and {}", MAX_ANIMATION_SCALE).into());
+    }
+    ensure_device_unlocked(serial)?;
+    let value = scale_value(scale);
+    let mut previous = Vec::new();
+    for key in ANIMATION_SCALES {
+        previous.push((key.to_string(), adb::get_setting(serial, "global", key)?.into()));
+        adb::put_setting(serial, "global", key, Some(&value))?;
+        let current = adb::get_setting(serial, "global", key)?;
+        if current.as_deref().and_then(|value| value.parse::<f64>().ok()) != Some(scale) {
+            return Err(read_back_error(serial, key, &value, current.as_deref()));
+        }
+    }
+    lb_log!(Level::Info, "settings", "Set animation scales to {} on {}", value, serial);
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("scale", scale.into()),
+        ("previous", JsonValue::Object(previous)),
+    ])
+    .to_string())
+}
+
+fn get_dev_options(serial: &str) -> Result<String, LbError> {
+    let mut scales = Vec::new();
+    for key in ANIMATION_SCALES {
+        let value = adb::get_setting(serial, "global", key)?;
+        // Unset scales behave as 1.
+        let scale = value.and_then(|value| value.parse::<f64>().ok()).unwrap_or(1.0);
+        scales.push((key.to_string(), scale.into()));
+    }
+    let mut fields = vec![
+        ("serial".to_string(), serial.into()),
+        ("animation_scales".to_string(), JsonValue::Object(scales)),
+    ];
+    for option in OPTIONS {
+        let value = option.read(serial)?;
+        fields.push((option.name.to_string(), option.is_enabled(value.as_deref()).into()));
+    }
+    Ok(JsonValue::Object(fields).to_string())
+}
+
+fn toggle(serial_ptr: *const c_char, option: &DevOption, enabled: i32) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(serial_ptr, "serial").and_then(|serial| set_option(serial, option, enabled != 0));
+        string_result(result, "developer option")
+    })
+}
+
+/// Sets the window, transition and animator duration scales to `scale` (0 turns
+/// animations off, 1 is normal, up to 10) and reads each back. Returns `{serial, scale,
+/// previous: {key: raw | null}}`.
+#[no_mangle]
+pub extern "C" fn lb_set_animation_scales(serial_ptr: *const c_char, scale: f64) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(serial_ptr, "serial").and_then(|serial| set_animation_scales(serial, scale));
+        string_result(result, "animation scales")
+    })
+}
+
+/// Current developer options: `{serial, animation_scales: {key: scale}, stay_awake,
+/// pointer_location, show_touches, gpu_profiling, strict_mode}`.
+#[no_mangle]
+pub extern "C" fn lb_get_dev_options(serial_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        string_result(read_c_str(serial_ptr, "serial").and_then(get_dev_options), "developer options")
+    })
+}
+
+/// Keeps the screen on while charging (`stay_on_while_plugged_in`). Like the other
+/// toggles, returns `{serial, option, enabled, value, previous}` after reading it back.
+#[no_mangle]
+pub extern "C" fn lb_set_stay_awake(serial_ptr: *const c_char, enabled: i32) -> *mut c_char {
+    toggle(serial_ptr, &STAY_AWAKE, enabled)
+}
+
+/// Draws the pointer location overlay (`system pointer_location`).
+#[no_mangle]
+pub extern "C" fn lb_set_pointer_location(serial_ptr: *const c_char, enabled: i32) -> *mut c_char {
+    toggle(serial_ptr, &POINTER_LOCATION, enabled)
+}
+
+/// Shows a dot for every touch (`system show_touches`).
+#[no_mangle]
+pub extern "C" fn lb_set_show_touches(serial_ptr: *const c_char, enabled: i32) -> *mut c_char {
+    toggle(serial_ptr, &SHOW_TOUCHES, enabled)
+}
+
+/// On-screen HWUI profile bars (`debug.hwui.profile=visual_bars`); running apps pick the
+/// change up through a sysprops poke, though some only on their next frame or restart.
+#[no_mangle]
+pub extern "C" fn lb_set_gpu_profiling(serial_ptr: *const c_char, enabled: i32) -> *mut c_char {
+    toggle(serial_ptr, &GPU_PROFILING, enabled)
+}
+
+/// Flashes the screen on main-thread StrictMode violations
+/// (`persist.sys.strictmode.visual`). Builds that do not let shell set the property fail
+/// the read-back with CommandFailed.
+#[no_mangle]
+pub extern "C" fn lb_set_strict_mode(serial_ptr: *const c_char, enabled: i32) -> *mut c_char {
+    toggle(serial_ptr, &STRICT_MODE, enabled)
+}
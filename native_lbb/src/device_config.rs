@@ -0,0 +1,294 @@
+use std::os::raw::c_char;
+use std::time::{Duration, Instant};
+
+use crate::adb;
+use crate::cancel;
+use crate::device_lock::ensure_device_unlocked;
+use crate::display::{parse_size, wm_field};
+use crate::error::{ErrorCode, LbError};
+use crate::json::JsonValue;
+use crate::logging::{lb_log, Level};
+use crate::{ffi_guard, read_c_str, string_result};
+
+/// ADB Change Language (`net.sanapeli.adbchangelanguage`) applies a locale live once it
+/// holds CHANGE_CONFIGURATION, which shell can grant; without it a locale change needs root.
+const LOCALE_HELPER_PACKAGE: &str = "net.sanapeli.adbchangelanguage";
+const LOCALE_HELPER_ACTIVITY: &str = "net.sanapeli.adbchangelanguage/.AdbChangeLanguage";
+const LOCALE_PROP: &str = "persist.sys.locale";
+/// The locale a factory-reset device boots in.
+const DEFAULT_LOCALE_PROP: &str = "ro.product.locale";
+const FALLBACK_LOCALE: &str = "en-US";
+/// A framework restart after changing `persist.sys.locale` takes as long as the tail of a boot.
+const FRAMEWORK_RESTART_TIMEOUT: Duration = Duration::from_secs(90);
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+const MAX_DENSITY: u32 = 1000;
+
+fn read_back_error(serial: &str, what: &str, wrote: &str, read: Option<&str>) -> LbError {
+    LbError::new(
+        ErrorCode::CommandFailed,
+        format!("{} reads back as {} after setting {}", what, read.unwrap_or("null"), wrote),
+    )
+    .with_serial(serial)
+}
+
+/// `wm size` / `wm density` as `{physical, override: {width, height, density_dpi}}`.
+fn wm_metrics(serial: &str) -> Result<JsonValue, LbError> {
+    let output = adb::shell(serial, "wm size; wm density")?;
+    let metrics = |kind| {
+        let size = wm_field(&output, kind, "size").and_then(parse_size);
+        let density = wm_field(&output, kind, "density").and_then(|value| value.parse::<u32>().ok());
+        JsonValue::object(vec![
+            ("width", size.map(|(width, _)| width).into()),
+            ("height", size.map(|(_, height)| height).into()),
+            ("density_dpi", density.into()),
+        ])
+    };
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("physical", metrics("Physical")),
+        ("override", metrics("Override")),
+    ]))
+}
+
+fn override_field(metrics: &JsonValue, name: &str) -> Option<u32> {
+    match metrics.get("override")?.get(name)? {
+        JsonValue::Number(value) => Some(*value as u32),
+        _ => None,
+    }
+}
+
+fn set_display(serial: &str, size: Option<&str>, density: u32) -> Result<String, LbError> {
+    let size = size.map(|value| parse_size(value).ok_or_else(|| format!("Size must be WIDTHxHEIGHT, got {:?}", value)));
+    let size = size.transpose().map_err(LbError::from)?;
+    if size.is_none() && density == 0 {
+        return Err("Pass a size, a density, or both".to_string().into());
+    }
+    if size.is_some_and(|(width, height)| width == 0 || height == 0) || density > MAX_DENSITY {
+        return Err(format!("Size must be non-zero and density at most {}", MAX_DENSITY).into());
+    }
+    ensure_device_unlocked(serial)?;
+    let mut commands = Vec::new();
+    if let Some((width, height)) = size {
+        commands.push(format!("wm size {}x{}", width, height));
+    }
+    if density > 0 {
+        commands.push(format!("wm density {}", density));
+    }
+    adb::shell(serial, &commands.join(" && "))?;
+    let metrics = wm_metrics(serial)?;
+    if let Some((width, height)) = size {
+        let read = (override_field(&metrics, "width"), override_field(&metrics, "height"));
+        if read != (Some(width), Some(height)) {
+            let read = read.0.zip(read.1).map(|(width, height)| format!("{}x{}", width, height));
+            return Err(read_back_error(serial, "wm size", &format!("{}x{}", width, height), read.as_deref()));
+        }
+    }
+    if density > 0 && override_field(&metrics, "density_dpi") != Some(density) {
+        let read = override_field(&metrics, "density_dpi").map(|density| density.to_string());
+        return Err(read_back_error(serial, "wm density", &density.to_string(), read.as_deref()));
+    }
+    lb_log!(Level::Info, "settings", "Overrode display metrics on {}", serial);
+    Ok(metrics.to_string())
+}
+
+fn reset_display(serial: &str) -> Result<String, LbError> {
+    ensure_device_unlocked(serial)?;
+    adb::shell(serial, "wm size reset && wm density reset")?;
+    let metrics = wm_metrics(serial)?;
+    if override_field(&metrics, "width").is_some() || override_field(&metrics, "density_dpi").is_some() {
+        return Err(LbError::new(ErrorCode::CommandFailed, "Display overrides remain after reset").with_serial(serial));
+    }
+    lb_log!(Level::Info, "settings", "Reset display metrics on {}", serial);
+    Ok(metrics.to_string())
+}
+
+/// `Night mode: yes` from `cmd uimode night` (Android 10+); also `no`, `auto`, `custom`.
+fn night_mode(serial: &str) -> Result<String, LbError> {
+    let output = adb::shell(serial, "cmd uimode night")?;
+    output
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Night mode:").map(|mode| mode.trim().to_string()))
+        .ok_or_else(|| LbError::parse(format!("Unexpected cmd uimode output: {}", output.trim())).with_serial(serial))
+}
+
+fn set_night_mode(serial: &str, mode: &str) -> Result<String, LbError> {
+    ensure_device_unlocked(serial)?;
+    let previous = night_mode(serial)?;
+    adb::shell(serial, &format!("cmd uimode night {}", mode))?;
+    let current = night_mode(serial)?;
+    if current != mode {
+        return Err(read_back_error(serial, "Night mode", mode, Some(&current)));
+    }
+    lb_log!(Level::Info, "settings", "Set night mode {} on {}", mode, serial);
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("dark_mode", (mode == "yes").into()),
+        ("night_mode", current.into()),
+        ("previous", previous.into()),
+    ])
+    .to_string())
+}
+
+/// `fr`, `pt-BR`, `zh-Hant-TW`, `en_US`; returned in BCP 47 form with `-` separators.
+fn normalize_locale(tag: &str) -> Result<String, LbError> {
+    let tag = tag.trim().replace('_', "-");
+    let mut parts = tag.split('-');
+    let language = parts.next().unwrap_or_default();
+    let valid = (2..=3).contains(&language.len())
+        && language.chars().all(|c| c.is_ascii_alphabetic())
+        && parts.all(|part| (2..=8).contains(&part.len()) && part.chars().all(|c| c.is_ascii_alphanumeric()));
+    if valid {
+        Ok(tag)
+    } else {
+        Err(format!("Not a locale tag: {:?}", tag).into())
+    }
+}
+
+fn current_locale(serial: &str) -> Result<Option<String>, LbError> {
+    let locale = adb::getprop(serial, LOCALE_PROP)?;
+    Ok(Some(locale.trim().to_string()).filter(|locale| !locale.is_empty()))
+}
+
+fn system_server_pid(serial: &str) -> Option<String> {
+    adb::shell(serial, "pidof system_server")
+        .ok()
+        .map(|pid| pid.trim().to_string())
+        .filter(|pid| !pid.is_empty())
+}
+
+/// Writes `persist.sys.locale` as root and restarts zygote so every process starts in the
+/// new locale, then waits for a new system_server to finish booting.
+fn restart_with_locale(serial: &str, locale: &str) -> Result<(), LbError> {
+    adb::require_root(serial)?;
+    let before = system_server_pid(serial);
+    adb::shell(serial, &format!("setprop {} {} && setprop ctl.restart zygote", LOCALE_PROP, adb::shell_quote(locale)))?;
+    let started = Instant::now();
+    loop {
+        let pid = system_server_pid(serial);
+        if pid.is_some() && pid != before {
+            break;
+        }
+        if started.elapsed() >= FRAMEWORK_RESTART_TIMEOUT {
+            return Err(
+                LbError::new(ErrorCode::Timeout, "Timed out waiting for the framework to restart").with_serial(serial)
+            );
+        }
+        cancel::sleep(POLL_INTERVAL)?;
+    }
+    adb::wait_for_boot_completed(serial, FRAMEWORK_RESTART_TIMEOUT.saturating_sub(started.elapsed())).map(|_| ())
+}
+
+fn apply_locale(serial: &str, locale: &str) -> Result<String, LbError> {
+    ensure_device_unlocked(serial)?;
+    let previous = current_locale(serial)?;
+    let helper_installed = !adb::shell(serial, &format!("pm path {}", LOCALE_HELPER_PACKAGE))
+        .unwrap_or_default()
+        .trim()
+        .is_empty();
+    let method = if helper_installed {
+        adb::shell(
+            serial,
+            &format!(
+                "pm grant {} android.permission.CHANGE_CONFIGURATION && am start -W -n {} -e language {}",
+                LOCALE_HELPER_PACKAGE,
+                LOCALE_HELPER_ACTIVITY,
+                adb::shell_quote(locale)
+            ),
+        )?;
+        "helper"
+    } else {
+        restart_with_locale(serial, locale)?;
+        "restart"
+    };
+    let current = current_locale(serial)?;
+    if !current.as_deref().is_some_and(|current| current.eq_ignore_ascii_case(locale)) {
+        return Err(read_back_error(serial, LOCALE_PROP, locale, current.as_deref()));
+    }
+    lb_log!(Level::Info, "settings", "Set locale {} on {} via {}", locale, serial, method);
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("locale", current.into()),
+        ("previous", previous.into()),
+        ("method", method.into()),
+    ])
+    .to_string())
+}
+
+fn set_locale(serial: &str, tag: &str) -> Result<String, LbError> {
+    let locale = normalize_locale(tag)?;
+    apply_locale(serial, &locale)
+}
+
+fn reset_locale(serial: &str) -> Result<String, LbError> {
+    let default = adb::getprop(serial, DEFAULT_LOCALE_PROP)?;
+    let default = normalize_locale(&default).unwrap_or_else(|_| FALLBACK_LOCALE.to_string());
+    apply_locale(serial, &default)
+}
+
+/// Overrides the display size (`WIDTHxHEIGHT`, null to keep) and/or density (dpi, 0 to
+/// keep) with `wm size` / `wm density`, reads both back, and returns `{serial, physical,
+/// override: {width, height, density_dpi}}`.
+#[no_mangle]
+pub extern "C" fn lb_set_display(serial_ptr: *const c_char, size_ptr: *const c_char, density: u32) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let size = if size_ptr.is_null() {
+            Ok(None)
+        } else {
+            read_c_str(size_ptr, "size").map(Some)
+        };
+        let result = size.and_then(|size| {
+            read_c_str(serial_ptr, "serial").and_then(|serial| set_display(serial, size, density))
+        });
+        string_result(result, "display override")
+    })
+}
+
+/// Clears both display overrides; returns the same shape as `lb_set_display` with a null
+/// `override`.
+#[no_mangle]
+pub extern "C" fn lb_reset_display(serial_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        string_result(read_c_str(serial_ptr, "serial").and_then(reset_display), "display reset")
+    })
+}
+
+/// Forces dark (`enabled` != 0) or light theme with `cmd uimode night yes|no` (Android 10+)
+/// and returns `{serial, dark_mode, night_mode, previous}`.
+#[no_mangle]
+pub extern "C" fn lb_set_dark_mode(serial_ptr: *const c_char, enabled: i32) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let mode = if enabled != 0 { "yes" } else { "no" };
+        let result = read_c_str(serial_ptr, "serial").and_then(|serial| set_night_mode(serial, mode));
+        string_result(result, "dark mode")
+    })
+}
+
+/// Returns night mode to `auto`, letting the system schedule decide.
+#[no_mangle]
+pub extern "C" fn lb_reset_dark_mode(serial_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(serial_ptr, "serial").and_then(|serial| set_night_mode(serial, "auto"));
+        string_result(result, "dark mode reset")
+    })
+}
+
+/// Switches the system locale to `tag` (e.g. `fr-FR`). Uses the ADB Change Language helper
+/// live when it is installed, otherwise needs root and restarts the framework (tens of
+/// seconds). Returns `{serial, locale, previous, method: "helper" | "restart"}`.
+#[no_mangle]
+pub extern "C" fn lb_set_locale(serial_ptr: *const c_char, tag_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(serial_ptr, "serial")
+            .and_then(|serial| read_c_str(tag_ptr, "locale").and_then(|tag| set_locale(serial, tag)));
+        string_result(result, "locale")
+    })
+}
+
+/// Switches back to the build's `ro.product.locale` (en-US when unset), the same way as
+/// `lb_set_locale`.
+#[no_mangle]
+pub extern "C" fn lb_reset_locale(serial_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        string_result(read_c_str(serial_ptr, "serial").and_then(reset_locale), "locale reset")
+    })
+}
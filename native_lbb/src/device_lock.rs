@@ -0,0 +1,218 @@
+use std::cell::RefCell;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::raw::c_char;
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::error::{ErrorCode, LbError};
+use crate::json::{self, JsonValue};
+use crate::{ffi_guard, now_millis, read_c_str, status_result, string_result};
+
+const LOCK_DIR_ENV: &str = "LAZY_BLACKTEA_LOCK_DIR";
+
+thread_local! {
+    /// The owner the calling thread acts for; only its own locks let destructive operations through.
+    static ACTING_OWNER: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// The owner set with `lb_set_lock_owner` on this thread.
+pub fn acting_owner() -> Option<String> {
+    ACTING_OWNER.with(|owner| owner.borrow().clone())
+}
+
+/// Runs `body` acting for `owner`, for threads that carry out work for a caller.
+pub fn act_as<T>(owner: Option<String>, body: impl FnOnce() -> T) -> T {
+    let previous = ACTING_OWNER.with(|current| current.replace(owner));
+    let result = body();
+    ACTING_OWNER.with(|current| *current.borrow_mut() = previous);
+    result
+}
+
+struct LockRecord {
+    owner: String,
+    pid: u32,
+    acquired_at_ms: u64,
+}
+
+impl LockRecord {
+    fn to_json(&self, serial: &str) -> JsonValue {
+        JsonValue::object(vec![
+            ("serial", serial.into()),
+            ("owner", self.owner.as_str().into()),
+            ("pid", self.pid.into()),
+            ("acquired_at_ms", self.acquired_at_ms.into()),
+            ("held_by_this_process", (self.pid == std::process::id()).into()),
+            ("held_by_caller", self.held_by_caller().into()),
+        ])
+    }
+
+    /// Held by this process for the owner the calling thread acts for. Two host windows in
+    /// one process share the pid, so the pid alone does not tell them apart.
+    fn held_by_caller(&self) -> bool {
+        self.pid == std::process::id()
+            && ACTING_OWNER.with(|owner| owner.borrow().as_deref() == Some(self.owner.as_str()))
+    }
+}
+
+fn lock_dir() -> PathBuf {
+    match std::env::var_os(LOCK_DIR_ENV) {
+        Some(dir) => PathBuf::from(dir),
+        None => std::env::temp_dir().join("lazy_blacktea_locks"),
+    }
+}
+
+fn lock_path(serial: &str) -> PathBuf {
+    let file_name: String = serial
+        .chars()
+        .map(|ch| if ch.is_ascii_alphanumeric() || ch == '-' || ch == '.' { ch } else { '_' })
+        .collect();
+    lock_dir().join(format!("{}.lock", file_name))
+}
+
+#[cfg(unix)]
+fn process_alive(pid: u32) -> bool {
+    Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(true)
+}
+
+#[cfg(windows)]
+fn process_alive(pid: u32) -> bool {
+    Command::new("tasklist")
+        .args(["/FI", &format!("PID eq {}", pid), "/NH"])
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).contains(&pid.to_string()))
+        .unwrap_or(true)
+}
+
+fn read_lock(serial: &str) -> Option<LockRecord> {
+    let content = fs::read_to_string(lock_path(serial)).ok()?;
+    let value = json::parse(&content).ok()?;
+    Some(LockRecord {
+        owner: value.get("owner")?.as_str()?.to_string(),
+        pid: value.get("pid")?.as_u64()? as u32,
+        acquired_at_ms: value.get("acquired_at_ms").and_then(JsonValue::as_u64).unwrap_or(0),
+    })
+}
+
+fn write_lock(serial: &str, record: &LockRecord) -> std::io::Result<()> {
+    let mut file = OpenOptions::new().write(true).create_new(true).open(lock_path(serial))?;
+    file.write_all(record.to_json(serial).to_string().as_bytes())
+}
+
+/// Returns the live lock for `serial`, discarding lock files left by dead processes.
+fn active_lock(serial: &str) -> Option<LockRecord> {
+    let record = read_lock(serial)?;
+    if record.pid != std::process::id() && !process_alive(record.pid) {
+        let _ = fs::remove_file(lock_path(serial));
+        return None;
+    }
+    Some(record)
+}
+
+fn lock_device(serial: &str, owner: &str) -> Result<(), LbError> {
+    if serial.is_empty() || owner.is_empty() {
+        return Err("Serial and owner must not be empty".into());
+    }
+    fs::create_dir_all(lock_dir()).map_err(|err| LbError::io(format!("Failed to create lock directory: {}", err)))?;
+
+    let record = LockRecord {
+        owner: owner.to_string(),
+        pid: std::process::id(),
+        acquired_at_ms: now_millis(),
+    };
+    match active_lock(serial) {
+        Some(existing) if existing.pid == record.pid && existing.owner == record.owner => Ok(()),
+        Some(existing) => Err(LbError::new(
+            ErrorCode::DeviceLocked,
+            format!("Device {} is locked by {} (pid {})", serial, existing.owner, existing.pid),
+        )
+        .with_serial(serial)),
+        None => write_lock(serial, &record)
+            .map_err(|err| LbError::io(format!("Failed to acquire lock for {}: {}", serial, err))),
+    }
+}
+
+fn unlock_device(serial: &str, owner: &str) -> Result<(), LbError> {
+    match read_lock(serial) {
+        None => Ok(()),
+        Some(existing) if existing.pid == std::process::id() && existing.owner == owner => {
+            fs::remove_file(lock_path(serial)).map_err(|err| LbError::io(format!("Failed to release lock for {}: {}", serial, err)))
+        }
+        Some(existing) => Err(LbError::new(
+            ErrorCode::DeviceLocked,
+            format!("Device {} is locked by {} (pid {}), not {}", serial, existing.owner, existing.pid, owner),
+        )
+        .with_serial(serial)),
+    }
+}
+
+/// Consulted by destructive operations before they touch a device. Only a lock taken by
+/// this process for the thread's acting owner (`lb_set_lock_owner`) lets them through;
+/// any other live lock blocks them, including one another owner holds in this process.
+pub fn ensure_device_unlocked(serial: &str) -> Result<(), LbError> {
+    match active_lock(serial) {
+        Some(existing) if !existing.held_by_caller() => Err(LbError::new(
+            ErrorCode::DeviceLocked,
+            format!("Device {} is locked by {} (pid {})", serial, existing.owner, existing.pid),
+        )
+        .with_serial(serial)),
+        _ => Ok(()),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn lb_lock_device(serial_ptr: *const c_char, owner_ptr: *const c_char) -> i32 {
+    ffi_guard(0, || {
+        let result = read_c_str(serial_ptr, "serial")
+            .and_then(|serial| read_c_str(owner_ptr, "owner").and_then(|owner| lock_device(serial, owner)));
+        status_result(result)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn lb_unlock_device(serial_ptr: *const c_char, owner_ptr: *const c_char) -> i32 {
+    ffi_guard(0, || {
+        let result = read_c_str(serial_ptr, "serial")
+            .and_then(|serial| read_c_str(owner_ptr, "owner").and_then(|owner| unlock_device(serial, owner)));
+        status_result(result)
+    })
+}
+
+/// Sets the owner the calling thread acts for (null clears it): destructive operations
+/// called on this thread, and the workers they fan out to, may then touch devices this
+/// owner locked. A host driving several sessions sets it before each session's calls.
+#[no_mangle]
+pub extern "C" fn lb_set_lock_owner(owner_ptr: *const c_char) -> i32 {
+    ffi_guard(0, || {
+        let owner = if owner_ptr.is_null() {
+            Ok(None)
+        } else {
+            read_c_str(owner_ptr, "owner").map(|owner| Some(owner.to_string()))
+        };
+        status_result(owner.map(|owner| ACTING_OWNER.with(|current| *current.borrow_mut() = owner)))
+    })
+}
+
+/// Returns the current lock as JSON, or the JSON literal `null` when the device is free.
+#[no_mangle]
+pub extern "C" fn lb_device_lock_info(serial_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(serial_ptr, "serial").map(|serial| match active_lock(serial) {
+            Some(record) => record.to_json(serial).to_string(),
+            None => JsonValue::Null.to_string(),
+        });
+        string_result(result, "lock info")
+    })
+}
+
+/// Lets the host consult the lock before destructive actions it still performs itself.
+#[no_mangle]
+pub extern "C" fn lb_ensure_device_unlocked(serial_ptr: *const c_char) -> i32 {
+    ffi_guard(0, || {
+        status_result(read_c_str(serial_ptr, "serial").and_then(ensure_device_unlocked))
+    })
+}
@@ -0,0 +1,300 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::os::raw::c_char;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+use crate::adb;
+use crate::error::LbError;
+use crate::json::{self, JsonValue};
+use crate::logging::{lb_log, Level};
+use crate::{ffi_guard, read_c_str, status_result, string_result};
+
+const DEFAULT_FILE_NAME: &str = ".lazy_blacktea_devices.json";
+const TEXT_FIELDS: [&str; 2] = ["alias", "notes"];
+const LIST_FIELDS: [&str; 2] = ["tags", "groups"];
+
+/// Override set by `lb_set_device_meta_path`; `None` means `~/.lazy_blacktea_devices.json`.
+/// The mutex also serializes read-modify-write of the file.
+static META_PATH: OnceLock<Mutex<Option<PathBuf>>> = OnceLock::new();
+
+fn meta_path_slot() -> &'static Mutex<Option<PathBuf>> {
+    META_PATH.get_or_init(|| Mutex::new(None))
+}
+
+fn meta_path(configured: &Option<PathBuf>) -> Result<PathBuf, LbError> {
+    if let Some(path) = configured {
+        return Ok(path.clone());
+    }
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(|home| PathBuf::from(home).join(DEFAULT_FILE_NAME))
+        .ok_or_else(|| LbError::not_found("No home directory; call lb_set_device_meta_path first"))
+}
+
+/// `{serial: {alias, notes, tags, groups}}` from disk; a missing file is empty. A file that
+/// does not parse is an error rather than silently replaced on the next write.
+fn load(path: &Path) -> Result<Vec<(String, JsonValue)>, LbError> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(LbError::io(format!("Failed to read {}: {}", path.display(), err))),
+    };
+    match json::parse(&text).map_err(|err| err.context(&format!("Failed to parse {}", path.display())))? {
+        JsonValue::Object(devices) => Ok(devices),
+        _ => Err(LbError::parse(format!("{} is not a JSON object", path.display()))),
+    }
+}
+
+fn save(path: &Path, devices: Vec<(String, JsonValue)>) -> Result<(), LbError> {
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)
+            .map_err(|err| LbError::io(format!("Failed to create {}: {}", parent.display(), err)))?;
+    }
+    // Written beside the target and renamed so a crash never leaves a half-written file.
+    let mut staging = path.as_os_str().to_owned();
+    staging.push(".tmp");
+    fs::write(&staging, JsonValue::Object(devices).to_string())
+        .and_then(|_| fs::rename(&staging, path))
+        .map_err(|err| LbError::io(format!("Failed to write {}: {}", path.display(), err)))
+}
+
+/// Trimmed, non-empty, each value once, in the order given.
+fn clean_list(field: &str, value: &JsonValue) -> Result<Vec<String>, LbError> {
+    let values = value
+        .as_string_array()
+        .ok_or_else(|| format!("Device metadata '{}' must be an array of strings", field))?;
+    let mut cleaned: Vec<String> = Vec::new();
+    for value in values.iter().map(|value| value.trim()).filter(|value| !value.is_empty()) {
+        if !cleaned.iter().any(|seen| seen == value) {
+            cleaned.push(value.to_string());
+        }
+    }
+    Ok(cleaned)
+}
+
+/// Applies `update` to `current`: fields present replace the stored ones, null or empty
+/// clears them, and fields not mentioned are kept.
+fn merge(current: Option<&JsonValue>, update: &JsonValue) -> Result<Vec<(String, JsonValue)>, LbError> {
+    let fields = update
+        .as_object()
+        .ok_or("Device metadata must be a JSON object or null")?;
+    if let Some((name, _)) = fields
+        .iter()
+        .find(|(name, _)| !TEXT_FIELDS.contains(&name.as_str()) && !LIST_FIELDS.contains(&name.as_str()))
+    {
+        return Err(format!("Unknown device metadata field '{}'; use alias, notes, tags or groups", name).into());
+    }
+    let mut merged = Vec::new();
+    for field in TEXT_FIELDS {
+        let value = match update.get(field) {
+            None => current.and_then(|current| current.get(field)).cloned(),
+            Some(JsonValue::Null) => None,
+            Some(value) => {
+                let text = value
+                    .as_str()
+                    .ok_or_else(|| format!("Device metadata '{}' must be a string", field))?
+                    .trim();
+                (!text.is_empty()).then(|| text.into())
+            }
+        };
+        if let Some(value) = value {
+            merged.push((field.to_string(), value));
+        }
+    }
+    for field in LIST_FIELDS {
+        let values = match update.get(field) {
+            None => current.and_then(|current| current.get(field)).cloned(),
+            Some(JsonValue::Null) => None,
+            Some(value) => {
+                Some(clean_list(field, value)?.iter().map(JsonValue::from).collect::<Vec<_>>().into())
+            }
+        };
+        if let Some(values) = values.filter(|values| values.as_array().is_some_and(|values| !values.is_empty())) {
+            merged.push((field.to_string(), values));
+        }
+    }
+    Ok(merged)
+}
+
+/// The stored record for `serial` with every field present: null alias/notes and empty
+/// tags/groups for devices that have none.
+fn metadata_fields(stored: Option<&JsonValue>) -> Vec<(&'static str, JsonValue)> {
+    let field = |name: &str| stored.and_then(|stored| stored.get(name)).cloned();
+    vec![
+        ("alias", field("alias").unwrap_or(JsonValue::Null)),
+        ("notes", field("notes").unwrap_or(JsonValue::Null)),
+        ("tags", field("tags").unwrap_or(JsonValue::Array(Vec::new()))),
+        ("groups", field("groups").unwrap_or(JsonValue::Array(Vec::new()))),
+    ]
+}
+
+fn describe(serial: &str, stored: Option<&JsonValue>) -> JsonValue {
+    let mut fields = vec![("serial", serial.into())];
+    fields.extend(metadata_fields(stored));
+    JsonValue::object(fields)
+}
+
+fn set_metadata(serial: &str, update_json: Option<&str>) -> Result<String, LbError> {
+    if serial.trim().is_empty() {
+        return Err("Serial is empty".into());
+    }
+    let update = json::parse(update_json.unwrap_or("null"))?;
+    let configured = meta_path_slot()
+        .lock()
+        .map_err(|_| LbError::internal("Device metadata path poisoned"))?;
+    let path = meta_path(&configured)?;
+    let mut devices = load(&path)?;
+    let position = devices.iter().position(|(listed, _)| listed == serial);
+    let current = position.map(|position| &devices[position].1);
+    let merged = match update {
+        JsonValue::Null => Vec::new(),
+        ref update => merge(current, update)?,
+    };
+    let stored = (!merged.is_empty()).then_some(JsonValue::Object(merged));
+    match (position, stored.clone()) {
+        (Some(position), Some(stored)) => devices[position].1 = stored,
+        (Some(position), None) => {
+            devices.remove(position);
+        }
+        (None, Some(stored)) => devices.push((serial.to_string(), stored)),
+        (None, None) => {}
+    }
+    devices.sort_by(|a, b| a.0.cmp(&b.0));
+    save(&path, devices)?;
+    lb_log!(Level::Debug, "settings", "Device metadata for {} saved to {}", serial, path.display());
+    Ok(describe(serial, stored.as_ref()).to_string())
+}
+
+fn stored_devices() -> Result<Vec<(String, JsonValue)>, LbError> {
+    let configured = meta_path_slot()
+        .lock()
+        .map_err(|_| LbError::internal("Device metadata path poisoned"))?;
+    load(&meta_path(&configured)?)
+}
+
+/// Serials whose stored `groups` include `group`, ordered by serial.
+pub fn group_members(group: &str) -> Result<Vec<String>, LbError> {
+    let members: Vec<String> = stored_devices()?
+        .into_iter()
+        .filter(|(_, stored)| {
+            stored
+                .get("groups")
+                .and_then(JsonValue::as_string_array)
+                .is_some_and(|groups| groups.iter().any(|name| name == group))
+        })
+        .map(|(serial, _)| serial)
+        .collect();
+    if members.is_empty() {
+        return Err(LbError::not_found(format!("No devices in group '{}'", group)));
+    }
+    Ok(members)
+}
+
+fn get_metadata(serial: &str) -> Result<String, LbError> {
+    let devices = stored_devices()?;
+    let stored = devices.iter().find(|(listed, _)| listed == serial).map(|(_, stored)| stored);
+    Ok(describe(serial, stored).to_string())
+}
+
+fn list_metadata() -> Result<String, LbError> {
+    let devices: Vec<JsonValue> = stored_devices()?
+        .iter()
+        .map(|(serial, stored)| describe(serial, Some(stored)))
+        .collect();
+    Ok(JsonValue::from(devices).to_string())
+}
+
+pub fn list_devices_json() -> Result<String, LbError> {
+    let stored = stored_devices()?;
+    let devices: Vec<JsonValue> = adb::list_devices()?
+        .into_iter()
+        .map(|device| {
+            let meta = stored
+                .iter()
+                .find(|(serial, _)| *serial == device.serial)
+                .map(|(_, stored)| stored);
+            let detail = |name: &str| {
+                device
+                    .details
+                    .iter()
+                    .find(|(key, _)| key == name)
+                    .map(|(_, value)| JsonValue::from(value))
+                    .unwrap_or(JsonValue::Null)
+            };
+            let mut fields = vec![
+                ("serial", device.serial.as_str().into()),
+                ("state", device.state.as_str().into()),
+                ("product", detail("product")),
+                ("model", detail("model")),
+                ("device", detail("device")),
+                ("transport_id", detail("transport_id")),
+                ("usb", detail("usb")),
+            ];
+            fields.extend(metadata_fields(meta));
+            JsonValue::object(fields)
+        })
+        .collect();
+    Ok(JsonValue::from(devices).to_string())
+}
+
+/// Updates the stored metadata for `serial` from `{alias, notes, tags, groups}`: fields
+/// given replace the stored ones (null or empty clears one), others are kept; a null
+/// `meta_json` forgets the device. Tags and groups are trimmed and de-duplicated. The
+/// serial need not be connected. Returns the record as `lb_device_meta_get` does.
+#[no_mangle]
+pub extern "C" fn lb_device_meta_set(serial_ptr: *const c_char, meta_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(serial_ptr, "serial").and_then(|serial| {
+            let update = if meta_ptr.is_null() {
+                None
+            } else {
+                Some(read_c_str(meta_ptr, "device metadata")?)
+            };
+            set_metadata(serial, update)
+        });
+        string_result(result, "device metadata")
+    })
+}
+
+/// `{serial, alias, notes, tags, groups}`; alias/notes are null and tags/groups empty for
+/// a device with no stored metadata.
+#[no_mangle]
+pub extern "C" fn lb_device_meta_get(serial_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        string_result(read_c_str(serial_ptr, "serial").and_then(get_metadata), "device metadata")
+    })
+}
+
+/// Every device with stored metadata, connected or not, ordered by serial.
+#[no_mangle]
+pub extern "C" fn lb_device_meta_list() -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || string_result(list_metadata(), "device metadata list"))
+}
+
+/// `adb devices -l` joined with stored metadata: `[{serial, state, product, model, device,
+/// transport_id, usb, alias, notes, tags, groups}]`, null where adb or the store has no value.
+#[no_mangle]
+pub extern "C" fn lb_list_devices_json() -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || string_result(list_devices_json(), "device list"))
+}
+
+/// Stores device metadata in `path` instead of `~/.lazy_blacktea_devices.json`; null
+/// restores the default.
+#[no_mangle]
+pub extern "C" fn lb_set_device_meta_path(path_ptr: *const c_char) -> i32 {
+    ffi_guard(0, || {
+        let result = if path_ptr.is_null() {
+            Ok(None)
+        } else {
+            read_c_str(path_ptr, "path").map(|path| Some(PathBuf::from(path)))
+        };
+        let result = result.and_then(|path| {
+            *meta_path_slot()
+                .lock()
+                .map_err(|_| LbError::internal("Device metadata path poisoned"))? = path;
+            Ok(())
+        });
+        status_result(result)
+    })
+}
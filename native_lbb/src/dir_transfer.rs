@@ -0,0 +1,387 @@
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::fs;
+use std::os::raw::c_char;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, UNIX_EPOCH};
+
+use crate::adb::{self, shell_quote};
+use crate::checksum::{self, Algorithm};
+use crate::adb_sync::SyncConnection;
+use crate::device_lock::ensure_device_unlocked;
+use crate::error::{ErrorCode, LbError};
+use crate::json::JsonValue;
+use crate::logging::{lb_log, Level};
+use crate::remote_fs::{self, S_IFDIR, S_IFMT, S_IFREG};
+use crate::stream::{LineCallback, LineSink};
+use crate::{ffi_guard, read_c_str, string_result};
+
+/// A regular file in the source tree.
+struct SourceFile {
+    /// `/`-separated path below the tree root.
+    relative: String,
+    size: u64,
+    /// Seconds since the epoch.
+    mtime: u64,
+}
+
+/// `(size, mtime)` of what already sits at each destination, by relative path.
+type Existing = HashMap<String, (u64, u64)>;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Direction {
+    Pull,
+    Push,
+}
+
+fn child(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", prefix, name)
+    }
+}
+
+/// Symlinks are not followed, so a link back up the tree cannot recurse forever.
+fn walk_remote(sync: &mut SyncConnection, root: &str, prefix: &str, files: &mut Vec<SourceFile>) -> Result<(), LbError> {
+    let dir = if prefix.is_empty() { root.to_string() } else { remote_fs::join(root, prefix) };
+    for entry in sync.list(&dir)? {
+        if entry.name == "." || entry.name == ".." {
+            continue;
+        }
+        let relative = child(prefix, &entry.name);
+        match entry.stat.mode & S_IFMT {
+            S_IFDIR => walk_remote(sync, root, &relative, files)?,
+            S_IFREG => files.push(SourceFile {
+                relative,
+                size: entry.stat.size,
+                mtime: entry.stat.mtime,
+            }),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn local_mtime(metadata: &fs::Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+fn walk_local(root: &Path, prefix: &str, files: &mut Vec<SourceFile>) -> Result<(), LbError> {
+    let dir = root.join(prefix);
+    let entries = fs::read_dir(&dir).map_err(|err| LbError::io(format!("Failed to read {}: {}", dir.display(), err)))?;
+    for entry in entries {
+        let entry = entry.map_err(|err| LbError::io(format!("Failed to read {}: {}", dir.display(), err)))?;
+        let relative = child(prefix, &entry.file_name().to_string_lossy());
+        // `DirEntry::metadata` does not follow symlinks.
+        let metadata = entry
+            .metadata()
+            .map_err(|err| LbError::io(format!("Failed to stat {}: {}", entry.path().display(), err)))?;
+        if metadata.is_dir() {
+            walk_local(root, &relative, files)?;
+        } else if metadata.is_file() {
+            files.push(SourceFile {
+                relative,
+                size: metadata.len(),
+                mtime: local_mtime(&metadata),
+            });
+        }
+    }
+    Ok(())
+}
+
+struct Transfer<'a> {
+    serial: &'a str,
+    remote_root: &'a str,
+    local_root: &'a Path,
+    direction: Direction,
+    checksum: bool,
+    sink: Option<LineSink>,
+}
+
+impl Transfer<'_> {
+    fn remote_path(&self, relative: &str) -> String {
+        remote_fs::join(self.remote_root, relative)
+    }
+
+    fn local_path(&self, relative: &str) -> PathBuf {
+        self.local_root.join(relative)
+    }
+
+    fn scan(&self, sync: &mut SyncConnection) -> Result<(Vec<SourceFile>, Existing), LbError> {
+        let root = sync.stat(self.remote_root)?;
+        let remote_is_dir = root.mode & S_IFMT == S_IFDIR;
+        let mut files = Vec::new();
+        let mut existing = HashMap::new();
+        match self.direction {
+            Direction::Pull => {
+                if !root.exists() {
+                    return Err(LbError::not_found(format!("{} does not exist on {}", self.remote_root, self.serial))
+                        .with_serial(self.serial));
+                }
+                if !remote_is_dir {
+                    return Err(format!("{} is not a directory", self.remote_root).into());
+                }
+                walk_remote(sync, self.remote_root, "", &mut files)?;
+                for file in &files {
+                    if let Ok(metadata) = fs::metadata(self.local_path(&file.relative)) {
+                        existing.insert(file.relative.clone(), (metadata.len(), local_mtime(&metadata)));
+                    }
+                }
+            }
+            Direction::Push => {
+                if !self.local_root.is_dir() {
+                    return Err(LbError::not_found(format!("Directory not found: {}", self.local_root.display())));
+                }
+                if root.exists() && !remote_is_dir {
+                    return Err(format!("{} is not a directory", self.remote_root).into());
+                }
+                walk_local(self.local_root, "", &mut files)?;
+                if root.exists() {
+                    let mut remote = Vec::new();
+                    walk_remote(sync, self.remote_root, "", &mut remote)?;
+                    existing.extend(remote.into_iter().map(|file| (file.relative, (file.size, file.mtime))));
+                }
+            }
+        }
+        files.sort_by(|a, b| a.relative.cmp(&b.relative));
+        Ok((files, existing))
+    }
+
+    /// Drops files whose destination copy already matches: same size and mtime, or with
+    /// `checksum` the same size and MD5.
+    fn pending(&self, files: Vec<SourceFile>, existing: &Existing) -> Result<Vec<SourceFile>, LbError> {
+        let same_size: Vec<&SourceFile> = files
+            .iter()
+            .filter(|file| existing.get(&file.relative).is_some_and(|(size, _)| *size == file.size))
+            .collect();
+        let unchanged: Vec<String> = if self.checksum {
+            let remote_paths: Vec<String> = same_size.iter().map(|file| self.remote_path(&file.relative)).collect();
+            let remote_sums = checksum::device_checksums(self.serial, &remote_paths, Algorithm::Md5)?;
+            same_size
+                .iter()
+                .filter(|file| {
+                    let local = checksum::local_checksum(&self.local_path(&file.relative), Algorithm::Md5).ok();
+                    local.is_some() && remote_sums.get(&self.remote_path(&file.relative)) == local.as_ref()
+                })
+                .map(|file| file.relative.clone())
+                .collect()
+        } else {
+            same_size
+                .iter()
+                .filter(|file| existing.get(&file.relative).is_some_and(|(_, mtime)| *mtime == file.mtime))
+                .map(|file| file.relative.clone())
+                .collect()
+        };
+        Ok(files.into_iter().filter(|file| !unchanged.contains(&file.relative)).collect())
+    }
+
+    /// With `checksum`, re-hashes both copies after the transfer. A mismatched destination
+    /// is deleted so it cannot pass for a complete file (or be skipped on the next run).
+    fn verify(&self, local: &Path, remote: &str) -> Result<(), LbError> {
+        if !self.checksum {
+            return Ok(());
+        }
+        let verified = checksum::verify_transfer(self.serial, local, remote, Algorithm::Md5);
+        if verified.as_ref().is_err_and(|err| err.code == ErrorCode::ChecksumMismatch) {
+            match self.direction {
+                Direction::Pull => {
+                    let _ = fs::remove_file(local);
+                }
+                Direction::Push => {
+                    let _ = adb::shell(self.serial, &format!("rm -f {}", shell_quote(remote)));
+                }
+            }
+        }
+        verified
+    }
+
+    fn run(&self) -> Result<String, LbError> {
+        let started = Instant::now();
+        let mut sync = SyncConnection::open(self.serial)?;
+        let (files, existing) = self.scan(&mut sync)?;
+        let file_total = files.len();
+        let pending = self.pending(files, &existing)?;
+        let overall_total: u64 = pending.iter().map(|file| file.size).sum();
+        lb_log!(
+            Level::Info,
+            "sync",
+            "{} {}: {} of {} file(s) to transfer ({} bytes)",
+            if self.direction == Direction::Pull { "Pulling" } else { "Pushing" },
+            self.remote_root,
+            pending.len(),
+            file_total,
+            overall_total
+        );
+
+        let mut overall_done = 0u64;
+        let mut transferred = 0usize;
+        let mut transferred_bytes = 0u64;
+        let mut failed = Vec::new();
+        for (index, file) in pending.iter().enumerate() {
+            let remote = self.remote_path(&file.relative);
+            let local = self.local_path(&file.relative);
+            let mut last_percents = None;
+            let progress = |bytes: u64, total: u64| {
+                let percent = (bytes.saturating_mul(100)).checked_div(total).unwrap_or(100).min(100);
+                let overall_bytes = overall_done + bytes;
+                let overall_percent =
+                    (overall_bytes.saturating_mul(100)).checked_div(overall_total).unwrap_or(100).min(100);
+                if let Some(sink) = self.sink.filter(|_| last_percents != Some((percent, overall_percent))) {
+                    last_percents = Some((percent, overall_percent));
+                    sink.emit(
+                        &JsonValue::object(vec![
+                            ("file", file.relative.as_str().into()),
+                            ("file_index", index.into()),
+                            ("file_count", pending.len().into()),
+                            ("bytes", bytes.into()),
+                            ("total", total.into()),
+                            ("percent", percent.into()),
+                            ("overall_bytes", overall_bytes.into()),
+                            ("overall_total", overall_total.into()),
+                            ("overall_percent", overall_percent.into()),
+                        ])
+                        .to_string(),
+                    );
+                }
+            };
+            let result = match self.direction {
+                Direction::Pull => local
+                    .parent()
+                    .map_or(Ok(()), fs::create_dir_all)
+                    .map_err(|err| LbError::io(format!("Failed to create directory for {}: {}", local.display(), err)))
+                    .and_then(|_| sync.pull(&remote, &local, progress)),
+                Direction::Push => sync.push(&local, &remote, progress),
+            };
+            let result = result.and_then(|bytes| self.verify(&local, &remote).map(|_| bytes));
+            match result {
+                Ok(bytes) => {
+                    overall_done += bytes;
+                    transferred += 1;
+                    transferred_bytes += bytes;
+                }
+                // The connection is gone; completed files stay in place for the next attempt.
+                Err(err) if matches!(err.code, ErrorCode::DeviceOffline | ErrorCode::Timeout) => {
+                    let done = format!("Transfer stopped after {} of {} file(s)", transferred, pending.len());
+                    return Err(err.context(&done));
+                }
+                Err(err) => {
+                    lb_log!(Level::Warn, "sync", "Skipping {}: {}", file.relative, err.message);
+                    failed.push(JsonValue::object(vec![
+                        ("file", file.relative.as_str().into()),
+                        ("code", err.code.name().into()),
+                        ("error", err.message.as_str().into()),
+                    ]));
+                    overall_done += file.size;
+                    // adbd ends the sync session after a failed SEND; start a fresh one.
+                    sync = SyncConnection::open(self.serial)?;
+                }
+            }
+        }
+
+        let (source, destination) = match self.direction {
+            Direction::Pull => (self.remote_root.to_string(), self.local_root.to_string_lossy().into_owned()),
+            Direction::Push => (self.local_root.to_string_lossy().into_owned(), self.remote_root.to_string()),
+        };
+        Ok(JsonValue::object(vec![
+            ("serial", self.serial.into()),
+            ("source", source.into()),
+            ("destination", destination.into()),
+            ("files", file_total.into()),
+            ("transferred", transferred.into()),
+            ("skipped", (file_total - pending.len()).into()),
+            ("failed", JsonValue::from(failed)),
+            ("bytes", transferred_bytes.into()),
+            ("duration_ms", (started.elapsed().as_millis() as u64).into()),
+        ])
+        .to_string())
+    }
+}
+
+fn transfer(
+    serial: &str,
+    remote_root: &str,
+    local_root: &str,
+    direction: Direction,
+    checksum: bool,
+    sink: Option<LineSink>,
+) -> Result<String, LbError> {
+    remote_fs::validate_path(remote_root)?;
+    if direction == Direction::Push {
+        ensure_device_unlocked(serial)?;
+    }
+    Transfer {
+        serial,
+        remote_root,
+        local_root: Path::new(local_root),
+        direction,
+        checksum,
+        sink,
+    }
+    .run()
+}
+
+fn transfer_export(
+    serial_ptr: *const c_char,
+    remote_ptr: *const c_char,
+    local_ptr: *const c_char,
+    direction: Direction,
+    checksum: i32,
+    callback: Option<LineCallback>,
+    user_data: *mut c_void,
+) -> *mut c_char {
+    let sink = callback.map(|callback| LineSink::new(Some(callback), user_data)).transpose();
+    let result = sink.and_then(|sink| {
+        read_c_str(serial_ptr, "serial").and_then(|serial| {
+            read_c_str(remote_ptr, "device directory").and_then(|remote| {
+                read_c_str(local_ptr, "local directory")
+                    .and_then(|local| transfer(serial, remote, local, direction, checksum != 0, sink))
+            })
+        })
+    });
+    string_result(result, "transfer result")
+}
+
+/// Copies the device directory `remote_dir` into `local_dir` over the sync protocol,
+/// keeping the tree layout and remote mtimes, and returns `{serial, source, destination,
+/// files, transferred, skipped, failed: [{file, code, error}], bytes, duration_ms}`. Files whose
+/// local copy has the same size and mtime (or, with `checksum` non-zero, the same size and
+/// MD5) are skipped, so rerunning an interrupted pull resumes it. With `checksum`, every
+/// copied file is also re-hashed on both sides; a mismatch is deleted and reported in
+/// `failed` with code `ChecksumMismatch`. `callback(user_data,
+/// json)` (may be null) receives `{file, file_index, file_count, bytes, total, percent,
+/// overall_bytes, overall_total, overall_percent}` on the calling thread.
+#[no_mangle]
+pub extern "C" fn lb_pull_dir(
+    serial_ptr: *const c_char,
+    remote_dir_ptr: *const c_char,
+    local_dir_ptr: *const c_char,
+    checksum: i32,
+    callback: Option<LineCallback>,
+    user_data: *mut c_void,
+) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        transfer_export(serial_ptr, remote_dir_ptr, local_dir_ptr, Direction::Pull, checksum, callback, user_data)
+    })
+}
+
+/// The reverse of `lb_pull_dir`: copies `local_dir` to `remote_dir` on the device (created
+/// as needed), keeping permission bits and mtimes, with the same skip rules, progress
+/// events and result shape.
+#[no_mangle]
+pub extern "C" fn lb_push_dir(
+    serial_ptr: *const c_char,
+    local_dir_ptr: *const c_char,
+    remote_dir_ptr: *const c_char,
+    checksum: i32,
+    callback: Option<LineCallback>,
+    user_data: *mut c_void,
+) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        transfer_export(serial_ptr, remote_dir_ptr, local_dir_ptr, Direction::Push, checksum, callback, user_data)
+    })
+}
@@ -0,0 +1,145 @@
+use std::os::raw::c_char;
+
+use crate::adb;
+use crate::error::LbError;
+use crate::json::JsonValue;
+use crate::{ffi_guard, read_c_str, string_result};
+
+/// `<kind> <name>:` value from `wm size` / `wm density`, e.g. `Physical size: 1080x2400`
+/// or `Override density: 360`.
+pub fn wm_field<'a>(output: &'a str, kind: &str, name: &str) -> Option<&'a str> {
+    let prefix = format!("{} {}:", kind, name);
+    output
+        .lines()
+        .find_map(|line| line.trim().strip_prefix(&prefix).map(str::trim))
+}
+
+/// `1080x2400` or `1080 x 2400`.
+pub fn parse_size(value: &str) -> Option<(u32, u32)> {
+    let (width, height) = value.split_once('x')?;
+    Some((width.trim().parse().ok()?, height.trim().parse().ok()?))
+}
+
+/// Leading digits of the text right after the first `label`.
+fn number_after(text: &str, label: &str) -> Option<u32> {
+    let rest = &text[text.find(label)? + label.len()..];
+    let digits: String = rest.trim_start().chars().take_while(char::is_ascii_digit).collect();
+    digits.parse().ok()
+}
+
+/// `, real 1080 x 2400` style pairs inside `DisplayInfo{...}`.
+fn size_after(text: &str, label: &str) -> Option<(u32, u32)> {
+    let rest = &text[text.find(label)? + label.len()..];
+    parse_size(rest.split(',').next()?)
+}
+
+/// `Rect(left, top - right, bottom)`, as printed by `Rect.toString()`.
+fn parse_rect(text: &str) -> Option<[i32; 4]> {
+    let inner = text.strip_prefix("Rect(")?.split(')').next()?;
+    let values: Vec<i32> = inner
+        .split([',', '-'])
+        .map(|part| part.trim().parse())
+        .collect::<Result<_, _>>()
+        .ok()?;
+    values.try_into().ok()
+}
+
+/// The block for the default display (`mDisplayId=0`), or the whole dump when displays
+/// are not sectioned (older releases).
+fn default_display_section(dump: &str) -> &str {
+    let Some(start) = dump.find("mDisplayId=0") else {
+        return dump;
+    };
+    let section = &dump[start..];
+    match section[1..].find("Display: mDisplayId=") {
+        Some(end) => &section[..end + 1],
+        None => section,
+    }
+}
+
+/// Quarter turns from `, rotation 1` in `DisplayInfo`, or `mRotation=1` /
+/// `mCurrentRotation=ROTATION_90` elsewhere in the dump.
+fn parse_rotation(display_info: Option<&str>, section: &str) -> Option<u32> {
+    if let Some(rotation) = display_info.and_then(|info| number_after(info, ", rotation ")) {
+        return Some(rotation);
+    }
+    if let Some(rotation) = number_after(section, "mRotation=") {
+        return Some(rotation);
+    }
+    let token = section[section.find("mCurrentRotation=")? + "mCurrentRotation=".len()..]
+        .split_whitespace()
+        .next()?;
+    match token.strip_prefix("ROTATION_") {
+        Some(degrees) => degrees.parse::<u32>().ok().map(|degrees| degrees / 90),
+        None => token.parse().ok(),
+    }
+}
+
+fn size_json(size: Option<(u32, u32)>) -> Vec<(&'static str, JsonValue)> {
+    vec![
+        ("width", size.map(|(width, _)| width).into()),
+        ("height", size.map(|(_, height)| height).into()),
+    ]
+}
+
+fn metrics_json(size: Option<(u32, u32)>, density: Option<u32>) -> JsonValue {
+    let mut fields = size_json(size);
+    fields.push(("density_dpi", density.into()));
+    JsonValue::object(fields)
+}
+
+fn parse_display_info(serial: &str, output: &str) -> Result<JsonValue, LbError> {
+    let wm_size = |kind| wm_field(output, kind, "size").and_then(parse_size);
+    let wm_density = |kind| wm_field(output, kind, "density").and_then(|value| value.parse::<u32>().ok());
+    let section = default_display_section(output);
+    let display_info = section.find("DisplayInfo{").map(|start| &section[start..]);
+    let physical_size = wm_size("Physical");
+    let physical_density = wm_density("Physical");
+    if physical_size.is_none() && display_info.is_none() {
+        return Err(LbError::parse("wm size and dumpsys window printed no display information").with_serial(serial));
+    }
+    let (override_size, override_density) = (wm_size("Override"), wm_density("Override"));
+    let rotation = parse_rotation(display_info, section);
+    let cutout = display_info
+        .and_then(|info| info.find("DisplayCutout{insets=").map(|start| &info[start + "DisplayCutout{insets=".len()..]))
+        .and_then(parse_rect)
+        .map(|[left, top, right, bottom]| {
+            JsonValue::object(vec![
+                ("left", left.into()),
+                ("top", top.into()),
+                ("right", right.into()),
+                ("bottom", bottom.into()),
+            ])
+        });
+    let real = display_info.and_then(|info| size_after(info, ", real "));
+    let app = display_info.and_then(|info| size_after(info, ", app "));
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("physical", metrics_json(physical_size, physical_density)),
+        ("override", metrics_json(override_size, override_density)),
+        ("effective", metrics_json(override_size.or(physical_size), override_density.or(physical_density))),
+        ("rotation", rotation.into()),
+        ("rotation_degrees", rotation.map(|rotation| rotation * 90).into()),
+        ("real", JsonValue::object(size_json(real))),
+        ("app", JsonValue::object(size_json(app))),
+        ("cutout_insets", cutout.into()),
+    ]))
+}
+
+fn display_info(serial: &str) -> Result<String, LbError> {
+    let output = adb::shell(serial, "wm size; wm density; dumpsys window displays")?;
+    Ok(parse_display_info(serial, &output)?.to_string())
+}
+
+/// Display geometry of the default display: `{serial, physical, override, effective:
+/// {width, height, density_dpi}, rotation (quarter turns), rotation_degrees, real, app:
+/// {width, height}, cutout_insets: {left, top, right, bottom} | null}`. `physical`,
+/// `override` and `effective` are in natural orientation; `real` is the current rotated
+/// size that screenshots and UI dumps use. Fields the device does not report are null.
+#[no_mangle]
+pub extern "C" fn lb_display_info(serial_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(serial_ptr, "serial").and_then(display_info);
+        string_result(result, "display info")
+    })
+}
@@ -0,0 +1,177 @@
+use std::os::raw::c_char;
+
+use crate::adb;
+use crate::batterystats;
+use crate::device_lock::ensure_device_unlocked;
+use crate::error::{ErrorCode, LbError};
+use crate::json::JsonValue;
+use crate::logging::{lb_log, Level};
+use crate::packages;
+use crate::{ffi_guard, read_c_str, string_result};
+
+const DOZE_MODES: [&str; 4] = ["deep", "light", "step", "off"];
+/// `UsageStatsManager.STANDBY_BUCKET_*`; only active through restricted can be set.
+const BUCKETS: [(&str, u32); 7] = [
+    ("exempted", 5),
+    ("active", 10),
+    ("working_set", 20),
+    ("frequent", 30),
+    ("rare", 40),
+    ("restricted", 45),
+    ("never", 50),
+];
+const SETTABLE_BUCKETS: [&str; 5] = ["active", "working_set", "frequent", "rare", "restricted"];
+
+/// `dumpsys deviceidle` exits 0 and explains refusals (`Unable to go deep idle; not
+/// enabled`) on stdout.
+fn deviceidle(serial: &str, command: &str) -> Result<String, LbError> {
+    let output = adb::shell(serial, &format!("dumpsys deviceidle {}", command))?;
+    if output.contains("Unable to") || output.contains("Unknown command") {
+        return Err(LbError::new(
+            ErrorCode::CommandFailed,
+            format!("dumpsys deviceidle {} failed: {}", command, output.trim()),
+        )
+        .with_serial(serial));
+    }
+    Ok(output)
+}
+
+/// (deep, light) states, e.g. `IDLE` / `ACTIVE` / `IDLE_PENDING` / `OVERRIDE`.
+fn doze_states(serial: &str) -> Result<(String, String), LbError> {
+    let output = adb::shell(serial, "dumpsys deviceidle get deep; dumpsys deviceidle get light")?;
+    let mut lines = output.lines().map(str::trim).filter(|line| !line.is_empty());
+    match (lines.next(), lines.next()) {
+        (Some(deep), Some(light)) => Ok((deep.to_string(), light.to_string())),
+        _ => {
+            let message = format!("Unexpected dumpsys deviceidle get output: {}", output.trim());
+            Err(LbError::parse(message).with_serial(serial))
+        }
+    }
+}
+
+fn set_doze(serial: &str, mode: &str) -> Result<String, LbError> {
+    if !DOZE_MODES.contains(&mode) {
+        return Err(format!("Doze mode must be one of {}", DOZE_MODES.join(", ")).into());
+    }
+    ensure_device_unlocked(serial)?;
+    match mode {
+        "off" => {
+            // Exiting forced idle only wakes the device when it is charging again.
+            batterystats::set_unplugged(serial, false)?;
+            deviceidle(serial, "unforce")?;
+        }
+        _ => {
+            // Doze never starts while the framework believes the device is charging.
+            batterystats::set_unplugged(serial, true)?;
+            if mode == "step" {
+                deviceidle(serial, "step deep")?;
+            } else {
+                deviceidle(serial, &format!("enable {}", mode))?;
+                deviceidle(serial, &format!("force-idle {}", mode))?;
+            }
+        }
+    }
+    let (deep, light) = doze_states(serial)?;
+    let reached = match mode {
+        "deep" => deep == "IDLE",
+        "light" => light == "IDLE",
+        "off" => deep != "IDLE" && light != "IDLE",
+        _ => true,
+    };
+    if !reached {
+        return Err(LbError::new(
+            ErrorCode::CommandFailed,
+            format!("Doze {} did not take effect (deep {}, light {})", mode, deep, light),
+        )
+        .with_serial(serial));
+    }
+    lb_log!(Level::Info, "battery", "Doze {} on {}: deep {}, light {}", mode, serial, deep, light);
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("mode", mode.into()),
+        ("deep_state", deep.into()),
+        ("light_state", light.into()),
+    ])
+    .to_string())
+}
+
+fn bucket_name(code: u32) -> Option<&'static str> {
+    BUCKETS.iter().find(|(_, value)| *value == code).map(|(name, _)| *name)
+}
+
+/// `am get-standby-bucket` prints the numeric bucket (Android 9+).
+fn standby_bucket(serial: &str, package: &str) -> Result<u32, LbError> {
+    let output = adb::shell(serial, &format!("am get-standby-bucket {}", package))?;
+    output.trim().parse().map_err(|_| {
+        LbError::new(
+            adb::classify_failure(output.trim()),
+            format!("am get-standby-bucket failed: {}", output.trim()),
+        )
+        .with_serial(serial)
+    })
+}
+
+fn set_standby_bucket(serial: &str, package: &str, bucket: &str) -> Result<String, LbError> {
+    if !SETTABLE_BUCKETS.contains(&bucket) {
+        return Err(format!("Standby bucket must be one of {}", SETTABLE_BUCKETS.join(", ")).into());
+    }
+    ensure_device_unlocked(serial)?;
+    packages::require_installed(serial, package)?;
+    let previous = standby_bucket(serial, package)?;
+    adb::shell(serial, &format!("am set-standby-bucket {} {}", package, bucket))?;
+    let current = standby_bucket(serial, package)?;
+    if bucket_name(current) != Some(bucket) {
+        // System and otherwise exempted apps keep their bucket.
+        return Err(LbError::new(
+            ErrorCode::CommandFailed,
+            format!(
+                "{} is in bucket {} after setting {}",
+                package,
+                bucket_name(current).unwrap_or("unknown"),
+                bucket
+            ),
+        )
+        .with_serial(serial));
+    }
+    lb_log!(Level::Info, "battery", "Moved {} to standby bucket {} on {}", package, bucket, serial);
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("package", package.into()),
+        ("bucket", bucket.into()),
+        ("bucket_code", current.into()),
+        ("previous", bucket_name(previous).into()),
+        ("previous_code", previous.into()),
+    ])
+    .to_string())
+}
+
+/// Drives Doze: `deep` / `light` fake unplugging and `force-idle` that mode, `step` advances
+/// the deep state machine one state, `off` restores charging and `unforce`s. Returns
+/// `{serial, mode, deep_state, light_state}` read back from `dumpsys deviceidle get`.
+#[no_mangle]
+pub extern "C" fn lb_set_doze(serial_ptr: *const c_char, mode_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(serial_ptr, "serial")
+            .and_then(|serial| read_c_str(mode_ptr, "doze mode").and_then(|mode| set_doze(serial, mode)));
+        string_result(result, "doze state")
+    })
+}
+
+/// Moves `package` into an App Standby bucket (`active`, `working_set`, `frequent`, `rare`,
+/// `restricted`) and reads it back: `{serial, package, bucket, bucket_code, previous,
+/// previous_code}`. Android 9+; exempted apps that keep their bucket fail with CommandFailed.
+#[no_mangle]
+pub extern "C" fn lb_set_standby_bucket(
+    serial_ptr: *const c_char,
+    package_ptr: *const c_char,
+    bucket_ptr: *const c_char,
+) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(serial_ptr, "serial").and_then(|serial| {
+            let package = read_c_str(package_ptr, "package")?;
+            let bucket = read_c_str(bucket_ptr, "standby bucket")?;
+            set_standby_bucket(serial, package, bucket)
+        });
+        string_result(result, "standby bucket")
+    })
+}
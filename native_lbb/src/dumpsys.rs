@@ -0,0 +1,756 @@
+use std::os::raw::c_char;
+
+use crate::adb;
+use crate::battery;
+use crate::error::LbError;
+use crate::exec;
+use crate::json::JsonValue;
+use crate::packages;
+use crate::{ffi_guard, read_c_str, string_result};
+
+type Parser = fn(&str) -> Option<JsonValue>;
+
+/// Structured parsers keyed by dumpsys service name, or `service subcommand` for dumps
+/// whose shape depends on the first argument (looked up first). A parser returns `None`
+/// when the output does not look like what it understands (e.g. unexpected args), in
+/// which case the raw text is returned instead. Add new services here.
+const PARSERS: [(&str, Parser); 9] = [
+    ("battery", parse_battery),
+    ("wifi", parse_wifi),
+    ("meminfo", parse_meminfo),
+    ("package", parse_package),
+    ("activity top", parse_activity_top),
+    ("activity activities", parse_activity_stack),
+    ("activity", parse_activity),
+    ("cpuinfo", parse_cpuinfo),
+    ("gfxinfo", parse_gfxinfo),
+];
+
+const PACKAGE_FIELDS: [&str; 9] = [
+    "versionCode",
+    "versionName",
+    "minSdk",
+    "targetSdk",
+    "firstInstallTime",
+    "lastUpdateTime",
+    "installerPackageName",
+    "codePath",
+    "dataDir",
+];
+
+/// `Link speed` -> `link_speed`, `TOTAL PSS` -> `total_pss`.
+fn snake_case(label: &str) -> String {
+    let mut key = String::new();
+    for ch in label.trim().chars() {
+        if ch.is_ascii_alphanumeric() {
+            key.push(ch.to_ascii_lowercase());
+        } else if !key.is_empty() && !key.ends_with('_') {
+            key.push('_');
+        }
+    }
+    key.trim_end_matches('_').to_string()
+}
+
+/// `versionCode` -> `version_code`.
+fn snake_case_camel(name: &str) -> String {
+    let mut key = String::new();
+    for ch in name.chars() {
+        if ch.is_ascii_uppercase() {
+            key.push('_');
+        }
+        key.push(ch.to_ascii_lowercase());
+    }
+    key
+}
+
+/// Numbers become JSON numbers; everything else stays a string.
+fn scalar(value: &str) -> JsonValue {
+    match value.parse::<f64>() {
+        Ok(number) if number.is_finite() => number.into(),
+        _ => value.into(),
+    }
+}
+
+fn insert_once(fields: &mut Vec<(String, JsonValue)>, key: String, value: JsonValue) {
+    if !key.is_empty() && !fields.iter().any(|(existing, _)| *existing == key) {
+        fields.push((key, value));
+    }
+}
+
+fn parse_battery(output: &str) -> Option<JsonValue> {
+    let mut fields = Vec::new();
+    for (name, value) in battery::parse_dumpsys(output) {
+        if value.is_empty() {
+            continue;
+        }
+        let value = if name == "health" { battery::health_name(&value).into() } else { scalar(&value) };
+        insert_once(&mut fields, snake_case(&name), value);
+    }
+    (!fields.is_empty()).then_some(JsonValue::Object(fields))
+}
+
+/// `Wi-Fi is enabled` plus the comma-separated `mWifiInfo SSID: "x", BSSID: ..., RSSI: -55` line.
+fn parse_wifi(output: &str) -> Option<JsonValue> {
+    let enabled = output.lines().find_map(|line| match line.trim() {
+        "Wi-Fi is enabled" => Some(true),
+        "Wi-Fi is disabled" => Some(false),
+        _ => None,
+    });
+    let connection = output
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("mWifiInfo "))
+        .map(|info| {
+            let mut fields = Vec::new();
+            for pair in info.split(", ") {
+                if let Some((name, value)) = pair.split_once(": ") {
+                    insert_once(&mut fields, snake_case(name), scalar(value.trim().trim_matches('"')));
+                }
+            }
+            JsonValue::Object(fields)
+        });
+    if enabled.is_none() && connection.is_none() {
+        return None;
+    }
+    Some(JsonValue::object(vec![("enabled", enabled.into()), ("connection", connection.into())]))
+}
+
+/// Every `Label: 1,234K` pair (several may share a line, as in `TOTAL PSS: 1 TOTAL RSS: 2`),
+/// keyed by label with values in kB. App summaries keep the first (PSS) column.
+fn parse_meminfo(output: &str) -> Option<JsonValue> {
+    let mut fields = Vec::new();
+    for line in output.lines() {
+        let mut rest = line;
+        while let Some((label, after)) = rest.split_once(':') {
+            let after = after.trim_start();
+            let token = after.split_whitespace().next().unwrap_or("");
+            let number = token.trim_end_matches('K').replace(',', "");
+            if let Ok(kb) = number.parse::<u64>() {
+                insert_once(&mut fields, snake_case(label), kb.into());
+            }
+            rest = &after[token.len()..];
+        }
+    }
+    (!fields.is_empty()).then_some(JsonValue::Object(fields))
+}
+
+/// The first `Package [name]` block: version and install fields plus runtime and install
+/// permission grants.
+fn parse_package(output: &str) -> Option<JsonValue> {
+    let package = output.lines().find_map(|line| {
+        let rest = line.trim().strip_prefix("Package [")?;
+        rest.split_once(']').map(|(name, _)| name.to_string())
+    })?;
+    let mut fields = Vec::new();
+    let mut granted = Vec::new();
+    let mut denied = Vec::new();
+    for line in output.lines() {
+        let line = line.trim();
+        if let Some((permission, state)) = line.split_once(": granted=") {
+            let permission = JsonValue::from(permission.trim());
+            if state.starts_with("true") {
+                granted.push(permission);
+            } else {
+                denied.push(permission);
+            }
+            continue;
+        }
+        for token in line.split_whitespace() {
+            if let Some((name, value)) = token.split_once('=') {
+                if PACKAGE_FIELDS.contains(&name) {
+                    insert_once(&mut fields, snake_case_camel(name), scalar(value));
+                }
+            }
+        }
+    }
+    let mut pairs = vec![("package".to_string(), JsonValue::from(package))];
+    pairs.extend(fields);
+    pairs.push(("granted_permissions".to_string(), granted.into()));
+    pairs.push(("denied_permissions".to_string(), denied.into()));
+    Some(JsonValue::Object(pairs))
+}
+
+/// `ActivityRecord{8d8e2f u0 com.example/.Main t128}` -> (`com.example/.Main`, task 128).
+fn activity_record(line: &str) -> Option<(String, Option<u64>)> {
+    let (_, record) = line.split_once('{')?;
+    let (record, _) = record.split_once('}')?;
+    let component = record.split_whitespace().find(|token| token.contains('/'))?;
+    let task_id = record
+        .split_whitespace()
+        .filter_map(|token| token.strip_prefix('t'))
+        .find_map(|id| id.parse::<u64>().ok());
+    Some((component.to_string(), task_id))
+}
+
+/// The resumed activity from `mResumedActivity:` / `topResumedActivity=` / `ResumedActivity:`.
+fn resumed_activity(output: &str) -> Option<(String, Option<u64>)> {
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|line| {
+            line.starts_with("mResumedActivity") || line.starts_with("topResumedActivity") || line.starts_with("ResumedActivity")
+        })
+        .find_map(activity_record)
+}
+
+fn package_of(component: &str) -> Option<&str> {
+    component.split_once('/').map(|(package, _)| package)
+}
+
+/// Resumed activity from `mResumedActivity:` / `topResumedActivity=` records and the
+/// number of tasks listed.
+fn parse_activity(output: &str) -> Option<JsonValue> {
+    let resumed = resumed_activity(output).map(|(component, _)| component);
+    let task_count = output
+        .lines()
+        .map(str::trim)
+        .filter(|line| line.starts_with("* Task{") || line.starts_with("* TaskRecord{"))
+        .count();
+    if resumed.is_none() && task_count == 0 {
+        return None;
+    }
+    let package = resumed.as_deref().and_then(package_of);
+    Some(JsonValue::object(vec![
+        ("resumed_activity", resumed.as_deref().into()),
+        ("resumed_package", package.into()),
+        ("task_count", task_count.into()),
+    ]))
+}
+
+struct StackTask {
+    id: Option<u64>,
+    display: Option<u64>,
+    kind: Option<String>,
+    affinity: Option<String>,
+    visible: Option<bool>,
+    activities: Vec<String>,
+}
+
+/// `* Task{5e6a0b5 #128 type=standard A=10234:com.example U=0 visible=true ...}` (Android 12+)
+/// or `* TaskRecord{3d0f0ae #128 A=com.example U=0 StackId=128 sz=2}`.
+/// Releases before 12 put the activity type on the enclosing `Stack #12: type=standard` line,
+/// passed as `stack_type`.
+fn stack_task(header: &str, display: Option<u64>, stack_type: Option<&str>) -> StackTask {
+    let field = |name: &str| {
+        header
+            .split_whitespace()
+            .find_map(|token| token.strip_prefix(name))
+            .map(|value| value.trim_end_matches('}'))
+    };
+    StackTask {
+        id: field("#").and_then(|id| id.parse().ok()),
+        display,
+        kind: field("type=").or(stack_type).map(str::to_string),
+        // Android 12+ prefixes the affinity with the owning uid.
+        affinity: field("A=").map(|affinity| affinity.rsplit(':').next().unwrap_or(affinity).to_string()),
+        visible: field("visible=").and_then(|visible| visible.parse().ok()),
+        activities: Vec::new(),
+    }
+}
+
+/// Tasks from `dumpsys activity activities`, top to bottom, each with its activities from
+/// the `* Hist #N:` history records (newest first), falling back to the `Run #N:` lists of
+/// older releases. Container tasks that hold no activities themselves are dropped.
+fn parse_activity_stack(output: &str) -> Option<JsonValue> {
+    let record_prefix = if output.lines().any(|line| line.trim_start().starts_with("* Hist")) { "* Hist" } else { "Run #" };
+    let mut display = None;
+    let mut stack_type = None;
+    let mut tasks: Vec<StackTask> = Vec::new();
+    for line in output.lines().map(str::trim) {
+        if let Some(rest) = line.strip_prefix("Display #") {
+            display = rest.split_whitespace().next().and_then(|id| id.parse().ok());
+        } else if line.starts_with("Stack #") {
+            stack_type = line.split_whitespace().find_map(|token| token.strip_prefix("type="));
+        } else if line.starts_with("* Task{") || line.starts_with("* TaskRecord{") {
+            tasks.push(stack_task(line, display, stack_type));
+        } else if line.starts_with(record_prefix) {
+            let Some((component, task_id)) = activity_record(line) else {
+                continue;
+            };
+            let task = match task_id {
+                Some(id) => tasks.iter_mut().rev().find(|task| task.id == Some(id)),
+                None => tasks.last_mut(),
+            };
+            if let Some(task) = task {
+                if !task.activities.contains(&component) {
+                    task.activities.push(component);
+                }
+            }
+        }
+    }
+    tasks.retain(|task| !task.activities.is_empty());
+    let resumed = resumed_activity(output);
+    if tasks.is_empty() && resumed.is_none() {
+        return None;
+    }
+    let focused_task = resumed.as_ref().and_then(|(_, task_id)| *task_id);
+    let focused = resumed.map(|(component, _)| component);
+    let tasks: Vec<JsonValue> = tasks
+        .into_iter()
+        .map(|task| {
+            let activities: Vec<JsonValue> = task
+                .activities
+                .iter()
+                .map(|component| {
+                    JsonValue::object(vec![("component", component.into()), ("package", package_of(component).into())])
+                })
+                .collect();
+            JsonValue::object(vec![
+                ("id", task.id.into()),
+                ("display", task.display.into()),
+                ("type", task.kind.into()),
+                ("affinity", task.affinity.into()),
+                ("visible", task.visible.into()),
+                ("top_activity", task.activities.first().into()),
+                ("activities", activities.into()),
+            ])
+        })
+        .collect();
+    Some(JsonValue::object(vec![
+        ("focused_activity", focused.as_deref().into()),
+        ("focused_package", focused.as_deref().and_then(package_of).into()),
+        ("focused_task_id", focused_task.into()),
+        ("tasks", tasks.into()),
+    ]))
+}
+
+/// `TASK com.example id=12 userId=0` followed by `ACTIVITY com.example/.Main 5d2f1a pid=4321`,
+/// one pair per resumed task; the first is the focused one.
+fn parse_activity_top(output: &str) -> Option<JsonValue> {
+    let mut task_id = None;
+    let mut activities = Vec::new();
+    for line in output.lines().map(str::trim) {
+        if let Some(task) = line.strip_prefix("TASK ") {
+            task_id = task
+                .split_whitespace()
+                .find_map(|token| token.strip_prefix("id="))
+                .and_then(|id| id.parse::<u64>().ok());
+        } else if let Some(activity) = line.strip_prefix("ACTIVITY ") {
+            let mut tokens = activity.split_whitespace();
+            let Some(component) = tokens.next().filter(|component| component.contains('/')) else {
+                continue;
+            };
+            let pid = tokens
+                .find_map(|token| token.strip_prefix("pid="))
+                .and_then(|pid| pid.parse::<u64>().ok());
+            let package = component.split_once('/').map(|(package, _)| package);
+            activities.push(JsonValue::object(vec![
+                ("component", component.into()),
+                ("package", package.into()),
+                ("pid", pid.into()),
+                ("task_id", task_id.into()),
+            ]));
+        }
+    }
+    let focused = activities.first().cloned();
+    (!activities.is_empty()).then(|| JsonValue::object(vec![("focused", focused.into()), ("activities", activities.into())]))
+}
+
+/// `12.5%` -> 12.5.
+fn percent(token: &str) -> Option<f64> {
+    token.trim().strip_suffix('%')?.parse().ok()
+}
+
+/// `8% user + 4% kernel + 0.5% iowait` -> `{"user": 8, "kernel": 4, "iowait": 0.5}`.
+fn cpu_breakdown(text: &str) -> Vec<(String, JsonValue)> {
+    let mut fields = Vec::new();
+    for part in text.split('+') {
+        let mut tokens = part.split_whitespace();
+        if let (Some(value), Some(name)) = (tokens.next().and_then(percent), tokens.next()) {
+            insert_once(&mut fields, snake_case(name), value.into());
+        }
+    }
+    fields
+}
+
+/// `Load: 1.0 / 2.0 / 3.0`, one `12% 1234/name: 8% user + 4% kernel / faults: ...` line per
+/// process, and the `27% TOTAL: ...` summary.
+fn parse_cpuinfo(output: &str) -> Option<JsonValue> {
+    let load = output.lines().find_map(|line| {
+        let averages: Vec<JsonValue> = line
+            .trim()
+            .strip_prefix("Load:")?
+            .split('/')
+            .filter_map(|value| value.trim().parse::<f64>().ok())
+            .map(JsonValue::from)
+            .collect();
+        (averages.len() == 3).then_some(averages)
+    });
+    let mut processes = Vec::new();
+    let mut total = None;
+    for line in output.lines().map(str::trim) {
+        let Some((head, breakdown)) = line.split_once(": ") else {
+            continue;
+        };
+        let Some((usage, name)) = head.split_once(' ') else {
+            continue;
+        };
+        let Some(usage) = percent(usage) else {
+            continue;
+        };
+        let breakdown = breakdown.split(" / ").next().unwrap_or(breakdown);
+        let mut fields = vec![("total".to_string(), JsonValue::from(usage))];
+        fields.extend(cpu_breakdown(breakdown));
+        if name.trim() == "TOTAL" {
+            total = Some(JsonValue::Object(fields));
+            continue;
+        }
+        let Some((pid, process)) = name.split_once('/').filter(|(pid, _)| pid.chars().all(|ch| ch.is_ascii_digit())) else {
+            continue;
+        };
+        let mut entry = vec![
+            ("pid".to_string(), JsonValue::from(pid.parse::<u64>().unwrap_or(0))),
+            ("name".to_string(), JsonValue::from(process)),
+        ];
+        entry.extend(fields);
+        processes.push(JsonValue::Object(entry));
+    }
+    if load.is_none() && total.is_none() && processes.is_empty() {
+        return None;
+    }
+    Some(JsonValue::object(vec![
+        ("load", load.into()),
+        ("total", total.into()),
+        ("processes", processes.into()),
+    ]))
+}
+
+/// The frame summary of `dumpsys gfxinfo <pkg>`: `Total frames rendered`, `Janky frames: 56
+/// (4.54%)`, `50th percentile: 8ms` and the `Number Slow UI thread: 12` style counters.
+pub fn parse_gfxinfo(output: &str) -> Option<JsonValue> {
+    let mut fields = Vec::new();
+    let mut percentiles = Vec::new();
+    let mut counters = Vec::new();
+    for line in output.lines().map(str::trim) {
+        let Some((label, value)) = line.split_once(": ") else {
+            continue;
+        };
+        let mut tokens = value.split_whitespace();
+        let first = tokens.next().unwrap_or("");
+        match label {
+            "Total frames rendered" => insert_once(&mut fields, "total_frames".into(), scalar(first)),
+            "Janky frames" => {
+                insert_once(&mut fields, "janky_frames".into(), scalar(first));
+                let share = tokens.next().and_then(|share| percent(share.trim_matches(['(', ')'])));
+                insert_once(&mut fields, "janky_percent".into(), share.into());
+            }
+            _ => {
+                if let Some(rank) = label.strip_suffix(" percentile") {
+                    let key = format!("p{}_ms", rank.trim_end_matches(|ch: char| ch.is_ascii_alphabetic()));
+                    if let Ok(ms) = first.trim_end_matches("ms").parse::<f64>() {
+                        insert_once(&mut percentiles, key, ms.into());
+                    }
+                } else if let Some(counter) = label.strip_prefix("Number ") {
+                    if let Ok(count) = first.parse::<u64>() {
+                        insert_once(&mut counters, snake_case(counter), count.into());
+                    }
+                }
+            }
+        }
+    }
+    if fields.is_empty() {
+        return None;
+    }
+    fields.push(("percentiles".to_string(), JsonValue::Object(percentiles)));
+    fields.push(("counters".to_string(), JsonValue::Object(counters)));
+    Some(JsonValue::Object(fields))
+}
+
+/// `args` is split like a command line (quotes group words) and every word is quoted for
+/// the device shell, so `;`, `|` or `$(...)` reach dumpsys as text instead of running.
+pub fn run_dumpsys(serial: &str, service: &str, args: &str) -> Result<String, LbError> {
+    if service.is_empty() || !service.chars().all(|ch| ch.is_ascii_alphanumeric() || "._-".contains(ch)) {
+        return Err(format!("Invalid dumpsys service name: {}", service).into());
+    }
+    let mut command = format!("dumpsys {}", service);
+    for arg in exec::shlex_split(args)? {
+        command.push(' ');
+        command.push_str(&adb::shell_quote(&arg));
+    }
+    let output = adb::shell(serial, &command)?;
+    // dumpsys exits 0 for unknown services and only prints this line.
+    if output.trim_start().starts_with("Can't find service:") {
+        return Err(LbError::not_found(format!("No dumpsys service '{}' on {}", service, serial)).with_serial(serial));
+    }
+    Ok(output)
+}
+
+fn parser_for(service: &str, args: &str) -> Option<Parser> {
+    let subcommand = args.split_whitespace().next().map(|first| format!("{} {}", service, first));
+    PARSERS
+        .iter()
+        .find(|(name, _)| subcommand.as_deref() == Some(*name))
+        .or_else(|| PARSERS.iter().find(|(name, _)| *name == service))
+        .map(|(_, parser)| *parser)
+}
+
+fn dumpsys(serial: &str, service: &str, args: &str) -> Result<String, LbError> {
+    let output = run_dumpsys(serial, service, args)?;
+    let parsed = parser_for(service, args).and_then(|parser| parser(&output));
+    let mut pairs = vec![
+        ("serial", serial.into()),
+        ("service", service.into()),
+        ("args", args.trim().into()),
+        ("parsed", parsed.is_some().into()),
+    ];
+    match parsed {
+        Some(data) => pairs.push(("data", data)),
+        None => pairs.push(("raw", output.into())),
+    }
+    Ok(JsonValue::object(pairs).to_string())
+}
+
+/// Runs `dumpsys <service> <args>` and returns `{"serial", "service", "args", "parsed": true,
+/// "data"}` when a registered parser (battery, wifi, meminfo, package, activity, activity top,
+/// activity activities, cpuinfo, gfxinfo) understands the output, or `{"parsed": false, "raw"}` with the text
+/// otherwise. `args` may be null; each word is quoted, so shell syntax in it is not run.
+#[no_mangle]
+pub extern "C" fn lb_dumpsys(serial_ptr: *const c_char, service_ptr: *const c_char, args_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let args = if args_ptr.is_null() { Ok("") } else { read_c_str(args_ptr, "args") };
+        let result = read_c_str(serial_ptr, "serial").and_then(|serial| {
+            read_c_str(service_ptr, "service").and_then(|service| args.and_then(|args| dumpsys(serial, service, args)))
+        });
+        string_result(result, "dumpsys report")
+    })
+}
+
+/// Runs one dump and returns only its parsed data; output the parser does not recognize
+/// is a ParseError rather than raw text.
+fn dumpsys_json(serial: &str, service: &str, package: Option<&str>, args: &str) -> Result<String, LbError> {
+    if let Some(package) = package {
+        packages::validate_package(package)?;
+    }
+    let args = match package {
+        Some(package) => format!("{} {}", args, package),
+        None => args.to_string(),
+    };
+    let output = run_dumpsys(serial, service, &args)?;
+    parser_for(service, &args)
+        .and_then(|parser| parser(&output))
+        .map(|data| data.to_string())
+        .ok_or_else(|| LbError::parse(format!("Unrecognized `dumpsys {} {}` output on {}", service, args.trim(), serial)).with_serial(serial))
+}
+
+/// `{level, scale, status, health, plugged, voltage, temperature, ...}` from `dumpsys battery`.
+#[no_mangle]
+pub extern "C" fn lb_dumpsys_battery_json(serial_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(serial_ptr, "serial").and_then(|serial| dumpsys_json(serial, "battery", None, ""));
+        string_result(result, "battery dump")
+    })
+}
+
+/// `dumpsys meminfo <package>` (or the system-wide summary when `package` is null) as
+/// `{label: kB}` pairs such as `total_pss`, `java_heap`, `native_heap`.
+#[no_mangle]
+pub extern "C" fn lb_dumpsys_meminfo_json(serial_ptr: *const c_char, package_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let package = if package_ptr.is_null() { Ok(None) } else { read_c_str(package_ptr, "package").map(Some) };
+        let result = read_c_str(serial_ptr, "serial")
+            .and_then(|serial| package.and_then(|package| dumpsys_json(serial, "meminfo", package, "")));
+        string_result(result, "meminfo dump")
+    })
+}
+
+/// `{load: [1m, 5m, 15m], total: {total, user, kernel, ...}, processes: [{pid, name, total,
+/// user, kernel}]}` from `dumpsys cpuinfo`; percentages are numbers.
+#[no_mangle]
+pub extern "C" fn lb_dumpsys_cpuinfo_json(serial_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(serial_ptr, "serial").and_then(|serial| dumpsys_json(serial, "cpuinfo", None, ""));
+        string_result(result, "cpuinfo dump")
+    })
+}
+
+/// `{total_frames, janky_frames, janky_percent, percentiles: {p50_ms, ...}, counters:
+/// {missed_vsync, slow_ui_thread, ...}}` from `dumpsys gfxinfo <package>`.
+#[no_mangle]
+pub extern "C" fn lb_dumpsys_gfxinfo_json(serial_ptr: *const c_char, package_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(serial_ptr, "serial").and_then(|serial| {
+            read_c_str(package_ptr, "package").and_then(|package| dumpsys_json(serial, "gfxinfo", Some(package), ""))
+        });
+        string_result(result, "gfxinfo dump")
+    })
+}
+
+/// `{focused, activities: [{component, package, pid, task_id}]}` from `dumpsys activity top`;
+/// `focused` is the first resumed activity.
+#[no_mangle]
+pub extern "C" fn lb_dumpsys_activity_top_json(serial_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(serial_ptr, "serial").and_then(|serial| dumpsys_json(serial, "activity", None, "top"));
+        string_result(result, "activity dump")
+    })
+}
+
+/// `{focused_activity, focused_package, focused_task_id, tasks: [{id, display, type, affinity,
+/// visible, top_activity, activities: [{component, package}]}]}` from `dumpsys activity
+/// activities`; tasks run top to bottom and activities newest first.
+#[no_mangle]
+pub extern "C" fn lb_activity_stack(serial_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result =
+            read_c_str(serial_ptr, "serial").and_then(|serial| dumpsys_json(serial, "activity", None, "activities"));
+        string_result(result, "activity stack")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> &'static str {
+        match name {
+            "battery" => include_str!("../tests/fixtures/dumpsys/battery.txt"),
+            "wifi" => include_str!("../tests/fixtures/dumpsys/wifi.txt"),
+            "meminfo" => include_str!("../tests/fixtures/dumpsys/meminfo.txt"),
+            "package" => include_str!("../tests/fixtures/dumpsys/package.txt"),
+            "activity" => include_str!("../tests/fixtures/dumpsys/activity.txt"),
+            "activity_top" => include_str!("../tests/fixtures/dumpsys/activity_top.txt"),
+            "cpuinfo" => include_str!("../tests/fixtures/dumpsys/cpuinfo.txt"),
+            "gfxinfo" => include_str!("../tests/fixtures/dumpsys/gfxinfo.txt"),
+            other => panic!("no fixture {}", other),
+        }
+    }
+
+    fn field<'a>(value: &'a JsonValue, path: &[&str]) -> &'a JsonValue {
+        path.iter().fold(value, |value, key| {
+            value.get(key).unwrap_or_else(|| panic!("missing {} in {}", key, value))
+        })
+    }
+
+    #[test]
+    fn battery() {
+        let parsed = parse_battery(fixture("battery")).unwrap();
+        assert_eq!(field(&parsed, &["level"]).as_u64(), Some(85));
+        assert_eq!(field(&parsed, &["usb_powered"]).as_str(), Some("true"));
+        assert_eq!(field(&parsed, &["health"]).as_str(), Some("Good"));
+        assert_eq!(field(&parsed, &["temperature"]).as_u64(), Some(281));
+        assert_eq!(field(&parsed, &["technology"]).as_str(), Some("Li-ion"));
+        // The section header has no value and is skipped.
+        assert!(parsed.get("current_battery_service_state").is_none());
+    }
+
+    #[test]
+    fn wifi() {
+        let parsed = parse_wifi(fixture("wifi")).unwrap();
+        assert_eq!(field(&parsed, &["enabled"]).as_bool(), Some(true));
+        let connection = field(&parsed, &["connection"]);
+        assert_eq!(field(connection, &["ssid"]).as_str(), Some("HomeNet"));
+        assert_eq!(field(connection, &["bssid"]).as_str(), Some("02:15:b2:00:01:00"));
+        assert_eq!(field(connection, &["rssi"]).as_f64(), Some(-55.0));
+        assert_eq!(field(connection, &["link_speed"]).as_str(), Some("72Mbps"));
+        assert_eq!(field(connection, &["supplicant_state"]).as_str(), Some("COMPLETED"));
+    }
+
+    #[test]
+    fn wifi_disabled_without_connection() {
+        let parsed = parse_wifi("Wi-Fi is disabled\n").unwrap();
+        assert_eq!(field(&parsed, &["enabled"]).as_bool(), Some(false));
+        assert!(field(&parsed, &["connection"]).as_object().is_none());
+        assert!(parse_wifi("Can't find service: wifi\n").is_none());
+    }
+
+    #[test]
+    fn meminfo() {
+        let parsed = parse_meminfo(fixture("meminfo")).unwrap();
+        assert_eq!(field(&parsed, &["java_heap"]).as_u64(), Some(8204));
+        assert_eq!(field(&parsed, &["native_heap"]).as_u64(), Some(12300));
+        assert_eq!(field(&parsed, &["stack"]).as_u64(), Some(512));
+        assert_eq!(field(&parsed, &["total_pss"]).as_u64(), Some(45678));
+        assert_eq!(field(&parsed, &["total_rss"]).as_u64(), Some(98765));
+        assert_eq!(field(&parsed, &["total_swap_pss"]).as_u64(), Some(30));
+    }
+
+    #[test]
+    fn package() {
+        let parsed = parse_package(fixture("package")).unwrap();
+        assert_eq!(field(&parsed, &["package"]).as_str(), Some("com.example.app"));
+        assert_eq!(field(&parsed, &["version_code"]).as_u64(), Some(42));
+        assert_eq!(field(&parsed, &["version_name"]).as_str(), Some("1.4.2"));
+        assert_eq!(field(&parsed, &["target_sdk"]).as_u64(), Some(34));
+        assert_eq!(field(&parsed, &["installer_package_name"]).as_str(), Some("com.android.vending"));
+        assert_eq!(
+            field(&parsed, &["granted_permissions"]).as_string_array(),
+            Some(vec!["android.permission.INTERNET".to_string(), "android.permission.CAMERA".to_string()])
+        );
+        assert_eq!(
+            field(&parsed, &["denied_permissions"]).as_string_array(),
+            Some(vec!["android.permission.ACCESS_FINE_LOCATION".to_string()])
+        );
+        assert!(parse_package("Unable to find package: com.missing\n").is_none());
+    }
+
+    #[test]
+    fn activity() {
+        let parsed = parse_activity(fixture("activity")).unwrap();
+        assert_eq!(field(&parsed, &["resumed_activity"]).as_str(), Some("com.example.app/.DetailActivity"));
+        assert_eq!(field(&parsed, &["resumed_package"]).as_str(), Some("com.example.app"));
+        assert_eq!(field(&parsed, &["task_count"]).as_u64(), Some(3));
+    }
+
+    #[test]
+    fn activity_stack() {
+        let parsed = parse_activity_stack(fixture("activity")).unwrap();
+        assert_eq!(field(&parsed, &["focused_task_id"]).as_u64(), Some(128));
+        let tasks = field(&parsed, &["tasks"]).as_array().unwrap();
+        // The home container task holds no activities of its own and is dropped.
+        assert_eq!(tasks.len(), 2);
+        assert_eq!(field(&tasks[0], &["id"]).as_u64(), Some(128));
+        assert_eq!(field(&tasks[0], &["affinity"]).as_str(), Some("com.example.app"));
+        assert_eq!(field(&tasks[0], &["visible"]).as_bool(), Some(true));
+        assert_eq!(field(&tasks[0], &["top_activity"]).as_str(), Some("com.example.app/.DetailActivity"));
+        assert_eq!(field(&tasks[0], &["activities"]).as_array().map(Vec::len), Some(2));
+        assert_eq!(field(&tasks[1], &["type"]).as_str(), Some("home"));
+        assert_eq!(field(&tasks[1], &["top_activity"]).as_str(), Some("com.android.launcher3/.Launcher"));
+    }
+
+    #[test]
+    fn activity_top() {
+        let parsed = parse_activity_top(fixture("activity_top")).unwrap();
+        assert_eq!(field(&parsed, &["focused", "component"]).as_str(), Some("com.example.app/.DetailActivity"));
+        assert_eq!(field(&parsed, &["focused", "pid"]).as_u64(), Some(4321));
+        assert_eq!(field(&parsed, &["focused", "task_id"]).as_u64(), Some(128));
+        let activities = field(&parsed, &["activities"]).as_array().unwrap();
+        assert_eq!(activities.len(), 2);
+        assert_eq!(field(&activities[1], &["package"]).as_str(), Some("com.android.launcher3"));
+    }
+
+    #[test]
+    fn cpuinfo() {
+        let parsed = parse_cpuinfo(fixture("cpuinfo")).unwrap();
+        let load: Vec<f64> = field(&parsed, &["load"]).as_array().unwrap().iter().filter_map(JsonValue::as_f64).collect();
+        assert_eq!(load, vec![2.5, 1.75, 1.2]);
+        assert_eq!(field(&parsed, &["total", "total"]).as_f64(), Some(27.0));
+        assert_eq!(field(&parsed, &["total", "iowait"]).as_f64(), Some(0.5));
+        let processes = field(&parsed, &["processes"]).as_array().unwrap();
+        assert_eq!(processes.len(), 3);
+        assert_eq!(field(&processes[0], &["pid"]).as_u64(), Some(4321));
+        assert_eq!(field(&processes[0], &["name"]).as_str(), Some("com.example.app"));
+        assert_eq!(field(&processes[0], &["user"]).as_f64(), Some(8.0));
+        assert_eq!(field(&processes[1], &["total"]).as_f64(), Some(3.5));
+    }
+
+    #[test]
+    fn gfxinfo() {
+        let parsed = parse_gfxinfo(fixture("gfxinfo")).unwrap();
+        assert_eq!(field(&parsed, &["total_frames"]).as_u64(), Some(1234));
+        assert_eq!(field(&parsed, &["janky_frames"]).as_u64(), Some(56));
+        assert_eq!(field(&parsed, &["janky_percent"]).as_f64(), Some(4.54));
+        assert_eq!(field(&parsed, &["percentiles", "p50_ms"]).as_f64(), Some(8.0));
+        assert_eq!(field(&parsed, &["percentiles", "p99_ms"]).as_f64(), Some(42.0));
+        assert_eq!(field(&parsed, &["counters", "slow_ui_thread"]).as_u64(), Some(12));
+        assert_eq!(field(&parsed, &["counters", "frame_deadline_missed"]).as_u64(), Some(9));
+        assert!(parse_gfxinfo("No process found for: com.missing\n").is_none());
+    }
+
+    #[test]
+    fn parser_lookup_prefers_subcommand() {
+        let activity_top: Parser = parse_activity_top;
+        let activity: Parser = parse_activity;
+        assert_eq!(parser_for("activity", "top").map(|parser| parser as usize), Some(activity_top as usize));
+        assert_eq!(parser_for("activity", "").map(|parser| parser as usize), Some(activity as usize));
+        assert!(parser_for("window", "").is_none());
+    }
+}
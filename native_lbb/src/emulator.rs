@@ -0,0 +1,351 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpStream};
+use std::os::raw::c_char;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::error::{ErrorCode, LbError};
+use crate::json::JsonValue;
+use crate::logging::{lb_log, Level};
+use crate::{ffi_guard, read_c_str, status_result, string_result};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+/// Most commands answer at once; `geo fix` and `sms send` can take a moment on a busy host.
+const IO_TIMEOUT: Duration = Duration::from_secs(10);
+const TOKEN_FILE: &str = ".emulator_console_auth_token";
+const NETWORK_SPEEDS: [&str; 9] = ["gsm", "hscsd", "gprs", "edge", "umts", "hsdpa", "lte", "evdo", "full"];
+const NETWORK_DELAYS: [&str; 4] = ["gprs", "edge", "umts", "none"];
+/// FFI call actions and the `gsm` verbs they map to.
+const CALL_ACTIONS: [(&str, &str); 5] = [
+    ("incoming", "call"),
+    ("accept", "accept"),
+    ("busy", "busy"),
+    ("hold", "hold"),
+    ("hangup", "cancel"),
+];
+
+/// `emulator-5554` -> 5554, the console port; adb talks to the emulator on port + 1.
+fn console_port(serial: &str) -> Result<u16, LbError> {
+    serial
+        .strip_prefix("emulator-")
+        .and_then(|port| port.parse().ok())
+        .ok_or_else(|| format!("{} is not an emulator serial (emulator-<port>)", serial).into())
+}
+
+/// One telnet-style console session. Every reply is output lines ending in `OK` (or
+/// `OK: message`), or a `KO: reason` line.
+struct Console {
+    serial: String,
+    port: u16,
+    reader: BufReader<TcpStream>,
+    stream: TcpStream,
+}
+
+impl Console {
+    fn connect(serial: &str) -> Result<Console, LbError> {
+        let port = console_port(serial)?;
+        let address = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+        let stream = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT).map_err(|err| {
+            LbError::new(ErrorCode::DeviceOffline, format!("No emulator console on port {}: {}", port, err))
+                .with_serial(serial)
+        })?;
+        stream
+            .set_read_timeout(Some(IO_TIMEOUT))
+            .and_then(|_| stream.set_write_timeout(Some(IO_TIMEOUT)))
+            .map_err(|err| LbError::io(format!("Failed to configure emulator console socket: {}", err)))?;
+        let reader = BufReader::new(
+            stream
+                .try_clone()
+                .map_err(|err| LbError::io(format!("Failed to clone emulator console socket: {}", err)))?,
+        );
+        let mut console = Console {
+            serial: serial.to_string(),
+            port,
+            reader,
+            stream,
+        };
+        let banner = console.read_reply("banner")?;
+        if banner.contains("Authentication required") {
+            console.authenticate(&banner)?;
+        }
+        Ok(console)
+    }
+
+    fn io_error(&self, action: &str, err: std::io::Error) -> LbError {
+        let code = if err.kind() == std::io::ErrorKind::WouldBlock || err.kind() == std::io::ErrorKind::TimedOut {
+            ErrorCode::Timeout
+        } else {
+            ErrorCode::DeviceOffline
+        };
+        LbError::new(code, format!("Emulator console {} failed on {}: {}", action, self.serial, err))
+            .with_serial(&self.serial)
+    }
+
+    fn read_reply(&mut self, action: &str) -> Result<String, LbError> {
+        let mut output = Vec::new();
+        loop {
+            let mut line = String::new();
+            let read = self.reader.read_line(&mut line).map_err(|err| self.io_error(action, err))?;
+            if read == 0 {
+                return Err(LbError::new(ErrorCode::DeviceOffline, format!("Emulator console closed during {}", action))
+                    .with_serial(&self.serial));
+            }
+            let line = line.trim_end_matches(['\r', '\n']);
+            // `kill` answers `OK: killing emulator, bye bye` before closing.
+            if let Some(message) = line.strip_prefix("OK").filter(|rest| rest.is_empty() || rest.starts_with(':')) {
+                let message = message.trim_start_matches(':').trim();
+                if !message.is_empty() {
+                    output.push(message.to_string());
+                }
+                return Ok(output.join("\n"));
+            }
+            if let Some(reason) = line.strip_prefix("KO:") {
+                return Err(LbError::new(ErrorCode::CommandFailed, format!("{}: {}", action, reason.trim()))
+                    .with_serial(&self.serial));
+            }
+            output.push(line.to_string());
+        }
+    }
+
+    /// The banner names the token file (`'/home/me/.emulator_console_auth_token'`); older
+    /// emulators only mention it, so fall back to the home directory.
+    fn authenticate(&mut self, banner: &str) -> Result<(), LbError> {
+        let path = banner
+            .lines()
+            .map(|line| line.trim().trim_matches('\''))
+            .find(|line| line.ends_with(TOKEN_FILE))
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME")
+                    .or_else(|| std::env::var_os("USERPROFILE"))
+                    .map(|home| PathBuf::from(home).join(TOKEN_FILE))
+            })
+            .ok_or_else(|| LbError::not_found("No home directory to find the emulator console auth token in"))?;
+        let token = fs::read_to_string(&path).map_err(|err| {
+            LbError::new(
+                ErrorCode::PermissionDenied,
+                format!("Emulator console needs the auth token from {}: {}", path.display(), err),
+            )
+            .with_serial(&self.serial)
+        })?;
+        self.command(&format!("auth {}", token.trim())).map_err(|err| {
+            let message = format!("Emulator console rejected the token in {}: {}", path.display(), err.message);
+            LbError::new(ErrorCode::PermissionDenied, message).with_serial(&self.serial)
+        })?;
+        Ok(())
+    }
+
+    fn command(&mut self, command: &str) -> Result<String, LbError> {
+        if command.contains(['\r', '\n']) {
+            return Err("Emulator console commands must be a single line".into());
+        }
+        let verb = command.split_whitespace().next().unwrap_or_default().to_string();
+        let line = format!("{}\n", command);
+        self.stream
+            .write_all(line.as_bytes())
+            .map_err(|err| self.io_error(&verb, err))?;
+        self.read_reply(&verb)
+    }
+}
+
+/// One command on a fresh console session, for `avd.rs` (`avd name`, `kill`).
+pub fn console_command(serial: &str, command: &str) -> Result<String, LbError> {
+    Console::connect(serial)?.command(command)
+}
+
+/// Runs `commands` in one console session, stopping at the first failure.
+fn run_commands(serial: &str, commands: &[String]) -> Result<(), LbError> {
+    let mut console = Console::connect(serial)?;
+    for command in commands {
+        console.command(command)?;
+    }
+    lb_log!(Level::Info, "emulator", "{} on {}", commands.join("; "), serial);
+    Ok(())
+}
+
+fn console_json(serial: &str, command: &str) -> Result<String, LbError> {
+    if command.trim().is_empty() {
+        return Err("Emulator console command is empty".into());
+    }
+    let mut console = Console::connect(serial)?;
+    let output = console.command(command.trim())?;
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("port", u32::from(console.port).into()),
+        ("command", command.trim().into()),
+        ("output", output.into()),
+    ])
+    .to_string())
+}
+
+/// Digits with an optional leading `+`, as the modem accepts them.
+fn validate_phone(phone: &str) -> Result<(), LbError> {
+    let digits = phone.strip_prefix('+').unwrap_or(phone);
+    if digits.is_empty() || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        return Err(format!("Invalid phone number: {:?}", phone).into());
+    }
+    Ok(())
+}
+
+/// A named profile, or `<n>` / `<min>:<max>` numbers (kbps for speeds, ms for delays).
+fn validate_shaping(value: &str, names: &[&str], what: &str) -> Result<(), LbError> {
+    let numeric = value
+        .split(':')
+        .all(|part| !part.is_empty() && part.bytes().all(|byte| byte.is_ascii_digit()))
+        && value.matches(':').count() <= 1;
+    if names.contains(&value) || numeric {
+        Ok(())
+    } else {
+        Err(format!("Network {} must be one of {} or <n>[:<m>], got {:?}", what, names.join(", "), value).into())
+    }
+}
+
+fn geo_fix(serial: &str, latitude: f64, longitude: f64, altitude: f64) -> Result<(), LbError> {
+    if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) || !altitude.is_finite() {
+        return Err(format!("Invalid location: {}, {} at {}m", latitude, longitude, altitude).into());
+    }
+    // The console takes longitude first.
+    run_commands(serial, &[format!("geo fix {} {} {}", longitude, latitude, altitude)])
+}
+
+fn send_sms(serial: &str, phone: &str, text: &str) -> Result<(), LbError> {
+    validate_phone(phone)?;
+    if text.is_empty() {
+        return Err("SMS text is empty".into());
+    }
+    // The console reads one line per command; newlines in the body would end it early.
+    let text = text.replace(['\r', '\n'], " ");
+    run_commands(serial, &[format!("sms send {} {}", phone, text)])
+}
+
+fn simulate_call(serial: &str, phone: &str, action: &str) -> Result<(), LbError> {
+    validate_phone(phone)?;
+    let verb = CALL_ACTIONS
+        .iter()
+        .find(|(name, _)| *name == action)
+        .map(|(_, verb)| *verb)
+        .ok_or_else(|| {
+            let names: Vec<&str> = CALL_ACTIONS.iter().map(|(name, _)| *name).collect();
+            format!("Call action must be one of {}", names.join(", "))
+        })?;
+    run_commands(serial, &[format!("gsm {} {}", verb, phone)])
+}
+
+fn set_battery(serial: &str, level: i32, charging: i32) -> Result<(), LbError> {
+    if !(0..=100).contains(&level) {
+        return Err("Battery level must be between 0 and 100".into());
+    }
+    let mut commands = vec![format!("power capacity {}", level)];
+    if charging >= 0 {
+        let (ac, status) = if charging > 0 { ("on", "charging") } else { ("off", "discharging") };
+        commands.push(format!("power ac {}", ac));
+        commands.push(format!("power status {}", status));
+    }
+    run_commands(serial, &commands)
+}
+
+fn shape_network(serial: &str, speed: Option<&str>, delay: Option<&str>) -> Result<(), LbError> {
+    let mut commands = Vec::new();
+    if let Some(speed) = speed {
+        validate_shaping(speed, &NETWORK_SPEEDS, "speed")?;
+        commands.push(format!("network speed {}", speed));
+    }
+    if let Some(delay) = delay {
+        validate_shaping(delay, &NETWORK_DELAYS, "delay")?;
+        commands.push(format!("network delay {}", delay));
+    }
+    if commands.is_empty() {
+        return Err("Give a network speed, a delay, or both".into());
+    }
+    run_commands(serial, &commands)
+}
+
+/// Sends one raw command to the console of emulator `serial` (`emulator-<port>`), handling
+/// the auth token from `~/.emulator_console_auth_token`, and returns `{serial, port, command,
+/// output}`. A `KO:` reply is CommandFailed, a missing or rejected token PermissionDenied,
+/// and a serial that is not an emulator InvalidArgument.
+#[no_mangle]
+pub extern "C" fn lb_emulator_console(serial_ptr: *const c_char, command_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(serial_ptr, "serial")
+            .and_then(|serial| read_c_str(command_ptr, "command").and_then(|command| console_json(serial, command)));
+        string_result(result, "emulator console output")
+    })
+}
+
+/// Moves the emulated GPS to `latitude`/`longitude` (degrees) at `altitude` metres.
+/// Returns 1 on success.
+#[no_mangle]
+pub extern "C" fn lb_emulator_geo_fix(serial_ptr: *const c_char, latitude: f64, longitude: f64, altitude: f64) -> i32 {
+    ffi_guard(0, || {
+        let result = read_c_str(serial_ptr, "serial").and_then(|serial| geo_fix(serial, latitude, longitude, altitude));
+        status_result(result)
+    })
+}
+
+/// Delivers an incoming SMS from `phone` (digits, optional leading `+`). Returns 1 on success.
+#[no_mangle]
+pub extern "C" fn lb_emulator_sms(serial_ptr: *const c_char, phone_ptr: *const c_char, text_ptr: *const c_char) -> i32 {
+    ffi_guard(0, || {
+        let result = read_c_str(serial_ptr, "serial").and_then(|serial| {
+            let phone = read_c_str(phone_ptr, "phone number")?;
+            let text = read_c_str(text_ptr, "SMS text")?;
+            send_sms(serial, phone, text)
+        });
+        status_result(result)
+    })
+}
+
+/// Drives a simulated voice call with `phone`: `incoming` rings the device, then `accept`,
+/// `busy`, `hold` or `hangup`. Returns 1 on success.
+#[no_mangle]
+pub extern "C" fn lb_emulator_call(
+    serial_ptr: *const c_char,
+    phone_ptr: *const c_char,
+    action_ptr: *const c_char,
+) -> i32 {
+    ffi_guard(0, || {
+        let result = read_c_str(serial_ptr, "serial").and_then(|serial| {
+            let phone = read_c_str(phone_ptr, "phone number")?;
+            let action = read_c_str(action_ptr, "call action")?;
+            simulate_call(serial, phone, action)
+        });
+        status_result(result)
+    })
+}
+
+/// Sets the emulated battery to `level` percent; `charging` 1/0 also plugs or unplugs AC
+/// (negative leaves it alone). Returns 1 on success.
+#[no_mangle]
+pub extern "C" fn lb_emulator_battery(serial_ptr: *const c_char, level: i32, charging: i32) -> i32 {
+    ffi_guard(0, || {
+        status_result(read_c_str(serial_ptr, "serial").and_then(|serial| set_battery(serial, level, charging)))
+    })
+}
+
+/// Shapes the emulated network: `speed` is `gsm`, `hscsd`, `gprs`, `edge`, `umts`, `hsdpa`,
+/// `lte`, `evdo`, `full` or `<up>:<down>` kbps; `delay` is `gprs`, `edge`, `umts`, `none` or
+/// `<min>:<max>` ms. Either may be null to leave it unchanged. Returns 1 on success.
+#[no_mangle]
+pub extern "C" fn lb_emulator_network(
+    serial_ptr: *const c_char,
+    speed_ptr: *const c_char,
+    delay_ptr: *const c_char,
+) -> i32 {
+    ffi_guard(0, || {
+        let optional = |ptr: *const c_char, name| {
+            if ptr.is_null() {
+                Ok(None)
+            } else {
+                read_c_str(ptr, name).map(Some)
+            }
+        };
+        let result = read_c_str(serial_ptr, "serial").and_then(|serial| {
+            let speed = optional(speed_ptr, "network speed")?;
+            let delay = optional(delay_ptr, "network delay")?;
+            shape_network(serial, speed, delay)
+        });
+        status_result(result)
+    })
+}
@@ -0,0 +1,145 @@
+use std::fmt;
+
+use crate::json::JsonValue;
+
+/// Stable numeric error codes exposed through `lb_last_error_code`. Values never change
+/// meaning; new kinds are appended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+pub enum ErrorCode {
+    Ok = 0,
+    NullPointer = 1,
+    Utf8 = 2,
+    InvalidArgument = 3,
+    ParseError = 4,
+    SpawnFailed = 5,
+    CommandFailed = 6,
+    Timeout = 7,
+    DeviceOffline = 8,
+    DeviceUnauthorized = 9,
+    DeviceLocked = 10,
+    PermissionDenied = 11,
+    NotFound = 12,
+    Io = 13,
+    Internal = 14,
+    ChecksumMismatch = 15,
+    Cancelled = 16,
+}
+
+impl ErrorCode {
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorCode::Ok => "Ok",
+            ErrorCode::NullPointer => "NullPointer",
+            ErrorCode::Utf8 => "Utf8",
+            ErrorCode::InvalidArgument => "InvalidArgument",
+            ErrorCode::ParseError => "ParseError",
+            ErrorCode::SpawnFailed => "SpawnFailed",
+            ErrorCode::CommandFailed => "CommandFailed",
+            ErrorCode::Timeout => "Timeout",
+            ErrorCode::DeviceOffline => "DeviceOffline",
+            ErrorCode::DeviceUnauthorized => "DeviceUnauthorized",
+            ErrorCode::DeviceLocked => "DeviceLocked",
+            ErrorCode::PermissionDenied => "PermissionDenied",
+            ErrorCode::NotFound => "NotFound",
+            ErrorCode::Io => "Io",
+            ErrorCode::Internal => "Internal",
+            ErrorCode::ChecksumMismatch => "ChecksumMismatch",
+            ErrorCode::Cancelled => "Cancelled",
+        }
+    }
+}
+
+/// Error carried through the crate and stored as the last error. Plain strings convert
+/// to `InvalidArgument`, which is what bare messages in option/argument validation mean.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LbError {
+    pub code: ErrorCode,
+    pub message: String,
+    pub command: Option<String>,
+    pub serial: Option<String>,
+    /// Failure bundle paths (screenshot, UI dump, logcat) when failure capture is enabled.
+    pub artifacts: Vec<String>,
+}
+
+impl LbError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        LbError {
+            code,
+            message: message.into(),
+            command: None,
+            serial: None,
+            artifacts: Vec::new(),
+        }
+    }
+
+    pub fn ok() -> Self {
+        LbError::new(ErrorCode::Ok, "")
+    }
+
+    pub fn parse(message: impl Into<String>) -> Self {
+        LbError::new(ErrorCode::ParseError, message)
+    }
+
+    pub fn io(message: impl Into<String>) -> Self {
+        LbError::new(ErrorCode::Io, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        LbError::new(ErrorCode::NotFound, message)
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        LbError::new(ErrorCode::Internal, message)
+    }
+
+    pub fn with_command(mut self, argv: &[String]) -> Self {
+        self.command = Some(argv.join(" "));
+        self
+    }
+
+    pub fn with_serial(mut self, serial: &str) -> Self {
+        self.serial = Some(serial.to_string());
+        self
+    }
+
+    pub fn with_artifacts(mut self, artifacts: Vec<String>) -> Self {
+        self.artifacts = artifacts;
+        self
+    }
+
+    /// Keeps code and context while rewording, e.g. to say which input failed to parse.
+    pub fn context(mut self, prefix: &str) -> Self {
+        self.message = format!("{}: {}", prefix, self.message);
+        self
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        JsonValue::object(vec![
+            ("code", (self.code as i32).into()),
+            ("name", self.code.name().into()),
+            ("message", self.message.as_str().into()),
+            ("command", self.command.clone().into()),
+            ("serial", self.serial.clone().into()),
+            ("artifacts", self.artifacts.iter().map(JsonValue::from).collect::<Vec<_>>().into()),
+        ])
+    }
+}
+
+impl fmt::Display for LbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl From<String> for LbError {
+    fn from(message: String) -> Self {
+        LbError::new(ErrorCode::InvalidArgument, message)
+    }
+}
+
+impl From<&str> for LbError {
+    fn from(message: &str) -> Self {
+        LbError::new(ErrorCode::InvalidArgument, message)
+    }
+}
@@ -0,0 +1,537 @@
+#[cfg(not(feature = "async-exec"))]
+use std::io::{Read, Write};
+#[cfg(not(feature = "async-exec"))]
+use std::process::Command;
+use std::process::{Child, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+#[cfg(not(feature = "async-exec"))]
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::cancel;
+use crate::error::{ErrorCode, LbError};
+use crate::history;
+use crate::json::JsonValue;
+use crate::logging::{lb_log, Level};
+use crate::retry::{self, RetrySpec};
+use crate::serial_lock;
+use crate::ffi_guard;
+use crate::transcript;
+
+/// Default cap on stdout + stderr captured per command; see `lb_set_output_limit`.
+const DEFAULT_OUTPUT_LIMIT: u64 = 64 * 1024 * 1024;
+const READ_CHUNK_BYTES: usize = 64 * 1024;
+
+/// Bytes; 0 means unlimited.
+static OUTPUT_LIMIT: AtomicU64 = AtomicU64::new(DEFAULT_OUTPUT_LIMIT);
+
+pub struct CommandOutput {
+    pub stdout: String,
+    pub stderr: String,
+    pub exit_code: Option<i32>,
+    pub pid: Option<u32>,
+    pub duration: Duration,
+    /// Output hit the cap: reading stopped, the child was killed, and `captured_bytes`
+    /// (stdout + stderr) is all that was kept.
+    pub truncated: bool,
+    pub captured_bytes: u64,
+    /// Runs made, including retries (see `retry::run_with_retry`).
+    pub attempts: u32,
+}
+
+/// One command for the structured runner.
+pub struct CommandSpec {
+    /// Parsed argv, or why it could not be parsed (reported in the command's `error`).
+    pub argv: Result<Vec<String>, LbError>,
+    pub stdin: Option<String>,
+    pub max_output_bytes: u64,
+    pub retry: Option<RetrySpec>,
+}
+
+impl CommandOutput {
+    pub fn success(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+}
+
+pub fn output_limit() -> u64 {
+    OUTPUT_LIMIT.load(Ordering::Relaxed)
+}
+
+/// Reads `pipe` into memory while the shared `captured` total stays within `limit`; the
+/// reader that crosses it keeps only the bytes that fit, flags `truncated`, and kills the child.
+#[cfg(not(feature = "async-exec"))]
+fn capture_pipe(
+    mut pipe: impl Read + Send + 'static,
+    limit: u64,
+    captured: Arc<AtomicU64>,
+    truncated: Arc<AtomicBool>,
+    child: Arc<Mutex<Child>>,
+) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut data = Vec::new();
+        let mut chunk = vec![0u8; READ_CHUNK_BYTES];
+        loop {
+            let read = match pipe.read(&mut chunk) {
+                Ok(0) | Err(_) => break,
+                Ok(read) => read,
+            };
+            if truncated.load(Ordering::Relaxed) {
+                break;
+            }
+            let before = captured.fetch_add(read as u64, Ordering::Relaxed);
+            if limit > 0 && before + read as u64 > limit {
+                let keep = limit.saturating_sub(before) as usize;
+                data.extend_from_slice(&chunk[..keep]);
+                captured.fetch_sub((read - keep) as u64, Ordering::Relaxed);
+                truncated.store(true, Ordering::Relaxed);
+                if let Ok(mut child) = child.lock() {
+                    let _ = child.kill();
+                }
+                break;
+            }
+            data.extend_from_slice(&chunk[..read]);
+        }
+        data
+    })
+}
+
+/// What a child left behind, however it was driven.
+struct Finished {
+    pid: u32,
+    stdout: String,
+    stderr: String,
+    status: std::io::Result<ExitStatus>,
+    /// The cancel token killed it.
+    killed: bool,
+    truncated: bool,
+    captured: u64,
+}
+
+/// Spawns `argv` and drives it to exit on threads: one per pipe, plus one for stdin. `Err`
+/// only when it could not be spawned.
+#[cfg(not(feature = "async-exec"))]
+fn drive_child(
+    argv: &[String],
+    input: Option<&[u8]>,
+    limit: u64,
+    token: &cancel::CancelToken,
+) -> std::io::Result<Finished> {
+    let mut child = Command::new(&argv[0])
+        .args(&argv[1..])
+        .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let pid = child.id();
+    // Written from another thread so a child that fills stdout before draining stdin
+    // cannot deadlock against us.
+    if let (Some(mut stdin), Some(input)) = (child.stdin.take(), input) {
+        let input = input.to_vec();
+        thread::spawn(move || {
+            let _ = stdin.write_all(&input);
+        });
+    }
+    let stdout = child.stdout.take();
+    let stderr = child.stderr.take();
+    let child = Arc::new(Mutex::new(child));
+    let captured = Arc::new(AtomicU64::new(0));
+    let truncated = Arc::new(AtomicBool::new(false));
+    // The pipes close once the child is killed, so the readers below finish promptly. While
+    // `wait` holds the lock the child has already closed its output and is exiting anyway.
+    let killed = Arc::new(AtomicBool::new(false));
+    let on_cancel = {
+        let child = child.clone();
+        let killed = killed.clone();
+        token.on_cancel(move || {
+            if let Ok(mut child) = child.try_lock() {
+                killed.store(child.kill().is_ok(), Ordering::Relaxed);
+            }
+        })
+    };
+    let stdout_reader = stdout.map(|pipe| capture_pipe(pipe, limit, captured.clone(), truncated.clone(), child.clone()));
+    let stderr_reader = stderr.map(|pipe| capture_pipe(pipe, limit, captured.clone(), truncated.clone(), child.clone()));
+    let collect = |reader: Option<thread::JoinHandle<Vec<u8>>>| {
+        reader
+            .and_then(|reader| reader.join().ok())
+            .map(|data| String::from_utf8_lossy(&data).into_owned())
+            .unwrap_or_default()
+    };
+    let stdout = collect(stdout_reader);
+    let stderr = collect(stderr_reader);
+    let status = match child.lock() {
+        Ok(mut child) => child.wait(),
+        Err(_) => Err(std::io::Error::other("Child process lock poisoned")),
+    };
+    drop(on_cancel);
+    Ok(Finished {
+        pid,
+        stdout,
+        stderr,
+        status,
+        killed: killed.load(Ordering::Relaxed),
+        truncated: truncated.load(Ordering::Relaxed),
+        captured: captured.load(Ordering::Relaxed),
+    })
+}
+
+/// `tokio::process` on the shared runtime (see `runtime.rs`): the pipes, stdin, the exit and
+/// the cancel token are awaited together, and a reader past `limit` signals the kill over a
+/// channel instead of reaching into the child.
+#[cfg(feature = "async-exec")]
+fn drive_child(
+    argv: &[String],
+    input: Option<&[u8]>,
+    limit: u64,
+    token: &cancel::CancelToken,
+) -> std::io::Result<Finished> {
+    let mut command = tokio::process::Command::new(&argv[0]);
+    command
+        .args(&argv[1..])
+        .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    let input = input.map(<[u8]>::to_vec);
+    let token = token.clone();
+    crate::runtime::block_on(async move {
+        use tokio::io::AsyncWriteExt;
+
+        // Spawned inside the runtime so the child is registered with its reaper.
+        let mut child = command.spawn()?;
+        let pid = child.id().unwrap_or_default();
+        if let (Some(mut stdin), Some(input)) = (child.stdin.take(), input) {
+            tokio::spawn(async move {
+                let _ = stdin.write_all(&input).await;
+            });
+        }
+        let captured = Arc::new(AtomicU64::new(0));
+        let truncated = Arc::new(AtomicBool::new(false));
+        let (overflow, mut overflowed) = tokio::sync::mpsc::channel::<()>(2);
+        let readers = [
+            child.stdout.take().map(|pipe| capture_pipe_async(pipe, limit, captured.clone(), truncated.clone(), overflow.clone())),
+            child.stderr.take().map(|pipe| capture_pipe_async(pipe, limit, captured.clone(), truncated.clone(), overflow.clone())),
+        ];
+        drop(overflow);
+        let mut killed = false;
+        let status = tokio::select! {
+            status = child.wait() => status,
+            _ = token.cancelled() => {
+                killed = child.start_kill().is_ok();
+                child.wait().await
+            }
+            Some(()) = overflowed.recv() => {
+                let _ = child.start_kill();
+                child.wait().await
+            }
+        };
+        let mut output = Vec::with_capacity(2);
+        for reader in readers {
+            let data = match reader {
+                Some(reader) => reader.await.unwrap_or_default(),
+                None => Vec::new(),
+            };
+            output.push(String::from_utf8_lossy(&data).into_owned());
+        }
+        let stderr = output.pop().unwrap_or_default();
+        let stdout = output.pop().unwrap_or_default();
+        Ok(Finished {
+            pid,
+            stdout,
+            stderr,
+            status,
+            killed,
+            truncated: truncated.load(Ordering::Relaxed),
+            captured: captured.load(Ordering::Relaxed),
+        })
+    })
+}
+
+/// `capture_pipe` as a task: the reader that crosses `limit` sends on `overflow` for the
+/// driver to kill the child.
+#[cfg(feature = "async-exec")]
+fn capture_pipe_async(
+    mut pipe: impl tokio::io::AsyncRead + Unpin + Send + 'static,
+    limit: u64,
+    captured: Arc<AtomicU64>,
+    truncated: Arc<AtomicBool>,
+    overflow: tokio::sync::mpsc::Sender<()>,
+) -> tokio::task::JoinHandle<Vec<u8>> {
+    use tokio::io::AsyncReadExt;
+
+    tokio::spawn(async move {
+        let mut data = Vec::new();
+        let mut chunk = vec![0u8; READ_CHUNK_BYTES];
+        loop {
+            let read = match pipe.read(&mut chunk).await {
+                Ok(0) | Err(_) => break,
+                Ok(read) => read,
+            };
+            if truncated.load(Ordering::Relaxed) {
+                break;
+            }
+            let before = captured.fetch_add(read as u64, Ordering::Relaxed);
+            if limit > 0 && before + read as u64 > limit {
+                let keep = limit.saturating_sub(before) as usize;
+                data.extend_from_slice(&chunk[..keep]);
+                captured.fetch_sub((read - keep) as u64, Ordering::Relaxed);
+                truncated.store(true, Ordering::Relaxed);
+                let _ = overflow.send(()).await;
+                break;
+            }
+            data.extend_from_slice(&chunk[..read]);
+        }
+        data
+    })
+}
+
+/// Runs a pre-tokenized command to completion and records it in active transcripts.
+pub fn run_argv(argv: &[String]) -> Result<CommandOutput, LbError> {
+    run_argv_with_input(argv, None, output_limit())
+}
+
+/// `run_argv` with `input` written to the child's stdin and then closed (stdin is
+/// `/dev/null` without input), capturing at most `limit` bytes of output (0 = unlimited).
+pub fn run_argv_with_input(argv: &[String], input: Option<&[u8]>, limit: u64) -> Result<CommandOutput, LbError> {
+    if argv.is_empty() {
+        return Err("Empty command".into());
+    }
+    // Redacted once for the transcript, history, the log lines and errors below.
+    let (shown, hide_output) = history::redacted(argv);
+    let command = shown.join(" ");
+    let token = cancel::current();
+    token.check().map_err(|err| err.with_command(&shown))?;
+    // Held until the child exits, so two commands never drive the same device at once.
+    let _device = serial_lock::acquire_for(argv);
+    let started = Instant::now();
+    let finished = match drive_child(argv, input, limit, &token) {
+        Ok(finished) => finished,
+        Err(err) => {
+            transcript::record_redacted(&shown, started.elapsed(), None, None);
+            lb_log!(Level::Warn, "exec", "{} -> failed to spawn: {}", command, err);
+            return Err(LbError::new(ErrorCode::SpawnFailed, err.to_string()).with_command(&shown));
+        }
+    };
+    let Finished {
+        pid,
+        stdout,
+        stderr,
+        status,
+        killed,
+        truncated,
+        captured,
+    } = finished;
+    let duration = started.elapsed();
+    let exit_code = status.as_ref().ok().and_then(|status| status.code());
+    let output = (!hide_output).then_some((stdout.as_str(), stderr.as_str()));
+    if killed {
+        transcript::record_redacted(&shown, duration, exit_code, output);
+        lb_log!(Level::Info, "exec", "{} -> killed after {}ms: cancelled", command, duration.as_millis());
+        return Err(token
+            .check()
+            .err()
+            .unwrap_or_else(|| LbError::new(ErrorCode::Cancelled, "Operation cancelled"))
+            .with_command(&shown));
+    }
+    transcript::record_redacted(&shown, duration, exit_code, output);
+    match exit_code {
+        Some(code) => lb_log!(Level::Debug, "exec", "{} -> exit {} in {}ms", command, code, duration.as_millis()),
+        None => lb_log!(Level::Warn, "exec", "{} -> no exit code after {}ms", command, duration.as_millis()),
+    }
+    if truncated {
+        lb_log!(Level::Warn, "exec", "{} -> output cut at {} bytes and process killed", command, limit);
+    }
+    status.map_err(|err| LbError::new(ErrorCode::SpawnFailed, err.to_string()).with_command(&shown))?;
+    Ok(CommandOutput {
+        stdout,
+        stderr,
+        exit_code,
+        pid: Some(pid),
+        duration,
+        truncated,
+        captured_bytes: captured,
+        attempts: 1,
+    })
+}
+
+/// A child its caller drives itself (progress parsing, streamed input) that the current
+/// cancel token kills, as `run_argv_with_input` does for its own children. Take the pipes
+/// before wrapping: `wait` holds the child's lock, and the kill hook only `try_lock`s it.
+pub struct CancellableChild {
+    child: Arc<Mutex<Child>>,
+    killed: Arc<AtomicBool>,
+    token: cancel::CancelToken,
+    on_cancel: Option<cancel::OnCancel>,
+}
+
+impl CancellableChild {
+    pub fn new(child: Child) -> CancellableChild {
+        let token = cancel::current();
+        let child = Arc::new(Mutex::new(child));
+        let killed = Arc::new(AtomicBool::new(false));
+        let on_cancel = {
+            let child = child.clone();
+            let killed = killed.clone();
+            token.on_cancel(move || {
+                if let Ok(mut child) = child.try_lock() {
+                    killed.store(child.kill().is_ok(), Ordering::Relaxed);
+                }
+            })
+        };
+        CancellableChild {
+            child,
+            killed,
+            token,
+            on_cancel: Some(on_cancel),
+        }
+    }
+
+    pub fn wait(&mut self) -> std::io::Result<std::process::ExitStatus> {
+        let mut child = self
+            .child
+            .lock()
+            .map_err(|_| std::io::Error::other("Child process lock poisoned"))?;
+        let status = child.wait();
+        self.on_cancel = None;
+        status
+    }
+
+    /// The token's error (Cancelled, or Timeout for a deadline) if it killed the child.
+    pub fn check(&self) -> Result<(), LbError> {
+        if !self.killed.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        Err(self
+            .token
+            .check()
+            .err()
+            .unwrap_or_else(|| LbError::new(ErrorCode::Cancelled, "Operation cancelled")))
+    }
+}
+
+pub fn shlex_split(command: &str) -> Result<Vec<String>, LbError> {
+    let mut parts: Vec<String> = Vec::new();
+    let mut current = String::new();
+    let mut in_single = false;
+    let mut in_double = false;
+    let mut escaped = false;
+
+    for ch in command.chars() {
+        if escaped {
+            current.push(ch);
+            escaped = false;
+            continue;
+        }
+        match ch {
+            '\\' if !in_single => {
+                escaped = true;
+            }
+            '\'' if !in_double => {
+                in_single = !in_single;
+            }
+            '"' if !in_single => {
+                in_double = !in_double;
+            }
+            ch if ch.is_whitespace() && !in_single && !in_double => {
+                if !current.is_empty() {
+                    parts.push(current.clone());
+                    current.clear();
+                }
+            }
+            _ => current.push(ch),
+        }
+    }
+
+    if escaped {
+        return Err("Trailing escape character".into());
+    }
+    if in_single || in_double {
+        return Err("Unterminated quoted string".into());
+    }
+    if !current.is_empty() {
+        parts.push(current);
+    }
+    Ok(parts)
+}
+
+/// Runs one command and renders it as result lines: stdout lines, then `ERROR(exit=N): stderr`
+/// on failure or `STDERR: ...` when a successful command wrote to stderr.
+pub fn execute_argv(argv: &[String]) -> Vec<String> {
+    if argv.is_empty() {
+        return vec![String::new()];
+    }
+    match retry::run_with_retry(retry::default_policy().as_ref(), || run_argv(argv)) {
+        Ok(output) => {
+            let mut lines: Vec<String> = output.stdout.lines().map(|line| line.to_string()).collect();
+            if output.truncated {
+                lines.push(format!("TRUNCATED: output exceeded {} bytes; process killed", output.captured_bytes));
+            } else if !output.success() {
+                lines.push(format!("ERROR(exit={}): {}", output.exit_code.unwrap_or(-1), output.stderr.trim()));
+            } else if !output.stderr.trim().is_empty() {
+                lines.push(format!("STDERR: {}", output.stderr.trim()));
+            }
+            if lines.is_empty() {
+                lines.push(String::new());
+            }
+            lines
+        }
+        Err(err) => vec![format!("ERROR(exec): {}", err)],
+    }
+}
+
+pub fn execute_command(command: &str) -> Vec<String> {
+    match shlex_split(command) {
+        Ok(parts) => execute_argv(&parts),
+        Err(err) => vec![format!("ERROR(parse): {}", err)],
+    }
+}
+
+/// One command as a JSON object: `{argv, exit_code, duration_ms, pid, stdout, stderr,
+/// truncated, captured_bytes, attempts, error}`.
+/// `error` is set (and the process fields null) when the command could not be parsed or spawned.
+pub fn execute_structured(spec: CommandSpec) -> JsonValue {
+    let (argv_json, output) = match spec.argv {
+        Ok(argv) => (
+            JsonValue::from(argv.iter().map(JsonValue::from).collect::<Vec<_>>()),
+            retry::run_with_retry(spec.retry.as_ref(), || {
+                run_argv_with_input(&argv, spec.stdin.as_deref().map(str::as_bytes), spec.max_output_bytes)
+            }),
+        ),
+        Err(err) => (JsonValue::Null, Err(err)),
+    };
+    match output {
+        Ok(output) => JsonValue::object(vec![
+            ("argv", argv_json),
+            ("exit_code", output.exit_code.into()),
+            ("duration_ms", (output.duration.as_millis() as u64).into()),
+            ("pid", output.pid.into()),
+            ("stdout", output.stdout.into()),
+            ("stderr", output.stderr.into()),
+            ("truncated", output.truncated.into()),
+            ("captured_bytes", output.captured_bytes.into()),
+            ("attempts", output.attempts.into()),
+            ("error", JsonValue::Null),
+        ]),
+        Err(err) => JsonValue::object(vec![
+            ("argv", argv_json),
+            ("exit_code", JsonValue::Null),
+            ("duration_ms", JsonValue::Null),
+            ("pid", JsonValue::Null),
+            ("stdout", JsonValue::Null),
+            ("stderr", JsonValue::Null),
+            ("truncated", false.into()),
+            ("captured_bytes", JsonValue::Null),
+            ("attempts", JsonValue::Null),
+            ("error", err.message.into()),
+        ]),
+    }
+}
+
+/// Caps stdout + stderr captured per command by the parallel and structured runners
+/// (default 64 MiB; 0 disables the cap). A command that exceeds it is killed and its
+/// result marked truncated.
+#[no_mangle]
+pub extern "C" fn lb_set_output_limit(max_bytes: u64) {
+    ffi_guard((), || OUTPUT_LIMIT.store(max_bytes, Ordering::Relaxed))
+}
@@ -0,0 +1,173 @@
+use std::fs;
+use std::os::raw::c_char;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+use crate::adb;
+use crate::error::LbError;
+use crate::json::{self, JsonValue};
+use crate::{ffi_guard, now_millis, read_c_str, status_result, string_result};
+
+const DEFAULT_LOGCAT_LINES: u64 = 200;
+/// Numbers the device-side temp files so concurrent captures on one device do not collide.
+static NEXT_CAPTURE: AtomicU64 = AtomicU64::new(1);
+
+struct CaptureConfig {
+    output_dir: PathBuf,
+    logcat_lines: u64,
+}
+
+static CAPTURE_CONFIG: OnceLock<Mutex<Option<CaptureConfig>>> = OnceLock::new();
+
+fn capture_config() -> &'static Mutex<Option<CaptureConfig>> {
+    CAPTURE_CONFIG.get_or_init(|| Mutex::new(None))
+}
+
+fn parse_config(options: &JsonValue) -> Result<Option<CaptureConfig>, LbError> {
+    if options.as_object().is_none() {
+        return Err("Failure capture options must be a JSON object".into());
+    }
+    if options.get("enabled").and_then(JsonValue::as_bool) == Some(false) {
+        return Ok(None);
+    }
+    let output_dir = options
+        .get("output_dir")
+        .and_then(JsonValue::as_str)
+        .filter(|dir| !dir.is_empty())
+        .ok_or("Failure capture option 'output_dir' is required")?;
+    let logcat_lines = match options.get("logcat_lines") {
+        None => DEFAULT_LOGCAT_LINES,
+        Some(lines) => lines
+            .as_u64()
+            .ok_or("Failure capture option 'logcat_lines' must be a non-negative integer")?,
+    };
+    Ok(Some(CaptureConfig {
+        output_dir: PathBuf::from(output_dir),
+        logcat_lines,
+    }))
+}
+
+fn set_failure_capture(options_json: &str) -> Result<(), LbError> {
+    let config = parse_config(&json::parse(options_json)?)?;
+    *capture_config()
+        .lock()
+        .map_err(|_| LbError::internal("Failure capture config poisoned"))? = config;
+    Ok(())
+}
+
+pub fn path_component(value: &str) -> String {
+    value
+        .chars()
+        .map(|ch| if ch.is_ascii_alphanumeric() || ch == '-' || ch == '.' { ch } else { '_' })
+        .collect()
+}
+
+/// Captures on-device into `remote`, pulls it to `local`, and removes the device copy.
+fn pull_capture(serial: &str, capture: &str, remote: &str, local: &Path) -> Result<(), LbError> {
+    adb::shell(serial, &format!("{} {}", capture, remote))?;
+    let local = local.to_string_lossy();
+    let pulled = adb::adb_checked(Some(serial), &["pull", remote, &local]);
+    let _ = adb::shell(serial, &format!("rm -f {}", remote));
+    pulled.map(|_| ())
+}
+
+/// Screenshot, UI dump, and logcat tail for `serial`, written under
+/// `<dir>/<serial>/<timestamp>-<label>/`. Each artifact is best effort; the paths of the
+/// ones that were written are returned.
+fn capture_bundle(serial: &str, label: &str, output_dir: &Path, logcat_lines: u64) -> Result<Vec<String>, LbError> {
+    let bundle_dir = output_dir
+        .join(path_component(serial))
+        .join(format!("{}-{}", now_millis(), path_component(label)));
+    fs::create_dir_all(&bundle_dir)
+        .map_err(|err| LbError::io(format!("Failed to create {}: {}", bundle_dir.display(), err)))?;
+
+    // The host pid keeps two processes driving the same device apart as well.
+    let remote_prefix = format!(
+        "/data/local/tmp/lb_failure_{}_{}",
+        std::process::id(),
+        NEXT_CAPTURE.fetch_add(1, Ordering::Relaxed)
+    );
+    let mut artifacts = Vec::new();
+    let screenshot = bundle_dir.join("screenshot.png");
+    if pull_capture(serial, "screencap -p", &format!("{}.png", remote_prefix), &screenshot).is_ok() {
+        artifacts.push(screenshot);
+    }
+    let ui_dump = bundle_dir.join("window_dump.xml");
+    if pull_capture(serial, "uiautomator dump", &format!("{}.xml", remote_prefix), &ui_dump).is_ok() {
+        artifacts.push(ui_dump);
+    }
+    let logcat = bundle_dir.join("logcat.txt");
+    if let Ok(output) = adb::adb_checked(Some(serial), &["logcat", "-d", "-t", &logcat_lines.to_string()]) {
+        if fs::write(&logcat, output).is_ok() {
+            artifacts.push(logcat);
+        }
+    }
+    Ok(artifacts
+        .into_iter()
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect())
+}
+
+/// Takes a bundle for `operation` when capture is enabled and returns its paths (empty when
+/// it is disabled or nothing could be written). For operations that report failure in
+/// their result (`success: false`) rather than as an error.
+pub fn capture(serial: &str, operation: &str) -> Vec<String> {
+    let target = match capture_config().lock() {
+        Ok(guard) => guard
+            .as_ref()
+            .map(|config| (config.output_dir.clone(), config.logcat_lines)),
+        Err(_) => None,
+    };
+    target
+        .and_then(|(output_dir, logcat_lines)| capture_bundle(serial, operation, &output_dir, logcat_lines).ok())
+        .unwrap_or_default()
+}
+
+/// Wraps the result of a managed device operation: when capture is enabled and the
+/// operation failed, a failure bundle is taken and its paths are attached to the error.
+pub fn on_failure<T>(serial: &str, operation: &str, result: Result<T, LbError>) -> Result<T, LbError> {
+    match result {
+        Ok(value) => Ok(value),
+        Err(err) => {
+            let artifacts = capture(serial, operation);
+            Err(if artifacts.is_empty() { err } else { err.with_artifacts(artifacts) })
+        }
+    }
+}
+
+fn capture_failure_bundle(serial: &str, label: &str) -> Result<String, LbError> {
+    let (output_dir, logcat_lines) = capture_config()
+        .lock()
+        .map_err(|_| LbError::internal("Failure capture config poisoned"))?
+        .as_ref()
+        .map(|config| (config.output_dir.clone(), config.logcat_lines))
+        .ok_or("Failure capture is not configured; call lb_set_failure_capture first")?;
+    let artifacts = capture_bundle(serial, label, &output_dir, logcat_lines)?;
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("artifacts", artifacts.iter().map(JsonValue::from).collect::<Vec<_>>().into()),
+    ])
+    .to_string())
+}
+
+/// Configures automatic failure bundles: `{"output_dir": "...", "logcat_lines": 200}`, or
+/// `{"enabled": false}` to turn them off. Managed operations that fail afterwards list
+/// the bundle paths under `artifacts` in `lb_last_error_json`.
+#[no_mangle]
+pub extern "C" fn lb_set_failure_capture(options_ptr: *const c_char) -> i32 {
+    ffi_guard(0, || {
+        status_result(read_c_str(options_ptr, "failure capture options").and_then(set_failure_capture))
+    })
+}
+
+/// Takes a failure bundle on demand (e.g. when a host-side assertion fails) and returns
+/// `{"serial", "artifacts": [paths]}`.
+#[no_mangle]
+pub extern "C" fn lb_capture_failure_bundle(serial_ptr: *const c_char, label_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(serial_ptr, "serial")
+            .and_then(|serial| read_c_str(label_ptr, "label").and_then(|label| capture_failure_bundle(serial, label)));
+        string_result(result, "failure bundle")
+    })
+}
@@ -0,0 +1,309 @@
+use std::ffi::c_void;
+use std::io::{BufRead, BufReader};
+use std::os::raw::c_char;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::Instant;
+
+use crate::device_lock::ensure_device_unlocked;
+use crate::error::{ErrorCode, LbError};
+use crate::exec::{self, CancellableChild, CommandOutput};
+use crate::json::JsonValue;
+use crate::logging::{lb_log, Level};
+use crate::serial_lock;
+use crate::stream::{LineCallback, LineSink};
+use crate::transcript;
+use crate::{ffi_guard, read_c_str, status_result, string_result};
+
+/// `fastboot reboot` targets; `system` is a plain `fastboot reboot`.
+const REBOOT_TARGETS: [&str; 4] = ["system", "bootloader", "recovery", "fastboot"];
+
+fn fastboot_argv(serial: Option<&str>, args: &[&str]) -> Vec<String> {
+    let mut argv = vec!["fastboot".to_string()];
+    if let Some(serial) = serial {
+        argv.push("-s".to_string());
+        argv.push(serial.to_string());
+    }
+    argv.extend(args.iter().map(|arg| arg.to_string()));
+    argv
+}
+
+fn run_fastboot(serial: Option<&str>, args: &[&str]) -> Result<CommandOutput, LbError> {
+    exec::run_argv(&fastboot_argv(serial, args)).map_err(|err| {
+        let err = err.context("Failed to run fastboot");
+        match serial {
+            Some(serial) => err.with_serial(serial),
+            None => err,
+        }
+    })
+}
+
+/// fastboot reports on stderr, including successful `getvar` values.
+fn fastboot_checked(serial: &str, args: &[&str]) -> Result<String, LbError> {
+    let output = run_fastboot(Some(serial), args)?;
+    let text = format!("{}{}", output.stdout, output.stderr);
+    if output.success() {
+        return Ok(text);
+    }
+    Err(LbError::new(ErrorCode::CommandFailed, format!("fastboot {} failed: {}", args.join(" "), failure_message(&text)))
+        .with_command(&fastboot_argv(Some(serial), args))
+        .with_serial(serial))
+}
+
+/// The text inside `FAILED (...)`, or the whole output when there is none.
+fn failure_message(output: &str) -> String {
+    output
+        .find("FAILED (")
+        .map(|start| {
+            let rest = &output[start + "FAILED (".len()..];
+            rest[..rest.rfind(')').unwrap_or(rest.len())].to_string()
+        })
+        .unwrap_or_else(|| output.trim().to_string())
+}
+
+fn validate_name(kind: &str, value: &str) -> Result<(), LbError> {
+    if !value.is_empty() && value.chars().all(|ch| ch.is_ascii_alphanumeric() || matches!(ch, '_' | '-' | '.')) {
+        Ok(())
+    } else {
+        Err(format!("Invalid fastboot {} '{}'", kind, value).into())
+    }
+}
+
+/// `SERIAL\tfastboot` lines, or `SERIAL  fastboot usb:1-1` with `-l`.
+fn devices() -> Result<Vec<(String, String, Option<String>)>, LbError> {
+    let output = run_fastboot(None, &["devices", "-l"])?;
+    Ok(output
+        .stdout
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let serial = fields.next()?.to_string();
+            let state = fields.next()?.to_string();
+            Some((serial, state, fields.next().map(str::to_string)))
+        })
+        .collect())
+}
+
+/// Whether `serial` is currently in bootloader or fastbootd mode.
+pub fn device_listed(serial: &str) -> Result<bool, LbError> {
+    Ok(devices()?.iter().any(|(listed, _, _)| listed == serial))
+}
+
+/// fastboot waits forever for a serial it cannot see, so check before every device command.
+fn require_listed(serial: &str) -> Result<(), LbError> {
+    if device_listed(serial)? {
+        Ok(())
+    } else {
+        Err(LbError::new(ErrorCode::DeviceOffline, format!("{} is not in bootloader or fastbootd mode", serial))
+            .with_serial(serial))
+    }
+}
+
+fn list_devices() -> Result<String, LbError> {
+    let devices: Vec<JsonValue> = devices()?
+        .into_iter()
+        .map(|(serial, state, transport)| {
+            JsonValue::object(vec![
+                ("serial", serial.into()),
+                ("state", state.into()),
+                ("transport", transport.into()),
+            ])
+        })
+        .collect();
+    Ok(JsonValue::from(devices).to_string())
+}
+
+/// `name: value` lines; `getvar all` prefixes each with `(bootloader) `.
+fn parse_getvar(output: &str) -> Vec<(String, JsonValue)> {
+    output
+        .lines()
+        .map(|line| line.trim().trim_start_matches("(bootloader)").trim())
+        .filter(|line| !line.starts_with("Finished") && !line.starts_with("Total time") && !line.starts_with("all:"))
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), JsonValue::from(value.trim())))
+        .collect()
+}
+
+fn getvar(serial: &str, name: &str) -> Result<String, LbError> {
+    validate_name("variable", name)?;
+    require_listed(serial)?;
+    let output = fastboot_checked(serial, &["getvar", name])?;
+    Ok(JsonValue::Object(parse_getvar(&output)).to_string())
+}
+
+/// Turns `Sending sparse 'system_a' 2/5 (524284 KB)   OKAY [ 12.1s]` style lines into
+/// progress events. Each part is sent and then written, so both count towards `percent`.
+struct FlashProgress {
+    sink: Option<LineSink>,
+    part: u64,
+    parts: u64,
+    percent: u64,
+}
+
+impl FlashProgress {
+    fn line(&mut self, line: &str) {
+        let mut words = line.split_whitespace();
+        let stage = match words.next() {
+            Some("Sending") => "sending",
+            Some("Writing") => "writing",
+            Some("Resizing") => "resizing",
+            Some("Erasing") => "erasing",
+            _ => return,
+        };
+        let rest: Vec<&str> = words.collect();
+        let position = rest
+            .iter()
+            .find_map(|word| word.split_once('/'))
+            .and_then(|(part, parts)| Some((part.parse::<u64>().ok()?, parts.parse::<u64>().ok()?)));
+        if let Some((part, parts)) = position {
+            self.part = part;
+            self.parts = parts.max(1);
+        }
+        let done = line.contains("OKAY");
+        if done && (stage == "sending" || stage == "writing") {
+            let steps = (self.part.max(1) - 1) * 2 + if stage == "sending" { 1 } else { 2 };
+            self.percent = (steps * 100 / (self.parts * 2)).min(100);
+        }
+        if let Some(sink) = &self.sink {
+            sink.emit(
+                &JsonValue::object(vec![
+                    ("stage", stage.into()),
+                    ("part", self.part.max(1).into()),
+                    ("parts", self.parts.into()),
+                    ("done", done.into()),
+                    ("percent", self.percent.into()),
+                ])
+                .to_string(),
+            );
+        }
+    }
+}
+
+fn flash(serial: &str, partition: &str, image: &str, sink: Option<LineSink>) -> Result<String, LbError> {
+    validate_name("partition", partition)?;
+    if !Path::new(image).is_file() {
+        return Err(LbError::not_found(format!("Image not found: {}", image)));
+    }
+    require_listed(serial)?;
+    ensure_device_unlocked(serial)?;
+    let argv = fastboot_argv(Some(serial), &["flash", partition, image]);
+    let _device = serial_lock::acquire_for(&argv);
+    let started = Instant::now();
+    let mut child = Command::new(&argv[0])
+        .args(&argv[1..])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| {
+            LbError::new(ErrorCode::SpawnFailed, format!("Failed to spawn {}: {}", argv[0], err))
+                .with_command(&argv)
+                .with_serial(serial)
+        })?;
+    let mut progress = FlashProgress {
+        sink,
+        part: 1,
+        parts: 1,
+        percent: 0,
+    };
+    let stderr = child.stderr.take();
+    let mut child = CancellableChild::new(child);
+    let mut output = String::new();
+    if let Some(stderr) = stderr {
+        for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+            progress.line(&line);
+            output.push_str(&line);
+            output.push('\n');
+        }
+    }
+    let status = child
+        .wait()
+        .map_err(|err| LbError::io(format!("Failed to wait for fastboot flash: {}", err)))?;
+    transcript::record_command(&argv, started.elapsed(), status.code());
+    child.check().map_err(|err| err.with_serial(serial).with_command(&argv))?;
+    let success = status.success() && !output.contains("FAILED");
+    lb_log!(Level::Info, "fastboot", "fastboot flash {} on {}: {}", partition, serial, if success { "ok" } else { "failed" });
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("partition", partition.into()),
+        ("image", image.into()),
+        ("success", success.into()),
+        ("failure_message", (!success).then(|| failure_message(&output)).into()),
+        ("duration_ms", (started.elapsed().as_millis() as u64).into()),
+    ])
+    .to_string())
+}
+
+fn reboot(serial: &str, target: Option<&str>) -> Result<(), LbError> {
+    let target = target.unwrap_or("system");
+    if !REBOOT_TARGETS.contains(&target) {
+        let expected = REBOOT_TARGETS.join(", ");
+        return Err(format!("Unknown fastboot reboot target '{}'; expected one of {}", target, expected).into());
+    }
+    require_listed(serial)?;
+    ensure_device_unlocked(serial)?;
+    let mut args = vec!["reboot"];
+    if target != "system" {
+        args.push(target);
+    }
+    fastboot_checked(serial, &args)?;
+    lb_log!(Level::Info, "fastboot", "fastboot reboot {} into {}", serial, target);
+    Ok(())
+}
+
+/// Devices in bootloader / fastbootd mode: `[{serial, state, transport}]`. Fails with
+/// SpawnFailed when `fastboot` is not on PATH.
+#[no_mangle]
+pub extern "C" fn lb_fastboot_devices() -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || string_result(list_devices(), "fastboot devices"))
+}
+
+/// Runs `fastboot getvar <var>` and returns `{"<name>": "<value>", ...}`; `all` returns
+/// every variable the bootloader reports.
+#[no_mangle]
+pub extern "C" fn lb_fastboot_getvar(serial_ptr: *const c_char, var_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(serial_ptr, "serial")
+            .and_then(|serial| read_c_str(var_ptr, "variable").and_then(|var| getvar(serial, var)));
+        string_result(result, "fastboot variables")
+    })
+}
+
+/// Flashes `image` to `partition` and returns `{serial, partition, image, success,
+/// failure_message, duration_ms}`. The optional callback gets `{stage, part, parts, done,
+/// percent}` per fastboot step (`sending`, `writing`, `resizing`, `erasing`) on the calling
+/// thread; sparse images report one part per chunk.
+#[no_mangle]
+pub extern "C" fn lb_fastboot_flash(
+    serial_ptr: *const c_char,
+    partition_ptr: *const c_char,
+    image_ptr: *const c_char,
+    callback: Option<LineCallback>,
+    user_data: *mut c_void,
+) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let sink = callback.map(|callback| LineSink::new(Some(callback), user_data)).transpose();
+        let result = sink.and_then(|sink| {
+            read_c_str(serial_ptr, "serial").and_then(|serial| {
+                read_c_str(partition_ptr, "partition").and_then(|partition| {
+                    read_c_str(image_ptr, "image path").and_then(|image| flash(serial, partition, image, sink))
+                })
+            })
+        });
+        string_result(result, "flash result")
+    })
+}
+
+/// `fastboot reboot` into `system` (null), `bootloader`, `recovery` or `fastboot` (fastbootd).
+#[no_mangle]
+pub extern "C" fn lb_fastboot_reboot(serial_ptr: *const c_char, target_ptr: *const c_char) -> i32 {
+    ffi_guard(0, || {
+        let target = if target_ptr.is_null() {
+            Ok(None)
+        } else {
+            read_c_str(target_ptr, "reboot target").map(Some)
+        };
+        let result = read_c_str(serial_ptr, "serial").and_then(|serial| target.and_then(|target| reboot(serial, target)));
+        status_result(result)
+    })
+}
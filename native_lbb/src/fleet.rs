@@ -0,0 +1,274 @@
+use std::collections::HashSet;
+use std::os::raw::c_char;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::adb;
+use crate::cancel;
+use crate::device_lock::ensure_device_unlocked;
+use crate::error::{ErrorCode, LbError};
+use crate::exec::{self, CommandSpec};
+use crate::history;
+use crate::json::{self, JsonValue};
+use crate::logging::{lb_log, Level};
+use crate::retry;
+use crate::{ffi_guard, read_c_str, string_result};
+
+const FLEET_BOOT_TIMEOUT_SECS: u64 = 300;
+const RECONNECT_INTERVAL_MS: u64 = 2000;
+/// Devices driven at once by `lb_run_on_devices`; each adb client is a separate process.
+const MAX_PARALLEL_DEVICES: usize = 8;
+const SERIAL_PLACEHOLDER: &str = "{serial}";
+
+/// A `local remote` pair as printed by `adb forward --list` / `adb reverse --list`.
+struct PortMapping {
+    local: String,
+    remote: String,
+}
+
+fn list_mappings(serial: &str, kind: &str) -> Vec<PortMapping> {
+    let output = match adb::adb_checked(Some(serial), &[kind, "--list"]) {
+        Ok(output) => output,
+        Err(_) => return Vec::new(),
+    };
+    output
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                [owner, local, remote] if kind == "reverse" || *owner == serial => Some(PortMapping {
+                    local: local.to_string(),
+                    remote: remote.to_string(),
+                }),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+fn restore_mappings(serial: &str, kind: &str, mappings: &[PortMapping]) -> (Vec<JsonValue>, Vec<JsonValue>) {
+    let mut restored = Vec::new();
+    let mut failed = Vec::new();
+    for mapping in mappings {
+        let label = JsonValue::from(format!("{} {}", mapping.local, mapping.remote));
+        match adb::adb_checked(Some(serial), &[kind, &mapping.local, &mapping.remote]) {
+            Ok(_) => restored.push(label),
+            Err(_) => failed.push(label),
+        }
+    }
+    (restored, failed)
+}
+
+fn wait_for_device(serial: &str, deadline: Instant) -> Result<bool, LbError> {
+    let network = adb::is_network_serial(serial);
+    let mut reconnected = false;
+    while Instant::now() < deadline {
+        if adb::get_state(serial).as_deref() == Some("device") {
+            return Ok(reconnected);
+        }
+        if network {
+            if let Ok(output) = adb::adb_checked(None, &["connect", serial]) {
+                reconnected = output.contains("connected to");
+            }
+        }
+        cancel::sleep(Duration::from_millis(RECONNECT_INTERVAL_MS))?;
+    }
+    Err(LbError::new(ErrorCode::DeviceOffline, format!("{} did not come back online", serial)).with_serial(serial))
+}
+
+fn reboot_and_verify(serial: &str) -> JsonValue {
+    let started = Instant::now();
+    let deadline = started + Duration::from_secs(FLEET_BOOT_TIMEOUT_SECS);
+    let forwards = list_mappings(serial, "forward");
+    let reverses = list_mappings(serial, "reverse");
+
+    let outcome = ensure_device_unlocked(serial)
+        .and_then(|_| adb::adb_checked(Some(serial), &["reboot"]))
+        .and_then(|_| {
+            // Give adbd time to drop the connection so the old session is not mistaken for the new one.
+            cancel::sleep(Duration::from_millis(RECONNECT_INTERVAL_MS))?;
+            wait_for_device(serial, deadline)
+        })
+        .and_then(|reconnected| {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            adb::wait_for_boot_completed(serial, remaining).map(|_| reconnected)
+        });
+
+    let mut report = vec![("serial", JsonValue::from(serial))];
+    match outcome {
+        Ok(reconnected) => {
+            let (forward_ok, forward_failed) = restore_mappings(serial, "forward", &forwards);
+            let (reverse_ok, reverse_failed) = restore_mappings(serial, "reverse", &reverses);
+            let complete = forward_failed.is_empty() && reverse_failed.is_empty();
+            report.push(("status", if complete { "ok" } else { "partial" }.into()));
+            report.push(("boot_time_ms", (started.elapsed().as_millis() as u64).into()));
+            report.push((
+                "wireless_reconnected",
+                if adb::is_network_serial(serial) { reconnected.into() } else { JsonValue::Null },
+            ));
+            report.push(("forwards_restored", forward_ok.into()));
+            report.push(("forwards_failed", forward_failed.into()));
+            report.push(("reverses_restored", reverse_ok.into()));
+            report.push(("reverses_failed", reverse_failed.into()));
+        }
+        Err(err) => {
+            report.push(("status", "failed".into()));
+            report.push(("elapsed_ms", (started.elapsed().as_millis() as u64).into()));
+            report.push(("error", err.message.into()));
+        }
+    }
+    JsonValue::object(report)
+}
+
+pub fn parse_serials(serials_json: &str) -> Result<Vec<String>, LbError> {
+    json::parse(serials_json)?
+        .as_string_array()
+        .ok_or_else(|| "Serial list must be a JSON array of strings".into())
+}
+
+fn reboot_fleet(serials: Vec<String>, staggered_ms: u64) -> String {
+    let handles: Vec<_> = serials
+        .into_iter()
+        .enumerate()
+        .map(|(index, serial)| {
+            let worker_serial = serial.clone();
+            let handle = cancel::spawn(move || {
+                // Devices still waiting out the stagger when the batch is cancelled are not rebooted.
+                match cancel::sleep(Duration::from_millis(staggered_ms.saturating_mul(index as u64))) {
+                    Ok(()) => reboot_and_verify(&worker_serial),
+                    Err(err) => JsonValue::object(vec![
+                        ("serial", worker_serial.into()),
+                        ("status", "failed".into()),
+                        ("error", err.message.into()),
+                    ]),
+                }
+            });
+            (serial, handle)
+        })
+        .collect();
+    let reports: Vec<JsonValue> = handles
+        .into_iter()
+        .map(|(serial, handle)| {
+            handle.join().unwrap_or_else(|_| {
+                JsonValue::object(vec![
+                    ("serial", serial.into()),
+                    ("status", "failed".into()),
+                    ("error", "Reboot worker panicked".into()),
+                ])
+            })
+        })
+        .collect();
+    JsonValue::Array(reports).to_string()
+}
+
+/// Tokenizes `template` once, then either substitutes `{serial}` inside tokens or, for a
+/// plain `adb ...` template, inserts `-s <serial>` after the program.
+fn device_argv(template: &[String], serial: &str) -> Result<Vec<String>, LbError> {
+    if template.iter().any(|token| token.contains(SERIAL_PLACEHOLDER)) {
+        return Ok(template.iter().map(|token| token.replace(SERIAL_PLACEHOLDER, serial)).collect());
+    }
+    let is_adb = template
+        .first()
+        .and_then(|program| Path::new(program).file_stem())
+        .is_some_and(|stem| stem.eq_ignore_ascii_case("adb"));
+    if !is_adb {
+        return Err(format!("Command template must contain {} or start with adb", SERIAL_PLACEHOLDER).into());
+    }
+    let mut argv = vec![template[0].clone(), "-s".to_string(), serial.to_string()];
+    argv.extend(template[1..].iter().cloned());
+    Ok(argv)
+}
+
+/// Runs `work` once per serial, at most 8 devices at a time, and returns the results in
+/// serial order.
+pub fn on_devices<F>(serials: &[String], work: F) -> Result<Vec<JsonValue>, LbError>
+where
+    F: Fn(&str) -> JsonValue + Send + Sync + 'static,
+{
+    let serials = Arc::new(serials.to_vec());
+    let work = Arc::new(work);
+    let next = Arc::new(AtomicUsize::new(0));
+    let results = Arc::new(Mutex::new(vec![JsonValue::Null; serials.len()]));
+    let workers: Vec<_> = (0..serials.len().min(MAX_PARALLEL_DEVICES))
+        .map(|_| {
+            let (serials, work, next, results) = (serials.clone(), work.clone(), next.clone(), results.clone());
+            cancel::spawn(move || loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(serial) = serials.get(index) else {
+                    break;
+                };
+                // Devices not yet started when the batch is cancelled report the cancellation.
+                let result = match cancel::current().check() {
+                    Ok(()) => work(serial),
+                    Err(err) => JsonValue::object(vec![("error", err.with_serial(serial).to_json())]),
+                };
+                if let Ok(mut results) = results.lock() {
+                    results[index] = result;
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker
+            .join()
+            .map_err(|_| LbError::internal("Thread panicked during command execution"))?;
+    }
+    let results = results
+        .lock()
+        .map_err(|_| LbError::internal("Device results poisoned"))?
+        .clone();
+    Ok(results)
+}
+
+/// `(serial, structured result)` for each distinct serial, in the order given.
+pub fn run_on_devices(serials: Vec<String>, template: &str) -> Result<Vec<(String, JsonValue)>, LbError> {
+    let template = exec::shlex_split(template)?;
+    if template.is_empty() {
+        return Err("Empty command template".into());
+    }
+    let mut seen = HashSet::new();
+    let serials: Vec<String> = serials.into_iter().filter(|serial| seen.insert(serial.clone())).collect();
+    // Checked up front so a bad template fails the call instead of every device.
+    device_argv(&template, "")?;
+    lb_log!(Level::Info, "exec", "Running {} on {} devices", history::redacted(&template).0.join(" "), serials.len());
+
+    let results = on_devices(&serials, move |serial| {
+        exec::execute_structured(CommandSpec {
+            argv: device_argv(&template, serial),
+            stdin: None,
+            max_output_bytes: exec::output_limit(),
+            retry: retry::default_policy(),
+        })
+    })?;
+    Ok(serials.into_iter().zip(results).collect())
+}
+
+/// Reboots every serial (starting one each `staggered_ms`), waits for full boot,
+/// restores forwards/reverses and wireless connections, and returns a JSON report array.
+/// Cancelling stops the waits; devices still in the stagger report it as their error.
+#[no_mangle]
+pub extern "C" fn lb_reboot_fleet(serials_json_ptr: *const c_char, staggered_ms: u64) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(serials_json_ptr, "serial list")
+            .and_then(parse_serials)
+            .map(|serials| reboot_fleet(serials, staggered_ms));
+        string_result(result, "fleet report")
+    })
+}
+
+/// Runs `command_template` once per serial, at most 8 devices at a time, and returns
+/// `{"<serial>": {argv, exit_code, duration_ms, pid, stdout, stderr, ...}}` like
+/// `lb_run_commands_structured`. `{serial}` in the template is replaced; otherwise an
+/// `adb ...` template gets `-s <serial>` inserted.
+#[no_mangle]
+pub extern "C" fn lb_run_on_devices(serials_json_ptr: *const c_char, template_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(serials_json_ptr, "serial list").and_then(parse_serials).and_then(|serials| {
+            read_c_str(template_ptr, "command template").and_then(|template| run_on_devices(serials, template))
+        });
+        let result = result.map(|keyed| JsonValue::Object(keyed).to_string());
+        string_result(result, "device results")
+    })
+}
@@ -0,0 +1,138 @@
+use std::collections::BTreeMap;
+use std::os::raw::c_char;
+use std::time::{Duration, Instant};
+
+use crate::cancel;
+use crate::dumpsys;
+use crate::error::LbError;
+use crate::json::JsonValue;
+use crate::packages;
+use crate::{ffi_guard, read_c_str, string_result};
+
+const MAX_DURATION_MS: u64 = 10 * 60 * 1000;
+/// framestats only keeps the last ~120 frames, so poll well before that fills at 120 Hz.
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(500);
+const DEFAULT_FRAME_BUDGET_NS: u64 = 16_666_667;
+const PERCENTILES: [u64; 4] = [50, 90, 95, 99];
+
+/// Frame durations (`FrameCompleted - IntendedVsync`, ns) keyed by `IntendedVsync` from the
+/// `---PROFILEDATA---` CSV blocks. Rows with non-zero `Flags` are not real frames.
+fn parse_framestats(output: &str, frames: &mut BTreeMap<u64, u64>) {
+    let mut columns: Option<(usize, usize, usize)> = None;
+    for line in output.lines().map(str::trim) {
+        if line.starts_with("Flags,") {
+            let header: Vec<&str> = line.split(',').collect();
+            let index = |name: &str| header.iter().position(|column| *column == name);
+            columns = match (index("Flags"), index("IntendedVsync"), index("FrameCompleted")) {
+                (Some(flags), Some(vsync), Some(completed)) => Some((flags, vsync, completed)),
+                _ => None,
+            };
+            continue;
+        }
+        if line == "---PROFILEDATA---" {
+            continue;
+        }
+        let Some((flags, vsync, completed)) = columns else {
+            continue;
+        };
+        let values: Vec<&str> = line.split(',').collect();
+        let field = |index: usize| values.get(index).and_then(|value| value.trim().parse::<u64>().ok());
+        match (field(flags), field(vsync), field(completed)) {
+            (Some(0), Some(vsync), Some(completed)) if completed > vsync && vsync > 0 => {
+                frames.insert(vsync, completed - vsync);
+            }
+            (None, _, _) => columns = None,
+            _ => {}
+        }
+    }
+}
+
+/// Shortest plausible gap between frame starts, i.e. the display's vsync period.
+fn frame_budget_ns(frames: &BTreeMap<u64, u64>) -> u64 {
+    frames
+        .keys()
+        .zip(frames.keys().skip(1))
+        .map(|(previous, next)| next - previous)
+        .filter(|gap| *gap >= 4_000_000)
+        .min()
+        .unwrap_or(DEFAULT_FRAME_BUDGET_NS)
+}
+
+fn millis(nanos: u64) -> f64 {
+    (nanos as f64 / 10_000.0).round() / 100.0
+}
+
+fn frame_metrics(serial: &str, package: &str, duration_ms: u64) -> Result<String, LbError> {
+    packages::validate_package(package)?;
+    if duration_ms == 0 || duration_ms > MAX_DURATION_MS {
+        return Err(format!("Frame metrics duration must be 1..={} ms", MAX_DURATION_MS).into());
+    }
+    dumpsys::run_dumpsys(serial, "gfxinfo", &format!("{} reset", package))?;
+    let started = Instant::now();
+    let deadline = started + Duration::from_millis(duration_ms);
+    let mut frames = BTreeMap::new();
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        cancel::sleep(SAMPLE_INTERVAL.min(deadline - now))?;
+        let output = dumpsys::run_dumpsys(serial, "gfxinfo", &format!("{} framestats", package))?;
+        parse_framestats(&output, &mut frames);
+    }
+    let summary = dumpsys::run_dumpsys(serial, "gfxinfo", package)
+        .ok()
+        .and_then(|output| dumpsys::parse_gfxinfo(&output));
+
+    let budget = frame_budget_ns(&frames);
+    let mut durations: Vec<u64> = frames.values().copied().collect();
+    durations.sort_unstable();
+    let janky = durations.iter().filter(|duration| **duration > budget).count();
+    let percentiles: Vec<(String, JsonValue)> = PERCENTILES
+        .iter()
+        .map(|rank| {
+            // Nearest-rank percentile.
+            let value = match durations.len() {
+                0 => JsonValue::Null,
+                len => {
+                    let index = ((*rank as usize * len).div_ceil(100)).clamp(1, len) - 1;
+                    millis(durations[index]).into()
+                }
+            };
+            (format!("p{}", rank), value)
+        })
+        .collect();
+    let janky_percent = if durations.is_empty() {
+        0.0
+    } else {
+        (janky as f64 * 10_000.0 / durations.len() as f64).round() / 100.0
+    };
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("package", package.into()),
+        ("duration_ms", (started.elapsed().as_millis() as u64).into()),
+        ("total_frames", durations.len().into()),
+        ("janky_frames", janky.into()),
+        ("janky_percent", janky_percent.into()),
+        ("frame_budget_ms", millis(budget).into()),
+        ("percentiles_ms", JsonValue::Object(percentiles)),
+        ("max_frame_ms", durations.last().map(|max| millis(*max)).into()),
+        ("summary", summary.into()),
+    ])
+    .to_string())
+}
+
+/// Resets gfxinfo for `package`, samples `framestats` for `duration_ms` (at most 10 minutes),
+/// and returns `{serial, package, duration_ms, total_frames, janky_frames, janky_percent,
+/// frame_budget_ms, percentiles_ms: {p50, p90, p95, p99}, max_frame_ms, summary}`. A frame is
+/// janky when it takes longer than one vsync period; `summary` is the platform's own
+/// `lb_dumpsys_gfxinfo_json` view. Blocks for the whole window.
+#[no_mangle]
+pub extern "C" fn lb_frame_metrics(serial_ptr: *const c_char, package_ptr: *const c_char, duration_ms: u64) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(serial_ptr, "serial").and_then(|serial| {
+            read_c_str(package_ptr, "package").and_then(|package| frame_metrics(serial, package, duration_ms))
+        });
+        string_result(result, "frame metrics")
+    })
+}
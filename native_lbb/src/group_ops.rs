@@ -0,0 +1,191 @@
+use std::os::raw::c_char;
+
+use crate::adb;
+use crate::device_meta;
+use crate::error::LbError;
+use crate::fleet;
+use crate::install;
+use crate::json::JsonValue;
+use crate::logging::{lb_log, Level};
+use crate::multi_capture;
+use crate::{ffi_guard, read_c_str, start_screen_record, stop_screen_record, string_result};
+
+/// A group's members split into those adb reports as `device` and the rest.
+struct GroupTargets {
+    group: String,
+    online: Vec<String>,
+    /// `(serial, state)`; `not connected` when adb does not list the serial at all.
+    skipped: Vec<(String, JsonValue)>,
+}
+
+fn resolve(group: &str) -> Result<GroupTargets, LbError> {
+    let members = device_meta::group_members(group)?;
+    let listed = adb::list_devices()?;
+    let mut targets = GroupTargets {
+        group: group.to_string(),
+        online: Vec::new(),
+        skipped: Vec::new(),
+    };
+    for serial in members {
+        match listed.iter().find(|device| device.serial == serial) {
+            Some(device) if device.state == "device" => targets.online.push(serial),
+            Some(device) => targets.skipped.push((serial, device.state.as_str().into())),
+            None => targets.skipped.push((serial, "not connected".into())),
+        }
+    }
+    lb_log!(
+        Level::Info,
+        "exec",
+        "Group {}: {} online, {} skipped",
+        group,
+        targets.online.len(),
+        targets.skipped.len()
+    );
+    Ok(targets)
+}
+
+/// `{group, succeeded, failed, skipped: {serial: state}, results: {serial: result}}`;
+/// `succeeded` lists the serials `ok` accepts and `failed` the others that ran.
+fn report(targets: GroupTargets, results: Vec<(String, JsonValue)>, ok: impl Fn(&JsonValue) -> bool) -> String {
+    let serials = |succeeded: bool| {
+        results
+            .iter()
+            .filter(|(_, result)| ok(result) == succeeded)
+            .map(|(serial, _)| JsonValue::from(serial))
+            .collect::<Vec<_>>()
+    };
+    let (succeeded, failed) = (serials(true), serials(false));
+    JsonValue::object(vec![
+        ("group", targets.group.into()),
+        ("succeeded", succeeded.into()),
+        ("failed", failed.into()),
+        ("skipped", JsonValue::Object(targets.skipped)),
+        ("results", JsonValue::Object(results)),
+    ])
+    .to_string()
+}
+
+/// A per-device error in place of that device's result, so one failure does not fail the call.
+fn error_result(err: LbError) -> JsonValue {
+    JsonValue::object(vec![("error", err.to_json())])
+}
+
+fn run_on_group(group: &str, template: &str) -> Result<String, LbError> {
+    let targets = resolve(group)?;
+    let results = fleet::run_on_devices(targets.online.clone(), template)?;
+    Ok(report(targets, results, |result| {
+        result.get("exit_code").and_then(JsonValue::as_u64) == Some(0)
+    }))
+}
+
+fn install_on_group(group: &str, apk_path: &str, options_json: Option<&str>) -> Result<String, LbError> {
+    let targets = resolve(group)?;
+    let (apk_path, options_json) = (apk_path.to_string(), options_json.map(str::to_string));
+    let results = fleet::on_devices(&targets.online, move |serial| {
+        install::install_apk(serial, &apk_path, options_json.as_deref(), None).unwrap_or_else(error_result)
+    })?;
+    let results = targets.online.iter().cloned().zip(results).collect();
+    Ok(report(targets, results, |result| {
+        result.get("success").and_then(JsonValue::as_bool) == Some(true)
+    }))
+}
+
+fn capture_group(group: &str, what: &str) -> Result<String, LbError> {
+    let targets = resolve(group)?;
+    let results = if targets.online.is_empty() {
+        Vec::new()
+    } else {
+        multi_capture::capture_all(targets.online.clone(), what)?
+    };
+    Ok(report(targets, results, |result| {
+        result.get("status").and_then(JsonValue::as_str) == Some("ok")
+    }))
+}
+
+fn record_group(group: &str, remote_path: Option<&str>) -> Result<String, LbError> {
+    let targets = resolve(group)?;
+    let remote_path = remote_path.map(str::to_string);
+    let results = fleet::on_devices(&targets.online, move |serial| {
+        let outcome = match &remote_path {
+            Some(remote_path) => start_screen_record(serial, remote_path),
+            None => stop_screen_record(serial),
+        };
+        match outcome {
+            Ok(()) => JsonValue::object(vec![("status", "ok".into())]),
+            Err(err) => error_result(err),
+        }
+    })?;
+    let results = targets.online.iter().cloned().zip(results).collect();
+    Ok(report(targets, results, |result| result.get("error").is_none()))
+}
+
+/// Runs `command_template` on every connected member of `group` (device metadata `groups`),
+/// like `lb_run_on_devices`. Returns `{group, succeeded, failed, skipped, results}`:
+/// `succeeded`/`failed` list serials by exit code, `skipped` maps members that are offline,
+/// unauthorized or not connected to their state, and `results` maps serials to the
+/// structured command result. NotFound when the group has no members.
+#[no_mangle]
+pub extern "C" fn lb_run_on_group(group_ptr: *const c_char, template_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(group_ptr, "group").and_then(|group| {
+            read_c_str(template_ptr, "command template").and_then(|template| run_on_group(group, template))
+        });
+        string_result(result, "group results")
+    })
+}
+
+/// Installs `apk_path` on every connected member of `group`, 8 at a time, with the options
+/// of `lb_install_apk` (may be null). `results` holds each `lb_install_apk` result, or
+/// `{error}` (as in `lb_last_error_json`) when that device could not be installed to;
+/// `succeeded` lists the serials whose install reported success.
+#[no_mangle]
+pub extern "C" fn lb_install_on_group(
+    group_ptr: *const c_char,
+    apk_path_ptr: *const c_char,
+    options_ptr: *const c_char,
+) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let options = if options_ptr.is_null() {
+            Ok(None)
+        } else {
+            read_c_str(options_ptr, "install options").map(Some)
+        };
+        let result = read_c_str(group_ptr, "group").and_then(|group| {
+            read_c_str(apk_path_ptr, "APK path")
+                .and_then(|apk_path| options.and_then(|options| install_on_group(group, apk_path, options)))
+        });
+        string_result(result, "group results")
+    })
+}
+
+/// `lb_capture_all` over the connected members of `group`; `what` is `screenshot`,
+/// `hierarchy` or both. `succeeded` lists captures whose status is `ok`.
+#[no_mangle]
+pub extern "C" fn lb_capture_group(group_ptr: *const c_char, what_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(group_ptr, "group")
+            .and_then(|group| read_c_str(what_ptr, "capture targets").and_then(|what| capture_group(group, what)));
+        string_result(result, "group results")
+    })
+}
+
+/// `lb_start_screen_record(serial, remote_path)` on every connected member of `group`.
+/// Each result is `{status: "ok"}` or `{error}`; one device failing to start does not
+/// stop the others.
+#[no_mangle]
+pub extern "C" fn lb_start_group_recording(group_ptr: *const c_char, remote_path_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(group_ptr, "group").and_then(|group| {
+            read_c_str(remote_path_ptr, "remote path").and_then(|remote_path| record_group(group, Some(remote_path)))
+        });
+        string_result(result, "group results")
+    })
+}
+
+/// `lb_stop_screen_record` on every connected member of `group`, in parallel.
+#[no_mangle]
+pub extern "C" fn lb_stop_group_recording(group_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        string_result(read_c_str(group_ptr, "group").and_then(|group| record_group(group, None)), "group results")
+    })
+}
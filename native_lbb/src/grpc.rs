@@ -0,0 +1,498 @@
+use std::collections::HashMap;
+use std::fs;
+use std::net::{SocketAddr, TcpListener};
+use std::os::raw::c_char;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tokio::runtime::Runtime;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tokio_stream::Stream;
+use tonic::transport::{Identity, Server, ServerTlsConfig};
+use tonic::{Request, Response, Status};
+
+use crate::adb;
+use crate::error::{ErrorCode, LbError};
+use crate::json::{self, JsonValue};
+use crate::logging::{lb_log, Level};
+use crate::server::token_matches;
+use crate::{device_meta, stream};
+use crate::{ffi_guard, read_c_str, start_screen_record, status_result, stop_screen_record, string_result};
+
+use command_service_server::{CommandService, CommandServiceServer};
+use device_service_server::{DeviceService, DeviceServiceServer};
+use media_service_server::{MediaService, MediaServiceServer};
+
+// Generated by build.rs from the method tables there.
+include!(concat!(env!("OUT_DIR"), "/lazy_blacktea.v1.DeviceService.rs"));
+include!(concat!(env!("OUT_DIR"), "/lazy_blacktea.v1.CommandService.rs"));
+include!(concat!(env!("OUT_DIR"), "/lazy_blacktea.v1.MediaService.rs"));
+
+const DEFAULT_TRACK_INTERVAL_MS: u32 = 1000;
+const MIN_TRACK_INTERVAL_MS: u32 = 250;
+/// Output lines buffered per streaming call before the adb reader waits for the client.
+const STREAM_BUFFER_LINES: usize = 256;
+/// How long `lb_grpc_stop` lets in-flight calls finish before they are cancelled.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+// Messages of `proto/lazy_blacktea.proto`, field for field.
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Device {
+    #[prost(string, tag = "1")]
+    pub serial: String,
+    #[prost(string, tag = "2")]
+    pub state: String,
+    #[prost(string, tag = "3")]
+    pub model: String,
+    #[prost(string, tag = "4")]
+    pub product: String,
+    #[prost(string, tag = "5")]
+    pub alias: String,
+    #[prost(string, repeated, tag = "6")]
+    pub tags: Vec<String>,
+    #[prost(string, repeated, tag = "7")]
+    pub groups: Vec<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListDevicesRequest {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListDevicesResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub devices: Vec<Device>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TrackDevicesRequest {
+    #[prost(uint32, tag = "1")]
+    pub interval_ms: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DeviceEvent {
+    #[prost(string, tag = "1")]
+    pub kind: String,
+    #[prost(message, optional, tag = "2")]
+    pub device: Option<Device>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RunRequest {
+    #[prost(string, tag = "1")]
+    pub serial: String,
+    #[prost(string, tag = "2")]
+    pub command: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RunResponse {
+    #[prost(int32, tag = "1")]
+    pub exit_code: i32,
+    #[prost(string, tag = "2")]
+    pub stdout: String,
+    #[prost(string, tag = "3")]
+    pub stderr: String,
+    #[prost(uint64, tag = "4")]
+    pub duration_ms: u64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct OutputLine {
+    #[prost(string, tag = "1")]
+    pub line: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ScreenshotRequest {
+    #[prost(string, tag = "1")]
+    pub serial: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Screenshot {
+    #[prost(bytes = "vec", tag = "1")]
+    pub png: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RecordingRequest {
+    #[prost(string, tag = "1")]
+    pub serial: String,
+    #[prost(string, tag = "2")]
+    pub remote_path: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RecordingResponse {}
+
+fn status(err: LbError) -> Status {
+    let code = match err.code {
+        ErrorCode::NullPointer | ErrorCode::Utf8 | ErrorCode::InvalidArgument | ErrorCode::ParseError => {
+            tonic::Code::InvalidArgument
+        }
+        ErrorCode::NotFound => tonic::Code::NotFound,
+        ErrorCode::PermissionDenied => tonic::Code::PermissionDenied,
+        ErrorCode::DeviceOffline | ErrorCode::DeviceUnauthorized | ErrorCode::DeviceLocked => {
+            tonic::Code::FailedPrecondition
+        }
+        ErrorCode::Timeout => tonic::Code::DeadlineExceeded,
+        ErrorCode::Cancelled => tonic::Code::Cancelled,
+        _ => tonic::Code::Internal,
+    };
+    Status::new(code, err.message)
+}
+
+// `Status` is what tonic's handlers and interceptors return, large or not.
+#[allow(clippy::result_large_err)]
+fn required(value: &str, name: &str) -> Result<(), Status> {
+    if value.is_empty() {
+        return Err(Status::invalid_argument(format!("'{}' must not be empty", name)));
+    }
+    Ok(())
+}
+
+/// Runs adb work on the blocking pool so it never stalls the runtime's workers.
+async fn blocking<T: Send + 'static>(work: impl FnOnce() -> Result<T, LbError> + Send + 'static) -> Result<T, Status> {
+    tokio::task::spawn_blocking(work)
+        .await
+        .map_err(|err| Status::internal(format!("Worker failed: {}", err)))?
+        .map_err(status)
+}
+
+fn device(listed: &JsonValue) -> Device {
+    let text = |name: &str| listed.get(name).and_then(JsonValue::as_str).unwrap_or_default().to_string();
+    let list = |name: &str| listed.get(name).and_then(JsonValue::as_string_array).unwrap_or_default();
+    Device {
+        serial: text("serial"),
+        state: text("state"),
+        model: text("model"),
+        product: text("product"),
+        alias: text("alias"),
+        tags: list("tags"),
+        groups: list("groups"),
+    }
+}
+
+/// `lb_list_devices_json`, so aliases, tags and groups come along.
+fn list_devices() -> Result<Vec<Device>, LbError> {
+    let listed = json::parse(&device_meta::list_devices_json()?)?;
+    Ok(listed
+        .as_array()
+        .map(|devices| devices.iter().map(device).collect())
+        .unwrap_or_default())
+}
+
+fn device_events(known: &[Device], current: &[Device]) -> Vec<DeviceEvent> {
+    let event = |kind: &str, device: &Device| DeviceEvent {
+        kind: kind.to_string(),
+        device: Some(device.clone()),
+    };
+    let mut events: Vec<DeviceEvent> = known
+        .iter()
+        .filter(|old| !current.iter().any(|new| new.serial == old.serial))
+        .map(|old| event("removed", old))
+        .collect();
+    for new in current {
+        match known.iter().find(|old| old.serial == new.serial) {
+            None => events.push(event("added", new)),
+            Some(old) if old != new => events.push(event("changed", new)),
+            Some(_) => {}
+        }
+    }
+    events
+}
+
+type EventStream = Pin<Box<dyn Stream<Item = Result<DeviceEvent, Status>> + Send>>;
+
+struct Devices;
+
+#[tonic::async_trait]
+impl DeviceService for Devices {
+    type TrackStream = EventStream;
+
+    async fn list(&self, _request: Request<ListDevicesRequest>) -> Result<Response<ListDevicesResponse>, Status> {
+        let devices = blocking(list_devices).await?;
+        Ok(Response::new(ListDevicesResponse { devices }))
+    }
+
+    /// Polls `adb devices` and sends the differences; ends when the client goes away or
+    /// adb fails.
+    async fn track(&self, request: Request<TrackDevicesRequest>) -> Result<Response<Self::TrackStream>, Status> {
+        let interval_ms = match request.into_inner().interval_ms {
+            0 => DEFAULT_TRACK_INTERVAL_MS,
+            interval_ms => interval_ms.max(MIN_TRACK_INTERVAL_MS),
+        };
+        let interval = Duration::from_millis(interval_ms as u64);
+        let (sender, receiver) = mpsc::channel(16);
+        tokio::spawn(async move {
+            let mut known = Vec::new();
+            loop {
+                let current = match blocking(list_devices).await {
+                    Ok(current) => current,
+                    Err(err) => {
+                        let _ = sender.send(Err(err)).await;
+                        return;
+                    }
+                };
+                for event in device_events(&known, &current) {
+                    if sender.send(Ok(event)).await.is_err() {
+                        return;
+                    }
+                }
+                known = current;
+                if tokio::time::timeout(interval, sender.closed()).await.is_ok() {
+                    return;
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(ReceiverStream::new(receiver))))
+    }
+}
+
+/// Output of one `stream::spawn_line_stream` child; dropping it (the call ended or the
+/// client cancelled) kills the child.
+struct LineStream {
+    id: u64,
+    lines: ReceiverStream<Result<OutputLine, Status>>,
+}
+
+impl Stream for LineStream {
+    type Item = Result<OutputLine, Status>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.lines).poll_next(cx)
+    }
+}
+
+impl Drop for LineStream {
+    fn drop(&mut self) {
+        // Already gone when the command exited on its own.
+        let _ = stream::stop_stream(self.id);
+    }
+}
+
+struct Commands;
+
+#[tonic::async_trait]
+impl CommandService for Commands {
+    type StreamStream = LineStream;
+
+    async fn run(&self, request: Request<RunRequest>) -> Result<Response<RunResponse>, Status> {
+        let RunRequest { serial, command } = request.into_inner();
+        required(&serial, "serial")?;
+        required(&command, "command")?;
+        let output = blocking(move || adb::run_adb(Some(&serial), &["shell", &command])).await?;
+        Ok(Response::new(RunResponse {
+            exit_code: output.exit_code.unwrap_or(-1),
+            stdout: output.stdout,
+            stderr: output.stderr,
+            duration_ms: output.duration.as_millis() as u64,
+        }))
+    }
+
+    async fn stream(&self, request: Request<RunRequest>) -> Result<Response<Self::StreamStream>, Status> {
+        let RunRequest { serial, command } = request.into_inner();
+        required(&serial, "serial")?;
+        required(&command, "command")?;
+        let (sender, receiver) = mpsc::channel(STREAM_BUFFER_LINES);
+        let argv = adb::adb_argv(Some(&serial), &["shell", &command]);
+        // The reader is a plain thread, so waiting on a full buffer only slows adb down.
+        let id = stream::spawn_line_stream(&argv, move |line| {
+            let _ = sender.blocking_send(Ok(OutputLine { line: line.to_string() }));
+        })
+        .map_err(status)?;
+        Ok(Response::new(LineStream {
+            id,
+            lines: ReceiverStream::new(receiver),
+        }))
+    }
+}
+
+struct Media;
+
+#[tonic::async_trait]
+impl MediaService for Media {
+    async fn screenshot(&self, request: Request<ScreenshotRequest>) -> Result<Response<Screenshot>, Status> {
+        let serial = request.into_inner().serial;
+        required(&serial, "serial")?;
+        let png = blocking(move || adb::screencap_png(&serial)).await?;
+        Ok(Response::new(Screenshot { png }))
+    }
+
+    async fn start_recording(&self, request: Request<RecordingRequest>) -> Result<Response<RecordingResponse>, Status> {
+        let RecordingRequest { serial, remote_path } = request.into_inner();
+        required(&serial, "serial")?;
+        required(&remote_path, "remote_path")?;
+        blocking(move || start_screen_record(&serial, &remote_path)).await?;
+        Ok(Response::new(RecordingResponse {}))
+    }
+
+    async fn stop_recording(&self, request: Request<RecordingRequest>) -> Result<Response<RecordingResponse>, Status> {
+        let serial = request.into_inner().serial;
+        required(&serial, "serial")?;
+        blocking(move || stop_screen_record(&serial)).await?;
+        Ok(Response::new(RecordingResponse {}))
+    }
+}
+
+/// Rejects calls without `authorization: Bearer <token>`.
+#[allow(clippy::result_large_err)]
+fn authenticate(token: Arc<str>) -> impl FnMut(Request<()>) -> Result<Request<()>, Status> + Clone {
+    move |request| {
+        let given = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        match given {
+            Some(given) if token_matches(given.trim(), &token) => Ok(request),
+            _ => Err(Status::unauthenticated("Missing or invalid auth token")),
+        }
+    }
+}
+
+struct GrpcServer {
+    runtime: Runtime,
+    shutdown: oneshot::Sender<()>,
+    serving: JoinHandle<()>,
+}
+
+static NEXT_GRPC_ID: AtomicU64 = AtomicU64::new(1);
+static GRPC_SERVERS: OnceLock<Mutex<HashMap<u64, GrpcServer>>> = OnceLock::new();
+
+fn grpc_registry() -> &'static Mutex<HashMap<u64, GrpcServer>> {
+    GRPC_SERVERS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn string_option<'a>(options: &'a JsonValue, name: &str) -> Result<Option<&'a str>, LbError> {
+    match options.get(name) {
+        None | Some(JsonValue::Null) => Ok(None),
+        Some(value) => value
+            .as_str()
+            .map(Some)
+            .ok_or_else(|| format!("gRPC option '{}' must be a string", name).into()),
+    }
+}
+
+fn read_pem(path: &str) -> Result<Vec<u8>, LbError> {
+    fs::read(path).map_err(|err| LbError::io(format!("Failed to read {}: {}", path, err)))
+}
+
+fn start_grpc(options_json: &str) -> Result<String, LbError> {
+    let options = json::parse(options_json)?;
+    let bind_addr = string_option(&options, "bind_addr")?.ok_or("gRPC options need 'bind_addr'")?;
+    let token = string_option(&options, "auth_token")?
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+        .ok_or("gRPC options need a non-empty 'auth_token'")?;
+    let identity = match (string_option(&options, "tls_cert_path")?, string_option(&options, "tls_key_path")?) {
+        (Some(cert), Some(key)) => Some(Identity::from_pem(read_pem(cert)?, read_pem(key)?)),
+        (None, None) => None,
+        _ => return Err("'tls_cert_path' and 'tls_key_path' must be given together".into()),
+    };
+    let tls = identity.is_some();
+
+    let mut builder = Server::builder();
+    if let Some(identity) = identity {
+        builder = builder
+            .tls_config(ServerTlsConfig::new().identity(identity))
+            .map_err(|err| LbError::from(format!("Invalid TLS configuration: {}", err)))?;
+    }
+    let listener = TcpListener::bind(bind_addr).map_err(|err| LbError::io(format!("Failed to bind {}: {}", bind_addr, err)))?;
+    let address: SocketAddr = listener
+        .local_addr()
+        .and_then(|address| listener.set_nonblocking(true).map(|_| address))
+        .map_err(|err| LbError::io(format!("Failed to prepare listener: {}", err)))?;
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .thread_name("lb-grpc")
+        .build()
+        .map_err(|err| LbError::internal(format!("Failed to start gRPC runtime: {}", err)))?;
+
+    let token: Arc<str> = Arc::from(token);
+    let (shutdown, stopped) = oneshot::channel::<()>();
+    let serving = runtime.spawn(async move {
+        let incoming = match tokio::net::TcpListener::from_std(listener) {
+            Ok(listener) => TcpListenerStream::new(listener),
+            Err(err) => {
+                lb_log!(Level::Error, "grpc", "Failed to register listener: {}", err);
+                return;
+            }
+        };
+        let result = builder
+            .add_service(DeviceServiceServer::with_interceptor(Devices, authenticate(Arc::clone(&token))))
+            .add_service(CommandServiceServer::with_interceptor(Commands, authenticate(Arc::clone(&token))))
+            .add_service(MediaServiceServer::with_interceptor(Media, authenticate(token)))
+            .serve_with_incoming_shutdown(incoming, async {
+                let _ = stopped.await;
+            })
+            .await;
+        if let Err(err) = result {
+            lb_log!(Level::Error, "grpc", "gRPC server failed: {}", err);
+        }
+    });
+
+    let id = NEXT_GRPC_ID.fetch_add(1, Ordering::Relaxed);
+    grpc_registry()
+        .lock()
+        .map_err(|_| LbError::internal("gRPC registry poisoned"))?
+        .insert(
+            id,
+            GrpcServer {
+                runtime,
+                shutdown,
+                serving,
+            },
+        );
+    lb_log!(Level::Info, "grpc", "gRPC server {} listening on {} (tls: {})", id, address, tls);
+    Ok(JsonValue::object(vec![
+        ("handle", id.into()),
+        ("address", address.to_string().into()),
+        ("tls", tls.into()),
+    ])
+    .to_string())
+}
+
+fn stop_grpc(id: u64) -> Result<(), LbError> {
+    let GrpcServer {
+        runtime,
+        shutdown,
+        serving,
+    } = grpc_registry()
+        .lock()
+        .map_err(|_| LbError::internal("gRPC registry poisoned"))?
+        .remove(&id)
+        .ok_or_else(|| LbError::not_found(format!("No gRPC server with handle {}", id)))?;
+    let _ = shutdown.send(());
+    // Graceful shutdown waits for open streams, which a `Track` caller may hold forever.
+    let drained = runtime.block_on(async { tokio::time::timeout(SHUTDOWN_GRACE, serving).await.is_ok() });
+    runtime.shutdown_timeout(Duration::from_secs(1));
+    lb_log!(Level::Info, "grpc", "gRPC server {} stopped{}", id, if drained { "" } else { " (calls cancelled)" });
+    Ok(())
+}
+
+/// Serves `DeviceService`, `CommandService` and `MediaService` from
+/// `proto/lazy_blacktea.proto` on `{bind_addr, auth_token, tls_cert_path?, tls_key_path?}`.
+/// Every call needs `authorization: Bearer <auth_token>`; with both PEM paths the server
+/// speaks TLS only. Returns `{handle, address, tls}` or null. Only built with the `grpc`
+/// cargo feature.
+#[no_mangle]
+pub extern "C" fn lb_grpc_start(options_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        string_result(read_c_str(options_ptr, "options").and_then(start_grpc), "gRPC server info")
+    })
+}
+
+/// Stops a gRPC server, letting in-flight calls finish for up to 5 s before cancelling
+/// them (which kills the adb processes behind cancelled streams).
+#[no_mangle]
+pub extern "C" fn lb_grpc_stop(handle: u64) -> i32 {
+    ffi_guard(0, || status_result(stop_grpc(handle)))
+}
@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+
+use super::UiNode;
+use crate::error::LbError;
+use crate::json::JsonValue;
+
+/// Baseline density at which one dp equals one pixel.
+const BASELINE_DPI: f64 = 160.0;
+const DEFAULT_MIN_TOUCH_TARGET_DP: f64 = 48.0;
+
+#[derive(Debug, Clone)]
+pub struct AuditOptions {
+    pub density_dpi: f64,
+    pub min_touch_target_dp: f64,
+}
+
+impl Default for AuditOptions {
+    fn default() -> Self {
+        AuditOptions {
+            density_dpi: BASELINE_DPI,
+            min_touch_target_dp: DEFAULT_MIN_TOUCH_TARGET_DP,
+        }
+    }
+}
+
+impl AuditOptions {
+    pub fn from_json(value: &JsonValue) -> Result<Self, LbError> {
+        if value.as_object().is_none() {
+            return Err("Audit options must be a JSON object".into());
+        }
+        let mut options = AuditOptions::default();
+        if let Some(density) = value.get("density_dpi") {
+            options.density_dpi = match density.as_u64() {
+                Some(density) if density > 0 => density as f64,
+                _ => return Err("Audit option 'density_dpi' must be a positive integer".into()),
+            };
+        }
+        if let Some(size) = value.get("min_touch_target_dp") {
+            options.min_touch_target_dp = size
+                .as_u64()
+                .ok_or("Audit option 'min_touch_target_dp' must be a non-negative integer")?
+                as f64;
+        }
+        Ok(options)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    fn name(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+struct Issue {
+    severity: Severity,
+    rule: &'static str,
+    message: String,
+    path: String,
+    node: JsonValue,
+}
+
+fn is_true(node: &UiNode, name: &str) -> bool {
+    node.attribute(name) == Some("true")
+}
+
+fn non_empty<'a>(node: &'a UiNode, name: &str) -> Option<&'a str> {
+    node.attribute(name).map(str::trim).filter(|value| !value.is_empty())
+}
+
+/// TalkBack reads text and descriptions of non-actionable descendants as the control's label.
+fn has_label(node: &UiNode) -> bool {
+    non_empty(node, "content-desc").is_some()
+        || non_empty(node, "text").is_some()
+        || node
+            .children
+            .iter()
+            .any(|child| !is_true(child, "clickable") && has_label(child))
+}
+
+fn node_summary(node: &UiNode) -> JsonValue {
+    JsonValue::object(vec![
+        ("class", node.attribute("class").unwrap_or(&node.tag).into()),
+        ("resource_id", node.attribute("resource-id").unwrap_or("").into()),
+        ("bounds", node.attribute("bounds").unwrap_or("").into()),
+    ])
+}
+
+struct Auditor<'a> {
+    options: &'a AuditOptions,
+    issues: Vec<Issue>,
+    descriptions: HashMap<String, Vec<(String, JsonValue)>>,
+}
+
+impl Auditor<'_> {
+    fn push(&mut self, severity: Severity, rule: &'static str, message: String, path: &str, node: &UiNode) {
+        self.issues.push(Issue {
+            severity,
+            rule,
+            message,
+            path: path.to_string(),
+            node: node_summary(node),
+        });
+    }
+
+    fn visit(&mut self, node: &UiNode, path: String) {
+        if node.attribute("visible-to-user") != Some("false") {
+            self.check(node, &path);
+        }
+        for (index, child) in node.children.iter().enumerate() {
+            self.visit(child, format!("{}/{}", path, index));
+        }
+    }
+
+    fn check(&mut self, node: &UiNode, path: &str) {
+        let actionable = is_true(node, "clickable") || is_true(node, "long-clickable");
+        if actionable && !has_label(node) {
+            self.push(
+                Severity::Error,
+                "missing-label",
+                "Clickable node has no content-desc or text for screen readers".into(),
+                path,
+                node,
+            );
+        }
+        if actionable {
+            if let Some(bounds) = node.bounds() {
+                let scale = self.options.density_dpi / BASELINE_DPI;
+                let width_dp = f64::from(bounds.width()) / scale;
+                let height_dp = f64::from(bounds.height()) / scale;
+                let minimum = self.options.min_touch_target_dp;
+                if bounds.width() > 0 && bounds.height() > 0 && (width_dp < minimum || height_dp < minimum) {
+                    self.push(
+                        Severity::Warning,
+                        "small-touch-target",
+                        format!(
+                            "Touch target is {:.0}x{:.0}dp, below the {:.0}dp minimum",
+                            width_dp, height_dp, minimum
+                        ),
+                        path,
+                        node,
+                    );
+                }
+            }
+        }
+        if let Some(description) = non_empty(node, "content-desc") {
+            self.descriptions
+                .entry(description.to_string())
+                .or_default()
+                .push((path.to_string(), node_summary(node)));
+        }
+    }
+
+    fn finish(mut self) -> Vec<Issue> {
+        let mut duplicates: Vec<(String, Vec<(String, JsonValue)>)> = self
+            .descriptions
+            .drain()
+            .filter(|(_, nodes)| nodes.len() > 1)
+            .collect();
+        duplicates.sort_by(|(a, _), (b, _)| a.cmp(b));
+        for (description, nodes) in duplicates {
+            let count = nodes.len();
+            for (path, node) in nodes {
+                self.issues.push(Issue {
+                    severity: Severity::Warning,
+                    rule: "duplicate-description",
+                    message: format!("content-desc \"{}\" is shared by {} nodes", description, count),
+                    path,
+                    node,
+                });
+            }
+        }
+        self.issues
+    }
+}
+
+/// Runs the audit and returns `{issue_count, errors, warnings, issues: [...]}`.
+/// Issue paths are child indexes from the root, e.g. `0/2/1`.
+pub fn accessibility_audit(roots: &[UiNode], options: &AuditOptions) -> JsonValue {
+    let mut auditor = Auditor {
+        options,
+        issues: Vec::new(),
+        descriptions: HashMap::new(),
+    };
+    for (index, root) in roots.iter().enumerate() {
+        auditor.visit(root, index.to_string());
+    }
+    let issues = auditor.finish();
+    let errors = issues.iter().filter(|issue| issue.severity == Severity::Error).count();
+    let issue_count = issues.len();
+    let issues: Vec<JsonValue> = issues
+        .into_iter()
+        .map(|issue| {
+            JsonValue::object(vec![
+                ("severity", issue.severity.name().into()),
+                ("rule", issue.rule.into()),
+                ("message", issue.message.into()),
+                ("path", issue.path.into()),
+                ("node", issue.node),
+            ])
+        })
+        .collect();
+    JsonValue::object(vec![
+        ("issue_count", issue_count.into()),
+        ("errors", errors.into()),
+        ("warnings", (issue_count - errors).into()),
+        ("issues", issues.into()),
+    ])
+}
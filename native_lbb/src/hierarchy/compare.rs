@@ -0,0 +1,282 @@
+use super::UiNode;
+use crate::error::LbError;
+use crate::json::JsonValue;
+
+const DEFAULT_ATTRIBUTES: [&str; 11] = [
+    "class",
+    "package",
+    "resource-id",
+    "text",
+    "content-desc",
+    "bounds",
+    "clickable",
+    "enabled",
+    "checkable",
+    "checked",
+    "scrollable",
+];
+const DEFAULT_MAX_MISMATCHES: usize = 1000;
+
+/// Tolerances that apply to one node; the defaults come from the top level of the rules
+/// and `nodes[]` entries override them for matching golden nodes.
+#[derive(Debug, Clone, Default)]
+struct Tolerance {
+    ignore_text: bool,
+    bounds_tolerance: i32,
+    ignore_attributes: Vec<String>,
+    ignore_children: bool,
+}
+
+impl Tolerance {
+    fn merge_json(&mut self, value: &JsonValue) -> Result<(), LbError> {
+        if let Some(flag) = value.get("ignore_text") {
+            self.ignore_text = flag.as_bool().ok_or("Rule 'ignore_text' must be a boolean")?;
+        }
+        if let Some(pixels) = value.get("bounds_tolerance") {
+            self.bounds_tolerance = pixels
+                .as_u64()
+                .ok_or("Rule 'bounds_tolerance' must be a non-negative integer")?
+                .min(i32::MAX as u64) as i32;
+        }
+        if let Some(names) = value.get("ignore_attributes") {
+            self.ignore_attributes = names
+                .as_string_array()
+                .ok_or("Rule 'ignore_attributes' must be an array of strings")?;
+        }
+        if let Some(flag) = value.get("ignore_children") {
+            self.ignore_children = flag.as_bool().ok_or("Rule 'ignore_children' must be a boolean")?;
+        }
+        Ok(())
+    }
+}
+
+/// Selects golden nodes by child-index `path` (e.g. `0/2/1`) and/or exact attribute values.
+#[derive(Debug, Clone)]
+struct NodeRule {
+    path: Option<String>,
+    attributes: Vec<(String, String)>,
+    tolerance: JsonValue,
+}
+
+impl NodeRule {
+    fn matches(&self, node: &UiNode, path: &str) -> bool {
+        self.path.as_deref().is_none_or(|rule_path| rule_path == path)
+            && self
+                .attributes
+                .iter()
+                .all(|(name, value)| node.attribute(name) == Some(value.as_str()))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CompareRules {
+    attributes: Vec<String>,
+    defaults: Tolerance,
+    nodes: Vec<NodeRule>,
+    max_mismatches: usize,
+}
+
+impl Default for CompareRules {
+    fn default() -> Self {
+        CompareRules {
+            attributes: DEFAULT_ATTRIBUTES.iter().map(|name| name.to_string()).collect(),
+            defaults: Tolerance::default(),
+            nodes: Vec::new(),
+            max_mismatches: DEFAULT_MAX_MISMATCHES,
+        }
+    }
+}
+
+impl CompareRules {
+    pub fn from_json(value: &JsonValue) -> Result<Self, LbError> {
+        if value.as_object().is_none() {
+            return Err("Comparison rules must be a JSON object".into());
+        }
+        let mut rules = CompareRules::default();
+        if let Some(names) = value.get("attributes") {
+            rules.attributes = names
+                .as_string_array()
+                .ok_or("Rule 'attributes' must be an array of strings")?;
+        }
+        if let Some(limit) = value.get("max_mismatches") {
+            rules.max_mismatches = limit
+                .as_u64()
+                .ok_or("Rule 'max_mismatches' must be a non-negative integer")? as usize;
+        }
+        rules.defaults.merge_json(value)?;
+        if let Some(nodes) = value.get("nodes") {
+            for entry in nodes.as_array().ok_or("Rule 'nodes' must be an array")? {
+                let path = match entry.get("path") {
+                    Some(path) => Some(path.as_str().ok_or("Node rule 'path' must be a string")?.to_string()),
+                    None => None,
+                };
+                let attributes = match entry.get("match") {
+                    Some(selector) => selector
+                        .as_object()
+                        .ok_or("Node rule 'match' must be an object")?
+                        .iter()
+                        .map(|(name, value)| {
+                            value
+                                .as_str()
+                                .map(|value| (name.clone(), value.to_string()))
+                                .ok_or_else(|| format!("Node rule match value for '{}' must be a string", name))
+                        })
+                        .collect::<Result<_, _>>()?,
+                    None => Vec::new(),
+                };
+                if path.is_none() && attributes.is_empty() {
+                    return Err("Node rules need a 'path' or a 'match' selector".into());
+                }
+                // Validate eagerly so a bad rule fails the call instead of silently never applying.
+                rules.defaults.clone().merge_json(entry)?;
+                rules.nodes.push(NodeRule {
+                    path,
+                    attributes,
+                    tolerance: entry.clone(),
+                });
+            }
+        }
+        Ok(rules)
+    }
+
+    fn tolerance_for(&self, node: &UiNode, path: &str) -> Tolerance {
+        let mut tolerance = self.defaults.clone();
+        for rule in self.nodes.iter().filter(|rule| rule.matches(node, path)) {
+            // Already validated in `from_json`.
+            let _ = tolerance.merge_json(&rule.tolerance);
+        }
+        tolerance
+    }
+}
+
+struct Comparison<'a> {
+    rules: &'a CompareRules,
+    compared: usize,
+    mismatch_count: usize,
+    mismatches: Vec<JsonValue>,
+}
+
+impl Comparison<'_> {
+    fn report(&mut self, path: &str, kind: &str, detail: Vec<(&str, JsonValue)>) {
+        self.mismatch_count += 1;
+        if self.mismatches.len() < self.rules.max_mismatches {
+            let mut entry = vec![("path", JsonValue::from(path)), ("kind", kind.into())];
+            entry.extend(detail);
+            self.mismatches.push(JsonValue::object(entry));
+        }
+    }
+
+    fn compare_bounds(&mut self, golden: &UiNode, actual: &UiNode, path: &str, tolerance: i32) {
+        let (expected, found) = (golden.attribute("bounds"), actual.attribute("bounds"));
+        let shift = match (golden.bounds(), actual.bounds()) {
+            (Some(a), Some(b)) => [a.left - b.left, a.top - b.top, a.right - b.right, a.bottom - b.bottom]
+                .iter()
+                .map(|delta| delta.abs())
+                .max(),
+            _ => None,
+        };
+        match shift {
+            Some(shift) if shift <= tolerance => {}
+            _ if expected == found => {}
+            shift => self.report(
+                path,
+                "bounds",
+                vec![
+                    ("expected", expected.into()),
+                    ("actual", found.into()),
+                    ("shift_px", shift.into()),
+                    ("tolerance_px", tolerance.into()),
+                ],
+            ),
+        }
+    }
+
+    fn compare(&mut self, golden: &UiNode, actual: &UiNode, path: &str) {
+        self.compared += 1;
+        let tolerance = self.rules.tolerance_for(golden, path);
+        if golden.tag != actual.tag {
+            self.report(
+                path,
+                "tag",
+                vec![("expected", golden.tag.as_str().into()), ("actual", actual.tag.as_str().into())],
+            );
+        }
+        for name in &self.rules.attributes {
+            if tolerance.ignore_attributes.contains(name) || (tolerance.ignore_text && name == "text") {
+                continue;
+            }
+            if name == "bounds" {
+                self.compare_bounds(golden, actual, path, tolerance.bounds_tolerance);
+                continue;
+            }
+            let (expected, found) = (golden.attribute(name), actual.attribute(name));
+            if expected != found {
+                self.report(
+                    path,
+                    "attribute",
+                    vec![
+                        ("attribute", name.as_str().into()),
+                        ("expected", expected.into()),
+                        ("actual", found.into()),
+                    ],
+                );
+            }
+        }
+        if !tolerance.ignore_children {
+            self.compare_children(&golden.children, &actual.children, path);
+        }
+    }
+
+    fn compare_children(&mut self, golden: &[UiNode], actual: &[UiNode], parent_path: &str) {
+        let child_path = |index: usize| {
+            if parent_path.is_empty() {
+                index.to_string()
+            } else {
+                format!("{}/{}", parent_path, index)
+            }
+        };
+        let shared = golden.len().min(actual.len());
+        for index in 0..shared {
+            self.compare(&golden[index], &actual[index], &child_path(index));
+        }
+        for (index, missing) in golden.iter().enumerate().skip(shared) {
+            self.report(
+                &child_path(index),
+                "missing_node",
+                vec![("expected", missing.attribute("class").unwrap_or(&missing.tag).into())],
+            );
+        }
+        for (index, extra) in actual.iter().enumerate().skip(shared) {
+            self.report(
+                &child_path(index),
+                "extra_node",
+                vec![("actual", extra.attribute("class").unwrap_or(&extra.tag).into())],
+            );
+        }
+    }
+}
+
+/// Walks both trees child-by-child and returns `{passed, compared_nodes, mismatch_count,
+/// truncated, mismatches: [{path, kind, ...}]}`.
+pub fn compare_hierarchies(golden: &[UiNode], actual: &[UiNode], rules: &CompareRules) -> JsonValue {
+    let mut comparison = Comparison {
+        rules,
+        compared: 0,
+        mismatch_count: 0,
+        mismatches: Vec::new(),
+    };
+    comparison.compare_children(golden, actual, "");
+    let Comparison {
+        compared,
+        mismatch_count,
+        mismatches,
+        ..
+    } = comparison;
+    JsonValue::object(vec![
+        ("passed", (mismatch_count == 0).into()),
+        ("compared_nodes", compared.into()),
+        ("mismatch_count", mismatch_count.into()),
+        ("truncated", (mismatches.len() < mismatch_count).into()),
+        ("mismatches", mismatches.into()),
+    ])
+}
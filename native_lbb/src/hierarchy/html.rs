@@ -0,0 +1,302 @@
+use std::sync::{Mutex, OnceLock};
+
+use super::schema::Schema;
+use super::{NodeFilter, UiNode};
+use crate::error::LbError;
+use crate::json::JsonValue;
+
+const CSS_SNIPPET: &str = "\
+\t<style>\n\tbody{\n\t\tfont-family: Arial, sans-serif;\n\t\tline-height: 1.6;\n\t\tcolor: #333;\n\t\tbackground-color: #f4f4f4;\n\t\tpadding: 20px;\n\t}\n\t\n\tul {\n\t\tlist-style-type: none;\n\t\tpadding-left:0;\n\t}\n\t\n\tul li {\n\t\tmargin: 5px 0;\n\t\tposition: relative;\n\t\tpadding: 5px;\n\t\tborder: 2px solid #ddd;\n\t\tbackground-color:#fffff;\n\t}\n\t\n\tul li ul {\n\t\tmargin-left: 20px;\n\t\tpadding-left: 20px;\n\t\tborder-left:1.2px dashed #888;\n\t}\n\t\n\tul li:before{\n\t\tcontent: '➡️';\n\t\tposition: absolute;\n\t\tleft:-15px;\n\t\tcolor: #888;\n\t}\n\t\n\t.attributes {\n\t\tcolor: #0000FF;\n\t\tfont-style: italic ;\n\t}\n\t\n\t.text {\n\t\tcolor: #008000;\n\t}\n\t</style>\n\t";
+
+const DARK_CSS: &str = "\
+<style>
+body { font-family: Arial, sans-serif; line-height: 1.6; color: #d4d4d4; background-color: #1e1e1e; padding: 20px; }
+ul { list-style-type: none; padding-left: 0; }
+ul li { margin: 5px 0; position: relative; padding: 5px; border: 2px solid #3c3c3c; background-color: #252526; }
+ul li ul { margin-left: 20px; padding-left: 20px; border-left: 1.2px dashed #6a6a6a; }
+ul li:before { content: '➡️'; position: absolute; left: -15px; color: #6a6a6a; }
+.attributes { color: #9cdcfe; font-style: italic; }
+.text { color: #ce9178; }
+#lb-filter { background-color: #3c3c3c; color: #d4d4d4; border: 1px solid #6a6a6a; }
+.lb-hit > .lb-node, .lb-hit > details > summary { background-color: #614d00 !important; }
+</style>
+";
+
+const HIGH_CONTRAST_CSS: &str = "\
+<style>
+body { font-family: Arial, sans-serif; font-size: 16px; line-height: 1.7; color: #ffffff; background-color: #000000; padding: 20px; }
+ul { list-style-type: none; padding-left: 0; }
+ul li { margin: 6px 0; position: relative; padding: 6px; border: 2px solid #ffffff; background-color: #000000; }
+ul li ul { margin-left: 20px; padding-left: 20px; border-left: 2px solid #ffff00; }
+ul li:before { content: '➡️'; position: absolute; left: -15px; color: #ffff00; }
+.attributes { color: #00ffff; font-weight: bold; }
+.text { color: #ffff00; }
+#lb-filter { background-color: #000000; color: #ffffff; border: 2px solid #ffffff; }
+.lb-hit > .lb-node, .lb-hit > details > summary { background-color: #0000ff !important; }
+</style>
+";
+
+const COLLAPSIBLE_CSS: &str = "\
+<style>\n\
+details > summary { cursor: pointer; }\n\
+#lb-filter { width: 100%; box-sizing: border-box; padding: 6px; margin-bottom: 10px; font-size: 14px; }\n\
+.lb-hit > .lb-node, .lb-hit > details > summary { background-color: #fff3a0; }\n\
+</style>\n";
+
+const FILTER_INPUT: &str = "<input type=\"search\" id=\"lb-filter\" placeholder=\"Filter nodes...\" autocomplete=\"off\">";
+
+const FILTER_SCRIPT: &str = "\
+<script>\n\
+(function(){\n\
+  var input=document.getElementById('lb-filter');\n\
+  if(!input){return;}\n\
+  input.addEventListener('input',function(){\n\
+    var query=input.value.trim().toLowerCase();\n\
+    var items=document.querySelectorAll('.lb-tree li');\n\
+    for(var i=items.length-1;i>=0;i--){\n\
+      var li=items[i];\n\
+      var label=li.querySelector(':scope > .lb-node, :scope > details > summary');\n\
+      var own=!!query&&!!label&&label.textContent.toLowerCase().indexOf(query)!==-1;\n\
+      var childMatch=li.querySelector(':scope > details > ul > li.lb-match');\n\
+      var match=!query||own||!!childMatch;\n\
+      li.classList.toggle('lb-match',match);\n\
+      li.classList.toggle('lb-hit',own);\n\
+      li.style.display=match?'':'none';\n\
+      if(query&&childMatch){\n\
+        var details=li.querySelector(':scope > details');\n\
+        if(details){details.open=true;}\n\
+      }\n\
+    }\n\
+  });\n\
+})();\n\
+</script>\n";
+
+const DEFAULT_PAGE_NODE_LIMIT: usize = 1500;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HtmlTheme {
+    Light,
+    Dark,
+    HighContrast,
+    Custom(String),
+}
+
+impl HtmlTheme {
+    /// Preset names select a built-in theme; anything else is treated as custom CSS.
+    pub fn parse(value: &str) -> HtmlTheme {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "" | "light" | "default" => HtmlTheme::Light,
+            "dark" => HtmlTheme::Dark,
+            "high-contrast" | "high_contrast" => HtmlTheme::HighContrast,
+            _ => HtmlTheme::Custom(value.to_string()),
+        }
+    }
+
+    fn push_css(&self, output: &mut String) {
+        match self {
+            HtmlTheme::Light => output.push_str(CSS_SNIPPET),
+            HtmlTheme::Dark => output.push_str(DARK_CSS),
+            HtmlTheme::HighContrast => output.push_str(HIGH_CONTRAST_CSS),
+            HtmlTheme::Custom(css) => {
+                if css.contains("<style") {
+                    output.push_str(css);
+                } else {
+                    output.push_str("<style>\n");
+                    output.push_str(css);
+                    output.push_str("\n</style>\n");
+                }
+            }
+        }
+    }
+}
+
+static DEFAULT_THEME: OnceLock<Mutex<HtmlTheme>> = OnceLock::new();
+
+fn default_theme_slot() -> &'static Mutex<HtmlTheme> {
+    DEFAULT_THEME.get_or_init(|| Mutex::new(HtmlTheme::Light))
+}
+
+pub fn set_default_theme(theme: HtmlTheme) {
+    if let Ok(mut guard) = default_theme_slot().lock() {
+        *guard = theme;
+    }
+}
+
+fn default_theme() -> HtmlTheme {
+    default_theme_slot()
+        .lock()
+        .map(|guard| guard.clone())
+        .unwrap_or(HtmlTheme::Light)
+}
+
+#[derive(Debug, Clone)]
+pub struct HtmlRenderOptions {
+    pub collapsible: bool,
+    pub expand_depth: usize,
+    pub theme: Option<HtmlTheme>,
+    pub filter: NodeFilter,
+    pub schema: Option<Schema>,
+    /// Single-page renders larger than this fail instead of freezing the embedded browser.
+    pub max_html_bytes: Option<usize>,
+    pub page_node_limit: usize,
+}
+
+impl Default for HtmlRenderOptions {
+    fn default() -> Self {
+        HtmlRenderOptions {
+            collapsible: false,
+            expand_depth: 2,
+            theme: None,
+            filter: NodeFilter::default(),
+            schema: None,
+            max_html_bytes: None,
+            page_node_limit: DEFAULT_PAGE_NODE_LIMIT,
+        }
+    }
+}
+
+impl HtmlRenderOptions {
+    pub fn from_json(value: &JsonValue) -> Result<Self, LbError> {
+        if value.as_object().is_none() {
+            return Err("Render options must be a JSON object".into());
+        }
+        let mut options = HtmlRenderOptions {
+            filter: NodeFilter::from_json(value)?,
+            ..HtmlRenderOptions::default()
+        };
+        if let Some(flag) = value.get("collapsible") {
+            options.collapsible = flag.as_bool().ok_or("Render option 'collapsible' must be a boolean")?;
+        }
+        if let Some(depth) = value.get("expand_depth") {
+            options.expand_depth = depth
+                .as_u64()
+                .ok_or("Render option 'expand_depth' must be a non-negative integer")? as usize;
+        }
+        if let Some(theme) = value.get("theme") {
+            options.theme = Some(HtmlTheme::parse(
+                theme.as_str().ok_or("Render option 'theme' must be a string")?,
+            ));
+        }
+        if let Some(schema) = value.get("schema") {
+            options.schema = Schema::parse(schema.as_str().ok_or("Render option 'schema' must be a string")?)?;
+        }
+        if let Some(limit) = value.get("max_html_bytes") {
+            options.max_html_bytes = Some(
+                limit
+                    .as_u64()
+                    .ok_or("Render option 'max_html_bytes' must be a non-negative integer")? as usize,
+            );
+        }
+        if let Some(limit) = value.get("page_node_limit") {
+            options.page_node_limit = match limit.as_u64() {
+                Some(limit) if limit > 0 => limit as usize,
+                _ => return Err("Render option 'page_node_limit' must be a positive integer".into()),
+            };
+        }
+        Ok(options)
+    }
+}
+
+pub fn escape_html(input: &str) -> String {
+    let mut escaped = String::with_capacity(input.len());
+    for ch in input.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+fn push_label(output: &mut String, node: &UiNode) {
+    output.push_str(&escape_html(&node.tag));
+    if !node.attributes.is_empty() {
+        output.push_str(" [");
+        for (idx, (name, value)) in node.attributes.iter().enumerate() {
+            if idx > 0 {
+                output.push_str(", ");
+            }
+            output.push_str("<span class=\"attributes\">");
+            output.push_str(&escape_html(name));
+            output.push_str("</span>=<span class=\"text\">");
+            output.push('"');
+            output.push_str(&escape_html(value));
+            output.push('"');
+            output.push_str("</span>");
+        }
+        output.push_str("] ");
+    }
+}
+
+fn push_node(output: &mut String, node: &UiNode, depth: usize, options: &HtmlRenderOptions) {
+    output.push_str("<li>");
+    if options.collapsible {
+        if node.children.is_empty() {
+            output.push_str("<span class=\"lb-node\">");
+            push_label(output, node);
+            output.push_str("</span>");
+        } else {
+            output.push_str(if depth < options.expand_depth { "<details open><summary>" } else { "<details><summary>" });
+            push_label(output, node);
+            output.push_str("</summary><ul>");
+            for child in &node.children {
+                push_node(output, child, depth + 1, options);
+            }
+            output.push_str("</ul></details>");
+        }
+    } else {
+        push_label(output, node);
+        if !node.children.is_empty() {
+            output.push_str("<ul>");
+            for child in &node.children {
+                push_node(output, child, depth + 1, options);
+            }
+            output.push_str("</ul>");
+        }
+    }
+    output.push_str("</li>");
+}
+
+pub fn push_theme_css(output: &mut String, options: &HtmlRenderOptions) {
+    match &options.theme {
+        Some(theme) => theme.push_css(output),
+        None => default_theme().push_css(output),
+    }
+}
+
+pub fn render_html(roots: &[UiNode], options: &HtmlRenderOptions, size_hint: usize) -> String {
+    render_html_with_header(roots, options, size_hint, None)
+}
+
+/// Like `render_html`, with raw HTML (e.g. page navigation) placed above the tree.
+pub fn render_html_with_header(
+    roots: &[UiNode],
+    options: &HtmlRenderOptions,
+    size_hint: usize,
+    header: Option<&str>,
+) -> String {
+    let mut output = String::with_capacity(size_hint.saturating_mul(2));
+    push_theme_css(&mut output, options);
+    if let Some(header) = header {
+        output.push_str(header);
+    }
+    if options.collapsible {
+        output.push_str(COLLAPSIBLE_CSS);
+        output.push_str(FILTER_INPUT);
+        output.push_str("<ul class=\"lb-tree\">");
+    } else {
+        output.push_str("<ul>");
+    }
+    for node in roots {
+        push_node(&mut output, node, 0, options);
+    }
+    output.push_str("</ul>");
+    if options.collapsible {
+        output.push_str(FILTER_SCRIPT);
+    }
+    output
+}
@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+
+use super::{too_deep, UiNode, MAX_DEPTH};
+
+/// A decoded protobuf field; only the wire types the layout protocol uses are kept.
+enum WireValue<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+struct Field<'a> {
+    number: u32,
+    value: WireValue<'a>,
+}
+
+fn read_varint(data: &[u8], cursor: &mut usize) -> Result<u64, String> {
+    let mut value: u64 = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = *data.get(*cursor).ok_or("Truncated varint in protobuf input")?;
+        *cursor += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err("Varint too long in protobuf input".into())
+}
+
+fn decode_fields(data: &[u8]) -> Result<Vec<Field<'_>>, String> {
+    let mut fields = Vec::new();
+    let mut cursor = 0;
+    while cursor < data.len() {
+        let key = read_varint(data, &mut cursor)?;
+        let number = (key >> 3) as u32;
+        let value = match key & 0x7 {
+            0 => WireValue::Varint(read_varint(data, &mut cursor)?),
+            1 => {
+                cursor += 8;
+                WireValue::Fixed
+            }
+            2 => {
+                let len = read_varint(data, &mut cursor)? as usize;
+                let end = cursor.checked_add(len).filter(|end| *end <= data.len());
+                let end = end.ok_or("Length-delimited field exceeds protobuf input")?;
+                let bytes = &data[cursor..end];
+                cursor = end;
+                WireValue::Bytes(bytes)
+            }
+            5 => {
+                cursor += 4;
+                WireValue::Fixed
+            }
+            other => return Err(format!("Unsupported protobuf wire type {}", other)),
+        };
+        if cursor > data.len() {
+            return Err("Truncated fixed-width field in protobuf input".into());
+        }
+        fields.push(Field { number, value });
+    }
+    Ok(fields)
+}
+
+fn varint_field(fields: &[Field<'_>], number: u32) -> Option<i64> {
+    fields.iter().rev().find_map(|field| match field.value {
+        WireValue::Varint(value) if field.number == number => Some(value as i64),
+        _ => None,
+    })
+}
+
+fn bytes_field<'a>(fields: &[Field<'a>], number: u32) -> Option<&'a [u8]> {
+    fields.iter().rev().find_map(|field| match field.value {
+        WireValue::Bytes(bytes) if field.number == number => Some(bytes),
+        _ => None,
+    })
+}
+
+// Field numbers follow the view layout inspection protocol:
+//   LayoutEvent { repeated StringEntry strings = 1; Point root_offset = 2; ViewNode root_view = 3; }
+//   StringEntry { int32 id = 1; string str = 2; }
+//   ViewNode    { int64 id = 1; repeated ViewNode children = 2; int32 package_name = 3;
+//                 int32 class_name = 4; Bounds bounds = 5; Resource resource = 6;
+//                 Resource layout_resource = 7; int32 text_value = 8; }
+//   Bounds      { Rect layout = 1; }   Rect { int32 x = 1; y = 2; w = 3; h = 4; }
+//   Resource    { int32 type = 1; int32 namespace = 2; int32 name = 3; }
+mod field {
+    pub const EVENT_STRINGS: u32 = 1;
+    pub const EVENT_ROOT_VIEW: u32 = 3;
+    pub const STRING_ID: u32 = 1;
+    pub const STRING_VALUE: u32 = 2;
+    pub const NODE_ID: u32 = 1;
+    pub const NODE_CHILDREN: u32 = 2;
+    pub const NODE_PACKAGE: u32 = 3;
+    pub const NODE_CLASS: u32 = 4;
+    pub const NODE_BOUNDS: u32 = 5;
+    pub const NODE_RESOURCE: u32 = 6;
+    pub const NODE_TEXT: u32 = 8;
+    pub const BOUNDS_LAYOUT: u32 = 1;
+    pub const RESOURCE_TYPE: u32 = 1;
+    pub const RESOURCE_NAMESPACE: u32 = 2;
+    pub const RESOURCE_NAME: u32 = 3;
+}
+
+struct StringTable(HashMap<i64, String>);
+
+impl StringTable {
+    fn lookup(&self, id: Option<i64>) -> String {
+        id.and_then(|id| self.0.get(&id).cloned()).unwrap_or_default()
+    }
+}
+
+fn resource_name(data: &[u8], strings: &StringTable) -> Result<String, String> {
+    let fields = decode_fields(data)?;
+    let name = strings.lookup(varint_field(&fields, field::RESOURCE_NAME));
+    if name.is_empty() {
+        return Ok(String::new());
+    }
+    Ok(format!(
+        "{}:{}/{}",
+        strings.lookup(varint_field(&fields, field::RESOURCE_NAMESPACE)),
+        strings.lookup(varint_field(&fields, field::RESOURCE_TYPE)),
+        name
+    ))
+}
+
+fn layout_bounds(data: &[u8]) -> Result<Option<String>, String> {
+    let fields = decode_fields(data)?;
+    let Some(rect) = bytes_field(&fields, field::BOUNDS_LAYOUT) else {
+        return Ok(None);
+    };
+    let rect = decode_fields(rect)?;
+    let value = |number| varint_field(&rect, number).unwrap_or(0) as i32;
+    let (x, y, w, h) = (value(1), value(2), value(3), value(4));
+    Ok(Some(format!("[{},{}][{},{}]", x, y, x + w, y + h)))
+}
+
+fn build_node(data: &[u8], index: usize, strings: &StringTable, depth: usize) -> Result<UiNode, String> {
+    // One level below the `hierarchy` root added by `parse_layout_event`.
+    if depth + 1 >= MAX_DEPTH {
+        return Err(too_deep());
+    }
+    let fields = decode_fields(data)?;
+    let package = strings.lookup(varint_field(&fields, field::NODE_PACKAGE));
+    let class_name = strings.lookup(varint_field(&fields, field::NODE_CLASS));
+    let resource_id = match bytes_field(&fields, field::NODE_RESOURCE) {
+        Some(resource) => resource_name(resource, strings)?,
+        None => String::new(),
+    };
+
+    let mut attributes = vec![
+        ("index".to_string(), index.to_string()),
+        ("text".to_string(), strings.lookup(varint_field(&fields, field::NODE_TEXT))),
+        ("resource-id".to_string(), resource_id),
+        ("class".to_string(), class_name),
+        ("package".to_string(), package),
+    ];
+    if let Some(bounds) = bytes_field(&fields, field::NODE_BOUNDS) {
+        if let Some(bounds) = layout_bounds(bounds)? {
+            attributes.push(("bounds".to_string(), bounds));
+        }
+    }
+    if let Some(id) = varint_field(&fields, field::NODE_ID) {
+        attributes.push(("view-id".to_string(), id.to_string()));
+    }
+
+    let mut children = Vec::new();
+    for child in fields
+        .iter()
+        .filter(|field| field.number == field::NODE_CHILDREN)
+    {
+        if let WireValue::Bytes(bytes) = child.value {
+            children.push(build_node(bytes, children.len(), strings, depth + 1)?);
+        }
+    }
+    Ok(UiNode {
+        tag: "node".to_string(),
+        attributes,
+        children,
+    })
+}
+
+/// Decodes a Layout Inspector `LayoutEvent` capture into a uiautomator-style tree.
+pub fn parse_layout_event(data: &[u8]) -> Result<Vec<UiNode>, String> {
+    let fields = decode_fields(data)?;
+    let mut table = HashMap::new();
+    for entry in fields.iter().filter(|field| field.number == field::EVENT_STRINGS) {
+        if let WireValue::Bytes(bytes) = entry.value {
+            let entry_fields = decode_fields(bytes)?;
+            let id = varint_field(&entry_fields, field::STRING_ID).unwrap_or(0);
+            let value = bytes_field(&entry_fields, field::STRING_VALUE)
+                .map(|raw| String::from_utf8_lossy(raw).into_owned())
+                .unwrap_or_default();
+            table.insert(id, value);
+        }
+    }
+    let root = bytes_field(&fields, field::EVENT_ROOT_VIEW).ok_or("Protobuf input has no root view")?;
+    let root = build_node(root, 0, &StringTable(table), 0)?;
+    Ok(vec![UiNode {
+        tag: "hierarchy".to_string(),
+        attributes: vec![("rotation".to_string(), "0".to_string())],
+        children: vec![root],
+    }])
+}
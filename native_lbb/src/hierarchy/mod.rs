@@ -0,0 +1,700 @@
+pub mod audit;
+pub mod compare;
+pub mod html;
+pub mod layout_proto;
+pub mod pages;
+pub mod schema;
+pub mod stats;
+pub mod text;
+pub mod view_dump;
+
+use std::os::raw::c_char;
+
+use crate::error::LbError;
+use crate::json::{self, JsonValue};
+use crate::logging::{lb_log, Level};
+use crate::{buffer_result, ffi_guard, read_c_str, status_result, string_result, LbResult};
+use audit::AuditOptions;
+use compare::CompareRules;
+use html::{HtmlRenderOptions, HtmlTheme};
+use schema::Schema;
+use text::{TextFormat, TextRenderOptions};
+
+/// Nesting limit for every hierarchy parser. Renderers, audits and comparisons recurse once
+/// per level, and a stack overflow aborts the host process instead of unwinding, so deeper
+/// input is a ParseError. Real view trees stay well under 100 levels.
+pub const MAX_DEPTH: usize = 256;
+
+pub fn too_deep() -> String {
+    format!("Hierarchy nests deeper than {} levels", MAX_DEPTH)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct UiNode {
+    pub tag: String,
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<UiNode>,
+}
+
+impl Drop for UiNode {
+    // Flattens the subtree first, so dropping a tree never recurses.
+    fn drop(&mut self) {
+        let mut pending = std::mem::take(&mut self.children);
+        while let Some(mut node) = pending.pop() {
+            pending.append(&mut node.children);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bounds {
+    pub left: i32,
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+}
+
+impl Bounds {
+    /// Parses the uiautomator `[left,top][right,bottom]` format.
+    pub fn parse(value: &str) -> Option<Bounds> {
+        let numbers: Vec<i32> = value
+            .split(['[', ']', ','])
+            .filter(|part| !part.trim().is_empty())
+            .map(|part| part.trim().parse::<i32>())
+            .collect::<Result<_, _>>()
+            .ok()?;
+        match numbers.as_slice() {
+            [left, top, right, bottom] => Some(Bounds {
+                left: *left,
+                top: *top,
+                right: *right,
+                bottom: *bottom,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn width(&self) -> i32 {
+        self.right - self.left
+    }
+
+    pub fn height(&self) -> i32 {
+        self.bottom - self.top
+    }
+}
+
+impl UiNode {
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn bounds(&self) -> Option<Bounds> {
+        self.attribute("bounds").and_then(Bounds::parse)
+    }
+
+    pub fn subtree_size(&self) -> usize {
+        1 + self.children.iter().map(UiNode::subtree_size).sum::<usize>()
+    }
+
+    /// `{path, tag, bounds, attributes, children}`; `path` is the child-index path (`0/2/1`)
+    /// used by audits and comparisons, `bounds` is `[left, top, right, bottom]` or null.
+    pub fn to_json(&self, path: &str) -> JsonValue {
+        let bounds = self.bounds().map(|bounds| {
+            JsonValue::from(vec![bounds.left.into(), bounds.top.into(), bounds.right.into(), bounds.bottom.into()])
+        });
+        let attributes = self
+            .attributes
+            .iter()
+            .map(|(name, value)| (name.clone(), JsonValue::from(value)))
+            .collect();
+        let children: Vec<JsonValue> = self
+            .children
+            .iter()
+            .enumerate()
+            .map(|(index, child)| child.to_json(&format!("{}/{}", path, index)))
+            .collect();
+        JsonValue::object(vec![
+            ("path", path.into()),
+            ("tag", self.tag.as_str().into()),
+            ("bounds", bounds.into()),
+            ("attributes", JsonValue::Object(attributes)),
+            ("children", children.into()),
+        ])
+    }
+}
+
+/// Tree pruning shared by every renderer: attribute whitelist, depth cap, and
+/// removal of zero-sized or invisible subtrees.
+#[derive(Debug, Clone, Default)]
+pub struct NodeFilter {
+    pub attributes: Option<Vec<String>>,
+    pub max_depth: Option<usize>,
+    pub skip_zero_size: bool,
+    pub skip_invisible: bool,
+}
+
+impl NodeFilter {
+    pub fn from_json(value: &JsonValue) -> Result<Self, LbError> {
+        let mut filter = NodeFilter::default();
+        if let Some(list) = value.get("attributes") {
+            filter.attributes = Some(
+                list.as_string_array()
+                    .ok_or("Render option 'attributes' must be an array of strings")?,
+            );
+        }
+        if let Some(depth) = value.get("max_depth") {
+            filter.max_depth = Some(
+                depth
+                    .as_u64()
+                    .ok_or("Render option 'max_depth' must be a non-negative integer")? as usize,
+            );
+        }
+        if let Some(flag) = value.get("skip_zero_size") {
+            filter.skip_zero_size = flag.as_bool().ok_or("Render option 'skip_zero_size' must be a boolean")?;
+        }
+        if let Some(flag) = value.get("skip_invisible") {
+            filter.skip_invisible = flag.as_bool().ok_or("Render option 'skip_invisible' must be a boolean")?;
+        }
+        Ok(filter)
+    }
+
+    pub fn is_noop(&self) -> bool {
+        self.attributes.is_none() && self.max_depth.is_none() && !self.skip_zero_size && !self.skip_invisible
+    }
+
+    fn keeps(&self, node: &UiNode) -> bool {
+        if self.skip_invisible && node.attribute("visible-to-user") == Some("false") {
+            return false;
+        }
+        if self.skip_zero_size {
+            if let Some(bounds) = node.bounds() {
+                if bounds.width() <= 0 || bounds.height() <= 0 {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    fn prune(&self, mut node: UiNode, depth: usize) -> Option<UiNode> {
+        if !self.keeps(&node) {
+            return None;
+        }
+        if let Some(allowed) = &self.attributes {
+            node.attributes.retain(|(name, _)| allowed.iter().any(|allowed_name| allowed_name == name));
+        }
+        let children = std::mem::take(&mut node.children);
+        if self.max_depth.is_none_or(|max_depth| depth < max_depth) {
+            node.children = children
+                .into_iter()
+                .filter_map(|child| self.prune(child, depth + 1))
+                .collect();
+        }
+        Some(node)
+    }
+
+    pub fn apply(&self, roots: Vec<UiNode>) -> Vec<UiNode> {
+        if self.is_noop() {
+            return roots;
+        }
+        roots.into_iter().filter_map(|root| self.prune(root, 0)).collect()
+    }
+}
+
+fn attach(stack: &mut [UiNode], roots: &mut Vec<UiNode>, node: UiNode) {
+    match stack.last_mut() {
+        Some(parent) => parent.children.push(node),
+        None => roots.push(node),
+    }
+}
+
+pub fn parse_xml(xml: &str) -> Result<Vec<UiNode>, LbError> {
+    let bytes = xml.as_bytes();
+    let mut index: usize = 0;
+    let mut stack: Vec<UiNode> = Vec::new();
+    let mut roots: Vec<UiNode> = Vec::new();
+
+    while index < bytes.len() {
+        match bytes[index] {
+            b'<' => {
+                if index + 1 >= bytes.len() {
+                    break;
+                }
+                match bytes[index + 1] {
+                    b'/' => {
+                        index += 2;
+                        while index < bytes.len() && bytes[index] != b'>' {
+                            index += 1;
+                        }
+                        if index < bytes.len() {
+                            index += 1;
+                        }
+                        if let Some(node) = stack.pop() {
+                            attach(&mut stack, &mut roots, node);
+                        }
+                    }
+                    b'!' => {
+                        index += 2;
+                        while index + 2 < bytes.len()
+                            && !(bytes[index] == b'-' && bytes[index + 1] == b'-' && bytes[index + 2] == b'>')
+                        {
+                            index += 1;
+                        }
+                        index = (index + 3).min(bytes.len());
+                    }
+                    b'?' => {
+                        index += 2;
+                        while index + 1 < bytes.len() && !(bytes[index] == b'?' && bytes[index + 1] == b'>') {
+                            index += 1;
+                        }
+                        index = (index + 2).min(bytes.len());
+                    }
+                    _ => {
+                        let start = index + 1;
+                        let mut cursor = start;
+                        while cursor < bytes.len() {
+                            let ch = bytes[cursor];
+                            if ch == b'/' || ch == b'>' || ch.is_ascii_whitespace() {
+                                break;
+                            }
+                            cursor += 1;
+                        }
+                        let tag_name = &xml[start..cursor];
+                        let mut attrs: Vec<(String, String)> = Vec::new();
+                        let mut self_closing = false;
+                        let mut attr_cursor = cursor;
+                        while attr_cursor < bytes.len() {
+                            while attr_cursor < bytes.len() && bytes[attr_cursor].is_ascii_whitespace() {
+                                attr_cursor += 1;
+                            }
+                            if attr_cursor >= bytes.len() {
+                                break;
+                            }
+                            let ch = bytes[attr_cursor];
+                            if ch == b'>' {
+                                attr_cursor += 1;
+                                break;
+                            }
+                            if ch == b'/' {
+                                self_closing = true;
+                                attr_cursor += 1;
+                                if attr_cursor < bytes.len() && bytes[attr_cursor] == b'>' {
+                                    attr_cursor += 1;
+                                }
+                                break;
+                            }
+
+                            let name_start = attr_cursor;
+                            while attr_cursor < bytes.len()
+                                && bytes[attr_cursor] != b'='
+                                && !bytes[attr_cursor].is_ascii_whitespace()
+                            {
+                                attr_cursor += 1;
+                            }
+                            if attr_cursor >= bytes.len() {
+                                return Err("Malformed attribute".into());
+                            }
+                            let name_end = attr_cursor;
+                            while attr_cursor < bytes.len() && bytes[attr_cursor].is_ascii_whitespace() {
+                                attr_cursor += 1;
+                            }
+                            if attr_cursor >= bytes.len() || bytes[attr_cursor] != b'=' {
+                                return Err("Malformed attribute assignment".into());
+                            }
+                            attr_cursor += 1;
+                            while attr_cursor < bytes.len() && bytes[attr_cursor].is_ascii_whitespace() {
+                                attr_cursor += 1;
+                            }
+                            if attr_cursor >= bytes.len() {
+                                return Err("Missing attribute value".into());
+                            }
+                            let quote = bytes[attr_cursor];
+                            if quote != b'"' && quote != b'\'' {
+                                return Err("Attribute value must be quoted".into());
+                            }
+                            attr_cursor += 1;
+                            let value_start = attr_cursor;
+                            while attr_cursor < bytes.len() && bytes[attr_cursor] != quote {
+                                attr_cursor += 1;
+                            }
+                            if attr_cursor >= bytes.len() {
+                                return Err("Unterminated attribute value".into());
+                            }
+                            let value_end = attr_cursor;
+                            attr_cursor += 1;
+
+                            let name = xml[name_start..name_end].trim();
+                            let value = &xml[value_start..value_end];
+                            attrs.push((name.to_string(), value.to_string()));
+                        }
+                        index = attr_cursor;
+
+                        if stack.len() >= MAX_DEPTH {
+                            return Err(LbError::parse(too_deep()));
+                        }
+                        let node = UiNode {
+                            tag: tag_name.to_string(),
+                            attributes: attrs,
+                            children: Vec::new(),
+                        };
+                        if self_closing {
+                            attach(&mut stack, &mut roots, node);
+                        } else {
+                            stack.push(node);
+                        }
+                    }
+                }
+            }
+            _ => {
+                index += 1;
+            }
+        }
+    }
+
+    while let Some(node) = stack.pop() {
+        attach(&mut stack, &mut roots, node);
+    }
+
+    Ok(roots)
+}
+
+/// Parses a page source and normalizes it to the uiautomator attribute vocabulary.
+/// `schema` of `None` auto-detects uiautomator, Appium Android, XCUITest, or
+/// `dumpsys activity top` sources.
+pub fn load(source: &str, schema: Option<Schema>) -> Result<(Vec<UiNode>, Schema), LbError> {
+    let is_view_dump = match schema {
+        Some(Schema::ViewDump) => true,
+        Some(Schema::LayoutInspector) => {
+            return Err("Layout Inspector captures are binary; convert them with lb_view_hierarchy_to_xml".into())
+        }
+        Some(_) => false,
+        None => !source.trim_start().starts_with('<') && view_dump::looks_like_view_dump(source),
+    };
+    if is_view_dump {
+        return Ok((view_dump::parse_view_dump(source).map_err(LbError::parse)?, Schema::ViewDump));
+    }
+    let mut roots = parse_xml(source)?;
+    let schema = schema.unwrap_or_else(|| schema::detect(&roots));
+    schema::normalize(&mut roots, schema);
+    lb_log!(
+        Level::Debug,
+        "hierarchy",
+        "Parsed {} bytes as {} ({} nodes)",
+        source.len(),
+        schema.name(),
+        roots.iter().map(UiNode::subtree_size).sum::<usize>()
+    );
+    Ok((roots, schema))
+}
+
+fn escape_xml_attribute(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for ch in value.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\n' => escaped.push_str("&#10;"),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+fn push_xml(output: &mut String, node: &UiNode, depth: usize) {
+    output.push_str(&"  ".repeat(depth));
+    output.push('<');
+    output.push_str(&node.tag);
+    for (name, value) in &node.attributes {
+        output.push(' ');
+        output.push_str(name);
+        output.push_str("=\"");
+        output.push_str(&escape_xml_attribute(value));
+        output.push('"');
+    }
+    if node.children.is_empty() {
+        output.push_str(" />\n");
+        return;
+    }
+    output.push_str(">\n");
+    for child in &node.children {
+        push_xml(output, child, depth + 1);
+    }
+    output.push_str(&"  ".repeat(depth));
+    output.push_str("</");
+    output.push_str(&node.tag);
+    output.push_str(">\n");
+}
+
+/// Serializes a tree as uiautomator-style XML so every XML entry point accepts it.
+pub fn to_xml(roots: &[UiNode]) -> String {
+    let mut output = String::from("<?xml version='1.0' encoding='UTF-8' standalone='yes' ?>\n");
+    for root in roots {
+        push_xml(&mut output, root, 0);
+    }
+    output
+}
+
+/// Loads raw capture bytes: UTF-8 page sources and view dumps go through `load`,
+/// anything else is decoded as a Layout Inspector protobuf.
+pub fn load_bytes(data: &[u8]) -> Result<(Vec<UiNode>, Schema), LbError> {
+    match std::str::from_utf8(data) {
+        Ok(source) if source.trim_start().starts_with('<') || view_dump::looks_like_view_dump(source) => {
+            load(source, None)
+        }
+        _ => Ok((layout_proto::parse_layout_event(data).map_err(LbError::parse)?, Schema::LayoutInspector)),
+    }
+}
+
+fn render_device_ui_html(xml: &str, options: &HtmlRenderOptions) -> Result<String, LbError> {
+    let (roots, _) = load(xml, options.schema)?;
+    let roots = options.filter.apply(roots);
+    let html = html::render_html(&roots, options, xml.len());
+    match options.max_html_bytes {
+        Some(limit) if html.len() > limit => Err(format!(
+            "HTML output is {} bytes, above max_html_bytes {}; render it paginated instead",
+            html.len(),
+            limit
+        )
+        .into()),
+        _ => Ok(html),
+    }
+}
+
+fn html_options(options_ptr: *const c_char) -> Result<HtmlRenderOptions, LbError> {
+    if options_ptr.is_null() {
+        return Ok(HtmlRenderOptions::default());
+    }
+    read_c_str(options_ptr, "render options")
+        .and_then(json::parse)
+        .and_then(|value| HtmlRenderOptions::from_json(&value))
+}
+
+fn render_html_export(xml_ptr: *const c_char, options_ptr: *const c_char) -> Result<String, LbError> {
+    let options = html_options(options_ptr)?;
+    read_c_str(xml_ptr, "XML input").and_then(|xml| render_device_ui_html(xml, &options))
+}
+
+#[no_mangle]
+pub extern "C" fn lb_render_device_ui_html(xml_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        string_result(render_html_export(xml_ptr, std::ptr::null()), "HTML output")
+    })
+}
+
+/// Renders with a JSON options object, e.g. `{"collapsible": true, "expand_depth": 2}`.
+/// A null options pointer selects the defaults used by `lb_render_device_ui_html`.
+#[no_mangle]
+pub extern "C" fn lb_render_device_ui_html_with_options(
+    xml_ptr: *const c_char,
+    options_ptr: *const c_char,
+) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        string_result(render_html_export(xml_ptr, options_ptr), "HTML output")
+    })
+}
+
+/// `lb_result` variant of `lb_render_device_ui_html_with_options`; options may be null.
+#[no_mangle]
+pub extern "C" fn lb_render_device_ui_html_buf(xml_ptr: *const c_char, options_ptr: *const c_char) -> LbResult {
+    ffi_guard(LbResult::empty(), || {
+        buffer_result(render_html_export(xml_ptr, options_ptr))
+    })
+}
+
+fn render_device_ui_html_pages(xml: &str, options: &HtmlRenderOptions, output_dir: &str) -> Result<String, LbError> {
+    let (roots, _) = load(xml, options.schema)?;
+    let roots = options.filter.apply(roots);
+    pages::write_pages(&roots, options, std::path::Path::new(output_dir)).map(|index| index.to_string())
+}
+
+/// Splits the hierarchy into pages of at most `page_node_limit` nodes (consecutive siblings
+/// share a page under their parent; subtrees too big for one are split further), writes `index.html` plus `page-NNN.html` into
+/// `output_dir`, and returns JSON describing the files.
+#[no_mangle]
+pub extern "C" fn lb_render_device_ui_html_paged(
+    xml_ptr: *const c_char,
+    options_ptr: *const c_char,
+    output_dir_ptr: *const c_char,
+) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = html_options(options_ptr).and_then(|options| {
+            read_c_str(xml_ptr, "XML input").and_then(|xml| {
+                read_c_str(output_dir_ptr, "output directory")
+                    .and_then(|output_dir| render_device_ui_html_pages(xml, &options, output_dir))
+            })
+        });
+        string_result(result, "page index")
+    })
+}
+
+/// Returns the detected page-source schema: `uiautomator`, `appium-android`, or `xcuitest`.
+#[no_mangle]
+pub extern "C" fn lb_detect_hierarchy_schema(xml_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(xml_ptr, "XML input")
+            .and_then(parse_xml)
+            .map(|roots| schema::detect(&roots).name().to_string());
+        string_result(result, "schema name")
+    })
+}
+
+/// Returns node/depth/class counts and duplicate resource-id warnings as JSON.
+#[no_mangle]
+pub extern "C" fn lb_ui_hierarchy_stats(xml_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(xml_ptr, "XML input")
+            .and_then(|xml| load(xml, None))
+            .map(|(roots, _)| stats::hierarchy_stats(&roots).to_string());
+        string_result(result, "hierarchy stats")
+    })
+}
+
+fn accessibility_audit(xml: &str, options: &AuditOptions) -> Result<String, LbError> {
+    let (roots, _) = load(xml, None)?;
+    Ok(audit::accessibility_audit(&roots, options).to_string())
+}
+
+/// Flags unlabeled clickable nodes, small touch targets (at 160 dpi), and duplicate
+/// content descriptions; returns the JSON report.
+#[no_mangle]
+pub extern "C" fn lb_accessibility_audit(xml_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(xml_ptr, "XML input").and_then(|xml| accessibility_audit(xml, &AuditOptions::default()));
+        string_result(result, "accessibility report")
+    })
+}
+
+/// `options_json` accepts `density_dpi` (from `wm density`) and `min_touch_target_dp`.
+#[no_mangle]
+pub extern "C" fn lb_accessibility_audit_with_options(
+    xml_ptr: *const c_char,
+    options_ptr: *const c_char,
+) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(options_ptr, "audit options")
+            .and_then(json::parse)
+            .and_then(|value| AuditOptions::from_json(&value))
+            .and_then(|options| read_c_str(xml_ptr, "XML input").and_then(|xml| accessibility_audit(xml, &options)));
+        string_result(result, "accessibility report")
+    })
+}
+
+fn assert_ui_matches(golden_xml: &str, actual_xml: &str, rules: &CompareRules) -> Result<String, LbError> {
+    let (golden, _) = load(golden_xml, None).map_err(|err| format!("Golden hierarchy: {}", err))?;
+    let (actual, _) = load(actual_xml, None).map_err(|err| format!("Actual hierarchy: {}", err))?;
+    Ok(compare::compare_hierarchies(&golden, &actual, rules).to_string())
+}
+
+/// Compares a captured hierarchy against a golden one. `rules_json` may be null; see
+/// `CompareRules::from_json` for `attributes`, `ignore_text`, `bounds_tolerance`, and per-node `nodes[]`.
+#[no_mangle]
+pub extern "C" fn lb_assert_ui_matches(
+    golden_ptr: *const c_char,
+    actual_ptr: *const c_char,
+    rules_ptr: *const c_char,
+) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let rules = if rules_ptr.is_null() {
+            Ok(CompareRules::default())
+        } else {
+            read_c_str(rules_ptr, "comparison rules")
+                .and_then(json::parse)
+                .and_then(|value| CompareRules::from_json(&value))
+        };
+        let result = rules.and_then(|rules| {
+            read_c_str(golden_ptr, "golden XML").and_then(|golden| {
+                read_c_str(actual_ptr, "actual XML").and_then(|actual| assert_ui_matches(golden, actual, &rules))
+            })
+        });
+        string_result(result, "comparison report")
+    })
+}
+
+/// Sets the theme used by every HTML renderer when no per-call `theme` option is given:
+/// `light`, `dark`, `high-contrast`, or a custom CSS string. Null restores `light`.
+#[no_mangle]
+pub extern "C" fn lb_set_html_theme(theme_ptr: *const c_char) -> i32 {
+    ffi_guard(0, || {
+        if theme_ptr.is_null() {
+            html::set_default_theme(HtmlTheme::Light);
+            return status_result(Ok(()));
+        }
+        let result = read_c_str(theme_ptr, "theme").map(|theme| html::set_default_theme(HtmlTheme::parse(theme)));
+        status_result(result)
+    })
+}
+
+fn render_device_ui_text(xml: &str, options: &TextRenderOptions) -> Result<String, LbError> {
+    let (roots, _) = load(xml, options.schema)?;
+    Ok(text::render_text(&options.filter.apply(roots), options))
+}
+
+/// Renders the hierarchy as an indented plain-text tree (`text`) or nested Markdown list (`markdown`).
+#[no_mangle]
+pub extern "C" fn lb_render_device_ui_text(xml_ptr: *const c_char, format_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(format_ptr, "format")
+            .and_then(TextFormat::parse)
+            .and_then(|format| {
+                read_c_str(xml_ptr, "XML input")
+                    .and_then(|xml| render_device_ui_text(xml, &TextRenderOptions::new(format)))
+            });
+        string_result(result, "text output")
+    })
+}
+
+fn render_text_export(xml_ptr: *const c_char, options_ptr: *const c_char) -> Result<String, LbError> {
+    read_c_str(options_ptr, "render options")
+        .and_then(json::parse)
+        .and_then(|value| TextRenderOptions::from_json(&value))
+        .and_then(|options| read_c_str(xml_ptr, "XML input").and_then(|xml| render_device_ui_text(xml, &options)))
+}
+
+/// Text rendering with a JSON options object: `format`, `verbose`, `schema`, and the filter keys.
+#[no_mangle]
+pub extern "C" fn lb_render_device_ui_text_with_options(
+    xml_ptr: *const c_char,
+    options_ptr: *const c_char,
+) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        string_result(render_text_export(xml_ptr, options_ptr), "text output")
+    })
+}
+
+/// `lb_result` variant of `lb_render_device_ui_text_with_options`.
+#[no_mangle]
+pub extern "C" fn lb_render_device_ui_text_buf(xml_ptr: *const c_char, options_ptr: *const c_char) -> LbResult {
+    ffi_guard(LbResult::empty(), || {
+        buffer_result(render_text_export(xml_ptr, options_ptr))
+    })
+}
+
+fn view_hierarchy_to_xml(data_ptr: *const u8, len: usize) -> Result<String, LbError> {
+    if data_ptr.is_null() {
+        return Err("Null pointer received for hierarchy data".into());
+    }
+    let data = unsafe { std::slice::from_raw_parts(data_ptr, len) };
+    load_bytes(data).map(|(roots, _)| to_xml(&roots))
+}
+
+/// Converts a Layout Inspector protobuf capture, a `dumpsys activity top` dump, or any
+/// supported XML page source into normalized uiautomator XML.
+#[no_mangle]
+pub extern "C" fn lb_view_hierarchy_to_xml(data_ptr: *const u8, len: usize) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        string_result(view_hierarchy_to_xml(data_ptr, len), "XML output")
+    })
+}
+
+/// `lb_result` variant of `lb_view_hierarchy_to_xml`; protobuf strings may carry NUL bytes.
+#[no_mangle]
+pub extern "C" fn lb_view_hierarchy_to_xml_buf(data_ptr: *const u8, len: usize) -> LbResult {
+    ffi_guard(LbResult::empty(), || {
+        buffer_result(view_hierarchy_to_xml(data_ptr, len))
+    })
+}
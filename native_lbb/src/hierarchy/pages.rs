@@ -0,0 +1,191 @@
+use std::fs;
+use std::path::Path;
+
+use super::html::{self, escape_html, HtmlRenderOptions};
+use super::UiNode;
+use crate::error::LbError;
+use crate::json::JsonValue;
+
+const INDEX_FILE: &str = "index.html";
+
+struct PageSource<'a> {
+    title: String,
+    /// Set when the page is a run of this node's children; it is rendered as their root.
+    parent: Option<&'a UiNode>,
+    nodes: &'a [UiNode],
+}
+
+impl PageSource<'_> {
+    fn node_count(&self) -> usize {
+        usize::from(self.parent.is_some()) + self.nodes.iter().map(UiNode::subtree_size).sum::<usize>()
+    }
+
+    fn render(&self, options: &HtmlRenderOptions, header: &str) -> String {
+        match self.parent {
+            Some(parent) => {
+                let root = UiNode {
+                    tag: parent.tag.clone(),
+                    attributes: parent.attributes.clone(),
+                    children: self.nodes.to_vec(),
+                };
+                html::render_html_with_header(std::slice::from_ref(&root), options, 0, Some(header))
+            }
+            None => html::render_html_with_header(self.nodes, options, 0, Some(header)),
+        }
+    }
+}
+
+fn short_name(node: &UiNode) -> &str {
+    let class = node.attribute("class").filter(|class| !class.is_empty()).unwrap_or(&node.tag);
+    class.rsplit('.').next().unwrap_or(class)
+}
+
+fn child_title(title: &str, child: &UiNode, index: usize) -> String {
+    if title.is_empty() {
+        format!("{}[{}]", short_name(child), index)
+    } else {
+        format!("{} › {}[{}]", title, short_name(child), index)
+    }
+}
+
+/// Emits `node` as one page when it fits in `limit` nodes, otherwise packs its children.
+fn split<'a>(node: &'a UiNode, title: String, limit: usize, pages: &mut Vec<PageSource<'a>>) {
+    if node.children.is_empty() || node.subtree_size() <= limit {
+        pages.push(PageSource {
+            title,
+            parent: None,
+            nodes: std::slice::from_ref(node),
+        });
+        return;
+    }
+    pack(Some(node), &node.children, &title, limit, pages);
+}
+
+/// Packs consecutive `nodes` into pages of at most `limit` nodes, counting `parent`, which
+/// heads every page as its root. A node too big to share a page is split on its own.
+fn pack<'a>(
+    parent: Option<&'a UiNode>,
+    nodes: &'a [UiNode],
+    title: &str,
+    limit: usize,
+    pages: &mut Vec<PageSource<'a>>,
+) {
+    let root_size = usize::from(parent.is_some());
+    let flush = |start: usize, end: usize, pages: &mut Vec<PageSource<'a>>| {
+        if start == end {
+            return;
+        }
+        let mut run_title = child_title(title, &nodes[start], start);
+        if end - start > 1 {
+            run_title.push_str(&format!(" … {}[{}]", short_name(&nodes[end - 1]), end - 1));
+        }
+        pages.push(PageSource {
+            title: run_title,
+            parent,
+            nodes: &nodes[start..end],
+        });
+    };
+    let mut start = 0;
+    let mut size = root_size;
+    for (index, node) in nodes.iter().enumerate() {
+        let node_size = node.subtree_size();
+        if root_size + node_size > limit {
+            flush(start, index, pages);
+            split(node, child_title(title, node, index), limit, pages);
+            start = index + 1;
+            size = root_size;
+            continue;
+        }
+        if size + node_size > limit {
+            flush(start, index, pages);
+            start = index;
+            size = root_size;
+        }
+        size += node_size;
+    }
+    flush(start, nodes.len(), pages);
+}
+
+fn page_sources(roots: &[UiNode], limit: usize) -> Vec<PageSource<'_>> {
+    // The uiautomator `hierarchy` wrapper carries no useful content, so pages start at its children.
+    let top_level = match roots {
+        [root] if !root.children.is_empty() => &root.children[..],
+        _ => roots,
+    };
+    let mut pages = Vec::new();
+    pack(None, top_level, "", limit, &mut pages);
+    pages
+}
+
+fn page_file(index: usize) -> String {
+    format!("page-{:03}.html", index + 1)
+}
+
+fn page_header(title: &str, index: usize, total: usize) -> String {
+    let mut header = format!("<nav><a href=\"{}\">Index</a>", INDEX_FILE);
+    if index > 0 {
+        header.push_str(&format!(" | <a href=\"{}\">Previous</a>", page_file(index - 1)));
+    }
+    if index + 1 < total {
+        header.push_str(&format!(" | <a href=\"{}\">Next</a>", page_file(index + 1)));
+    }
+    header.push_str(&format!(
+        " | Page {} of {}</nav><h3>{}</h3>",
+        index + 1,
+        total,
+        escape_html(title)
+    ));
+    header
+}
+
+fn render_index(pages: &[(String, String, usize)], options: &HtmlRenderOptions) -> String {
+    let mut output = String::new();
+    html::push_theme_css(&mut output, options);
+    output.push_str("<h3>UI hierarchy pages</h3><ol>");
+    for (file, title, node_count) in pages {
+        output.push_str(&format!(
+            "<li><a href=\"{}\">{}</a> ({} nodes)</li>",
+            file,
+            escape_html(title),
+            node_count
+        ));
+    }
+    output.push_str("</ol>");
+    output
+}
+
+/// Renders the tree as HTML pages of at most `options.page_node_limit` nodes each, packing
+/// consecutive siblings under their parent, plus an `index.html` linking them, and returns `{"index": path, "pages": [{path, title, node_count}]}`.
+pub fn write_pages(roots: &[UiNode], options: &HtmlRenderOptions, output_dir: &Path) -> Result<JsonValue, LbError> {
+    fs::create_dir_all(output_dir)
+        .map_err(|err| LbError::io(format!("Failed to create output directory {}: {}", output_dir.display(), err)))?;
+    let sources = page_sources(roots, options.page_node_limit);
+    let mut written = Vec::with_capacity(sources.len());
+    for (index, source) in sources.iter().enumerate() {
+        let file = page_file(index);
+        let node_count = source.node_count();
+        let header = page_header(&source.title, index, sources.len());
+        let page = source.render(options, &header);
+        let path = output_dir.join(&file);
+        fs::write(&path, page).map_err(|err| LbError::io(format!("Failed to write {}: {}", path.display(), err)))?;
+        written.push((file, source.title.clone(), node_count));
+    }
+    let index_path = output_dir.join(INDEX_FILE);
+    fs::write(&index_path, render_index(&written, options))
+        .map_err(|err| LbError::io(format!("Failed to write {}: {}", index_path.display(), err)))?;
+
+    let pages: Vec<JsonValue> = written
+        .into_iter()
+        .map(|(file, title, node_count)| {
+            JsonValue::object(vec![
+                ("path", output_dir.join(file).to_string_lossy().into_owned().into()),
+                ("title", title.into()),
+                ("node_count", node_count.into()),
+            ])
+        })
+        .collect();
+    Ok(JsonValue::object(vec![
+        ("index", index_path.to_string_lossy().into_owned().into()),
+        ("pages", pages.into()),
+    ]))
+}
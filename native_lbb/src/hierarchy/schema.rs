@@ -0,0 +1,127 @@
+use super::UiNode;
+use crate::error::LbError;
+
+const DETECTION_NODE_LIMIT: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Schema {
+    /// `uiautomator dump`: `<hierarchy><node class=... bounds=...>`.
+    UiAutomator,
+    /// Appium UiAutomator2 page source: element tags are widget class names.
+    AppiumAndroid,
+    /// Appium XCUITest page source: `XCUIElementType*` tags with x/y/width/height.
+    XcuiTest,
+    /// `dumpsys activity top` text view hierarchy.
+    ViewDump,
+    /// Binary Layout Inspector `LayoutEvent` capture.
+    LayoutInspector,
+}
+
+impl Schema {
+    pub fn parse(name: &str) -> Result<Option<Schema>, LbError> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "" | "auto" => Ok(None),
+            "uiautomator" => Ok(Some(Schema::UiAutomator)),
+            "appium-android" | "appium_android" => Ok(Some(Schema::AppiumAndroid)),
+            "xcuitest" | "appium-ios" | "ios" => Ok(Some(Schema::XcuiTest)),
+            "dumpsys-activity-top" | "view-dump" => Ok(Some(Schema::ViewDump)),
+            "layout-inspector" | "protobuf" => Ok(Some(Schema::LayoutInspector)),
+            other => Err(format!("Unknown hierarchy schema: {}", other).into()),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Schema::UiAutomator => "uiautomator",
+            Schema::AppiumAndroid => "appium-android",
+            Schema::XcuiTest => "xcuitest",
+            Schema::ViewDump => "dumpsys-activity-top",
+            Schema::LayoutInspector => "layout-inspector",
+        }
+    }
+}
+
+fn collect_tags<'a>(nodes: &'a [UiNode], tags: &mut Vec<&'a str>) {
+    for node in nodes {
+        if tags.len() >= DETECTION_NODE_LIMIT {
+            return;
+        }
+        tags.push(&node.tag);
+        collect_tags(&node.children, tags);
+    }
+}
+
+pub fn detect(roots: &[UiNode]) -> Schema {
+    let mut tags = Vec::new();
+    collect_tags(roots, &mut tags);
+    if tags
+        .iter()
+        .any(|tag| *tag == "AppiumAUT" || tag.starts_with("XCUIElementType"))
+    {
+        return Schema::XcuiTest;
+    }
+    if tags.contains(&"node") {
+        return Schema::UiAutomator;
+    }
+    if tags.iter().any(|tag| tag.contains('.')) {
+        return Schema::AppiumAndroid;
+    }
+    Schema::UiAutomator
+}
+
+fn set_default(node: &mut UiNode, name: &str, value: String) {
+    if node.attribute(name).is_none() {
+        node.attributes.push((name.to_string(), value));
+    }
+}
+
+fn normalize_xcuitest(node: &mut UiNode) {
+    let class = node.attribute("type").unwrap_or(&node.tag).to_string();
+    set_default(node, "class", class);
+    let frame: Option<Vec<i32>> = ["x", "y", "width", "height"]
+        .iter()
+        .map(|name| node.attribute(name).and_then(|value| value.trim().parse::<f64>().ok()).map(|v| v.round() as i32))
+        .collect();
+    if let Some(frame) = frame {
+        let bounds = format!("[{},{}][{},{}]", frame[0], frame[1], frame[0] + frame[2], frame[1] + frame[3]);
+        set_default(node, "bounds", bounds);
+    }
+    if let Some(visible) = node.attribute("visible").map(str::to_string) {
+        set_default(node, "visible-to-user", visible);
+    }
+    if let Some(label) = node.attribute("label").or_else(|| node.attribute("name")).map(str::to_string) {
+        set_default(node, "content-desc", label);
+    }
+    if let Some(value) = node.attribute("value").map(str::to_string) {
+        set_default(node, "text", value);
+    }
+    if let Some(accessible) = node.attribute("accessible").map(str::to_string) {
+        set_default(node, "focusable", accessible);
+    }
+    for child in &mut node.children {
+        normalize_xcuitest(child);
+    }
+}
+
+fn normalize_appium_android(node: &mut UiNode) {
+    if node.tag.contains('.') {
+        let class = node.tag.clone();
+        set_default(node, "class", class);
+    }
+    if let Some(displayed) = node.attribute("displayed").map(str::to_string) {
+        set_default(node, "visible-to-user", displayed);
+    }
+    for child in &mut node.children {
+        normalize_appium_android(child);
+    }
+}
+
+/// Fills in the uiautomator attribute names (`class`, `bounds`, `visible-to-user`, ...)
+/// so filters and analyses work on any schema. Original attributes are kept.
+pub fn normalize(roots: &mut [UiNode], schema: Schema) {
+    match schema {
+        Schema::UiAutomator | Schema::ViewDump | Schema::LayoutInspector => {}
+        Schema::AppiumAndroid => roots.iter_mut().for_each(normalize_appium_android),
+        Schema::XcuiTest => roots.iter_mut().for_each(normalize_xcuitest),
+    }
+}
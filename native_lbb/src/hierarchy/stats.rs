@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+
+use super::UiNode;
+use crate::json::JsonValue;
+
+#[derive(Default)]
+struct Totals {
+    node_count: usize,
+    max_depth: usize,
+    clickable: usize,
+    focusable: usize,
+    classes: HashMap<String, usize>,
+    resource_ids: HashMap<String, usize>,
+}
+
+fn visit(node: &UiNode, depth: usize, totals: &mut Totals) {
+    totals.node_count += 1;
+    totals.max_depth = totals.max_depth.max(depth);
+    if node.attribute("clickable") == Some("true") {
+        totals.clickable += 1;
+    }
+    if node.attribute("focusable") == Some("true") {
+        totals.focusable += 1;
+    }
+    let class = node.attribute("class").filter(|class| !class.is_empty()).unwrap_or(&node.tag);
+    *totals.classes.entry(class.to_string()).or_default() += 1;
+    if let Some(resource_id) = node.attribute("resource-id").filter(|id| !id.is_empty()) {
+        *totals.resource_ids.entry(resource_id.to_string()).or_default() += 1;
+    }
+    for child in &node.children {
+        visit(child, depth + 1, totals);
+    }
+}
+
+/// Most frequent first, ties broken by name so reports diff cleanly between dumps.
+fn sorted_counts(counts: HashMap<String, usize>) -> Vec<(String, usize)> {
+    let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+    counts.sort_by(|(name_a, count_a), (name_b, count_b)| count_b.cmp(count_a).then_with(|| name_a.cmp(name_b)));
+    counts
+}
+
+/// Complexity summary of a hierarchy; depth is counted from the root element (0).
+pub fn hierarchy_stats(roots: &[UiNode]) -> JsonValue {
+    let mut totals = Totals::default();
+    for root in roots {
+        visit(root, 0, &mut totals);
+    }
+    let classes = sorted_counts(totals.classes)
+        .into_iter()
+        .map(|(class, count)| (class, JsonValue::from(count)))
+        .collect();
+    let duplicates: Vec<JsonValue> = sorted_counts(totals.resource_ids)
+        .into_iter()
+        .filter(|(_, count)| *count > 1)
+        .map(|(resource_id, count)| {
+            JsonValue::object(vec![
+                ("resource_id", resource_id.into()),
+                ("count", count.into()),
+                (
+                    "warning",
+                    format!("resource-id is shared by {} nodes; selectors using it are ambiguous", count).into(),
+                ),
+            ])
+        })
+        .collect();
+    JsonValue::object(vec![
+        ("node_count", totals.node_count.into()),
+        ("max_depth", totals.max_depth.into()),
+        ("clickable_count", totals.clickable.into()),
+        ("focusable_count", totals.focusable.into()),
+        ("class_counts", JsonValue::Object(classes)),
+        ("duplicate_resource_ids", duplicates.into()),
+    ])
+}
@@ -0,0 +1,135 @@
+use super::schema::Schema;
+use super::{NodeFilter, UiNode};
+use crate::error::LbError;
+use crate::json::JsonValue;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextFormat {
+    Plain,
+    Markdown,
+}
+
+impl TextFormat {
+    pub fn parse(name: &str) -> Result<TextFormat, LbError> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "" | "text" | "plain" => Ok(TextFormat::Plain),
+            "markdown" | "md" => Ok(TextFormat::Markdown),
+            other => Err(format!("Unknown text format: {}", other).into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TextRenderOptions {
+    pub format: TextFormat,
+    /// Keeps empty and `"false"` attributes, which are omitted by default to keep trees pasteable.
+    pub verbose: bool,
+    pub filter: NodeFilter,
+    pub schema: Option<Schema>,
+}
+
+impl TextRenderOptions {
+    pub fn new(format: TextFormat) -> Self {
+        TextRenderOptions {
+            format,
+            verbose: false,
+            filter: NodeFilter::default(),
+            schema: None,
+        }
+    }
+
+    pub fn from_json(value: &JsonValue) -> Result<Self, LbError> {
+        if value.as_object().is_none() {
+            return Err("Render options must be a JSON object".into());
+        }
+        let format = match value.get("format") {
+            Some(format) => TextFormat::parse(format.as_str().ok_or("Render option 'format' must be a string")?)?,
+            None => TextFormat::Plain,
+        };
+        let mut options = TextRenderOptions {
+            filter: NodeFilter::from_json(value)?,
+            ..TextRenderOptions::new(format)
+        };
+        if let Some(flag) = value.get("verbose") {
+            options.verbose = flag.as_bool().ok_or("Render option 'verbose' must be a boolean")?;
+        }
+        if let Some(schema) = value.get("schema") {
+            options.schema = Schema::parse(schema.as_str().ok_or("Render option 'schema' must be a string")?)?;
+        }
+        Ok(options)
+    }
+}
+
+fn visible_attributes(node: &UiNode, verbose: bool) -> impl Iterator<Item = &(String, String)> {
+    node.attributes
+        .iter()
+        .filter(move |(_, value)| verbose || !(value.is_empty() || value == "false"))
+}
+
+fn plain_label(node: &UiNode, verbose: bool) -> String {
+    let attrs: Vec<String> = visible_attributes(node, verbose)
+        .map(|(name, value)| format!("{}=\"{}\"", name, value.replace('\n', "\\n")))
+        .collect();
+    if attrs.is_empty() {
+        node.tag.clone()
+    } else {
+        format!("{} [{}]", node.tag, attrs.join(", "))
+    }
+}
+
+fn markdown_code(value: &str) -> String {
+    let value = value.replace('\n', " ");
+    if value.contains('`') {
+        format!("`` {} ``", value)
+    } else {
+        format!("`{}`", value)
+    }
+}
+
+fn markdown_label(node: &UiNode, verbose: bool) -> String {
+    let mut label = format!("**{}**", markdown_code(&node.tag));
+    for (name, value) in visible_attributes(node, verbose) {
+        label.push(' ');
+        label.push_str(name);
+        label.push('=');
+        label.push_str(&markdown_code(value));
+    }
+    label
+}
+
+fn push_plain(output: &mut String, node: &UiNode, prefix: &str, is_last: bool, is_root: bool, verbose: bool) {
+    let child_prefix = if is_root {
+        output.push_str(&plain_label(node, verbose));
+        String::new()
+    } else {
+        output.push_str(prefix);
+        output.push_str(if is_last { "└── " } else { "├── " });
+        output.push_str(&plain_label(node, verbose));
+        format!("{}{}", prefix, if is_last { "    " } else { "│   " })
+    };
+    output.push('\n');
+    for (idx, child) in node.children.iter().enumerate() {
+        push_plain(output, child, &child_prefix, idx + 1 == node.children.len(), false, verbose);
+    }
+}
+
+fn push_markdown(output: &mut String, node: &UiNode, depth: usize, verbose: bool) {
+    output.push_str(&"  ".repeat(depth));
+    output.push_str("- ");
+    output.push_str(&markdown_label(node, verbose));
+    output.push('\n');
+    for child in &node.children {
+        push_markdown(output, child, depth + 1, verbose);
+    }
+}
+
+pub fn render_text(roots: &[UiNode], options: &TextRenderOptions) -> String {
+    let mut output = String::new();
+    for root in roots {
+        match options.format {
+            TextFormat::Plain => push_plain(&mut output, root, "", true, true, options.verbose),
+            TextFormat::Markdown => push_markdown(&mut output, root, 0, options.verbose),
+        }
+    }
+    output
+}
@@ -0,0 +1,171 @@
+use super::{too_deep, UiNode, MAX_DEPTH};
+
+const HIERARCHY_MARKER: &str = "View Hierarchy:";
+
+/// One `View.toString()` line from `dumpsys activity top`, e.g.
+/// `android.widget.TextView{c1d2e3 V.ED..C.. ........ 42,100-300,160 #7f0a0123 app:id/title}`.
+struct ViewLine {
+    class_name: String,
+    view_flags: Vec<char>,
+    private_flags: Vec<char>,
+    frame: Option<(i32, i32, i32, i32)>,
+    resource_name: Option<String>,
+}
+
+fn parse_frame(value: &str) -> Option<(i32, i32, i32, i32)> {
+    let (start, end) = value.split_once('-')?;
+    let (left, top) = start.split_once(',')?;
+    let (right, bottom) = end.split_once(',')?;
+    Some((
+        left.parse().ok()?,
+        top.parse().ok()?,
+        right.parse().ok()?,
+        bottom.parse().ok()?,
+    ))
+}
+
+fn parse_view_line(line: &str) -> ViewLine {
+    let Some((class_name, rest)) = line.split_once('{') else {
+        // Window roots print as `DecorView@1a2b3c[MainActivity]`.
+        let class_name = line.split('@').next().unwrap_or(line).to_string();
+        return ViewLine {
+            class_name,
+            view_flags: Vec::new(),
+            private_flags: Vec::new(),
+            frame: None,
+            resource_name: None,
+        };
+    };
+    let fields: Vec<&str> = rest.trim_end_matches('}').split_whitespace().collect();
+    ViewLine {
+        class_name: class_name.to_string(),
+        view_flags: fields.get(1).map(|flags| flags.chars().collect()).unwrap_or_default(),
+        private_flags: fields.get(2).map(|flags| flags.chars().collect()).unwrap_or_default(),
+        frame: fields.get(3).and_then(|frame| parse_frame(frame)),
+        resource_name: fields.get(5).map(|name| name.to_string()),
+    }
+}
+
+fn flag(flags: &[char], position: usize, expected: char) -> &'static str {
+    if flags.get(position) == Some(&expected) {
+        "true"
+    } else {
+        "false"
+    }
+}
+
+fn build_node(line: &ViewLine, index: usize, origin: (i32, i32)) -> (UiNode, (i32, i32)) {
+    let mut attributes = vec![
+        ("index".to_string(), index.to_string()),
+        ("resource-id".to_string(), line.resource_name.clone().unwrap_or_default()),
+        ("class".to_string(), line.class_name.clone()),
+    ];
+    let mut absolute_origin = origin;
+    if !line.view_flags.is_empty() {
+        let flags = &line.view_flags;
+        let scrollable = flags.get(4) == Some(&'H') || flags.get(5) == Some(&'V');
+        attributes.extend([
+            ("clickable".to_string(), flag(flags, 6, 'C').to_string()),
+            ("enabled".to_string(), flag(flags, 2, 'E').to_string()),
+            ("focusable".to_string(), flag(flags, 1, 'F').to_string()),
+            ("focused".to_string(), flag(&line.private_flags, 1, 'F').to_string()),
+            ("scrollable".to_string(), scrollable.to_string()),
+            ("long-clickable".to_string(), flag(flags, 7, 'L').to_string()),
+            ("selected".to_string(), flag(&line.private_flags, 2, 'S').to_string()),
+            ("visible-to-user".to_string(), flag(flags, 0, 'V').to_string()),
+        ]);
+    }
+    if let Some((left, top, right, bottom)) = line.frame {
+        // Frames are relative to the parent; scroll offsets are not part of the dump.
+        let (origin_x, origin_y) = origin;
+        absolute_origin = (origin_x + left, origin_y + top);
+        attributes.push((
+            "bounds".to_string(),
+            format!(
+                "[{},{}][{},{}]",
+                origin_x + left,
+                origin_y + top,
+                origin_x + right,
+                origin_y + bottom
+            ),
+        ));
+    }
+    let node = UiNode {
+        tag: "node".to_string(),
+        attributes,
+        children: Vec::new(),
+    };
+    (node, absolute_origin)
+}
+
+fn indentation(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+/// Collapses a stack of open nodes down to `depth` entries, attaching each popped node to its parent.
+fn close_to(stack: &mut Vec<(UiNode, usize, (i32, i32))>, depth: usize, window: &mut Vec<UiNode>) {
+    while stack.len() > depth {
+        let (node, _, _) = stack.pop().expect("stack is non-empty");
+        match stack.last_mut() {
+            Some((parent, _, _)) => parent.children.push(node),
+            None => window.push(node),
+        }
+    }
+}
+
+pub fn looks_like_view_dump(source: &str) -> bool {
+    source.contains(HIERARCHY_MARKER)
+}
+
+/// Parses every `View Hierarchy:` section of `dumpsys activity top` into a single
+/// uiautomator-style `hierarchy` root, one child per window.
+pub fn parse_view_dump(source: &str) -> Result<Vec<UiNode>, String> {
+    let lines: Vec<&str> = source.lines().collect();
+    let mut windows: Vec<UiNode> = Vec::new();
+    let mut cursor = 0;
+    while cursor < lines.len() {
+        if lines[cursor].trim() != HIERARCHY_MARKER {
+            cursor += 1;
+            continue;
+        }
+        let section_indent = indentation(lines[cursor]);
+        cursor += 1;
+        let mut stack: Vec<(UiNode, usize, (i32, i32))> = Vec::new();
+        let mut section: Vec<UiNode> = Vec::new();
+        while cursor < lines.len() {
+            let line = lines[cursor];
+            if line.trim().is_empty() {
+                cursor += 1;
+                continue;
+            }
+            let indent = indentation(line);
+            if indent <= section_indent {
+                break;
+            }
+            let depth = stack.iter().take_while(|(_, open_indent, _)| *open_indent < indent).count();
+            close_to(&mut stack, depth, &mut section);
+            // Counting the `hierarchy` root every window is attached to.
+            if stack.len() + 1 >= MAX_DEPTH {
+                return Err(too_deep());
+            }
+            let origin = stack.last().map(|(_, _, origin)| *origin).unwrap_or((0, 0));
+            let index = match stack.last() {
+                Some((parent, _, _)) => parent.children.len(),
+                None => section.len(),
+            };
+            let (node, node_origin) = build_node(&parse_view_line(line.trim()), index, origin);
+            stack.push((node, indent, node_origin));
+            cursor += 1;
+        }
+        close_to(&mut stack, 0, &mut section);
+        windows.extend(section);
+    }
+    if windows.is_empty() {
+        return Err("No view hierarchy found in dumpsys output".into());
+    }
+    Ok(vec![UiNode {
+        tag: "hierarchy".to_string(),
+        attributes: vec![("rotation".to_string(), "0".to_string())],
+        children: windows,
+    }])
+}
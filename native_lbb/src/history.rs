@@ -0,0 +1,463 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::os::raw::c_char;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use crate::error::LbError;
+use crate::json::{self, JsonValue};
+use crate::logging::{lb_log, Level};
+use crate::serial_lock::target_serial;
+use crate::{ffi_guard, now_millis, read_c_str, status_result, string_result};
+
+const DEFAULT_FILE_NAME: &str = ".lazy_blacktea_history.jsonl";
+/// Per stream; enough for an error message or the head of a listing.
+const MAX_OUTPUT_BYTES: usize = 2048;
+/// Past this the file moves to `<path>.1` (replacing the previous one) and a new one starts.
+const MAX_HISTORY_BYTES: u64 = 16 * 1024 * 1024;
+const DEFAULT_QUERY_LIMIT: u64 = 100;
+const REDACTED: &str = "[redacted]";
+const COMMAND_ENDS: [char; 4] = [';', '&', '|', ')'];
+/// Commands whose argument after the marker is secret (clipboard text).
+const SECRET_ARGUMENT_MARKERS: [&str; 2] = ["set-primary-clip", "clipper.set"];
+/// Commands whose output is secret (the clipboard contents).
+const SECRET_OUTPUT_MARKERS: [&str; 2] = ["get-primary-clip", "clipper.get"];
+
+/// Off until the host opts in: entries carry full command lines and output.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Default)]
+struct HistoryStore {
+    /// Set by `lb_set_history_path`; `None` means `~/.lazy_blacktea_history.jsonl`.
+    path_override: Option<PathBuf>,
+    /// The open file and its size, reopened when the path changes.
+    writer: Option<(PathBuf, File, u64)>,
+    /// A failed write is logged once, not once per command.
+    warned: bool,
+}
+
+static HISTORY: OnceLock<Mutex<HistoryStore>> = OnceLock::new();
+
+fn history_store() -> &'static Mutex<HistoryStore> {
+    HISTORY.get_or_init(|| Mutex::new(HistoryStore::default()))
+}
+
+fn history_path(store: &HistoryStore) -> Result<PathBuf, LbError> {
+    if let Some(path) = &store.path_override {
+        return Ok(path.clone());
+    }
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(|home| PathBuf::from(home).join(DEFAULT_FILE_NAME))
+        .ok_or_else(|| LbError::not_found("No home directory; call lb_set_history_path first"))
+}
+
+fn rotated_path(path: &Path) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(".1");
+    PathBuf::from(rotated)
+}
+
+/// The start of `text`, cut on a character boundary, and whether anything was cut.
+fn clip(text: &str) -> (&str, bool) {
+    if text.len() <= MAX_OUTPUT_BYTES {
+        return (text, false);
+    }
+    let mut cut = MAX_OUTPUT_BYTES;
+    while !text.is_char_boundary(cut) {
+        cut -= 1;
+    }
+    (&text[..cut], true)
+}
+
+fn append_line(store: &mut HistoryStore, line: &str) -> Result<(), LbError> {
+    let path = history_path(store)?;
+    if store.writer.as_ref().is_none_or(|(open, _, _)| *open != path) {
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)
+                .map_err(|err| LbError::io(format!("Failed to create {}: {}", parent.display(), err)))?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&path)
+            .map_err(|err| LbError::io(format!("Failed to open {}: {}", path.display(), err)))?;
+        let mut size = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+        // A line left unfinished by a crash would otherwise swallow the next entry.
+        let mut last = [0u8];
+        let read_last = file.seek(SeekFrom::End(-1)).and_then(|_| file.read_exact(&mut last));
+        if size > 0 && read_last.is_ok() && last[0] != b'\n' {
+            file.write_all(b"\n")
+                .map_err(|err| LbError::io(format!("Failed to write {}: {}", path.display(), err)))?;
+            size += 1;
+        }
+        store.writer = Some((path.clone(), file, size));
+    }
+    let Some((_, file, size)) = store.writer.as_mut() else {
+        return Ok(());
+    };
+    // One write per line so concurrent processes appending to the same file do not interleave.
+    file.write_all(format!("{}\n", line).as_bytes())
+        .map_err(|err| LbError::io(format!("Failed to write {}: {}", path.display(), err)))?;
+    *size += line.len() as u64 + 1;
+    if *size >= MAX_HISTORY_BYTES {
+        store.writer = None;
+        fs::rename(&path, rotated_path(&path))
+            .map_err(|err| LbError::io(format!("Failed to rotate {}: {}", path.display(), err)))?;
+    }
+    Ok(())
+}
+
+/// `settings put <namespace> <key> <value>` -> `... <key> [redacted]`, for every `put` in a
+/// shell command line (also inside `$(...)`). A quoted value may span several tokens.
+fn redact_settings_values(command: &str) -> Option<String> {
+    let tokens: Vec<&str> = command.split_whitespace().collect();
+    let mut kept = Vec::with_capacity(tokens.len());
+    let mut hidden = false;
+    let mut index = 0;
+    while index < tokens.len() {
+        let is_put = tokens[index].ends_with("settings") && tokens.get(index + 1) == Some(&"put");
+        if !is_put || index + 4 >= tokens.len() {
+            kept.push(tokens[index]);
+            index += 1;
+            continue;
+        }
+        kept.extend_from_slice(&tokens[index..index + 4]);
+        kept.push(REDACTED);
+        let mut value = index + 4;
+        if let Some(quote) = tokens[value].chars().next().filter(|ch| matches!(ch, '\'' | '"')) {
+            let closes = |token: &str| token.len() > 1 && token.trim_end_matches(COMMAND_ENDS).ends_with(quote);
+            while !closes(tokens[value]) && value + 1 < tokens.len() {
+                value += 1;
+            }
+        }
+        // Keep what ends the command (`;`, `&&`, `)`) so the line still reads right.
+        let last = tokens[value];
+        let end = &last[last.trim_end_matches(COMMAND_ENDS).len()..];
+        if !end.is_empty() {
+            kept.push(end);
+        }
+        hidden = true;
+        index = value + 1;
+    }
+    hidden.then(|| kept.join(" "))
+}
+
+/// Hides what history must not keep: the pairing code of `adb pair`, clipboard text and
+/// `settings put` values. Returns whether the command's output must be dropped as well.
+fn redact(argv: &mut [String]) -> bool {
+    let mut hide_output = false;
+    if let Some(pair) = argv.iter().position(|arg| arg == "pair") {
+        if let Some(code) = argv.get_mut(pair + 2) {
+            *code = REDACTED.to_string();
+        }
+        hide_output = true;
+    }
+    for arg in argv.iter_mut() {
+        for marker in SECRET_ARGUMENT_MARKERS {
+            if let Some(start) = arg.find(marker) {
+                *arg = format!("{} {}", &arg[..start + marker.len()], REDACTED);
+                hide_output = true;
+            }
+        }
+        if SECRET_OUTPUT_MARKERS.iter().any(|marker| arg.contains(marker)) {
+            hide_output = true;
+        }
+        if let Some(redacted) = redact_settings_values(arg) {
+            *arg = redacted;
+            hide_output = true;
+        }
+    }
+    hide_output
+}
+
+/// `argv` as every sink may show it (history, transcripts, log lines), and whether the
+/// command's output must be dropped. Callers redact once and hand the copy to each sink.
+pub fn redacted(argv: &[String]) -> (Vec<String>, bool) {
+    let mut argv = argv.to_vec();
+    let hide_output = redact(&mut argv);
+    (argv, hide_output)
+}
+
+/// Appends one executed command to the history file when recording is on. `argv` comes
+/// from `redacted`, and `output` is `(stdout, stderr)` when the caller captured it and
+/// `redacted` did not ask to hide it; each is kept up to 2 KiB.
+pub fn record(argv: &[String], duration: Duration, exit_code: Option<i32>, output: Option<(&str, &str)>) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let duration_ms = duration.as_millis() as u64;
+    let (stdout, stdout_cut) = output.map_or((None, false), |(stdout, _)| {
+        let (stdout, cut) = clip(stdout);
+        (Some(stdout), cut)
+    });
+    let (stderr, stderr_cut) = output.map_or((None, false), |(_, stderr)| {
+        let (stderr, cut) = clip(stderr);
+        (Some(stderr), cut)
+    });
+    let line = JsonValue::object(vec![
+        ("started_at_ms", now_millis().saturating_sub(duration_ms).into()),
+        ("serial", target_serial(argv).into()),
+        ("argv", argv.iter().map(JsonValue::from).collect::<Vec<_>>().into()),
+        ("duration_ms", duration_ms.into()),
+        ("exit_code", exit_code.into()),
+        ("stdout", stdout.into()),
+        ("stderr", stderr.into()),
+        ("truncated", (stdout_cut || stderr_cut).into()),
+    ])
+    .to_string();
+    let Ok(mut store) = history_store().lock() else {
+        return;
+    };
+    match append_line(&mut store, &line) {
+        Ok(()) => store.warned = false,
+        Err(err) if !store.warned => {
+            store.warned = true;
+            lb_log!(Level::Warn, "exec", "Command history not recorded: {}", err.message);
+        }
+        Err(_) => {}
+    }
+}
+
+struct HistoryFilter {
+    serial: Option<String>,
+    contains: Option<String>,
+    since_ms: Option<u64>,
+    until_ms: Option<u64>,
+    exit_code: Option<i64>,
+    failed: Option<bool>,
+    limit: u64,
+}
+
+fn parse_filter(filter_json: Option<&str>) -> Result<HistoryFilter, LbError> {
+    let filter = json::parse(filter_json.unwrap_or("{}"))?;
+    if filter.as_object().is_none() {
+        return Err("History filter must be a JSON object".into());
+    }
+    let text = |name: &str| match filter.get(name) {
+        None | Some(JsonValue::Null) => Ok(None),
+        Some(value) => value
+            .as_str()
+            .map(|value| Some(value.to_string()))
+            .ok_or_else(|| LbError::from(format!("History filter '{}' must be a string", name))),
+    };
+    let number = |name: &str| match filter.get(name) {
+        None | Some(JsonValue::Null) => Ok(None),
+        Some(value) => value
+            .as_u64()
+            .map(Some)
+            .ok_or_else(|| LbError::from(format!("History filter '{}' must be a non-negative integer", name))),
+    };
+    let exit_code = match filter.get("exit_code") {
+        None | Some(JsonValue::Null) => None,
+        Some(value) => Some(
+            value
+                .as_f64()
+                .filter(|code| code.fract() == 0.0)
+                .ok_or("History filter 'exit_code' must be an integer")? as i64,
+        ),
+    };
+    let failed = match filter.get("failed") {
+        None | Some(JsonValue::Null) => None,
+        Some(value) => Some(value.as_bool().ok_or("History filter 'failed' must be a boolean")?),
+    };
+    Ok(HistoryFilter {
+        serial: text("serial")?,
+        contains: text("contains")?,
+        since_ms: number("since_ms")?,
+        until_ms: number("until_ms")?,
+        exit_code,
+        failed,
+        limit: number("limit")?.unwrap_or(DEFAULT_QUERY_LIMIT).max(1),
+    })
+}
+
+impl HistoryFilter {
+    fn matches(&self, entry: &JsonValue) -> bool {
+        let started = entry.get("started_at_ms").and_then(JsonValue::as_u64).unwrap_or(0);
+        let exit_code = entry.get("exit_code").and_then(JsonValue::as_f64).map(|code| code as i64);
+        let command = || {
+            entry
+                .get("argv")
+                .and_then(JsonValue::as_string_array)
+                .unwrap_or_default()
+                .join(" ")
+        };
+        self.serial
+            .as_deref()
+            .is_none_or(|serial| entry.get("serial").and_then(JsonValue::as_str) == Some(serial))
+            && self.since_ms.is_none_or(|since| started >= since)
+            && self.until_ms.is_none_or(|until| started < until)
+            && self.exit_code.is_none_or(|code| exit_code == Some(code))
+            && self.failed.is_none_or(|failed| failed == (exit_code != Some(0)))
+            && self.contains.as_deref().is_none_or(|text| command().contains(text))
+    }
+}
+
+fn read_lines(path: &Path) -> Result<String, LbError> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(contents),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(String::new()),
+        Err(err) => Err(LbError::io(format!("Failed to read {}: {}", path.display(), err))),
+    }
+}
+
+fn query_history(filter_json: Option<&str>) -> Result<String, LbError> {
+    let filter = parse_filter(filter_json)?;
+    let path = {
+        let store = history_store()
+            .lock()
+            .map_err(|_| LbError::internal("History store poisoned"))?;
+        history_path(&store)?
+    };
+    let older = read_lines(&rotated_path(&path))?;
+    let current = read_lines(&path)?;
+    // Newest first; a line cut short by a crash mid-write is skipped.
+    let matching = current
+        .lines()
+        .rev()
+        .chain(older.lines().rev())
+        .filter_map(|line| json::parse(line).ok())
+        .filter(|entry| filter.matches(entry));
+    let mut entries = Vec::new();
+    let mut matched = 0u64;
+    for entry in matching {
+        matched += 1;
+        if matched <= filter.limit {
+            entries.push(entry);
+        }
+    }
+    Ok(JsonValue::object(vec![
+        ("path", path.to_string_lossy().as_ref().into()),
+        ("matched", matched.into()),
+        ("entries", entries.into()),
+    ])
+    .to_string())
+}
+
+fn clear_history() -> Result<(), LbError> {
+    let mut store = history_store()
+        .lock()
+        .map_err(|_| LbError::internal("History store poisoned"))?;
+    store.writer = None;
+    let path = history_path(&store)?;
+    for path in [rotated_path(&path), path] {
+        match fs::remove_file(&path) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(LbError::io(format!("Failed to remove {}: {}", path.display(), err))),
+        }
+    }
+    Ok(())
+}
+
+/// Searches the command history (newest first) with `filter_json` (may be null):
+/// `{serial, contains, since_ms, until_ms, exit_code, failed, limit}`, all optional.
+/// `contains` matches the space-joined argv; `until_ms` is exclusive; `failed` selects
+/// non-zero or missing exit codes; `limit` defaults to 100. Returns `{path, matched,
+/// entries: [{started_at_ms, serial, argv, duration_ms, exit_code, stdout, stderr,
+/// truncated}]}`, where `matched` counts past the limit and stdout/stderr are null for
+/// commands whose output was streamed rather than captured.
+#[no_mangle]
+pub extern "C" fn lb_history_query(filter_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let filter = if filter_ptr.is_null() {
+            Ok(None)
+        } else {
+            read_c_str(filter_ptr, "history filter").map(Some)
+        };
+        string_result(filter.and_then(query_history), "history query")
+    })
+}
+
+/// Records history in `path` instead of `~/.lazy_blacktea_history.jsonl`; null restores
+/// the default.
+#[no_mangle]
+pub extern "C" fn lb_set_history_path(path_ptr: *const c_char) -> i32 {
+    ffi_guard(0, || {
+        let result = if path_ptr.is_null() {
+            Ok(None)
+        } else {
+            read_c_str(path_ptr, "path").map(|path| Some(PathBuf::from(path)))
+        };
+        let result = result.and_then(|path| {
+            history_store()
+                .lock()
+                .map_err(|_| LbError::internal("History store poisoned"))?
+                .path_override = path;
+            Ok(())
+        });
+        status_result(result)
+    })
+}
+
+/// Turns command history recording on or off (the default); existing history is kept.
+/// Recorded commands keep their full argv and up to 2 KiB of output, minus pairing codes,
+/// clipboard text and `settings put` values.
+#[no_mangle]
+pub extern "C" fn lb_set_history_enabled(enabled: i32) {
+    ffi_guard((), || ENABLED.store(enabled != 0, Ordering::Relaxed))
+}
+
+/// Deletes the history file and its rotated predecessor.
+#[no_mangle]
+pub extern "C" fn lb_history_clear() -> i32 {
+    ffi_guard(0, || status_result(clear_history()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redacted(argv: &[&str]) -> (Vec<String>, bool) {
+        super::redacted(&argv.iter().map(|arg| arg.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn pairing_code() {
+        let (argv, hide_output) = redacted(&["adb", "pair", "192.168.1.5:37000", "123456"]);
+        assert_eq!(argv, ["adb", "pair", "192.168.1.5:37000", REDACTED]);
+        assert!(hide_output);
+    }
+
+    #[test]
+    fn clipboard_text() {
+        let (argv, hide_output) =
+            redacted(&["adb", "-s", "emu-1", "shell", "cmd clipboard set-primary-clip 'my password'"]);
+        assert_eq!(argv[4], "cmd clipboard set-primary-clip [redacted]");
+        assert!(hide_output);
+        let (argv, hide_output) =
+            redacted(&["adb", "-s", "emu-1", "shell", "am broadcast -a clipper.set -e text 'secret'"]);
+        assert_eq!(argv[4], "am broadcast -a clipper.set [redacted]");
+        assert!(hide_output);
+        let (argv, hide_output) = redacted(&["adb", "-s", "emu-1", "shell", "cmd clipboard get-primary-clip"]);
+        assert_eq!(argv[4], "cmd clipboard get-primary-clip");
+        assert!(hide_output);
+    }
+
+    #[test]
+    fn settings_values() {
+        let (argv, hide_output) = redacted(&[
+            "adb",
+            "shell",
+            "settings put global http_proxy 'user:pass@proxy:8080' && settings put secure x \"a b\"; echo done",
+        ]);
+        assert_eq!(
+            argv[2],
+            "settings put global http_proxy [redacted] && settings put secure x [redacted] ; echo done"
+        );
+        assert!(hide_output);
+        let (argv, _) = redacted(&["adb", "shell", "out=$(settings put system font_scale 1.3 2>&1); echo $?"]);
+        assert_eq!(argv[2], "out=$(settings put system font_scale [redacted] 2>&1); echo $?");
+    }
+
+    #[test]
+    fn ordinary_commands_untouched() {
+        let (argv, hide_output) = redacted(&["adb", "-s", "emu-1", "shell", "settings get global adb_enabled"]);
+        assert_eq!(argv[4], "settings get global adb_enabled");
+        assert!(!hide_output);
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::os::raw::c_char;
+use std::sync::{Mutex, OnceLock};
+
+use crate::adb;
+use crate::device_lock::ensure_device_unlocked;
+use crate::error::LbError;
+use crate::json::JsonValue;
+use crate::{ffi_guard, read_c_str, status_result, string_result};
+
+/// The user's keyboard before the first `lb_set_ime`, restored by `lb_reset_ime`.
+#[derive(Clone)]
+struct SavedIme {
+    selected: Option<String>,
+    enabled: Vec<String>,
+}
+
+static SAVED_IMES: OnceLock<Mutex<HashMap<String, SavedIme>>> = OnceLock::new();
+
+fn saved_registry() -> &'static Mutex<HashMap<String, SavedIme>> {
+    SAVED_IMES.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// `ime list -s` prints one component id per line (`com.example/.Service`).
+fn parse_ids(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|line| line.contains('/'))
+        .map(str::to_string)
+        .collect()
+}
+
+fn installed_imes(serial: &str) -> Result<Vec<String>, LbError> {
+    adb::shell(serial, "ime list -a -s").map(|output| parse_ids(&output))
+}
+
+fn enabled_imes(serial: &str) -> Result<Vec<String>, LbError> {
+    adb::shell(serial, "ime list -s").map(|output| parse_ids(&output))
+}
+
+fn selected_ime(serial: &str) -> Result<Option<String>, LbError> {
+    adb::get_setting(serial, "secure", "default_input_method")
+}
+
+fn list_imes(serial: &str) -> Result<String, LbError> {
+    let installed = installed_imes(serial)?;
+    let enabled = enabled_imes(serial)?;
+    let selected = selected_ime(serial)?;
+    let imes: Vec<JsonValue> = installed
+        .iter()
+        .map(|id| {
+            JsonValue::object(vec![
+                ("id", id.into()),
+                ("enabled", enabled.contains(id).into()),
+                ("selected", (selected.as_ref() == Some(id)).into()),
+            ])
+        })
+        .collect();
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("selected", selected.into()),
+        ("imes", imes.into()),
+    ])
+    .to_string())
+}
+
+fn set_ime(serial: &str, ime_id: &str) -> Result<(), LbError> {
+    ensure_device_unlocked(serial)?;
+    // `ime set` exits 0 even for unknown ids, so check against the installed list first.
+    if !installed_imes(serial)?.iter().any(|id| id == ime_id) {
+        return Err(LbError::not_found(format!("IME {} is not installed on {}", ime_id, serial)).with_serial(serial));
+    }
+    let enabled = enabled_imes(serial)?;
+    let selected = selected_ime(serial)?;
+    {
+        let mut saved = saved_registry()
+            .lock()
+            .map_err(|_| LbError::internal("IME registry poisoned"))?;
+        // Only the first switch records the user's keyboard; later ones swap test IMEs.
+        saved
+            .entry(serial.to_string())
+            .or_insert_with(|| SavedIme { selected, enabled: enabled.clone() });
+    }
+    if !enabled.iter().any(|id| id == ime_id) {
+        adb::shell(serial, &format!("ime enable {}", ime_id))?;
+    }
+    adb::shell(serial, &format!("ime set {}", ime_id)).map(|_| ())
+}
+
+fn reset_ime(serial: &str) -> Result<(), LbError> {
+    let saved = saved_registry()
+        .lock()
+        .map_err(|_| LbError::internal("IME registry poisoned"))?
+        .get(serial)
+        .cloned();
+    let Some(saved) = saved else {
+        // Nothing recorded in this process: fall back to the system default keyboard.
+        return adb::shell(serial, "ime reset").map(|_| ());
+    };
+    match &saved.selected {
+        Some(id) => adb::shell(serial, &format!("ime set {}", id))?,
+        None => adb::shell(serial, "ime reset")?,
+    };
+    for id in enabled_imes(serial)? {
+        if !saved.enabled.contains(&id) {
+            adb::shell(serial, &format!("ime disable {}", id))?;
+        }
+    }
+    // Forgotten only once restored, so a failed reset can be retried.
+    saved_registry()
+        .lock()
+        .map_err(|_| LbError::internal("IME registry poisoned"))?
+        .remove(serial);
+    Ok(())
+}
+
+/// Returns `{"serial", "selected", "imes": [{"id", "enabled", "selected"}]}` for every
+/// installed input method.
+#[no_mangle]
+pub extern "C" fn lb_list_imes(serial_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        string_result(read_c_str(serial_ptr, "serial").and_then(list_imes), "IME list")
+    })
+}
+
+/// Enables and selects `ime_id`. The keyboard selected before the first call is kept
+/// for `lb_reset_ime`.
+#[no_mangle]
+pub extern "C" fn lb_set_ime(serial_ptr: *const c_char, ime_id_ptr: *const c_char) -> i32 {
+    ffi_guard(0, || {
+        let result = read_c_str(serial_ptr, "serial")
+            .and_then(|serial| read_c_str(ime_id_ptr, "ime_id").and_then(|ime_id| set_ime(serial, ime_id)));
+        status_result(result)
+    })
+}
+
+/// Restores the keyboard saved by `lb_set_ime` and disables IMEs it enabled; without a
+/// saved keyboard, runs `ime reset`. The saved keyboard is kept until a reset succeeds.
+#[no_mangle]
+pub extern "C" fn lb_reset_ime(serial_ptr: *const c_char) -> i32 {
+    ffi_guard(0, || status_result(read_c_str(serial_ptr, "serial").and_then(reset_ime)))
+}
@@ -0,0 +1,508 @@
+use std::collections::HashMap;
+use std::os::raw::c_char;
+use std::sync::{Mutex, OnceLock};
+
+use crate::adb;
+use crate::device_lock::ensure_device_unlocked;
+use crate::error::{ErrorCode, LbError};
+use crate::json::{self, JsonValue};
+use crate::shell_session;
+use crate::{ffi_guard, read_c_str, status_result};
+
+const DEFAULT_FRAME_MS: u64 = 20;
+const DEFAULT_GESTURE_MS: u64 = 400;
+const DEFAULT_HOLD_MS: u64 = 800;
+const MAX_POINTERS: usize = 10;
+
+// Linux input event codes for multi-touch protocol B.
+const EV_SYN: u32 = 0;
+const EV_KEY: u32 = 1;
+const EV_ABS: u32 = 3;
+const SYN_REPORT: u32 = 0;
+const BTN_TOUCH: u32 = 330;
+const ABS_MT_SLOT: u32 = 47;
+const ABS_MT_POSITION_X: u32 = 53;
+const ABS_MT_POSITION_Y: u32 = 54;
+const ABS_MT_TRACKING_ID: u32 = 57;
+/// `-1` as the unsigned value `sendevent` expects; lifts the finger in the current slot.
+const TRACKING_ID_NONE: u32 = u32::MAX;
+
+// One persistent shell per device for tap/swipe/text/keyevent, so each call costs a
+// round trip instead of an adb process spawn.
+static INPUT_SESSIONS: OnceLock<Mutex<HashMap<String, u64>>> = OnceLock::new();
+
+fn input_sessions() -> &'static Mutex<HashMap<String, u64>> {
+    INPUT_SESSIONS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Waypoint {
+    x: f64,
+    y: f64,
+    t_ms: u64,
+}
+
+/// One finger: down at its first waypoint, up after its last.
+type PointerPath = Vec<Waypoint>;
+
+struct Gesture {
+    pointers: Vec<PointerPath>,
+    frame_ms: u64,
+}
+
+fn point(value: Option<&JsonValue>, name: &str) -> Result<(f64, f64), LbError> {
+    let items = value
+        .and_then(JsonValue::as_array)
+        .filter(|items| items.len() == 2)
+        .ok_or_else(|| format!("Gesture field '{}' must be an [x, y] array", name))?;
+    match (items[0].as_f64(), items[1].as_f64()) {
+        (Some(x), Some(y)) => Ok((x, y)),
+        _ => Err(format!("Gesture field '{}' must contain numbers", name).into()),
+    }
+}
+
+fn number(value: &JsonValue, name: &str, default: f64) -> Result<f64, LbError> {
+    match value.get(name) {
+        None => Ok(default),
+        Some(number) => number
+            .as_f64()
+            .ok_or_else(|| format!("Gesture field '{}' must be a number", name).into()),
+    }
+}
+
+fn millis(value: &JsonValue, name: &str, default: u64) -> Result<u64, LbError> {
+    match value.get(name) {
+        None => Ok(default),
+        Some(number) => number
+            .as_u64()
+            .ok_or_else(|| format!("Gesture field '{}' must be a non-negative integer", name).into()),
+    }
+}
+
+fn line(from: (f64, f64), to: (f64, f64), start_ms: u64, end_ms: u64) -> PointerPath {
+    vec![
+        Waypoint { x: from.0, y: from.1, t_ms: start_ms },
+        Waypoint { x: to.0, y: to.1, t_ms: end_ms },
+    ]
+}
+
+/// Two fingers on opposite sides of `center`, moving from `start_distance` apart to
+/// `end_distance` apart along `angle_deg` (0 = horizontal).
+fn pinch(value: &JsonValue) -> Result<Vec<PointerPath>, LbError> {
+    let center = point(value.get("center"), "center")?;
+    let start = number(value, "start_distance", 400.0)? / 2.0;
+    let end = number(value, "end_distance", 100.0)? / 2.0;
+    let angle = number(value, "angle_deg", 0.0)?.to_radians();
+    let duration = millis(value, "duration_ms", DEFAULT_GESTURE_MS)?;
+    let offset = |radius: f64, sign: f64| {
+        (center.0 + sign * radius * angle.cos(), center.1 + sign * radius * angle.sin())
+    };
+    Ok(vec![
+        line(offset(start, -1.0), offset(end, -1.0), 0, duration),
+        line(offset(start, 1.0), offset(end, 1.0), 0, duration),
+    ])
+}
+
+/// `fingers` parallel swipes spaced `spacing` px apart perpendicular to the motion.
+fn swipe(value: &JsonValue) -> Result<Vec<PointerPath>, LbError> {
+    let from = point(value.get("from"), "from")?;
+    let to = point(value.get("to"), "to")?;
+    let fingers = millis(value, "fingers", 2)? as usize;
+    if fingers == 0 || fingers > MAX_POINTERS {
+        return Err(format!("Gesture field 'fingers' must be between 1 and {}", MAX_POINTERS).into());
+    }
+    let spacing = number(value, "spacing", 120.0)?;
+    let duration = millis(value, "duration_ms", DEFAULT_GESTURE_MS)?;
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    let length = (dx * dx + dy * dy).sqrt().max(1.0);
+    let (nx, ny) = (-dy / length, dx / length);
+    Ok((0..fingers)
+        .map(|finger| {
+            let shift = (finger as f64 - (fingers as f64 - 1.0) / 2.0) * spacing;
+            line(
+                (from.0 + nx * shift, from.1 + ny * shift),
+                (to.0 + nx * shift, to.1 + ny * shift),
+                0,
+                duration,
+            )
+        })
+        .collect())
+}
+
+fn long_press_drag(value: &JsonValue) -> Result<Vec<PointerPath>, LbError> {
+    let from = point(value.get("from"), "from")?;
+    let to = point(value.get("to"), "to")?;
+    let hold = millis(value, "hold_ms", DEFAULT_HOLD_MS)?;
+    let duration = millis(value, "duration_ms", DEFAULT_GESTURE_MS)?;
+    Ok(vec![vec![
+        Waypoint { x: from.0, y: from.1, t_ms: 0 },
+        Waypoint { x: from.0, y: from.1, t_ms: hold },
+        Waypoint { x: to.0, y: to.1, t_ms: hold + duration },
+    ]])
+}
+
+/// `{"pointers": [[{"x", "y", "t_ms"}, ...], ...]}` with waypoints in time order.
+fn explicit_pointers(value: &JsonValue) -> Result<Vec<PointerPath>, LbError> {
+    let pointers = value
+        .get("pointers")
+        .and_then(JsonValue::as_array)
+        .ok_or("Gesture needs a 'type' or a 'pointers' array")?;
+    pointers
+        .iter()
+        .map(|pointer| {
+            let waypoints = pointer.as_array().ok_or("Each pointer must be an array of waypoints")?;
+            let path: PointerPath = waypoints
+                .iter()
+                .map(|waypoint| match (waypoint.get("x"), waypoint.get("y"), waypoint.get("t_ms")) {
+                    (Some(x), Some(y), Some(t_ms)) => match (x.as_f64(), y.as_f64(), t_ms.as_u64()) {
+                        (Some(x), Some(y), Some(t_ms)) => Ok(Waypoint { x, y, t_ms }),
+                        _ => Err(LbError::from("Waypoint x/y must be numbers and t_ms a non-negative integer")),
+                    },
+                    _ => Err(LbError::from("Each waypoint needs x, y, and t_ms")),
+                })
+                .collect::<Result<_, _>>()?;
+            if path.is_empty() || path.windows(2).any(|pair| pair[1].t_ms < pair[0].t_ms) {
+                return Err("Pointer waypoints must be non-empty and ordered by t_ms".into());
+            }
+            Ok(path)
+        })
+        .collect()
+}
+
+fn parse_gesture(value: &JsonValue) -> Result<Gesture, LbError> {
+    if value.as_object().is_none() {
+        return Err("Gesture must be a JSON object".into());
+    }
+    let pointers = match value.get("type").and_then(JsonValue::as_str) {
+        Some("pinch") => pinch(value)?,
+        Some("swipe") => swipe(value)?,
+        Some("long_press_drag") => long_press_drag(value)?,
+        Some(other) => return Err(format!("Unknown gesture type: {}", other).into()),
+        None => explicit_pointers(value)?,
+    };
+    if pointers.is_empty() || pointers.len() > MAX_POINTERS {
+        return Err(format!("Gesture must use between 1 and {} pointers", MAX_POINTERS).into());
+    }
+    let frame_ms = millis(value, "frame_ms", DEFAULT_FRAME_MS)?.max(1);
+    Ok(Gesture { pointers, frame_ms })
+}
+
+/// Position of `path` at `t_ms`, or `None` while the finger is not on the screen.
+fn position_at(path: &PointerPath, t_ms: u64) -> Option<(f64, f64)> {
+    let first = path.first()?;
+    let last = path.last()?;
+    if t_ms < first.t_ms || t_ms > last.t_ms {
+        return None;
+    }
+    let segment = path.windows(2).find(|pair| t_ms <= pair[1].t_ms);
+    Some(match segment {
+        Some([from, to]) if to.t_ms > from.t_ms => {
+            let progress = (t_ms - from.t_ms) as f64 / (to.t_ms - from.t_ms) as f64;
+            (from.x + (to.x - from.x) * progress, from.y + (to.y - from.y) * progress)
+        }
+        Some([_, to]) => (to.x, to.y),
+        _ => (first.x, first.y),
+    })
+}
+
+/// Frame times: every `frame_ms`, plus each waypoint so corners and end points are exact.
+fn frame_times(gesture: &Gesture) -> Vec<u64> {
+    let end = gesture
+        .pointers
+        .iter()
+        .filter_map(|path| path.last())
+        .map(|waypoint| waypoint.t_ms)
+        .max()
+        .unwrap_or(0);
+    let mut times: Vec<u64> = (0..=end).step_by(gesture.frame_ms as usize).collect();
+    times.extend(gesture.pointers.iter().flatten().map(|waypoint| waypoint.t_ms));
+    times.sort_unstable();
+    times.dedup();
+    times
+}
+
+fn sleep_command(delta_ms: u64) -> Option<String> {
+    (delta_ms > 0).then(|| format!("sleep {}.{:03}", delta_ms / 1000, delta_ms % 1000))
+}
+
+/// Single-pointer gestures go through `input motionevent`, which needs no device node.
+fn motionevent_script(path: &PointerPath, times: &[u64]) -> Vec<String> {
+    let mut script = Vec::new();
+    let mut previous_t = None;
+    let mut down = false;
+    for &t_ms in times {
+        let Some((x, y)) = position_at(path, t_ms) else {
+            continue;
+        };
+        if let Some(sleep) = previous_t.and_then(|previous| sleep_command(t_ms - previous)) {
+            script.push(sleep);
+        }
+        let action = if down { "MOVE" } else { "DOWN" };
+        script.push(format!("input motionevent {} {:.0} {:.0}", action, x, y));
+        down = true;
+        previous_t = Some(t_ms);
+    }
+    if let Some(last) = path.last() {
+        script.push(format!("input motionevent UP {:.0} {:.0}", last.x, last.y));
+    }
+    script
+}
+
+/// `(min, max)` of an absolute axis as reported by the driver.
+pub type AxisRange = (f64, f64);
+
+pub struct TouchDevice {
+    pub path: String,
+    pub x_range: AxisRange,
+    pub y_range: AxisRange,
+}
+
+fn axis_range(line: &str) -> Option<AxisRange> {
+    let field = |name: &str| -> Option<f64> {
+        let rest = &line[line.find(name)? + name.len()..];
+        rest.split(|ch: char| ch == ',' || ch.is_whitespace()).next()?.parse().ok()
+    };
+    Some((field("min ")?, field("max ")?))
+}
+
+/// First device in `getevent -pl` output that reports both multi-touch position axes.
+pub fn parse_touch_device(output: &str) -> Option<TouchDevice> {
+    let mut current: Option<(String, Option<AxisRange>, Option<AxisRange>)> = None;
+    for line in output.lines().chain(std::iter::once("add device")) {
+        if line.starts_with("add device") {
+            if let Some((path, Some(x_range), Some(y_range))) = current.take() {
+                return Some(TouchDevice { path, x_range, y_range });
+            }
+            current = line.split_once(": ").map(|(_, path)| (path.trim().to_string(), None, None));
+        } else if let Some((_, x_range, y_range)) = current.as_mut() {
+            if line.contains("ABS_MT_POSITION_X") {
+                *x_range = axis_range(line);
+            } else if line.contains("ABS_MT_POSITION_Y") {
+                *y_range = axis_range(line);
+            }
+        }
+    }
+    None
+}
+
+/// `Physical size: 1080x2400`; the touch panel maps to the unrotated physical display.
+pub fn parse_screen_size(output: &str) -> Option<(f64, f64)> {
+    let line = output.lines().find(|line| line.starts_with("Physical size:"))?;
+    let (width, height) = line["Physical size:".len()..].trim().split_once('x')?;
+    Some((width.parse().ok()?, height.parse().ok()?))
+}
+
+fn sendevent_script(device: &TouchDevice, screen: (f64, f64), gesture: &Gesture, times: &[u64]) -> Vec<String> {
+    let scale = |value: f64, extent: f64, (min, max): AxisRange| {
+        (min + value / extent.max(1.0) * (max - min)).round().clamp(min, max) as i64
+    };
+    let event = |kind: u32, code: u32, value: i64| format!("sendevent {} {} {} {}", device.path, kind, code, value);
+
+    let mut script = Vec::new();
+    let mut active = vec![false; gesture.pointers.len()];
+    let mut touching = false;
+    let mut previous_t: Option<u64> = None;
+    // One extra frame after the end lifts every remaining finger.
+    let release_t = times.last().copied().unwrap_or(0) + gesture.frame_ms;
+    for &t_ms in times.iter().chain(std::iter::once(&release_t)) {
+        if let Some(sleep) = previous_t.and_then(|previous| sleep_command(t_ms - previous)) {
+            script.push(sleep);
+        }
+        previous_t = Some(t_ms);
+        for (slot, path) in gesture.pointers.iter().enumerate() {
+            let position = if t_ms == release_t { None } else { position_at(path, t_ms) };
+            match position {
+                Some((x, y)) => {
+                    script.push(event(EV_ABS, ABS_MT_SLOT, slot as i64));
+                    if !active[slot] {
+                        script.push(event(EV_ABS, ABS_MT_TRACKING_ID, slot as i64 + 1));
+                        active[slot] = true;
+                    }
+                    script.push(event(EV_ABS, ABS_MT_POSITION_X, scale(x, screen.0, device.x_range)));
+                    script.push(event(EV_ABS, ABS_MT_POSITION_Y, scale(y, screen.1, device.y_range)));
+                }
+                None if active[slot] => {
+                    script.push(event(EV_ABS, ABS_MT_SLOT, slot as i64));
+                    script.push(event(EV_ABS, ABS_MT_TRACKING_ID, i64::from(TRACKING_ID_NONE)));
+                    active[slot] = false;
+                }
+                None => {}
+            }
+        }
+        let any_active = active.iter().any(|active| *active);
+        if any_active != touching {
+            script.push(event(EV_KEY, BTN_TOUCH, i64::from(any_active)));
+            touching = any_active;
+        }
+        script.push(event(EV_SYN, SYN_REPORT, 0));
+    }
+    script
+}
+
+fn input_gesture(serial: &str, gesture_json: &str) -> Result<(), LbError> {
+    let gesture = parse_gesture(&json::parse(gesture_json)?)?;
+    ensure_device_unlocked(serial)?;
+    let times = frame_times(&gesture);
+    // The whole gesture runs as one shell script so frame timing is kept on the device.
+    let script = if gesture.pointers.len() == 1 {
+        motionevent_script(&gesture.pointers[0], &times)
+    } else {
+        let device = parse_touch_device(&adb::shell(serial, "getevent -pl")?).ok_or_else(|| {
+            LbError::not_found(format!("No multi-touch input device found on {}", serial)).with_serial(serial)
+        })?;
+        let screen = parse_screen_size(&adb::shell(serial, "wm size")?).ok_or_else(|| {
+            LbError::new(ErrorCode::CommandFailed, format!("Could not read the screen size of {}", serial))
+                .with_serial(serial)
+        })?;
+        sendevent_script(&device, screen, &gesture, &times)
+    };
+    adb::shell(serial, &script.join(" && ")).map(|_| ())
+}
+
+/// Performs a timed gesture. `gesture_json` is one of
+/// `{"type": "pinch", "center": [x, y], "start_distance", "end_distance", "angle_deg", "duration_ms"}`,
+/// `{"type": "swipe", "from": [x, y], "to": [x, y], "fingers": 2, "spacing", "duration_ms"}`,
+/// `{"type": "long_press_drag", "from": [x, y], "to": [x, y], "hold_ms", "duration_ms"}`, or
+/// `{"pointers": [[{"x", "y", "t_ms"}, ...], ...]}`; all accept `frame_ms`. Coordinates are
+/// screen pixels in the natural (unrotated) orientation. Multi-finger gestures use `sendevent`
+/// on the touch panel; single-finger ones use `input motionevent`.
+#[no_mangle]
+pub extern "C" fn lb_input_gesture(serial_ptr: *const c_char, gesture_ptr: *const c_char) -> i32 {
+    ffi_guard(0, || {
+        let result = read_c_str(serial_ptr, "serial")
+            .and_then(|serial| read_c_str(gesture_ptr, "gesture").and_then(|gesture| input_gesture(serial, gesture)));
+        status_result(result)
+    })
+}
+
+fn input_session(serial: &str) -> Result<u64, LbError> {
+    let mut sessions = input_sessions()
+        .lock()
+        .map_err(|_| LbError::internal("Input session registry poisoned"))?;
+    if let Some(id) = sessions.get(serial) {
+        return Ok(*id);
+    }
+    let id = shell_session::open_session(serial)?;
+    sessions.insert(serial.to_string(), id);
+    Ok(id)
+}
+
+/// Runs an `input ...` command in the device's input session, reopening it once if the
+/// previous one died (device reconnected, shell timed out).
+pub fn run_input(serial: &str, command: &str) -> Result<(), LbError> {
+    ensure_device_unlocked(serial)?;
+    let mut retried = false;
+    loop {
+        let id = input_session(serial)?;
+        match shell_session::run(id, command) {
+            Ok((_, 0, _)) => return Ok(()),
+            Ok((output, exit_code, _)) => {
+                return Err(LbError::new(
+                    ErrorCode::CommandFailed,
+                    format!("{} failed (exit={}): {}", command, exit_code, output.trim()),
+                )
+                .with_serial(serial))
+            }
+            Err(err) => {
+                if let Ok(mut sessions) = input_sessions().lock() {
+                    sessions.remove(serial);
+                }
+                if retried {
+                    return Err(err);
+                }
+                retried = true;
+            }
+        }
+    }
+}
+
+/// Quotes `text` for `input text`: spaces become `%s` (the only way `input` accepts them)
+/// and the whole argument is single-quoted so the device shell passes `&`, `;`, `$`,
+/// backticks and quotes through literally. A literal `%s` is rejected, since the device
+/// would type it as a space.
+pub fn input_text_argument(text: &str) -> Result<String, LbError> {
+    if text.is_empty() {
+        return Err("Input text must not be empty".into());
+    }
+    if let Some(ch) = text.chars().find(|ch| !(ch.is_ascii_graphic() || *ch == ' ')) {
+        return Err(format!("input text only supports printable ASCII; got {:?}", ch).into());
+    }
+    if text.contains("%s") {
+        return Err("input text cannot type a literal \"%s\" (the device turns it into a space)".into());
+    }
+    Ok(format!("'{}'", text.replace(' ', "%s").replace('\'', "'\\''")))
+}
+
+/// `KEYCODE_HOME`, `HOME`, or `3`; several may be given space-separated.
+pub fn keyevent_arguments(keycodes: &str) -> Result<String, LbError> {
+    let codes: Vec<&str> = keycodes.split_whitespace().collect();
+    if codes.is_empty() || codes.iter().any(|code| !code.chars().all(|ch| ch.is_ascii_alphanumeric() || ch == '_')) {
+        return Err(format!("Invalid keycode list: {}", keycodes).into());
+    }
+    Ok(codes.join(" "))
+}
+
+/// Taps at screen pixel (`x`, `y`).
+#[no_mangle]
+pub extern "C" fn lb_input_tap(serial_ptr: *const c_char, x: i32, y: i32) -> i32 {
+    ffi_guard(0, || {
+        let result = read_c_str(serial_ptr, "serial").and_then(|serial| run_input(serial, &format!("input tap {} {}", x, y)));
+        status_result(result)
+    })
+}
+
+/// Swipes from (`x1`, `y1`) to (`x2`, `y2`) over `duration_ms` (0 lets the device pick).
+#[no_mangle]
+pub extern "C" fn lb_input_swipe(serial_ptr: *const c_char, x1: i32, y1: i32, x2: i32, y2: i32, duration_ms: u32) -> i32 {
+    ffi_guard(0, || {
+        let command = if duration_ms == 0 {
+            format!("input swipe {} {} {} {}", x1, y1, x2, y2)
+        } else {
+            format!("input swipe {} {} {} {} {}", x1, y1, x2, y2, duration_ms)
+        };
+        status_result(read_c_str(serial_ptr, "serial").and_then(|serial| run_input(serial, &command)))
+    })
+}
+
+/// Types `text` into the focused field. Printable ASCII only (a limit of `input text`);
+/// spaces and shell metacharacters are escaped.
+#[no_mangle]
+pub extern "C" fn lb_input_text(serial_ptr: *const c_char, text_ptr: *const c_char) -> i32 {
+    ffi_guard(0, || {
+        let result = read_c_str(serial_ptr, "serial").and_then(|serial| {
+            read_c_str(text_ptr, "text")
+                .and_then(input_text_argument)
+                .and_then(|text| run_input(serial, &format!("input text {}", text)))
+        });
+        status_result(result)
+    })
+}
+
+/// Sends one or more space-separated keycodes (`KEYCODE_BACK`, `HOME`, `66`) in order.
+#[no_mangle]
+pub extern "C" fn lb_input_keyevent(serial_ptr: *const c_char, keycodes_ptr: *const c_char) -> i32 {
+    ffi_guard(0, || {
+        let result = read_c_str(serial_ptr, "serial").and_then(|serial| {
+            read_c_str(keycodes_ptr, "keycodes")
+                .and_then(keyevent_arguments)
+                .and_then(|codes| run_input(serial, &format!("input keyevent {}", codes)))
+        });
+        status_result(result)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_argument_escapes_spaces_and_quotes() {
+        assert_eq!(input_text_argument("it's 100% done").unwrap(), "'it'\\''s%s100%%sdone'");
+    }
+
+    #[test]
+    fn text_argument_rejects_literal_percent_s() {
+        let err = input_text_argument("100%sure").unwrap_err();
+        assert_eq!(err.code, ErrorCode::InvalidArgument);
+    }
+}
@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+use std::os::raw::c_char;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::adb;
+use crate::cancel;
+use crate::error::{ErrorCode, LbError};
+use crate::failure_capture;
+use crate::input::{self, TouchDevice};
+use crate::json::{self, JsonValue};
+use crate::logging::{lb_log, Level};
+use crate::stream;
+use crate::{ffi_guard, handle_result, read_c_str, status_result, string_result};
+
+const SCRIPT_VERSION: u64 = 1;
+/// Movement below this many pixels still counts as a tap or long press.
+const TAP_SLOP_PX: f64 = 24.0;
+const LONG_PRESS_MS: u64 = 500;
+
+/// Linux key names whose Android keycode is not simply `KEYCODE_` + the rest.
+const KEY_NAMES: [(&str, &str); 6] = [
+    ("KEY_VOLUMEUP", "KEYCODE_VOLUME_UP"),
+    ("KEY_VOLUMEDOWN", "KEYCODE_VOLUME_DOWN"),
+    ("KEY_HOMEPAGE", "KEYCODE_HOME"),
+    ("KEY_APPSELECT", "KEYCODE_APP_SWITCH"),
+    ("KEY_MUTE", "KEYCODE_VOLUME_MUTE"),
+    ("KEY_BACKSPACE", "KEYCODE_DEL"),
+];
+
+struct Touch {
+    start: (f64, f64),
+    start_ms: u64,
+}
+
+/// Folds `getevent -lt` lines into taps, long presses, swipes and key presses. Only the
+/// first finger (slot 0) of the touch panel is followed.
+struct Recorder {
+    device: TouchDevice,
+    screen: (f64, f64),
+    first_timestamp: Option<f64>,
+    slot: u64,
+    position: (f64, f64),
+    touch: Option<Touch>,
+    pressed: Option<bool>,
+    actions: Vec<JsonValue>,
+}
+
+static RECORDERS: OnceLock<Mutex<HashMap<u64, Arc<Mutex<Recorder>>>>> = OnceLock::new();
+
+fn recorder_registry() -> &'static Mutex<HashMap<u64, Arc<Mutex<Recorder>>>> {
+    RECORDERS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn android_keycode(key: &str) -> String {
+    KEY_NAMES
+        .iter()
+        .find(|(linux, _)| *linux == key)
+        .map(|(_, android)| android.to_string())
+        .unwrap_or_else(|| format!("KEYCODE_{}", key.trim_start_matches("KEY_")))
+}
+
+fn point(position: (f64, f64)) -> JsonValue {
+    vec![JsonValue::from(position.0.round()), JsonValue::from(position.1.round())].into()
+}
+
+impl Recorder {
+    fn elapsed_ms(&mut self, timestamp: f64) -> u64 {
+        let first = *self.first_timestamp.get_or_insert(timestamp);
+        ((timestamp - first).max(0.0) * 1000.0).round() as u64
+    }
+
+    fn scale(&self, raw: &str, (min, max): input::AxisRange, extent: f64) -> Option<f64> {
+        let raw = u32::from_str_radix(raw, 16).ok()? as i32 as f64;
+        Some(((raw - min) / (max - min).max(1.0) * extent).clamp(0.0, extent))
+    }
+
+    /// `[   1234.567890] /dev/input/event2: EV_ABS       ABS_MT_POSITION_X    000002a3`
+    fn feed(&mut self, line: &str) {
+        let Some((timestamp, rest)) = line.trim_start().strip_prefix('[').and_then(|rest| rest.split_once(']')) else {
+            return;
+        };
+        let Ok(timestamp) = timestamp.trim().parse::<f64>() else {
+            return;
+        };
+        let (device, event) = match rest.trim().split_once(": ") {
+            Some((device, event)) => (Some(device), event),
+            None => (None, rest.trim()),
+        };
+        let mut fields = event.split_whitespace();
+        let (Some(kind), Some(code), Some(value)) = (fields.next(), fields.next(), fields.next()) else {
+            return;
+        };
+        if kind == "EV_KEY" && code.starts_with("KEY_") {
+            if value == "DOWN" {
+                let t_ms = self.elapsed_ms(timestamp);
+                self.actions.push(JsonValue::object(vec![
+                    ("type", "key".into()),
+                    ("keycode", android_keycode(code).into()),
+                    ("t_ms", t_ms.into()),
+                ]));
+            }
+            return;
+        }
+        if device.is_some_and(|device| device != self.device.path) {
+            return;
+        }
+        match (kind, code) {
+            ("EV_ABS", "ABS_MT_SLOT") => self.slot = u64::from_str_radix(value, 16).unwrap_or(0),
+            ("EV_ABS", "ABS_MT_POSITION_X") if self.slot == 0 => {
+                if let Some(x) = self.scale(value, self.device.x_range, self.screen.0) {
+                    self.position.0 = x;
+                }
+            }
+            ("EV_ABS", "ABS_MT_POSITION_Y") if self.slot == 0 => {
+                if let Some(y) = self.scale(value, self.device.y_range, self.screen.1) {
+                    self.position.1 = y;
+                }
+            }
+            ("EV_ABS", "ABS_MT_TRACKING_ID") if self.slot == 0 => self.pressed = Some(value != "ffffffff"),
+            ("EV_KEY", "BTN_TOUCH") => self.pressed = Some(value == "DOWN"),
+            ("EV_SYN", "SYN_REPORT") => self.sync(timestamp),
+            _ => {}
+        }
+    }
+
+    fn sync(&mut self, timestamp: f64) {
+        let t_ms = self.elapsed_ms(timestamp);
+        match (self.pressed.take(), self.touch.is_some()) {
+            (Some(true), false) => {
+                self.touch = Some(Touch {
+                    start: self.position,
+                    start_ms: t_ms,
+                })
+            }
+            (Some(false), true) => {
+                if let Some(touch) = self.touch.take() {
+                    self.finish(touch, t_ms);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn finish(&mut self, touch: Touch, end_ms: u64) {
+        let (x, y) = touch.start;
+        let distance = (self.position.0 - x).hypot(self.position.1 - y);
+        let duration_ms = end_ms.saturating_sub(touch.start_ms);
+        let action = if distance < TAP_SLOP_PX && duration_ms < LONG_PRESS_MS {
+            JsonValue::object(vec![("type", "tap".into()), ("at", point(touch.start))])
+        } else if distance < TAP_SLOP_PX {
+            JsonValue::object(vec![
+                ("type", "long_press".into()),
+                ("at", point(touch.start)),
+                ("duration_ms", duration_ms.into()),
+            ])
+        } else {
+            JsonValue::object(vec![
+                ("type", "swipe".into()),
+                ("from", point(touch.start)),
+                ("to", point(self.position)),
+                ("duration_ms", duration_ms.into()),
+            ])
+        };
+        let JsonValue::Object(mut fields) = action else {
+            return;
+        };
+        fields.push(("t_ms".to_string(), touch.start_ms.into()));
+        self.actions.push(JsonValue::Object(fields));
+    }
+
+    fn script(&self) -> JsonValue {
+        JsonValue::object(vec![
+            ("version", SCRIPT_VERSION.into()),
+            ("screen", point(self.screen)),
+            ("actions", self.actions.clone().into()),
+        ])
+    }
+}
+
+fn screen_size(serial: &str) -> Result<(f64, f64), LbError> {
+    input::parse_screen_size(&adb::shell(serial, "wm size")?).ok_or_else(|| {
+        LbError::new(ErrorCode::CommandFailed, format!("Could not read the screen size of {}", serial)).with_serial(serial)
+    })
+}
+
+fn start_recording(serial: &str) -> Result<u64, LbError> {
+    let device = input::parse_touch_device(&adb::shell(serial, "getevent -pl")?)
+        .ok_or_else(|| LbError::not_found(format!("No multi-touch input device found on {}", serial)).with_serial(serial))?;
+    let recorder = Arc::new(Mutex::new(Recorder {
+        device,
+        screen: screen_size(serial)?,
+        first_timestamp: None,
+        slot: 0,
+        position: (0.0, 0.0),
+        touch: None,
+        pressed: None,
+        actions: Vec::new(),
+    }));
+    let feeder = recorder.clone();
+    let argv = adb::adb_argv(Some(serial), &["shell", "getevent", "-lt"]);
+    let id = stream::spawn_line_stream(&argv, move |line| {
+        if let Ok(mut recorder) = feeder.lock() {
+            recorder.feed(line);
+        }
+    })?;
+    recorder_registry()
+        .lock()
+        .map_err(|_| LbError::internal("Input recorder registry poisoned"))?
+        .insert(id, recorder);
+    lb_log!(Level::Info, "stream", "Input recording {} started on {}", id, serial);
+    Ok(id)
+}
+
+fn stop_recording(id: u64) -> Result<String, LbError> {
+    let recorder = recorder_registry()
+        .lock()
+        .map_err(|_| LbError::internal("Input recorder registry poisoned"))?
+        .remove(&id)
+        .ok_or_else(|| LbError::not_found(format!("No input recording with handle {}", id)))?;
+    // Already ended if the device went away; the actions captured so far still count.
+    let _ = stream::stop_stream(id);
+    let script = recorder
+        .lock()
+        .map_err(|_| LbError::internal("Input recorder poisoned"))?
+        .script();
+    Ok(script.to_string())
+}
+
+fn coordinates(action: &JsonValue, key: &str, scale: (f64, f64)) -> Result<(i64, i64), LbError> {
+    let pair = action
+        .get(key)
+        .and_then(JsonValue::as_array)
+        .filter(|pair| pair.len() == 2)
+        .and_then(|pair| Some((pair[0].as_f64()?, pair[1].as_f64()?)))
+        .ok_or_else(|| format!("Input action field '{}' must be [x, y]", key))?;
+    Ok(((pair.0 * scale.0).round() as i64, (pair.1 * scale.1).round() as i64))
+}
+
+/// Turns one script action into an `input` command, scaling coordinates to this screen.
+fn action_command(action: &JsonValue, scale: (f64, f64), speed: f64) -> Result<String, LbError> {
+    let duration = || {
+        action
+            .get("duration_ms")
+            .and_then(JsonValue::as_u64)
+            .map(|duration| ((duration as f64 / speed).round() as u64).max(1))
+            .ok_or("Input action field 'duration_ms' must be a non-negative integer")
+    };
+    match action.get("type").and_then(JsonValue::as_str) {
+        Some("tap") => {
+            let (x, y) = coordinates(action, "at", scale)?;
+            Ok(format!("input tap {} {}", x, y))
+        }
+        Some("long_press") => {
+            let (x, y) = coordinates(action, "at", scale)?;
+            Ok(format!("input swipe {} {} {} {} {}", x, y, x, y, duration()?))
+        }
+        Some("swipe") => {
+            let (x1, y1) = coordinates(action, "from", scale)?;
+            let (x2, y2) = coordinates(action, "to", scale)?;
+            Ok(format!("input swipe {} {} {} {} {}", x1, y1, x2, y2, duration()?))
+        }
+        Some("key") => {
+            let keycode = action
+                .get("keycode")
+                .and_then(JsonValue::as_str)
+                .filter(|code| !code.is_empty() && code.chars().all(|ch| ch.is_ascii_alphanumeric() || ch == '_'))
+                .ok_or("Input action field 'keycode' must be a keycode name")?;
+            Ok(format!("input keyevent {}", keycode))
+        }
+        other => Err(format!("Unknown input action type: {:?}", other.unwrap_or("")).into()),
+    }
+}
+
+fn replay(serial: &str, script_json: &str, speed: f64) -> Result<(), LbError> {
+    let speed = if speed > 0.0 { speed } else { 1.0 };
+    let script = json::parse(script_json)?;
+    let actions = script
+        .get("actions")
+        .and_then(JsonValue::as_array)
+        .ok_or("Input script must have an 'actions' array")?;
+    let recorded = script
+        .get("screen")
+        .and_then(JsonValue::as_array)
+        .and_then(|screen| Some((screen.first()?.as_f64()?, screen.get(1)?.as_f64()?)));
+    let scale = match recorded {
+        Some((width, height)) if width > 0.0 && height > 0.0 => {
+            let (target_width, target_height) = screen_size(serial)?;
+            (target_width / width, target_height / height)
+        }
+        _ => (1.0, 1.0),
+    };
+    // Validate everything up front so a bad action does not leave a flow half replayed.
+    let commands = actions
+        .iter()
+        .map(|action| {
+            let t_ms = action.get("t_ms").and_then(JsonValue::as_u64).unwrap_or(0);
+            action_command(action, scale, speed).map(|command| ((t_ms as f64 / speed) as u64, command))
+        })
+        .collect::<Result<Vec<_>, LbError>>()?;
+    lb_log!(Level::Info, "shell", "Replaying {} input actions on {} at {}x", commands.len(), serial, speed);
+    let started = Instant::now();
+    let sent = commands.into_iter().try_for_each(|(offset_ms, command)| {
+        let due = Duration::from_millis(offset_ms);
+        if let Some(wait) = due.checked_sub(started.elapsed()) {
+            cancel::sleep(wait)?;
+        }
+        input::run_input(serial, &command)
+    });
+    failure_capture::on_failure(serial, "replay_input", sent)
+}
+
+/// Starts capturing touches and key presses on `serial` (`getevent -lt`). Returns a
+/// stream handle for `lb_record_input_stop`, or 0 on error.
+#[no_mangle]
+pub extern "C" fn lb_record_input_start(serial_ptr: *const c_char) -> u64 {
+    ffi_guard(0, || handle_result(read_c_str(serial_ptr, "serial").and_then(start_recording)))
+}
+
+/// Ends a recording and returns its script: `{"version": 1, "screen": [w, h], "actions":
+/// [{"type": "tap", "at": [x, y]} | {"type": "long_press", "at", "duration_ms"} |
+/// {"type": "swipe", "from", "to", "duration_ms"} | {"type": "key", "keycode"}, ...]}`,
+/// each action carrying `t_ms` from the first event. Coordinates are natural-orientation
+/// screen pixels; only the first finger is recorded.
+#[no_mangle]
+pub extern "C" fn lb_record_input_stop(handle: u64) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || string_result(stop_recording(handle), "input script"))
+}
+
+/// Replays a recorded script on `serial` through `input`, keeping the recorded timing
+/// divided by `speed` (<= 0 means 1.0) and scaling coordinates when the screen size
+/// differs from the recording's. Blocks until the last action is sent.
+#[no_mangle]
+pub extern "C" fn lb_replay_input(serial_ptr: *const c_char, script_ptr: *const c_char, speed: f64) -> i32 {
+    ffi_guard(0, || {
+        let result = read_c_str(serial_ptr, "serial")
+            .and_then(|serial| read_c_str(script_ptr, "input script").and_then(|script| replay(serial, script, speed)));
+        status_result(result)
+    })
+}
@@ -0,0 +1,113 @@
+use std::os::raw::c_char;
+use std::time::Instant;
+
+use crate::adb;
+use crate::display::{parse_size, wm_field};
+use crate::error::LbError;
+use crate::hierarchy::{self, UiNode};
+use crate::json::{self, JsonValue};
+use crate::logging::{lb_log, Level};
+use crate::png;
+use crate::{ffi_guard, now_millis, read_c_str, string_result};
+
+const REMOTE_SCREENSHOT: &str = "/data/local/tmp/lb_inspect.png";
+const REMOTE_UI_DUMP: &str = "/data/local/tmp/lb_inspect.xml";
+
+fn display_metrics(wm_output: &str, roots: &[UiNode]) -> JsonValue {
+    // The override is what apps are laid out against.
+    let wm_value = |name| wm_field(wm_output, "Override", name).or_else(|| wm_field(wm_output, "Physical", name));
+    let size = wm_value("size").and_then(parse_size);
+    let rotation = roots
+        .iter()
+        .find_map(|root| root.attribute("rotation"))
+        .and_then(|rotation| rotation.parse::<u32>().ok());
+    JsonValue::object(vec![
+        ("width", size.map(|(width, _)| width).into()),
+        ("height", size.map(|(_, height)| height).into()),
+        ("density_dpi", wm_value("density").and_then(|density| density.parse::<u32>().ok()).into()),
+        ("rotation", rotation.into()),
+    ])
+}
+
+/// Width and height from a PNG's `IHDR`.
+fn png_size(data: &[u8]) -> Option<(u32, u32)> {
+    let (header, _) = png::parts(data)?;
+    let field = |offset: usize| {
+        header
+            .get(offset..offset + 4)
+            .map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    };
+    Some((field(0)?, field(4)?))
+}
+
+fn capture_inspection_bundle(serial: &str) -> Result<String, LbError> {
+    let requested_at = now_millis();
+    let started = Instant::now();
+    // Both captures start together; a dump followed by a screenshot can be seconds apart,
+    // long enough for the screen to have moved on.
+    let script = format!(
+        "rm -f {png} {xml}; screencap -p {png} & uiautomator dump {xml} >/dev/null; wait; wm size; wm density",
+        png = REMOTE_SCREENSHOT,
+        xml = REMOTE_UI_DUMP
+    );
+    let wm_output = adb::shell(serial, &script)?;
+    let captured_at = now_millis();
+    let capture_ms = started.elapsed().as_millis() as u64;
+
+    let screenshot = adb::exec_out(serial, &format!("cat {}", REMOTE_SCREENSHOT));
+    let dump = adb::exec_out(serial, &format!("cat {}", REMOTE_UI_DUMP));
+    let _ = adb::shell(serial, &format!("rm -f {} {}", REMOTE_SCREENSHOT, REMOTE_UI_DUMP));
+    let screenshot = screenshot.map_err(|err| err.context("Screenshot capture failed"))?;
+    let (width, height) = png_size(&screenshot)
+        .ok_or_else(|| LbError::parse("screencap did not write a PNG").with_serial(serial))?;
+    let dump = String::from_utf8_lossy(&dump.map_err(|err| err.context("UI dump failed"))?).into_owned();
+    let (roots, _) = hierarchy::load(&dump, None).map_err(|err| err.with_serial(serial).context("UI dump failed"))?;
+
+    let node_count: usize = roots.iter().map(UiNode::subtree_size).sum();
+    lb_log!(
+        Level::Info,
+        "hierarchy",
+        "Inspection bundle from {}: {}x{} screenshot, {} nodes in {} ms",
+        serial,
+        width,
+        height,
+        node_count,
+        capture_ms
+    );
+    let tree: Vec<JsonValue> = roots
+        .iter()
+        .enumerate()
+        .map(|(index, root)| root.to_json(&index.to_string()))
+        .collect();
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("requested_at_ms", requested_at.into()),
+        ("captured_at_ms", captured_at.into()),
+        ("capture_ms", capture_ms.into()),
+        ("display", display_metrics(&wm_output, &roots)),
+        (
+            "screenshot",
+            JsonValue::object(vec![
+                ("width", width.into()),
+                ("height", height.into()),
+                ("png_base64", json::base64_encode(&screenshot).into()),
+            ]),
+        ),
+        ("node_count", node_count.into()),
+        ("hierarchy", tree.into()),
+    ])
+    .to_string())
+}
+
+/// Takes a screenshot and a UI dump at the same moment and returns them as one document:
+/// `{serial, requested_at_ms, captured_at_ms, capture_ms, display: {width, height,
+/// density_dpi, rotation}, screenshot: {width, height, png_base64}, node_count, hierarchy}`.
+/// `hierarchy` holds `{path, tag, bounds, attributes, children}` trees whose `bounds` are in
+/// screenshot pixels.
+#[no_mangle]
+pub extern "C" fn lb_capture_inspection_bundle(serial_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(serial_ptr, "serial").and_then(capture_inspection_bundle);
+        string_result(result, "inspection bundle")
+    })
+}
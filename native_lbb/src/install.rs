@@ -0,0 +1,334 @@
+use std::ffi::c_void;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::raw::c_char;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Instant;
+
+use crate::adb;
+use crate::device_lock::ensure_device_unlocked;
+use crate::error::{ErrorCode, LbError};
+use crate::exec::CancellableChild;
+use crate::failure_capture;
+use crate::json::{self, JsonValue};
+use crate::logging::{lb_log, Level};
+use crate::serial_lock;
+use crate::stream::{LineCallback, LineSink};
+use crate::transcript;
+use crate::{ffi_guard, read_c_str, string_result};
+
+const WRITE_CHUNK_BYTES: usize = 256 * 1024;
+
+/// `adb install` / `cmd package install-create` flags, shared by both install paths.
+struct InstallOptions {
+    replace: bool,
+    downgrade: bool,
+    grant_permissions: bool,
+    user: Option<String>,
+}
+
+impl InstallOptions {
+    fn parse(options_json: Option<&str>) -> Result<InstallOptions, LbError> {
+        let options = match options_json {
+            Some(source) => json::parse(source)?,
+            None => JsonValue::Object(Vec::new()),
+        };
+        if options.as_object().is_none() {
+            return Err("Install options must be a JSON object".into());
+        }
+        let flag = |key: &str| -> Result<bool, LbError> {
+            match options.get(key) {
+                None => Ok(false),
+                Some(value) => value
+                    .as_bool()
+                    .ok_or_else(|| format!("Install option '{}' must be a boolean", key).into()),
+            }
+        };
+        let user = match options.get("user") {
+            None | Some(JsonValue::Null) => None,
+            Some(JsonValue::String(user)) if user == "all" || user == "current" => Some(user.clone()),
+            Some(user) => Some(
+                user.as_u64()
+                    .ok_or("Install option 'user' must be a user id or \"all\"/\"current\"")?
+                    .to_string(),
+            ),
+        };
+        Ok(InstallOptions {
+            replace: flag("replace")?,
+            downgrade: flag("downgrade")?,
+            grant_permissions: flag("grant_permissions")?,
+            user,
+        })
+    }
+
+    fn flags(&self) -> Vec<String> {
+        let mut flags = Vec::new();
+        for (enabled, flag) in [(self.replace, "-r"), (self.downgrade, "-d"), (self.grant_permissions, "-g")] {
+            if enabled {
+                flags.push(flag.to_string());
+            }
+        }
+        if let Some(user) = &self.user {
+            flags.push("--user".to_string());
+            flags.push(user.clone());
+        }
+        flags
+    }
+}
+
+/// Reports `{"stage", "bytes", "total", "percent"}` to the host, once per whole percent.
+struct Progress {
+    sink: Option<LineSink>,
+    total: u64,
+    last_percent: Option<u64>,
+}
+
+impl Progress {
+    fn report(&mut self, stage: &str, bytes: u64) {
+        let percent = (bytes.saturating_mul(100)).checked_div(self.total).unwrap_or(100).min(100);
+        if stage == "writing" && self.last_percent == Some(percent) {
+            return;
+        }
+        self.last_percent = Some(percent);
+        if let Some(sink) = &self.sink {
+            sink.emit(
+                &JsonValue::object(vec![
+                    ("stage", stage.into()),
+                    ("bytes", bytes.into()),
+                    ("total", self.total.into()),
+                    ("percent", percent.into()),
+                ])
+                .to_string(),
+            );
+        }
+    }
+}
+
+/// Why the package manager rejected the APK: the `INSTALL_FAILED_*` / `INSTALL_PARSE_FAILED_*`
+/// code and the text after it, from `Failure [CODE: detail]` or a bare code in adb's output.
+fn failure_reason(output: &str) -> (Option<String>, String) {
+    let detail = output
+        .find("Failure [")
+        .map(|start| {
+            let rest = &output[start + "Failure [".len()..];
+            rest[..rest.rfind(']').unwrap_or(rest.len())].trim().to_string()
+        })
+        .unwrap_or_else(|| output.trim().to_string());
+    let reason = detail
+        .split(|ch: char| !(ch.is_ascii_uppercase() || ch.is_ascii_digit() || ch == '_'))
+        .find(|token| token.starts_with("INSTALL_") && token.len() > "INSTALL_".len())
+        .map(str::to_string);
+    let message = match &reason {
+        Some(reason) => detail
+            .split_once(reason.as_str())
+            .map(|(_, rest)| rest.trim_start_matches([':', ' ']).trim().to_string())
+            .unwrap_or_default(),
+        None => detail,
+    };
+    (reason, message)
+}
+
+/// Streams the APK into a session over `exec-in` so progress reflects bytes the device
+/// accepted; `exec-in` keeps the binary stream intact where `shell` would not.
+fn write_session(serial: &str, session: &str, apk: &Path, progress: &mut Progress) -> Result<(), LbError> {
+    let size = progress.total.to_string();
+    let argv = adb::adb_argv(
+        Some(serial),
+        &["exec-in", "cmd", "package", "install-write", "-S", &size, session, "base.apk", "-"],
+    );
+    let started = Instant::now();
+    let mut child = Command::new(&argv[0])
+        .args(&argv[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| {
+            LbError::new(ErrorCode::SpawnFailed, format!("Failed to spawn {}: {}", argv[0], err))
+                .with_command(&argv)
+                .with_serial(serial)
+        })?;
+    let mut file = File::open(apk).map_err(|err| LbError::io(format!("Failed to open {}: {}", apk.display(), err)))?;
+    let mut stdin = child.stdin.take().ok_or("Failed to capture install input")?;
+    let readers: Vec<_> = [child.stdout.take().map(read_all), child.stderr.take().map(read_all)]
+        .into_iter()
+        .flatten()
+        .collect();
+    // A cancel kills adb, so the next write fails and the loop below ends.
+    let mut child = CancellableChild::new(child);
+    let mut chunk = vec![0u8; WRITE_CHUNK_BYTES];
+    let mut written = 0u64;
+    progress.report("writing", 0);
+    let copied = loop {
+        let read = match file.read(&mut chunk) {
+            Ok(0) => break Ok(()),
+            Ok(read) => read,
+            Err(err) => break Err(LbError::io(format!("Failed to read {}: {}", apk.display(), err))),
+        };
+        if let Err(err) = stdin.write_all(&chunk[..read]) {
+            // The device side closed early; its output below says why.
+            lb_log!(Level::Debug, "install", "install-write stopped reading: {}", err);
+            break Ok(());
+        }
+        written += read as u64;
+        progress.report("writing", written);
+    };
+    drop(stdin);
+    let status = child.wait();
+    let exit_code = status.as_ref().ok().and_then(|status| status.code());
+    transcript::record_command(&argv, started.elapsed(), exit_code);
+    child.check().map_err(|err| err.with_command(&argv).with_serial(serial))?;
+    copied?;
+    status.map_err(|err| LbError::io(format!("Failed to wait for install-write: {}", err)))?;
+    let text: String = readers.into_iter().filter_map(|reader| reader.join().ok()).collect();
+    if written < progress.total || !text.contains("Success") {
+        return Err(LbError::new(ErrorCode::CommandFailed, format!("install-write failed: {}", text.trim()))
+            .with_command(&argv)
+            .with_serial(serial));
+    }
+    Ok(())
+}
+
+fn read_all(mut stream: impl Read + Send + 'static) -> thread::JoinHandle<String> {
+    thread::spawn(move || {
+        let mut text = String::new();
+        let _ = stream.read_to_string(&mut text);
+        text
+    })
+}
+
+/// `Success: created install session [1234]` -> `1234`.
+fn session_id(output: &str) -> Option<String> {
+    let start = output.find('[')? + 1;
+    let end = start + output[start..].find(']')?;
+    let id = &output[start..end];
+    id.chars().all(|ch| ch.is_ascii_digit()).then(|| id.to_string())
+}
+
+/// Returns the package manager's final output; device rejections come back as `Ok` text
+/// so the caller can report their reason.
+fn install_with_session(
+    serial: &str,
+    apk: &Path,
+    options: &InstallOptions,
+    progress: &mut Progress,
+) -> Result<Option<String>, LbError> {
+    // One claim from create to commit or abandon, so no other command on the device lands
+    // between the steps; the session's own commands run on this thread and pass through.
+    let _device = serial_lock::acquire(serial);
+    let mut create = vec!["shell".to_string(), "cmd".to_string(), "package".to_string(), "install-create".to_string()];
+    create.extend(options.flags());
+    create.extend(["-S".to_string(), progress.total.to_string()]);
+    let create: Vec<&str> = create.iter().map(String::as_str).collect();
+    let created = adb::run_adb(Some(serial), &create)?;
+    let Some(session) = session_id(&created.stdout).filter(|_| created.success()) else {
+        if created.stdout.contains("Failure [") {
+            return Ok(Some(created.stdout));
+        }
+        // Pre-Nougat devices lack `cmd package`; the caller falls back to `adb install`.
+        lb_log!(Level::Debug, "install", "Session API unavailable on {}: {}", serial, created.stdout.trim());
+        return Ok(None);
+    };
+    if let Err(err) = write_session(serial, &session, apk, progress) {
+        let _ = adb::shell(serial, &format!("cmd package install-abandon {}", session));
+        return if err.message.contains("Failure [") { Ok(Some(err.message)) } else { Err(err) };
+    }
+    progress.report("committing", progress.total);
+    let committed = adb::run_adb(Some(serial), &["shell", "cmd", "package", "install-commit", &session])?;
+    Ok(Some(format!("{}{}", committed.stdout, committed.stderr)))
+}
+
+fn install_with_adb(serial: &str, apk: &Path, options: &InstallOptions, progress: &mut Progress) -> Result<String, LbError> {
+    let path = apk.to_string_lossy();
+    let mut args = vec!["install".to_string()];
+    args.extend(options.flags());
+    args.push(path.into_owned());
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    progress.report("writing", 0);
+    let output = adb::run_adb(Some(serial), &args)?;
+    Ok(format!("{}{}", output.stdout, output.stderr))
+}
+
+pub fn install_apk(
+    serial: &str,
+    path: &str,
+    options_json: Option<&str>,
+    sink: Option<LineSink>,
+) -> Result<JsonValue, LbError> {
+    let options = InstallOptions::parse(options_json)?;
+    ensure_device_unlocked(serial)?;
+    let apk = Path::new(path);
+    let total = apk
+        .metadata()
+        .map_err(|err| LbError::not_found(format!("APK {} is not readable: {}", path, err)))?
+        .len();
+    let mut progress = Progress {
+        sink,
+        total,
+        last_percent: None,
+    };
+    let started = Instant::now();
+    lb_log!(Level::Info, "install", "Installing {} on {}", path, serial);
+    let installed = match install_with_session(serial, apk, &options, &mut progress) {
+        Ok(Some(output)) => Ok(("session", output)),
+        Ok(None) => install_with_adb(serial, apk, &options, &mut progress).map(|output| ("adb", output)),
+        Err(err) => Err(err),
+    };
+    let (method, output) = failure_capture::on_failure(serial, "install_apk", installed)?;
+    let success = output.contains("Success") && !output.contains("Failure");
+    let (failure_reason, failure_message, artifacts) = if success {
+        progress.report("done", total);
+        (None, None, Vec::new())
+    } else {
+        let (reason, message) = failure_reason(&output);
+        lb_log!(Level::Warn, "install", "Install of {} on {} failed: {}", path, serial, output.trim());
+        (reason, Some(message), failure_capture::capture(serial, "install_apk"))
+    };
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("path", path.into()),
+        ("success", success.into()),
+        ("method", method.into()),
+        ("failure_reason", failure_reason.into()),
+        ("failure_message", failure_message.into()),
+        ("bytes", total.into()),
+        ("duration_ms", (started.elapsed().as_millis() as u64).into()),
+        ("artifacts", artifacts.into_iter().map(JsonValue::from).collect::<Vec<_>>().into()),
+    ]))
+}
+
+/// Installs the APK at `path` on `serial` and returns `{serial, path, success, method,
+/// failure_reason, failure_message, bytes, duration_ms, artifacts}`; `artifacts` lists the
+/// failure bundle taken for a rejected install when `lb_set_failure_capture` is on. Options
+/// (all optional): `{"replace", "downgrade", "grant_permissions", "user"}` map to `-r`, `-d`,
+/// `-g`, `--user`.
+/// A package manager rejection is a result with `success: false` and the `INSTALL_FAILED_*`
+/// code in `failure_reason`; a missing file or unreachable device returns null.
+/// `callback(user_data, json)` (may be null) receives `{stage, bytes, total, percent}`
+/// progress on the calling thread while the APK is streamed through the session API.
+#[no_mangle]
+pub extern "C" fn lb_install_apk(
+    serial_ptr: *const c_char,
+    path_ptr: *const c_char,
+    options_ptr: *const c_char,
+    callback: Option<LineCallback>,
+    user_data: *mut c_void,
+) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let sink = callback.map(|callback| LineSink::new(Some(callback), user_data)).transpose();
+        let options = if options_ptr.is_null() {
+            Ok(None)
+        } else {
+            read_c_str(options_ptr, "install options").map(Some)
+        };
+        let result = sink.and_then(|sink| {
+            read_c_str(serial_ptr, "serial").and_then(|serial| {
+                read_c_str(path_ptr, "APK path")
+                    .and_then(|path| options.and_then(|options| install_apk(serial, path, options, sink)))
+            })
+        });
+        string_result(result.map(|result| result.to_string()), "install result")
+    })
+}
@@ -0,0 +1,395 @@
+use std::ffi::c_void;
+use std::io::{BufRead, BufReader, Read};
+use std::os::raw::c_char;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Instant;
+
+use crate::adb;
+use crate::device_lock::ensure_device_unlocked;
+use crate::error::{ErrorCode, LbError};
+use crate::exec::CancellableChild;
+use crate::failure_capture;
+use crate::json::{self, JsonValue};
+use crate::launch::validate_component;
+use crate::logging::{lb_log, Level};
+use crate::stream::{LineCallback, LineSink};
+use crate::transcript;
+use crate::{ffi_guard, read_c_str, string_result};
+
+/// `Activity.RESULT_OK`, which `INSTRUMENTATION_CODE:` reports for a run that finished.
+const RESULT_OK: i64 = -1;
+
+/// `INSTRUMENTATION_STATUS_CODE` values from `InstrumentationResultPrinter`.
+fn status_name(code: i64) -> Option<&'static str> {
+    match code {
+        1 => Some("start"),
+        0 => Some("pass"),
+        -1 => Some("error"),
+        -2 => Some("fail"),
+        -3 => Some("ignored"),
+        -4 => Some("assumption_failure"),
+        _ => None,
+    }
+}
+
+/// `-e key value` pairs for the runner, e.g. `{"class": "com.x.LoginTest", "size": "small"}`.
+fn runner_args(args_json: Option<&str>) -> Result<Vec<String>, LbError> {
+    let Some(source) = args_json else {
+        return Ok(Vec::new());
+    };
+    let parsed = json::parse(source)?;
+    let args = parsed.as_object().ok_or("Instrumentation args must be a JSON object")?;
+    let mut argv = Vec::new();
+    for (key, value) in args {
+        let valid_key = !key.is_empty() && key.chars().all(|ch| ch.is_ascii_alphanumeric() || "._-".contains(ch));
+        if !valid_key {
+            return Err(format!("Invalid instrumentation arg name: {}", key).into());
+        }
+        let rendered = match value {
+            JsonValue::String(text) => text.clone(),
+            JsonValue::Bool(flag) => flag.to_string(),
+            JsonValue::Number(number) => number.to_string(),
+            _ => return Err(format!("Instrumentation arg '{}' must be a string, number or boolean", key).into()),
+        };
+        argv.push(format!("-e {} {}", key, adb::shell_quote(&rendered)));
+    }
+    Ok(argv)
+}
+
+/// Which bundle a continuation line (a stack trace, the result stream) belongs to.
+enum Target {
+    None,
+    Status,
+    Result,
+}
+
+struct TestRecord {
+    class: String,
+    test: String,
+    status: &'static str,
+    duration_ms: Option<u64>,
+    stack: Option<String>,
+}
+
+/// Incremental parser for `am instrument -r` output: `INSTRUMENTATION_STATUS: key=value`
+/// lines build a bundle that `INSTRUMENTATION_STATUS_CODE: n` closes; values can span lines.
+struct RawProtocol {
+    sink: Option<LineSink>,
+    status: Vec<(String, String)>,
+    result: Vec<(String, String)>,
+    target: Target,
+    result_code: Option<i64>,
+    failure: Option<String>,
+    total: Option<u64>,
+    started: Option<(String, String, Instant)>,
+    tests: Vec<TestRecord>,
+    other: Vec<String>,
+}
+
+impl RawProtocol {
+    fn new(sink: Option<LineSink>) -> RawProtocol {
+        RawProtocol {
+            sink,
+            status: Vec::new(),
+            result: Vec::new(),
+            target: Target::None,
+            result_code: None,
+            failure: None,
+            total: None,
+            started: None,
+            tests: Vec::new(),
+            other: Vec::new(),
+        }
+    }
+
+    fn line(&mut self, line: &str) {
+        let line = line.trim_end_matches('\r');
+        if let Some(pair) = line.strip_prefix("INSTRUMENTATION_STATUS: ") {
+            self.status.push(split_pair(pair));
+            self.target = Target::Status;
+        } else if let Some(code) = line.strip_prefix("INSTRUMENTATION_STATUS_CODE: ") {
+            self.target = Target::None;
+            let bundle = std::mem::take(&mut self.status);
+            if let Some(code) = code.trim().parse().ok().and_then(status_name) {
+                self.status_event(code, &bundle);
+            }
+        } else if let Some(pair) = line.strip_prefix("INSTRUMENTATION_RESULT: ") {
+            self.result.push(split_pair(pair));
+            self.target = Target::Result;
+        } else if let Some(code) = line.strip_prefix("INSTRUMENTATION_CODE: ") {
+            self.target = Target::None;
+            self.result_code = code.trim().parse().ok();
+        } else if let Some(message) = line.strip_prefix("INSTRUMENTATION_FAILED: ") {
+            self.target = Target::None;
+            self.failure = Some(message.trim().to_string());
+        } else {
+            let bundle = match self.target {
+                Target::Status => &mut self.status,
+                Target::Result => &mut self.result,
+                Target::None => {
+                    if !line.trim().is_empty() {
+                        self.other.push(line.to_string());
+                    }
+                    return;
+                }
+            };
+            if let Some((_, value)) = bundle.last_mut() {
+                value.push('\n');
+                value.push_str(line);
+            }
+        }
+    }
+
+    fn status_event(&mut self, status: &'static str, bundle: &[(String, String)]) {
+        let field = |key: &str| bundle.iter().find(|(name, _)| name == key).map(|(_, value)| value.as_str());
+        let class = field("class").unwrap_or_default().to_string();
+        let test = field("test").unwrap_or_default().to_string();
+        let current = field("current").and_then(|value| value.trim().parse::<u64>().ok());
+        if let Some(total) = field("numtests").and_then(|value| value.trim().parse().ok()) {
+            self.total = Some(total);
+        }
+        let mut event = vec![
+            ("event", status.into()),
+            ("class", class.as_str().into()),
+            ("test", test.as_str().into()),
+            ("current", current.into()),
+            ("total", self.total.into()),
+        ];
+        if status == "start" {
+            self.started = Some((class.clone(), test.clone(), Instant::now()));
+        } else {
+            let duration_ms = match self.started.take() {
+                Some((started_class, started_test, at)) if started_class == class && started_test == test => {
+                    Some(at.elapsed().as_millis() as u64)
+                }
+                _ => None,
+            };
+            let stack = field("stack").map(|stack| stack.trim_end().to_string());
+            event.push(("duration_ms", duration_ms.into()));
+            event.push(("stack", stack.as_deref().into()));
+            self.tests.push(TestRecord {
+                class,
+                test,
+                status,
+                duration_ms,
+                stack,
+            });
+        }
+        if let Some(sink) = &self.sink {
+            sink.emit(&JsonValue::object(event).to_string());
+        }
+    }
+
+    /// A test that started but never reported back, because the process crashed.
+    fn close_pending(&mut self) {
+        let Some((class, test, at)) = self.started.take() else {
+            return;
+        };
+        let reason = self.result_field("shortMsg").unwrap_or("Test did not finish").to_string();
+        let duration_ms = at.elapsed().as_millis() as u64;
+        if let Some(sink) = &self.sink {
+            sink.emit(
+                &JsonValue::object(vec![
+                    ("event", "error".into()),
+                    ("class", class.as_str().into()),
+                    ("test", test.as_str().into()),
+                    ("current", JsonValue::Null),
+                    ("total", self.total.into()),
+                    ("duration_ms", duration_ms.into()),
+                    ("stack", reason.as_str().into()),
+                ])
+                .to_string(),
+            );
+        }
+        self.tests.push(TestRecord {
+            class,
+            test,
+            status: "error",
+            duration_ms: Some(duration_ms),
+            stack: Some(reason),
+        });
+    }
+
+    fn result_field(&self, key: &str) -> Option<&str> {
+        self.result
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn count(&self, status: &str) -> usize {
+        self.tests.iter().filter(|test| test.status == status).count()
+    }
+}
+
+/// `key=value`; a value may itself contain `=`.
+fn split_pair(pair: &str) -> (String, String) {
+    match pair.split_once('=') {
+        Some((key, value)) => (key.to_string(), value.to_string()),
+        None => (pair.to_string(), String::new()),
+    }
+}
+
+fn run_instrumentation(
+    serial: &str,
+    runner: &str,
+    args_json: Option<&str>,
+    sink: Option<LineSink>,
+) -> Result<String, LbError> {
+    validate_component(runner)?;
+    let mut command = vec!["am instrument -r -w".to_string()];
+    command.extend(runner_args(args_json)?);
+    command.push(runner.to_string());
+    let command = command.join(" ");
+    ensure_device_unlocked(serial)?;
+
+    let argv = adb::adb_argv(Some(serial), &["shell", &command]);
+    lb_log!(Level::Info, "exec", "Running {} on {}", runner, serial);
+    let started = Instant::now();
+    let mut child = Command::new(&argv[0])
+        .args(&argv[1..])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| {
+            LbError::new(ErrorCode::SpawnFailed, format!("Failed to spawn {}: {}", argv[0], err))
+                .with_command(&argv)
+                .with_serial(serial)
+        })?;
+    let stderr = child.stderr.take().map(|mut stderr| {
+        thread::spawn(move || {
+            let mut text = String::new();
+            let _ = stderr.read_to_string(&mut text);
+            text
+        })
+    });
+    let stdout = child.stdout.take();
+    let mut child = CancellableChild::new(child);
+    let mut protocol = RawProtocol::new(sink);
+    if let Some(stdout) = stdout {
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            protocol.line(&line);
+        }
+    }
+    let status = child
+        .wait()
+        .map_err(|err| LbError::io(format!("Failed to wait for am instrument: {}", err)))?;
+    transcript::record_command(&argv, started.elapsed(), status.code());
+    child.check().map_err(|err| err.with_serial(serial).with_command(&argv))?;
+    let errors = stderr.and_then(|reader| reader.join().ok()).unwrap_or_default();
+    protocol.close_pending();
+
+    // Nothing ran: the runner is not installed, or adb itself failed.
+    if protocol.tests.is_empty() && protocol.result_code.is_none() {
+        let detail = protocol
+            .failure
+            .iter()
+            .chain(&protocol.other)
+            .map(String::as_str)
+            .chain(errors.lines())
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let detail = detail.as_str();
+        let code = if detail.contains("Unable to find instrumentation") || protocol.failure.is_some() {
+            ErrorCode::NotFound
+        } else {
+            adb::classify_failure(detail)
+        };
+        let err = LbError::new(code, format!("am instrument {} failed: {}", runner, detail))
+            .with_serial(serial)
+            .with_command(&argv);
+        return failure_capture::on_failure(serial, "run_instrumentation", Err(err));
+    }
+
+    let (passed, failed, errored) = (protocol.count("pass"), protocol.count("fail"), protocol.count("error"));
+    let short_msg = protocol.result_field("shortMsg").map(str::to_string);
+    let completed = protocol.result_code == Some(RESULT_OK) && short_msg.is_none();
+    let success = completed && failed == 0 && errored == 0;
+    let duration_ms = started.elapsed().as_millis() as u64;
+    lb_log!(
+        Level::Info,
+        "exec",
+        "{} on {}: {} passed, {} failed, {} errors in {} ms",
+        runner,
+        serial,
+        passed,
+        failed,
+        errored,
+        duration_ms
+    );
+    let tests: Vec<JsonValue> = protocol
+        .tests
+        .iter()
+        .map(|test| {
+            JsonValue::object(vec![
+                ("class", test.class.as_str().into()),
+                ("test", test.test.as_str().into()),
+                ("status", test.status.into()),
+                ("duration_ms", test.duration_ms.into()),
+                ("stack", test.stack.as_deref().into()),
+            ])
+        })
+        .collect();
+    let artifacts = if success {
+        Vec::new()
+    } else {
+        failure_capture::capture(serial, "run_instrumentation")
+    };
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("runner", runner.into()),
+        ("success", success.into()),
+        ("completed", completed.into()),
+        ("total", protocol.total.into()),
+        ("passed", passed.into()),
+        ("failed", failed.into()),
+        ("errors", errored.into()),
+        ("ignored", protocol.count("ignored").into()),
+        ("assumption_failures", protocol.count("assumption_failure").into()),
+        ("result_code", protocol.result_code.into()),
+        ("short_msg", short_msg.into()),
+        ("message", protocol.result_field("stream").map(str::trim).into()),
+        ("tests", tests.into()),
+        ("duration_ms", duration_ms.into()),
+        ("artifacts", artifacts.into_iter().map(JsonValue::from).collect::<Vec<_>>().into()),
+    ])
+    .to_string())
+}
+
+/// Runs `am instrument -r -w` for `runner` (`com.example.test/androidx.test.runner.
+/// AndroidJUnitRunner`) with `args_json` (null or `{"class": "com.example.LoginTest", ...}`, passed as `-e` pairs)
+/// and blocks until the run ends. The optional callback gets `{event, class, test, current,
+/// total}` on the calling thread as each test starts, plus `duration_ms` and `stack` when it
+/// finishes (`event` is `start`, `pass`, `fail`, `error`, `ignored` or `assumption_failure`).
+/// Returns `{serial, runner, success, completed, total, passed, failed, errors, ignored,
+/// assumption_failures, result_code, short_msg, message, tests, duration_ms, artifacts}`; a
+/// crash shows up as `completed: false` with `short_msg`. `artifacts` lists the failure bundle
+/// taken for an unsuccessful run when `lb_set_failure_capture` is on. Runs on different serials may go in parallel.
+#[no_mangle]
+pub extern "C" fn lb_run_instrumentation(
+    serial_ptr: *const c_char,
+    runner_ptr: *const c_char,
+    args_json_ptr: *const c_char,
+    callback: Option<LineCallback>,
+    user_data: *mut c_void,
+) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let sink = callback.map(|callback| LineSink::new(Some(callback), user_data)).transpose();
+        let args = if args_json_ptr.is_null() {
+            Ok(None)
+        } else {
+            read_c_str(args_json_ptr, "instrumentation args").map(Some)
+        };
+        let result = sink.and_then(|sink| {
+            read_c_str(serial_ptr, "serial").and_then(|serial| {
+                read_c_str(runner_ptr, "runner")
+                    .and_then(|runner| args.and_then(|args| run_instrumentation(serial, runner, args, sink)))
+            })
+        });
+        string_result(result, "instrumentation result")
+    })
+}
@@ -0,0 +1,271 @@
+use std::os::raw::c_char;
+use std::time::Instant;
+
+use crate::adb;
+use crate::device_lock::ensure_device_unlocked;
+use crate::error::{ErrorCode, LbError};
+use crate::json::{self, JsonValue};
+use crate::logging::{lb_log, Level};
+use crate::{ffi_guard, read_c_str, string_result};
+
+/// `lb_send_intent` flags.
+const INTENT_BROADCAST: u32 = 1;
+const INTENT_WAIT: u32 = 2;
+
+fn integer(value: &JsonValue) -> Option<i64> {
+    value
+        .as_f64()
+        .filter(|number| number.fract() == 0.0 && number.abs() < 9.0e15)
+        .map(|number| number as i64)
+}
+
+/// An explicitly typed extra, `{"type": "long", "value": 1700000000000}`, for types plain
+/// JSON cannot express. `long` also takes a decimal string for values past 2^53.
+fn typed_extra(key: &str, extra: &JsonValue) -> Result<(&'static str, Option<String>), LbError> {
+    let kind = extra
+        .get("type")
+        .and_then(JsonValue::as_str)
+        .ok_or_else(|| LbError::from(format!("Intent extra '{}' must have a string 'type'", key)))?;
+    let value = extra.get("value").unwrap_or(&JsonValue::Null);
+    let invalid = || LbError::from(format!("Intent extra '{}' has a value that is not a valid {}", key, kind));
+    let text = || value.as_str().map(str::to_string).ok_or_else(invalid);
+    let rendered = match kind {
+        "string" => ("--es", text()?),
+        "uri" => ("--eu", text()?),
+        "component" => ("--ecn", text()?),
+        "bool" => ("--ez", value.as_bool().ok_or_else(invalid)?.to_string()),
+        "int" => (
+            "--ei",
+            integer(value)
+                .filter(|number| i32::try_from(*number).is_ok())
+                .ok_or_else(invalid)?
+                .to_string(),
+        ),
+        "long" => match value {
+            JsonValue::String(digits) => ("--el", digits.parse::<i64>().map_err(|_| invalid())?.to_string()),
+            _ => ("--el", integer(value).ok_or_else(invalid)?.to_string()),
+        },
+        "float" => ("--ef", value.as_f64().ok_or_else(invalid)?.to_string()),
+        "null" => return Ok(("--esn", None)),
+        _ => {
+            return Err(format!(
+                "Intent extra '{}' has unknown type '{}'; expected string, int, long, float, bool, uri, component or null",
+                key, kind
+            )
+            .into())
+        }
+    };
+    Ok((rendered.0, Some(rendered.1)))
+}
+
+/// `am` extra arguments, shell-quoted. Plain values are typed by their JSON shape:
+/// strings `--es`, booleans `--ez`, integers `--ei` (`--el` past i32), other numbers
+/// `--ef`, null `--esn`, and homogeneous arrays `--esa` / `--eia` / `--ela` / `--efa`.
+/// `{"type", "value"}` objects pick the type explicitly (see `typed_extra`).
+pub fn extra_args(extras: &[(String, JsonValue)]) -> Result<Vec<String>, LbError> {
+    let fits_i32 = |number: i64| i32::try_from(number).is_ok();
+    let mut args = Vec::new();
+    for (key, value) in extras {
+        if key.is_empty() {
+            return Err("Intent extra keys must not be empty".into());
+        }
+        let (flag, rendered) = match value {
+            JsonValue::Null => ("--esn", None),
+            JsonValue::String(text) => ("--es", Some(text.clone())),
+            JsonValue::Bool(flag) => ("--ez", Some(flag.to_string())),
+            JsonValue::Number(number) => match integer(value) {
+                Some(whole) if fits_i32(whole) => ("--ei", Some(whole.to_string())),
+                Some(whole) => ("--el", Some(whole.to_string())),
+                None => ("--ef", Some(number.to_string())),
+            },
+            JsonValue::Array(items) => {
+                let strings: Option<Vec<String>> = items
+                    .iter()
+                    .map(|item| item.as_str().map(|text| text.replace(',', "\\,")))
+                    .collect();
+                let integers: Option<Vec<i64>> = items.iter().map(integer).collect();
+                let floats: Option<Vec<f64>> = items.iter().map(JsonValue::as_f64).collect();
+                let joined = |values: Vec<String>| Some(values.join(","));
+                match (strings, integers, floats) {
+                    (Some(strings), _, _) => ("--esa", joined(strings)),
+                    (_, Some(integers), _) => {
+                        let flag = if integers.iter().all(|number| fits_i32(*number)) { "--eia" } else { "--ela" };
+                        (flag, joined(integers.iter().map(i64::to_string).collect()))
+                    }
+                    (_, _, Some(floats)) => ("--efa", joined(floats.iter().map(f64::to_string).collect())),
+                    _ => return Err(format!("Intent extra '{}' must be an array of strings or numbers", key).into()),
+                }
+            }
+            JsonValue::Object(_) => typed_extra(key, value)?,
+        };
+        args.push(flag.to_string());
+        args.push(adb::shell_quote(key));
+        if let Some(rendered) = rendered {
+            args.push(adb::shell_quote(&rendered));
+        }
+    }
+    Ok(args)
+}
+
+/// `Key: value` from `am` output (`Status: ok`, `TotalTime: 412`).
+pub fn am_field<'a>(output: &'a str, key: &str) -> Option<&'a str> {
+    let prefix = format!("{}:", key);
+    output
+        .lines()
+        .find_map(|line| line.trim().strip_prefix(&prefix).map(str::trim))
+}
+
+/// The `Warning:` line am prints when, e.g., the activity was only brought to the front.
+pub fn am_warning(output: &str) -> Option<String> {
+    output
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Warning:"))
+        .map(|warning| warning.trim().to_string())
+}
+
+/// Runs an `am start` / `am broadcast` command line and returns its output and duration.
+/// am exits 0 for most failures, so `Error:` lines become NotFound (nothing resolves the
+/// intent) or CommandFailed errors, and output without `expected` (`Starting:`,
+/// `Broadcasting:`) is treated as an adb-level failure.
+pub fn run_am(serial: &str, command: &str, expected: &str) -> Result<(String, u64), LbError> {
+    let started = Instant::now();
+    let output = adb::run_adb(Some(serial), &["shell", command])?;
+    let duration_ms = started.elapsed().as_millis() as u64;
+    let text = format!("{}{}", output.stdout, output.stderr);
+    if let Some(error) = text.lines().map(str::trim).find_map(|line| line.strip_prefix("Error:")) {
+        let code = if error.contains("does not exist") || error.contains("Unable to resolve") {
+            ErrorCode::NotFound
+        } else {
+            ErrorCode::CommandFailed
+        };
+        return Err(LbError::new(code, format!("{} failed: {}", command, error.trim())).with_serial(serial));
+    }
+    if !text.contains(expected) {
+        let detail = text.trim();
+        return Err(LbError::new(adb::classify_failure(detail), format!("{} failed: {}", command, detail)).with_serial(serial));
+    }
+    Ok((text, duration_ms))
+}
+
+/// `android.intent.action.VIEW`-style names; am would misparse anything else.
+fn validate_action(action: &str) -> Result<(), LbError> {
+    let valid = !action.is_empty() && action.chars().all(|ch| ch.is_ascii_alphanumeric() || "._-".contains(ch));
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("Invalid intent action: {}", action).into())
+    }
+}
+
+/// `Broadcast completed: result=-1, data="done"`.
+fn broadcast_result(output: &str) -> (Option<i64>, Option<String>) {
+    let Some(completed) = output.lines().find_map(|line| line.trim().strip_prefix("Broadcast completed:")) else {
+        return (None, None);
+    };
+    let code = completed
+        .split_once("result=")
+        .and_then(|(_, rest)| rest.split(',').next())
+        .and_then(|code| code.trim().parse().ok());
+    let data = completed
+        .split_once("data=\"")
+        .and_then(|(_, rest)| rest.rsplit_once('"'))
+        .map(|(data, _)| data.to_string());
+    (code, data)
+}
+
+fn send_intent(
+    serial: &str,
+    action: Option<&str>,
+    data_uri: Option<&str>,
+    extras_json: Option<&str>,
+    flags: u32,
+) -> Result<String, LbError> {
+    if action.is_none() && data_uri.is_none() {
+        return Err("An intent needs an action or a data URI".into());
+    }
+    if let Some(action) = action {
+        validate_action(action)?;
+    }
+    let broadcast = flags & INTENT_BROADCAST != 0;
+    if broadcast && flags & INTENT_WAIT != 0 {
+        return Err("The wait flag only applies to activity starts".into());
+    }
+    let extras = match extras_json {
+        None => Vec::new(),
+        Some(source) => json::parse(source)?
+            .as_object()
+            .cloned()
+            .ok_or("Intent extras must be a JSON object")?,
+    };
+    ensure_device_unlocked(serial)?;
+
+    let mut command = vec![if broadcast { "am broadcast" } else { "am start" }.to_string()];
+    if flags & INTENT_WAIT != 0 {
+        command.push("-W".to_string());
+    }
+    if let Some(action) = action {
+        command.push(format!("-a {}", action));
+    }
+    if let Some(uri) = data_uri {
+        command.push(format!("-d {}", adb::shell_quote(uri)));
+    }
+    command.extend(extra_args(&extras)?);
+    let command = command.join(" ");
+    lb_log!(Level::Info, "exec", "Sending intent on {}: {}", serial, command);
+    let (output, duration_ms) = run_am(serial, &command, if broadcast { "Broadcasting:" } else { "Starting:" })?;
+
+    let mut result = vec![
+        ("serial", serial.into()),
+        ("kind", if broadcast { "broadcast" } else { "start" }.into()),
+        ("action", action.into()),
+        ("data_uri", data_uri.into()),
+    ];
+    if broadcast {
+        let (code, data) = broadcast_result(&output);
+        result.push(("result_code", code.into()));
+        result.push(("result_data", data.into()));
+    } else {
+        result.push(("status", am_field(&output, "Status").into()));
+        result.push(("activity", am_field(&output, "Activity").into()));
+        result.push(("launch_state", am_field(&output, "LaunchState").into()));
+        result.push(("total_time_ms", am_field(&output, "TotalTime").and_then(|time| time.parse::<u64>().ok()).into()));
+        result.push(("warning", am_warning(&output).into()));
+    }
+    result.push(("duration_ms", duration_ms.into()));
+    Ok(JsonValue::object(result).to_string())
+}
+
+/// Sends an intent built from `action` and `data_uri` (either may be null, not both) and
+/// `extras_json` (null or an object; see `extra_args` for typing, with `{"type": "uri" |
+/// "long" | ..., "value"}` for explicit types). `flags`: 1 = `am broadcast` instead of
+/// `am start`, 2 = wait for the launch (`-W`). Returns `{serial, kind, action, data_uri,
+/// status, activity, launch_state, total_time_ms, warning, duration_ms}` for starts and
+/// `{..., result_code, result_data, duration_ms}` for broadcasts. An intent nothing
+/// resolves is a NotFound error.
+#[no_mangle]
+pub extern "C" fn lb_send_intent(
+    serial_ptr: *const c_char,
+    action_ptr: *const c_char,
+    data_uri_ptr: *const c_char,
+    extras_json_ptr: *const c_char,
+    flags: u32,
+) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let optional = |ptr: *const c_char, what: &str| {
+            if ptr.is_null() {
+                Ok(None)
+            } else {
+                read_c_str(ptr, what).map(Some)
+            }
+        };
+        let result = read_c_str(serial_ptr, "serial").and_then(|serial| {
+            optional(action_ptr, "action").and_then(|action| {
+                optional(data_uri_ptr, "data URI").and_then(|data_uri| {
+                    optional(extras_json_ptr, "intent extras")
+                        .and_then(|extras| send_intent(serial, action, data_uri, extras, flags))
+                })
+            })
+        });
+        string_result(result, "intent result")
+    })
+}
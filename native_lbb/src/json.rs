@@ -0,0 +1,392 @@
+use std::fmt::{self, Write as _};
+
+use crate::error::LbError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<JsonValue>),
+    Object(Vec<(String, JsonValue)>),
+}
+
+impl JsonValue {
+    pub fn object(pairs: Vec<(&str, JsonValue)>) -> JsonValue {
+        JsonValue::Object(
+            pairs
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+
+    pub fn get(&self, key: &str) -> Option<&JsonValue> {
+        match self {
+            JsonValue::Object(pairs) => pairs.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            JsonValue::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            JsonValue::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            JsonValue::Number(value) if value.fract() == 0.0 && *value >= 0.0 => Some(*value as u64),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            JsonValue::Number(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<JsonValue>> {
+        match self {
+            JsonValue::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn as_string_array(&self) -> Option<Vec<String>> {
+        self.as_array()?
+            .iter()
+            .map(|item| item.as_str().map(str::to_string))
+            .collect()
+    }
+
+    pub fn as_object(&self) -> Option<&Vec<(String, JsonValue)>> {
+        match self {
+            JsonValue::Object(pairs) => Some(pairs),
+            _ => None,
+        }
+    }
+}
+
+impl From<bool> for JsonValue {
+    fn from(value: bool) -> Self {
+        JsonValue::Bool(value)
+    }
+}
+
+impl From<&str> for JsonValue {
+    fn from(value: &str) -> Self {
+        JsonValue::String(value.to_string())
+    }
+}
+
+impl From<String> for JsonValue {
+    fn from(value: String) -> Self {
+        JsonValue::String(value)
+    }
+}
+
+impl From<&String> for JsonValue {
+    fn from(value: &String) -> Self {
+        JsonValue::String(value.clone())
+    }
+}
+
+impl From<f64> for JsonValue {
+    fn from(value: f64) -> Self {
+        JsonValue::Number(value)
+    }
+}
+
+macro_rules! json_from_integer {
+    ($($ty:ty),*) => {
+        $(
+            impl From<$ty> for JsonValue {
+                fn from(value: $ty) -> Self {
+                    JsonValue::Number(value as f64)
+                }
+            }
+        )*
+    };
+}
+
+json_from_integer!(i32, i64, u32, u64, usize);
+
+impl From<Vec<JsonValue>> for JsonValue {
+    fn from(value: Vec<JsonValue>) -> Self {
+        JsonValue::Array(value)
+    }
+}
+
+impl<T: Into<JsonValue>> From<Option<T>> for JsonValue {
+    fn from(value: Option<T>) -> Self {
+        match value {
+            Some(inner) => inner.into(),
+            None => JsonValue::Null,
+        }
+    }
+}
+
+fn write_escaped(out: &mut fmt::Formatter<'_>, value: &str) -> fmt::Result {
+    out.write_char('"')?;
+    for ch in value.chars() {
+        match ch {
+            '"' => out.write_str("\\\"")?,
+            '\\' => out.write_str("\\\\")?,
+            '\n' => out.write_str("\\n")?,
+            '\r' => out.write_str("\\r")?,
+            '\t' => out.write_str("\\t")?,
+            ch if (ch as u32) < 0x20 => write!(out, "\\u{:04x}", ch as u32)?,
+            _ => out.write_char(ch)?,
+        }
+    }
+    out.write_char('"')
+}
+
+impl fmt::Display for JsonValue {
+    fn fmt(&self, out: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JsonValue::Null => out.write_str("null"),
+            JsonValue::Bool(value) => out.write_str(if *value { "true" } else { "false" }),
+            JsonValue::Number(value) => {
+                if !value.is_finite() {
+                    out.write_str("null")
+                } else if value.fract() == 0.0 && value.abs() < 1e15 {
+                    write!(out, "{}", *value as i64)
+                } else {
+                    write!(out, "{}", value)
+                }
+            }
+            JsonValue::String(value) => write_escaped(out, value),
+            JsonValue::Array(items) => {
+                out.write_char('[')?;
+                for (idx, item) in items.iter().enumerate() {
+                    if idx > 0 {
+                        out.write_char(',')?;
+                    }
+                    write!(out, "{}", item)?;
+                }
+                out.write_char(']')
+            }
+            JsonValue::Object(pairs) => {
+                out.write_char('{')?;
+                for (idx, (key, value)) in pairs.iter().enumerate() {
+                    if idx > 0 {
+                        out.write_char(',')?;
+                    }
+                    write_escaped(out, key)?;
+                    out.write_char(':')?;
+                    write!(out, "{}", value)?;
+                }
+                out.write_char('}')
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    source: &'a str,
+    index: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_whitespace(&mut self) {
+        while self.index < self.bytes.len() && self.bytes[self.index].is_ascii_whitespace() {
+            self.index += 1;
+        }
+    }
+
+    fn expect_literal(&mut self, literal: &str, value: JsonValue) -> Result<JsonValue, String> {
+        if self.source[self.index..].starts_with(literal) {
+            self.index += literal.len();
+            Ok(value)
+        } else {
+            Err(format!("Unexpected token at offset {}", self.index))
+        }
+    }
+
+    fn parse_value(&mut self, depth: usize) -> Result<JsonValue, String> {
+        if depth > 128 {
+            return Err("JSON nesting too deep".into());
+        }
+        self.skip_whitespace();
+        if self.index >= self.bytes.len() {
+            return Err("Unexpected end of JSON input".into());
+        }
+        match self.bytes[self.index] {
+            b'n' => self.expect_literal("null", JsonValue::Null),
+            b't' => self.expect_literal("true", JsonValue::Bool(true)),
+            b'f' => self.expect_literal("false", JsonValue::Bool(false)),
+            b'"' => self.parse_string().map(JsonValue::String),
+            b'[' => {
+                self.index += 1;
+                let mut items = Vec::new();
+                self.skip_whitespace();
+                if self.index < self.bytes.len() && self.bytes[self.index] == b']' {
+                    self.index += 1;
+                    return Ok(JsonValue::Array(items));
+                }
+                loop {
+                    items.push(self.parse_value(depth + 1)?);
+                    self.skip_whitespace();
+                    match self.bytes.get(self.index) {
+                        Some(b',') => self.index += 1,
+                        Some(b']') => {
+                            self.index += 1;
+                            return Ok(JsonValue::Array(items));
+                        }
+                        _ => return Err(format!("Expected ',' or ']' at offset {}", self.index)),
+                    }
+                }
+            }
+            b'{' => {
+                self.index += 1;
+                let mut pairs = Vec::new();
+                self.skip_whitespace();
+                if self.index < self.bytes.len() && self.bytes[self.index] == b'}' {
+                    self.index += 1;
+                    return Ok(JsonValue::Object(pairs));
+                }
+                loop {
+                    self.skip_whitespace();
+                    if self.bytes.get(self.index) != Some(&b'"') {
+                        return Err(format!("Expected object key at offset {}", self.index));
+                    }
+                    let key = self.parse_string()?;
+                    self.skip_whitespace();
+                    if self.bytes.get(self.index) != Some(&b':') {
+                        return Err(format!("Expected ':' at offset {}", self.index));
+                    }
+                    self.index += 1;
+                    let value = self.parse_value(depth + 1)?;
+                    pairs.push((key, value));
+                    self.skip_whitespace();
+                    match self.bytes.get(self.index) {
+                        Some(b',') => self.index += 1,
+                        Some(b'}') => {
+                            self.index += 1;
+                            return Ok(JsonValue::Object(pairs));
+                        }
+                        _ => return Err(format!("Expected ',' or '}}' at offset {}", self.index)),
+                    }
+                }
+            }
+            b'-' | b'0'..=b'9' => self.parse_number(),
+            _ => Err(format!("Unexpected character at offset {}", self.index)),
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<JsonValue, String> {
+        let start = self.index;
+        while self.index < self.bytes.len() {
+            match self.bytes[self.index] {
+                b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9' => self.index += 1,
+                _ => break,
+            }
+        }
+        self.source[start..self.index]
+            .parse::<f64>()
+            .map(JsonValue::Number)
+            .map_err(|_| format!("Invalid number at offset {}", start))
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, String> {
+        let digits = match self.source.get(self.index..self.index + 4) {
+            Some(digits) => digits,
+            None => return Err("Truncated unicode escape".into()),
+        };
+        self.index += 4;
+        u32::from_str_radix(digits, 16).map_err(|_| "Invalid unicode escape".to_string())
+    }
+
+    fn parse_string(&mut self) -> Result<String, String> {
+        self.index += 1;
+        let mut value = String::new();
+        loop {
+            let start = self.index;
+            while self.index < self.bytes.len() && self.bytes[self.index] != b'"' && self.bytes[self.index] != b'\\' {
+                self.index += 1;
+            }
+            value.push_str(&self.source[start..self.index]);
+            match self.bytes.get(self.index) {
+                Some(b'"') => {
+                    self.index += 1;
+                    return Ok(value);
+                }
+                Some(b'\\') => {
+                    self.index += 1;
+                    let escape = match self.bytes.get(self.index) {
+                        Some(byte) => *byte,
+                        None => return Err("Unterminated string".into()),
+                    };
+                    self.index += 1;
+                    match escape {
+                        b'"' => value.push('"'),
+                        b'\\' => value.push('\\'),
+                        b'/' => value.push('/'),
+                        b'b' => value.push('\u{0008}'),
+                        b'f' => value.push('\u{000c}'),
+                        b'n' => value.push('\n'),
+                        b'r' => value.push('\r'),
+                        b't' => value.push('\t'),
+                        b'u' => {
+                            let mut code = self.parse_hex4()?;
+                            if (0xD800..0xDC00).contains(&code) && self.source[self.index..].starts_with("\\u") {
+                                self.index += 2;
+                                let low = self.parse_hex4()?;
+                                code = 0x10000 + ((code - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF);
+                            }
+                            value.push(char::from_u32(code).unwrap_or('\u{FFFD}'));
+                        }
+                        _ => return Err(format!("Invalid escape at offset {}", self.index - 1)),
+                    }
+                }
+                _ => return Err("Unterminated string".into()),
+            }
+        }
+    }
+}
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Standard padded base64, for binary fields such as screenshots embedded in JSON results.
+pub fn base64_encode(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for group in data.chunks(3) {
+        let bits = group.iter().enumerate().fold(0u32, |bits, (index, byte)| bits | u32::from(*byte) << (16 - 8 * index));
+        for index in 0..4 {
+            if index <= group.len() {
+                encoded.push(BASE64_ALPHABET[(bits >> (18 - 6 * index) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+pub fn parse(source: &str) -> Result<JsonValue, LbError> {
+    let mut parser = Parser {
+        bytes: source.as_bytes(),
+        source,
+        index: 0,
+    };
+    let value = parser.parse_value(0).map_err(LbError::parse)?;
+    parser.skip_whitespace();
+    if parser.index != parser.bytes.len() {
+        return Err(LbError::parse(format!("Trailing characters at offset {}", parser.index)));
+    }
+    Ok(value)
+}
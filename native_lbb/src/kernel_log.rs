@@ -0,0 +1,97 @@
+use std::ffi::c_void;
+use std::os::raw::c_char;
+
+use crate::adb;
+use crate::error::{ErrorCode, LbError};
+use crate::stream::{self, LineCallback, LineSink};
+use crate::{ffi_guard, handle_result, read_c_str, string_result};
+
+/// Ways to reach the kernel ring buffer, tried in order: plain shell (or adbd root), AOSP
+/// `su uid cmd`, then Magisk-style `su -c cmd`. Devices with `dmesg_restrict=1` need one of the latter.
+const ACCESS_PREFIXES: [&str; 3] = ["", "su 0 ", "su -c "];
+
+fn dmesg_command(prefix: &str, args: &str) -> String {
+    let command = format!("dmesg{}", args);
+    match prefix {
+        "su -c " => format!("su -c '{}'", command),
+        _ => format!("{}{}", prefix, command),
+    }
+}
+
+/// Finds the first access method that can read the ring buffer and returns it with the dump.
+fn probe_access(serial: &str) -> Result<(&'static str, String), LbError> {
+    let mut last_error = String::new();
+    for prefix in ACCESS_PREFIXES {
+        match adb::shell(serial, &dmesg_command(prefix, "")) {
+            Ok(output) if !output.trim().is_empty() => return Ok((prefix, output)),
+            Ok(_) => last_error = "dmesg returned no output".into(),
+            Err(err) => last_error = err.message,
+        }
+    }
+    Err(LbError::new(
+        ErrorCode::PermissionDenied,
+        format!(
+            "Kernel log unavailable on {}: dmesg is restricted and no root access was found ({})",
+            serial, last_error
+        ),
+    )
+    .with_serial(serial))
+}
+
+/// Kernel timestamp in seconds since boot from `[  123.456789] ...` (optionally after a `<6>` level).
+fn timestamp(line: &str) -> Option<f64> {
+    let start = line.find('[').filter(|start| *start <= 4)?;
+    let end = start + line[start..].find(']')?;
+    line[start + 1..end].trim().parse().ok()
+}
+
+fn is_since(line: &str, since_secs: f64) -> bool {
+    since_secs <= 0.0 || timestamp(line).is_none_or(|ts| ts >= since_secs)
+}
+
+fn get_kernel_log(serial: &str, since_secs: f64) -> Result<String, LbError> {
+    let (_, output) = probe_access(serial)?;
+    let mut log = String::with_capacity(output.len());
+    for line in output.lines().filter(|line| is_since(line, since_secs)) {
+        log.push_str(line);
+        log.push('\n');
+    }
+    Ok(log)
+}
+
+fn follow_kernel_log(serial: &str, since_secs: f64, sink: LineSink) -> Result<u64, LbError> {
+    let (prefix, _) = probe_access(serial)?;
+    let argv = adb::adb_argv(Some(serial), &["shell", &dmesg_command(prefix, " -w")]);
+    stream::spawn_line_stream(&argv, move |line| {
+        if is_since(line, since_secs) {
+            sink.emit(line);
+        }
+    })
+}
+
+/// Returns the kernel ring buffer, keeping lines stamped at or after `since_secs` seconds
+/// since boot (0 keeps everything). Falls back to `su` when `dmesg` is restricted.
+#[no_mangle]
+pub extern "C" fn lb_get_kernel_log(serial_ptr: *const c_char, since_secs: f64) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(serial_ptr, "serial").and_then(|serial| get_kernel_log(serial, since_secs));
+        string_result(result, "kernel log")
+    })
+}
+
+/// Follows the ring buffer (`dmesg -w`), invoking `callback(user_data, line)` per line on a
+/// background thread. Returns a stream handle for `lb_stop_stream`, or 0 on error.
+#[no_mangle]
+pub extern "C" fn lb_follow_kernel_log(
+    serial_ptr: *const c_char,
+    since_secs: f64,
+    callback: Option<LineCallback>,
+    user_data: *mut c_void,
+) -> u64 {
+    ffi_guard(0, || {
+        let result = LineSink::new(callback, user_data).and_then(|sink| {
+            read_c_str(serial_ptr, "serial").and_then(|serial| follow_kernel_log(serial, since_secs, sink))
+        });
+        handle_result(result)
+    })
+}
@@ -0,0 +1,174 @@
+use std::os::raw::c_char;
+
+use crate::device_lock::ensure_device_unlocked;
+use crate::error::LbError;
+use crate::intent::{am_field, am_warning, extra_args, run_am};
+use crate::json::{self, JsonValue};
+use crate::logging::{lb_log, Level};
+use crate::packages;
+use crate::{ffi_guard, read_c_str, string_result};
+
+pub struct LaunchOptions {
+    extras: Vec<(String, JsonValue)>,
+    wait: bool,
+    force_stop: bool,
+    /// `am start --user`: a user id or `current`.
+    user: Option<String>,
+}
+
+fn parse_extras(extras_json: Option<&str>) -> Result<Vec<(String, JsonValue)>, LbError> {
+    match extras_json {
+        None => Ok(Vec::new()),
+        Some(source) => json::parse(source)?
+            .as_object()
+            .cloned()
+            .ok_or_else(|| "Launch extras must be a JSON object".into()),
+    }
+}
+
+pub fn parse_options(options_json: &str) -> Result<LaunchOptions, LbError> {
+    let options = json::parse(options_json)?;
+    if options.as_object().is_none() {
+        return Err("Launch options must be a JSON object".into());
+    }
+    let flag = |name: &str| match options.get(name) {
+        None => Ok(false),
+        Some(value) => value
+            .as_bool()
+            .ok_or_else(|| LbError::from(format!("Launch option '{}' must be a boolean", name))),
+    };
+    let extras = match options.get("extras") {
+        None | Some(JsonValue::Null) => Vec::new(),
+        Some(extras) => extras.as_object().cloned().ok_or("Launch option 'extras' must be an object")?,
+    };
+    let user = match options.get("user") {
+        None | Some(JsonValue::Null) => None,
+        Some(JsonValue::String(user)) if user == "current" => Some(user.clone()),
+        Some(user) => Some(
+            user.as_u64()
+                .ok_or("Launch option 'user' must be a user id or \"current\"")?
+                .to_string(),
+        ),
+    };
+    Ok(LaunchOptions {
+        extras,
+        wait: flag("wait")?,
+        force_stop: flag("force_stop")?,
+        user,
+    })
+}
+
+/// `com.example/.Main` or `com.example/com.example.ui.Main`.
+pub fn validate_component(component: &str) -> Result<(), LbError> {
+    let (package, activity) = component
+        .split_once('/')
+        .ok_or_else(|| LbError::from(format!("Component must be package/activity, got '{}'", component)))?;
+    packages::validate_package(package)?;
+    let valid = !activity.is_empty() && activity.chars().all(|ch| ch.is_ascii_alphanumeric() || "._$".contains(ch));
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("Invalid activity name: {}", activity).into())
+    }
+}
+
+pub fn launch_activity(serial: &str, component: &str, options: LaunchOptions) -> Result<String, LbError> {
+    validate_component(component)?;
+    ensure_device_unlocked(serial)?;
+    let mut command = vec!["am start".to_string()];
+    if options.wait {
+        command.push("-W".to_string());
+    }
+    if options.force_stop {
+        command.push("-S".to_string());
+    }
+    if let Some(user) = &options.user {
+        command.push("--user".to_string());
+        command.push(user.clone());
+    }
+    command.push("-n".to_string());
+    command.push(component.to_string());
+    command.extend(extra_args(&options.extras)?);
+    let command = command.join(" ");
+
+    lb_log!(Level::Info, "exec", "Launching {} on {}", component, serial);
+    let (text, duration_ms) = run_am(serial, &command, "Starting:")?;
+    let millis = |key: &str| am_field(&text, key).and_then(|value| value.parse::<u64>().ok());
+    let warning = am_warning(&text);
+    if let Some(warning) = &warning {
+        lb_log!(Level::Warn, "exec", "Launch of {} on {}: {}", component, serial, warning);
+    }
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("component", component.into()),
+        ("status", am_field(&text, "Status").into()),
+        ("launch_state", am_field(&text, "LaunchState").into()),
+        ("activity", am_field(&text, "Activity").into()),
+        ("this_time_ms", millis("ThisTime").into()),
+        ("total_time_ms", millis("TotalTime").into()),
+        ("wait_time_ms", millis("WaitTime").into()),
+        ("force_stopped", options.force_stop.into()),
+        ("user", options.user.into()),
+        ("warning", warning.into()),
+        ("duration_ms", duration_ms.into()),
+    ])
+    .to_string())
+}
+
+/// Starts `component` (`com.example/.Main`) with `extras_json` (null, or an object whose
+/// values pick the `am` extra type: string, boolean, integer, float, null, an array of
+/// strings/numbers, or a typed `{"type", "value"}` object as in `lb_send_intent`). With `wait` non-zero this is `am start -W` and the result carries its
+/// timings: `{serial, component, status, launch_state, activity, this_time_ms, total_time_ms,
+/// wait_time_ms, force_stopped, warning, duration_ms}` (timings are null without `wait` or
+/// on releases that omit them). Unknown activities are NotFound errors.
+#[no_mangle]
+pub extern "C" fn lb_launch_activity(
+    serial_ptr: *const c_char,
+    component_ptr: *const c_char,
+    extras_json_ptr: *const c_char,
+    wait: i32,
+) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let extras = if extras_json_ptr.is_null() {
+            Ok(None)
+        } else {
+            read_c_str(extras_json_ptr, "launch extras").map(Some)
+        };
+        let result = read_c_str(serial_ptr, "serial").and_then(|serial| {
+            read_c_str(component_ptr, "component").and_then(|component| {
+                extras.and_then(parse_extras).and_then(|extras| {
+                    let options = LaunchOptions {
+                        extras,
+                        wait: wait != 0,
+                        force_stop: false,
+                        user: None,
+                    };
+                    launch_activity(serial, component, options)
+                })
+            })
+        });
+        string_result(result, "launch result")
+    })
+}
+
+/// `lb_launch_activity` with `{"extras": {...}, "wait": true, "force_stop": true, "user":
+/// 10}`; `force_stop` adds `am start -S` so the app is killed first and the timing is a true
+/// cold start, and `user` (an `lb_list_users` id or `"current"`) starts the copy of the app
+/// installed in that user or work profile.
+#[no_mangle]
+pub extern "C" fn lb_launch_activity_with_options(
+    serial_ptr: *const c_char,
+    component_ptr: *const c_char,
+    options_ptr: *const c_char,
+) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(serial_ptr, "serial").and_then(|serial| {
+            read_c_str(component_ptr, "component").and_then(|component| {
+                read_c_str(options_ptr, "launch options")
+                    .and_then(parse_options)
+                    .and_then(|options| launch_activity(serial, component, options))
+            })
+        });
+        string_result(result, "launch result")
+    })
+}
@@ -1,18 +1,134 @@
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
+
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::ffi::{CStr, CString};
 use std::os::raw::c_char;
-use std::process::{Child, Command};
-use std::sync::{Mutex, OnceLock};
+use std::panic::{self, AssertUnwindSafe};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, Once, OnceLock};
 use std::thread;
-use std::time::{Duration, Instant};
-
-const CSS_SNIPPET: &str = "\
-\t<style>\n\tbody{\n\t\tfont-family: Arial, sans-serif;\n\t\tline-height: 1.6;\n\t\tcolor: #333;\n\t\tbackground-color: #f4f4f4;\n\t\tpadding: 20px;\n\t}\n\t\n\tul {\n\t\tlist-style-type: none;\n\t\tpadding-left:0;\n\t}\n\t\n\tul li {\n\t\tmargin: 5px 0;\n\t\tposition: relative;\n\t\tpadding: 5px;\n\t\tborder: 2px solid #ddd;\n\t\tbackground-color:#fffff;\n\t}\n\t\n\tul li ul {\n\t\tmargin-left: 20px;\n\t\tpadding-left: 20px;\n\t\tborder-left:1.2px dashed #888;\n\t}\n\t\n\tul li:before{\n\t\tcontent: '➡️';\n\t\tposition: absolute;\n\t\tleft:-15px;\n\t\tcolor: #888;\n\t}\n\t\n\t.attributes {\n\t\tcolor: #0000FF;\n\t\tfont-style: italic ;\n\t}\n\t\n\t.text {\n\t\tcolor: #008000;\n\t}\n\t</style>\n\t";
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+mod adb;
+mod adb_server;
+mod adb_sync;
+mod animation;
+mod avd;
+mod backup;
+mod battery;
+mod batterystats;
+mod benchmark;
+mod burst;
+mod cancel;
+mod checksum;
+#[doc(hidden)]
+pub mod cli;
+mod clipboard;
+mod command_stream;
+mod cpu;
+mod crash_watch;
+mod deflate;
+mod demo_mode;
+mod dev_options;
+mod device_config;
+mod device_lock;
+mod device_meta;
+mod dir_transfer;
+mod display;
+mod doze;
+mod dumpsys;
+mod emulator;
+mod error;
+mod exec;
+mod failure_capture;
+mod fastboot;
+mod fleet;
+mod frame_metrics;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod group_ops;
+mod hierarchy;
+mod history;
+mod ime;
+mod input;
+mod input_macro;
+mod inspection;
+mod install;
+mod instrumentation;
+mod intent;
+mod json;
+mod kernel_log;
+mod launch;
+mod lmk;
+mod monitor;
+mod monkey;
+mod netstats;
+mod multi_capture;
+mod obb;
+mod packages;
+mod perfetto;
+mod permissions;
+mod png;
+mod presets;
+#[cfg(feature = "python")]
+mod python;
+mod reboot;
+mod recording_reconcile;
+mod remote_fs;
+mod retry;
+mod root;
+mod rpc;
+#[cfg(feature = "async-exec")]
+mod runtime;
+mod scheduler;
+mod script;
+mod serial_lock;
+mod server;
+mod settings;
+mod shell_session;
+mod shutdown;
+mod sideload;
+mod logging;
+mod stream;
+mod thermal;
+mod thumbnail;
+mod tombstones;
+mod top;
+mod transcript;
+mod users;
+mod version;
+mod wireless;
+mod yaml;
+mod zip;
+
+use error::{ErrorCode, LbError};
+use json::JsonValue;
+use logging::{lb_log, Level};
+
+thread_local! {
+    // Per calling thread, so concurrent host threads never read each other's failures.
+    static LAST_ERROR: RefCell<LbError> = RefCell::new(LbError::ok());
+    // Depth of `ffi_guard` calls on this thread and the message of a panic caught by one.
+    static GUARD_DEPTH: Cell<u32> = const { Cell::new(0) };
+    static PANIC_MESSAGE: RefCell<Option<String>> = const { RefCell::new(None) };
+}
 
-static LAST_ERROR: OnceLock<Mutex<String>> = OnceLock::new();
+static PANIC_HOOK: Once = Once::new();
 
 struct RecordingHandle {
-    child: Child,
+    /// None for a recording adopted by `lb_reconcile_recordings` from an earlier host process.
+    child: Option<Child>,
+    remote_path: String,
+    started_ms: u64,
+    /// Set by the reaper when the adb process exits before `stop_screen_record`.
+    ended: Option<RecordingEnd>,
+}
+
+struct RecordingEnd {
+    exit_code: Option<i32>,
+    ended_ms: u64,
 }
 
 static RECORDING_PROCESSES: OnceLock<Mutex<HashMap<String, RecordingHandle>>> = OnceLock::new();
@@ -21,566 +137,640 @@ fn recording_registry() -> &'static Mutex<HashMap<String, RecordingHandle>> {
     RECORDING_PROCESSES.get_or_init(|| Mutex::new(HashMap::new()))
 }
 
-fn set_last_error(message: impl Into<String>) {
-    let locker = LAST_ERROR.get_or_init(|| Mutex::new(String::new()));
-    if let Ok(mut guard) = locker.lock() {
-        *guard = message.into();
+fn set_last_error(error: impl Into<LbError>) {
+    let error = error.into();
+    match error.code {
+        ErrorCode::Ok => {}
+        ErrorCode::Internal => lb_log!(Level::Error, "error", "{}: {}", error.code.name(), error.message),
+        _ => lb_log!(Level::Warn, "error", "{}: {}", error.code.name(), error.message),
     }
+    LAST_ERROR.with(|slot| *slot.borrow_mut() = error);
 }
 
 fn clear_last_error() {
-    set_last_error(String::new());
+    set_last_error(LbError::ok());
+}
+
+/// What the last export called on this thread recorded; `Ok` after a success.
+fn last_error() -> LbError {
+    LAST_ERROR.with(|slot| slot.borrow().clone())
+}
+
+/// Panics inside an export are recorded for `ffi_guard` instead of printed; panics on
+/// other threads (stream readers) keep the default report.
+fn install_panic_hook() {
+    PANIC_HOOK.call_once(|| {
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if GUARD_DEPTH.with(Cell::get) == 0 {
+                default_hook(info);
+                return;
+            }
+            let payload = info
+                .payload()
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| info.payload().downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("unknown panic payload");
+            let message = match info.location() {
+                Some(location) => format!("Internal panic at {}:{}: {}", location.file(), location.line(), payload),
+                None => format!("Internal panic: {}", payload),
+            };
+            PANIC_MESSAGE.with(|slot| *slot.borrow_mut() = Some(message));
+        }));
+    });
+}
+
+/// Runs the body of an `extern "C"` export so a panic never unwinds into the host: it
+/// becomes an `Internal` last error and the export returns `fallback`.
+fn ffi_guard<T>(fallback: T, body: impl FnOnce() -> T) -> T {
+    install_panic_hook();
+    GUARD_DEPTH.with(|depth| depth.set(depth.get() + 1));
+    let result = panic::catch_unwind(AssertUnwindSafe(body));
+    GUARD_DEPTH.with(|depth| depth.set(depth.get() - 1));
+    match result {
+        Ok(value) => value,
+        Err(_) => {
+            let message = PANIC_MESSAGE
+                .with(|slot| slot.borrow_mut().take())
+                .unwrap_or_else(|| "Internal panic".to_string());
+            set_last_error(LbError::internal(message));
+            fallback
+        }
+    }
 }
 
+/// Message of the last failed call made from the calling thread.
 #[no_mangle]
 pub extern "C" fn lb_last_error() -> *mut c_char {
-    let locker = LAST_ERROR.get_or_init(|| Mutex::new(String::new()));
-    match locker.lock() {
-        Ok(guard) => match CString::new(guard.as_str()) {
+    ffi_guard(std::ptr::null_mut(), || {
+        LAST_ERROR.with(|slot| match CString::new(slot.borrow().message.as_str()) {
             Ok(c_string) => c_string.into_raw(),
             Err(_) => std::ptr::null_mut(),
-        },
-        Err(_) => std::ptr::null_mut(),
-    }
+        })
+    })
 }
 
+/// Numeric `ErrorCode` of the last failed call (0 after a success).
 #[no_mangle]
-pub extern "C" fn lb_free_string(ptr: *mut c_char) {
-    if ptr.is_null() {
-        return;
-    }
-    unsafe {
-        let _ = CString::from_raw(ptr);
-    }
+pub extern "C" fn lb_last_error_code() -> i32 {
+    ffi_guard(0, || {
+        LAST_ERROR.with(|slot| slot.borrow().code as i32)
+    })
 }
 
-#[derive(Default)]
-struct FrameState {
-    has_children: bool,
+/// `{"code", "name", "message", "command", "serial"}` for the last failed call.
+#[no_mangle]
+pub extern "C" fn lb_last_error_json() -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        LAST_ERROR.with(|slot| match CString::new(slot.borrow().to_json().to_string()) {
+            Ok(c_string) => c_string.into_raw(),
+            Err(_) => std::ptr::null_mut(),
+        })
+    })
 }
 
-fn escape_html(input: &str) -> String {
-    let mut escaped = String::with_capacity(input.len());
-    for ch in input.chars() {
-        match ch {
-            '&' => escaped.push_str("&amp;"),
-            '<' => escaped.push_str("&lt;"),
-            '>' => escaped.push_str("&gt;"),
-            '"' => escaped.push_str("&quot;"),
-            '\'' => escaped.push_str("&#39;"),
-            _ => escaped.push(ch),
+#[no_mangle]
+pub extern "C" fn lb_free_string(ptr: *mut c_char) {
+    ffi_guard((), || {
+        if ptr.is_null() {
+            return;
         }
-    }
-    escaped
+        unsafe {
+            let _ = CString::from_raw(ptr);
+        }
+    })
 }
 
-fn render_device_ui_html(xml: &str) -> Result<String, String> {
-    let mut output = String::with_capacity(xml.len().saturating_mul(2));
-    output.push_str(CSS_SNIPPET);
-    output.push_str("<ul>");
-
-    let bytes = xml.as_bytes();
-    let mut index: usize = 0;
-    let mut stack: Vec<FrameState> = Vec::new();
-
-    while index < bytes.len() {
-        match bytes[index] {
-            b'<' => {
-                if index + 1 >= bytes.len() {
-                    break;
-                }
-                match bytes[index + 1] {
-                    b'/' => {
-                        index += 2;
-                        while index < bytes.len() && bytes[index] != b'>' {
-                            index += 1;
-                        }
-                        if index < bytes.len() {
-                            index += 1;
-                        }
-                        if let Some(frame) = stack.pop() {
-                            if frame.has_children {
-                                output.push_str("</ul>");
-                            }
-                            output.push_str("</li>");
-                        }
-                    }
-                    b'!' => {
-                        index += 2;
-                        while index + 2 < bytes.len()
-                            && !(bytes[index] == b'-'
-                                && bytes[index + 1] == b'-'
-                                && bytes[index + 2] == b'>')
-                        {
-                            index += 1;
-                        }
-                        index = (index + 3).min(bytes.len());
-                    }
-                    b'?' => {
-                        index += 2;
-                        while index + 1 < bytes.len() && !(bytes[index] == b'?' && bytes[index + 1] == b'>') {
-                            index += 1;
-                        }
-                        index = (index + 2).min(bytes.len());
-                    }
-                    _ => {
-                        let start = index + 1;
-                        let mut cursor = start;
-                        while cursor < bytes.len() {
-                            let ch = bytes[cursor];
-                            if ch == b'/' || ch == b'>' || ch.is_ascii_whitespace() {
-                                break;
-                            }
-                            cursor += 1;
-                        }
-                        if cursor > bytes.len() {
-                            return Err("Malformed XML tag".into());
-                        }
-                        let tag_name = &xml[start..cursor];
-                        let mut attrs: Vec<(String, String)> = Vec::new();
-                        let mut self_closing = false;
-                        let mut attr_cursor = cursor;
-                        while attr_cursor < bytes.len() {
-                            while attr_cursor < bytes.len() && bytes[attr_cursor].is_ascii_whitespace() {
-                                attr_cursor += 1;
-                            }
-                            if attr_cursor >= bytes.len() {
-                                break;
-                            }
-                            let ch = bytes[attr_cursor];
-                            if ch == b'>' {
-                                attr_cursor += 1;
-                                break;
-                            }
-                            if ch == b'/' {
-                                self_closing = true;
-                                attr_cursor += 1;
-                                if attr_cursor < bytes.len() && bytes[attr_cursor] == b'>' {
-                                    attr_cursor += 1;
-                                }
-                                break;
-                            }
-
-                            let name_start = attr_cursor;
-                            while attr_cursor < bytes.len()
-                                && bytes[attr_cursor] != b'='
-                                && !bytes[attr_cursor].is_ascii_whitespace()
-                            {
-                                attr_cursor += 1;
-                            }
-                            if attr_cursor >= bytes.len() {
-                                return Err("Malformed attribute".into());
-                            }
-                            let name_end = attr_cursor;
-                            while attr_cursor < bytes.len() && bytes[attr_cursor].is_ascii_whitespace() {
-                                attr_cursor += 1;
-                            }
-                            if attr_cursor >= bytes.len() || bytes[attr_cursor] != b'=' {
-                                return Err("Malformed attribute assignment".into());
-                            }
-                            attr_cursor += 1;
-                            while attr_cursor < bytes.len() && bytes[attr_cursor].is_ascii_whitespace() {
-                                attr_cursor += 1;
-                            }
-                            if attr_cursor >= bytes.len() {
-                                return Err("Missing attribute value".into());
-                            }
-                            let quote = bytes[attr_cursor];
-                            if quote != b'"' && quote != b'\'' {
-                                return Err("Attribute value must be quoted".into());
-                            }
-                            attr_cursor += 1;
-                            let value_start = attr_cursor;
-                            while attr_cursor < bytes.len() && bytes[attr_cursor] != quote {
-                                attr_cursor += 1;
-                            }
-                            if attr_cursor >= bytes.len() {
-                                return Err("Unterminated attribute value".into());
-                            }
-                            let value_end = attr_cursor;
-                            attr_cursor += 1;
-
-                            let name = xml[name_start..name_end].trim();
-                            let value = &xml[value_start..value_end];
-                            attrs.push((name.to_string(), value.to_string()));
-                        }
-                        index = attr_cursor;
-
-                        if let Some(parent) = stack.last_mut() {
-                            if !parent.has_children {
-                                parent.has_children = true;
-                                output.push_str("<ul>");
-                            }
-                        }
-
-                        output.push_str("<li>");
-                        output.push_str(&escape_html(tag_name));
-                        if !attrs.is_empty() {
-                            output.push_str(" [");
-                            for (idx, (name, value)) in attrs.iter().enumerate() {
-                                if idx > 0 {
-                                    output.push_str(", ");
-                                }
-                                output.push_str("<span class=\"attributes\">");
-                                output.push_str(&escape_html(name));
-                                output.push_str("</span>=<span class=\"text\">");
-                                output.push('"');
-                                output.push_str(&escape_html(value));
-                                output.push('"');
-                                output.push_str("</span>");
-                            }
-                            output.push_str("] ");
-                        }
-
-                        if self_closing {
-                            output.push_str("</li>");
-                        } else {
-                            stack.push(FrameState::default());
-                        }
-                    }
-                }
-            }
-            _ => {
-                index += 1;
-            }
-        }
-    }
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0)
+}
 
-    while let Some(frame) = stack.pop() {
-        if frame.has_children {
-            output.push_str("</ul>");
-        }
-        output.push_str("</li>");
+fn read_c_str<'a>(ptr: *const c_char, name: &str) -> Result<&'a str, LbError> {
+    if ptr.is_null() {
+        return Err(LbError::new(ErrorCode::NullPointer, format!("Null pointer received for {}", name)));
     }
-
-    output.push_str("</ul>");
-    Ok(output)
+    unsafe { CStr::from_ptr(ptr) }
+        .to_str()
+        .map_err(|_| LbError::new(ErrorCode::Utf8, format!("{} must be valid UTF-8", name)))
 }
 
-#[no_mangle]
-pub extern "C" fn lb_render_device_ui_html(xml_ptr: *const c_char) -> *mut c_char {
-    if xml_ptr.is_null() {
-        set_last_error("Null pointer received for XML input");
-        return std::ptr::null_mut();
-    }
-    let c_slice = unsafe { CStr::from_ptr(xml_ptr) };
-    match c_slice.to_str() {
-        Ok(xml) => match render_device_ui_html(xml) {
-            Ok(html) => match CString::new(html) {
-                Ok(c_string) => {
-                    clear_last_error();
-                    c_string.into_raw()
-                }
-                Err(_) => {
-                    set_last_error("Failed to allocate CString for HTML output");
-                    std::ptr::null_mut()
-                }
-            },
-            Err(err) => {
-                set_last_error(err);
+fn string_result(result: Result<String, LbError>, what: &str) -> *mut c_char {
+    match result {
+        Ok(value) => match CString::new(value) {
+            Ok(c_string) => {
+                clear_last_error();
+                c_string.into_raw()
+            }
+            Err(_) => {
+                set_last_error(LbError::internal(format!("Failed to allocate CString for {}", what)));
                 std::ptr::null_mut()
             }
         },
-        Err(_) => {
-            set_last_error("XML input must be valid UTF-8");
+        Err(err) => {
+            set_last_error(err);
             std::ptr::null_mut()
         }
     }
 }
 
-fn shlex_split(command: &str) -> Result<Vec<String>, String> {
-    let mut parts: Vec<String> = Vec::new();
-    let mut current = String::new();
-    let mut chars = command.chars().peekable();
-    let mut in_single = false;
-    let mut in_double = false;
-    let mut escaped = false;
+/// Length-prefixed result buffer (`lb_result` in the C ABI), used where output may contain
+/// NUL bytes that a `CString` cannot carry. `ptr` is null on error; free with `lb_free_result`.
+#[repr(C)]
+pub struct LbResult {
+    pub ptr: *mut u8,
+    pub len: usize,
+}
 
-    while let Some(ch) = chars.next() {
-        if escaped {
-            current.push(ch);
-            escaped = false;
-            continue;
-        }
-        match ch {
-            '\\' if !in_single => {
-                escaped = true;
-            }
-            '\'' if !in_double => {
-                in_single = !in_single;
-            }
-            '"' if !in_single => {
-                in_double = !in_double;
-            }
-            ch if ch.is_whitespace() && !in_single && !in_double => {
-                if !current.is_empty() {
-                    parts.push(current.clone());
-                    current.clear();
-                }
-            }
-            _ => current.push(ch),
+impl LbResult {
+    fn empty() -> LbResult {
+        LbResult {
+            ptr: std::ptr::null_mut(),
+            len: 0,
         }
     }
+}
 
-    if escaped {
-        return Err("Trailing escape character".into());
-    }
-    if in_single || in_double {
-        return Err("Unterminated quoted string".into());
-    }
-    if !current.is_empty() {
-        parts.push(current);
-    }
-    Ok(parts)
+fn buffer_result(result: Result<String, LbError>) -> LbResult {
+    bytes_result(result.map(String::into_bytes))
 }
 
-fn execute_command(command: &str) -> Vec<String> {
-    match shlex_split(command) {
-        Ok(parts) => {
-            if parts.is_empty() {
-                return vec![String::new()];
-            }
-            let mut cmd = Command::new(&parts[0]);
-            if parts.len() > 1 {
-                cmd.args(&parts[1..]);
-            }
-            match cmd.output() {
-                Ok(output) => {
-                    let stdout = String::from_utf8_lossy(&output.stdout);
-                    let stderr = String::from_utf8_lossy(&output.stderr);
-                    let mut lines: Vec<String> = stdout.lines().map(|line| line.to_string()).collect();
-                    if !output.status.success() {
-                        lines.push(format!("ERROR(exit={}): {}", output.status.code().unwrap_or(-1), stderr.trim()));
-                    } else if !stderr.trim().is_empty() {
-                        lines.push(format!("STDERR: {}", stderr.trim()));
-                    }
-                    if lines.is_empty() {
-                        lines.push(String::new());
-                    }
-                    lines
-                }
-                Err(err) => vec![format!("ERROR(exec): {}", err)],
+fn bytes_result(result: Result<Vec<u8>, LbError>) -> LbResult {
+    match result {
+        Ok(value) => {
+            clear_last_error();
+            let bytes = value.into_boxed_slice();
+            let len = bytes.len();
+            LbResult {
+                ptr: Box::into_raw(bytes) as *mut u8,
+                len,
             }
         }
-        Err(err) => vec![format!("ERROR(parse): {}", err)],
+        Err(err) => {
+            set_last_error(err);
+            LbResult::empty()
+        }
     }
 }
 
-const SCREENRECORD_STOP_TIMEOUT_SECS: u64 = 5;
-
 #[no_mangle]
-pub extern "C" fn lb_start_screen_record(serial_ptr: *const c_char, remote_path_ptr: *const c_char) -> i32 {
-    if serial_ptr.is_null() || remote_path_ptr.is_null() {
-        set_last_error("Null pointer provided to lb_start_screen_record");
-        return 0;
-    }
-
-    let serial = match unsafe { CStr::from_ptr(serial_ptr) }.to_str() {
-        Ok(value) => value.to_string(),
-        Err(_) => {
-            set_last_error("Serial must be valid UTF-8");
-            return 0;
+pub extern "C" fn lb_free_result(result: LbResult) {
+    ffi_guard((), || {
+        if result.ptr.is_null() {
+            return;
         }
-    };
-
-    let remote_path = match unsafe { CStr::from_ptr(remote_path_ptr) }.to_str() {
-        Ok(value) => value.to_string(),
-        Err(_) => {
-            set_last_error("Remote path must be valid UTF-8");
-            return 0;
+        unsafe {
+            drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(result.ptr, result.len)));
         }
-    };
+    })
+}
 
-    let registry = recording_registry();
-    let mut guard = match registry.lock() {
-        Ok(guard) => guard,
-        Err(_) => {
-            set_last_error("Recording registry is unavailable");
-            return 0;
+fn status_result(result: Result<(), LbError>) -> i32 {
+    match result {
+        Ok(()) => {
+            clear_last_error();
+            1
+        }
+        Err(err) => {
+            set_last_error(err);
+            0
         }
-    };
-
-    if guard.contains_key(&serial) {
-        set_last_error("Recording already active for serial");
-        return 0;
     }
+}
 
-    match Command::new("adb")
-        .args(["-s", &serial, "shell", "screenrecord", &remote_path])
-        .spawn()
-    {
-        Ok(child) => {
-            guard.insert(
-                serial,
-                RecordingHandle {
-                    child,
-                },
-            );
+/// For APIs returning a registry handle: 0 signals an error, as handles start at 1.
+fn handle_result(result: Result<u64, LbError>) -> u64 {
+    match result {
+        Ok(handle) => {
             clear_last_error();
-            1
+            handle
         }
         Err(err) => {
-            set_last_error(format!("Failed to spawn screenrecord: {}", err));
+            set_last_error(err);
             0
         }
     }
 }
 
-#[no_mangle]
-pub extern "C" fn lb_stop_screen_record(serial_ptr: *const c_char) -> i32 {
-    if serial_ptr.is_null() {
-        set_last_error("Null pointer provided to lb_stop_screen_record");
-        return 0;
-    }
+const SCREENRECORD_STOP_TIMEOUT_SECS: u64 = 5;
 
-    let serial = match unsafe { CStr::from_ptr(serial_ptr) }.to_str() {
-        Ok(value) => value.to_string(),
-        Err(_) => {
-            set_last_error("Serial must be valid UTF-8");
-            return 0;
+/// Spawns `adb shell screenrecord remote_path` and tracks it until `stop_screen_record`.
+fn start_screen_record(serial: &str, remote_path: &str) -> Result<(), LbError> {
+    let mut guard = recording_registry()
+        .lock()
+        .map_err(|_| LbError::internal("Recording registry is unavailable"))?;
+    match guard.get(serial) {
+        Some(recording) if recording.ended.is_none() => {
+            return Err(LbError::from("Recording already active for serial").with_serial(serial));
         }
-    };
+        Some(_) => lb_log!(Level::Debug, "recording", "Replacing ended recording on {}", serial),
+        None => {}
+    }
 
-    let registry = recording_registry();
-    let mut guard = match registry.lock() {
-        Ok(guard) => guard,
-        Err(_) => {
-            set_last_error("Recording registry is unavailable");
-            return 0;
+    let argv = ["adb", "-s", serial, "shell", "screenrecord", remote_path];
+    // Detached from our stdin/stdout so a caller capturing output (e.g. `lbb record start`
+    // in a pipeline) is not held open for the whole recording.
+    let spawned = Command::new(argv[0])
+        .args(&argv[1..])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .spawn();
+    let argv: Vec<String> = argv.iter().map(|arg| arg.to_string()).collect();
+    transcript::record_command(&argv, Duration::ZERO, None);
+    let child = spawned.map_err(|err| {
+        LbError::new(ErrorCode::SpawnFailed, format!("Failed to spawn screenrecord: {}", err))
+            .with_command(&argv)
+            .with_serial(serial)
+    })?;
+    lb_log!(Level::Info, "recording", "Started screenrecord on {} -> {}", serial, remote_path);
+    guard.insert(
+        serial.to_string(),
+        RecordingHandle {
+            child: Some(child),
+            remote_path: remote_path.to_string(),
+            started_ms: now_millis(),
+            ended: None,
+        },
+    );
+    // Checked under the registry lock, which the reaper also holds when it decides to exit.
+    if !REAPER_RUNNING.swap(true, Ordering::SeqCst) {
+        thread::spawn(reap_recordings);
+    }
+    Ok(())
+}
+
+const REAPER_INTERVAL: Duration = Duration::from_secs(1);
+static REAPER_RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Polls every live recording's adb process while any remain, so one that dies on its own
+/// (time limit, device reboot, unplugged cable) is reaped instead of lingering as a zombie,
+/// and reported with a Warn log line since the host is not waiting on it.
+fn reap_recordings() {
+    loop {
+        // Plain sleep: the reaper outlives any cancel token, including `lb_shutdown`'s.
+        thread::sleep(REAPER_INTERVAL);
+        let Ok(mut guard) = recording_registry().lock() else {
+            REAPER_RUNNING.store(false, Ordering::SeqCst);
+            return;
+        };
+        let mut live = 0;
+        for (serial, recording) in guard.iter_mut() {
+            let Some(child) = recording.child.as_mut().filter(|_| recording.ended.is_none()) else {
+                continue;
+            };
+            match child.try_wait() {
+                Ok(None) => live += 1,
+                Ok(Some(status)) => {
+                    let ended_ms = now_millis();
+                    let exit = match status.code() {
+                        Some(code) => format!("exit {}", code),
+                        None => "killed by a signal".to_string(),
+                    };
+                    lb_log!(
+                        Level::Warn,
+                        "recording",
+                        "Recording on {} ended unexpectedly after {}s ({}); {} may be incomplete",
+                        serial,
+                        ended_ms.saturating_sub(recording.started_ms) / 1000,
+                        exit,
+                        recording.remote_path
+                    );
+                    recording.ended = Some(RecordingEnd {
+                        exit_code: status.code(),
+                        ended_ms,
+                    });
+                }
+                Err(err) => {
+                    lb_log!(Level::Warn, "recording", "Failed to poll screenrecord for {}: {}", serial, err);
+                    live += 1;
+                }
+            }
         }
-    };
+        if live == 0 {
+            REAPER_RUNNING.store(false, Ordering::SeqCst);
+            return;
+        }
+    }
+}
+
+/// `[{serial, remote_path, started_ms, adopted, state: recording|ended, exit_code, ended_ms}]`.
+fn list_recordings() -> Result<String, LbError> {
+    let guard = recording_registry()
+        .lock()
+        .map_err(|_| LbError::internal("Recording registry is unavailable"))?;
+    let mut serials: Vec<&String> = guard.keys().collect();
+    serials.sort();
+    let recordings: Vec<JsonValue> = serials
+        .into_iter()
+        .map(|serial| {
+            let recording = &guard[serial];
+            let ended = recording.ended.as_ref();
+            JsonValue::object(vec![
+                ("serial", serial.as_str().into()),
+                ("remote_path", recording.remote_path.as_str().into()),
+                ("started_ms", recording.started_ms.into()),
+                ("adopted", recording.child.is_none().into()),
+                ("state", if ended.is_some() { "ended" } else { "recording" }.into()),
+                ("exit_code", ended.and_then(|end| end.exit_code).into()),
+                ("ended_ms", ended.map(|end| end.ended_ms).into()),
+            ])
+        })
+        .collect();
+    Ok(JsonValue::from(recordings).to_string())
+}
 
-    let handle = guard.remove(&serial);
-    drop(guard);
+/// Serials with a screenrecord started by `start_screen_record` and not yet stopped.
+fn recording_serials() -> Vec<String> {
+    recording_registry()
+        .lock()
+        .map(|guard| guard.keys().cloned().collect())
+        .unwrap_or_default()
+}
+
+/// Sends SIGINT to screenrecord on the device so it finalizes the file, then waits for the
+/// tracked adb process (if any) to exit.
+fn stop_screen_record(serial: &str) -> Result<(), LbError> {
+    stop_screen_record_within(serial, Duration::from_secs(SCREENRECORD_STOP_TIMEOUT_SECS))
+}
 
-    let stop_output = Command::new("adb")
-        .args(["-s", &serial, "shell", "pkill", "-SIGINT", "screenrecord"])
-        .output();
+/// `stop_screen_record`, killing the tracked adb process if it has not exited after `timeout`.
+fn stop_screen_record_within(serial: &str, timeout: Duration) -> Result<(), LbError> {
+    let handle = recording_registry()
+        .lock()
+        .map_err(|_| LbError::internal("Recording registry is unavailable"))?
+        .remove(serial);
+    if handle.is_none() {
+        lb_log!(Level::Debug, "recording", "No tracked screenrecord for {}; sending SIGINT anyway", serial);
+    }
+    // Already reaped: the device-side process is gone, and `pkill` would fail finding nothing.
+    if handle.as_ref().is_some_and(|recording| recording.ended.is_some()) {
+        lb_log!(Level::Debug, "recording", "Recording on {} had already ended", serial);
+        return Ok(());
+    }
 
-    let mut had_error = false;
-    if let Ok(output) = stop_output {
-        if !output.status.success() {
+    let stop_argv = ["adb", "-s", serial, "shell", "pkill", "-SIGINT", "screenrecord"];
+    let stop_started = Instant::now();
+    let stop_output = Command::new(stop_argv[0]).args(&stop_argv[1..]).output();
+    let stop_argv: Vec<String> = stop_argv.iter().map(|arg| arg.to_string()).collect();
+    transcript::record_command(
+        &stop_argv,
+        stop_started.elapsed(),
+        stop_output.as_ref().ok().and_then(|output| output.status.code()),
+    );
+
+    // Still wait for the recording below; a failed stop command is reported afterwards.
+    let stop_error = match stop_output {
+        Ok(output) if output.status.success() => None,
+        Ok(output) => {
             let stderr = String::from_utf8_lossy(&output.stderr);
-            set_last_error(format!(
-                "Failed to stop screenrecord cleanly: {}",
-                stderr.trim()
-            ));
-            had_error = true;
+            Some(
+                LbError::new(
+                    ErrorCode::CommandFailed,
+                    format!("Failed to stop screenrecord cleanly: {}", stderr.trim()),
+                )
+                .with_command(&stop_argv)
+                .with_serial(serial),
+            )
         }
-    } else if let Err(err) = stop_output {
-        set_last_error(format!("Failed to invoke stop command: {}", err));
-        had_error = true;
-    }
+        Err(err) => Some(
+            LbError::new(ErrorCode::SpawnFailed, format!("Failed to invoke stop command: {}", err))
+                .with_command(&stop_argv)
+                .with_serial(serial),
+        ),
+    };
 
-    if let Some(mut recording) = handle {
-        let timeout = Duration::from_secs(SCREENRECORD_STOP_TIMEOUT_SECS);
+    if let Some(mut child) = handle.and_then(|recording| recording.child) {
         let deadline = Instant::now() + timeout;
         loop {
-            match recording.child.try_wait() {
+            match child.try_wait() {
                 Ok(Some(_status)) => {
                     break;
                 }
                 Ok(None) => {
                     if Instant::now() >= deadline {
-                        let _ = recording.child.kill();
-                        let _ = recording.child.wait();
-                        set_last_error("Timeout waiting for screenrecord process to exit");
-                        return 0;
+                        let _ = child.kill();
+                        let _ = child.wait();
+                        return Err(
+                            LbError::new(ErrorCode::Timeout, "Timeout waiting for screenrecord process to exit")
+                                .with_serial(serial),
+                        );
                     }
                     thread::sleep(Duration::from_millis(100));
                 }
                 Err(err) => {
-                    set_last_error(format!("Failed to poll screenrecord process: {}", err));
-                    return 0;
+                    return Err(LbError::internal(format!("Failed to poll screenrecord process: {}", err)));
                 }
             }
         }
     }
 
-    if had_error {
-        return 0;
+    match stop_error {
+        Some(err) => Err(err),
+        None => Ok(()),
     }
+}
 
-    clear_last_error();
-    1
+#[no_mangle]
+pub extern "C" fn lb_start_screen_record(serial_ptr: *const c_char, remote_path_ptr: *const c_char) -> i32 {
+    ffi_guard(0, || {
+        let result = read_c_str(serial_ptr, "serial").and_then(|serial| {
+            read_c_str(remote_path_ptr, "remote path").and_then(|remote_path| start_screen_record(serial, remote_path))
+        });
+        status_result(result)
+    })
 }
 
 #[no_mangle]
-pub extern "C" fn lb_run_commands_parallel(payload_ptr: *const c_char) -> *mut c_char {
-    if payload_ptr.is_null() {
-        set_last_error("Null payload passed to lb_run_commands_parallel");
-        return std::ptr::null_mut();
-    }
+pub extern "C" fn lb_stop_screen_record(serial_ptr: *const c_char) -> i32 {
+    ffi_guard(0, || status_result(read_c_str(serial_ptr, "serial").and_then(stop_screen_record)))
+}
 
-    let payload_cstr = unsafe { CStr::from_ptr(payload_ptr) };
-    let payload = match payload_cstr.to_str() {
-        Ok(value) => value,
-        Err(_) => {
-            set_last_error("Payload must be valid UTF-8");
-            return std::ptr::null_mut();
-        }
-    };
+/// Recordings in the registry, including ones that ended on their own and have not been
+/// stopped yet (`state: "ended"` with the adb exit code). A new `lb_start_screen_record`
+/// replaces an ended entry; `lb_stop_screen_record` clears it.
+#[no_mangle]
+pub extern "C" fn lb_list_recordings() -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || string_result(list_recordings(), "recordings"))
+}
 
+fn run_commands_parallel(payload: &str) -> Result<String, LbError> {
     let mut lines = payload.lines();
-    let count_line = match lines.next() {
-        Some(value) => value.trim(),
-        None => {
-            set_last_error("Payload missing command count header");
-            return std::ptr::null_mut();
-        }
-    };
-
-    let command_count: usize = match count_line.parse() {
-        Ok(value) => value,
-        Err(_) => {
-            set_last_error("Invalid command count in payload");
-            return std::ptr::null_mut();
-        }
-    };
+    let count_line = lines.next().ok_or("Payload missing command count header")?.trim();
+    let command_count: usize = count_line
+        .parse()
+        .map_err(|_| "Invalid command count in payload".to_string())?;
 
     let mut commands: Vec<String> = Vec::with_capacity(command_count);
     for _ in 0..command_count {
-        match lines.next() {
-            Some(cmd) => commands.push(cmd.to_string()),
-            None => {
-                set_last_error("Insufficient command lines in payload");
-                return std::ptr::null_mut();
-            }
-        }
+        let cmd = lines.next().ok_or("Insufficient command lines in payload")?;
+        commands.push(cmd.to_string());
     }
 
-    let mut handles = Vec::with_capacity(commands.len());
-    for (index, command) in commands.into_iter().enumerate() {
-        handles.push(std::thread::spawn(move || (index, execute_command(&command))));
+    lb_log!(Level::Info, "exec", "Running {} commands in parallel", commands.len());
+    run_parallel(commands, |command| exec::execute_command(&command))
+}
+
+/// Runs every item on its own thread and joins the result lines with the parallel-runner
+/// separators, in input order.
+fn run_parallel<T, F>(items: Vec<T>, run: F) -> Result<String, LbError>
+where
+    T: Send + 'static,
+    F: Fn(T) -> Vec<String> + Copy + Send + 'static,
+{
+    let mut handles = Vec::with_capacity(items.len());
+    for (index, item) in items.into_iter().enumerate() {
+        handles.push(cancel::spawn(move || (index, run(item))));
     }
 
     let mut collected: Vec<(usize, Vec<String>)> = Vec::new();
     for handle in handles {
-        match handle.join() {
-            Ok(pair) => collected.push(pair),
-            Err(_) => {
-                set_last_error("Thread panicked during command execution");
-                return std::ptr::null_mut();
-            }
-        }
+        let pair = handle
+            .join()
+            .map_err(|_| LbError::internal("Thread panicked during command execution"))?;
+        collected.push(pair);
     }
     collected.sort_by_key(|(index, _)| *index);
 
-    let mut results: Vec<String> = Vec::new();
-    for (_, lines) in collected.into_iter() {
-        let joined = lines.join("\u{001f}");
-        results.push(joined);
+    let results: Vec<String> = collected
+        .into_iter()
+        .map(|(_, lines)| lines.join("\u{001f}"))
+        .collect();
+    Ok(results.join("\u{001e}"))
+}
+
+/// `[["adb", "-s", "X", "shell", "ls"], ...]`: argv arrays run as given, without splitting.
+fn run_argv_parallel(payload: &str) -> Result<String, LbError> {
+    let commands = json::parse(payload)?
+        .as_array()
+        .ok_or("Argv payload must be a JSON array of string arrays")?
+        .iter()
+        .map(|argv| {
+            argv.as_string_array()
+                .filter(|argv| !argv.is_empty())
+                .ok_or_else(|| LbError::from("Each command must be a non-empty array of strings"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    lb_log!(Level::Info, "exec", "Running {} argv commands in parallel", commands.len());
+    run_parallel(commands, |argv| exec::execute_argv(&argv))
+}
+
+/// A string (split with `shlex_split`) or an argv array.
+fn parse_command(command: &JsonValue) -> Result<Vec<String>, LbError> {
+    match command {
+        JsonValue::String(command) => exec::shlex_split(command),
+        command => command
+            .as_string_array()
+            .ok_or_else(|| LbError::from("Each command must be a string or an array of strings")),
     }
+}
 
-    let combined = results.join("\u{001e}");
-    match CString::new(combined) {
-        Ok(c_string) => {
-            clear_last_error();
-            c_string.into_raw()
-        }
-        Err(_) => {
-            set_last_error("Failed to build CString for command results");
-            std::ptr::null_mut()
-        }
+/// `["adb devices", ["adb", "-s", "X", "shell", "ls"], {"command": ..., "stdin": "...",
+/// "max_output_bytes": N, "retry": {...}}]`: strings are split with `shlex_split`, arrays
+/// are used as argv, and objects add stdin, an output cap, and a retry spec.
+fn parse_structured_command(command: &JsonValue) -> exec::CommandSpec {
+    let mut spec = exec::CommandSpec {
+        argv: Ok(Vec::new()),
+        stdin: None,
+        max_output_bytes: exec::output_limit(),
+        retry: retry::default_policy(),
+    };
+    spec.argv = if command.as_object().is_some() {
+        apply_command_options(command, &mut spec)
+    } else {
+        parse_command(command)
+    };
+    spec
+}
+
+/// Reads the optional keys of a command object into `spec` and returns its argv.
+fn apply_command_options(command: &JsonValue, spec: &mut exec::CommandSpec) -> Result<Vec<String>, LbError> {
+    let argv = parse_command(command.get("command").ok_or("Command objects need a 'command' string or array")?)?;
+    if let Some(limit) = command.get("max_output_bytes") {
+        spec.max_output_bytes = limit
+            .as_u64()
+            .ok_or("'max_output_bytes' must be a non-negative integer")?;
+    }
+    if let Some(retry) = command.get("retry") {
+        spec.retry = Some(retry::RetrySpec::parse(retry)?);
     }
+    spec.stdin = command.get("stdin").and_then(JsonValue::as_str).map(str::to_string);
+    Ok(argv)
+}
+
+fn run_commands_structured(payload: &str) -> Result<String, LbError> {
+    let commands: Vec<exec::CommandSpec> = json::parse(payload)?
+        .as_array()
+        .ok_or("Command payload must be a JSON array")?
+        .iter()
+        .map(parse_structured_command)
+        .collect();
+    lb_log!(Level::Info, "exec", "Running {} structured commands in parallel", commands.len());
+    let mut handles = Vec::with_capacity(commands.len());
+    for command in commands {
+        handles.push(cancel::spawn(move || exec::execute_structured(command)));
+    }
+    let mut results = Vec::with_capacity(handles.len());
+    for handle in handles {
+        results.push(
+            handle
+                .join()
+                .map_err(|_| LbError::internal("Thread panicked during command execution"))?,
+        );
+    }
+    Ok(JsonValue::from(results).to_string())
+}
+
+fn read_payload<'a>(payload_ptr: *const c_char) -> Result<&'a str, LbError> {
+    if payload_ptr.is_null() {
+        return Err(LbError::new(
+            ErrorCode::NullPointer,
+            "Null payload passed to lb_run_commands_parallel",
+        ));
+    }
+    unsafe { CStr::from_ptr(payload_ptr) }
+        .to_str()
+        .map_err(|_| LbError::new(ErrorCode::Utf8, "Payload must be valid UTF-8"))
+}
+
+#[no_mangle]
+pub extern "C" fn lb_run_commands_parallel(payload_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        string_result(
+            read_payload(payload_ptr).and_then(run_commands_parallel),
+            "command results",
+        )
+    })
+}
+
+/// Same protocol as `lb_run_commands_parallel`, returned as an `lb_result` buffer so
+/// command output containing NUL bytes is delivered intact.
+#[no_mangle]
+pub extern "C" fn lb_run_commands_parallel_buf(payload_ptr: *const c_char) -> LbResult {
+    ffi_guard(LbResult::empty(), || {
+        buffer_result(read_payload(payload_ptr).and_then(run_commands_parallel))
+    })
+}
+
+/// Like `lb_run_commands_parallel_buf`, but takes a JSON array of argv arrays so paths with
+/// spaces, backslashes, or quotes reach the process untouched. Same result separators.
+#[no_mangle]
+pub extern "C" fn lb_run_argv_parallel(payload_ptr: *const c_char) -> LbResult {
+    ffi_guard(LbResult::empty(), || {
+        buffer_result(read_c_str(payload_ptr, "argv payload").and_then(run_argv_parallel))
+    })
+}
+
+/// Runs commands in parallel and returns a JSON array, in input order, of
+/// `{argv, exit_code, duration_ms, pid, stdout, stderr, truncated, captured_bytes, attempts,
+/// error}`. Commands are shell-style strings, argv arrays, or `{"command", "stdin",
+/// "max_output_bytes", "retry"}` objects; `error` explains commands that could not be
+/// parsed or spawned.
+#[no_mangle]
+pub extern "C" fn lb_run_commands_structured(payload_ptr: *const c_char) -> LbResult {
+    ffi_guard(LbResult::empty(), || {
+        buffer_result(read_c_str(payload_ptr, "command payload").and_then(run_commands_structured))
+    })
 }
@@ -0,0 +1,161 @@
+use std::ffi::c_void;
+use std::os::raw::c_char;
+
+use crate::adb;
+use crate::error::LbError;
+use crate::json::JsonValue;
+use crate::stream::{self, LineCallback, LineSink};
+use crate::{ffi_guard, handle_result, now_millis, read_c_str};
+
+const LMKD_TAG: &str = "lowmemorykiller";
+const LEGACY_MINFREE_PATH: &str = "/sys/module/lowmemorykiller/parameters/minfree";
+const LEGACY_ADJ_PATH: &str = "/sys/module/lowmemorykiller/parameters/adj";
+const PRESSURE_PATH: &str = "/proc/pressure/memory";
+
+/// One lmkd kill, parsed from lines such as
+/// `Kill 'com.example' (12345), uid 10123, oom_score_adj 905 to free 69860kB rss, 23444kB swap; reason: ...`
+/// or the pre-Android 10 form `Kill 'com.example' (12345), uid 10123, oom_adj 900 to free 4096kB`.
+struct KillEvent {
+    process: String,
+    pid: Option<i64>,
+    uid: Option<i64>,
+    oom_score_adj: Option<i64>,
+    freed_kb: Option<i64>,
+    reason: Option<String>,
+}
+
+fn number_after(message: &str, marker: &str) -> Option<i64> {
+    let rest = &message[message.find(marker)? + marker.len()..];
+    let end = rest
+        .char_indices()
+        .find(|(index, ch)| !(ch.is_ascii_digit() || (*index == 0 && *ch == '-')))
+        .map(|(index, _)| index)
+        .unwrap_or(rest.len());
+    rest[..end].parse().ok()
+}
+
+fn parse_kill(message: &str) -> Option<KillEvent> {
+    let start = message.find("Kill '").map(|index| index + 6).or_else(|| message.find("Killing '").map(|index| index + 9))?;
+    let end = start + message[start..].find('\'')?;
+    let rest = &message[end..];
+    Some(KillEvent {
+        process: message[start..end].to_string(),
+        pid: number_after(rest, "' ("),
+        uid: number_after(rest, "uid "),
+        oom_score_adj: number_after(rest, "oom_score_adj ")
+            .or_else(|| number_after(rest, "oom_adj "))
+            .or_else(|| number_after(rest, "adj ")),
+        freed_kb: number_after(rest, "to free "),
+        reason: rest.find("reason: ").map(|index| rest[index + 8..].trim().to_string()),
+    })
+}
+
+/// Strips the `-v brief` prefix: `I/lowmemorykiller(  612): message`.
+fn brief_message(line: &str) -> Option<&str> {
+    let (header, message) = line.split_once("): ")?;
+    header.contains(LMKD_TAG).then_some(message)
+}
+
+pub fn matches_package(process: &str, package: &str) -> bool {
+    package.is_empty()
+        || process == package
+        || process
+            .strip_prefix(package)
+            .is_some_and(|suffix| suffix.starts_with(':'))
+}
+
+/// `some avg10=0.00 avg60=0.00 avg300=0.00 total=0` lines as `{"some": {...}, "full": {...}}`.
+fn read_pressure(serial: &str) -> JsonValue {
+    let Ok(output) = adb::shell(serial, &format!("cat {} 2>/dev/null", PRESSURE_PATH)) else {
+        return JsonValue::Null;
+    };
+    let entries: Vec<(String, JsonValue)> = output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let kind = fields.next()?.to_string();
+            let values = fields
+                .filter_map(|field| field.split_once('='))
+                .filter_map(|(key, value)| value.parse::<f64>().ok().map(|value| (key.to_string(), value.into())))
+                .collect();
+            Some((kind, JsonValue::Object(values)))
+        })
+        .collect();
+    if entries.is_empty() {
+        JsonValue::Null
+    } else {
+        JsonValue::Object(entries)
+    }
+}
+
+/// Thresholds of the in-kernel driver on older devices; `null` where lmkd replaced it.
+fn read_legacy_thresholds(serial: &str) -> JsonValue {
+    let read = |path: &str| {
+        adb::shell(serial, &format!("cat {} 2>/dev/null", path))
+            .ok()
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+    match (read(LEGACY_MINFREE_PATH), read(LEGACY_ADJ_PATH)) {
+        (Some(minfree), Some(adj)) => JsonValue::object(vec![("minfree_pages", minfree.into()), ("adj", adj.into())]),
+        _ => JsonValue::Null,
+    }
+}
+
+fn event_json(serial: &str, event: KillEvent, pressure: JsonValue, thresholds: &JsonValue) -> JsonValue {
+    JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("process", event.process.into()),
+        ("pid", event.pid.into()),
+        ("uid", event.uid.into()),
+        ("oom_score_adj", event.oom_score_adj.into()),
+        ("freed_kb", event.freed_kb.into()),
+        ("reason", event.reason.into()),
+        ("memory_pressure", pressure),
+        ("legacy_thresholds", thresholds.clone()),
+        ("timestamp_ms", now_millis().into()),
+    ])
+}
+
+fn watch_lmk(serial: &str, package: &str, sink: LineSink) -> Result<u64, LbError> {
+    let thresholds = read_legacy_thresholds(serial);
+    let argv = adb::adb_argv(
+        Some(serial),
+        &["logcat", "-v", "brief", "-T", "1", &format!("{}:I", LMKD_TAG), "*:S"],
+    );
+    let serial = serial.to_string();
+    let package = package.trim().to_string();
+    stream::spawn_line_stream(&argv, move |line| {
+        let Some(event) = brief_message(line).and_then(parse_kill) else {
+            return;
+        };
+        if matches_package(&event.process, &package) {
+            // Sampled right after the kill, so it reflects the pressure that triggered it.
+            let pressure = read_pressure(&serial);
+            sink.emit(&event_json(&serial, event, pressure, &thresholds).to_string());
+        }
+    })
+}
+
+/// Watches lmkd kills and calls `callback(user_data, event_json)` for each kill of `package`
+/// (or any process when `package` is null/empty), including oom_score_adj, freed memory,
+/// the kill reason, and `/proc/pressure/memory`. Returns a handle for `lb_stop_stream`.
+#[no_mangle]
+pub extern "C" fn lb_watch_lmk_kills(
+    serial_ptr: *const c_char,
+    package_ptr: *const c_char,
+    callback: Option<LineCallback>,
+    user_data: *mut c_void,
+) -> u64 {
+    ffi_guard(0, || {
+        let package = if package_ptr.is_null() {
+            Ok("")
+        } else {
+            read_c_str(package_ptr, "package")
+        };
+        let result = LineSink::new(callback, user_data).and_then(|sink| {
+            package.and_then(|package| read_c_str(serial_ptr, "serial").and_then(|serial| watch_lmk(serial, package, sink)))
+        });
+        handle_result(result)
+    })
+}
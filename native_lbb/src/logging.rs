@@ -0,0 +1,261 @@
+use std::ffi::CString;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::os::raw::c_char;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Mutex, OnceLock};
+
+use crate::error::LbError;
+use crate::remote_fs::civil_from_days;
+use crate::{ffi_guard, now_millis, read_c_str, status_result};
+
+/// Host log sink: `callback(level, target, message)`. `target` names the subsystem
+/// (`exec`, `recording`, `hierarchy`, ...). Both strings are only valid during the call,
+/// which may happen on any thread.
+pub type LogCallback = extern "C" fn(level: i32, target: *const c_char, message: *const c_char);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(i32)]
+pub enum Level {
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+}
+
+impl Level {
+    fn name(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+        }
+    }
+}
+
+/// Size-rotated log file: `path` is the live file, `path.1` .. `path.<max_files - 1>` the
+/// older ones, newest first.
+struct FileSink {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    max_files: u32,
+}
+
+impl FileSink {
+    fn open(path: PathBuf, max_size: u64, max_files: u32) -> Result<FileSink, LbError> {
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)
+                .map_err(|err| LbError::io(format!("Failed to create {}: {}", parent.display(), err)))?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|err| LbError::io(format!("Failed to open {}: {}", path.display(), err)))?;
+        let size = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+        Ok(FileSink {
+            path,
+            file,
+            size,
+            max_size,
+            max_files,
+        })
+    }
+
+    fn rotated(&self, index: u32) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", index));
+        PathBuf::from(name)
+    }
+
+    /// Shifts `path.N` to `path.N+1`, dropping the oldest, and starts an empty live file.
+    fn rotate(&mut self) -> std::io::Result<()> {
+        if self.max_files > 1 {
+            let _ = fs::remove_file(self.rotated(self.max_files - 1));
+            for index in (1..self.max_files - 1).rev() {
+                let _ = fs::rename(self.rotated(index), self.rotated(index + 1));
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) {
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size && self.rotate().is_err() {
+            return;
+        }
+        // A failing disk must never fail the operation being logged.
+        if self.file.write_all(line.as_bytes()).is_ok() {
+            self.size += line.len() as u64;
+        }
+    }
+}
+
+/// `2026-01-31T12:34:56.789Z` from Unix milliseconds.
+fn utc_timestamp(millis: u64) -> String {
+    let secs = millis / 1000;
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60,
+        millis % 1000
+    )
+}
+
+/// One log line as delivered to in-process listeners: level, target, message.
+pub type LogEvent = (Level, String, String);
+
+/// In-process consumer of log lines up to `max_level` (the control server's event
+/// streams); dropped once its receiver is gone.
+struct Listener {
+    id: u64,
+    max_level: Level,
+    sender: Sender<LogEvent>,
+}
+
+static LOG_CALLBACK: OnceLock<Mutex<Option<LogCallback>>> = OnceLock::new();
+static LOG_FILE: OnceLock<Mutex<Option<FileSink>>> = OnceLock::new();
+static LISTENERS: OnceLock<Mutex<Vec<Listener>>> = OnceLock::new();
+static NEXT_LISTENER_ID: AtomicU64 = AtomicU64::new(1);
+
+fn log_callback() -> &'static Mutex<Option<LogCallback>> {
+    LOG_CALLBACK.get_or_init(|| Mutex::new(None))
+}
+
+fn current_callback() -> Option<LogCallback> {
+    log_callback().lock().ok().and_then(|guard| *guard)
+}
+
+fn log_file() -> &'static Mutex<Option<FileSink>> {
+    LOG_FILE.get_or_init(|| Mutex::new(None))
+}
+
+fn file_logging_enabled() -> bool {
+    log_file().lock().is_ok_and(|guard| guard.is_some())
+}
+
+fn listeners() -> &'static Mutex<Vec<Listener>> {
+    LISTENERS.get_or_init(|| Mutex::new(Vec::new()))
+}
+
+fn has_listeners() -> bool {
+    listeners().lock().is_ok_and(|guard| !guard.is_empty())
+}
+
+/// Delivers every log line up to `max_level` to the returned receiver until
+/// `remove_listener(id)` or the receiver is dropped.
+pub fn add_listener(max_level: Level) -> Result<(u64, Receiver<LogEvent>), LbError> {
+    let (sender, receiver) = mpsc::channel();
+    let id = NEXT_LISTENER_ID.fetch_add(1, Ordering::Relaxed);
+    listeners()
+        .lock()
+        .map_err(|_| LbError::internal("Log listeners poisoned"))?
+        .push(Listener { id, max_level, sender });
+    Ok((id, receiver))
+}
+
+pub fn remove_listener(id: u64) {
+    if let Ok(mut guard) = listeners().lock() {
+        guard.retain(|listener| listener.id != id);
+    }
+}
+
+/// Cheap check so call sites can skip formatting when nobody is listening.
+pub fn enabled() -> bool {
+    current_callback().is_some() || file_logging_enabled() || has_listeners()
+}
+
+pub fn emit(level: Level, target: &str, message: &str) {
+    // Channel sends never block, so a slow listener cannot stall the caller.
+    if let Ok(mut guard) = listeners().lock() {
+        guard.retain(|listener| {
+            level > listener.max_level
+                || listener
+                    .sender
+                    .send((level, target.to_string(), message.to_string()))
+                    .is_ok()
+        });
+    }
+    if let Ok(mut guard) = log_file().lock() {
+        if let Some(sink) = guard.as_mut() {
+            let line = format!("{} {} {}: {}\n", utc_timestamp(now_millis()), level.name(), target, message);
+            sink.write_line(&line);
+        }
+    }
+    // Copied out of the lock so a callback that re-registers itself cannot deadlock.
+    let Some(callback) = current_callback() else {
+        return;
+    };
+    let target = CString::new(target.replace('\0', "")).unwrap_or_default();
+    let message = CString::new(message.replace('\0', "")).unwrap_or_default();
+    callback(level as i32, target.as_ptr(), message.as_ptr());
+}
+
+/// `lb_log!(Level::Debug, "exec", "ran {}", command)`; the message is only formatted when
+/// a host callback is registered.
+macro_rules! lb_log {
+    ($level:expr, $target:expr, $($arg:tt)+) => {
+        if $crate::logging::enabled() {
+            $crate::logging::emit($level, $target, &format!($($arg)+));
+        }
+    };
+}
+pub(crate) use lb_log;
+
+/// Registers the host log sink (levels: 1 error, 2 warn, 3 info, 4 debug), or removes it
+/// when `callback` is null.
+#[no_mangle]
+pub extern "C" fn lb_set_log_callback(callback: Option<LogCallback>) {
+    ffi_guard((), || {
+        if let Ok(mut guard) = log_callback().lock() {
+            *guard = callback;
+        }
+    })
+}
+
+fn enable_file_logging(path: &str, max_size: u64, max_files: u32) -> Result<(), LbError> {
+    if path.is_empty() {
+        return Err("Log file path must not be empty".into());
+    }
+    if max_size == 0 || max_files == 0 {
+        return Err("max_size and max_files must both be at least 1".into());
+    }
+    let sink = FileSink::open(PathBuf::from(path), max_size, max_files)?;
+    *log_file()
+        .lock()
+        .map_err(|_| LbError::internal("Log file sink poisoned"))? = Some(sink);
+    Ok(())
+}
+
+/// Appends every native log line (all levels, timestamped in UTC) to `path`, rotating to
+/// `path.1` .. `path.<max_files - 1>` once the file would exceed `max_size` bytes. Runs
+/// alongside the host callback; a null `path` stops file logging.
+#[no_mangle]
+pub extern "C" fn lb_enable_file_logging(path_ptr: *const c_char, max_size: u64, max_files: u32) -> i32 {
+    ffi_guard(0, || {
+        if path_ptr.is_null() {
+            if let Ok(mut guard) = log_file().lock() {
+                *guard = None;
+            }
+            return status_result(Ok(()));
+        }
+        let result = read_c_str(path_ptr, "path").and_then(|path| enable_file_logging(path, max_size, max_files));
+        status_result(result)
+    })
+}
@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::os::raw::c_char;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::adb;
+use crate::battery;
+use crate::error::LbError;
+use crate::json::JsonValue;
+use crate::logging::{lb_log, Level};
+use crate::stream::{LineCallback, LineSink};
+use crate::thermal;
+use crate::{ffi_guard, handle_result, now_millis, read_c_str, status_result};
+
+const METRIC_BATTERY: u32 = 1;
+const METRIC_MEMORY: u32 = 2;
+const METRIC_CPU: u32 = 4;
+const METRIC_STORAGE: u32 = 8;
+const METRIC_THERMAL: u32 = 16;
+const ALL_METRICS: u32 = METRIC_BATTERY | METRIC_MEMORY | METRIC_CPU | METRIC_STORAGE | METRIC_THERMAL;
+const MIN_INTERVAL_MS: u64 = 500;
+const SECTION_MARKER: &str = "__LB_MONITOR__";
+
+/// Shell commands per metric; all selected ones run in a single `adb shell` per sample.
+const SECTIONS: [(u32, &str, &str); 5] = [
+    (METRIC_BATTERY, "battery", "dumpsys battery"),
+    (METRIC_MEMORY, "memory", "cat /proc/meminfo"),
+    (METRIC_CPU, "cpu", "cat /proc/loadavg; head -n 1 /proc/stat"),
+    (METRIC_STORAGE, "storage", "df -k /data"),
+    (METRIC_THERMAL, "thermal", "dumpsys thermalservice"),
+];
+
+static NEXT_MONITOR_ID: AtomicU64 = AtomicU64::new(1);
+// Dropping a monitor's sender wakes its thread out of the interval wait and ends it.
+static MONITORS: OnceLock<Mutex<HashMap<u64, Sender<()>>>> = OnceLock::new();
+
+fn monitor_registry() -> &'static Mutex<HashMap<u64, Sender<()>>> {
+    MONITORS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn number(value: Option<&str>) -> Option<f64> {
+    value.and_then(|value| value.trim().parse::<f64>().ok())
+}
+
+fn parse_battery(output: &str) -> JsonValue {
+    let fields = battery::parse_dumpsys(output);
+    let field = |name: &str| fields.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str());
+    let plugged = ["ac powered", "usb powered", "wireless powered"]
+        .iter()
+        .any(|name| field(name) == Some("true"));
+    JsonValue::object(vec![
+        ("level", number(field("level")).into()),
+        // dumpsys reports tenths of a degree.
+        ("temperature_c", number(field("temperature")).map(|tenths| tenths / 10.0).into()),
+        ("plugged", plugged.into()),
+        ("health", field("health").map(battery::health_name).into()),
+    ])
+}
+
+/// `MemTotal:  5842332 kB` style lines; `MemAvailable` is missing before Linux 3.14.
+fn parse_memory(output: &str) -> JsonValue {
+    let field = |name: &str| {
+        output
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+            .and_then(|value| value.split_whitespace().next())
+            .and_then(|value| value.parse::<u64>().ok())
+    };
+    let total = field("MemTotal");
+    let available = field("MemAvailable").or_else(|| Some(field("MemFree")? + field("Cached")?));
+    let used_percent = match (total, available) {
+        (Some(total), Some(available)) if total > 0 => {
+            Some((total.saturating_sub(available) as f64 * 1000.0 / total as f64).round() / 10.0)
+        }
+        _ => None,
+    };
+    JsonValue::object(vec![
+        ("total_kb", total.into()),
+        ("available_kb", available.into()),
+        ("used_percent", used_percent.into()),
+    ])
+}
+
+/// `(busy, total)` jiffies from the aggregate `cpu  user nice system idle iowait ...` line.
+fn cpu_jiffies(output: &str) -> Option<(u64, u64)> {
+    let values: Vec<u64> = output
+        .lines()
+        .find_map(|line| line.strip_prefix("cpu "))?
+        .split_whitespace()
+        .filter_map(|value| value.parse().ok())
+        .collect();
+    let total: u64 = values.iter().sum();
+    let idle = values.get(3).copied().unwrap_or(0) + values.get(4).copied().unwrap_or(0);
+    Some((total - idle, total))
+}
+
+/// Load averages from `/proc/loadavg`, plus usage since the previous sample from `/proc/stat`.
+fn parse_cpu(output: &str, previous: &mut Option<(u64, u64)>) -> JsonValue {
+    let mut load = output.lines().next().unwrap_or("").split_whitespace();
+    let current = cpu_jiffies(output);
+    let usage_percent = match (*previous, current) {
+        (Some((busy_before, total_before)), Some((busy, total))) if total > total_before => {
+            let busy = busy.saturating_sub(busy_before) as f64;
+            Some((busy * 1000.0 / (total - total_before) as f64).round() / 10.0)
+        }
+        _ => None,
+    };
+    *previous = current;
+    JsonValue::object(vec![
+        ("load_1m", number(load.next()).into()),
+        ("load_5m", number(load.next()).into()),
+        ("load_15m", number(load.next()).into()),
+        ("usage_percent", usage_percent.into()),
+    ])
+}
+
+/// The data line of `df -k /data`: `/dev/block/dm-5  115164164 40123456 75040708  35% /data`.
+fn parse_storage(output: &str) -> JsonValue {
+    let row: Option<Vec<u64>> = output.lines().skip(1).find_map(|line| {
+        let values: Vec<u64> = line
+            .split_whitespace()
+            .skip(1)
+            .take(3)
+            .filter_map(|value| value.parse().ok())
+            .collect();
+        (values.len() == 3).then_some(values)
+    });
+    let (total, used, available) = match row.as_deref() {
+        Some([total, used, available]) => (Some(*total), Some(*used), Some(*available)),
+        _ => (None, None, None),
+    };
+    JsonValue::object(vec![
+        ("total_kb", total.into()),
+        ("used_kb", used.into()),
+        ("available_kb", available.into()),
+    ])
+}
+
+fn sample(serial: &str, mask: u32, previous_cpu: &mut Option<(u64, u64)>) -> Result<Vec<(String, JsonValue)>, LbError> {
+    let selected: Vec<_> = SECTIONS.iter().filter(|(bit, _, _)| mask & bit != 0).collect();
+    let script = selected
+        .iter()
+        .map(|(_, _, command)| format!("{}; echo {}", command, SECTION_MARKER))
+        .collect::<Vec<_>>()
+        .join("; ");
+    let output = adb::shell(serial, &script)?;
+    let mut metrics = Vec::new();
+    for ((_, name, _), section) in selected.iter().zip(output.split(SECTION_MARKER)) {
+        let value = match *name {
+            "battery" => parse_battery(section),
+            "memory" => parse_memory(section),
+            "cpu" => parse_cpu(section.trim_start(), previous_cpu),
+            "thermal" => thermal::parse_thermalservice(section),
+            _ => parse_storage(section.trim_start()),
+        };
+        metrics.push((name.to_string(), value));
+    }
+    Ok(metrics)
+}
+
+fn start_monitor(serial: &str, interval_ms: u64, metrics_mask: u32, sink: LineSink) -> Result<u64, LbError> {
+    if interval_ms < MIN_INTERVAL_MS {
+        return Err(format!("Monitor interval must be at least {} ms", MIN_INTERVAL_MS).into());
+    }
+    let mask = if metrics_mask == 0 { ALL_METRICS } else { metrics_mask };
+    if mask & !ALL_METRICS != 0 {
+        return Err(format!("Unknown monitor metrics in mask {:#x}", metrics_mask).into());
+    }
+    let (stop, stopped) = mpsc::channel::<()>();
+    let id = NEXT_MONITOR_ID.fetch_add(1, Ordering::Relaxed);
+    monitor_registry()
+        .lock()
+        .map_err(|_| LbError::internal("Monitor registry poisoned"))?
+        .insert(id, stop);
+
+    let serial = serial.to_string();
+    let interval = Duration::from_millis(interval_ms);
+    lb_log!(Level::Info, "stream", "Monitor {} started on {} every {}ms", id, serial, interval_ms);
+    thread::spawn(move || {
+        let mut previous_cpu = None;
+        loop {
+            // The next sample is scheduled after this one finishes, so slow devices
+            // stretch the interval instead of queueing adb calls.
+            let started = Instant::now();
+            let mut snapshot = vec![
+                ("serial".to_string(), JsonValue::from(serial.as_str())),
+                ("timestamp_ms".to_string(), now_millis().into()),
+            ];
+            match sample(&serial, mask, &mut previous_cpu) {
+                Ok(metrics) => snapshot.extend(metrics),
+                Err(err) => snapshot.push(("error".to_string(), err.message.into())),
+            }
+            // Stopped while sampling: drop the snapshot rather than call back late.
+            if stopped.try_recv() == Err(TryRecvError::Disconnected) {
+                break;
+            }
+            sink.emit(&JsonValue::Object(snapshot).to_string());
+            match stopped.recv_timeout(interval.saturating_sub(started.elapsed())) {
+                Err(RecvTimeoutError::Timeout) => continue,
+                _ => break,
+            }
+        }
+        lb_log!(Level::Debug, "stream", "Monitor {} ended", id);
+    });
+    Ok(id)
+}
+
+fn stop_monitor(id: u64) -> Result<(), LbError> {
+    monitor_registry()
+        .lock()
+        .map_err(|_| LbError::internal("Monitor registry poisoned"))?
+        .remove(&id)
+        .ok_or_else(|| LbError::not_found(format!("No active monitor with handle {}", id)))?;
+    lb_log!(Level::Info, "stream", "Monitor {} stopped", id);
+    Ok(())
+}
+
+/// Stops every monitor and returns how many were running.
+pub fn stop_all() -> usize {
+    let stopped = monitor_registry().lock().map(|mut guard| guard.drain().count()).unwrap_or(0);
+    if stopped > 0 {
+        lb_log!(Level::Info, "stream", "Stopped {} monitors", stopped);
+    }
+    stopped
+}
+
+/// Samples `serial` every `interval_ms` (at least 500) on a background thread and calls
+/// `callback(user_data, json)` with `{serial, timestamp_ms, battery?, memory?, cpu?, storage?,
+/// thermal?}` or `{serial, timestamp_ms, error}` when a sample fails. `metrics_mask` ORs
+/// 1 battery, 2 memory, 4 CPU, 8 storage, 16 thermal (0 = all). Returns a handle for `lb_monitor_stop`, or 0.
+#[no_mangle]
+pub extern "C" fn lb_monitor_start(
+    serial_ptr: *const c_char,
+    interval_ms: u64,
+    metrics_mask: u32,
+    callback: Option<LineCallback>,
+    user_data: *mut c_void,
+) -> u64 {
+    ffi_guard(0, || {
+        let result = LineSink::new(callback, user_data).and_then(|sink| {
+            read_c_str(serial_ptr, "serial").and_then(|serial| start_monitor(serial, interval_ms, metrics_mask, sink))
+        });
+        handle_result(result)
+    })
+}
+
+/// Stops a monitor; no callback is made after this returns except one already in flight.
+#[no_mangle]
+pub extern "C" fn lb_monitor_stop(handle: u64) -> i32 {
+    ffi_guard(0, || status_result(stop_monitor(handle)))
+}
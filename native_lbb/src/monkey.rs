@@ -0,0 +1,341 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read};
+use std::os::raw::c_char;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::adb;
+use crate::cancel;
+use crate::device_lock::ensure_device_unlocked;
+use crate::error::{ErrorCode, LbError};
+use crate::json::JsonValue;
+use crate::logging::{lb_log, Level};
+use crate::packages;
+use crate::transcript;
+use crate::{ffi_guard, now_millis, read_c_str, status_result, string_result};
+
+/// How long `lb_monkey_stop` waits for adb to exit after the device-side kill.
+const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A crash or ANR reported by monkey, stamped with how many events had been sent.
+struct Incident {
+    process: String,
+    pid: Option<u32>,
+    at_event: u64,
+    message: Option<String>,
+    long_message: Option<String>,
+}
+
+#[derive(Default)]
+struct MonkeyProgress {
+    seed: Option<i64>,
+    events_sent: u64,
+    crashes: Vec<Incident>,
+    anrs: Vec<Incident>,
+    /// Which list the following `// Short Msg:` / `Reason:` lines describe.
+    last_is_anr: bool,
+    aborted: bool,
+    finished: bool,
+}
+
+impl MonkeyProgress {
+    fn line(&mut self, line: &str) {
+        let line = line.trim();
+        if let Some(header) = line.strip_prefix(":Monkey: seed=") {
+            self.seed = header.split_whitespace().next().and_then(|seed| seed.parse().ok());
+        } else if let Some(count) = line.strip_prefix("// Sending event #") {
+            if let Ok(count) = count.trim().parse::<u64>() {
+                self.events_sent = self.events_sent.max(count);
+            }
+        } else if let Some(count) = line.strip_prefix("Events injected:") {
+            if let Ok(count) = count.trim().parse::<u64>() {
+                self.events_sent = self.events_sent.max(count);
+            }
+        } else if let Some(process) = line.strip_prefix("// CRASH:") {
+            self.crashes.push(self.incident(process));
+            self.last_is_anr = false;
+        } else if let Some(process) = line.strip_prefix("// NOT RESPONDING:") {
+            self.anrs.push(self.incident(process));
+            self.last_is_anr = true;
+        } else if let Some(message) = line.strip_prefix("// Short Msg:") {
+            if let Some(incident) = self.last_incident() {
+                incident.message.get_or_insert_with(|| message.trim().to_string());
+            }
+        } else if let Some(message) = line.strip_prefix("// Long Msg:") {
+            if let Some(incident) = self.last_incident() {
+                incident.long_message.get_or_insert_with(|| message.trim().to_string());
+            }
+        } else if let Some(reason) = line.strip_prefix("Reason:") {
+            if self.last_is_anr {
+                if let Some(incident) = self.anrs.last_mut() {
+                    incident.message.get_or_insert_with(|| reason.trim().to_string());
+                }
+            }
+        } else if line.starts_with("** Monkey aborted") {
+            self.aborted = true;
+        } else if line == "// Monkey finished" {
+            self.finished = true;
+        }
+    }
+
+    /// ` com.example (pid 1234)`.
+    fn incident(&self, header: &str) -> Incident {
+        let header = header.trim();
+        let (process, pid) = match header.split_once(" (pid ") {
+            Some((process, pid)) => (process, pid.trim_end_matches(')').trim().parse().ok()),
+            None => (header, None),
+        };
+        Incident {
+            process: process.to_string(),
+            pid,
+            at_event: self.events_sent,
+            message: None,
+            long_message: None,
+        }
+    }
+
+    fn last_incident(&mut self) -> Option<&mut Incident> {
+        if self.last_is_anr {
+            self.anrs.last_mut()
+        } else {
+            self.crashes.last_mut()
+        }
+    }
+}
+
+fn incidents_json(incidents: &[Incident]) -> JsonValue {
+    incidents
+        .iter()
+        .map(|incident| {
+            JsonValue::object(vec![
+                ("process", incident.process.as_str().into()),
+                ("pid", incident.pid.into()),
+                ("at_event", incident.at_event.into()),
+                ("message", incident.message.as_deref().into()),
+                ("long_message", incident.long_message.as_deref().into()),
+            ])
+        })
+        .collect::<Vec<_>>()
+        .into()
+}
+
+struct MonkeyRun {
+    child: Child,
+    progress: Arc<Mutex<MonkeyProgress>>,
+    readers: Vec<JoinHandle<()>>,
+    package: String,
+    event_count: u32,
+    started_ms: u64,
+}
+
+impl MonkeyRun {
+    fn running(&mut self) -> bool {
+        matches!(self.child.try_wait(), Ok(None))
+    }
+
+    fn report(&mut self, serial: &str) -> Result<JsonValue, LbError> {
+        let running = self.running();
+        let progress = self
+            .progress
+            .lock()
+            .map_err(|_| LbError::internal("Monkey progress poisoned"))?;
+        let percent = (progress.events_sent * 100 / u64::from(self.event_count)).min(100);
+        Ok(JsonValue::object(vec![
+            ("serial", serial.into()),
+            ("package", self.package.as_str().into()),
+            ("running", running.into()),
+            ("seed", progress.seed.into()),
+            ("event_count", self.event_count.into()),
+            ("events_sent", progress.events_sent.into()),
+            ("percent", percent.into()),
+            ("crashes", incidents_json(&progress.crashes)),
+            ("anrs", incidents_json(&progress.anrs)),
+            ("aborted", progress.aborted.into()),
+            ("finished", progress.finished.into()),
+            ("elapsed_ms", now_millis().saturating_sub(self.started_ms).into()),
+        ]))
+    }
+}
+
+static MONKEY_RUNS: OnceLock<Mutex<HashMap<String, MonkeyRun>>> = OnceLock::new();
+
+fn monkey_registry() -> &'static Mutex<HashMap<String, MonkeyRun>> {
+    MONKEY_RUNS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Feeds one of monkey's output pipes into the shared progress on its own thread; crash
+/// reports arrive on stderr and progress on stdout.
+fn follow<R: Read + Send + 'static>(
+    pipe: Option<R>,
+    serial: &str,
+    progress: &Arc<Mutex<MonkeyProgress>>,
+) -> Option<JoinHandle<()>> {
+    let pipe = pipe?;
+    let (serial, progress) = (serial.to_string(), Arc::clone(progress));
+    Some(thread::spawn(move || {
+        for line in BufReader::new(pipe).lines().map_while(Result::ok) {
+            let Ok(mut progress) = progress.lock() else {
+                return;
+            };
+            let (crashes, anrs) = (progress.crashes.len(), progress.anrs.len());
+            progress.line(&line);
+            if progress.crashes.len() > crashes || progress.anrs.len() > anrs {
+                lb_log!(Level::Warn, "monkey", "Monkey on {}: {}", serial, line.trim().trim_start_matches("// "));
+            }
+        }
+    }))
+}
+
+/// Ends monkey on the device; killing the host-side adb leaves it running there, where it
+/// shows up under its package name.
+pub fn stop_on_device(serial: &str) {
+    let _ = adb::run_adb(Some(serial), &["shell", "pkill -INT -f com.android.commands.monkey"]);
+}
+
+/// Unregisters every run and hands back its serial and adb process, for `lb_shutdown` to stop.
+pub fn take_all() -> Vec<(String, Child)> {
+    monkey_registry()
+        .lock()
+        .map(|mut guard| guard.drain().map(|(serial, run)| (serial, run.child)).collect())
+        .unwrap_or_default()
+}
+
+fn start_monkey(serial: &str, package: &str, event_count: u32, throttle_ms: u32, seed: i64) -> Result<(), LbError> {
+    packages::validate_package(package)?;
+    if event_count == 0 {
+        return Err("Monkey event count must be at least 1".into());
+    }
+    let mut registry = monkey_registry()
+        .lock()
+        .map_err(|_| LbError::internal("Monkey registry poisoned"))?;
+    if let Some(run) = registry.get_mut(serial) {
+        if run.running() {
+            return Err(format!("Monkey run already active for {}", serial).into());
+        }
+    }
+    ensure_device_unlocked(serial)?;
+
+    let mut args = vec!["shell".to_string(), "monkey".to_string(), "-p".to_string(), package.to_string()];
+    // A negative seed lets monkey pick one; the status reports it so the run can be replayed.
+    if seed >= 0 {
+        args.extend(["-s".to_string(), seed.to_string()]);
+    }
+    args.extend(["--throttle".to_string(), throttle_ms.to_string(), "-v".to_string(), event_count.to_string()]);
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let argv = adb::adb_argv(Some(serial), &args);
+    let mut child = Command::new(&argv[0])
+        .args(&argv[1..])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| {
+            LbError::new(ErrorCode::SpawnFailed, format!("Failed to spawn {}: {}", argv[0], err))
+                .with_command(&argv)
+                .with_serial(serial)
+        })?;
+    transcript::record_command(&argv, Duration::ZERO, None);
+    let progress = Arc::new(Mutex::new(MonkeyProgress::default()));
+    let readers = [follow(child.stdout.take(), serial, &progress), follow(child.stderr.take(), serial, &progress)]
+        .into_iter()
+        .flatten()
+        .collect();
+    lb_log!(Level::Info, "monkey", "Started monkey on {} for {} ({} events)", serial, package, event_count);
+    // A finished run nobody stopped is replaced; dropping a `Child` does not reap it.
+    if let Some(mut previous) = registry.insert(
+        serial.to_string(),
+        MonkeyRun {
+            child,
+            progress,
+            readers,
+            package: package.to_string(),
+            event_count,
+            started_ms: now_millis(),
+        },
+    ) {
+        let _ = previous.child.wait();
+    }
+    Ok(())
+}
+
+fn monkey_status(serial: &str) -> Result<String, LbError> {
+    let mut registry = monkey_registry()
+        .lock()
+        .map_err(|_| LbError::internal("Monkey registry poisoned"))?;
+    let run = registry
+        .get_mut(serial)
+        .ok_or_else(|| LbError::not_found(format!("No monkey run for {}", serial)))?;
+    Ok(run.report(serial)?.to_string())
+}
+
+fn stop_monkey(serial: &str) -> Result<String, LbError> {
+    let mut run = monkey_registry()
+        .lock()
+        .map_err(|_| LbError::internal("Monkey registry poisoned"))?
+        .remove(serial)
+        .ok_or_else(|| LbError::not_found(format!("No monkey run for {}", serial)))?;
+    if run.running() {
+        stop_on_device(serial);
+        let deadline = Instant::now() + STOP_TIMEOUT;
+        // A cancelled stop skips the rest of the grace period and kills adb.
+        while run.running() && Instant::now() < deadline {
+            if cancel::sleep(Duration::from_millis(100)).is_err() {
+                break;
+            }
+        }
+        if run.running() {
+            let _ = run.child.kill();
+        }
+        lb_log!(Level::Info, "monkey", "Stopped monkey on {}", serial);
+    }
+    let _ = run.child.wait();
+    // The pipes are closed now; let the readers take in the last lines (`Events injected:`).
+    for reader in run.readers.drain(..) {
+        let _ = reader.join();
+    }
+    Ok(run.report(serial)?.to_string())
+}
+
+/// Starts `monkey -p <package> [-s seed] --throttle <throttle_ms> -v <event_count>` in the
+/// background (a negative `seed` lets monkey choose). One run per device; poll it with
+/// `lb_monkey_status` and end it with `lb_monkey_stop`, also after it finished by itself.
+#[no_mangle]
+pub extern "C" fn lb_monkey_start(
+    serial_ptr: *const c_char,
+    package_ptr: *const c_char,
+    event_count: u32,
+    throttle_ms: u32,
+    seed: i64,
+) -> i32 {
+    ffi_guard(0, || {
+        let result = read_c_str(serial_ptr, "serial").and_then(|serial| {
+            read_c_str(package_ptr, "package")
+                .and_then(|package| start_monkey(serial, package, event_count, throttle_ms, seed))
+        });
+        status_result(result)
+    })
+}
+
+/// Progress of the device's monkey run: `{serial, package, running, seed, event_count,
+/// events_sent, percent, crashes, anrs, aborted, finished, elapsed_ms}`. `events_sent`
+/// advances in steps of 100; crashes and ANRs are `{process, pid, at_event, message,
+/// long_message}` (`message` is the ANR reason for ANRs).
+#[no_mangle]
+pub extern "C" fn lb_monkey_status(serial_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(serial_ptr, "serial").and_then(monkey_status);
+        string_result(result, "monkey status")
+    })
+}
+
+/// Kills the device's monkey run if it is still going and returns its final
+/// `lb_monkey_status` report, removing it from the registry.
+#[no_mangle]
+pub extern "C" fn lb_monkey_stop(serial_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(serial_ptr, "serial").and_then(stop_monkey);
+        string_result(result, "monkey report")
+    })
+}
@@ -0,0 +1,185 @@
+use std::collections::HashSet;
+use std::os::raw::c_char;
+use std::sync::{Arc, Barrier};
+use std::time::Duration;
+
+use crate::adb;
+use crate::cancel;
+use crate::error::LbError;
+use crate::fleet::parse_serials;
+use crate::json::{self, JsonValue};
+use crate::logging::{lb_log, Level};
+use crate::png;
+use crate::shell_session;
+use crate::{ffi_guard, now_millis, read_c_str, string_result};
+
+const REMOTE_SCREENSHOT: &str = "/data/local/tmp/lb_capture_all.png";
+const REMOTE_UI_DUMP: &str = "/data/local/tmp/lb_capture_all.xml";
+
+/// Artifact fields of one device's bundle entry.
+type Fields = Vec<(&'static str, JsonValue)>;
+
+#[derive(Clone, Copy)]
+struct Targets {
+    screenshot: bool,
+    hierarchy: bool,
+}
+
+fn parse_targets(what: &str) -> Result<Targets, LbError> {
+    let mut targets = Targets {
+        screenshot: false,
+        hierarchy: false,
+    };
+    for part in what.split(',').map(str::trim) {
+        match part {
+            "screenshot" => targets.screenshot = true,
+            "hierarchy" => targets.hierarchy = true,
+            "all" => {
+                targets.screenshot = true;
+                targets.hierarchy = true;
+            }
+            _ => {
+                return Err(format!("Unknown capture '{}'; expected screenshot, hierarchy or all", part).into());
+            }
+        }
+    }
+    Ok(targets)
+}
+
+/// Both captures start together so the screenshot is not delayed by the much slower
+/// `uiautomator dump`.
+fn capture_command(targets: Targets) -> String {
+    let dump = format!("uiautomator dump {} >/dev/null", REMOTE_UI_DUMP);
+    match (targets.screenshot, targets.hierarchy) {
+        (true, true) => format!("screencap -p {} & {}; wait", REMOTE_SCREENSHOT, dump),
+        (true, false) => format!("screencap -p {}", REMOTE_SCREENSHOT),
+        _ => dump,
+    }
+}
+
+/// Opens the shell session and clears stale captures before the barrier, so the trigger is
+/// a single write to an already running device shell.
+fn warm_session(serial: &str) -> Result<u64, LbError> {
+    let id = shell_session::open_session(serial)?;
+    shell_session::run(id, &format!("rm -f {} {}", REMOTE_SCREENSHOT, REMOTE_UI_DUMP))?;
+    Ok(id)
+}
+
+/// Reads the captured files back (no longer time critical) and returns the artifact fields
+/// plus one message per artifact that is missing.
+fn collect(serial: &str, targets: Targets) -> (Fields, Vec<JsonValue>) {
+    let mut fields = Vec::new();
+    let mut errors = Vec::new();
+    if targets.screenshot {
+        match adb::exec_out(serial, &format!("cat {}", REMOTE_SCREENSHOT)) {
+            Ok(data) if data.starts_with(&png::SIGNATURE) => {
+                fields.push(("screenshot_png_base64", json::base64_encode(&data).into()));
+            }
+            Ok(_) => errors.push("screenshot: screencap did not write a PNG".into()),
+            Err(err) => errors.push(format!("screenshot: {}", err.message).into()),
+        }
+    }
+    if targets.hierarchy {
+        match adb::exec_out(serial, &format!("cat {}", REMOTE_UI_DUMP)) {
+            Ok(data) if data.windows(10).any(|window| window == b"<hierarchy") => {
+                fields.push(("hierarchy_xml", String::from_utf8_lossy(&data).into_owned().into()));
+            }
+            Ok(_) => errors.push("hierarchy: uiautomator did not write a dump".into()),
+            Err(err) => errors.push(format!("hierarchy: {}", err.message).into()),
+        }
+    }
+    (fields, errors)
+}
+
+struct DeviceCapture {
+    triggered_at: u64,
+    outcome: Result<(Duration, Fields, Vec<JsonValue>), LbError>,
+}
+
+fn capture_device(serial: &str, targets: Targets, barrier: &Barrier) -> DeviceCapture {
+    let session = warm_session(serial);
+    // Every worker reaches the barrier, even with a failed session, or the others would hang.
+    barrier.wait();
+    let triggered_at = now_millis();
+    let outcome = session.and_then(|id| {
+        let captured = shell_session::run(id, &capture_command(targets));
+        let collected = captured.map(|(_, _, duration)| {
+            let (fields, errors) = collect(serial, targets);
+            let _ = shell_session::run(id, &format!("rm -f {} {}", REMOTE_SCREENSHOT, REMOTE_UI_DUMP));
+            (duration, fields, errors)
+        });
+        let _ = shell_session::close_session(id);
+        collected
+    });
+    DeviceCapture { triggered_at, outcome }
+}
+
+pub fn capture_all(serials: Vec<String>, what: &str) -> Result<Vec<(String, JsonValue)>, LbError> {
+    let targets = parse_targets(what)?;
+    let mut seen = HashSet::new();
+    let serials: Vec<String> = serials.into_iter().filter(|serial| seen.insert(serial.clone())).collect();
+    if serials.is_empty() {
+        return Err("Serial list is empty".into());
+    }
+    lb_log!(Level::Info, "exec", "Synchronized {} capture on {} devices", what, serials.len());
+
+    let barrier = Arc::new(Barrier::new(serials.len()));
+    let handles: Vec<_> = serials
+        .iter()
+        .map(|serial| {
+            let (serial, barrier) = (serial.clone(), barrier.clone());
+            cancel::spawn(move || capture_device(&serial, targets, &barrier))
+        })
+        .collect();
+    let captures = handles
+        .into_iter()
+        .map(|handle| handle.join())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| LbError::internal("Capture worker panicked"))?;
+
+    let first_trigger = captures.iter().map(|capture| capture.triggered_at).min().unwrap_or(0);
+    let bundle = serials
+        .into_iter()
+        .zip(captures)
+        .map(|(serial, capture)| {
+            let mut report = vec![
+                ("triggered_at_ms", JsonValue::from(capture.triggered_at)),
+                ("trigger_offset_ms", (capture.triggered_at - first_trigger).into()),
+            ];
+            match capture.outcome {
+                Ok((duration, fields, errors)) => {
+                    let status = match (fields.is_empty(), errors.is_empty()) {
+                        (_, true) => "ok",
+                        (false, false) => "partial",
+                        (true, false) => "failed",
+                    };
+                    report.insert(0, ("status", status.into()));
+                    report.push(("capture_ms", (duration.as_millis() as u64).into()));
+                    report.extend(fields);
+                    report.push(("errors", errors.into()));
+                }
+                Err(err) => {
+                    report.insert(0, ("status", "failed".into()));
+                    report.push(("errors", vec![JsonValue::from(err.message)].into()));
+                }
+            }
+            (serial, JsonValue::object(report))
+        })
+        .collect();
+    Ok(bundle)
+}
+
+/// Captures `what` (`screenshot`, `hierarchy`, a comma list of both, or `all`) on every
+/// serial at once: each device gets a warmed-up shell session and all are triggered together
+/// behind a barrier. Returns `{"<serial>": {status, triggered_at_ms, trigger_offset_ms,
+/// capture_ms, screenshot_png_base64, hierarchy_xml, errors}}`, `status` being `ok`,
+/// `partial` or `failed`.
+#[no_mangle]
+pub extern "C" fn lb_capture_all(serials_json_ptr: *const c_char, what_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(serials_json_ptr, "serial list")
+            .and_then(parse_serials)
+            .and_then(|serials| read_c_str(what_ptr, "capture targets").and_then(|what| capture_all(serials, what)));
+        string_result(result.map(|bundle| JsonValue::Object(bundle).to_string()), "capture bundle")
+    })
+}
@@ -0,0 +1,202 @@
+use std::os::raw::c_char;
+
+use crate::adb;
+use crate::error::LbError;
+use crate::json::JsonValue;
+use crate::packages;
+use crate::{ffi_guard, read_c_str, string_result};
+
+/// Per-UID counters before Android 10 moved accounting to eBPF.
+const QTAGUID_STATS: &str = "/proc/net/xt_qtaguid/stats";
+
+#[derive(Default, Clone, Copy)]
+struct Traffic {
+    rx_bytes: u64,
+    rx_packets: u64,
+    tx_bytes: u64,
+    tx_packets: u64,
+}
+
+impl Traffic {
+    fn add(&mut self, other: Traffic) {
+        self.rx_bytes += other.rx_bytes;
+        self.rx_packets += other.rx_packets;
+        self.tx_bytes += other.tx_bytes;
+        self.tx_packets += other.tx_packets;
+    }
+
+    fn to_json(self) -> JsonValue {
+        JsonValue::object(vec![
+            ("rx_bytes", self.rx_bytes.into()),
+            ("rx_packets", self.rx_packets.into()),
+            ("tx_bytes", self.tx_bytes.into()),
+            ("tx_packets", self.tx_packets.into()),
+        ])
+    }
+}
+
+/// Totals for one UID, split by process state and by network.
+#[derive(Default)]
+struct UidTraffic {
+    foreground: Traffic,
+    background: Traffic,
+    wifi: Traffic,
+    mobile: Traffic,
+    /// Oldest and newest bucket start (Unix ms); netstats only.
+    range_ms: Option<(u64, u64)>,
+}
+
+impl UidTraffic {
+    fn add(&mut self, foreground: bool, network: Option<&str>, traffic: Traffic) {
+        if foreground {
+            self.foreground.add(traffic);
+        } else {
+            self.background.add(traffic);
+        }
+        match network {
+            Some("wifi") => self.wifi.add(traffic),
+            Some("mobile") => self.mobile.add(traffic),
+            _ => {}
+        }
+    }
+}
+
+/// `type=WIFI` (before 12) or `type=1` (`ConnectivityManager.TYPE_*`) in a netstats ident.
+fn ident_network(ident: &str) -> Option<&'static str> {
+    let network_type = ident.split("type=").nth(1)?.split([',', '}', ' ']).next()?;
+    match network_type {
+        "MOBILE" | "0" => Some("mobile"),
+        "WIFI" | "1" => Some("wifi"),
+        _ => None,
+    }
+}
+
+/// The `UID stats:` section of `dumpsys netstats --full --uid` (its complete history):
+/// `ident=[{type=1, ...}] uid=10123 set=FOREGROUND tag=0x0` followed by bucket lines
+/// `st=1700000000 rb=12345 rp=10 tb=2345 tp=8 op=0`. `UID tag stats:` repeats tagged
+/// subsets of the same traffic and is skipped.
+fn parse_netstats(output: &str, uid: u32) -> Option<UidTraffic> {
+    let mut in_uid_stats = false;
+    let mut seen_section = false;
+    // (foreground, network) of the entry whose buckets follow, when it belongs to `uid`.
+    let mut current: Option<(bool, Option<&str>)> = None;
+    let mut traffic = UidTraffic::default();
+    for line in output.lines() {
+        let line = line.trim();
+        if line.ends_with("stats:") {
+            in_uid_stats = line == "UID stats:";
+            seen_section |= in_uid_stats;
+            current = None;
+            continue;
+        }
+        if !in_uid_stats {
+            continue;
+        }
+        if line.starts_with("ident=") {
+            let field = |name: &str| line.split(name).nth(1)?.split_whitespace().next();
+            current = (field(" uid=").and_then(|value| value.parse::<u32>().ok()) == Some(uid)
+                && field(" tag=") == Some("0x0"))
+            .then(|| (field(" set=") == Some("FOREGROUND"), ident_network(line)));
+            continue;
+        }
+        let Some((foreground, network)) = current else {
+            continue;
+        };
+        let mut bucket = Traffic::default();
+        let mut start = None;
+        for pair in line.split_whitespace() {
+            let Some((key, value)) = pair.split_once('=') else {
+                continue;
+            };
+            let value = value.parse::<u64>().unwrap_or(0);
+            match key {
+                "st" => start = Some(value * 1000),
+                "rb" => bucket.rx_bytes = value,
+                "rp" => bucket.rx_packets = value,
+                "tb" => bucket.tx_bytes = value,
+                "tp" => bucket.tx_packets = value,
+                _ => {}
+            }
+        }
+        if let Some(start) = start {
+            traffic.add(foreground, network, bucket);
+            traffic.range_ms = Some(match traffic.range_ms {
+                Some((first, last)) => (first.min(start), last.max(start)),
+                None => (start, start),
+            });
+        }
+    }
+    seen_section.then_some(traffic)
+}
+
+/// `idx iface acct_tag_hex uid_tag_int cnt_set rx_bytes rx_packets tx_bytes tx_packets ...`
+/// rows since boot; `cnt_set` 1 is foreground.
+fn parse_qtaguid(output: &str, uid: u32) -> UidTraffic {
+    let mut traffic = UidTraffic::default();
+    for line in output.lines().skip(1) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 9 || fields[2] != "0x0" || fields[3].parse::<u32>().ok() != Some(uid) {
+            continue;
+        }
+        let number = |index: usize| fields[index].parse::<u64>().unwrap_or(0);
+        let network = if fields[1].starts_with("wlan") {
+            Some("wifi")
+        } else if fields[1].starts_with("rmnet") || fields[1].starts_with("ccmni") {
+            Some("mobile")
+        } else {
+            None
+        };
+        traffic.add(
+            fields[4] == "1",
+            network,
+            Traffic {
+                rx_bytes: number(5),
+                rx_packets: number(6),
+                tx_bytes: number(7),
+                tx_packets: number(8),
+            },
+        );
+    }
+    traffic
+}
+
+fn network_stats(serial: &str, package: &str) -> Result<String, LbError> {
+    let uid = packages::package_uid(serial, package)?;
+    let (source, traffic) = match parse_netstats(&adb::shell(serial, "dumpsys netstats --full --uid")?, uid) {
+        Some(traffic) => ("netstats", traffic),
+        None => {
+            let output = adb::shell(serial, &format!("cat {}", QTAGUID_STATS))?;
+            ("xt_qtaguid", parse_qtaguid(&output, uid))
+        }
+    };
+    let mut total = traffic.foreground;
+    total.add(traffic.background);
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("package", package.into()),
+        ("uid", uid.into()),
+        ("source", source.into()),
+        ("foreground", traffic.foreground.to_json()),
+        ("background", traffic.background.to_json()),
+        ("total", total.to_json()),
+        ("wifi", traffic.wifi.to_json()),
+        ("mobile", traffic.mobile.to_json()),
+        ("first_bucket_ms", traffic.range_ms.map(|(first, _)| first).into()),
+        ("last_bucket_ms", traffic.range_ms.map(|(_, last)| last).into()),
+    ])
+    .to_string())
+}
+
+/// Data used by `package`'s UID (shared with any package in the same shared UID):
+/// `{serial, package, uid, source, foreground, background, total, wifi, mobile,
+/// first_bucket_ms, last_bucket_ms}`, each traffic field `{rx_bytes, rx_packets, tx_bytes,
+/// tx_packets}`. `source` is `netstats` (all history netstats retains, normally weeks) or
+/// `xt_qtaguid` when netstats has no UID section (old releases; since boot, no bucket range).
+#[no_mangle]
+pub extern "C" fn lb_network_stats(serial_ptr: *const c_char, package_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(serial_ptr, "serial")
+            .and_then(|serial| read_c_str(package_ptr, "package").and_then(|package| network_stats(serial, package)));
+        string_result(result, "network stats")
+    })
+}
@@ -0,0 +1,122 @@
+use std::ffi::c_void;
+use std::fs;
+use std::os::raw::c_char;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::adb::{self, shell_quote};
+use crate::adb_sync::SyncConnection;
+use crate::device_lock::ensure_device_unlocked;
+use crate::error::{ErrorCode, LbError};
+use crate::json::JsonValue;
+use crate::logging::{lb_log, Level};
+use crate::packages;
+use crate::remote_fs;
+use crate::stream::{LineCallback, LineSink};
+use crate::{ffi_guard, read_c_str, string_result};
+
+const OBB_ROOT: &str = "/sdcard/Android/obb";
+
+/// `main.<versionCode>.<package>.obb` or `patch.…`, the names the Play expansion-file
+/// APIs look for. Anything else still works for games that open the file themselves.
+fn is_expansion_name(name: &str, package: &str) -> bool {
+    name.strip_suffix(".obb")
+        .and_then(|stem| stem.strip_suffix(package))
+        .and_then(|stem| stem.strip_suffix('.'))
+        .and_then(|stem| stem.strip_prefix("main.").or_else(|| stem.strip_prefix("patch.")))
+        .is_some_and(|version| !version.is_empty() && version.bytes().all(|byte| byte.is_ascii_digit()))
+}
+
+fn push_obb(serial: &str, package: &str, obb_path: &str, sink: Option<LineSink>) -> Result<String, LbError> {
+    packages::validate_package(package)?;
+    let local = Path::new(obb_path);
+    if !local.is_file() {
+        return Err(LbError::not_found(format!("OBB file not found: {}", obb_path)));
+    }
+    let name = local
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| format!("OBB path has no usable file name: {}", obb_path))?;
+    let directory = format!("{}/{}", OBB_ROOT, package);
+    let remote = remote_fs::join(&directory, name);
+    remote_fs::validate_path(&remote)?;
+    ensure_device_unlocked(serial)?;
+
+    let started = Instant::now();
+    adb::shell(serial, &format!("mkdir -p {}", shell_quote(&directory)))?;
+    let mut sync = SyncConnection::open(serial)?;
+    let replaced = sync.stat(&remote)?.exists();
+    lb_log!(Level::Info, "sync", "Pushing {} to {} on {}", obb_path, remote, serial);
+    let mut last_percent = None;
+    let bytes = sync.push(local, &remote, |bytes, total| {
+        let percent = (bytes.saturating_mul(100)).checked_div(total).unwrap_or(100).min(100);
+        if let Some(sink) = sink.filter(|_| last_percent != Some(percent)) {
+            last_percent = Some(percent);
+            sink.emit(
+                &JsonValue::object(vec![
+                    ("file", name.into()),
+                    ("bytes", bytes.into()),
+                    ("total", total.into()),
+                    ("percent", percent.into()),
+                ])
+                .to_string(),
+            );
+        }
+    })?;
+
+    // Sync v1 `STAT` sizes wrap at 4 GiB on devices without `stat_v2`, so ask the device directly.
+    let local_size = fs::metadata(local)
+        .map_err(|err| LbError::io(format!("Failed to stat {}: {}", obb_path, err)))?
+        .len();
+    let output = adb::shell(serial, &format!("stat -c %s {}", shell_quote(&remote)))?;
+    let remote_size = output
+        .trim()
+        .parse::<u64>()
+        .map_err(|_| LbError::parse(format!("Unexpected stat output: {}", output.trim())).with_serial(serial))?;
+    if remote_size != local_size {
+        // A short file would look like a finished download to the game; do not leave it behind.
+        let _ = adb::shell(serial, &format!("rm -f {}", shell_quote(&remote)));
+        return Err(LbError::new(
+            ErrorCode::ChecksumMismatch,
+            format!("{} is {} bytes on the device but {} locally", remote, remote_size, local_size),
+        )
+        .with_serial(serial));
+    }
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("package", package.into()),
+        ("source", obb_path.into()),
+        ("destination", remote.into()),
+        ("bytes", bytes.into()),
+        ("replaced", replaced.into()),
+        ("expansion_name", is_expansion_name(name, package).into()),
+        ("duration_ms", (started.elapsed().as_millis() as u64).into()),
+    ])
+    .to_string())
+}
+
+/// Pushes `obb_path` to `/sdcard/Android/obb/<package>/<file name>`, creating the directory
+/// first, and checks the device copy has the local size (a mismatch is deleted and reported
+/// as ChecksumMismatch). The package does not need to be installed yet. `callback(user_data,
+/// json)` (may be null) receives `{file, bytes, total, percent}`. Returns `{serial, package,
+/// source, destination, bytes, replaced, expansion_name, duration_ms}`; `expansion_name` is
+/// false unless the file is named `main|patch.<versionCode>.<package>.obb`.
+#[no_mangle]
+pub extern "C" fn lb_push_obb(
+    serial_ptr: *const c_char,
+    package_ptr: *const c_char,
+    obb_path_ptr: *const c_char,
+    callback: Option<LineCallback>,
+    user_data: *mut c_void,
+) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let sink = callback.map(|callback| LineSink::new(Some(callback), user_data)).transpose();
+        let result = sink.and_then(|sink| {
+            let serial = read_c_str(serial_ptr, "serial")?;
+            let package = read_c_str(package_ptr, "package")?;
+            let obb_path = read_c_str(obb_path_ptr, "OBB path")?;
+            push_obb(serial, package, obb_path, sink)
+        });
+        string_result(result, "OBB push result")
+    })
+}
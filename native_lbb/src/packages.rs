@@ -0,0 +1,369 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::c_void;
+use std::fs;
+use std::os::raw::c_char;
+use std::path::Path;
+
+use crate::adb;
+use crate::adb_sync::SyncConnection;
+use crate::device_lock::ensure_device_unlocked;
+use crate::error::LbError;
+use crate::json::JsonValue;
+use crate::logging::{lb_log, Level};
+use crate::stream::{LineCallback, LineSink};
+use crate::users;
+use crate::{ffi_guard, read_c_str, string_result};
+
+/// `lb_list_packages` filters and the `pm list packages` flag each maps to.
+const FILTERS: [(&str, &str); 5] = [
+    ("all", ""),
+    ("system", " -s"),
+    ("third-party", " -3"),
+    ("enabled", " -e"),
+    ("disabled", " -d"),
+];
+
+pub fn validate_package(package: &str) -> Result<(), LbError> {
+    let valid = !package.is_empty()
+        && package.contains('.')
+        && package.chars().all(|ch| ch.is_ascii_alphanumeric() || ch == '.' || ch == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("Invalid package name: {}", package).into())
+    }
+}
+
+/// `package:com.example` lines, optionally `package:/path/base.apk=com.example` with `-f`.
+fn list_names(serial: &str, flags: &str) -> Result<Vec<String>, LbError> {
+    let output = adb::shell(serial, &format!("pm list packages{}", flags))?;
+    Ok(output
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("package:"))
+        .map(str::to_string)
+        .collect())
+}
+
+/// `versionName` / `versionCode` per `Package [name]` block of `dumpsys package packages`.
+fn parse_versions(output: &str) -> HashMap<String, (Option<String>, Option<u64>)> {
+    let mut versions = HashMap::new();
+    let mut current: Option<String> = None;
+    for line in output.lines() {
+        let line = line.trim();
+        if let Some(rest) = line.strip_prefix("Package [") {
+            current = rest.split_once(']').map(|(name, _)| name.to_string());
+            continue;
+        }
+        let Some(package) = &current else {
+            continue;
+        };
+        let entry: &mut (Option<String>, Option<u64>) = versions.entry(package.clone()).or_default();
+        for token in line.split_whitespace() {
+            match token.split_once('=') {
+                Some(("versionName", name)) if entry.0.is_none() => entry.0 = Some(name.to_string()),
+                Some(("versionCode", code)) if entry.1.is_none() => entry.1 = code.parse().ok(),
+                _ => {}
+            }
+        }
+    }
+    versions
+}
+
+fn list_packages(serial: &str, filter: &str) -> Result<String, LbError> {
+    let filter = if filter.is_empty() { "all" } else { filter };
+    let flags = FILTERS
+        .iter()
+        .find(|(name, _)| *name == filter)
+        .map(|(_, flags)| *flags)
+        .ok_or_else(|| format!("Unknown package filter: {} (expected all, system, third-party, enabled, disabled)", filter))?;
+    let paths = list_names(serial, &format!("{} -f", flags))?;
+    let system: HashSet<String> = list_names(serial, " -s")?.into_iter().collect();
+    let disabled: HashSet<String> = list_names(serial, " -d")?.into_iter().collect();
+    let versions = parse_versions(&adb::shell(serial, "dumpsys package packages")?);
+
+    let mut packages: Vec<(String, Option<String>)> = paths
+        .into_iter()
+        .map(|entry| match entry.rsplit_once('=') {
+            Some((path, package)) => (package.to_string(), Some(path.to_string())),
+            None => (entry, None),
+        })
+        .collect();
+    packages.sort();
+    let packages: Vec<JsonValue> = packages
+        .into_iter()
+        .map(|(package, path)| {
+            let (version_name, version_code) = versions.get(&package).cloned().unwrap_or_default();
+            JsonValue::object(vec![
+                ("package", package.as_str().into()),
+                ("path", path.into()),
+                ("system", system.contains(&package).into()),
+                ("enabled", (!disabled.contains(&package)).into()),
+                ("version_name", version_name.into()),
+                ("version_code", version_code.into()),
+            ])
+        })
+        .collect();
+    Ok(JsonValue::from(packages).to_string())
+}
+
+/// `am force-stop` and friends accept unknown packages silently, so check first.
+pub fn require_installed(serial: &str, package: &str) -> Result<(), LbError> {
+    require_installed_for_user(serial, package, None)
+}
+
+/// `require_installed` for one user; work-profile apps are only installed for the profile.
+pub fn require_installed_for_user(serial: &str, package: &str, user: Option<u32>) -> Result<(), LbError> {
+    validate_package(package)?;
+    if let Some(user) = user {
+        users::require_user(serial, user)?;
+    }
+    // `pm path` exits 1 for unknown packages; `|| true` keeps adb failures distinguishable.
+    let path = adb::shell(serial, &format!("pm path{} {} || true", users::user_args(user), package))?;
+    if !path.contains("package:") {
+        let target = user.map_or(String::new(), |user| format!(" for user {}", user));
+        let message = format!("Package {} is not installed{} on {}", package, target, serial);
+        return Err(LbError::not_found(message).with_serial(serial));
+    }
+    Ok(())
+}
+
+/// The app's UID from `pm list packages -U` (`package:com.example uid:10123`, Android 8+),
+/// or the `userId=` line of `dumpsys package` on older releases.
+pub fn package_uid(serial: &str, package: &str) -> Result<u32, LbError> {
+    validate_package(package)?;
+    // The filter is a substring match, so `com.example` also lists `com.example.debug`.
+    let listed = adb::shell(serial, &format!("pm list packages -U {} || true", package))?;
+    let uid = listed.lines().find_map(|line| {
+        let (name, uid) = line.trim().strip_prefix("package:")?.split_once(" uid:")?;
+        // Secondary users append their UIDs after a comma.
+        (name == package).then(|| uid.split(',').next()?.trim().parse().ok()).flatten()
+    });
+    let uid = match uid {
+        Some(uid) => Some(uid),
+        None => adb::shell(serial, &format!("dumpsys package {}", package))?
+            .lines()
+            .find_map(|line| line.trim().strip_prefix("userId=")?.split_whitespace().next()?.parse().ok()),
+    };
+    uid.ok_or_else(|| LbError::not_found(format!("Package {} is not installed on {}", package, serial)).with_serial(serial))
+}
+
+/// `Success`, or `Failure [DELETE_FAILED_INTERNAL_ERROR]` / `Failed` as printed by pm.
+fn package_result(serial: &str, package: &str, user: Option<u32>, action: &str, output: &str) -> String {
+    let success = output.lines().any(|line| line.trim() == "Success");
+    let failure_reason = (!success).then(|| {
+        output
+            .split_once("Failure [")
+            .and_then(|(_, rest)| rest.split_once(']'))
+            .map(|(reason, _)| reason.trim().to_string())
+            .unwrap_or_else(|| output.trim().to_string())
+    });
+    if let Some(reason) = &failure_reason {
+        lb_log!(Level::Warn, "install", "{} of {} on {} failed: {}", action, package, serial, reason);
+    }
+    JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("package", package.into()),
+        ("user", user.into()),
+        ("action", action.into()),
+        ("success", success.into()),
+        ("failure_reason", failure_reason.into()),
+    ])
+    .to_string()
+}
+
+/// Runs a pm/am command whose failures are reported on stdout; adb-level failures
+/// (device gone) stay errors.
+fn package_command(
+    serial: &str,
+    package: &str,
+    user: Option<u32>,
+    action: &str,
+    command: &str,
+) -> Result<String, LbError> {
+    ensure_device_unlocked(serial)?;
+    require_installed_for_user(serial, package, user)?;
+    lb_log!(Level::Info, "install", "{} {} on {}", action, package, serial);
+    let output = adb::run_adb(Some(serial), &["shell", command])?;
+    Ok(package_result(serial, package, user, action, &format!("{}{}", output.stdout, output.stderr)))
+}
+
+/// Without a user, pm uninstalls for every user; with one, only that user loses the app.
+fn uninstall(serial: &str, package: &str, keep_data: bool, user: Option<u32>) -> Result<String, LbError> {
+    let keep = if keep_data { " -k" } else { "" };
+    let command = format!("pm uninstall{}{} {}", keep, users::user_args(user), package);
+    package_command(serial, package, user, "uninstall", &command)
+}
+
+fn clear_app_data(serial: &str, package: &str, user: Option<u32>) -> Result<String, LbError> {
+    let command = format!("pm clear{} {}", users::user_args(user), package);
+    package_command(serial, package, user, "clear", &command)
+}
+
+fn force_stop(serial: &str, package: &str) -> Result<String, LbError> {
+    // `am force-stop` prints nothing on success.
+    package_command(serial, package, None, "force-stop", &format!("am force-stop {} && echo Success", package))
+}
+
+/// Every APK of an installed package (`base.apk` plus splits), in `pm path` order.
+fn apk_paths(serial: &str, package: &str) -> Result<Vec<String>, LbError> {
+    validate_package(package)?;
+    let output = adb::shell(serial, &format!("pm path {} || true", package))?;
+    let paths: Vec<String> = output
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("package:"))
+        .map(str::to_string)
+        .collect();
+    if paths.is_empty() {
+        return Err(LbError::not_found(format!("Package {} is not installed on {}", package, serial)).with_serial(serial));
+    }
+    Ok(paths)
+}
+
+/// Pulls into `<local_dir>/<package>/` keeping device file names, so the splits can be
+/// reinstalled together with `adb install-multiple`.
+fn pull_apk(serial: &str, package: &str, local_dir: &str, sink: Option<LineSink>) -> Result<String, LbError> {
+    let remotes = apk_paths(serial, package)?;
+    let target_dir = Path::new(local_dir).join(package);
+    fs::create_dir_all(&target_dir)
+        .map_err(|err| LbError::io(format!("Failed to create {}: {}", target_dir.display(), err)))?;
+    let mut sync = SyncConnection::open(serial)?;
+    lb_log!(Level::Info, "sync", "Pulling {} APK(s) of {} from {}", remotes.len(), package, serial);
+
+    let mut files = Vec::new();
+    for (index, remote) in remotes.iter().enumerate() {
+        let name = remote.rsplit('/').next().unwrap_or(remote);
+        let local = target_dir.join(name);
+        let mut last_percent = None;
+        let received = sync.pull(remote, &local, |bytes, total| {
+            let percent = (bytes.saturating_mul(100)).checked_div(total).unwrap_or(100).min(100);
+            if let Some(sink) = sink.filter(|_| last_percent != Some(percent)) {
+                last_percent = Some(percent);
+                sink.emit(
+                    &JsonValue::object(vec![
+                        ("file", name.into()),
+                        ("file_index", index.into()),
+                        ("file_count", remotes.len().into()),
+                        ("bytes", bytes.into()),
+                        ("total", total.into()),
+                        ("percent", percent.into()),
+                    ])
+                    .to_string(),
+                );
+            }
+        })?;
+        files.push(JsonValue::object(vec![
+            ("remote", remote.as_str().into()),
+            ("local", local.to_string_lossy().as_ref().into()),
+            ("bytes", received.into()),
+        ]));
+    }
+    Ok(JsonValue::from(files).to_string())
+}
+
+fn with_package(
+    serial_ptr: *const c_char,
+    package_ptr: *const c_char,
+    action: impl FnOnce(&str, &str) -> Result<String, LbError>,
+) -> Result<String, LbError> {
+    read_c_str(serial_ptr, "serial")
+        .and_then(|serial| read_c_str(package_ptr, "package").and_then(|package| action(serial, package)))
+}
+
+/// Returns `[{package, path, system, enabled, version_name, version_code}]` sorted by
+/// package. `filter` is `all` (or null/empty), `system`, `third-party`, `enabled`, or `disabled`.
+#[no_mangle]
+pub extern "C" fn lb_list_packages(serial_ptr: *const c_char, filter_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let filter = if filter_ptr.is_null() { Ok("") } else { read_c_str(filter_ptr, "filter") };
+        let result = read_c_str(serial_ptr, "serial").and_then(|serial| filter.and_then(|filter| list_packages(serial, filter)));
+        string_result(result, "package list")
+    })
+}
+
+/// Uninstalls `package` (keeping its data and cache when `keep_data` is non-zero). Returns
+/// `{serial, package, action, success, failure_reason}`; a package manager refusal is a
+/// result with `success: false`, an unknown package is a NotFound error.
+#[no_mangle]
+pub extern "C" fn lb_uninstall(serial_ptr: *const c_char, package_ptr: *const c_char, keep_data: i32) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = with_package(serial_ptr, package_ptr, |serial, package| {
+            uninstall(serial, package, keep_data != 0, None)
+        });
+        string_result(result, "uninstall result")
+    })
+}
+
+/// `lb_uninstall` for one user (`lb_list_users` ids, e.g. a work profile); the app stays
+/// installed for everyone else. A negative `user_id` behaves like `lb_uninstall`.
+#[no_mangle]
+pub extern "C" fn lb_uninstall_for_user(
+    serial_ptr: *const c_char,
+    package_ptr: *const c_char,
+    keep_data: i32,
+    user_id: i32,
+) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = with_package(serial_ptr, package_ptr, |serial, package| {
+            uninstall(serial, package, keep_data != 0, users::user_from_ffi(user_id))
+        });
+        string_result(result, "uninstall result")
+    })
+}
+
+/// Pulls every APK of `package` (base and splits) into `<local_dir>/<package>/` over the
+/// sync protocol and returns `[{remote, local, bytes}]`. `callback(user_data, json)` (may
+/// be null) receives `{file, file_index, file_count, bytes, total, percent}` on the calling
+/// thread as each file downloads.
+#[no_mangle]
+pub extern "C" fn lb_pull_apk(
+    serial_ptr: *const c_char,
+    package_ptr: *const c_char,
+    local_dir_ptr: *const c_char,
+    callback: Option<LineCallback>,
+    user_data: *mut c_void,
+) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let sink = callback.map(|callback| LineSink::new(Some(callback), user_data)).transpose();
+        let result = sink.and_then(|sink| {
+            with_package(serial_ptr, package_ptr, |serial, package| {
+                read_c_str(local_dir_ptr, "local directory").and_then(|local_dir| pull_apk(serial, package, local_dir, sink))
+            })
+        });
+        string_result(result, "pulled APK list")
+    })
+}
+
+/// `pm clear`: wipes the package's data, cache, and granted runtime permissions. Same
+/// result shape as `lb_uninstall`.
+#[no_mangle]
+pub extern "C" fn lb_clear_app_data(serial_ptr: *const c_char, package_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = with_package(serial_ptr, package_ptr, |serial, package| clear_app_data(serial, package, None));
+        string_result(result, "clear result")
+    })
+}
+
+/// `lb_clear_app_data` for one user's copy of the app. A negative `user_id` clears the
+/// default user's data.
+#[no_mangle]
+pub extern "C" fn lb_clear_app_data_for_user(
+    serial_ptr: *const c_char,
+    package_ptr: *const c_char,
+    user_id: i32,
+) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = with_package(serial_ptr, package_ptr, |serial, package| {
+            clear_app_data(serial, package, users::user_from_ffi(user_id))
+        });
+        string_result(result, "clear result")
+    })
+}
+
+/// `am force-stop`: kills the package's processes and cancels its alarms and jobs. Same
+/// result shape as `lb_uninstall`.
+#[no_mangle]
+pub extern "C" fn lb_force_stop(serial_ptr: *const c_char, package_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        string_result(with_package(serial_ptr, package_ptr, force_stop), "force-stop result")
+    })
+}
@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::os::raw::c_char;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+use crate::adb;
+use crate::adb_sync::SyncConnection;
+use crate::error::LbError;
+use crate::exec;
+use crate::json::JsonValue;
+use crate::logging::{lb_log, Level};
+use crate::{ffi_guard, now_millis, read_c_str, status_result, string_result};
+
+/// The only directory perfetto may write to under SELinux on Android 12+.
+const TRACE_DIR: &str = "/data/misc/perfetto-traces";
+
+/// A detached perfetto session; `key` is what `--attach` / `--stop` refer to.
+struct TraceSession {
+    key: String,
+    remote_path: String,
+    started_ms: u64,
+}
+
+static TRACE_SESSIONS: OnceLock<Mutex<HashMap<String, TraceSession>>> = OnceLock::new();
+
+fn trace_registry() -> &'static Mutex<HashMap<String, TraceSession>> {
+    TRACE_SESSIONS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn start_trace(serial: &str, config: &str) -> Result<(), LbError> {
+    if config.trim().is_empty() {
+        return Err("Perfetto config must not be empty".into());
+    }
+    let mut registry = trace_registry()
+        .lock()
+        .map_err(|_| LbError::internal("Trace registry poisoned"))?;
+    if registry.contains_key(serial) {
+        return Err(format!("Perfetto trace already active for {}", serial).into());
+    }
+    let started_ms = now_millis();
+    let key = format!("lb_{}", started_ms);
+    let remote_path = format!("{}/{}.perfetto-trace", TRACE_DIR, key);
+    // The config goes over stdin: perfetto cannot read files outside its own
+    // directories, and pushing into TRACE_DIR needs root.
+    let detach = format!("--detach={}", key);
+    let argv = adb::adb_argv(
+        Some(serial),
+        &["shell", "perfetto", "--txt", "-c", "-", "-o", &remote_path, &detach],
+    );
+    let output = exec::run_argv_with_input(&argv, Some(config.as_bytes()), exec::output_limit())?;
+    if !output.success() {
+        let detail = if output.stderr.trim().is_empty() { &output.stdout } else { &output.stderr };
+        return Err(LbError::new(
+            adb::classify_failure(detail),
+            format!("perfetto failed to start (exit={}): {}", output.exit_code.unwrap_or(-1), detail.trim()),
+        )
+        .with_command(&argv)
+        .with_serial(serial));
+    }
+    lb_log!(Level::Info, "recording", "Perfetto trace {} started on {}", key, serial);
+    registry.insert(
+        serial.to_string(),
+        TraceSession {
+            key,
+            remote_path,
+            started_ms,
+        },
+    );
+    Ok(())
+}
+
+fn stop_and_pull(serial: &str, local_path: &str) -> Result<String, LbError> {
+    let session = trace_registry()
+        .lock()
+        .map_err(|_| LbError::internal("Trace registry poisoned"))?
+        .remove(serial)
+        .ok_or_else(|| LbError::not_found(format!("No perfetto trace active for {}", serial)))?;
+    let attach = format!("--attach={}", session.key);
+    // `--stop` blocks until the trace file is finalized.
+    let stopped = adb::adb_checked(Some(serial), &["shell", "perfetto", &attach, "--stop"]);
+    if let Err(err) = stopped {
+        // The session is gone from the registry; say where the trace may still be.
+        return Err(LbError::new(
+            err.code,
+            format!("Failed to stop perfetto trace {} (trace at {}): {}", session.key, session.remote_path, err.message),
+        )
+        .with_serial(serial));
+    }
+    let local = Path::new(local_path);
+    if let Some(parent) = local.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent).map_err(|err| LbError::io(format!("Failed to create {}: {}", parent.display(), err)))?;
+    }
+    let bytes = SyncConnection::open(serial)?.pull(&session.remote_path, local, |_, _| {})?;
+    let _ = adb::shell(serial, &format!("rm -f {}", session.remote_path));
+    lb_log!(Level::Info, "recording", "Perfetto trace {} pulled from {} ({} bytes)", session.key, serial, bytes);
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("local_path", local_path.into()),
+        ("bytes", bytes.into()),
+        ("duration_ms", now_millis().saturating_sub(session.started_ms).into()),
+    ])
+    .to_string())
+}
+
+/// Starts a detached perfetto session on `serial` from a text-format (`.pbtxt`) config.
+/// One trace per device; it keeps running until `lb_perfetto_stop_and_pull`.
+#[no_mangle]
+pub extern "C" fn lb_perfetto_start(serial_ptr: *const c_char, config_ptr: *const c_char) -> i32 {
+    ffi_guard(0, || {
+        let result = read_c_str(serial_ptr, "serial")
+            .and_then(|serial| read_c_str(config_ptr, "perfetto config").and_then(|config| start_trace(serial, config)));
+        status_result(result)
+    })
+}
+
+/// Stops the device's trace, pulls it to `local_path`, deletes the device copy, and returns
+/// `{serial, local_path, bytes, duration_ms}`.
+#[no_mangle]
+pub extern "C" fn lb_perfetto_stop_and_pull(serial_ptr: *const c_char, local_path_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(serial_ptr, "serial")
+            .and_then(|serial| read_c_str(local_path_ptr, "local path").and_then(|local| stop_and_pull(serial, local)));
+        string_result(result, "trace result")
+    })
+}
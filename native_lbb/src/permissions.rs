@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+use std::os::raw::c_char;
+
+use crate::adb;
+use crate::device_lock::ensure_device_unlocked;
+use crate::error::LbError;
+use crate::json::{self, JsonValue};
+use crate::logging::{lb_log, Level};
+use crate::packages;
+use crate::{ffi_guard, read_c_str, string_result};
+
+const RESULT_MARKER: &str = "__LB_PERMISSION__";
+const APPOPS_MARKER: &str = "__LB_APPOPS__";
+const APPOP_MODES: [&str; 5] = ["allow", "ignore", "deny", "default", "foreground"];
+
+enum Change {
+    Permission { name: String, granted: bool },
+    AppOp { name: String, mode: String },
+}
+
+impl Change {
+    /// `{"permission": "CAMERA" | "android.permission.CAMERA", "granted": bool}` or
+    /// `{"appop": "SYSTEM_ALERT_WINDOW", "mode": "allow"}`.
+    fn parse(entry: &JsonValue) -> Result<Change, LbError> {
+        if let Some(name) = entry.get("permission").and_then(JsonValue::as_str) {
+            let valid = !name.is_empty() && name.chars().all(|ch| ch.is_ascii_alphanumeric() || ch == '.' || ch == '_');
+            if !valid {
+                return Err(format!("Invalid permission name: {}", name).into());
+            }
+            let granted = entry
+                .get("granted")
+                .and_then(JsonValue::as_bool)
+                .ok_or_else(|| format!("Permission {} needs a boolean \"granted\"", name))?;
+            let name = if name.contains('.') {
+                name.to_string()
+            } else {
+                format!("android.permission.{}", name)
+            };
+            return Ok(Change::Permission { name, granted });
+        }
+        if let Some(name) = entry.get("appop").and_then(JsonValue::as_str) {
+            let name = name.strip_prefix("android:").unwrap_or(name).to_ascii_uppercase();
+            if name.is_empty() || !name.chars().all(|ch| ch.is_ascii_alphanumeric() || ch == '_') {
+                return Err(format!("Invalid appop name: {}", name).into());
+            }
+            let mode = entry.get("mode").and_then(JsonValue::as_str).unwrap_or_default();
+            if !APPOP_MODES.contains(&mode) {
+                return Err(format!("Appop {} needs a mode of {}", name, APPOP_MODES.join(", ")).into());
+            }
+            return Ok(Change::AppOp { name, mode: mode.to_string() });
+        }
+        Err("Each change needs a \"permission\" or an \"appop\"".to_string().into())
+    }
+
+    fn command(&self, package: &str) -> String {
+        match self {
+            Change::Permission { name, granted } => {
+                format!("pm {} {} {}", if *granted { "grant" } else { "revoke" }, package, name)
+            }
+            Change::AppOp { name, mode } => format!("appops set {} {} {}", package, name, mode),
+        }
+    }
+}
+
+/// Runtime and install permission grants from `dumpsys package` plus package-level appop
+/// modes from `appops get`.
+struct PermissionState {
+    granted: HashMap<String, bool>,
+    appops: HashMap<String, String>,
+}
+
+impl PermissionState {
+    fn read(serial: &str, package: &str) -> Result<PermissionState, LbError> {
+        let output = adb::shell(
+            serial,
+            &format!("dumpsys package {}; echo {}; appops get {} 2>&1", package, APPOPS_MARKER, package),
+        )?;
+        let (dumpsys, appops) = output.split_once(APPOPS_MARKER).unwrap_or((&output, ""));
+        let mut granted = HashMap::new();
+        for line in dumpsys.lines() {
+            // `android.permission.CAMERA: granted=true, flags=[ USER_SET ]`; user 0 comes first.
+            if let Some((permission, state)) = line.trim().split_once(": granted=") {
+                granted
+                    .entry(permission.trim().to_string())
+                    .or_insert_with(|| state.starts_with("true"));
+            }
+        }
+        let mut modes = HashMap::new();
+        for line in appops.lines() {
+            // `CAMERA: ignore; time=+1m2s ago`; `Uid mode:` lines are the UID-wide state.
+            let line = line.trim();
+            if line.starts_with("Uid mode:") {
+                continue;
+            }
+            if let Some((op, rest)) = line.split_once(": ") {
+                let mode = rest.split([';', ' ']).next().unwrap_or_default();
+                if APPOP_MODES.contains(&mode) {
+                    modes.insert(op.to_string(), mode.to_string());
+                }
+            }
+        }
+        Ok(PermissionState { granted, appops: modes })
+    }
+
+    /// Unlisted appops are at their default.
+    fn value(&self, change: &Change) -> JsonValue {
+        match change {
+            Change::Permission { name, .. } => self.granted.get(name).copied().into(),
+            Change::AppOp { name, .. } => self.appops.get(name).map(String::as_str).unwrap_or("default").into(),
+        }
+    }
+
+    fn matches(&self, change: &Change) -> bool {
+        match change {
+            Change::Permission { name, granted } => self.granted.get(name) == Some(granted),
+            Change::AppOp { name, mode } => self.appops.get(name).map(String::as_str).unwrap_or("default") == mode,
+        }
+    }
+}
+
+fn set_permissions(serial: &str, package: &str, grants_json: &str) -> Result<String, LbError> {
+    let entries = json::parse(grants_json)?;
+    let entries = entries.as_array().ok_or("Permission changes must be a JSON array")?;
+    let changes = entries.iter().map(Change::parse).collect::<Result<Vec<_>, _>>()?;
+    if changes.is_empty() {
+        return Err("No permission changes given".to_string().into());
+    }
+    ensure_device_unlocked(serial)?;
+    packages::require_installed(serial, package)?;
+    let before = PermissionState::read(serial, package)?;
+    let script = changes
+        .iter()
+        .map(|change| {
+            format!(
+                // pm prints `Exception occurred while executing 'grant':` before the reason.
+                "out=$({} 2>&1); echo \"{} $? $(echo \"$out\" | grep -m 1 Exception: || echo \"$out\" | grep -m 1 .)\"",
+                change.command(package),
+                RESULT_MARKER
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("; ");
+    let output = adb::shell(serial, &script)?;
+    let results: Vec<&str> = output
+        .lines()
+        .filter_map(|line| line.strip_prefix(RESULT_MARKER))
+        .map(str::trim)
+        .collect();
+    let after = PermissionState::read(serial, package)?;
+
+    let mut applied = 0usize;
+    let mut items = Vec::new();
+    for (index, change) in changes.iter().enumerate() {
+        let result = results.get(index).copied().unwrap_or("? no result");
+        let (status, detail) = result.split_once(' ').unwrap_or((result, ""));
+        let took_effect = after.matches(change);
+        let error = (!took_effect).then(|| {
+            if detail.is_empty() {
+                format!("exit {}; state unchanged", status)
+            } else {
+                detail.to_string()
+            }
+        });
+        applied += usize::from(took_effect);
+        let (kind, name, wanted) = match change {
+            Change::Permission { name, granted } => ("permission", name, JsonValue::from(*granted)),
+            Change::AppOp { name, mode } => ("appop", name, JsonValue::from(mode.as_str())),
+        };
+        items.push(JsonValue::object(vec![
+            ("type", kind.into()),
+            ("name", name.as_str().into()),
+            ("wanted", wanted),
+            ("before", before.value(change)),
+            ("after", after.value(change)),
+            ("took_effect", took_effect.into()),
+            ("error", error.into()),
+        ]));
+    }
+    let failed = changes.len() - applied;
+    let level = if failed > 0 { Level::Warn } else { Level::Info };
+    lb_log!(level, "install", "Applied {}/{} permission changes to {} on {}", applied, changes.len(), package, serial);
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("package", package.into()),
+        ("applied", applied.into()),
+        ("failed", failed.into()),
+        ("changes", items.into()),
+    ])
+    .to_string())
+}
+
+/// Grants/revokes runtime permissions and sets appop modes for `package` in one shell, then
+/// re-reads `dumpsys package` and `appops get`. `grants_json` is an array of
+/// `{"permission": name, "granted": bool}` (bare names get `android.permission.`) and
+/// `{"appop": OP, "mode": "allow" | "ignore" | "deny" | "default" | "foreground"}`.
+/// Returns `{serial, package, applied, failed, changes: [{type, name, wanted, before, after,
+/// took_effect, error}]}`; a change the device refused or silently ignored (OEM builds,
+/// non-changeable permissions) has `took_effect: false` rather than failing the call.
+#[no_mangle]
+pub extern "C" fn lb_set_permissions(
+    serial_ptr: *const c_char,
+    package_ptr: *const c_char,
+    grants_json_ptr: *const c_char,
+) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(serial_ptr, "serial").and_then(|serial| {
+            let package = read_c_str(package_ptr, "package")?;
+            let grants_json = read_c_str(grants_json_ptr, "grants JSON")?;
+            set_permissions(serial, package, grants_json)
+        });
+        string_result(result, "permission changes")
+    })
+}
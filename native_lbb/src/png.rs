@@ -0,0 +1,167 @@
+use std::io::{self, Write};
+use std::sync::OnceLock;
+
+use crate::animation::RawFrame;
+use crate::deflate;
+use crate::error::LbError;
+
+pub const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+const COLOR_RGB: u8 = 2;
+const COLOR_RGBA: u8 = 6;
+
+fn crc32(parts: &[&[u8]]) -> u32 {
+    static TABLE: OnceLock<[u32; 256]> = OnceLock::new();
+    let table = TABLE.get_or_init(|| {
+        let mut table = [0u32; 256];
+        for (index, entry) in table.iter_mut().enumerate() {
+            let mut value = index as u32;
+            for _ in 0..8 {
+                value = if value & 1 == 1 { 0xedb8_8320 ^ (value >> 1) } else { value >> 1 };
+            }
+            *entry = value;
+        }
+        table
+    });
+    let mut crc = 0xffff_ffffu32;
+    for byte in parts.iter().flat_map(|part| part.iter()) {
+        crc = table[((crc ^ u32::from(*byte)) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+pub fn write_chunk(out: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    out.write_all(&crc32(&[kind, data]).to_be_bytes())
+}
+
+/// `IHDR` payload and the concatenated `IDAT` payloads of a PNG.
+pub fn parts(png: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+    if png.get(..8)? != SIGNATURE {
+        return None;
+    }
+    let mut header = None;
+    let mut data = Vec::new();
+    let mut offset = 8;
+    while offset + 12 <= png.len() {
+        let length = u32::from_be_bytes(png[offset..offset + 4].try_into().ok()?) as usize;
+        let kind = &png[offset + 4..offset + 8];
+        let body = png.get(offset + 8..offset + 8 + length)?;
+        match kind {
+            b"IHDR" => header = Some(body.to_vec()),
+            b"IDAT" => data.extend_from_slice(body),
+            b"IEND" => break,
+            _ => {}
+        }
+        offset += 12 + length;
+    }
+    Some((header?, data))
+}
+
+fn paeth(left: u8, up: u8, up_left: u8) -> u8 {
+    let estimate = i16::from(left) + i16::from(up) - i16::from(up_left);
+    let (to_left, to_up, to_up_left) =
+        ((estimate - i16::from(left)).abs(), (estimate - i16::from(up)).abs(), (estimate - i16::from(up_left)).abs());
+    if to_left <= to_up && to_left <= to_up_left {
+        left
+    } else if to_up <= to_up_left {
+        up
+    } else {
+        up_left
+    }
+}
+
+/// Decodes an 8-bit, non-interlaced RGB or RGBA PNG (what `screencap -p` writes) to RGBA.
+pub fn decode(png: &[u8]) -> Result<RawFrame, LbError> {
+    let (header, compressed) = parts(png).ok_or_else(|| LbError::parse("Not a PNG image"))?;
+    if header.len() != 13 {
+        return Err(LbError::parse("PNG has a malformed IHDR chunk"));
+    }
+    let width = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+    let height = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+    let (depth, color, interlace) = (header[8], header[9], header[12]);
+    let channels = match color {
+        COLOR_RGB => 3,
+        COLOR_RGBA => 4,
+        _ => return Err(LbError::parse(format!("Unsupported PNG color type {}", color))),
+    };
+    if depth != 8 || interlace != 0 {
+        return Err(LbError::parse("Only 8-bit, non-interlaced PNGs are supported"));
+    }
+    let filtered = deflate::zlib_decompress(&compressed)?;
+    let stride = width as usize * channels;
+    if filtered.len() < (stride + 1) * height as usize {
+        return Err(LbError::parse("PNG image data is truncated"));
+    }
+    let mut previous = vec![0u8; stride];
+    let mut rgba = Vec::with_capacity(width as usize * height as usize * 4);
+    for row in filtered.chunks_exact(stride + 1).take(height as usize) {
+        let mut line = row[1..].to_vec();
+        for index in 0..stride {
+            let left = if index >= channels { line[index - channels] } else { 0 };
+            let up_left = if index >= channels { previous[index - channels] } else { 0 };
+            let predictor = match row[0] {
+                0 => 0,
+                1 => left,
+                2 => previous[index],
+                3 => ((u16::from(left) + u16::from(previous[index])) / 2) as u8,
+                4 => paeth(left, previous[index], up_left),
+                filter => return Err(LbError::parse(format!("Invalid PNG filter type {}", filter))),
+            };
+            line[index] = line[index].wrapping_add(predictor);
+        }
+        for pixel in line.chunks_exact(channels) {
+            rgba.extend_from_slice(&pixel[..3]);
+            rgba.push(if channels == 4 { pixel[3] } else { 0xff });
+        }
+        previous = line;
+    }
+    Ok(RawFrame { width, height, rgba })
+}
+
+/// Encodes RGBA pixels as an 8-bit RGB PNG (alpha dropped), picking each row's filter by
+/// the usual minimum-sum-of-absolute-differences heuristic.
+pub fn encode(frame: &RawFrame) -> Vec<u8> {
+    let stride = frame.width as usize * 3;
+    let rgb: Vec<u8> = frame.rgba.chunks_exact(4).flat_map(|pixel| pixel[..3].iter().copied()).collect();
+    let mut filtered = Vec::with_capacity((stride + 1) * frame.height as usize);
+    let zero = vec![0u8; stride];
+    for (row_index, line) in rgb.chunks_exact(stride).enumerate() {
+        let previous = if row_index == 0 { &zero[..] } else { &rgb[(row_index - 1) * stride..row_index * stride] };
+        let candidates: Vec<Vec<u8>> = (0..5u8)
+            .map(|filter| {
+                let mut out = Vec::with_capacity(stride + 1);
+                out.push(filter);
+                for index in 0..stride {
+                    let left = if index >= 3 { line[index - 3] } else { 0 };
+                    let up_left = if index >= 3 { previous[index - 3] } else { 0 };
+                    let predictor = match filter {
+                        0 => 0,
+                        1 => left,
+                        2 => previous[index],
+                        3 => ((u16::from(left) + u16::from(previous[index])) / 2) as u8,
+                        _ => paeth(left, previous[index], up_left),
+                    };
+                    out.push(line[index].wrapping_sub(predictor));
+                }
+                out
+            })
+            .collect();
+        let cost = |row: &Vec<u8>| row[1..].iter().map(|&byte| u32::from((byte as i8).unsigned_abs())).sum::<u32>();
+        if let Some(best) = candidates.iter().min_by_key(|row| cost(row)) {
+            filtered.extend_from_slice(best);
+        }
+    }
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&frame.width.to_be_bytes());
+    header.extend_from_slice(&frame.height.to_be_bytes());
+    header.extend_from_slice(&[8, COLOR_RGB, 0, 0, 0]);
+    let mut png = SIGNATURE.to_vec();
+    // Writing into a Vec cannot fail.
+    let _ = write_chunk(&mut png, b"IHDR", &header);
+    let _ = write_chunk(&mut png, b"IDAT", &deflate::zlib_compress(&filtered));
+    let _ = write_chunk(&mut png, b"IEND", &[]);
+    png
+}
@@ -0,0 +1,159 @@
+use std::fs;
+use std::os::raw::c_char;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+
+use crate::error::LbError;
+use crate::json::{self, JsonValue};
+use crate::logging::{lb_log, Level};
+use crate::{ffi_guard, read_c_str, status_result, string_result};
+
+const KINDS: [&str; 4] = ["logcat_filter", "recording", "install", "device_group"];
+const DEFAULT_DIR_NAME: &str = ".lazy_blacktea_presets";
+
+/// Override set by `lb_set_presets_dir`; `None` means `~/.lazy_blacktea_presets`.
+static PRESETS_DIR: OnceLock<Mutex<Option<PathBuf>>> = OnceLock::new();
+
+fn presets_dir_slot() -> &'static Mutex<Option<PathBuf>> {
+    PRESETS_DIR.get_or_init(|| Mutex::new(None))
+}
+
+pub fn presets_dir() -> Result<PathBuf, LbError> {
+    let configured = presets_dir_slot()
+        .lock()
+        .map_err(|_| LbError::internal("Presets dir poisoned"))?
+        .clone();
+    if let Some(dir) = configured {
+        return Ok(dir);
+    }
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(|home| PathBuf::from(home).join(DEFAULT_DIR_NAME))
+        .ok_or_else(|| LbError::not_found("No home directory; call lb_set_presets_dir first"))
+}
+
+/// `<dir>/<kind>/`, after checking `kind` is one the host knows how to apply.
+fn kind_dir(kind: &str) -> Result<PathBuf, LbError> {
+    if !KINDS.contains(&kind) {
+        return Err(format!("Unknown preset kind '{}'; expected one of {}", kind, KINDS.join(", ")).into());
+    }
+    Ok(presets_dir()?.join(kind))
+}
+
+/// Names become file stems, so they may not contain separators or start with a dot.
+fn validate_name(name: &str) -> Result<(), LbError> {
+    let valid = !name.trim().is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|ch| ch.is_alphanumeric() || matches!(ch, '-' | '_' | '.' | ' '));
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("Invalid preset name '{}'; use letters, digits, spaces, '-', '_' or '.'", name).into())
+    }
+}
+
+fn save_preset(kind: &str, name: &str, preset_json: &str) -> Result<(), LbError> {
+    validate_name(name)?;
+    let preset = json::parse(preset_json)?;
+    let dir = kind_dir(kind)?;
+    fs::create_dir_all(&dir).map_err(|err| LbError::io(format!("Failed to create {}: {}", dir.display(), err)))?;
+    let path = dir.join(format!("{}.json", name));
+    // Written beside the target and renamed so a crash never leaves a half-written preset.
+    let staging = dir.join(format!(".{}.json.tmp", name));
+    fs::write(&staging, preset.to_string())
+        .and_then(|_| fs::rename(&staging, &path))
+        .map_err(|err| LbError::io(format!("Failed to write {}: {}", path.display(), err)))
+}
+
+fn list_presets(kind: &str) -> Result<String, LbError> {
+    let dir = kind_dir(kind)?;
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(JsonValue::Array(Vec::new()).to_string());
+        }
+        Err(err) => return Err(LbError::io(format!("Failed to read {}: {}", dir.display(), err))),
+    };
+    let mut presets: Vec<(String, JsonValue)> = Vec::new();
+    for path in entries.filter_map(Result::ok).map(|entry| entry.path()) {
+        let Some(name) = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(".json"))
+            .filter(|name| validate_name(name).is_ok())
+        else {
+            continue;
+        };
+        // A hand-edited or shared file that no longer parses is skipped, not fatal.
+        match fs::read_to_string(&path).map_err(|err| LbError::io(err.to_string())).and_then(|text| json::parse(&text)) {
+            Ok(preset) => presets.push((name.to_string(), preset)),
+            Err(err) => lb_log!(Level::Warn, "presets", "Skipping {}: {}", path.display(), err.message),
+        }
+    }
+    presets.sort_by(|a, b| a.0.cmp(&b.0));
+    let items: Vec<JsonValue> = presets
+        .into_iter()
+        .map(|(name, preset)| JsonValue::object(vec![("name", name.into()), ("preset", preset)]))
+        .collect();
+    Ok(JsonValue::from(items).to_string())
+}
+
+fn delete_preset(kind: &str, name: &str) -> Result<(), LbError> {
+    validate_name(name)?;
+    let path = kind_dir(kind)?.join(format!("{}.json", name));
+    fs::remove_file(&path).map_err(|err| match err.kind() {
+        std::io::ErrorKind::NotFound => LbError::not_found(format!("No {} preset named '{}'", kind, name)),
+        _ => LbError::io(format!("Failed to delete {}: {}", path.display(), err)),
+    })
+}
+
+/// Stores presets under `path` instead of `~/.lazy_blacktea_presets`; null restores the default.
+#[no_mangle]
+pub extern "C" fn lb_set_presets_dir(path_ptr: *const c_char) -> i32 {
+    ffi_guard(0, || {
+        let result = if path_ptr.is_null() {
+            Ok(None)
+        } else {
+            read_c_str(path_ptr, "path").map(|path| Some(PathBuf::from(path)))
+        };
+        let result = result.and_then(|dir| {
+            *presets_dir_slot()
+                .lock()
+                .map_err(|_| LbError::internal("Presets dir poisoned"))? = dir;
+            Ok(())
+        });
+        status_result(result)
+    })
+}
+
+/// Saves `preset_json` as `<presets dir>/<kind>/<name>.json`, replacing a preset of the same
+/// name. Kinds: `logcat_filter`, `recording`, `install`, `device_group`.
+#[no_mangle]
+pub extern "C" fn lb_save_preset(kind_ptr: *const c_char, name_ptr: *const c_char, preset_ptr: *const c_char) -> i32 {
+    ffi_guard(0, || {
+        let result = read_c_str(kind_ptr, "kind").and_then(|kind| {
+            read_c_str(name_ptr, "name")
+                .and_then(|name| read_c_str(preset_ptr, "preset").and_then(|preset| save_preset(kind, name, preset)))
+        });
+        status_result(result)
+    })
+}
+
+/// Returns `[{"name", "preset"}]` for every readable preset of `kind`, sorted by name.
+#[no_mangle]
+pub extern "C" fn lb_list_presets(kind_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        string_result(read_c_str(kind_ptr, "kind").and_then(list_presets), "preset list")
+    })
+}
+
+/// Removes one preset; a missing preset is a `NotFound` error.
+#[no_mangle]
+pub extern "C" fn lb_delete_preset(kind_ptr: *const c_char, name_ptr: *const c_char) -> i32 {
+    ffi_guard(0, || {
+        let result = read_c_str(kind_ptr, "kind").and_then(|kind| read_c_str(name_ptr, "name").and_then(|name| delete_preset(kind, name)));
+        status_result(result)
+    })
+}
@@ -0,0 +1,207 @@
+//! `lazy_blacktea_native` CPython extension over the same internals as the C ABI. Values
+//! cross as real Python objects instead of JSON strings, failures raise typed exceptions,
+//! and every call that talks to adb releases the GIL while it runs.
+
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use pyo3::types::{PyBool, PyBytes, PyDict, PyFloat, PyInt, PyList, PyString, PyTuple};
+
+use crate::adb;
+use crate::device_meta;
+use crate::error::{ErrorCode, LbError};
+use crate::json::{self, JsonValue};
+use crate::rpc;
+
+create_exception!(lazy_blacktea_native, NativeError, PyException, "Base class for native library failures.");
+create_exception!(lazy_blacktea_native, InvalidArgumentError, NativeError, "Bad argument or unparsable input.");
+create_exception!(lazy_blacktea_native, CommandError, NativeError, "adb could not be spawned or exited non-zero.");
+create_exception!(lazy_blacktea_native, NativeTimeoutError, NativeError, "A command exceeded its deadline.");
+create_exception!(lazy_blacktea_native, DeviceError, NativeError, "Device offline, unauthorized, locked or denied access.");
+create_exception!(lazy_blacktea_native, NotFoundError, NativeError, "Unknown device, method, path or handle.");
+
+/// Raises the exception class for `err.code`, carrying `code`, `name`, `serial`, `command`
+/// and `artifacts` as attributes so callers need not parse the message.
+fn to_py_err(py: Python<'_>, err: LbError) -> PyErr {
+    let message = err.message.clone();
+    let exception = match err.code {
+        ErrorCode::NullPointer | ErrorCode::Utf8 | ErrorCode::InvalidArgument | ErrorCode::ParseError => {
+            InvalidArgumentError::new_err(message)
+        }
+        ErrorCode::SpawnFailed | ErrorCode::CommandFailed => CommandError::new_err(message),
+        ErrorCode::Timeout => NativeTimeoutError::new_err(message),
+        ErrorCode::DeviceOffline
+        | ErrorCode::DeviceUnauthorized
+        | ErrorCode::DeviceLocked
+        | ErrorCode::PermissionDenied => DeviceError::new_err(message),
+        ErrorCode::NotFound => NotFoundError::new_err(message),
+        _ => NativeError::new_err(message),
+    };
+    let value = exception.value(py);
+    let attributes = value
+        .setattr("code", err.code as i32)
+        .and_then(|_| value.setattr("name", err.code.name()))
+        .and_then(|_| value.setattr("serial", err.serial.clone()))
+        .and_then(|_| value.setattr("command", err.command.clone()))
+        .and_then(|_| value.setattr("artifacts", err.artifacts.clone()));
+    match attributes {
+        Ok(()) => exception,
+        Err(err) => err,
+    }
+}
+
+/// Runs `work` with the GIL released and converts its error into the matching exception.
+fn blocking<T, F>(py: Python<'_>, work: F) -> PyResult<T>
+where
+    T: Send,
+    F: Send + FnOnce() -> Result<T, LbError>,
+{
+    py.allow_threads(work).map_err(|err| to_py_err(py, err))
+}
+
+fn to_python(py: Python<'_>, value: &JsonValue) -> PyResult<PyObject> {
+    Ok(match value {
+        JsonValue::Null => py.None(),
+        JsonValue::Bool(flag) => flag.into_pyobject(py)?.to_owned().into_any().unbind(),
+        JsonValue::Number(number) if number.fract() == 0.0 && number.abs() < 9.007_199_254_740_992e15 => {
+            (*number as i64).into_pyobject(py)?.into_any().unbind()
+        }
+        JsonValue::Number(number) => number.into_pyobject(py)?.into_any().unbind(),
+        JsonValue::String(text) => text.into_pyobject(py)?.into_any().unbind(),
+        JsonValue::Array(items) => {
+            let items = items.iter().map(|item| to_python(py, item)).collect::<PyResult<Vec<_>>>()?;
+            PyList::new(py, items)?.into_any().unbind()
+        }
+        JsonValue::Object(pairs) => {
+            let dict = PyDict::new(py);
+            for (key, item) in pairs {
+                dict.set_item(key, to_python(py, item)?)?;
+            }
+            dict.into_any().unbind()
+        }
+    })
+}
+
+fn from_python(value: &Bound<'_, PyAny>) -> PyResult<JsonValue> {
+    if value.is_none() {
+        return Ok(JsonValue::Null);
+    }
+    // bool before int: Python's bool is an int subclass.
+    if let Ok(flag) = value.downcast::<PyBool>() {
+        return Ok(JsonValue::Bool(flag.is_true()));
+    }
+    if value.is_instance_of::<PyInt>() {
+        return Ok(JsonValue::Number(value.extract::<i64>()? as f64));
+    }
+    if let Ok(number) = value.downcast::<PyFloat>() {
+        return Ok(JsonValue::Number(number.value()));
+    }
+    if value.is_instance_of::<PyString>() {
+        return Ok(JsonValue::String(value.extract()?));
+    }
+    if let Ok(dict) = value.downcast::<PyDict>() {
+        let mut pairs = Vec::with_capacity(dict.len());
+        for (key, item) in dict.iter() {
+            let key: String = key
+                .extract()
+                .map_err(|_| InvalidArgumentError::new_err("Dictionary keys must be strings"))?;
+            pairs.push((key, from_python(&item)?));
+        }
+        return Ok(JsonValue::Object(pairs));
+    }
+    if value.is_instance_of::<PyList>() || value.is_instance_of::<PyTuple>() {
+        let items = value.try_iter()?.map(|item| from_python(&item?)).collect::<PyResult<Vec<_>>>()?;
+        return Ok(JsonValue::Array(items));
+    }
+    Err(InvalidArgumentError::new_err(format!(
+        "Cannot convert {} to JSON",
+        value.get_type().name()?
+    )))
+}
+
+fn parse_result(py: Python<'_>, text: &str) -> PyResult<PyObject> {
+    let value = json::parse(text).map_err(|err| to_py_err(py, err))?;
+    to_python(py, &value)
+}
+
+/// `{"version", "git_hash", "profile"}` as a dict.
+#[pyfunction]
+fn version(py: Python<'_>) -> PyResult<PyObject> {
+    to_python(py, &crate::version::version_json())
+}
+
+/// Capability names compiled into this build.
+#[pyfunction]
+fn capabilities() -> Vec<&'static str> {
+    crate::version::capabilities()
+}
+
+/// Calls any `lb_invoke` method by name; `params` is a dict (or list for positional methods).
+#[pyfunction]
+#[pyo3(signature = (method, params = None, version = None))]
+fn invoke(py: Python<'_>, method: &str, params: Option<&Bound<'_, PyAny>>, version: Option<u64>) -> PyResult<PyObject> {
+    let params = params.map(from_python).transpose()?;
+    let method = method.to_string();
+    let result = blocking(py, move || rpc::dispatch(&method, version, params.as_ref()))?;
+    to_python(py, &result)
+}
+
+/// Attached devices merged with stored labels and groups, as a list of dicts.
+#[pyfunction]
+fn list_devices(py: Python<'_>) -> PyResult<PyObject> {
+    let devices = blocking(py, device_meta::list_devices_json)?;
+    parse_result(py, &devices)
+}
+
+/// Runs structured command specs (`{"argv": [...], ...}`) in parallel; one result dict per spec.
+#[pyfunction]
+fn run_commands(py: Python<'_>, commands: &Bound<'_, PyAny>) -> PyResult<PyObject> {
+    let payload = from_python(commands)?.to_string();
+    let results = blocking(py, move || crate::run_commands_structured(&payload))?;
+    parse_result(py, &results)
+}
+
+/// Full-size screenshot as PNG bytes.
+#[pyfunction]
+fn screenshot<'py>(py: Python<'py>, serial: &str) -> PyResult<Bound<'py, PyBytes>> {
+    let data = blocking(py, || adb::screencap_png(serial))?;
+    Ok(PyBytes::new(py, &data))
+}
+
+/// `uiautomator dump` XML of the current screen.
+#[pyfunction]
+fn dump_hierarchy(py: Python<'_>, serial: &str) -> PyResult<String> {
+    let data = blocking(py, || adb::dump_ui_xml(serial))?;
+    Ok(String::from_utf8_lossy(&data).into_owned())
+}
+
+#[pyfunction]
+fn start_screen_record(py: Python<'_>, serial: &str, remote_path: &str) -> PyResult<()> {
+    blocking(py, || crate::start_screen_record(serial, remote_path))
+}
+
+#[pyfunction]
+fn stop_screen_record(py: Python<'_>, serial: &str) -> PyResult<()> {
+    blocking(py, || crate::stop_screen_record(serial))
+}
+
+#[pymodule]
+fn lazy_blacktea_native(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    let py = module.py();
+    module.add("NativeError", py.get_type::<NativeError>())?;
+    module.add("InvalidArgumentError", py.get_type::<InvalidArgumentError>())?;
+    module.add("CommandError", py.get_type::<CommandError>())?;
+    module.add("NativeTimeoutError", py.get_type::<NativeTimeoutError>())?;
+    module.add("DeviceError", py.get_type::<DeviceError>())?;
+    module.add("NotFoundError", py.get_type::<NotFoundError>())?;
+    module.add_function(wrap_pyfunction!(version, module)?)?;
+    module.add_function(wrap_pyfunction!(capabilities, module)?)?;
+    module.add_function(wrap_pyfunction!(invoke, module)?)?;
+    module.add_function(wrap_pyfunction!(list_devices, module)?)?;
+    module.add_function(wrap_pyfunction!(run_commands, module)?)?;
+    module.add_function(wrap_pyfunction!(screenshot, module)?)?;
+    module.add_function(wrap_pyfunction!(dump_hierarchy, module)?)?;
+    module.add_function(wrap_pyfunction!(start_screen_record, module)?)?;
+    module.add_function(wrap_pyfunction!(stop_screen_record, module)?)?;
+    Ok(())
+}
@@ -0,0 +1,212 @@
+use std::os::raw::c_char;
+use std::time::{Duration, Instant};
+
+use crate::adb;
+use crate::cancel;
+use crate::device_lock::ensure_device_unlocked;
+use crate::error::{ErrorCode, LbError};
+use crate::fastboot;
+use crate::json::JsonValue;
+use crate::logging::{lb_log, Level};
+use crate::{ffi_guard, read_c_str, status_result, string_result};
+
+/// `lb_reboot` targets and the `adb reboot` argument for each (`system` takes none).
+const TARGETS: [(&str, Option<&str>); 4] = [
+    ("system", None),
+    ("recovery", Some("recovery")),
+    ("bootloader", Some("bootloader")),
+    ("sideload", Some("sideload")),
+];
+/// `adb get-state` values plus `booted` (device with `sys.boot_completed=1`), `bootloader`
+/// (listed by `fastboot devices`) and `disconnect` (gone from adb).
+const STATES: [&str; 7] = ["device", "booted", "recovery", "sideload", "rescue", "bootloader", "disconnect"];
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// How long `lb_reboot` waits for the device to drop its current state.
+const LEAVE_STATE_TIMEOUT: Duration = Duration::from_secs(30);
+/// `lb_wait_for_boot` stages, in the order a booting device passes them.
+const BOOT_STAGES: [&str; 4] = ["present", "boot_completed", "package_manager", "launcher"];
+/// The placeholder HOME Settings shows until the user unlocks after boot.
+const FALLBACK_HOME: &str = "FallbackHome";
+
+fn in_state(serial: &str, state: &str) -> Result<bool, LbError> {
+    let current = adb::get_state(serial);
+    Ok(match state {
+        "disconnect" => current.is_none(),
+        "bootloader" => fastboot::device_listed(serial)?,
+        "booted" => {
+            current.as_deref() == Some("device") && adb::getprop(serial, "sys.boot_completed").as_deref() == Ok("1")
+        }
+        _ => current.as_deref() == Some(state),
+    })
+}
+
+pub fn wait_for_state(serial: &str, state: &str, timeout: Duration) -> Result<(), LbError> {
+    if !STATES.contains(&state) {
+        return Err(format!("Unknown device state '{}'; expected one of {}", state, STATES.join(", ")).into());
+    }
+    let started = Instant::now();
+    let network = adb::is_network_serial(serial);
+    loop {
+        if in_state(serial, state)? {
+            lb_log!(Level::Debug, "adb", "{} reached {} after {}ms", serial, state, started.elapsed().as_millis());
+            return Ok(());
+        }
+        if started.elapsed() >= timeout {
+            return Err(LbError::new(
+                ErrorCode::Timeout,
+                format!("Timed out after {}ms waiting for {} to reach {}", timeout.as_millis(), serial, state),
+            )
+            .with_serial(serial));
+        }
+        // Wireless devices do not rejoin adb on their own after a reboot.
+        if network && state != "disconnect" && state != "bootloader" && adb::get_state(serial).is_none() {
+            let _ = adb::adb_checked(None, &["connect", serial]);
+        }
+        cancel::sleep(POLL_INTERVAL)?;
+    }
+}
+
+/// The resolved HOME activity's package, unless only the boot-time fallback resolves.
+fn launcher_package(serial: &str) -> Result<Option<String>, LbError> {
+    let output = adb::shell(
+        serial,
+        "cmd package resolve-activity --brief -a android.intent.action.MAIN -c android.intent.category.HOME",
+    )?;
+    Ok(output
+        .lines()
+        .rev()
+        .map(str::trim)
+        .find(|line| line.contains('/'))
+        .filter(|component| !component.ends_with(FALLBACK_HOME))
+        .and_then(|component| component.split('/').next())
+        .map(str::to_string))
+}
+
+fn stage_reached(serial: &str, stage: &str) -> Result<bool, LbError> {
+    Ok(match stage {
+        "present" => adb::get_state(serial).as_deref() == Some("device"),
+        "boot_completed" => adb::getprop(serial, "sys.boot_completed").as_deref() == Ok("1"),
+        // `pm` fails with `Can't find service: package` until the service registers.
+        "package_manager" => adb::shell(serial, "pm path android || true")?.contains("package:"),
+        _ => match launcher_package(serial)? {
+            Some(launcher) => adb::focused_window(serial)?.contains(&format!(" {}/", launcher)),
+            None => false,
+        },
+    })
+}
+
+/// Walks `BOOT_STAGES` in order (skipping `launcher` unless asked), recording when each
+/// was reached. Shell failures while the device is still coming up count as not yet.
+fn wait_for_boot(serial: &str, timeout: Duration, wait_launcher: bool) -> Result<String, LbError> {
+    let started = Instant::now();
+    let network = adb::is_network_serial(serial);
+    let stages = if wait_launcher { &BOOT_STAGES[..] } else { &BOOT_STAGES[..3] };
+    let mut reached = Vec::new();
+    for stage in stages {
+        loop {
+            if stage_reached(serial, stage).unwrap_or(false) {
+                reached.push((*stage, JsonValue::from(started.elapsed().as_millis() as u64)));
+                break;
+            }
+            if started.elapsed() >= timeout {
+                return Err(LbError::new(
+                    ErrorCode::Timeout,
+                    format!(
+                        "Timed out after {}ms waiting for {} at stage {}",
+                        timeout.as_millis(),
+                        serial,
+                        stage
+                    ),
+                )
+                .with_serial(serial));
+            }
+            if network && adb::get_state(serial).is_none() {
+                let _ = adb::adb_checked(None, &["connect", serial]);
+            }
+            cancel::sleep(POLL_INTERVAL)?;
+        }
+    }
+    let total_ms = started.elapsed().as_millis() as u64;
+    lb_log!(Level::Info, "adb", "{} ready after {}ms", serial, total_ms);
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("stages", JsonValue::Object(reached.into_iter().map(|(stage, ms)| (stage.to_string(), ms)).collect())),
+        ("total_ms", total_ms.into()),
+    ])
+    .to_string())
+}
+
+fn reboot(serial: &str, target: &str) -> Result<(), LbError> {
+    let (_, argument) = TARGETS.iter().find(|(name, _)| *name == target).ok_or_else(|| {
+        let names: Vec<&str> = TARGETS.iter().map(|(name, _)| *name).collect();
+        LbError::from(format!("Unknown reboot target '{}'; expected one of {}", target, names.join(", ")))
+    })?;
+    ensure_device_unlocked(serial)?;
+    let before = adb::get_state(serial);
+    let mut args = vec!["reboot"];
+    args.extend(argument);
+    adb::adb_checked(Some(serial), &args)?;
+    lb_log!(Level::Info, "adb", "Rebooting {} into {}", serial, target);
+    // Until the old connection drops, `get-state` still reports the pre-reboot state, and a
+    // caller waiting for `device` would return before the reboot even started.
+    let Some(before) = before else {
+        return Ok(());
+    };
+    if !wait_to_leave(serial, &before, LEAVE_STATE_TIMEOUT)? {
+        lb_log!(Level::Warn, "adb", "{} still reports {} after reboot; continuing", serial, before);
+    }
+    Ok(())
+}
+
+/// Polls until `get-state` stops reporting `state`; false if it still does at `timeout`.
+pub fn wait_to_leave(serial: &str, state: &str, timeout: Duration) -> Result<bool, LbError> {
+    let started = Instant::now();
+    while started.elapsed() < timeout {
+        if adb::get_state(serial).as_deref() != Some(state) {
+            return Ok(true);
+        }
+        cancel::sleep(POLL_INTERVAL)?;
+    }
+    Ok(false)
+}
+
+/// Reboots `serial` into `system`, `recovery`, `bootloader` or `sideload`, returning once
+/// the device has dropped its previous adb state (at most 30s). Pair with
+/// `lb_wait_for_state` to sequence work after the reboot.
+#[no_mangle]
+pub extern "C" fn lb_reboot(serial_ptr: *const c_char, target_ptr: *const c_char) -> i32 {
+    ffi_guard(0, || {
+        let result = read_c_str(serial_ptr, "serial")
+            .and_then(|serial| read_c_str(target_ptr, "reboot target").and_then(|target| reboot(serial, target)));
+        status_result(result)
+    })
+}
+
+/// Blocks until `serial` is in `state` (`device`, `booted`, `recovery`, `sideload`, `rescue`,
+/// `bootloader` or `disconnect`), polling every 500 ms and reconnecting wireless devices.
+/// Fails with Timeout after `timeout_ms`.
+#[no_mangle]
+pub extern "C" fn lb_wait_for_state(serial_ptr: *const c_char, state_ptr: *const c_char, timeout_ms: u64) -> i32 {
+    ffi_guard(0, || {
+        let result = read_c_str(serial_ptr, "serial").and_then(|serial| {
+            read_c_str(state_ptr, "state")
+                .and_then(|state| wait_for_state(serial, state, Duration::from_millis(timeout_ms)))
+        });
+        status_result(result)
+    })
+}
+
+/// Blocks until `serial` is usable after a (re)boot: present in adb as `device`,
+/// `sys.boot_completed=1`, the package manager answering, and with `wait_launcher`
+/// non-zero the HOME app focused (not Settings' `FallbackHome`, so a device waiting for
+/// its first unlock never gets there). Returns `{serial, stages: {present, boot_completed,
+/// package_manager[, launcher]}, total_ms}` with the ms at which each stage was reached;
+/// a Timeout names the stage it was stuck at.
+#[no_mangle]
+pub extern "C" fn lb_wait_for_boot(serial_ptr: *const c_char, timeout_ms: u64, wait_launcher: i32) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(serial_ptr, "serial")
+            .and_then(|serial| wait_for_boot(serial, Duration::from_millis(timeout_ms), wait_launcher != 0));
+        string_result(result, "boot readiness")
+    })
+}
@@ -0,0 +1,229 @@
+//! Crash recovery for screen recordings. The registry lives in host memory, so after the host
+//! crashes its `screenrecord` processes keep running on the devices with nobody to stop
+//! them; `lb_reconcile_recordings` finds those on startup and adopts or stops them.
+
+use std::os::raw::c_char;
+use std::time::{Duration, Instant};
+
+use crate::adb;
+use crate::cancel;
+use crate::error::LbError;
+use crate::fleet::{self, parse_serials};
+use crate::json::JsonValue;
+use crate::logging::{lb_log, Level};
+use crate::{
+    ffi_guard, now_millis, read_c_str, recording_registry, string_result, RecordingHandle, SCREENRECORD_STOP_TIMEOUT_SECS,
+};
+
+const STOP_POLL_INTERVAL_MS: u64 = 200;
+
+/// One pid and its full command line per line, for every `screenrecord` on the device.
+const LIST_RECORDINGS: &str =
+    "for pid in $(pidof screenrecord); do echo \"$pid $(tr '\\0' ' ' < /proc/$pid/cmdline)\"; done";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Action {
+    Report,
+    Adopt,
+    Stop,
+}
+
+impl Action {
+    fn parse(action: Option<&str>) -> Result<Action, LbError> {
+        match action.unwrap_or("report") {
+            "report" => Ok(Action::Report),
+            "adopt" => Ok(Action::Adopt),
+            "stop" => Ok(Action::Stop),
+            other => Err(format!("Unknown reconcile action '{}'; expected report, adopt or stop", other).into()),
+        }
+    }
+}
+
+struct DeviceRecording {
+    pid: u32,
+    /// The output file: `screenrecord [options] <file>` always ends with it.
+    remote_path: Option<String>,
+}
+
+fn parse_recordings(output: &str) -> Vec<DeviceRecording> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let pid = fields.next()?.parse().ok()?;
+            let remote_path = fields.last().filter(|arg| !arg.starts_with('-') && !arg.ends_with("screenrecord"));
+            Some(DeviceRecording {
+                pid,
+                remote_path: remote_path.map(str::to_string),
+            })
+        })
+        .collect()
+}
+
+fn device_recordings(serial: &str) -> Result<Vec<DeviceRecording>, LbError> {
+    adb::shell(serial, LIST_RECORDINGS).map(|output| parse_recordings(&output))
+}
+
+/// SIGINT lets screenrecord write the MP4 index; waits for every pid to exit so the files
+/// are complete before they are reported.
+fn stop_recordings(serial: &str, pids: &[u32]) -> Result<(), LbError> {
+    let pid_list: Vec<String> = pids.iter().map(u32::to_string).collect();
+    adb::shell(serial, &format!("kill -INT {}", pid_list.join(" ")))?;
+    let deadline = Instant::now() + Duration::from_secs(SCREENRECORD_STOP_TIMEOUT_SECS);
+    loop {
+        let running = device_recordings(serial)?;
+        if !running.iter().any(|recording| pids.contains(&recording.pid)) {
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            return Err(LbError::from("screenrecord did not exit after SIGINT").with_serial(serial));
+        }
+        cancel::sleep(Duration::from_millis(STOP_POLL_INTERVAL_MS))?;
+    }
+}
+
+/// `{path, size_bytes}` for each file, `size_bytes` null when it does not exist.
+fn remote_files(serial: &str, paths: &[String]) -> Vec<JsonValue> {
+    paths
+        .iter()
+        .map(|path| {
+            let size = adb::shell(serial, &format!("stat -c %s {}", adb::shell_quote(path)))
+                .ok()
+                .and_then(|size| size.trim().parse::<u64>().ok());
+            JsonValue::object(vec![("path", path.as_str().into()), ("size_bytes", size.into())])
+        })
+        .collect()
+}
+
+fn reconcile_device(serial: &str, action: Action) -> Result<JsonValue, LbError> {
+    let recordings = device_recordings(serial)?;
+    let tracked_path = recording_registry()
+        .lock()
+        .map_err(|_| LbError::internal("Recording registry is unavailable"))?
+        .get(serial)
+        .filter(|recording| recording.ended.is_none())
+        .map(|recording| recording.remote_path.clone());
+    // Only the process writing the tracked file is ours; any other screenrecord on the
+    // device is an orphan even while a recording is tracked.
+    let orphans: Vec<&DeviceRecording> = recordings
+        .iter()
+        .filter(|recording| {
+            tracked_path
+                .as_deref()
+                .is_none_or(|tracked| recording.remote_path.as_deref().unwrap_or_default() != tracked)
+        })
+        .collect();
+    let mut orphan_paths: Vec<String> = orphans.iter().filter_map(|orphan| orphan.remote_path.clone()).collect();
+    orphan_paths.sort();
+    orphan_paths.dedup();
+
+    let mut error = None;
+    let status = match (action, orphans.as_slice()) {
+        (_, []) => "none",
+        (Action::Report, _) => "reported",
+        (Action::Adopt, _) if tracked_path.is_some() => {
+            error = Some("A recording is already tracked on this device; stop it before adopting".to_string());
+            "reported"
+        }
+        (Action::Adopt, [orphan]) => {
+            let mut registry = recording_registry()
+                .lock()
+                .map_err(|_| LbError::internal("Recording registry is unavailable"))?;
+            // `lb_start_screen_record` may have run since the registry was read.
+            if registry.get(serial).is_some_and(|recording| recording.ended.is_none()) {
+                error = Some("A recording was started while reconciling".to_string());
+                "reported"
+            } else {
+                registry.insert(
+                    serial.to_string(),
+                    RecordingHandle {
+                        child: None,
+                        remote_path: orphan.remote_path.clone().unwrap_or_default(),
+                        started_ms: now_millis(),
+                        ended: None,
+                    },
+                );
+                lb_log!(Level::Info, "recording", "Adopted screenrecord {} on {}", orphan.pid, serial);
+                "adopted"
+            }
+        }
+        (Action::Adopt, _) => {
+            error = Some("Several screenrecord processes are running; only one can be adopted".to_string());
+            "reported"
+        }
+        (Action::Stop, _) => {
+            let pids: Vec<u32> = orphans.iter().map(|orphan| orphan.pid).collect();
+            match stop_recordings(serial, &pids) {
+                Ok(()) => {
+                    lb_log!(Level::Info, "recording", "Stopped {} orphaned screenrecord on {}", pids.len(), serial);
+                    "stopped"
+                }
+                Err(err) => {
+                    error = Some(err.message);
+                    "reported"
+                }
+            }
+        }
+    };
+
+    let orphans: Vec<JsonValue> = orphans
+        .iter()
+        .map(|orphan| {
+            JsonValue::object(vec![
+                ("pid", orphan.pid.into()),
+                ("remote_path", orphan.remote_path.clone().into()),
+            ])
+        })
+        .collect();
+    Ok(JsonValue::object(vec![
+        ("tracked", tracked_path.into()),
+        ("orphans", orphans.into()),
+        ("status", status.into()),
+        ("files", remote_files(serial, &orphan_paths).into()),
+        ("error", error.into()),
+    ]))
+}
+
+fn reconcile_recordings(serials: Vec<String>, action: Option<&str>) -> Result<String, LbError> {
+    let action = Action::parse(action)?;
+    let serials = if serials.is_empty() {
+        adb::list_devices()?
+            .into_iter()
+            .filter(|device| device.state == "device")
+            .map(|device| device.serial)
+            .collect()
+    } else {
+        serials
+    };
+    let results = fleet::on_devices(&serials, move |serial| {
+        reconcile_device(serial, action).unwrap_or_else(|err| {
+            JsonValue::object(vec![("error", err.with_serial(serial).to_json())])
+        })
+    })?;
+    let report: Vec<(String, JsonValue)> = serials.into_iter().zip(results).collect();
+    Ok(JsonValue::Object(report).to_string())
+}
+
+/// Finds `screenrecord` processes on `serials` (a JSON array; `[]` = every connected device)
+/// that the recording registry does not track, e.g. left running by a host that crashed;
+/// on a device with a tracked recording, every process not writing its file is an orphan.
+/// Meant to be called once at host startup. `action` (null = `report`) is `report`,
+/// `adopt` (register the device's single orphan so `lb_stop_screen_record` stops it) or
+/// `stop` (SIGINT, then wait for the files to be finalized). Returns `{"<serial>": {tracked,
+/// orphans: [{pid, remote_path}], status: none|reported|adopted|stopped, files: [{path,
+/// size_bytes}], error}}` or `{"<serial>": {error}}` for a device that could not be checked;
+/// `files` are the orphans' outputs, left on the device for the host to pull or delete.
+#[no_mangle]
+pub extern "C" fn lb_reconcile_recordings(serials_json_ptr: *const c_char, action_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let action = if action_ptr.is_null() {
+            Ok(None)
+        } else {
+            read_c_str(action_ptr, "action").map(Some)
+        };
+        let result = read_c_str(serials_json_ptr, "serial list")
+            .and_then(parse_serials)
+            .and_then(|serials| action.and_then(|action| reconcile_recordings(serials, action)));
+        string_result(result, "reconcile report")
+    })
+}
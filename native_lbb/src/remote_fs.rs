@@ -0,0 +1,309 @@
+use std::os::raw::c_char;
+
+use crate::adb::{self, shell_quote};
+use crate::adb_sync::{RemoteEntry, RemoteStat, SyncConnection};
+use crate::device_lock::ensure_device_unlocked;
+use crate::error::{ErrorCode, LbError};
+use crate::json::JsonValue;
+use crate::{ffi_guard, read_c_str, status_result, string_result};
+
+pub const S_IFMT: u32 = 0o170000;
+pub const S_IFDIR: u32 = 0o040000;
+pub const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
+/// Name, status, and symlink target of one directory entry.
+type Row = (String, RemoteStat, Option<String>);
+
+pub fn validate_path(path: &str) -> Result<(), LbError> {
+    if path.starts_with('/') && !path.contains('\0') {
+        Ok(())
+    } else {
+        Err(format!("Device paths must be absolute, got '{}'", path).into())
+    }
+}
+
+pub fn join(dir: &str, name: &str) -> String {
+    format!("{}/{}", dir.trim_end_matches('/'), name)
+}
+
+fn kind(mode: u32) -> &'static str {
+    match mode & S_IFMT {
+        S_IFDIR => "dir",
+        S_IFREG => "file",
+        S_IFLNK => "symlink",
+        _ => "other",
+    }
+}
+
+fn entry_json(name: &str, stat: &RemoteStat, link_target: Option<String>) -> JsonValue {
+    JsonValue::object(vec![
+        ("name", name.into()),
+        ("type", kind(stat.mode).into()),
+        ("size", stat.size.into()),
+        ("mode", (stat.mode & 0o7777).into()),
+        ("mtime", stat.mtime.into()),
+        ("link_target", link_target.into()),
+    ])
+}
+
+/// Symlink targets for `names` in `dir`, in order; sync `LIST` only reports that they are links.
+fn read_links(serial: &str, dir: &str, names: &[&str]) -> Vec<Option<String>> {
+    if names.is_empty() {
+        return Vec::new();
+    }
+    let quoted: Vec<String> = names.iter().map(|name| shell_quote(&join(dir, name))).collect();
+    let script = format!("for f in {}; do echo \"$(readlink \"$f\")\"; done", quoted.join(" "));
+    let output = adb::shell(serial, &script).unwrap_or_default();
+    let mut targets: Vec<Option<String>> = output
+        .lines()
+        .map(|line| Some(line.to_string()).filter(|target| !target.is_empty()))
+        .collect();
+    targets.resize(names.len(), None);
+    targets
+}
+
+/// Days since 1970-01-01 for a civil date (proleptic Gregorian).
+pub fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// `(year, month, day)` for days since 1970-01-01; the inverse of `days_from_civil`.
+pub fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    (year_of_era + era * 400 + i64::from(month <= 2), month, day)
+}
+
+/// `2024-01-31 12:05` in the device's local time, read as UTC; minute precision only.
+fn parse_ls_time(date: &str, time: &str) -> Option<u64> {
+    let mut date = date.split('-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let (hour, minute) = time.split_once(':')?;
+    let (hour, minute) = (hour.parse::<i64>().ok()?, minute.parse::<i64>().ok()?);
+    let seconds = days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60;
+    u64::try_from(seconds).ok()
+}
+
+fn parse_permissions(text: &str) -> Option<u32> {
+    let bytes = text.as_bytes();
+    if bytes.len() < 10 {
+        return None;
+    }
+    let file_type = match bytes[0] {
+        b'd' => S_IFDIR,
+        b'l' => S_IFLNK,
+        b'-' => S_IFREG,
+        _ => 0,
+    };
+    let mut mode = 0;
+    for (index, byte) in bytes[1..10].iter().enumerate() {
+        let bit = 1 << (8 - index);
+        match byte {
+            b'-' => {}
+            // setuid / setgid / sticky replace the execute slot, lowercase when it is also set.
+            b's' | b't' => mode |= bit | (0o4000 >> (index / 3)),
+            b'S' | b'T' => mode |= 0o4000 >> (index / 3),
+            _ => mode |= bit,
+        }
+    }
+    Some(file_type | mode)
+}
+
+/// Toybox `ls -la` rows: `drwxrwx--x 4 system system 4096 2024-01-31 12:05 name`, with
+/// `major, minor` instead of a size for device nodes and `name -> target` for links.
+fn parse_ls_line(line: &str) -> Option<Row> {
+    let permissions = line.split_whitespace().next()?;
+    let mode = parse_permissions(permissions)?;
+    let device = permissions.starts_with('c') || permissions.starts_with('b');
+    let fields_before_name = if device { 8 } else { 7 };
+    let mut rest = line;
+    let mut fields = Vec::new();
+    for _ in 0..fields_before_name {
+        let trimmed = rest.trim_start();
+        let end = trimmed.find(char::is_whitespace)?;
+        fields.push(&trimmed[..end]);
+        rest = &trimmed[end..];
+    }
+    let name = rest.trim_start();
+    let (size, date, time) = (
+        if device { 0 } else { fields[4].parse().ok()? },
+        fields[fields_before_name - 2],
+        fields[fields_before_name - 1],
+    );
+    let (name, target) = match name.split_once(" -> ") {
+        Some((name, target)) if mode & S_IFMT == S_IFLNK => (name, Some(target.to_string())),
+        _ => (name, None),
+    };
+    let stat = RemoteStat {
+        mode,
+        size,
+        mtime: parse_ls_time(date, time).unwrap_or(0),
+    };
+    Some((name.to_string(), stat, target))
+}
+
+/// `ls -la` for paths the sync service cannot read (e.g. directories only `shell` may list).
+fn ls_entries(serial: &str, path: &str, directory: bool) -> Result<Vec<Row>, LbError> {
+    let flags = if directory { "-la" } else { "-lad" };
+    let output = adb::shell(serial, &format!("ls {} {}", flags, shell_quote(path))).map_err(|err| {
+        let code = if err.message.contains("No such file") {
+            ErrorCode::NotFound
+        } else if err.message.contains("Permission denied") {
+            ErrorCode::PermissionDenied
+        } else {
+            err.code
+        };
+        LbError::new(code, format!("Cannot read {} on {}: {}", path, serial, err.message)).with_serial(serial)
+    })?;
+    Ok(output
+        .lines()
+        .filter_map(parse_ls_line)
+        .filter(|(name, _, _)| name != "." && name != "..")
+        .collect())
+}
+
+fn list(serial: &str, path: &str) -> Result<String, LbError> {
+    validate_path(path)?;
+    let mut sync = SyncConnection::open(serial)?;
+    let entries: Vec<RemoteEntry> = sync.list(path)?;
+    let mut rows: Vec<Row> = if entries.is_empty() {
+        // adbd answers an unreadable or missing directory with an empty listing.
+        let stat = sync.stat(path)?;
+        if stat.exists() && stat.mode & S_IFMT != S_IFDIR && stat.mode & S_IFMT != S_IFLNK {
+            return Err(format!("{} is not a directory", path).into());
+        }
+        ls_entries(serial, path, true)?
+    } else {
+        let entries: Vec<RemoteEntry> =
+            entries.into_iter().filter(|entry| entry.name != "." && entry.name != "..").collect();
+        let links: Vec<&str> = entries
+            .iter()
+            .filter(|entry| entry.stat.mode & S_IFMT == S_IFLNK)
+            .map(|entry| entry.name.as_str())
+            .collect();
+        let mut targets = read_links(serial, path, &links).into_iter();
+        entries
+            .into_iter()
+            .map(|entry| {
+                let target = if entry.stat.mode & S_IFMT == S_IFLNK { targets.next().flatten() } else { None };
+                (entry.name, entry.stat, target)
+            })
+            .collect()
+    };
+    rows.sort_by(|a, b| a.0.cmp(&b.0));
+    let entries: Vec<JsonValue> =
+        rows.iter().map(|(name, stat, target)| entry_json(name, stat, target.clone())).collect();
+    Ok(JsonValue::from(entries).to_string())
+}
+
+fn stat(serial: &str, path: &str) -> Result<String, LbError> {
+    validate_path(path)?;
+    let name = path.trim_end_matches('/').rsplit('/').next().filter(|name| !name.is_empty()).unwrap_or("/");
+    let stat = SyncConnection::open(serial)?.stat(path)?;
+    if stat.exists() {
+        let target = if stat.mode & S_IFMT == S_IFLNK {
+            let parent = path.trim_end_matches('/').rsplit_once('/').map_or("/", |(parent, _)| parent);
+            read_links(serial, parent, &[name]).pop().flatten()
+        } else {
+            None
+        };
+        return Ok(entry_json(name, &stat, target).to_string());
+    }
+    let (_, stat, target) = ls_entries(serial, path, false)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| LbError::not_found(format!("{} does not exist on {}", path, serial)).with_serial(serial))?;
+    Ok(entry_json(name, &stat, target).to_string())
+}
+
+fn mkdir(serial: &str, path: &str) -> Result<(), LbError> {
+    validate_path(path)?;
+    ensure_device_unlocked(serial)?;
+    adb::shell(serial, &format!("mkdir -p {}", shell_quote(path))).map(|_| ())
+}
+
+fn remove(serial: &str, path: &str, recursive: bool) -> Result<(), LbError> {
+    validate_path(path)?;
+    if path.trim_end_matches('/').is_empty() {
+        return Err("Refusing to remove /".into());
+    }
+    ensure_device_unlocked(serial)?;
+    let flags = if recursive { "-rf" } else { "-f" };
+    // `rm -f` is silent about missing paths; a file manager should hear about them.
+    let quoted = shell_quote(path);
+    adb::shell(serial, &format!("ls -d {} >/dev/null && rm {} {}", quoted, flags, quoted)).map(|_| ())
+}
+
+fn rename(serial: &str, from: &str, to: &str) -> Result<(), LbError> {
+    validate_path(from)?;
+    validate_path(to)?;
+    ensure_device_unlocked(serial)?;
+    adb::shell(serial, &format!("mv {} {}", shell_quote(from), shell_quote(to))).map(|_| ())
+}
+
+/// Lists a device directory as `[{name, type, size, mode, mtime, link_target}]` sorted by
+/// name, without `.` and `..`. `type` is `file`, `dir`, `symlink` or `other`; `mode` holds
+/// the permission bits and `mtime` is in epoch seconds. Uses the sync protocol, falling back
+/// to `ls -la` where the sync service sees nothing (minute-precision mtimes then).
+#[no_mangle]
+pub extern "C" fn lb_fs_list(serial_ptr: *const c_char, path_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(serial_ptr, "serial")
+            .and_then(|serial| read_c_str(path_ptr, "path").and_then(|path| list(serial, path)));
+        string_result(result, "directory listing")
+    })
+}
+
+/// One entry in the `lb_fs_list` shape for `path` itself; NotFound when it does not exist.
+#[no_mangle]
+pub extern "C" fn lb_fs_stat(serial_ptr: *const c_char, path_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(serial_ptr, "serial")
+            .and_then(|serial| read_c_str(path_ptr, "path").and_then(|path| stat(serial, path)));
+        string_result(result, "file status")
+    })
+}
+
+/// `mkdir -p` on the device.
+#[no_mangle]
+pub extern "C" fn lb_fs_mkdir(serial_ptr: *const c_char, path_ptr: *const c_char) -> i32 {
+    ffi_guard(0, || {
+        let result = read_c_str(serial_ptr, "serial")
+            .and_then(|serial| read_c_str(path_ptr, "path").and_then(|path| mkdir(serial, path)));
+        status_result(result)
+    })
+}
+
+/// Removes a file, or a directory tree when `recursive` is non-zero; missing paths fail.
+#[no_mangle]
+pub extern "C" fn lb_fs_rm(serial_ptr: *const c_char, path_ptr: *const c_char, recursive: i32) -> i32 {
+    ffi_guard(0, || {
+        let result = read_c_str(serial_ptr, "serial")
+            .and_then(|serial| read_c_str(path_ptr, "path").and_then(|path| remove(serial, path, recursive != 0)));
+        status_result(result)
+    })
+}
+
+/// Moves or renames `from` to `to` on the device.
+#[no_mangle]
+pub extern "C" fn lb_fs_mv(serial_ptr: *const c_char, from_ptr: *const c_char, to_ptr: *const c_char) -> i32 {
+    ffi_guard(0, || {
+        let result = read_c_str(serial_ptr, "serial").and_then(|serial| {
+            read_c_str(from_ptr, "source path")
+                .and_then(|from| read_c_str(to_ptr, "destination path").and_then(|to| rename(serial, from, to)))
+        });
+        status_result(result)
+    })
+}
@@ -0,0 +1,129 @@
+use std::os::raw::c_char;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use crate::cancel;
+use crate::error::LbError;
+use crate::exec::CommandOutput;
+use crate::json::{self, JsonValue};
+use crate::logging::{lb_log, Level};
+use crate::{ffi_guard, read_c_str, status_result};
+
+const DEFAULT_BACKOFF_MS: u64 = 500;
+/// Transient adb states that usually clear within a second or two.
+const DEFAULT_RETRY_ON: [&str; 5] = [
+    "device offline",
+    "device still authorizing",
+    "device still connecting",
+    "protocol fault",
+    "connection reset",
+];
+
+/// How often a failed command is re-run. Only failures whose output matches `retry_on`
+/// (case-insensitive substrings) are retried; the wait doubles after each attempt.
+#[derive(Debug, Clone)]
+pub struct RetrySpec {
+    max_attempts: u32,
+    backoff_ms: u64,
+    retry_on: Vec<String>,
+}
+
+impl RetrySpec {
+    /// `{"max_attempts": 3, "backoff_ms": 500, "retry_on": ["device offline", ...]}`.
+    pub fn parse(value: &JsonValue) -> Result<RetrySpec, LbError> {
+        if value.as_object().is_none() {
+            return Err("Retry spec must be a JSON object".into());
+        }
+        let max_attempts = match value.get("max_attempts") {
+            None => 3,
+            Some(attempts) => attempts
+                .as_u64()
+                .filter(|attempts| (1..=u64::from(u32::MAX)).contains(attempts))
+                .ok_or("Retry field 'max_attempts' must be a positive integer")? as u32,
+        };
+        let backoff_ms = match value.get("backoff_ms") {
+            None => DEFAULT_BACKOFF_MS,
+            Some(backoff) => backoff
+                .as_u64()
+                .ok_or("Retry field 'backoff_ms' must be a non-negative integer")?,
+        };
+        let retry_on = match value.get("retry_on") {
+            None => DEFAULT_RETRY_ON.iter().map(|pattern| pattern.to_string()).collect(),
+            Some(patterns) => patterns
+                .as_string_array()
+                .ok_or("Retry field 'retry_on' must be an array of strings")?,
+        };
+        Ok(RetrySpec {
+            max_attempts,
+            backoff_ms,
+            retry_on: retry_on.into_iter().map(|pattern| pattern.to_ascii_lowercase()).collect(),
+        })
+    }
+
+    fn should_retry(&self, result: &Result<CommandOutput, LbError>) -> bool {
+        let detail = match result {
+            Ok(output) if output.success() || output.truncated => return false,
+            Ok(output) => format!("{}\n{}", output.stderr, output.stdout),
+            Err(err) => err.message.clone(),
+        }
+        .to_ascii_lowercase();
+        self.retry_on.iter().any(|pattern| detail.contains(pattern.as_str()))
+    }
+}
+
+static DEFAULT_POLICY: OnceLock<Mutex<Option<RetrySpec>>> = OnceLock::new();
+
+fn default_policy_slot() -> &'static Mutex<Option<RetrySpec>> {
+    DEFAULT_POLICY.get_or_init(|| Mutex::new(None))
+}
+
+/// The policy set by `lb_set_retry_policy`, used for commands without their own spec.
+pub fn default_policy() -> Option<RetrySpec> {
+    default_policy_slot().lock().ok().and_then(|guard| guard.clone())
+}
+
+/// Runs `attempt` until it succeeds, fails in a way `spec` does not retry, or runs out of
+/// attempts. `CommandOutput::attempts` reports how many runs were made.
+pub fn run_with_retry(
+    spec: Option<&RetrySpec>,
+    mut attempt: impl FnMut() -> Result<CommandOutput, LbError>,
+) -> Result<CommandOutput, LbError> {
+    let mut result = attempt();
+    let Some(spec) = spec else {
+        return result;
+    };
+    let mut attempts = 1;
+    let mut backoff = spec.backoff_ms;
+    while attempts < spec.max_attempts && spec.should_retry(&result) {
+        lb_log!(Level::Info, "exec", "Transient failure; retrying in {}ms (attempt {} of {})", backoff, attempts + 1, spec.max_attempts);
+        cancel::sleep(Duration::from_millis(backoff))?;
+        backoff = backoff.saturating_mul(2);
+        attempts += 1;
+        result = attempt();
+    }
+    result.map(|mut output| {
+        output.attempts = attempts;
+        output
+    })
+}
+
+fn set_retry_policy(policy_json: &str) -> Result<(), LbError> {
+    let value = json::parse(policy_json)?;
+    let policy = if value.get("enabled").and_then(JsonValue::as_bool) == Some(false) {
+        None
+    } else {
+        Some(RetrySpec::parse(&value)?)
+    };
+    *default_policy_slot()
+        .lock()
+        .map_err(|_| LbError::internal("Retry policy poisoned"))? = policy;
+    Ok(())
+}
+
+/// Sets the retry policy for every command run by the parallel runners:
+/// `{"max_attempts", "backoff_ms", "retry_on"}`, or `{"enabled": false}` to stop retrying
+/// (the default). Structured commands can override it with their own `retry` object.
+#[no_mangle]
+pub extern "C" fn lb_set_retry_policy(policy_ptr: *const c_char) -> i32 {
+    ffi_guard(0, || status_result(read_c_str(policy_ptr, "retry policy").and_then(set_retry_policy)))
+}
@@ -0,0 +1,127 @@
+use std::os::raw::c_char;
+use std::time::{Duration, Instant};
+
+use crate::adb;
+use crate::cancel;
+use crate::device_lock::ensure_device_unlocked;
+use crate::error::{ErrorCode, LbError};
+use crate::json::JsonValue;
+use crate::logging::{lb_log, Level};
+use crate::reboot;
+use crate::{ffi_guard, read_c_str, status_result, string_result};
+
+/// adbd restarts in well under a second on most devices; slow emulators need a few.
+const RESTART_TIMEOUT: Duration = Duration::from_secs(20);
+const POLL_INTERVAL: Duration = Duration::from_millis(300);
+
+pub fn adbd_uid(serial: &str) -> Option<String> {
+    adb::shell(serial, "id -u").ok().map(|uid| uid.trim().to_string())
+}
+
+/// Runs `adb root` / `adb unroot`, then waits until adbd is back and `id -u` reports the
+/// wanted uid. The old daemon keeps answering for a moment after the command returns,
+/// and talking to it then is what fails with "device offline".
+fn switch_adbd(serial: &str, root: bool) -> Result<(), LbError> {
+    ensure_device_unlocked(serial)?;
+    let wanted = |uid: &str| if root { uid == "0" } else { uid != "0" };
+    if adbd_uid(serial).as_deref().is_some_and(wanted) {
+        return Ok(());
+    }
+    let command = if root { "root" } else { "unroot" };
+    let output = adb::run_adb(Some(serial), &[command])?;
+    let detail = format!("{}{}", output.stdout, output.stderr);
+    if detail.contains("production builds") {
+        return Err(LbError::new(
+            ErrorCode::PermissionDenied,
+            format!("{} is a production build; adbd cannot run as root", serial),
+        )
+        .with_serial(serial));
+    }
+    if !output.success() {
+        return Err(LbError::new(adb::classify_failure(&detail), format!("adb {} failed: {}", command, detail.trim()))
+            .with_serial(serial));
+    }
+    lb_log!(Level::Info, "adb", "adb {} on {}: {}", command, serial, detail.trim());
+    let started = Instant::now();
+    loop {
+        let remaining = RESTART_TIMEOUT.saturating_sub(started.elapsed());
+        reboot::wait_for_state(serial, "device", remaining)?;
+        if adbd_uid(serial).as_deref().is_some_and(wanted) {
+            return Ok(());
+        }
+        if started.elapsed() >= RESTART_TIMEOUT {
+            let expected = if root { "as root" } else { "unrooted" };
+            return Err(LbError::new(
+                ErrorCode::Timeout,
+                format!("adbd on {} did not come back {} within {}s", serial, expected, RESTART_TIMEOUT.as_secs()),
+            )
+            .with_serial(serial));
+        }
+        cancel::sleep(POLL_INTERVAL)?;
+    }
+}
+
+/// Reasons `adb remount` gives for not remounting, in the order they are checked.
+const REMOUNT_BLOCKERS: [(&str, &str); 4] = [
+    ("reboot your device", "reboot_required"),
+    ("verity", "verity_enabled"),
+    ("bootloader", "bootloader_locked"),
+    ("Permission denied", "bootloader_locked"),
+];
+
+fn remount(serial: &str) -> Result<String, LbError> {
+    switch_adbd(serial, true)?;
+    let output = adb::run_adb(Some(serial), &["remount"])?;
+    let message = format!("{}\n{}", output.stdout.trim(), output.stderr.trim()).trim().to_string();
+    let lower = message.to_ascii_lowercase();
+    let remounted = output.success() && lower.contains("remount succeeded");
+    let reason = if remounted {
+        None
+    } else {
+        Some(
+            REMOUNT_BLOCKERS
+                .iter()
+                .find(|(fragment, _)| lower.contains(&fragment.to_ascii_lowercase()))
+                .map_or("other", |(_, reason)| *reason),
+        )
+    };
+    // Android 10+ remounts dynamic partitions through overlayfs instead of rw ext4.
+    let overlayfs = lower.contains("overlayfs")
+        || adb::shell(serial, "mount")
+            .map(|mounts| {
+                mounts
+                    .lines()
+                    .any(|line| line.starts_with("overlay on /system") || line.starts_with("overlay on /vendor"))
+            })
+            .unwrap_or(false);
+    lb_log!(Level::Info, "adb", "adb remount on {}: {}", serial, message);
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("remounted", remounted.into()),
+        ("reason", reason.into()),
+        ("overlayfs", overlayfs.into()),
+        ("message", message.into()),
+    ])
+    .to_string())
+}
+
+/// Restarts adbd as root and returns once `id -u` on the device reports 0. Production
+/// builds fail with PermissionDenied; already-root devices return immediately.
+#[no_mangle]
+pub extern "C" fn lb_adb_root(serial_ptr: *const c_char) -> i32 {
+    ffi_guard(0, || status_result(read_c_str(serial_ptr, "serial").and_then(|serial| switch_adbd(serial, true))))
+}
+
+/// Restarts adbd unprivileged and waits until `id -u` is no longer 0.
+#[no_mangle]
+pub extern "C" fn lb_adb_unroot(serial_ptr: *const c_char) -> i32 {
+    ffi_guard(0, || status_result(read_c_str(serial_ptr, "serial").and_then(|serial| switch_adbd(serial, false))))
+}
+
+/// Roots adbd if needed, runs `adb remount`, and returns `{serial, remounted, reason,
+/// overlayfs, message}`. `reason` is `verity_enabled` (run `adb disable-verity` and
+/// reboot), `reboot_required`, `bootloader_locked` or `other` when nothing was remounted.
+#[no_mangle]
+pub extern "C" fn lb_remount(serial_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || string_result(read_c_str(serial_ptr, "serial").and_then(remount), "remount result"))
+}
@@ -0,0 +1,618 @@
+use std::ffi::CString;
+use std::os::raw::c_char;
+use std::time::Duration;
+
+use crate::error::{ErrorCode, LbError};
+use crate::json::{self, JsonValue};
+use crate::logging::{lb_log, Level};
+use crate::{
+    adb_server, avd, backup, battery, batterystats, benchmark, burst, cancel, checksum, clipboard, command_stream, cpu,
+    demo_mode, dev_options, device_config, device_lock, device_meta, dir_transfer, display, doze, dumpsys, emulator,
+    exec, failure_capture, fastboot, fleet, frame_metrics, group_ops, hierarchy, history, ime, input, input_macro,
+    inspection, install, instrumentation, intent, kernel_log, launch, logging, monitor, monkey, multi_capture, netstats,
+    obb, packages, perfetto, permissions, presets, reboot, recording_reconcile, remote_fs, retry, root, scheduler,
+    script, serial_lock, settings, shell_session, sideload, stream, thermal, thumbnail, tombstones, top, transcript,
+    users, version, wireless,
+    ffi_guard, read_c_str, string_result,
+};
+
+/// Version of the request/response envelope itself, reported by `rpc.describe`.
+const PROTOCOL_VERSION: u64 = 1;
+
+/// Schema version every method starts at. A method whose params or result change
+/// incompatibly gets a higher version, so a host pinned to the old one fails loudly.
+const INITIAL_SCHEMA: u64 = 1;
+
+/// One dispatchable export. `params` are `(name, kind)` in the export's argument order;
+/// `kind` is one of the `Args` accessors below.
+struct Method {
+    export: &'static str,
+    version: u64,
+    params: &'static [(&'static str, &'static str)],
+    returns: &'static str,
+    call: fn(&Args) -> Result<JsonValue, LbError>,
+}
+
+impl Method {
+    fn name(&self) -> &'static str {
+        self.export.trim_start_matches("lb_")
+    }
+
+    fn describe(&self) -> JsonValue {
+        let params: Vec<JsonValue> = self
+            .params
+            .iter()
+            .map(|(name, kind)| JsonValue::object(vec![("name", (*name).into()), ("kind", (*kind).into())]))
+            .collect();
+        JsonValue::object(vec![
+            ("method", self.name().into()),
+            ("version", self.version.into()),
+            ("export", self.export.into()),
+            ("params", params.into()),
+            ("returns", self.returns.into()),
+        ])
+    }
+}
+
+/// Converted params, kept alive for the duration of the export call.
+enum Arg {
+    Text(Option<CString>),
+    Integer(i64),
+    Float(f64),
+}
+
+struct Args {
+    values: Vec<(&'static str, Arg)>,
+}
+
+impl Args {
+    fn arg(&self, name: &str) -> &Arg {
+        self.values
+            .iter()
+            .find(|(bound, _)| *bound == name)
+            .map(|(_, arg)| arg)
+            .expect("params are bound from the method's own list")
+    }
+
+    fn text(&self, name: &str) -> *const c_char {
+        match self.arg(name) {
+            Arg::Text(Some(text)) => text.as_ptr(),
+            _ => std::ptr::null(),
+        }
+    }
+
+    fn integer(&self, name: &str) -> i64 {
+        match self.arg(name) {
+            Arg::Integer(value) => *value,
+            _ => 0,
+        }
+    }
+
+    fn str(&self, name: &str) -> *const c_char {
+        self.text(name)
+    }
+
+    fn opt_str(&self, name: &str) -> *const c_char {
+        self.text(name)
+    }
+
+    fn json(&self, name: &str) -> *const c_char {
+        self.text(name)
+    }
+
+    fn opt_json(&self, name: &str) -> *const c_char {
+        self.text(name)
+    }
+
+    fn bool(&self, name: &str) -> i32 {
+        self.integer(name) as i32
+    }
+
+    fn i32(&self, name: &str) -> i32 {
+        self.integer(name) as i32
+    }
+
+    fn u32(&self, name: &str) -> u32 {
+        self.integer(name) as u32
+    }
+
+    fn u64(&self, name: &str) -> u64 {
+        self.integer(name) as u64
+    }
+
+    fn i64(&self, name: &str) -> i64 {
+        self.integer(name)
+    }
+
+    fn f64(&self, name: &str) -> f64 {
+        match self.arg(name) {
+            Arg::Float(value) => *value,
+            _ => 0.0,
+        }
+    }
+}
+
+fn c_string(method: &str, name: &str, text: String) -> Result<CString, LbError> {
+    CString::new(text).map_err(|_| format!("Parameter '{}' of {} contains a NUL byte", name, method).into())
+}
+
+fn integer(method: &str, name: &str, value: &JsonValue, min: f64, max: f64) -> Result<Arg, LbError> {
+    match value.as_f64() {
+        Some(number) if number.fract() == 0.0 && number >= min && number <= max => Ok(Arg::Integer(number as i64)),
+        _ => Err(format!("Parameter '{}' of {} must be an integer from {} to {}", name, method, min, max).into()),
+    }
+}
+
+/// Checks `params` (an object, or null for none) against the method's list and converts
+/// each value for its kind. Unknown names are rejected so a typo never becomes a default.
+fn bind(method: &Method, params: Option<&JsonValue>) -> Result<Args, LbError> {
+    let name = method.name();
+    let given: &[(String, JsonValue)] = match params {
+        None | Some(JsonValue::Null) => &[],
+        Some(JsonValue::Object(given)) => given,
+        Some(_) => return Err(format!("params of {} must be a JSON object", name).into()),
+    };
+    if let Some((unknown, _)) = given
+        .iter()
+        .find(|(given, _)| !method.params.iter().any(|(param, _)| param == given))
+    {
+        let expected: Vec<&str> = method.params.iter().map(|(param, _)| *param).collect();
+        return Err(format!(
+            "Unknown parameter '{}' for {}; expected: {}",
+            unknown,
+            name,
+            if expected.is_empty() { "none".to_string() } else { expected.join(", ") }
+        )
+        .into());
+    }
+    let mut values = Vec::with_capacity(method.params.len());
+    for &(param, kind) in method.params {
+        let value = given
+            .iter()
+            .find(|(given, _)| given == param)
+            .map(|(_, value)| value)
+            .filter(|value| **value != JsonValue::Null);
+        let optional = kind.starts_with("opt_");
+        let Some(value) = value else {
+            if optional {
+                values.push((param, Arg::Text(None)));
+                continue;
+            }
+            return Err(format!("Missing parameter '{}' ({}) for {}", param, kind, name).into());
+        };
+        let arg = match kind {
+            "str" | "opt_str" => {
+                let text = value
+                    .as_str()
+                    .ok_or_else(|| format!("Parameter '{}' of {} must be a string", param, name))?;
+                Arg::Text(Some(c_string(name, param, text.to_string())?))
+            }
+            // Passed to the export as its JSON text, so hosts send objects rather than strings.
+            "json" | "opt_json" => Arg::Text(Some(c_string(name, param, value.to_string())?)),
+            "bool" => Arg::Integer(
+                value
+                    .as_bool()
+                    .ok_or_else(|| format!("Parameter '{}' of {} must be true or false", param, name))?
+                    as i64,
+            ),
+            "i32" => integer(name, param, value, i32::MIN as f64, i32::MAX as f64)?,
+            "u32" => integer(name, param, value, 0.0, u32::MAX as f64)?,
+            // Beyond 2^53 a JSON number is no longer exact, which no handle or duration reaches.
+            "u64" => integer(name, param, value, 0.0, 9_007_199_254_740_991.0)?,
+            "i64" => integer(name, param, value, -9_007_199_254_740_991.0, 9_007_199_254_740_991.0)?,
+            "f64" => Arg::Float(
+                value
+                    .as_f64()
+                    .ok_or_else(|| format!("Parameter '{}' of {} must be a number", param, name))?,
+            ),
+            _ => return Err(LbError::internal(format!("Unknown parameter kind '{}' for {}", kind, name))),
+        };
+        values.push((param, arg));
+    }
+    Ok(Args { values })
+}
+
+/// Converts an export's raw return into the `result` value, reading the thread's last
+/// error when the export signalled failure.
+mod returns {
+    use std::ffi::CString;
+    use std::os::raw::c_char;
+
+    use crate::error::{ErrorCode, LbError};
+    use crate::json::{self, JsonValue};
+    use crate::{last_error, lb_free_result, LbResult};
+
+    fn failure() -> LbError {
+        let err = last_error();
+        if err.code == ErrorCode::Ok {
+            LbError::internal("Export failed without recording an error")
+        } else {
+            err
+        }
+    }
+
+    fn take_string(ptr: *mut c_char) -> Result<String, LbError> {
+        if ptr.is_null() {
+            return Err(failure());
+        }
+        // Allocated by `CString::into_raw` in the export, as `lb_free_string` expects.
+        let owned = unsafe { CString::from_raw(ptr) };
+        Ok(owned.to_string_lossy().into_owned())
+    }
+
+    fn take_bytes(result: LbResult) -> Result<Vec<u8>, LbError> {
+        if result.ptr.is_null() {
+            return Err(failure());
+        }
+        let bytes = unsafe { std::slice::from_raw_parts(result.ptr, result.len) }.to_vec();
+        lb_free_result(result);
+        Ok(bytes)
+    }
+
+    pub fn json(ptr: *mut c_char) -> Result<JsonValue, LbError> {
+        take_string(ptr).and_then(|text| json::parse(&text))
+    }
+
+    pub fn text(ptr: *mut c_char) -> Result<JsonValue, LbError> {
+        take_string(ptr).map(JsonValue::from)
+    }
+
+    pub fn status(status: i32) -> Result<JsonValue, LbError> {
+        if status == 0 {
+            return Err(failure());
+        }
+        Ok(JsonValue::Null)
+    }
+
+    pub fn handle(handle: u64) -> Result<JsonValue, LbError> {
+        if handle == 0 {
+            return Err(failure());
+        }
+        Ok(handle.into())
+    }
+
+    pub fn unit(_: ()) -> Result<JsonValue, LbError> {
+        Ok(JsonValue::Null)
+    }
+
+    pub fn buffer_json(result: LbResult) -> Result<JsonValue, LbError> {
+        take_bytes(result).and_then(|bytes| json::parse(&String::from_utf8_lossy(&bytes)))
+    }
+
+    pub fn buffer_text(result: LbResult) -> Result<JsonValue, LbError> {
+        take_bytes(result).map(|bytes| String::from_utf8_lossy(&bytes).into_owned().into())
+    }
+
+    /// `{png_base64}`, as screenshots are embedded elsewhere in JSON results.
+    pub fn png(result: LbResult) -> Result<JsonValue, LbError> {
+        take_bytes(result).map(|bytes| JsonValue::object(vec![("png_base64", json::base64_encode(&bytes).into())]))
+    }
+}
+
+/// `module::export(name: kind, ...) [fixed trailing args] -> return kind;`. Exports whose callback
+/// is optional get `[None, null_mut()]`: progress is not streamed over `lb_invoke`.
+macro_rules! methods {
+    ($($module:ident::$export:ident($($param:literal: $kind:ident),*) $([$($fixed:expr),*])? -> $ret:ident;)*) => {
+        const METHODS: &[Method] = &[$(
+            Method {
+                export: stringify!($export),
+                version: INITIAL_SCHEMA,
+                params: &[$(($param, stringify!($kind))),*],
+                returns: stringify!($ret),
+                call: |_args| returns::$ret($module::$export($(_args.$kind($param),)* $($($fixed),*)?)),
+            },
+        )*];
+    };
+}
+
+methods! {
+    version::lb_version() -> json;
+    version::lb_capabilities() -> json;
+    adb_server::lb_adb_server_status() -> json;
+    adb_server::lb_adb_start_server() -> json;
+    adb_server::lb_adb_kill_server() -> status;
+    avd::lb_list_avds() -> json;
+    avd::lb_start_avd("name": str, "options": opt_json) -> json;
+    avd::lb_stop_avd("serial": str) -> status;
+    backup::lb_app_backup("serial": str, "packages": json, "local_path": str) [None, std::ptr::null_mut()] -> json;
+    backup::lb_app_restore("serial": str, "path": str) [None, std::ptr::null_mut()] -> json;
+    battery::lb_get_battery_health("serial": str) -> json;
+    batterystats::lb_batterystats_reset("serial": str) -> status;
+    batterystats::lb_batterystats_dump_json("serial": str) -> json;
+    batterystats::lb_battery_set_unplugged("serial": str, "unplugged": bool) -> status;
+    benchmark::lb_enter_benchmark_mode("serial": str) -> json;
+    benchmark::lb_exit_benchmark_mode("serial": str) -> json;
+    burst::lb_capture_burst("serial": str, "fps": u32, "duration_ms": u64, "output_path": str) -> json;
+    checksum::lb_file_checksum("serial": str, "path": str, "algorithm": opt_str) -> json;
+    clipboard::lb_get_clipboard("serial": str) -> json;
+    clipboard::lb_set_clipboard("serial": str, "text": str) -> status;
+    command_stream::lb_command_start("command": str) -> handle;
+    command_stream::lb_command_start_with_stdin("command": str) -> handle;
+    command_stream::lb_command_close_stdin("handle": u64) -> status;
+    command_stream::lb_command_read("handle": u64) -> json;
+    command_stream::lb_command_stop("handle": u64) -> status;
+    cpu::lb_get_cpu_info("serial": str) -> json;
+    cpu::lb_set_cpu_governor("serial": str, "governor": str) -> status;
+    cpu::lb_set_cpu_online("serial": str, "cpu": u32, "online": bool) -> status;
+    cpu::lb_pin_cpu_freq("serial": str, "cpu": i32, "freq_khz": u64) -> status;
+    demo_mode::lb_demo_mode_enable("serial": str, "clock": opt_str, "battery": i32, "network": opt_str) -> json;
+    demo_mode::lb_demo_mode_disable("serial": str) -> status;
+    dev_options::lb_set_animation_scales("serial": str, "scale": f64) -> json;
+    dev_options::lb_get_dev_options("serial": str) -> json;
+    dev_options::lb_set_stay_awake("serial": str, "enabled": bool) -> json;
+    dev_options::lb_set_pointer_location("serial": str, "enabled": bool) -> json;
+    dev_options::lb_set_show_touches("serial": str, "enabled": bool) -> json;
+    dev_options::lb_set_gpu_profiling("serial": str, "enabled": bool) -> json;
+    dev_options::lb_set_strict_mode("serial": str, "enabled": bool) -> json;
+    device_config::lb_set_display("serial": str, "size": opt_str, "density": u32) -> json;
+    device_config::lb_reset_display("serial": str) -> json;
+    device_config::lb_set_dark_mode("serial": str, "enabled": bool) -> json;
+    device_config::lb_reset_dark_mode("serial": str) -> json;
+    device_config::lb_set_locale("serial": str, "tag": str) -> json;
+    device_config::lb_reset_locale("serial": str) -> json;
+    device_lock::lb_lock_device("serial": str, "owner": str) -> status;
+    device_lock::lb_unlock_device("serial": str, "owner": str) -> status;
+    device_lock::lb_device_lock_info("serial": str) -> json;
+    device_lock::lb_ensure_device_unlocked("serial": str) -> status;
+    device_meta::lb_device_meta_set("serial": str, "meta": opt_json) -> json;
+    device_meta::lb_device_meta_get("serial": str) -> json;
+    device_meta::lb_device_meta_list() -> json;
+    device_meta::lb_list_devices_json() -> json;
+    device_meta::lb_set_device_meta_path("path": opt_str) -> status;
+    dir_transfer::lb_pull_dir("serial": str, "remote_dir": str, "local_dir": str, "checksum": bool)
+        [None, std::ptr::null_mut()] -> json;
+    dir_transfer::lb_push_dir("serial": str, "local_dir": str, "remote_dir": str, "checksum": bool)
+        [None, std::ptr::null_mut()] -> json;
+    display::lb_display_info("serial": str) -> json;
+    doze::lb_set_doze("serial": str, "mode": str) -> json;
+    doze::lb_set_standby_bucket("serial": str, "package": str, "bucket": str) -> json;
+    dumpsys::lb_dumpsys("serial": str, "service": str, "args": opt_str) -> json;
+    dumpsys::lb_dumpsys_battery_json("serial": str) -> json;
+    dumpsys::lb_dumpsys_meminfo_json("serial": str, "package": opt_str) -> json;
+    dumpsys::lb_dumpsys_cpuinfo_json("serial": str) -> json;
+    dumpsys::lb_dumpsys_gfxinfo_json("serial": str, "package": str) -> json;
+    dumpsys::lb_dumpsys_activity_top_json("serial": str) -> json;
+    dumpsys::lb_activity_stack("serial": str) -> json;
+    emulator::lb_emulator_console("serial": str, "command": str) -> json;
+    emulator::lb_emulator_geo_fix("serial": str, "latitude": f64, "longitude": f64, "altitude": f64) -> status;
+    emulator::lb_emulator_sms("serial": str, "phone": str, "text": str) -> status;
+    emulator::lb_emulator_call("serial": str, "phone": str, "action": str) -> status;
+    emulator::lb_emulator_battery("serial": str, "level": i32, "charging": bool) -> status;
+    emulator::lb_emulator_network("serial": str, "speed": opt_str, "delay": opt_str) -> status;
+    exec::lb_set_output_limit("max_bytes": u64) -> unit;
+    failure_capture::lb_set_failure_capture("options": json) -> status;
+    failure_capture::lb_capture_failure_bundle("serial": str, "label": str) -> json;
+    fastboot::lb_fastboot_devices() -> json;
+    fastboot::lb_fastboot_getvar("serial": str, "var": str) -> json;
+    fastboot::lb_fastboot_flash("serial": str, "partition": str, "image": str) [None, std::ptr::null_mut()] -> json;
+    fastboot::lb_fastboot_reboot("serial": str, "target": opt_str) -> status;
+    fleet::lb_reboot_fleet("serials": json, "staggered_ms": u64) -> json;
+    fleet::lb_run_on_devices("serials": json, "command_template": str) -> json;
+    frame_metrics::lb_frame_metrics("serial": str, "package": str, "duration_ms": u64) -> json;
+    group_ops::lb_run_on_group("group": str, "command_template": str) -> json;
+    group_ops::lb_install_on_group("group": str, "apk_path": str, "options": opt_json) -> json;
+    group_ops::lb_capture_group("group": str, "what": str) -> json;
+    group_ops::lb_start_group_recording("group": str, "remote_path": str) -> json;
+    group_ops::lb_stop_group_recording("group": str) -> json;
+    hierarchy::lb_render_device_ui_html("xml": str) -> text;
+    hierarchy::lb_render_device_ui_html_with_options("xml": str, "options": opt_json) -> text;
+    hierarchy::lb_render_device_ui_html_paged("xml": str, "options": opt_json, "output_dir": str) -> json;
+    hierarchy::lb_detect_hierarchy_schema("xml": str) -> text;
+    hierarchy::lb_ui_hierarchy_stats("xml": str) -> json;
+    hierarchy::lb_accessibility_audit("xml": str) -> json;
+    hierarchy::lb_accessibility_audit_with_options("xml": str, "options": json) -> json;
+    hierarchy::lb_assert_ui_matches("golden": str, "actual": str, "rules": opt_json) -> json;
+    hierarchy::lb_set_html_theme("theme": opt_str) -> status;
+    hierarchy::lb_render_device_ui_text("xml": str, "format": str) -> text;
+    hierarchy::lb_render_device_ui_text_with_options("xml": str, "options": json) -> text;
+    history::lb_history_query("filter": opt_json) -> json;
+    history::lb_set_history_path("path": opt_str) -> status;
+    history::lb_set_history_enabled("enabled": bool) -> unit;
+    history::lb_history_clear() -> status;
+    ime::lb_list_imes("serial": str) -> json;
+    ime::lb_set_ime("serial": str, "ime_id": str) -> status;
+    ime::lb_reset_ime("serial": str) -> status;
+    input::lb_input_gesture("serial": str, "gesture": json) -> status;
+    input::lb_input_tap("serial": str, "x": i32, "y": i32) -> status;
+    input::lb_input_swipe("serial": str, "x1": i32, "y1": i32, "x2": i32, "y2": i32, "duration_ms": u32) -> status;
+    input::lb_input_text("serial": str, "text": str) -> status;
+    input::lb_input_keyevent("serial": str, "keycodes": str) -> status;
+    input_macro::lb_record_input_start("serial": str) -> handle;
+    input_macro::lb_record_input_stop("handle": u64) -> json;
+    input_macro::lb_replay_input("serial": str, "script": json, "speed": f64) -> status;
+    inspection::lb_capture_inspection_bundle("serial": str) -> json;
+    install::lb_install_apk("serial": str, "path": str, "options": opt_json) [None, std::ptr::null_mut()] -> json;
+    instrumentation::lb_run_instrumentation("serial": str, "runner": str, "args": opt_json)
+        [None, std::ptr::null_mut()] -> json;
+    intent::lb_send_intent("serial": str, "action": opt_str, "data_uri": opt_str, "extras": opt_json, "flags": u32)
+        -> json;
+    kernel_log::lb_get_kernel_log("serial": str, "since_secs": f64) -> text;
+    launch::lb_launch_activity("serial": str, "component": str, "extras": opt_json, "wait": bool) -> json;
+    launch::lb_launch_activity_with_options("serial": str, "component": str, "options": json) -> json;
+    crate::lb_start_screen_record("serial": str, "remote_path": str) -> status;
+    crate::lb_stop_screen_record("serial": str) -> status;
+    crate::lb_list_recordings() -> json;
+    crate::lb_run_commands_parallel("payload": str) -> text;
+    crate::lb_run_argv_parallel("payload": json) -> buffer_text;
+    crate::lb_run_commands_structured("payload": json) -> buffer_json;
+    logging::lb_enable_file_logging("path": opt_str, "max_size": u64, "max_files": u32) -> status;
+    monitor::lb_monitor_stop("handle": u64) -> status;
+    monkey::lb_monkey_start("serial": str, "package": str, "event_count": u32, "throttle_ms": u32, "seed": i64)
+        -> status;
+    monkey::lb_monkey_status("serial": str) -> json;
+    monkey::lb_monkey_stop("serial": str) -> json;
+    multi_capture::lb_capture_all("serials": json, "what": str) -> json;
+    netstats::lb_network_stats("serial": str, "package": str) -> json;
+    obb::lb_push_obb("serial": str, "package": str, "obb_path": str) [None, std::ptr::null_mut()] -> json;
+    packages::lb_list_packages("serial": str, "filter": opt_str) -> json;
+    packages::lb_uninstall("serial": str, "package": str, "keep_data": bool) -> json;
+    packages::lb_uninstall_for_user("serial": str, "package": str, "keep_data": bool, "user_id": i32) -> json;
+    packages::lb_pull_apk("serial": str, "package": str, "local_dir": str) [None, std::ptr::null_mut()] -> json;
+    packages::lb_clear_app_data("serial": str, "package": str) -> json;
+    packages::lb_clear_app_data_for_user("serial": str, "package": str, "user_id": i32) -> json;
+    packages::lb_force_stop("serial": str, "package": str) -> json;
+    perfetto::lb_perfetto_start("serial": str, "config": str) -> status;
+    perfetto::lb_perfetto_stop_and_pull("serial": str, "local_path": str) -> json;
+    permissions::lb_set_permissions("serial": str, "package": str, "grants": json) -> json;
+    presets::lb_set_presets_dir("path": opt_str) -> status;
+    presets::lb_save_preset("kind": str, "name": str, "preset": json) -> status;
+    presets::lb_list_presets("kind": str) -> json;
+    presets::lb_delete_preset("kind": str, "name": str) -> status;
+    reboot::lb_reboot("serial": str, "target": str) -> status;
+    reboot::lb_wait_for_state("serial": str, "state": str, "timeout_ms": u64) -> status;
+    reboot::lb_wait_for_boot("serial": str, "timeout_ms": u64, "wait_launcher": bool) -> json;
+    recording_reconcile::lb_reconcile_recordings("serials": json, "action": opt_str) -> json;
+    remote_fs::lb_fs_list("serial": str, "path": str) -> json;
+    remote_fs::lb_fs_stat("serial": str, "path": str) -> json;
+    remote_fs::lb_fs_mkdir("serial": str, "path": str) -> status;
+    remote_fs::lb_fs_rm("serial": str, "path": str, "recursive": bool) -> status;
+    remote_fs::lb_fs_mv("serial": str, "from": str, "to": str) -> status;
+    retry::lb_set_retry_policy("policy": json) -> status;
+    root::lb_adb_root("serial": str) -> status;
+    root::lb_adb_unroot("serial": str) -> status;
+    root::lb_remount("serial": str) -> json;
+    scheduler::lb_schedule_list() -> json;
+    scheduler::lb_schedule_cancel("id": u64) -> status;
+    script::lb_run_script("script": str) [None, std::ptr::null_mut()] -> json;
+    serial_lock::lb_set_serial_locking("enabled": bool) -> unit;
+    settings::lb_settings_get("serial": str, "namespace": str, "key": str) -> json;
+    settings::lb_settings_set("serial": str, "namespace": str, "key": str, "value": opt_str) -> json;
+    settings::lb_settings_list("serial": str, "namespace": str) -> json;
+    settings::lb_settings_snapshot("serial": str, "namespaces": opt_json) -> json;
+    settings::lb_settings_restore("serial": str, "snapshot": json) -> json;
+    shell_session::lb_shell_open("serial": str) -> handle;
+    shell_session::lb_shell_exec("handle": u64, "command": str) -> json;
+    shell_session::lb_shell_close("handle": u64) -> status;
+    sideload::lb_adb_sideload("serial": str, "zip_path": str) [None, std::ptr::null_mut()] -> json;
+    stream::lb_stop_stream("handle": u64) -> status;
+    thermal::lb_thermal_status("serial": str) -> json;
+    thumbnail::lb_screenshot_thumbnail("serial": str, "max_edge_px": u32) -> png;
+    thumbnail::lb_screenshot_thumbnail_for_user("serial": str, "max_edge_px": u32, "user_id": u32) -> png;
+    tombstones::lb_collect_native_crashes("serial": str, "since_ms": u64, "local_dir": str) -> json;
+    top::lb_top_sample("serial": str, "interval_ms": u32, "count": u32, "package_filter": opt_str) -> json;
+    transcript::lb_transcript_start("session_id": str) -> status;
+    transcript::lb_transcript_stop("session_id": str) -> status;
+    transcript::lb_transcript_discard("session_id": str) -> status;
+    transcript::lb_export_transcript("session_id": str, "path": str) -> status;
+    users::lb_list_users("serial": str) -> json;
+    wireless::lb_adb_pair("host_port": str, "code": str) -> json;
+    wireless::lb_adb_connect("host_port": str) -> json;
+    wireless::lb_adb_disconnect("host_port": str) -> json;
+    wireless::lb_wireless_endpoints() -> json;
+    wireless::lb_forget_wireless_endpoint("host_port": str) -> status;
+}
+
+/// `rpc.describe`: every method, or the one named by `params.method`.
+fn describe(params: Option<&JsonValue>) -> Result<JsonValue, LbError> {
+    let wanted = match params.and_then(|params| params.get("method")) {
+        None | Some(JsonValue::Null) => None,
+        Some(method) => Some(method.as_str().ok_or("rpc.describe 'method' must be a string")?),
+    };
+    let methods: Vec<JsonValue> = METHODS
+        .iter()
+        .filter(|method| wanted.is_none_or(|wanted| method.name() == wanted))
+        .map(Method::describe)
+        .collect();
+    if let Some(wanted) = wanted.filter(|_| methods.is_empty()) {
+        return Err(LbError::not_found(format!("Unknown method '{}'", wanted)));
+    }
+    Ok(JsonValue::object(vec![
+        ("protocol_version", PROTOCOL_VERSION.into()),
+        ("methods", methods.into()),
+    ]))
+}
+
+pub fn dispatch(method_name: &str, version: Option<u64>, params: Option<&JsonValue>) -> Result<JsonValue, LbError> {
+    if method_name == "rpc.describe" {
+        return describe(params);
+    }
+    let method = METHODS
+        .iter()
+        .find(|method| method.name() == method_name)
+        .ok_or_else(|| LbError::not_found(format!("Unknown method '{}'; see rpc.describe", method_name)))?;
+    if let Some(version) = version.filter(|version| *version != method.version) {
+        return Err(format!(
+            "{} implements schema version {}, not {}",
+            method_name, method.version, version
+        )
+        .into());
+    }
+    let args = bind(method, params)?;
+    lb_log!(Level::Debug, "rpc", "Invoking {}", method.export);
+    (method.call)(&args)
+}
+
+/// `{id, version, result}` or `{id, error}`; `id` is echoed as given (null when absent).
+pub fn invoke(request: &str) -> String {
+    let request = json::parse(request);
+    let id = request
+        .as_ref()
+        .ok()
+        .and_then(|request| request.get("id"))
+        .cloned()
+        .unwrap_or(JsonValue::Null);
+    let outcome = request.and_then(|request| {
+        if request.as_object().is_none() {
+            return Err("Request must be a JSON object".into());
+        }
+        let method = request
+            .get("method")
+            .and_then(JsonValue::as_str)
+            .ok_or("Request needs a string 'method'")?;
+        let version = match request.get("version") {
+            None | Some(JsonValue::Null) => None,
+            Some(version) => Some(version.as_u64().ok_or("Request 'version' must be a positive integer")?),
+        };
+        let token = match request.get("cancel_token") {
+            None | Some(JsonValue::Null) => cancel::current(),
+            Some(handle) => cancel::token(handle.as_u64().ok_or("Request 'cancel_token' must be a token handle")?)?,
+        };
+        let token = match request.get("timeout_ms") {
+            None | Some(JsonValue::Null) => token,
+            Some(timeout) => {
+                let timeout = timeout.as_u64().ok_or("Request 'timeout_ms' must be a positive integer")?;
+                token.child_with_timeout(Duration::from_millis(timeout))
+            }
+        };
+        let lock_owner = match request.get("lock_owner") {
+            None | Some(JsonValue::Null) => device_lock::acting_owner(),
+            Some(owner) => Some(owner.as_str().ok_or("Request 'lock_owner' must be a string")?.to_string()),
+        };
+        let result = device_lock::act_as(lock_owner, || {
+            cancel::scope(&token, || dispatch(method, version, request.get("params")))
+        })?;
+        let version = METHODS
+            .iter()
+            .find(|listed| listed.name() == method)
+            .map_or(PROTOCOL_VERSION, |listed| listed.version);
+        Ok((version, result))
+    });
+    let response = match outcome {
+        Ok((version, result)) => JsonValue::object(vec![("id", id), ("version", version.into()), ("result", result)]),
+        Err(err) => {
+            if err.code == ErrorCode::Internal {
+                lb_log!(Level::Error, "rpc", "{}", err.message);
+            }
+            JsonValue::object(vec![("id", id), ("error", err.to_json())])
+        }
+    };
+    response.to_string()
+}
+
+/// Single entry point for every capability: `request_json` is `{"id", "method", "params",
+/// "version"}` where `method` is an export name without `lb_` (`get_battery_health`),
+/// `params` an object of the export's arguments by name (JSON values for JSON arguments,
+/// `true`/`false` for flags), and `id`/`version` optional. `cancel_token` (an
+/// `lb_cancel_token_new` handle) and `timeout_ms` bound the call: when either fires, running
+/// adb children are killed and the error is Cancelled or Timeout. `lock_owner` names the
+/// device-lock owner the call acts for (see `lb_set_lock_owner`). Returns `{id, version, result}`
+/// or `{id, error}` with `error` shaped like `lb_last_error_json`; a method whose export
+/// returns only a status has a null `result`. `{"method": "rpc.describe"}` lists every
+/// method with its params, result kind and schema version. Callbacks are not available:
+/// streams and schedules keep their own exports. Null only when `request_json` is unreadable.
+#[no_mangle]
+pub extern "C" fn lb_invoke(request_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        string_result(read_c_str(request_ptr, "request").map(invoke), "RPC response")
+    })
+}
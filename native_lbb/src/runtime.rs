@@ -0,0 +1,44 @@
+//! The tokio runtime behind the `async-exec` feature. Built on first use and shared by every
+//! command: a blocking export hands its future to the runtime and waits for the result on a
+//! plain channel, which works from host threads and from gRPC's blocking pool alike.
+
+use std::future::Future;
+use std::io;
+use std::sync::{mpsc, OnceLock};
+
+use tokio::runtime::{Builder, Runtime};
+
+const WORKER_THREADS: usize = 2;
+
+static RUNTIME: OnceLock<Result<Runtime, String>> = OnceLock::new();
+
+fn runtime() -> io::Result<&'static Runtime> {
+    RUNTIME
+        .get_or_init(|| {
+            Builder::new_multi_thread()
+                .worker_threads(WORKER_THREADS)
+                .thread_name("lb-exec")
+                .enable_all()
+                .build()
+                .map_err(|err| err.to_string())
+        })
+        .as_ref()
+        .map_err(|err| io::Error::other(format!("Failed to start the exec runtime: {}", err)))
+}
+
+/// Runs `future` on the runtime without waiting for it.
+pub fn spawn(future: impl Future<Output = ()> + Send + 'static) -> io::Result<()> {
+    runtime()?.spawn(future);
+    Ok(())
+}
+
+/// Runs `future` on the runtime and blocks the calling thread until it finishes.
+pub fn block_on<T: Send + 'static>(future: impl Future<Output = io::Result<T>> + Send + 'static) -> io::Result<T> {
+    let (done, result) = mpsc::channel();
+    spawn(async move {
+        let _ = done.send(future.await);
+    })?;
+    result
+        .recv()
+        .map_err(|_| io::Error::other("The exec runtime dropped the command"))?
+}
@@ -0,0 +1,392 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::os::raw::c_char;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+use crate::adb;
+use crate::error::LbError;
+use crate::json::JsonValue;
+use crate::logging::{lb_log, Level};
+use crate::remote_fs::civil_from_days;
+use crate::stream::{LineCallback, LineSink};
+use crate::{ffi_guard, handle_result, now_millis, read_c_str, status_result, string_result};
+
+const MIN_INTERVAL_MS: u64 = 1000;
+/// Long enough for `0 0 29 2 *` across a skipped leap year; `0 0 30 2 *` never runs.
+const CRON_HORIZON_MINUTES: u64 = 8 * 366 * 24 * 60;
+
+/// One cron field as a bitmask of the values it allows.
+#[derive(Clone, Copy)]
+struct CronField {
+    allowed: u64,
+    /// False for `*` (and `*/1`); standard cron ORs day-of-month and day-of-week only
+    /// when both are restricted.
+    restricted: bool,
+}
+
+impl CronField {
+    /// `*`, `5`, `1-5`, `*/15`, `10-40/10` and comma lists of them, within `min..=max`.
+    fn parse(text: &str, name: &str, min: u64, max: u64) -> Result<CronField, LbError> {
+        let invalid = || LbError::from(format!("Invalid cron {} field: {:?}", name, text));
+        let mut allowed = 0u64;
+        for part in text.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => (range, step.parse::<u64>().ok().filter(|step| *step > 0).ok_or_else(invalid)?),
+                None => (part, 1),
+            };
+            let (start, end) = if range == "*" {
+                (min, max)
+            } else if let Some((start, end)) = range.split_once('-') {
+                (start.parse().map_err(|_| invalid())?, end.parse().map_err(|_| invalid())?)
+            } else {
+                let value = range.parse().map_err(|_| invalid())?;
+                // `5/15` means "from 5 to the end, every 15".
+                (value, if part.contains('/') { max } else { value })
+            };
+            if start < min || end > max || start > end {
+                return Err(invalid());
+            }
+            for value in (start..=end).step_by(step as usize) {
+                allowed |= 1 << value;
+            }
+        }
+        let full = (min..=max).fold(0u64, |mask, value| mask | 1 << value);
+        Ok(CronField { allowed, restricted: allowed != full })
+    }
+
+    fn matches(&self, value: u64) -> bool {
+        self.allowed & (1 << value) != 0
+    }
+}
+
+/// `minute hour day-of-month month day-of-week`, evaluated in UTC.
+struct Cron {
+    minute: CronField,
+    hour: CronField,
+    day: CronField,
+    month: CronField,
+    weekday: CronField,
+}
+
+impl Cron {
+    fn parse(expression: &str) -> Result<Cron, LbError> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            let message = format!("Cron expression needs 5 fields (minute hour day month weekday): {:?}", expression);
+            return Err(message.into());
+        };
+        let mut weekday = CronField::parse(weekday, "weekday", 0, 7)?;
+        // 7 is another spelling of Sunday.
+        if weekday.matches(7) {
+            weekday.allowed = (weekday.allowed | 1) & !(1 << 7);
+        }
+        weekday.restricted = weekday.allowed != 0x7f;
+        Ok(Cron {
+            minute: CronField::parse(minute, "minute", 0, 59)?,
+            hour: CronField::parse(hour, "hour", 0, 23)?,
+            day: CronField::parse(day, "day", 1, 31)?,
+            month: CronField::parse(month, "month", 1, 12)?,
+            weekday,
+        })
+    }
+
+    fn matches_day(&self, epoch_days: u64) -> bool {
+        let (_, month, day) = civil_from_days(epoch_days as i64);
+        // 1970-01-01 was a Thursday.
+        let weekday = (epoch_days + 4) % 7;
+        let day_matches = match (self.day.restricted, self.weekday.restricted) {
+            (true, true) => self.day.matches(day as u64) || self.weekday.matches(weekday),
+            _ => self.day.matches(day as u64) && self.weekday.matches(weekday),
+        };
+        self.month.matches(month as u64) && day_matches
+    }
+
+    /// The first matching minute strictly after `after_ms`, in Unix milliseconds.
+    fn next_after(&self, after_ms: u64) -> Option<u64> {
+        const DAY_MINUTES: u64 = 24 * 60;
+        let first = after_ms / 60_000 + 1;
+        let mut minute = first;
+        while minute < first + CRON_HORIZON_MINUTES {
+            if !self.matches_day(minute / DAY_MINUTES) {
+                minute = (minute / DAY_MINUTES + 1) * DAY_MINUTES;
+            } else if self.hour.matches(minute / 60 % 24) && self.minute.matches(minute % 60) {
+                return Some(minute * 60_000);
+            } else {
+                minute += 1;
+            }
+        }
+        None
+    }
+}
+
+enum Schedule {
+    Interval(u64),
+    Cron(Cron),
+}
+
+impl Schedule {
+    /// All digits is an interval in milliseconds; anything else a cron expression.
+    fn parse(text: &str) -> Result<Schedule, LbError> {
+        let text = text.trim();
+        if !text.is_empty() && text.bytes().all(|byte| byte.is_ascii_digit()) {
+            let interval_ms = text.parse::<u64>().map_err(|_| format!("Interval too large: {}", text))?;
+            if interval_ms < MIN_INTERVAL_MS {
+                return Err(format!("Schedule interval must be at least {} ms", MIN_INTERVAL_MS).into());
+            }
+            return Ok(Schedule::Interval(interval_ms));
+        }
+        let cron = Cron::parse(text)?;
+        if cron.next_after(now_millis()).is_none() {
+            return Err(format!("Cron expression {:?} never matches", text).into());
+        }
+        Ok(Schedule::Cron(cron))
+    }
+
+    /// Intervals run at once and then every interval after each run started; cron runs
+    /// at the next matching minute. Runs missed while a slow one was going are skipped.
+    fn next_run(&self, previous_start_ms: Option<u64>, now_ms: u64) -> Option<u64> {
+        match self {
+            Schedule::Interval(interval_ms) => {
+                Some(previous_start_ms.map_or(now_ms, |start| (start + interval_ms).max(now_ms)))
+            }
+            Schedule::Cron(cron) => cron.next_after(now_ms),
+        }
+    }
+}
+
+#[derive(Default)]
+struct ScheduleStatus {
+    runs: u64,
+    failures: u64,
+    next_run_ms: Option<u64>,
+    last_run_ms: Option<u64>,
+    last_exit_code: Option<i32>,
+}
+
+struct ScheduledCommand {
+    serial: String,
+    command: String,
+    schedule: String,
+    created_ms: u64,
+    status: Arc<Mutex<ScheduleStatus>>,
+    /// Held across each callback so that once `lb_schedule_cancel` returns nothing calls
+    /// back into a callback the caller may have freed.
+    cancelled: Arc<Mutex<bool>>,
+    // Dropping it wakes the schedule's thread out of its wait and ends it.
+    _stop: Sender<()>,
+}
+
+static NEXT_SCHEDULE_ID: AtomicU64 = AtomicU64::new(1);
+static SCHEDULES: OnceLock<Mutex<HashMap<u64, ScheduledCommand>>> = OnceLock::new();
+
+thread_local! {
+    /// The schedule whose callback this thread is inside, so cancelling it from its own
+    /// callback does not wait on the lock that thread already holds.
+    static IN_CALLBACK: Cell<u64> = const { Cell::new(0) };
+}
+
+fn schedule_registry() -> &'static Mutex<HashMap<u64, ScheduledCommand>> {
+    SCHEDULES.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Runs `command` in `adb shell` and reports it; a non-zero exit is a failed run, not an
+/// error, so the schedule keeps going.
+fn run_once(id: u64, serial: &str, command: &str, run: u64) -> (JsonValue, Option<i32>) {
+    let started_ms = now_millis();
+    let (exit_code, stdout, stderr, error) = match adb::run_adb(Some(serial), &["shell", command]) {
+        Ok(output) => (output.exit_code, output.stdout, output.stderr, None),
+        Err(err) => (None, String::new(), String::new(), Some(err.message)),
+    };
+    let event = JsonValue::object(vec![
+        ("id", id.into()),
+        ("serial", serial.into()),
+        ("command", command.into()),
+        ("run", run.into()),
+        ("started_ms", started_ms.into()),
+        ("duration_ms", now_millis().saturating_sub(started_ms).into()),
+        ("exit_code", exit_code.into()),
+        ("stdout", stdout.into()),
+        ("stderr", stderr.into()),
+        ("error", error.into()),
+    ]);
+    (event, exit_code)
+}
+
+fn schedule_command(serial: &str, command: &str, schedule_text: &str, sink: LineSink) -> Result<u64, LbError> {
+    if command.trim().is_empty() {
+        return Err("Scheduled command is empty".into());
+    }
+    let schedule = Schedule::parse(schedule_text)?;
+    let (stop, stopped) = mpsc::channel::<()>();
+    let id = NEXT_SCHEDULE_ID.fetch_add(1, Ordering::Relaxed);
+    let cancelled = Arc::new(Mutex::new(false));
+    let status = Arc::new(Mutex::new(ScheduleStatus {
+        next_run_ms: schedule.next_run(None, now_millis()),
+        ..ScheduleStatus::default()
+    }));
+    schedule_registry()
+        .lock()
+        .map_err(|_| LbError::internal("Schedule registry poisoned"))?
+        .insert(
+            id,
+            ScheduledCommand {
+                serial: serial.to_string(),
+                command: command.to_string(),
+                schedule: schedule_text.trim().to_string(),
+                created_ms: now_millis(),
+                status: Arc::clone(&status),
+                cancelled: Arc::clone(&cancelled),
+                _stop: stop,
+            },
+        );
+
+    let (serial, command) = (serial.to_string(), command.to_string());
+    lb_log!(Level::Info, "stream", "Schedule {} on {}: `{}` at {}", id, serial, command, schedule_text.trim());
+    thread::spawn(move || {
+        let mut run = 0u64;
+        loop {
+            let next = status.lock().ok().and_then(|status| status.next_run_ms);
+            let Some(next) = next else {
+                break;
+            };
+            match stopped.recv_timeout(Duration::from_millis(next.saturating_sub(now_millis()))) {
+                Err(RecvTimeoutError::Timeout) => {}
+                _ => break,
+            }
+            run += 1;
+            let started = Some(now_millis());
+            let (event, exit_code) = run_once(id, &serial, &command, run);
+            let Ok(cancelled) = cancelled.lock() else {
+                break;
+            };
+            if *cancelled {
+                break;
+            }
+            if let Ok(mut status) = status.lock() {
+                status.runs += 1;
+                status.failures += u64::from(exit_code != Some(0));
+                status.last_run_ms = started;
+                status.last_exit_code = exit_code;
+                status.next_run_ms = schedule.next_run(started, now_millis());
+            }
+            if exit_code != Some(0) {
+                let code = exit_code.map_or("none".to_string(), |code| code.to_string());
+                lb_log!(Level::Warn, "stream", "Schedule {} run {} on {} failed (exit {})", id, run, serial, code);
+            }
+            IN_CALLBACK.with(|current| current.set(id));
+            sink.emit(&event.to_string());
+            IN_CALLBACK.with(|current| current.set(0));
+        }
+        lb_log!(Level::Debug, "stream", "Schedule {} ended", id);
+    });
+    Ok(id)
+}
+
+fn list_schedules() -> Result<String, LbError> {
+    let registry = schedule_registry()
+        .lock()
+        .map_err(|_| LbError::internal("Schedule registry poisoned"))?;
+    let mut ids: Vec<&u64> = registry.keys().collect();
+    ids.sort();
+    let schedules: Vec<JsonValue> = ids
+        .into_iter()
+        .map(|id| {
+            let entry = &registry[id];
+            let status = entry.status.lock();
+            let status = status.as_deref();
+            JsonValue::object(vec![
+                ("id", (*id).into()),
+                ("serial", entry.serial.as_str().into()),
+                ("command", entry.command.as_str().into()),
+                ("schedule", entry.schedule.as_str().into()),
+                ("created_ms", entry.created_ms.into()),
+                ("runs", status.map_or(0, |status| status.runs).into()),
+                ("failures", status.map_or(0, |status| status.failures).into()),
+                ("next_run_ms", status.ok().and_then(|status| status.next_run_ms).into()),
+                ("last_run_ms", status.ok().and_then(|status| status.last_run_ms).into()),
+                ("last_exit_code", status.ok().and_then(|status| status.last_exit_code).into()),
+            ])
+        })
+        .collect();
+    Ok(JsonValue::from(schedules).to_string())
+}
+
+fn cancel_schedule(id: u64) -> Result<(), LbError> {
+    let entry = schedule_registry()
+        .lock()
+        .map_err(|_| LbError::internal("Schedule registry poisoned"))?
+        .remove(&id)
+        .ok_or_else(|| LbError::not_found(format!("No schedule with id {}", id)))?;
+    // Dropping the entry's sender already ends a waiting thread; this covers one mid-run.
+    if IN_CALLBACK.with(|current| current.get()) != id {
+        if let Ok(mut cancelled) = entry.cancelled.lock() {
+            *cancelled = true;
+        }
+    }
+    lb_log!(Level::Info, "stream", "Schedule {} cancelled", id);
+    Ok(())
+}
+
+/// Cancels every schedule and returns how many there were.
+pub fn cancel_all() -> usize {
+    let entries: Vec<(u64, ScheduledCommand)> = match schedule_registry().lock() {
+        Ok(mut guard) => guard.drain().collect(),
+        Err(_) => return 0,
+    };
+    let in_callback = IN_CALLBACK.with(|current| current.get());
+    for (id, entry) in &entries {
+        if *id != in_callback {
+            if let Ok(mut cancelled) = entry.cancelled.lock() {
+                *cancelled = true;
+            }
+        }
+    }
+    if !entries.is_empty() {
+        lb_log!(Level::Info, "stream", "Cancelled {} schedules", entries.len());
+    }
+    entries.len()
+}
+
+/// Runs `adb -s serial shell command` on a background timer thread. `schedule` is either
+/// an interval in milliseconds (at least 1000; the first run is immediate) or a 5-field
+/// cron expression (`*/5 * * * *`; `*`, lists, ranges and steps; evaluated in UTC).
+/// Each run calls `callback(user_data, json)` with `{id, serial, command, run,
+/// started_ms, duration_ms, exit_code, stdout, stderr, error}`; failures do not stop the
+/// schedule and a run never overlaps the previous one. Returns an id for
+/// `lb_schedule_cancel`, or 0.
+#[no_mangle]
+pub extern "C" fn lb_schedule_command(
+    serial_ptr: *const c_char,
+    command_ptr: *const c_char,
+    schedule_ptr: *const c_char,
+    callback: Option<LineCallback>,
+    user_data: *mut c_void,
+) -> u64 {
+    ffi_guard(0, || {
+        let result = LineSink::new(callback, user_data).and_then(|sink| {
+            let serial = read_c_str(serial_ptr, "serial")?;
+            let command = read_c_str(command_ptr, "command")?;
+            let schedule = read_c_str(schedule_ptr, "schedule")?;
+            schedule_command(serial, command, schedule, sink)
+        });
+        handle_result(result)
+    })
+}
+
+/// Active schedules: `[{id, serial, command, schedule, created_ms, runs, failures,
+/// next_run_ms, last_run_ms, last_exit_code}]` ordered by id.
+#[no_mangle]
+pub extern "C" fn lb_schedule_list() -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || string_result(list_schedules(), "schedule list"))
+}
+
+/// Cancels a schedule; no callback is made after this returns except one already in flight.
+#[no_mangle]
+pub extern "C" fn lb_schedule_cancel(id: u64) -> i32 {
+    ffi_guard(0, || status_result(cancel_schedule(id)))
+}
@@ -0,0 +1,535 @@
+use std::ffi::c_void;
+use std::fs;
+use std::os::raw::c_char;
+use std::time::{Duration, Instant};
+
+use crate::adb;
+use crate::cancel;
+use crate::error::{ErrorCode, LbError};
+use crate::hierarchy::{self, UiNode};
+use crate::input;
+use crate::install;
+use crate::json::{self, JsonValue};
+use crate::launch;
+use crate::logging::{lb_log, Level};
+use crate::stream::{LineCallback, LineSink};
+use crate::yaml;
+use crate::{ffi_guard, read_c_str, string_result};
+
+const ACTIONS: [&str; 9] = [
+    "wait-for-device",
+    "install",
+    "launch",
+    "input",
+    "shell",
+    "assert-ui",
+    "screenshot",
+    "record",
+    "sleep",
+];
+/// Keys every step may carry; everything else belongs to the action.
+const STEP_KEYS: [&str; 4] = ["action", "id", "if", "continue_on_failure"];
+const DEFAULT_BOOT_TIMEOUT_MS: u64 = 120_000;
+const ASSERT_POLL_INTERVAL_MS: u64 = 500;
+
+/// JSON when the source starts with `{`, the YAML subset in `yaml.rs` otherwise.
+fn parse_source(source: &str) -> Result<JsonValue, LbError> {
+    if source.trim_start().starts_with('{') {
+        json::parse(source)
+    } else {
+        yaml::parse(source)
+    }
+}
+
+/// When a step runs: `{"step": id}` plus one of `exit_code`, `exit_code_not` or `success`.
+enum Condition {
+    ExitCode(String, i64),
+    ExitCodeNot(String, i64),
+    Success(String, bool),
+}
+
+struct Step {
+    action: String,
+    id: Option<String>,
+    condition: Option<Condition>,
+    continue_on_failure: bool,
+    /// The step object as written; `{name}` placeholders are expanded just before it runs.
+    fields: JsonValue,
+}
+
+fn parse_condition(value: &JsonValue, known_ids: &[String]) -> Result<Condition, LbError> {
+    let step = value
+        .get("step")
+        .and_then(JsonValue::as_str)
+        .ok_or("Step 'if' needs a string 'step' naming an earlier step id")?;
+    if !known_ids.iter().any(|id| id == step) {
+        return Err(format!("Step 'if' refers to '{}', which is not an earlier step id", step).into());
+    }
+    let integer = |key: &str| {
+        value
+            .get(key)
+            .and_then(JsonValue::as_f64)
+            .filter(|number| number.fract() == 0.0)
+            .map(|number| number as i64)
+            .ok_or_else(|| LbError::from(format!("Step 'if.{}' must be an integer", key)))
+    };
+    let step = step.to_string();
+    if value.get("exit_code").is_some() {
+        Ok(Condition::ExitCode(step, integer("exit_code")?))
+    } else if value.get("exit_code_not").is_some() {
+        Ok(Condition::ExitCodeNot(step, integer("exit_code_not")?))
+    } else if let Some(success) = value.get("success") {
+        Ok(Condition::Success(step, success.as_bool().ok_or("Step 'if.success' must be a boolean")?))
+    } else {
+        Err("Step 'if' needs 'exit_code', 'exit_code_not' or 'success'".into())
+    }
+}
+
+fn parse_steps(steps: &[JsonValue]) -> Result<Vec<Step>, LbError> {
+    let mut ids: Vec<String> = Vec::new();
+    let mut parsed = Vec::with_capacity(steps.len());
+    for (index, step) in steps.iter().enumerate() {
+        let context = format!("Step {}", index);
+        let action = step
+            .get("action")
+            .and_then(JsonValue::as_str)
+            .ok_or_else(|| LbError::from(format!("{} needs a string 'action'", context)))?;
+        if !ACTIONS.contains(&action) {
+            return Err(format!("{}: unknown action '{}' (expected one of {})", context, action, ACTIONS.join(", ")).into());
+        }
+        let condition = match step.get("if") {
+            None | Some(JsonValue::Null) => None,
+            Some(condition) => Some(parse_condition(condition, &ids).map_err(|err| err.context(&context))?),
+        };
+        let id = match step.get("id") {
+            None | Some(JsonValue::Null) => None,
+            Some(JsonValue::String(id)) if !id.is_empty() && !ids.contains(id) => Some(id.clone()),
+            Some(_) => return Err(format!("{}: 'id' must be a unique non-empty string", context).into()),
+        };
+        if let Some(id) = &id {
+            ids.push(id.clone());
+        }
+        let continue_on_failure = match step.get("continue_on_failure") {
+            None => false,
+            Some(value) => value
+                .as_bool()
+                .ok_or_else(|| LbError::from(format!("{}: 'continue_on_failure' must be a boolean", context)))?,
+        };
+        parsed.push(Step {
+            action: action.to_string(),
+            id,
+            condition,
+            continue_on_failure,
+            fields: step.clone(),
+        });
+    }
+    Ok(parsed)
+}
+
+/// What a finished step exposes to later `{id.exit_code}` / `{id.stdout}` placeholders
+/// and `if` conditions.
+struct Finished {
+    id: String,
+    success: bool,
+    exit_code: Option<i64>,
+    stdout: String,
+}
+
+/// Replaces `{name}` with `serial`, a `vars` entry, or `id.exit_code` / `id.stdout` /
+/// `id.success` of an earlier step. Unknown names stay as written, so shell braces survive.
+fn expand(text: &str, variables: &[(String, String)], finished: &[Finished]) -> String {
+    let lookup = |name: &str| -> Option<String> {
+        if let Some((_, value)) = variables.iter().find(|(key, _)| key == name) {
+            return Some(value.clone());
+        }
+        let (id, field) = name.split_once('.')?;
+        let step = finished.iter().find(|step| step.id == id)?;
+        match field {
+            "exit_code" => step.exit_code.map(|code| code.to_string()),
+            "stdout" => Some(step.stdout.trim_end().to_string()),
+            "success" => Some(step.success.to_string()),
+            _ => None,
+        }
+    };
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(open) = rest.find('{') {
+        output.push_str(&rest[..open]);
+        let after = &rest[open + 1..];
+        match after.find('}').and_then(|close| lookup(&after[..close]).map(|value| (close, value))) {
+            Some((close, value)) => {
+                output.push_str(&value);
+                rest = &after[close + 1..];
+            }
+            None => {
+                output.push('{');
+                rest = after;
+            }
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+fn expand_value(value: &JsonValue, variables: &[(String, String)], finished: &[Finished]) -> JsonValue {
+    match value {
+        JsonValue::String(text) => JsonValue::String(expand(text, variables, finished)),
+        JsonValue::Array(items) => JsonValue::Array(items.iter().map(|item| expand_value(item, variables, finished)).collect()),
+        JsonValue::Object(pairs) => JsonValue::Object(
+            pairs
+                .iter()
+                .map(|(key, item)| (key.clone(), expand_value(item, variables, finished)))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+fn condition_holds(condition: &Condition, finished: &[Finished]) -> bool {
+    let step = |id: &str| finished.iter().find(|step| step.id == id);
+    match condition {
+        Condition::ExitCode(id, code) => step(id).and_then(|step| step.exit_code) == Some(*code),
+        Condition::ExitCodeNot(id, code) => step(id).is_some_and(|step| step.exit_code != Some(*code)),
+        Condition::Success(id, success) => step(id).is_some_and(|step| step.success == *success),
+    }
+}
+
+/// A step's outcome before it is wrapped with index, timing and status.
+struct Outcome {
+    success: bool,
+    exit_code: Option<i64>,
+    stdout: String,
+    output: JsonValue,
+}
+
+impl Outcome {
+    fn ok(output: JsonValue) -> Outcome {
+        Outcome {
+            success: true,
+            exit_code: None,
+            stdout: String::new(),
+            output,
+        }
+    }
+}
+
+fn string_field<'a>(step: &'a JsonValue, key: &str) -> Result<&'a str, LbError> {
+    step.get(key)
+        .and_then(JsonValue::as_str)
+        .ok_or_else(|| format!("Step needs a string '{}'", key).into())
+}
+
+fn millis_field(step: &JsonValue, key: &str, default: u64) -> Result<u64, LbError> {
+    match step.get(key) {
+        None | Some(JsonValue::Null) => Ok(default),
+        Some(value) => value
+            .as_u64()
+            .ok_or_else(|| format!("Step '{}' must be a non-negative integer", key).into()),
+    }
+}
+
+/// The step's own keys (everything but `STEP_KEYS` and `skip`) as an options object.
+fn options_object(step: &JsonValue, skip: &[&str]) -> JsonValue {
+    JsonValue::Object(
+        step.as_object()
+            .into_iter()
+            .flatten()
+            .filter(|(key, _)| !STEP_KEYS.contains(&key.as_str()) && !skip.contains(&key.as_str()))
+            .cloned()
+            .collect(),
+    )
+}
+
+fn integers(value: &JsonValue, key: &str, lengths: &[usize]) -> Result<Vec<i64>, LbError> {
+    let numbers: Option<Vec<i64>> = value.as_array().map(|items| {
+        items
+            .iter()
+            .filter_map(|item| item.as_f64().filter(|number| number.fract() == 0.0).map(|number| number as i64))
+            .collect()
+    });
+    match numbers {
+        Some(numbers) if lengths.contains(&numbers.len()) && value.as_array().map(Vec::len) == Some(numbers.len()) => {
+            Ok(numbers)
+        }
+        _ => Err(format!("Input '{}' must be an array of {:?} integers", key, lengths).into()),
+    }
+}
+
+fn run_input(serial: &str, step: &JsonValue) -> Result<Outcome, LbError> {
+    let command = if let Some(text) = step.get("text") {
+        let text = text.as_str().ok_or("Input 'text' must be a string")?;
+        format!("input text {}", input::input_text_argument(text)?)
+    } else if let Some(keys) = step.get("keyevent") {
+        let keys = keys.as_str().ok_or("Input 'keyevent' must be a string")?;
+        format!("input keyevent {}", input::keyevent_arguments(keys)?)
+    } else if let Some(tap) = step.get("tap") {
+        let point = integers(tap, "tap", &[2])?;
+        format!("input tap {} {}", point[0], point[1])
+    } else if let Some(swipe) = step.get("swipe") {
+        let points = integers(swipe, "swipe", &[4, 5])?;
+        let points: Vec<String> = points.iter().map(i64::to_string).collect();
+        format!("input swipe {}", points.join(" "))
+    } else {
+        return Err("Input step needs 'text', 'keyevent', 'tap' or 'swipe'".into());
+    };
+    input::run_input(serial, &command)?;
+    Ok(Outcome::ok(JsonValue::object(vec![("command", command.into())])))
+}
+
+/// First node (depth first) whose attributes equal every selector given.
+fn find_node<'a>(nodes: &'a [UiNode], selectors: &[(&str, String)]) -> Option<&'a UiNode> {
+    nodes.iter().find_map(|node| {
+        let matches = selectors
+            .iter()
+            .all(|(attribute, expected)| node.attribute(attribute) == Some(expected.as_str()));
+        if matches {
+            Some(node)
+        } else {
+            find_node(&node.children, selectors)
+        }
+    })
+}
+
+/// Polls the UI dump until a node matching `text` / `resource_id` / `content_desc` is
+/// present (or, with `present: false`, absent) or `timeout_ms` runs out.
+fn assert_ui(serial: &str, step: &JsonValue) -> Result<Outcome, LbError> {
+    let mut selectors = Vec::new();
+    for (key, attribute) in [("text", "text"), ("resource_id", "resource-id"), ("content_desc", "content-desc")] {
+        if let Some(value) = step.get(key) {
+            let value = value
+                .as_str()
+                .ok_or_else(|| LbError::from(format!("assert-ui '{}' must be a string", key)))?;
+            selectors.push((attribute, value.to_string()));
+        }
+    }
+    if selectors.is_empty() {
+        return Err("assert-ui needs 'text', 'resource_id' or 'content_desc'".into());
+    }
+    let present = match step.get("present") {
+        None => true,
+        Some(value) => value.as_bool().ok_or("assert-ui 'present' must be a boolean")?,
+    };
+    let timeout = Duration::from_millis(millis_field(step, "timeout_ms", 0)?);
+    let started = Instant::now();
+    loop {
+        let xml = adb::dump_ui_xml(serial)?;
+        let nodes = hierarchy::parse_xml(&String::from_utf8_lossy(&xml))?;
+        let found = find_node(&nodes, &selectors);
+        if found.is_some() == present {
+            let bounds = found.and_then(|node| node.attribute("bounds")).map(str::to_string);
+            return Ok(Outcome::ok(JsonValue::object(vec![
+                ("present", present.into()),
+                ("bounds", bounds.into()),
+                ("waited_ms", (started.elapsed().as_millis() as u64).into()),
+            ])));
+        }
+        if started.elapsed() >= timeout {
+            let wanted: Vec<String> = selectors
+                .iter()
+                .map(|(attribute, value)| format!("{}={:?}", attribute, value))
+                .collect();
+            return Err(LbError::new(
+                ErrorCode::Timeout,
+                format!(
+                    "UI assertion failed: {} {}",
+                    wanted.join(" "),
+                    if present { "not found" } else { "still present" }
+                ),
+            )
+            .with_serial(serial));
+        }
+        cancel::sleep(Duration::from_millis(ASSERT_POLL_INTERVAL_MS))?;
+    }
+}
+
+fn run_action(action: &str, serial: &str, step: &JsonValue) -> Result<Outcome, LbError> {
+    match action {
+        "wait-for-device" => {
+            let timeout = Duration::from_millis(millis_field(step, "timeout_ms", DEFAULT_BOOT_TIMEOUT_MS)?);
+            let waited = adb::wait_for_boot_completed(serial, timeout)?;
+            Ok(Outcome::ok(JsonValue::object(vec![("waited_ms", (waited.as_millis() as u64).into())])))
+        }
+        "install" => {
+            let path = string_field(step, "path")?;
+            let options = options_object(step, &["path"]).to_string();
+            let result = install::install_apk(serial, path, Some(&options), None)?;
+            let success = result.get("success").and_then(JsonValue::as_bool) == Some(true);
+            Ok(Outcome {
+                success,
+                exit_code: None,
+                stdout: String::new(),
+                output: result,
+            })
+        }
+        "launch" => {
+            let component = string_field(step, "component")?;
+            let options = launch::parse_options(&options_object(step, &["component"]).to_string())?;
+            let result = launch::launch_activity(serial, component, options)?;
+            Ok(Outcome::ok(json::parse(&result)?))
+        }
+        "input" => run_input(serial, step),
+        "shell" => {
+            let command = string_field(step, "command")?;
+            let output = adb::run_adb(Some(serial), &["shell", command])?;
+            let exit_code = output.exit_code.map(i64::from);
+            Ok(Outcome {
+                success: exit_code == Some(0),
+                exit_code,
+                output: JsonValue::object(vec![
+                    ("command", command.into()),
+                    ("exit_code", exit_code.map(|code| code as f64).into()),
+                    ("stdout", output.stdout.as_str().into()),
+                    ("stderr", output.stderr.as_str().into()),
+                ]),
+                stdout: output.stdout,
+            })
+        }
+        "assert-ui" => assert_ui(serial, step),
+        "screenshot" => {
+            let path = string_field(step, "path")?;
+            let data = adb::screencap_png(serial)?;
+            fs::write(path, &data).map_err(|err| LbError::io(format!("Failed to write {}: {}", path, err)))?;
+            Ok(Outcome::ok(JsonValue::object(vec![
+                ("path", path.into()),
+                ("bytes", data.len().into()),
+            ])))
+        }
+        "record" => match string_field(step, "mode")? {
+            "start" => {
+                let remote_path = string_field(step, "remote_path")?;
+                crate::start_screen_record(serial, remote_path)?;
+                Ok(Outcome::ok(JsonValue::object(vec![("remote_path", remote_path.into())])))
+            }
+            "stop" => {
+                crate::stop_screen_record(serial)?;
+                Ok(Outcome::ok(JsonValue::Null))
+            }
+            other => Err(format!("Record 'mode' must be \"start\" or \"stop\", got '{}'", other).into()),
+        },
+        "sleep" => {
+            let millis = millis_field(step, "ms", 0)?;
+            cancel::sleep(Duration::from_millis(millis))?;
+            Ok(Outcome::ok(JsonValue::Null))
+        }
+        other => Err(format!("Unknown action '{}'", other).into()),
+    }
+}
+
+/// Runs the steps in order against the script's `serial`, reporting each result to
+/// `sink`. A failed step stops the run unless it sets `continue_on_failure`.
+pub fn run_script(source: &str, sink: Option<LineSink>) -> Result<JsonValue, LbError> {
+    let script = parse_source(source).map_err(|err| err.context("Invalid script"))?;
+    if script.as_object().is_none() {
+        return Err("Script must be an object with 'serial' and 'steps'".into());
+    }
+    let serial = string_field(&script, "serial")
+        .map_err(|_| LbError::from("Script needs a string 'serial'"))?
+        .to_string();
+    let steps = parse_steps(
+        script
+            .get("steps")
+            .and_then(JsonValue::as_array)
+            .ok_or("Script needs a 'steps' array")?,
+    )?;
+    let mut variables = vec![("serial".to_string(), serial.clone())];
+    for (name, value) in script.get("vars").and_then(JsonValue::as_object).into_iter().flatten() {
+        let value = match value {
+            JsonValue::String(text) => text.clone(),
+            other => other.to_string(),
+        };
+        variables.push((name.clone(), value));
+    }
+
+    lb_log!(Level::Info, "script", "Running {} steps on {}", steps.len(), serial);
+    let started = Instant::now();
+    let mut finished: Vec<Finished> = Vec::new();
+    let mut results = Vec::with_capacity(steps.len());
+    let mut stopped_at = None;
+    let mut success = true;
+    for (index, step) in steps.iter().enumerate() {
+        if let Err(err) = cancel::current().check() {
+            lb_log!(Level::Info, "script", "Script on {} stopped before step {}: {}", serial, index, err.message);
+            success = false;
+            stopped_at = Some(index);
+            break;
+        }
+        let step_started = Instant::now();
+        let skipped = step
+            .condition
+            .as_ref()
+            .is_some_and(|condition| !condition_holds(condition, &finished));
+        let (status, outcome, error) = if skipped {
+            ("skipped", None, None)
+        } else {
+            let fields = expand_value(&step.fields, &variables, &finished);
+            match run_action(&step.action, &serial, &fields) {
+                Ok(outcome) if outcome.success => ("ok", Some(outcome), None),
+                Ok(outcome) => ("failed", Some(outcome), None),
+                Err(err) => ("failed", None, Some(err)),
+            }
+        };
+        lb_log!(Level::Debug, "script", "Step {} ({}) on {}: {}", index, step.action, serial, status);
+        let exit_code = outcome.as_ref().and_then(|outcome| outcome.exit_code);
+        let result = JsonValue::object(vec![
+            ("index", index.into()),
+            ("id", step.id.clone().into()),
+            ("action", step.action.as_str().into()),
+            ("status", status.into()),
+            ("exit_code", exit_code.map(|code| code as f64).into()),
+            ("duration_ms", (step_started.elapsed().as_millis() as u64).into()),
+            ("output", outcome.as_ref().map_or(JsonValue::Null, |outcome| outcome.output.clone())),
+            ("error", error.as_ref().map_or(JsonValue::Null, LbError::to_json)),
+        ]);
+        if let Some(sink) = &sink {
+            sink.emit(&result.to_string());
+        }
+        results.push(result);
+        if let (Some(id), false) = (&step.id, skipped) {
+            finished.push(Finished {
+                id: id.clone(),
+                success: status == "ok",
+                exit_code,
+                stdout: outcome.map(|outcome| outcome.stdout).unwrap_or_default(),
+            });
+        }
+        if status == "failed" {
+            success = false;
+            if !step.continue_on_failure {
+                lb_log!(Level::Warn, "script", "Stopping script on {} at step {} ({})", serial, index, step.action);
+                stopped_at = Some(index);
+                break;
+            }
+        }
+    }
+    Ok(JsonValue::object(vec![
+        ("serial", serial.as_str().into()),
+        ("success", success.into()),
+        ("steps", results.into()),
+        ("stopped_at", stopped_at.map(|index| index as u64).into()),
+        ("duration_ms", (started.elapsed().as_millis() as u64).into()),
+    ]))
+}
+
+/// Runs a device script (JSON, or the block YAML subset in `yaml.rs`, where one-line flow
+/// collections take plain items like `tap: [540, 960]` or `extras: {mode: demo}`):
+/// `{serial, vars?, steps: [{action, id?, if?, continue_on_failure?, ...}]}`. Actions:
+/// `wait-for-device {timeout_ms}`, `install {path, replace, downgrade, grant_permissions}`,
+/// `launch {component, extras, wait, force_stop}`, `input {text | keyevent | tap: [x, y] |
+/// swipe: [x1, y1, x2, y2, ms?]}`, `shell {command}`, `assert-ui {text | resource_id |
+/// content_desc, present, timeout_ms}`, `screenshot {path}`, `record {mode: start|stop,
+/// remote_path}`, `sleep {ms}`. Strings may use `{serial}`, `{var}` and `{id.exit_code}` /
+/// `{id.stdout}` / `{id.success}` of earlier steps; `if: {step, exit_code | exit_code_not |
+/// success}` skips a step unless it holds. Returns `{serial, success, steps, stopped_at,
+/// duration_ms}`; `callback(user_data, json)` (may be null) gets each step result as it ends.
+#[no_mangle]
+pub extern "C" fn lb_run_script(
+    script_ptr: *const c_char,
+    callback: Option<LineCallback>,
+    user_data: *mut c_void,
+) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let sink = callback.map(|callback| LineSink::new(Some(callback), user_data)).transpose();
+        let result = sink.and_then(|sink| read_c_str(script_ptr, "script").and_then(|script| run_script(script, sink)));
+        string_result(result.map(|result| result.to_string()), "script result")
+    })
+}
@@ -0,0 +1,136 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::marker::PhantomData;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex, OnceLock};
+
+use crate::ffi_guard;
+use crate::logging::{lb_log, Level};
+
+/// adb global options that take a value, so the value is not mistaken for the subcommand.
+const ADB_VALUE_OPTIONS: [&str; 5] = ["-s", "-t", "-H", "-P", "-L"];
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+static BUSY: OnceLock<(Mutex<HashSet<String>>, Condvar)> = OnceLock::new();
+
+thread_local! {
+    /// Serials this thread holds a guard for; its own commands on them go straight through.
+    static HELD: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
+}
+
+fn busy_serials() -> &'static (Mutex<HashSet<String>>, Condvar) {
+    BUSY.get_or_init(|| (Mutex::new(HashSet::new()), Condvar::new()))
+}
+
+/// Holds one device; dropping it lets the next command for that serial run. Not `Send`:
+/// it is released on the thread that took it.
+pub struct SerialGuard {
+    serial: String,
+    _thread: PhantomData<*const ()>,
+}
+
+impl Drop for SerialGuard {
+    fn drop(&mut self) {
+        HELD.with(|held| held.borrow_mut().remove(&self.serial));
+        let (busy, released) = busy_serials();
+        if let Ok(mut busy) = busy.lock() {
+            busy.remove(&self.serial);
+        }
+        released.notify_all();
+    }
+}
+
+/// The `-s <serial>` of an `adb` or `fastboot` invocation, looking only at global options
+/// before the subcommand (`adb -s X shell ls -s` targets `X`).
+pub fn target_serial(argv: &[String]) -> Option<&str> {
+    let program = Path::new(argv.first()?).file_stem()?.to_str()?;
+    if !program.eq_ignore_ascii_case("adb") && !program.eq_ignore_ascii_case("fastboot") {
+        return None;
+    }
+    let mut args = argv[1..].iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with('-') {
+            return None;
+        }
+        if arg == "-s" {
+            return args.next().map(String::as_str);
+        }
+        if ADB_VALUE_OPTIONS.contains(&arg.as_str()) {
+            args.next();
+        }
+    }
+    None
+}
+
+/// Waits until no other command targets the same device, then claims it. Returns `None`
+/// for non-adb commands, commands without `-s`, or when locking is disabled.
+pub fn acquire_for(argv: &[String]) -> Option<SerialGuard> {
+    acquire(target_serial(argv)?)
+}
+
+/// Claims `serial` for a multi-step operation (an install session, a backup); the commands it
+/// runs on this thread pass through, everyone else waits until the guard drops. `None` when
+/// this thread already holds it or locking is disabled.
+pub fn acquire(serial: &str) -> Option<SerialGuard> {
+    if !ENABLED.load(Ordering::Relaxed) || HELD.with(|held| held.borrow().contains(serial)) {
+        return None;
+    }
+    let (busy, released) = busy_serials();
+    let mut busy = busy.lock().ok()?;
+    if busy.contains(serial) {
+        lb_log!(Level::Debug, "exec", "Waiting for in-flight command on {}", serial);
+    }
+    while busy.contains(serial) {
+        busy = released.wait(busy).ok()?;
+    }
+    busy.insert(serial.to_string());
+    HELD.with(|held| held.borrow_mut().insert(serial.to_string()));
+    Some(SerialGuard {
+        serial: serial.to_string(),
+        _thread: PhantomData,
+    })
+}
+
+/// Turns per-device serialization of adb commands on (the default) or off. While on,
+/// commands with the same `-s <serial>` run one at a time; other devices stay parallel.
+#[no_mangle]
+pub extern "C" fn lb_set_serial_locking(enabled: i32) {
+    ffi_guard((), || ENABLED.store(enabled != 0, Ordering::Relaxed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    fn argv(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn target_serial_reads_global_options_only() {
+        assert_eq!(target_serial(&argv(&["adb", "-s", "X", "shell", "ls", "-s"])), Some("X"));
+        assert_eq!(target_serial(&argv(&["fastboot", "-s", "F", "flash", "boot", "b.img"])), Some("F"));
+        assert_eq!(target_serial(&argv(&["adb", "shell", "-s", "X"])), None);
+        assert_eq!(target_serial(&argv(&["ls", "-s", "X"])), None);
+    }
+
+    #[test]
+    fn holder_passes_through_and_others_wait() {
+        let guard = acquire("lock-test-1").expect("first claim");
+        assert!(acquire_for(&argv(&["adb", "-s", "lock-test-1", "shell", "true"])).is_none());
+        let (claimed, waiter) = mpsc::channel();
+        let other = thread::spawn(move || {
+            let _guard = acquire("lock-test-1");
+            let _ = claimed.send(());
+        });
+        assert!(waiter.recv_timeout(Duration::from_millis(200)).is_err());
+        drop(guard);
+        assert!(waiter.recv_timeout(Duration::from_secs(5)).is_ok());
+        other.join().unwrap();
+        assert!(acquire("lock-test-1").is_some());
+    }
+}
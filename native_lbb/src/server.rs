@@ -0,0 +1,858 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::os::raw::c_char;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::adb;
+use crate::error::{ErrorCode, LbError};
+use crate::json::{self, JsonValue};
+use crate::logging::{self, lb_log, Level};
+use crate::{device_meta, rpc, stream};
+use crate::{ffi_guard, now_millis, read_c_str, start_screen_record, status_result, stop_screen_record, string_result};
+
+const MAX_HEADER_BYTES: u64 = 64 * 1024;
+/// Request bodies and reassembled WebSocket messages; APK paths travel, APKs do not.
+const MAX_BODY_BYTES: usize = 16 * 1024 * 1024;
+/// How long an HTTP client may take to send its request; open WebSockets never time out.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// Open connections per server, counting those still sending their request; the accept
+/// thread answers any more with 503 instead of spawning for them.
+const MAX_CONNECTIONS: usize = 64;
+/// `lb_invoke` calls one WebSocket may have running; another closes it with 1013.
+const MAX_SOCKET_CALLS: usize = 16;
+/// RFC 6455 handshake suffix.
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+/// Close status codes sent when the peer breaks the protocol.
+const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+const CLOSE_UNSUPPORTED_DATA: u16 = 1003;
+const CLOSE_TOO_BIG: u16 = 1009;
+/// "Try Again Later", sent when a client has too many calls in flight.
+const CLOSE_TRY_AGAIN_LATER: u16 = 1013;
+
+struct Server {
+    address: SocketAddr,
+    stopping: Arc<AtomicBool>,
+    /// Open connections by id, so stopping the server can shut them down.
+    connections: Arc<Mutex<HashMap<u64, TcpStream>>>,
+    acceptor: JoinHandle<()>,
+}
+
+static NEXT_SERVER_ID: AtomicU64 = AtomicU64::new(1);
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(1);
+static SERVERS: OnceLock<Mutex<HashMap<u64, Server>>> = OnceLock::new();
+
+fn server_registry() -> &'static Mutex<HashMap<u64, Server>> {
+    SERVERS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// SHA-1 (FIPS 180-4), needed only for `Sec-WebSocket-Accept`.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x6745_2301, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476, 0xc3d2_e1f0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64).wrapping_mul(8).to_be_bytes());
+    for block in message.chunks_exact(64) {
+        let mut words = [0u32; 80];
+        for (word, bytes) in words.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        for index in 16..80 {
+            words[index] = (words[index - 3] ^ words[index - 8] ^ words[index - 14] ^ words[index - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (index, word) in words.iter().enumerate() {
+            let (mix, constant) = match index / 20 {
+                0 => ((b & c) | (!b & d), 0x5a82_7999),
+                1 => (b ^ c ^ d, 0x6ed9_eba1),
+                2 => ((b & c) | (b & d) | (c & d), 0x8f1b_bcdc),
+                _ => (b ^ c ^ d, 0xca62_c1d6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(mix)
+                .wrapping_add(e)
+                .wrapping_add(constant)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (state, value) in state.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+    let mut digest = [0u8; 20];
+    for (bytes, word) in digest.chunks_exact_mut(4).zip(state) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+/// `%XX` escapes and `+` for space, as browsers encode query strings.
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = bytes
+            .get(index + 1..index + 3)
+            .filter(|_| bytes[index] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (escaped, bytes[index]) {
+            (Some(byte), _) => {
+                decoded.push(byte);
+                index += 3;
+                continue;
+            }
+            (None, b'+') => decoded.push(b' '),
+            (None, byte) => decoded.push(byte),
+        }
+        index += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Compares without an early exit, so response timing does not reveal how much of a
+/// guessed token was right.
+pub fn token_matches(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0u8, |difference, (left, right)| difference | (left ^ right))
+            == 0
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: Vec<(String, String)>,
+    /// Names lowercased.
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn query(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn required_query(&self, name: &str) -> Result<&str, LbError> {
+        self.query(name)
+            .filter(|value| !value.is_empty())
+            .ok_or_else(|| format!("Missing query parameter '{}'", name).into())
+    }
+
+    fn json_body(&self) -> Result<JsonValue, LbError> {
+        let text = std::str::from_utf8(&self.body)
+            .map_err(|_| LbError::new(ErrorCode::Utf8, "Request body must be valid UTF-8"))?;
+        json::parse(text)
+    }
+
+    /// `Authorization: Bearer <token>`, or `?token=` for browser WebSockets, which cannot
+    /// set headers.
+    fn authorized(&self, token: &str) -> bool {
+        let given = self
+            .header("authorization")
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .or_else(|| self.query("token"));
+        given.is_some_and(|given| token_matches(given.trim(), token))
+    }
+
+    fn wants_websocket(&self) -> bool {
+        self.header("upgrade")
+            .is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket"))
+    }
+}
+
+/// Reads one request; `None` when the client closed the connection without sending one.
+fn read_request(reader: &mut BufReader<TcpStream>) -> Result<Option<Request>, LbError> {
+    let malformed = |detail: &str| LbError::parse(format!("Malformed HTTP request: {}", detail));
+    let mut lines = Vec::new();
+    let mut head = reader.by_ref().take(MAX_HEADER_BYTES);
+    loop {
+        let mut line = String::new();
+        let read = head
+            .read_line(&mut line)
+            .map_err(|err| LbError::io(format!("Failed to read request: {}", err)))?;
+        if read == 0 {
+            if lines.is_empty() {
+                return Ok(None);
+            }
+            return Err(malformed("headers are truncated or too large"));
+        }
+        let line = line.trim_end_matches(['\r', '\n']).to_string();
+        if line.is_empty() {
+            break;
+        }
+        lines.push(line);
+    }
+    let request_line = lines.first().ok_or_else(|| malformed("empty request"))?;
+    let mut parts = request_line.split(' ');
+    let (Some(method), Some(target), Some(_version)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(malformed(request_line));
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let query = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(key), percent_decode(value))
+        })
+        .collect();
+    let headers: Vec<(String, String)> = lines[1..]
+        .iter()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+        .collect();
+    let length = match headers.iter().find(|(name, _)| name == "content-length") {
+        Some((_, value)) => value.parse::<usize>().map_err(|_| malformed("invalid Content-Length"))?,
+        None => 0,
+    };
+    if length > MAX_BODY_BYTES {
+        return Err(format!("Request body exceeds {} bytes", MAX_BODY_BYTES).into());
+    }
+    let mut body = vec![0u8; length];
+    reader
+        .read_exact(&mut body)
+        .map_err(|err| LbError::io(format!("Failed to read request body: {}", err)))?;
+    Ok(Some(Request {
+        method: method.to_string(),
+        path: percent_decode(path),
+        query,
+        headers,
+        body,
+    }))
+}
+
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn json(status: u16, body: String) -> Response {
+        Response {
+            status,
+            content_type: "application/json",
+            body: body.into_bytes(),
+        }
+    }
+
+    fn error(status: u16, err: &LbError) -> Response {
+        Response::json(status, JsonValue::object(vec![("error", err.to_json())]).to_string())
+    }
+}
+
+fn status_for(code: ErrorCode) -> u16 {
+    match code {
+        ErrorCode::NullPointer | ErrorCode::Utf8 | ErrorCode::InvalidArgument | ErrorCode::ParseError => 400,
+        ErrorCode::PermissionDenied => 403,
+        ErrorCode::NotFound => 404,
+        ErrorCode::DeviceOffline | ErrorCode::DeviceUnauthorized | ErrorCode::DeviceLocked => 409,
+        ErrorCode::Timeout => 504,
+        ErrorCode::Cancelled => 503,
+        _ => 500,
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        409 => "Conflict",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => "Internal Server Error",
+    }
+}
+
+/// One request per connection; CORS is open because every call needs the token anyway.
+fn write_response(stream: &mut TcpStream, response: &Response) -> io::Result<()> {
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\n\
+         Access-Control-Allow-Origin: *\r\nAccess-Control-Allow-Headers: Authorization, Content-Type\r\n\
+         Access-Control-Allow-Methods: GET, POST, OPTIONS\r\nConnection: close\r\n\r\n",
+        response.status,
+        reason(response.status),
+        response.content_type,
+        response.body.len()
+    );
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(&response.body)?;
+    stream.flush()
+}
+
+fn string_field<'a>(body: &'a JsonValue, name: &str) -> Result<&'a str, LbError> {
+    body.get(name)
+        .and_then(JsonValue::as_str)
+        .filter(|value| !value.is_empty())
+        .ok_or_else(|| format!("Request body needs a string '{}'", name).into())
+}
+
+/// `adb shell <command>`; a non-zero exit is reported in the result, not as an error.
+fn run_command(body: &JsonValue) -> Result<Response, LbError> {
+    let serial = string_field(body, "serial")?;
+    let command = string_field(body, "command")?;
+    let output = adb::run_adb(Some(serial), &["shell", command])?;
+    let result = JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("command", command.into()),
+        ("exit_code", output.exit_code.into()),
+        ("stdout", output.stdout.into()),
+        ("stderr", output.stderr.into()),
+        ("duration_ms", (output.duration.as_millis() as u64).into()),
+    ]);
+    Ok(Response::json(200, result.to_string()))
+}
+
+fn screenshot(serial: &str) -> Result<Response, LbError> {
+    Ok(Response {
+        status: 200,
+        content_type: "image/png",
+        body: adb::screencap_png(serial)?,
+    })
+}
+
+fn hierarchy(serial: &str) -> Result<Response, LbError> {
+    Ok(Response {
+        status: 200,
+        content_type: "application/xml",
+        body: adb::dump_ui_xml(serial)?,
+    })
+}
+
+fn recording(body: &JsonValue, start: bool) -> Result<Response, LbError> {
+    let serial = string_field(body, "serial")?;
+    if start {
+        start_screen_record(serial, string_field(body, "remote_path")?)?;
+    } else {
+        stop_screen_record(serial)?;
+    }
+    Ok(Response::json(200, JsonValue::object(vec![("serial", serial.into())]).to_string()))
+}
+
+fn route(request: &Request) -> Result<Response, LbError> {
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/devices") => Ok(Response::json(200, device_meta::list_devices_json()?)),
+        ("POST", "/rpc") => {
+            let text = std::str::from_utf8(&request.body)
+                .map_err(|_| LbError::new(ErrorCode::Utf8, "Request body must be valid UTF-8"))?;
+            Ok(Response::json(200, rpc::invoke(text)))
+        }
+        ("POST", "/run") => run_command(&request.json_body()?),
+        ("GET", "/screenshot") => screenshot(request.required_query("serial")?),
+        ("GET", "/hierarchy") => hierarchy(request.required_query("serial")?),
+        ("POST", "/recording/start") => recording(&request.json_body()?, true),
+        ("POST", "/recording/stop") => recording(&request.json_body()?, false),
+        (method, path) => Err(LbError::not_found(format!("No route for {} {}", method, path))),
+    }
+}
+
+/// Writes frames for one WebSocket; shared by the reader, request threads and event
+/// forwarders, each of which sends whole frames under the lock.
+struct Socket {
+    writer: Mutex<TcpStream>,
+}
+
+impl Socket {
+    fn send(&self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        let mut frame = Vec::with_capacity(payload.len() + 10);
+        frame.push(0x80 | opcode);
+        match payload.len() {
+            length if length < 126 => frame.push(length as u8),
+            length if length <= u16::MAX as usize => {
+                frame.push(126);
+                frame.extend_from_slice(&(length as u16).to_be_bytes());
+            }
+            length => {
+                frame.push(127);
+                frame.extend_from_slice(&(length as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(payload);
+        let mut writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+        writer.write_all(&frame)?;
+        writer.flush()
+    }
+
+    fn send_text(&self, text: &str) -> io::Result<()> {
+        self.send(OPCODE_TEXT, text.as_bytes())
+    }
+
+    fn close(&self, status: u16) {
+        let _ = self.send(OPCODE_CLOSE, &status.to_be_bytes());
+    }
+}
+
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+enum FrameError {
+    /// Connection closed or failed; nothing more can be sent.
+    Io,
+    Close(u16),
+}
+
+fn read_frame(reader: &mut impl Read) -> Result<Frame, FrameError> {
+    let mut head = [0u8; 2];
+    reader.read_exact(&mut head).map_err(|_| FrameError::Io)?;
+    let length = match head[1] & 0x7f {
+        126 => {
+            let mut bytes = [0u8; 2];
+            reader.read_exact(&mut bytes).map_err(|_| FrameError::Io)?;
+            u16::from_be_bytes(bytes) as u64
+        }
+        127 => {
+            let mut bytes = [0u8; 8];
+            reader.read_exact(&mut bytes).map_err(|_| FrameError::Io)?;
+            u64::from_be_bytes(bytes)
+        }
+        length => length as u64,
+    };
+    // Clients must mask every frame (RFC 6455 section 5.1).
+    if head[1] & 0x80 == 0 {
+        return Err(FrameError::Close(CLOSE_PROTOCOL_ERROR));
+    }
+    if length > MAX_BODY_BYTES as u64 {
+        return Err(FrameError::Close(CLOSE_TOO_BIG));
+    }
+    let mut mask = [0u8; 4];
+    reader.read_exact(&mut mask).map_err(|_| FrameError::Io)?;
+    let mut payload = vec![0u8; length as usize];
+    reader.read_exact(&mut payload).map_err(|_| FrameError::Io)?;
+    for (index, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[index % 4];
+    }
+    Ok(Frame {
+        fin: head[0] & 0x80 != 0,
+        opcode: head[0] & 0x0f,
+        payload,
+    })
+}
+
+/// Per-connection state for the WebSocket-only methods.
+struct Session {
+    socket: Arc<Socket>,
+    log_listener: Option<u64>,
+    streams: Vec<u64>,
+    /// `lb_invoke` calls still running on their own threads.
+    calls: Arc<AtomicUsize>,
+}
+
+fn log_level(value: Option<&JsonValue>) -> Result<Level, LbError> {
+    match value.filter(|value| **value != JsonValue::Null).map(JsonValue::as_u64) {
+        None => Ok(Level::Info),
+        Some(Some(1)) => Ok(Level::Error),
+        Some(Some(2)) => Ok(Level::Warn),
+        Some(Some(3)) => Ok(Level::Info),
+        Some(Some(4)) => Ok(Level::Debug),
+        Some(_) => Err("events.subscribe 'level' must be 1 (error) to 4 (debug)".into()),
+    }
+}
+
+impl Session {
+    /// `events.subscribe {level}`: forwards native log lines up to `level` (default 3) as
+    /// `{event: "log", level, target, message, timestamp_ms}`.
+    fn subscribe(&mut self, params: Option<&JsonValue>) -> Result<JsonValue, LbError> {
+        let level = log_level(params.and_then(|params| params.get("level")))?;
+        self.unsubscribe();
+        let (id, events) = logging::add_listener(level)?;
+        self.log_listener = Some(id);
+        let socket = Arc::clone(&self.socket);
+        thread::spawn(move || {
+            for (level, target, message) in events {
+                let event = JsonValue::object(vec![
+                    ("event", "log".into()),
+                    ("level", (level as i32).into()),
+                    ("target", target.into()),
+                    ("message", message.into()),
+                    ("timestamp_ms", now_millis().into()),
+                ]);
+                if socket.send_text(&event.to_string()).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(JsonValue::Null)
+    }
+
+    fn unsubscribe(&mut self) {
+        if let Some(id) = self.log_listener.take() {
+            logging::remove_listener(id);
+        }
+    }
+
+    /// `stream.start {serial, command}`: runs `adb shell command` and sends each output
+    /// line as `{event: "line", stream, serial, line}`. Returns `{stream}`.
+    fn start_stream(&mut self, params: Option<&JsonValue>) -> Result<JsonValue, LbError> {
+        let params = params.ok_or("stream.start needs params {serial, command}")?;
+        let serial = string_field(params, "serial")?.to_string();
+        let command = string_field(params, "command")?;
+        let argv = adb::adb_argv(Some(&serial), &["shell", command]);
+        // Held until the handle is known, so the first line cannot be sent without it.
+        let handle = Arc::new(Mutex::new(0u64));
+        let mut guard = handle.lock().unwrap_or_else(PoisonError::into_inner);
+        let socket = Arc::clone(&self.socket);
+        let line_handle = Arc::clone(&handle);
+        let id = stream::spawn_line_stream(&argv, move |line| {
+            let id = *line_handle.lock().unwrap_or_else(PoisonError::into_inner);
+            let event = JsonValue::object(vec![
+                ("event", "line".into()),
+                ("stream", id.into()),
+                ("serial", serial.as_str().into()),
+                ("line", line.into()),
+            ]);
+            let _ = socket.send_text(&event.to_string());
+        })?;
+        *guard = id;
+        drop(guard);
+        self.streams.push(id);
+        Ok(JsonValue::object(vec![("stream", id.into())]))
+    }
+
+    fn stop_stream(&mut self, params: Option<&JsonValue>) -> Result<JsonValue, LbError> {
+        let id = params
+            .and_then(|params| params.get("stream"))
+            .and_then(JsonValue::as_u64)
+            .ok_or("stream.stop needs params {stream}")?;
+        if !self.streams.contains(&id) {
+            return Err(LbError::not_found(format!("No stream {} on this connection", id)));
+        }
+        self.streams.retain(|stream| *stream != id);
+        stream::stop_stream(id)?;
+        Ok(JsonValue::Null)
+    }
+
+    /// Session methods answer inline; everything else goes through `rpc::invoke` on its
+    /// own thread, so a slow call never holds up pings, events or other requests. Returns
+    /// false once the socket was closed for having `MAX_SOCKET_CALLS` calls in flight.
+    fn handle(&mut self, text: String) -> bool {
+        let request = json::parse(&text).ok();
+        let method = request
+            .as_ref()
+            .and_then(|request| request.get("method"))
+            .and_then(JsonValue::as_str);
+        let params = request.as_ref().and_then(|request| request.get("params"));
+        let outcome = match method {
+            Some("events.subscribe") => self.subscribe(params),
+            Some("events.unsubscribe") => {
+                self.unsubscribe();
+                Ok(JsonValue::Null)
+            }
+            Some("stream.start") => self.start_stream(params),
+            Some("stream.stop") => self.stop_stream(params),
+            _ => {
+                if self.calls.fetch_add(1, Ordering::SeqCst) >= MAX_SOCKET_CALLS {
+                    self.calls.fetch_sub(1, Ordering::SeqCst);
+                    lb_log!(Level::Warn, "server", "Closing WebSocket: {} calls already in flight", MAX_SOCKET_CALLS);
+                    self.socket.close(CLOSE_TRY_AGAIN_LATER);
+                    return false;
+                }
+                let socket = Arc::clone(&self.socket);
+                let calls = Arc::clone(&self.calls);
+                thread::spawn(move || {
+                    let _ = socket.send_text(&rpc::invoke(&text));
+                    calls.fetch_sub(1, Ordering::SeqCst);
+                });
+                return true;
+            }
+        };
+        let id = request
+            .as_ref()
+            .and_then(|request| request.get("id"))
+            .cloned()
+            .unwrap_or(JsonValue::Null);
+        let response = match outcome {
+            Ok(result) => JsonValue::object(vec![("id", id), ("version", 1u64.into()), ("result", result)]),
+            Err(err) => JsonValue::object(vec![("id", id), ("error", err.to_json())]),
+        };
+        let _ = self.socket.send_text(&response.to_string());
+        true
+    }
+
+    fn end(&mut self) {
+        self.unsubscribe();
+        for id in self.streams.drain(..) {
+            // Streams that already exited on their own are gone from the registry.
+            let _ = stream::stop_stream(id);
+        }
+    }
+}
+
+fn serve_websocket(mut stream: TcpStream, reader: &mut BufReader<TcpStream>, request: &Request) -> Result<(), LbError> {
+    let key = request
+        .header("sec-websocket-key")
+        .ok_or("WebSocket upgrade needs Sec-WebSocket-Key")?;
+    let accept = json::base64_encode(&sha1(format!("{}{}", key, WEBSOCKET_GUID).as_bytes()));
+    let handshake = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept
+    );
+    stream
+        .write_all(handshake.as_bytes())
+        .map_err(|err| LbError::io(format!("Failed to complete WebSocket handshake: {}", err)))?;
+    let _ = stream.set_read_timeout(None);
+    let mut session = Session {
+        socket: Arc::new(Socket {
+            writer: Mutex::new(stream),
+        }),
+        log_listener: None,
+        streams: Vec::new(),
+        calls: Arc::new(AtomicUsize::new(0)),
+    };
+    let mut message: Vec<u8> = Vec::new();
+    loop {
+        let frame = match read_frame(reader) {
+            Ok(frame) => frame,
+            Err(FrameError::Io) => break,
+            Err(FrameError::Close(status)) => {
+                session.socket.close(status);
+                break;
+            }
+        };
+        match frame.opcode {
+            OPCODE_CLOSE => {
+                session.socket.close(1000);
+                break;
+            }
+            OPCODE_PING => {
+                let _ = session.socket.send(OPCODE_PONG, &frame.payload);
+            }
+            OPCODE_PONG => {}
+            OPCODE_BINARY => {
+                session.socket.close(CLOSE_UNSUPPORTED_DATA);
+                break;
+            }
+            OPCODE_TEXT | OPCODE_CONTINUATION => {
+                if message.len() + frame.payload.len() > MAX_BODY_BYTES {
+                    session.socket.close(CLOSE_TOO_BIG);
+                    break;
+                }
+                message.extend_from_slice(&frame.payload);
+                if frame.fin {
+                    let text = String::from_utf8_lossy(&std::mem::take(&mut message)).into_owned();
+                    if !session.handle(text) {
+                        break;
+                    }
+                }
+            }
+            _ => {
+                session.socket.close(CLOSE_PROTOCOL_ERROR);
+                break;
+            }
+        }
+    }
+    session.end();
+    Ok(())
+}
+
+/// Reads and authenticates the request first: routes, WebSocket sessions and their call
+/// threads only start for a client that sent the token.
+fn serve_connection(stream: TcpStream, token: &str) -> Result<(), LbError> {
+    let _ = stream.set_read_timeout(Some(REQUEST_TIMEOUT));
+    let clone = || {
+        stream
+            .try_clone()
+            .map_err(|err| LbError::io(format!("Failed to clone connection: {}", err)))
+    };
+    let mut reader = BufReader::new(clone()?);
+    let mut writer = clone()?;
+    let request = match read_request(&mut reader) {
+        Ok(Some(request)) => request,
+        Ok(None) => return Ok(()),
+        Err(err) => {
+            let _ = write_response(&mut writer, &Response::error(status_for(err.code), &err));
+            return Err(err);
+        }
+    };
+    if request.method == "OPTIONS" {
+        let preflight = Response {
+            status: 204,
+            content_type: "text/plain",
+            body: Vec::new(),
+        };
+        let _ = write_response(&mut writer, &preflight);
+        return Ok(());
+    }
+    if !request.authorized(token) {
+        let err = LbError::new(ErrorCode::PermissionDenied, "Missing or invalid auth token");
+        let _ = write_response(&mut writer, &Response::error(401, &err));
+        return Err(err.context(&format!("{} {}", request.method, request.path)));
+    }
+    if request.path == "/ws" && request.wants_websocket() {
+        return serve_websocket(writer, &mut reader, &request);
+    }
+    let response = route(&request).unwrap_or_else(|err| Response::error(status_for(err.code), &err));
+    lb_log!(Level::Debug, "server", "{} {} -> {}", request.method, request.path, response.status);
+    write_response(&mut writer, &response).map_err(|err| LbError::io(format!("Failed to send response: {}", err)))
+}
+
+/// Answered on the accept thread, so a flood of connections never gets a thread each.
+fn reject_busy(mut stream: TcpStream) {
+    let _ = stream.set_write_timeout(Some(Duration::from_secs(1)));
+    let err = LbError::io(format!("Control server is busy ({} open connections); try again later", MAX_CONNECTIONS));
+    let _ = write_response(&mut stream, &Response::error(503, &err));
+}
+
+fn accept_loop(
+    listener: TcpListener,
+    token: Arc<str>,
+    stopping: Arc<AtomicBool>,
+    connections: Arc<Mutex<HashMap<u64, TcpStream>>>,
+) {
+    for incoming in listener.incoming() {
+        if stopping.load(Ordering::SeqCst) {
+            break;
+        }
+        let Ok(stream) = incoming else {
+            continue;
+        };
+        let mut open = connections.lock().unwrap_or_else(PoisonError::into_inner);
+        if open.len() >= MAX_CONNECTIONS {
+            drop(open);
+            lb_log!(Level::Warn, "server", "Refused a connection: {} already open", MAX_CONNECTIONS);
+            reject_busy(stream);
+            continue;
+        }
+        // Registered (and so counted) before its thread starts; one that cannot be tracked
+        // could not be closed by `lb_server_stop` either.
+        let Ok(clone) = stream.try_clone() else {
+            continue;
+        };
+        let id = NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed);
+        open.insert(id, clone);
+        drop(open);
+        let token = Arc::clone(&token);
+        let connections = Arc::clone(&connections);
+        thread::spawn(move || {
+            if let Err(err) = serve_connection(stream, &token) {
+                lb_log!(Level::Debug, "server", "Connection {} failed: {}", id, err.message);
+            }
+            connections
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .remove(&id);
+        });
+    }
+}
+
+fn start_server(bind_addr: &str, auth_token: &str) -> Result<String, LbError> {
+    if auth_token.trim().is_empty() {
+        return Err("auth_token must not be empty".into());
+    }
+    let listener = TcpListener::bind(bind_addr)
+        .map_err(|err| LbError::io(format!("Failed to bind {}: {}", bind_addr, err)))?;
+    let address = listener
+        .local_addr()
+        .map_err(|err| LbError::io(format!("Failed to read bound address: {}", err)))?;
+    let stopping = Arc::new(AtomicBool::new(false));
+    let connections = Arc::new(Mutex::new(HashMap::new()));
+    let acceptor = {
+        let token: Arc<str> = Arc::from(auth_token.trim());
+        let stopping = Arc::clone(&stopping);
+        let connections = Arc::clone(&connections);
+        thread::spawn(move || accept_loop(listener, token, stopping, connections))
+    };
+    let id = NEXT_SERVER_ID.fetch_add(1, Ordering::Relaxed);
+    server_registry()
+        .lock()
+        .map_err(|_| LbError::internal("Server registry poisoned"))?
+        .insert(
+            id,
+            Server {
+                address,
+                stopping,
+                connections,
+                acceptor,
+            },
+        );
+    lb_log!(Level::Info, "server", "Control server {} listening on {}", id, address);
+    Ok(JsonValue::object(vec![("handle", id.into()), ("address", address.to_string().into())]).to_string())
+}
+
+fn stop_server(id: u64) -> Result<(), LbError> {
+    let server = server_registry()
+        .lock()
+        .map_err(|_| LbError::internal("Server registry poisoned"))?
+        .remove(&id)
+        .ok_or_else(|| LbError::not_found(format!("No control server with handle {}", id)))?;
+    server.stopping.store(true, Ordering::SeqCst);
+    // `accept` has no timeout; a throwaway connection wakes it to see the flag.
+    let mut wake = server.address;
+    if wake.ip().is_unspecified() {
+        wake.set_ip(match wake {
+            SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+            SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+        });
+    }
+    let _ = TcpStream::connect_timeout(&wake, Duration::from_secs(1));
+    let _ = server.acceptor.join();
+    for (_, connection) in server.connections.lock().unwrap_or_else(PoisonError::into_inner).drain() {
+        let _ = connection.shutdown(Shutdown::Both);
+    }
+    lb_log!(Level::Info, "server", "Control server {} stopped", id);
+    Ok(())
+}
+
+/// Serves the library over HTTP and WebSocket on `bind_addr` (`127.0.0.1:8765`; port 0
+/// picks one). Every request needs `auth_token`, as `Authorization: Bearer <token>` or
+/// `?token=`. HTTP: `GET /devices`, `POST /rpc` (an `lb_invoke` request), `POST /run
+/// {serial, command}`, `GET /screenshot?serial=` (PNG), `GET /hierarchy?serial=` (XML),
+/// `POST /recording/start {serial, remote_path}` and `POST /recording/stop {serial}`.
+/// `GET /ws` upgrades to a WebSocket taking `lb_invoke` requests as text messages plus
+/// `events.subscribe`, `events.unsubscribe`, `stream.start` and `stream.stop`. Returns
+/// `{handle, address}` or null.
+#[no_mangle]
+pub extern "C" fn lb_server_start(bind_addr_ptr: *const c_char, auth_token_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(bind_addr_ptr, "bind address")
+            .and_then(|bind_addr| read_c_str(auth_token_ptr, "auth token").map(|token| (bind_addr, token)))
+            .and_then(|(bind_addr, token)| start_server(bind_addr, token));
+        string_result(result, "server info")
+    })
+}
+
+/// Stops accepting, closes every open connection (ending its streams and event
+/// subscriptions) and frees the handle.
+#[no_mangle]
+pub extern "C" fn lb_server_stop(handle: u64) -> i32 {
+    ffi_guard(0, || status_result(stop_server(handle)))
+}
@@ -0,0 +1,367 @@
+use std::os::raw::c_char;
+
+use crate::adb;
+use crate::device_lock::ensure_device_unlocked;
+use crate::error::{ErrorCode, LbError};
+use crate::json::{self, JsonValue};
+use crate::logging::{lb_log, Level};
+use crate::{ffi_guard, now_millis, read_c_str, string_result};
+
+const NAMESPACES: [&str; 3] = ["system", "secure", "global"];
+/// Settings that identify one device or count its history; carrying them to another
+/// install would be wrong, so restores leave them alone.
+const DEVICE_SPECIFIC: [(&str, &str); 4] = [
+    ("secure", "android_id"),
+    ("secure", "bluetooth_address"),
+    ("global", "boot_count"),
+    ("global", "device_provisioned"),
+];
+/// `settings put` commands per `adb shell` during a restore.
+const RESTORE_BATCH: usize = 40;
+const RESULT_MARKER: &str = "__LB_SETTING__";
+/// Doubles hold integers exactly up to 2^53; larger ones stay strings.
+const MAX_EXACT_INTEGER: i64 = 1 << 53;
+
+fn validate_namespace(namespace: &str) -> Result<(), LbError> {
+    if NAMESPACES.contains(&namespace) {
+        Ok(())
+    } else {
+        Err(format!("Unknown settings namespace: {} (expected system, secure or global)", namespace).into())
+    }
+}
+
+fn validate_key(key: &str) -> Result<(), LbError> {
+    let valid = !key.is_empty()
+        && key
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || "_.-:".contains(ch));
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("Invalid setting key: {}", key).into())
+    }
+}
+
+/// Settings are stored as strings; plain decimal numbers (`1`, `0.5`, `-2`; not `007`,
+/// `1e3` or integers past 2^53) become JSON numbers, everything else stays a string.
+fn typed(value: Option<&str>) -> JsonValue {
+    let Some(value) = value else {
+        return JsonValue::Null;
+    };
+    if let Ok(integer) = value.parse::<i64>() {
+        if integer.to_string() == value && integer.abs() <= MAX_EXACT_INTEGER {
+            return JsonValue::Number(integer as f64);
+        }
+    } else if let Ok(float) = value.parse::<f64>() {
+        let plain = value.contains('.') && value.chars().all(|ch| ch.is_ascii_digit() || ch == '.' || ch == '-');
+        let leading_zero = value.trim_start_matches('-').starts_with("00");
+        if float.is_finite() && plain && !leading_zero && !value.ends_with('.') {
+            return JsonValue::Number(float);
+        }
+    }
+    JsonValue::String(value.to_string())
+}
+
+/// `settings list <namespace>` prints `key=value` lines; values may contain `=` and an
+/// unset value prints as `null`.
+fn list_raw(serial: &str, namespace: &str) -> Result<Vec<(String, Option<String>)>, LbError> {
+    let output = adb::shell(serial, &format!("settings list {}", namespace))?;
+    Ok(output
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .filter(|(key, _)| !key.is_empty() && !key.contains(' '))
+        .map(|(key, value)| {
+            let value = (value != "null").then(|| value.to_string());
+            (key.to_string(), value)
+        })
+        .collect())
+}
+
+fn settings_get(serial: &str, namespace: &str, key: &str) -> Result<String, LbError> {
+    validate_namespace(namespace)?;
+    validate_key(key)?;
+    let value = adb::get_setting(serial, namespace, key)?;
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("namespace", namespace.into()),
+        ("key", key.into()),
+        ("value", typed(value.as_deref())),
+        ("raw", value.into()),
+    ])
+    .to_string())
+}
+
+fn settings_set(serial: &str, namespace: &str, key: &str, value: Option<&str>) -> Result<String, LbError> {
+    validate_namespace(namespace)?;
+    validate_key(key)?;
+    ensure_device_unlocked(serial)?;
+    let previous = adb::get_setting(serial, namespace, key)?;
+    adb::put_setting(serial, namespace, key, value)?;
+    // `settings put system` silently drops keys that apps may not write on 6.0+.
+    let current = adb::get_setting(serial, namespace, key)?;
+    if current.as_deref() != value {
+        return Err(LbError::new(
+            ErrorCode::CommandFailed,
+            format!(
+                "Setting {} {} reads back as {} after writing {}",
+                namespace,
+                key,
+                current.as_deref().unwrap_or("null"),
+                value.unwrap_or("null")
+            ),
+        )
+        .with_serial(serial));
+    }
+    lb_log!(Level::Info, "settings", "Set {} {} on {}", namespace, key, serial);
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("namespace", namespace.into()),
+        ("key", key.into()),
+        ("value", typed(current.as_deref())),
+        ("previous", typed(previous.as_deref())),
+    ])
+    .to_string())
+}
+
+fn settings_list(serial: &str, namespace: &str) -> Result<String, LbError> {
+    validate_namespace(namespace)?;
+    let settings: Vec<(String, JsonValue)> = list_raw(serial, namespace)?
+        .into_iter()
+        .map(|(key, value)| (key, typed(value.as_deref())))
+        .collect();
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("namespace", namespace.into()),
+        ("count", settings.len().into()),
+        ("settings", JsonValue::Object(settings)),
+    ])
+    .to_string())
+}
+
+fn parse_namespaces(namespaces_json: Option<&str>) -> Result<Vec<String>, LbError> {
+    let Some(source) = namespaces_json else {
+        return Ok(NAMESPACES.iter().map(|namespace| namespace.to_string()).collect());
+    };
+    let namespaces = json::parse(source)?
+        .as_string_array()
+        .ok_or("Settings namespaces must be a JSON array of strings")?;
+    for namespace in &namespaces {
+        validate_namespace(namespace)?;
+    }
+    Ok(namespaces)
+}
+
+fn settings_snapshot(serial: &str, namespaces_json: Option<&str>) -> Result<String, LbError> {
+    let mut snapshot = Vec::new();
+    for namespace in parse_namespaces(namespaces_json)? {
+        // Raw strings, so a restore writes back exactly what was read.
+        let values: Vec<(String, JsonValue)> = list_raw(serial, &namespace)?
+            .into_iter()
+            .map(|(key, value)| (key, value.into()))
+            .collect();
+        snapshot.push((namespace, JsonValue::Object(values)));
+    }
+    let fingerprint = adb::getprop(serial, "ro.build.fingerprint").ok().filter(|value| !value.is_empty());
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("fingerprint", fingerprint.into()),
+        ("taken_at_ms", now_millis().into()),
+        ("settings", JsonValue::Object(snapshot)),
+    ])
+    .to_string())
+}
+
+struct PendingWrite {
+    namespace: String,
+    key: String,
+    value: String,
+}
+
+/// Runs one batch of writes and returns `(write, error)` for each that failed.
+fn apply_batch<'a>(serial: &str, batch: &'a [PendingWrite]) -> Result<Vec<(&'a PendingWrite, String)>, LbError> {
+    let script = batch
+        .iter()
+        .map(|write| {
+            format!(
+                "out=$(settings put {} {} {} 2>&1); echo \"{} $? $(echo \"$out\" | head -n 1)\"",
+                write.namespace,
+                write.key,
+                adb::shell_quote(&write.value),
+                RESULT_MARKER
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("; ");
+    let output = adb::shell(serial, &script)?;
+    let results: Vec<&str> = output
+        .lines()
+        .filter_map(|line| line.strip_prefix(RESULT_MARKER))
+        .map(str::trim)
+        .collect();
+    Ok(batch
+        .iter()
+        .enumerate()
+        .filter_map(|(index, write)| {
+            let result = results.get(index).copied().unwrap_or("? no result");
+            let (status, detail) = result.split_once(' ').unwrap_or((result, ""));
+            (status != "0").then(|| (write, if detail.is_empty() { format!("exit {}", status) } else { detail.to_string() }))
+        })
+        .collect())
+}
+
+fn settings_restore(serial: &str, snapshot_json: &str) -> Result<String, LbError> {
+    let snapshot = json::parse(snapshot_json)?;
+    let namespaces = snapshot
+        .get("settings")
+        .and_then(JsonValue::as_object)
+        .ok_or("Settings snapshot has no settings object")?;
+    ensure_device_unlocked(serial)?;
+
+    let mut writes = Vec::new();
+    let (mut unchanged, mut skipped) = (0usize, 0usize);
+    for (namespace, values) in namespaces {
+        validate_namespace(namespace)?;
+        let values = values
+            .as_object()
+            .ok_or_else(|| format!("Snapshot namespace {} must be an object", namespace))?;
+        let current = list_raw(serial, namespace)?;
+        for (key, value) in values {
+            // Unset keys are left alone: deleting what the device has would be destructive.
+            let Some(value) = value.as_str() else {
+                skipped += 1;
+                continue;
+            };
+            if validate_key(key).is_err() || DEVICE_SPECIFIC.contains(&(namespace.as_str(), key.as_str())) {
+                skipped += 1;
+                continue;
+            }
+            let now = current.iter().find(|(name, _)| name == key).and_then(|(_, value)| value.as_deref());
+            if now == Some(value) {
+                unchanged += 1;
+                continue;
+            }
+            writes.push(PendingWrite {
+                namespace: namespace.clone(),
+                key: key.clone(),
+                value: value.to_string(),
+            });
+        }
+    }
+
+    let mut failed = Vec::new();
+    for batch in writes.chunks(RESTORE_BATCH) {
+        for (write, error) in apply_batch(serial, batch)? {
+            failed.push(JsonValue::object(vec![
+                ("namespace", write.namespace.as_str().into()),
+                ("key", write.key.as_str().into()),
+                ("error", error.into()),
+            ]));
+        }
+    }
+    let applied = writes.len() - failed.len();
+    lb_log!(
+        Level::Info,
+        "settings",
+        "Restored {} settings on {} ({} unchanged, {} failed)",
+        applied,
+        serial,
+        unchanged,
+        failed.len()
+    );
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("applied", applied.into()),
+        ("unchanged", unchanged.into()),
+        ("skipped", skipped.into()),
+        ("failed", failed.into()),
+    ])
+    .to_string())
+}
+
+fn optional_str<'a>(ptr: *const c_char, name: &str) -> Result<Option<&'a str>, LbError> {
+    if ptr.is_null() {
+        Ok(None)
+    } else {
+        read_c_str(ptr, name).map(Some)
+    }
+}
+
+/// Reads one setting: `{serial, namespace, key, value, raw}`. `namespace` is `system`,
+/// `secure` or `global`; `value` is a number when the stored string is one, `raw` the
+/// string itself, and both are null for unset keys.
+#[no_mangle]
+pub extern "C" fn lb_settings_get(
+    serial_ptr: *const c_char,
+    namespace_ptr: *const c_char,
+    key_ptr: *const c_char,
+) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(serial_ptr, "serial").and_then(|serial| {
+            read_c_str(namespace_ptr, "namespace").and_then(|namespace| {
+                read_c_str(key_ptr, "key").and_then(|key| settings_get(serial, namespace, key))
+            })
+        });
+        string_result(result, "setting")
+    })
+}
+
+/// Writes one setting (a null `value` deletes it) and reads it back, failing with
+/// CommandFailed when the device did not keep the value. Returns `{serial, namespace, key,
+/// value, previous}`.
+#[no_mangle]
+pub extern "C" fn lb_settings_set(
+    serial_ptr: *const c_char,
+    namespace_ptr: *const c_char,
+    key_ptr: *const c_char,
+    value_ptr: *const c_char,
+) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(serial_ptr, "serial").and_then(|serial| {
+            read_c_str(namespace_ptr, "namespace").and_then(|namespace| {
+                read_c_str(key_ptr, "key").and_then(|key| {
+                    optional_str(value_ptr, "value").and_then(|value| settings_set(serial, namespace, key, value))
+                })
+            })
+        });
+        string_result(result, "setting")
+    })
+}
+
+/// Every key in one namespace: `{serial, namespace, count, settings: {key: value}}`, values
+/// typed as in `lb_settings_get`.
+#[no_mangle]
+pub extern "C" fn lb_settings_list(serial_ptr: *const c_char, namespace_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(serial_ptr, "serial").and_then(|serial| {
+            read_c_str(namespace_ptr, "namespace").and_then(|namespace| settings_list(serial, namespace))
+        });
+        string_result(result, "settings")
+    })
+}
+
+/// Captures `namespaces_json` (a JSON array; null for all three) as `{serial, fingerprint,
+/// taken_at_ms, settings: {namespace: {key: raw string | null}}}` for `lb_settings_restore`.
+#[no_mangle]
+pub extern "C" fn lb_settings_snapshot(serial_ptr: *const c_char, namespaces_json_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(serial_ptr, "serial").and_then(|serial| {
+            optional_str(namespaces_json_ptr, "namespaces").and_then(|namespaces| settings_snapshot(serial, namespaces))
+        });
+        string_result(result, "settings snapshot")
+    })
+}
+
+/// Writes back every snapshot value that differs from the device, batched into a few
+/// shells. Keys missing from or null in the snapshot are not deleted, and device
+/// identifiers (`android_id`, `bluetooth_address`, ...) are skipped. Returns `{serial,
+/// applied, unchanged, skipped, failed: [{namespace, key, error}]}`; keys the device
+/// refuses are reported in `failed` rather than failing the call.
+#[no_mangle]
+pub extern "C" fn lb_settings_restore(serial_ptr: *const c_char, snapshot_json_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(serial_ptr, "serial").and_then(|serial| {
+            read_c_str(snapshot_json_ptr, "snapshot").and_then(|snapshot| settings_restore(serial, snapshot))
+        });
+        string_result(result, "settings restore")
+    })
+}
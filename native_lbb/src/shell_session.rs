@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::os::raw::c_char;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::adb;
+use crate::error::{ErrorCode, LbError};
+use crate::json::JsonValue;
+use crate::logging::{lb_log, Level};
+use crate::transcript;
+use crate::{ffi_guard, handle_result, read_c_str, status_result, string_result};
+
+const EXEC_TIMEOUT: Duration = Duration::from_secs(60);
+const END_MARKER: &str = "__LB_SHELL_END__";
+
+/// One long-lived `adb shell`. Commands are written to stdin and their output is read
+/// back up to an end marker carrying the exit status.
+struct Session {
+    serial: String,
+    /// Shared with the registry so `close_all` can kill a session that is mid-command.
+    child: Arc<Mutex<Child>>,
+    stdin: ChildStdin,
+    lines: Receiver<String>,
+    next_command: u64,
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        if let Ok(mut child) = self.child.lock() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+struct Registered {
+    session: Arc<Mutex<Session>>,
+    child: Arc<Mutex<Child>>,
+}
+
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(1);
+// Each session has its own lock so commands on different devices run concurrently.
+static SESSIONS: OnceLock<Mutex<HashMap<u64, Registered>>> = OnceLock::new();
+
+fn session_registry() -> &'static Mutex<HashMap<u64, Registered>> {
+    SESSIONS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn lookup(id: u64) -> Result<Arc<Mutex<Session>>, LbError> {
+    session_registry()
+        .lock()
+        .map_err(|_| LbError::internal("Shell session registry poisoned"))?
+        .get(&id)
+        .map(|registered| Arc::clone(&registered.session))
+        .ok_or_else(|| LbError::not_found(format!("No open shell session with handle {}", id)))
+}
+
+fn remove(id: u64) -> Option<Registered> {
+    session_registry().lock().ok().and_then(|mut guard| guard.remove(&id))
+}
+
+pub fn open_session(serial: &str) -> Result<u64, LbError> {
+    let argv = adb::adb_argv(Some(serial), &["shell"]);
+    let mut child = Command::new(&argv[0])
+        .args(&argv[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|err| {
+            LbError::new(ErrorCode::SpawnFailed, format!("Failed to spawn {}: {}", argv[0], err))
+                .with_command(&argv)
+                .with_serial(serial)
+        })?;
+    let stdin = child.stdin.take().ok_or("Failed to capture shell input")?;
+    let stdout = child.stdout.take().ok_or("Failed to capture shell output")?;
+    let (sender, lines) = mpsc::channel();
+    thread::spawn(move || {
+        let mut reader = BufReader::new(stdout);
+        let mut buffer = Vec::new();
+        loop {
+            buffer.clear();
+            match reader.read_until(b'\n', &mut buffer) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {
+                    let line = String::from_utf8_lossy(&buffer).trim_end_matches(['\r', '\n']).to_string();
+                    if sender.send(line).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+    let id = NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed);
+    let child = Arc::new(Mutex::new(child));
+    let session = Session {
+        serial: serial.to_string(),
+        child: Arc::clone(&child),
+        stdin,
+        lines,
+        next_command: 1,
+    };
+    session_registry()
+        .lock()
+        .map_err(|_| LbError::internal("Shell session registry poisoned"))?
+        .insert(
+            id,
+            Registered {
+                session: Arc::new(Mutex::new(session)),
+                child,
+            },
+        );
+    lb_log!(Level::Info, "shell", "Shell session {} opened on {}", id, serial);
+    Ok(id)
+}
+
+/// Runs `command` with stdin detached (so it cannot swallow the next command) and stderr
+/// folded into the output, then prints the marker with the exit status. Output without a
+/// trailing newline ends up on the marker's line, so the marker is searched anywhere in it.
+fn run_in_session(session: &mut Session, command: &str) -> Result<(String, i32), LbError> {
+    // The trailing space keeps `..END__1` from matching `..END__12`.
+    let marker = format!("{}{} ", END_MARKER, session.next_command);
+    session.next_command += 1;
+    let script = format!("{{ {}\n}} </dev/null 2>&1; echo \"{}$?\"\n", command, marker);
+    session
+        .stdin
+        .write_all(script.as_bytes())
+        .and_then(|_| session.stdin.flush())
+        .map_err(|err| LbError::new(ErrorCode::DeviceOffline, format!("Shell session closed: {}", err)))?;
+
+    let deadline = Instant::now() + EXEC_TIMEOUT;
+    let mut output = Vec::new();
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match session.lines.recv_timeout(remaining) {
+            Ok(line) => match line.find(&marker) {
+                Some(start) => {
+                    if start > 0 {
+                        output.push(line[..start].to_string());
+                    }
+                    let status = line[start + marker.len()..].trim().parse().unwrap_or(-1);
+                    return Ok((output.join("\n"), status));
+                }
+                None => output.push(line),
+            },
+            Err(RecvTimeoutError::Timeout) => {
+                return Err(LbError::new(
+                    ErrorCode::Timeout,
+                    format!("Shell command did not finish within {}s", EXEC_TIMEOUT.as_secs()),
+                ))
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(LbError::new(ErrorCode::DeviceOffline, "Shell session ended"));
+            }
+        }
+    }
+}
+
+/// Runs `command` in session `id` and returns its output, exit status, and duration. A
+/// session that times out or loses its device is closed and later calls get NotFound.
+pub fn run(id: u64, command: &str) -> Result<(String, i32, Duration), LbError> {
+    let session = lookup(id)?;
+    let mut session = session
+        .lock()
+        .map_err(|_| LbError::internal("Shell session poisoned"))?;
+    let started = Instant::now();
+    let result = run_in_session(&mut session, command);
+    let duration = started.elapsed();
+    let argv = adb::adb_argv(Some(&session.serial), &["shell", command]);
+    match &result {
+        Ok((output, exit_code)) => transcript::record_command_output(&argv, duration, Some(*exit_code), output, ""),
+        Err(_) => transcript::record_command(&argv, duration, None),
+    }
+    match result {
+        Ok((output, exit_code)) => Ok((output, exit_code, duration)),
+        Err(err) => {
+            // A timed-out or dead shell is out of step with its markers; drop it.
+            let serial = session.serial.clone();
+            drop(session);
+            remove(id);
+            lb_log!(Level::Warn, "shell", "Shell session {} on {} closed: {}", id, serial, err.message);
+            Err(err.with_command(&argv).with_serial(&serial))
+        }
+    }
+}
+
+fn shell_exec(id: u64, command: &str) -> Result<String, LbError> {
+    let (output, exit_code, duration) = run(id, command)?;
+    Ok(JsonValue::object(vec![
+        ("output", output.into()),
+        ("exit_code", exit_code.into()),
+        ("duration_ms", (duration.as_millis() as u64).into()),
+    ])
+    .to_string())
+}
+
+pub fn close_session(id: u64) -> Result<(), LbError> {
+    remove(id).ok_or_else(|| LbError::not_found(format!("No open shell session with handle {}", id)))?;
+    lb_log!(Level::Info, "shell", "Shell session {} closed", id);
+    Ok(())
+}
+
+/// Closes every session, for `lb_shutdown`. Killing the `adb shell` also ends a command
+/// still running in it: its reader sees the session end. Returns how many were open.
+pub fn close_all() -> usize {
+    let sessions: Vec<Registered> = session_registry()
+        .lock()
+        .map(|mut guard| guard.drain().map(|(_, registered)| registered).collect())
+        .unwrap_or_default();
+    for registered in &sessions {
+        if let Ok(mut child) = registered.child.lock() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+    sessions.len()
+}
+
+/// Opens a persistent `adb shell` on `serial` and returns its handle (0 on error).
+/// Each call opens a separate session.
+#[no_mangle]
+pub extern "C" fn lb_shell_open(serial_ptr: *const c_char) -> u64 {
+    ffi_guard(0, || handle_result(read_c_str(serial_ptr, "serial").and_then(open_session)))
+}
+
+/// Runs one command in the session and returns `{"output", "exit_code", "duration_ms"}`;
+/// stderr is folded into `output`. Commands on one handle run one at a time. A command
+/// that times out (60s) or loses the device closes the session.
+#[no_mangle]
+pub extern "C" fn lb_shell_exec(handle: u64, command_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(command_ptr, "command").and_then(|command| shell_exec(handle, command));
+        string_result(result, "shell result")
+    })
+}
+
+/// Ends the session and its `adb shell` process.
+#[no_mangle]
+pub extern "C" fn lb_shell_close(handle: u64) -> i32 {
+    ffi_guard(0, || status_result(close_session(handle)))
+}
@@ -0,0 +1,161 @@
+//! `lb_shutdown`: what the host calls before it exits. Killing our adb children alone is not
+//! enough — `adb shell screenrecord` keeps running on the device when its host-side adb
+//! dies — so recordings and monkey runs are stopped on the device and local children get
+//! SIGINT and a deadline before anything is force-killed.
+
+use std::os::raw::c_char;
+use std::process::{Child, Command};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::cancel;
+use crate::command_stream;
+use crate::error::LbError;
+use crate::json::JsonValue;
+use crate::logging::{lb_log, Level};
+use crate::monitor;
+use crate::monkey;
+use crate::scheduler;
+use crate::shell_session;
+use crate::stream;
+use crate::top;
+use crate::{ffi_guard, recording_serials, stop_screen_record_within, string_result};
+
+const DEFAULT_DEADLINE_MS: u64 = 5000;
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[cfg(unix)]
+fn interrupt(child: &Child) {
+    let _ = Command::new("kill").args(["-INT", &child.id().to_string()]).output();
+}
+
+/// Windows has no SIGINT for a child without a console; it is force-killed at the deadline.
+#[cfg(windows)]
+fn interrupt(_child: &Child) {}
+
+/// Waits for `children` until `deadline`, then kills the rest. Returns how many were killed.
+fn reap_until(mut children: Vec<Child>, deadline: Instant) -> usize {
+    loop {
+        children.retain_mut(|child| matches!(child.try_wait(), Ok(None)));
+        if children.is_empty() || Instant::now() >= deadline {
+            break;
+        }
+        // Plain sleep: the caller's token may be the one `cancel_all` just fired.
+        thread::sleep(POLL_INTERVAL);
+    }
+    for child in &mut children {
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+    children.len()
+}
+
+fn shutdown(deadline_ms: u64) -> Result<JsonValue, LbError> {
+    let started = Instant::now();
+    let deadline_ms = if deadline_ms == 0 { DEFAULT_DEADLINE_MS } else { deadline_ms };
+    let deadline = started + Duration::from_millis(deadline_ms);
+    lb_log!(Level::Info, "exec", "Shutting down (deadline {}ms)", deadline_ms);
+
+    // Batches, scripts and polling loops stop first so nothing new is spawned meanwhile.
+    cancel::cancel_all();
+    let schedules = scheduler::cancel_all();
+    let monitors = monitor::stop_all();
+    let shell_sessions = shell_session::close_all();
+
+    let mut children = stream::take_all();
+    children.extend(command_stream::take_all());
+    children.extend(top::take_all());
+    for child in &children {
+        interrupt(child);
+    }
+    // Monkey's adb is left to exit once monkey dies on the device; the deadline still applies.
+    let monkeys = monkey::take_all();
+    for (serial, _) in &monkeys {
+        let serial = serial.clone();
+        thread::spawn(move || monkey::stop_on_device(&serial));
+    }
+    let monkey_count = monkeys.len();
+    children.extend(monkeys.into_iter().map(|(_, child)| child));
+    let processes = children.len();
+
+    let serials = recording_serials();
+    let (done, results) = mpsc::channel();
+    for serial in &serials {
+        let serial = serial.clone();
+        let done = done.clone();
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        thread::spawn(move || {
+            let result = stop_screen_record_within(&serial, timeout);
+            let _ = done.send((serial, result));
+        });
+    }
+    drop(done);
+
+    let force_killed = reap_until(children, deadline);
+
+    let mut stopped: Vec<(String, Result<(), LbError>)> = Vec::with_capacity(serials.len());
+    while stopped.len() < serials.len() {
+        // A device whose `pkill` hangs past the deadline is reported rather than waited on.
+        match results.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(result) => stopped.push(result),
+            Err(_) => break,
+        }
+    }
+    let recordings: Vec<JsonValue> = serials
+        .iter()
+        .map(|serial| {
+            let error = match stopped.iter().find(|(stopped_serial, _)| stopped_serial == serial) {
+                Some((_, Ok(()))) => None,
+                Some((_, Err(err))) => Some(err.message.clone()),
+                None => Some("Recording did not stop before the shutdown deadline".to_string()),
+            };
+            JsonValue::object(vec![
+                ("serial", serial.as_str().into()),
+                ("stopped", error.is_none().into()),
+                ("error", error.into()),
+            ])
+        })
+        .collect();
+
+    let duration_ms = started.elapsed().as_millis() as u64;
+    lb_log!(
+        Level::Info,
+        "exec",
+        "Shutdown finished in {}ms: {} recordings, {} monkey runs, {} processes ({} force-killed), \
+         {} shell sessions, {} monitors, {} schedules",
+        duration_ms,
+        serials.len(),
+        monkey_count,
+        processes,
+        force_killed,
+        shell_sessions,
+        monitors,
+        schedules
+    );
+    Ok(JsonValue::object(vec![
+        ("recordings", recordings.into()),
+        ("monkeys", monkey_count.into()),
+        ("processes", processes.into()),
+        ("force_killed", force_killed.into()),
+        ("shell_sessions", shell_sessions.into()),
+        ("monitors", monitors.into()),
+        ("schedules", schedules.into()),
+        ("duration_ms", duration_ms.into()),
+    ]))
+}
+
+/// Stops everything the library started, for the host to call on exit: fires every cancel
+/// token (in-flight batches, scripts, waits), stops monitors and schedules, closes shell
+/// sessions, sends SIGINT to stream, command and `top` children, `pkill -SIGINT screenrecord`
+/// to every recording device and `pkill -INT` to every device running monkey, waits up to
+/// `deadline_ms` (0 = 5000) and force-kills whatever is left. Returns `{recordings: [{serial,
+/// stopped, error}], monkeys, processes, force_killed, shell_sessions, monitors, schedules,
+/// duration_ms}`; `processes` counts the monkey runs' adb too. The library stays usable
+/// afterwards.
+#[no_mangle]
+pub extern "C" fn lb_shutdown(deadline_ms: u64) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        string_result(shutdown(deadline_ms).map(|report| report.to_string()), "shutdown report")
+    })
+}
@@ -0,0 +1,155 @@
+use std::ffi::c_void;
+use std::io::Read;
+use std::os::raw::c_char;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::adb;
+use crate::device_lock::ensure_device_unlocked;
+use crate::error::{ErrorCode, LbError};
+use crate::exec::CancellableChild;
+use crate::json::JsonValue;
+use crate::logging::{lb_log, Level};
+use crate::reboot;
+use crate::serial_lock;
+use crate::stream::{LineCallback, LineSink};
+use crate::transcript;
+use crate::{ffi_guard, read_c_str, string_result};
+
+/// Recovery drops out of sideload mode once the whole package has been served.
+const LEAVE_SIDELOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// `serving: 'ota.zip'  (~47%)`, redrawn in place with `\r`.
+fn parse_percent(status: &str) -> Option<u64> {
+    let start = status.rfind("(~")? + 2;
+    let end = start + status[start..].find('%')?;
+    status[start..end].trim().parse().ok()
+}
+
+fn sideload(serial: &str, zip_path: &str, sink: Option<LineSink>) -> Result<String, LbError> {
+    let zip = Path::new(zip_path);
+    if !zip.is_file() {
+        return Err(LbError::not_found(format!("OTA package not found: {}", zip_path)));
+    }
+    match adb::get_state(serial).as_deref() {
+        Some("sideload") => {}
+        state => {
+            return Err(LbError::new(
+                ErrorCode::DeviceOffline,
+                format!(
+                    "{} is in state {}, not sideload; use lb_reboot(serial, \"sideload\") and wait for it",
+                    serial,
+                    state.unwrap_or("unknown")
+                ),
+            )
+            .with_serial(serial))
+        }
+    }
+    ensure_device_unlocked(serial)?;
+    let argv = adb::adb_argv(Some(serial), &["sideload", zip_path]);
+    // Held through the transfer and the wait for the device to leave sideload mode.
+    let _device = serial_lock::acquire_for(&argv);
+    let started = Instant::now();
+    let mut child = Command::new(&argv[0])
+        .args(&argv[1..])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| {
+            LbError::new(ErrorCode::SpawnFailed, format!("Failed to spawn {}: {}", argv[0], err))
+                .with_command(&argv)
+                .with_serial(serial)
+        })?;
+    // Failures arrive on stderr; drain it on its own thread so neither pipe can fill up.
+    let stderr = child.stderr.take().map(|mut stderr| {
+        thread::spawn(move || {
+            let mut text = String::new();
+            let _ = stderr.read_to_string(&mut text);
+            text
+        })
+    });
+    // Progress lines end in `\r`, so split on both line endings instead of using `lines()`.
+    let stdout = child.stdout.take();
+    let mut child = CancellableChild::new(child);
+    let mut output = Vec::new();
+    let mut pending = Vec::new();
+    let mut last_percent = None;
+    if let Some(mut stdout) = stdout {
+        let mut chunk = [0u8; 4096];
+        while let Ok(read) = stdout.read(&mut chunk) {
+            if read == 0 {
+                break;
+            }
+            output.extend_from_slice(&chunk[..read]);
+            for byte in &chunk[..read] {
+                if *byte != b'\r' && *byte != b'\n' {
+                    pending.push(*byte);
+                    continue;
+                }
+                let percent = parse_percent(&String::from_utf8_lossy(&pending));
+                pending.clear();
+                if percent.is_none() || percent == last_percent {
+                    continue;
+                }
+                last_percent = percent;
+                if let (Some(sink), Some(percent)) = (&sink, percent) {
+                    sink.emit(&JsonValue::object(vec![("percent", percent.into())]).to_string());
+                }
+            }
+        }
+    }
+    let status = child
+        .wait()
+        .map_err(|err| LbError::io(format!("Failed to wait for adb sideload: {}", err)))?;
+    transcript::record_command(&argv, started.elapsed(), status.code());
+    child.check().map_err(|err| err.with_serial(serial).with_command(&argv))?;
+    let errors = stderr.and_then(|reader| reader.join().ok()).unwrap_or_default();
+    let text = format!("{}\n{}", String::from_utf8_lossy(&output), errors).replace('\r', "\n");
+    let message = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && parse_percent(line).is_none())
+        .collect::<Vec<_>>()
+        .join("\n");
+    let success = status.success();
+    if success {
+        // The next step is usually `lb_wait_for_state(serial, "recovery"|"booted")`; do not
+        // let that wait match the sideload transport that is about to go away.
+        if !reboot::wait_to_leave(serial, "sideload", LEAVE_SIDELOAD_TIMEOUT)? {
+            lb_log!(Level::Warn, "install", "{} still in sideload mode after the transfer", serial);
+        }
+    }
+    lb_log!(Level::Info, "install", "Sideloaded {} to {}: {}", zip_path, serial, if success { "ok" } else { "failed" });
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("path", zip_path.into()),
+        ("success", success.into()),
+        ("message", message.into()),
+        ("duration_ms", (started.elapsed().as_millis() as u64).into()),
+    ])
+    .to_string())
+}
+
+/// Streams an OTA package to a device in recovery's "Apply update from ADB" mode and returns
+/// `{serial, path, success, message, duration_ms}`. The optional callback gets `{percent}`
+/// on the calling thread whenever adb's progress changes. The device must already be in
+/// `sideload` state (`lb_reboot(serial, "sideload")` + `lb_wait_for_state`).
+#[no_mangle]
+pub extern "C" fn lb_adb_sideload(
+    serial_ptr: *const c_char,
+    zip_path_ptr: *const c_char,
+    callback: Option<LineCallback>,
+    user_data: *mut c_void,
+) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let sink = callback.map(|callback| LineSink::new(Some(callback), user_data)).transpose();
+        let result = sink.and_then(|sink| {
+            read_c_str(serial_ptr, "serial")
+                .and_then(|serial| read_c_str(zip_path_ptr, "OTA package path").and_then(|zip| sideload(serial, zip, sink)))
+        });
+        string_result(result, "sideload result")
+    })
+}
@@ -0,0 +1,207 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::ffi::{c_void, CString};
+use std::io::{BufRead, BufReader};
+use std::os::raw::c_char;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::Instant;
+
+use crate::error::{ErrorCode, LbError};
+use crate::{ffi_guard, status_result};
+use crate::history;
+use crate::logging::{lb_log, Level};
+use crate::transcript;
+
+/// Host callback receiving one NUL-terminated UTF-8 payload (a line or a JSON event).
+/// The pointer is only valid for the duration of the call.
+pub type LineCallback = extern "C" fn(user_data: *mut c_void, payload: *const c_char);
+
+/// A host callback plus its opaque pointer, movable onto reader threads. The host
+/// guarantees `user_data` stays valid until the stream is stopped.
+#[derive(Clone, Copy)]
+pub struct LineSink {
+    callback: LineCallback,
+    user_data: *mut c_void,
+}
+
+unsafe impl Send for LineSink {}
+
+impl LineSink {
+    pub fn new(callback: Option<LineCallback>, user_data: *mut c_void) -> Result<LineSink, LbError> {
+        let callback = callback.ok_or("Null pointer received for callback")?;
+        Ok(LineSink { callback, user_data })
+    }
+
+    pub fn emit(&self, payload: &str) {
+        let payload = if payload.contains('\0') {
+            payload.replace('\0', "")
+        } else {
+            payload.to_string()
+        };
+        if let Ok(payload) = CString::new(payload) {
+            (self.callback)(self.user_data, payload.as_ptr());
+        }
+    }
+}
+
+/// Lets `stop_stream` guarantee nothing is delivered after it returns: every callback runs
+/// through `deliver`, which holds `delivering` and checks `stopped` under it. Streams that
+/// also emit from a thread of their own (e.g. a flush timer) share the gate with it.
+#[derive(Default)]
+pub struct Gate {
+    stopped: AtomicBool,
+    delivering: Mutex<()>,
+}
+
+impl Gate {
+    fn key(&self) -> usize {
+        self as *const Gate as usize
+    }
+
+    /// Runs `deliver` unless the stream was stopped; false once it has been.
+    pub fn deliver(&self, deliver: impl FnOnce()) -> bool {
+        let Ok(_delivering) = self.delivering.lock() else {
+            return false;
+        };
+        if self.stopped.load(Ordering::SeqCst) {
+            return false;
+        }
+        let outer = DELIVERING.with(|delivering| delivering.replace(self.key()));
+        deliver();
+        DELIVERING.with(|delivering| delivering.set(outer));
+        true
+    }
+
+    /// Stops deliveries and waits out one in progress, unless called from a callback of
+    /// this gate, which would wait on itself; its next delivery sees the flag.
+    fn close(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        if DELIVERING.with(Cell::get) != self.key() {
+            drop(self.delivering.lock());
+        }
+    }
+}
+
+struct Stream {
+    child: Child,
+    gate: Arc<Gate>,
+}
+
+static NEXT_STREAM_ID: AtomicU64 = AtomicU64::new(1);
+static STREAMS: OnceLock<Mutex<HashMap<u64, Stream>>> = OnceLock::new();
+
+thread_local! {
+    /// The gate whose callback this thread is running (0 outside callbacks).
+    static DELIVERING: Cell<usize> = const { Cell::new(0) };
+}
+
+fn stream_registry() -> &'static Mutex<HashMap<u64, Stream>> {
+    STREAMS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn reap(id: u64) -> Option<Stream> {
+    stream_registry().lock().ok().and_then(|mut guard| guard.remove(&id))
+}
+
+/// Spawns `argv` and hands every stdout line to `on_line` on a reader thread until the
+/// process exits or `stop_stream` is called. Returns the stream handle.
+pub fn spawn_line_stream<F>(argv: &[String], on_line: F) -> Result<u64, LbError>
+where
+    F: FnMut(&str) + Send + 'static,
+{
+    spawn_gated_line_stream(argv, Arc::new(Gate::default()), on_line)
+}
+
+/// `spawn_line_stream` with a gate the caller also delivers through from its own threads.
+pub fn spawn_gated_line_stream<F>(argv: &[String], gate: Arc<Gate>, mut on_line: F) -> Result<u64, LbError>
+where
+    F: FnMut(&str) + Send + 'static,
+{
+    if argv.is_empty() {
+        return Err("Empty command".into());
+    }
+    let mut child = Command::new(&argv[0])
+        .args(&argv[1..])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|err| LbError::new(ErrorCode::SpawnFailed, format!("Failed to spawn {}: {}", argv[0], err)).with_command(argv))?;
+    let stdout = child.stdout.take().ok_or("Failed to capture stream output")?;
+    let id = NEXT_STREAM_ID.fetch_add(1, Ordering::Relaxed);
+    stream_registry()
+        .lock()
+        .map_err(|_| LbError::internal("Stream registry poisoned"))?
+        .insert(
+            id,
+            Stream {
+                child,
+                gate: Arc::clone(&gate),
+            },
+        );
+
+    lb_log!(Level::Info, "stream", "Stream {} started: {}", id, history::redacted(argv).0.join(" "));
+    let argv = argv.to_vec();
+    let started = Instant::now();
+    thread::spawn(move || {
+        let mut reader = BufReader::new(stdout);
+        let mut buffer = Vec::new();
+        loop {
+            buffer.clear();
+            match reader.read_until(b'\n', &mut buffer) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {
+                    let line = String::from_utf8_lossy(&buffer);
+                    if !gate.deliver(|| on_line(line.trim_end_matches(['\r', '\n']))) {
+                        break;
+                    }
+                }
+            }
+        }
+        // Natural exit: nobody called `stop_stream`, so the handle is still registered.
+        let exit_code = reap(id)
+            .and_then(|mut stream| stream.child.wait().ok())
+            .and_then(|status| status.code());
+        transcript::record_command(&argv, started.elapsed(), exit_code);
+        lb_log!(Level::Debug, "stream", "Stream {} ended (exit {:?})", id, exit_code);
+    });
+    Ok(id)
+}
+
+/// Kills the stream's process. Once this returns its callback is not called again.
+pub fn stop_stream(id: u64) -> Result<(), LbError> {
+    let mut stream = reap(id).ok_or_else(|| LbError::not_found(format!("No active stream with handle {}", id)))?;
+    stream.gate.close();
+    let _ = stream.child.kill();
+    let _ = stream.child.wait();
+    lb_log!(Level::Info, "stream", "Stream {} stopped", id);
+    Ok(())
+}
+
+/// Unregisters every stream, silences its callback and hands back its process, for
+/// `lb_shutdown` to stop.
+pub fn take_all() -> Vec<Child> {
+    let streams: Vec<Stream> = stream_registry()
+        .lock()
+        .map(|mut guard| guard.drain().map(|(_, stream)| stream).collect())
+        .unwrap_or_default();
+    streams
+        .into_iter()
+        .map(|stream| {
+            stream.gate.close();
+            stream.child
+        })
+        .collect()
+}
+
+/// Stops any follow/watch stream (kernel log, logcat watchers, ...) by handle. The
+/// callback is not called once this returns.
+#[no_mangle]
+pub extern "C" fn lb_stop_stream(handle: u64) -> i32 {
+    ffi_guard(0, || {
+        status_result(stop_stream(handle))
+    })
+}
@@ -0,0 +1,199 @@
+use std::os::raw::c_char;
+
+use crate::adb;
+use crate::error::LbError;
+use crate::json::JsonValue;
+use crate::{ffi_guard, read_c_str, string_result};
+
+/// Millidegree zones from the kernel, for releases without `thermalservice` (before 10).
+const THERMAL_ZONES_SCRIPT: &str =
+    "for z in /sys/class/thermal/thermal_zone*; do echo \"$(cat $z/type 2>/dev/null)|$(cat $z/temp 2>/dev/null)\"; done";
+
+/// `PowerManager.THERMAL_STATUS_*`.
+fn status_name(status: i64) -> &'static str {
+    match status {
+        0 => "none",
+        1 => "light",
+        2 => "moderate",
+        3 => "severe",
+        4 => "critical",
+        5 => "emergency",
+        6 => "shutdown",
+        _ => "unknown",
+    }
+}
+
+/// `Temperature.TYPE_*`.
+fn sensor_type_name(sensor_type: i64) -> &'static str {
+    match sensor_type {
+        0 => "cpu",
+        1 => "gpu",
+        2 => "battery",
+        3 => "skin",
+        4 => "usb_port",
+        5 => "power_amplifier",
+        6 => "bcl_voltage",
+        7 => "bcl_current",
+        8 => "bcl_percentage",
+        9 => "npu",
+        10 => "tpu",
+        11 => "display",
+        12 => "modem",
+        13 => "soc",
+        _ => "unknown",
+    }
+}
+
+/// `Temperature{mValue=36.2, mType=0, mName=cpu0, mStatus=0}` -> `[("mValue", "36.2"), ...]`.
+/// Array values (`mHotThrottlingThresholds=[NaN, 95.0]`) keep their brackets.
+fn record_fields<'a>(line: &'a str, kind: &str) -> Option<Vec<(&'a str, &'a str)>> {
+    let body = line.trim().strip_prefix(kind)?.strip_prefix('{')?.strip_suffix('}')?;
+    let mut fields = Vec::new();
+    let mut rest = body;
+    while let Some((key, after)) = rest.split_once('=') {
+        let end = if after.starts_with('[') {
+            after.find(']').map_or(after.len(), |end| end + 1)
+        } else {
+            after.find(", ").unwrap_or(after.len())
+        };
+        fields.push((key.trim_start_matches([',', ' ']), &after[..end]));
+        rest = &after[end..];
+    }
+    Some(fields)
+}
+
+fn field<'a>(fields: &[(&str, &'a str)], name: &str) -> Option<&'a str> {
+    fields.iter().find(|(key, _)| *key == name).map(|(_, value)| *value)
+}
+
+struct Sensor {
+    name: String,
+    sensor_type: i64,
+    temperature_c: Option<f64>,
+    status: Option<i64>,
+    throttling_threshold_c: Option<f64>,
+}
+
+impl Sensor {
+    fn to_json(&self) -> JsonValue {
+        JsonValue::object(vec![
+            ("name", self.name.as_str().into()),
+            ("type", sensor_type_name(self.sensor_type).into()),
+            ("temperature_c", self.temperature_c.into()),
+            ("status", self.status.map(status_name).into()),
+            ("throttling_threshold_c", self.throttling_threshold_c.into()),
+        ])
+    }
+}
+
+/// `dumpsys thermalservice` (Android 10+). Sensors come from `Current temperatures from
+/// HAL:`, or the service's `Cached temperatures:` when the HAL section is empty.
+pub fn parse_thermalservice(output: &str) -> JsonValue {
+    let mut status = None;
+    let mut section = "";
+    let (mut current, mut cached) = (Vec::new(), Vec::new());
+    let mut cooling = Vec::new();
+    let mut thresholds: Vec<(String, f64)> = Vec::new();
+    for line in output.lines() {
+        let trimmed = line.trim();
+        if let Some(value) = trimmed.strip_prefix("Thermal Status:") {
+            status = value.trim().parse::<i64>().ok();
+        } else if trimmed.ends_with(':') && !line.starts_with([' ', '\t']) {
+            section = trimmed;
+        } else if let Some(fields) = record_fields(trimmed, "Temperature") {
+            let sensor = Sensor {
+                name: field(&fields, "mName").unwrap_or_default().to_string(),
+                sensor_type: field(&fields, "mType").and_then(|value| value.parse().ok()).unwrap_or(-1),
+                temperature_c: field(&fields, "mValue").and_then(|value| value.parse().ok()),
+                status: field(&fields, "mStatus").and_then(|value| value.parse().ok()),
+                throttling_threshold_c: None,
+            };
+            match section {
+                "Current temperatures from HAL:" => current.push(sensor),
+                "Cached temperatures:" => cached.push(sensor),
+                _ => {}
+            }
+        } else if let Some(fields) = record_fields(trimmed, "CoolingDevice") {
+            cooling.push(JsonValue::object(vec![
+                ("name", field(&fields, "mName").into()),
+                ("value", field(&fields, "mValue").and_then(|value| value.parse::<i64>().ok()).into()),
+            ]));
+        } else if let Some(fields) = record_fields(trimmed, "TemperatureThreshold") {
+            // Indexed by severity; the first finite one is where throttling starts.
+            let first = field(&fields, "mHotThrottlingThresholds").and_then(|values| {
+                values
+                    .trim_matches(['[', ']'])
+                    .split(',')
+                    .filter_map(|value| value.trim().parse::<f64>().ok())
+                    .find(|value| value.is_finite())
+            });
+            if let (Some(name), Some(first)) = (field(&fields, "mName"), first) {
+                thresholds.push((name.to_string(), first));
+            }
+        }
+    }
+    let mut sensors = if current.is_empty() { cached } else { current };
+    for sensor in &mut sensors {
+        sensor.throttling_threshold_c = thresholds.iter().find(|(name, _)| *name == sensor.name).map(|(_, value)| *value);
+    }
+    summary("thermalservice", status, &sensors, cooling)
+}
+
+/// `type|millidegrees` lines from the thermal zones; unreadable zones are skipped.
+fn parse_thermal_zones(output: &str) -> JsonValue {
+    let sensors: Vec<Sensor> = output
+        .lines()
+        .filter_map(|line| {
+            let (name, value) = line.split_once('|')?;
+            let millidegrees = value.trim().parse::<f64>().ok()?;
+            Some(Sensor {
+                name: name.trim().to_string(),
+                sensor_type: -1,
+                temperature_c: Some(millidegrees / 1000.0),
+                status: None,
+                throttling_threshold_c: None,
+            })
+        })
+        .collect();
+    summary("thermal_zones", None, &sensors, Vec::new())
+}
+
+fn summary(source: &str, status: Option<i64>, sensors: &[Sensor], cooling: Vec<JsonValue>) -> JsonValue {
+    let max = sensors.iter().filter_map(|sensor| sensor.temperature_c).fold(None, |max: Option<f64>, value| {
+        Some(max.map_or(value, |max| max.max(value)))
+    });
+    let sensors: Vec<JsonValue> = sensors.iter().map(Sensor::to_json).collect();
+    JsonValue::object(vec![
+        ("source", source.into()),
+        ("status", status.map(status_name).into()),
+        ("status_code", status.into()),
+        ("throttling", status.map(|status| status > 0).into()),
+        ("max_temperature_c", max.into()),
+        ("sensors", sensors.into()),
+        ("cooling_devices", cooling.into()),
+    ])
+}
+
+fn thermal_status(serial: &str) -> Result<String, LbError> {
+    let output = adb::shell(serial, "dumpsys thermalservice")?;
+    let mut status = if output.contains("Thermal Status:") {
+        parse_thermalservice(&output)
+    } else {
+        parse_thermal_zones(&adb::shell(serial, THERMAL_ZONES_SCRIPT)?)
+    };
+    if let JsonValue::Object(fields) = &mut status {
+        fields.insert(0, ("serial".to_string(), serial.into()));
+    }
+    Ok(status.to_string())
+}
+
+/// Returns `{serial, source, status, status_code, throttling, max_temperature_c, sensors,
+/// cooling_devices}`. `status` is the framework's throttling level (`none` .. `shutdown`)
+/// and sensors are `{name, type, temperature_c, status, throttling_threshold_c}`. Before
+/// Android 10 `source` is `thermal_zones`: kernel zone temperatures with no status.
+#[no_mangle]
+pub extern "C" fn lb_thermal_status(serial_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        string_result(read_c_str(serial_ptr, "serial").and_then(thermal_status), "thermal status")
+    })
+}
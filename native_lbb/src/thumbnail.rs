@@ -0,0 +1,94 @@
+use std::os::raw::c_char;
+
+use crate::adb;
+use crate::animation::RawFrame;
+use crate::error::LbError;
+use crate::logging::{lb_log, Level};
+use crate::png;
+use crate::users;
+use crate::{bytes_result, ffi_guard, read_c_str, LbResult};
+
+const MIN_EDGE_PX: u32 = 16;
+const MAX_EDGE_PX: u32 = 1024;
+
+/// Box-filters `frame` so its longer edge is at most `max_edge`, averaging every source
+/// pixel that falls into each output pixel. Frames already small enough are returned as-is.
+fn downscale(frame: RawFrame, max_edge: u32) -> RawFrame {
+    let longest = frame.width.max(frame.height);
+    if longest <= max_edge {
+        return frame;
+    }
+    let scaled = |edge: u32| ((u64::from(edge) * u64::from(max_edge) / u64::from(longest)) as u32).max(1);
+    let (width, height) = (scaled(frame.width), scaled(frame.height));
+    let (source_width, source_height) = (frame.width as usize, frame.height as usize);
+    let mut rgba = Vec::with_capacity(width as usize * height as usize * 4);
+    for y in 0..height as usize {
+        let rows = y * source_height / height as usize..((y + 1) * source_height / height as usize).max(y + 1);
+        for x in 0..width as usize {
+            let columns = x * source_width / width as usize..((x + 1) * source_width / width as usize).max(x + 1);
+            let mut sums = [0u32; 4];
+            for row in rows.clone() {
+                let start = (row * source_width + columns.start) * 4;
+                for pixel in frame.rgba[start..start + columns.len() * 4].chunks_exact(4) {
+                    for (sum, channel) in sums.iter_mut().zip(pixel) {
+                        *sum += u32::from(*channel);
+                    }
+                }
+            }
+            let count = (rows.len() * columns.len()) as u32;
+            rgba.extend(sums.iter().map(|sum| ((sum + count / 2) / count) as u8));
+        }
+    }
+    RawFrame { width, height, rgba }
+}
+
+fn screenshot_thumbnail(serial: &str, max_edge_px: u32) -> Result<Vec<u8>, LbError> {
+    if !(MIN_EDGE_PX..=MAX_EDGE_PX).contains(&max_edge_px) {
+        return Err(format!("max_edge_px must be between {} and {}", MIN_EDGE_PX, MAX_EDGE_PX).into());
+    }
+    let screenshot = adb::exec_out(serial, "screencap -p")?;
+    let frame = png::decode(&screenshot).map_err(|err| err.with_serial(serial).context("Failed to decode screenshot"))?;
+    let (source_width, source_height) = (frame.width, frame.height);
+    let thumbnail = downscale(frame, max_edge_px);
+    let encoded = png::encode(&thumbnail);
+    lb_log!(
+        Level::Debug,
+        "exec",
+        "Thumbnail of {} {}x{} -> {}x{} ({} bytes, screenshot {} bytes)",
+        serial,
+        source_width,
+        source_height,
+        thumbnail.width,
+        thumbnail.height,
+        encoded.len(),
+        screenshot.len()
+    );
+    Ok(encoded)
+}
+
+/// Captures a screenshot and returns it as a PNG whose longer edge is at most
+/// `max_edge_px` (16-1024), so grid views never move full-resolution images through the FFI.
+/// The buffer holds the PNG bytes; free it with `lb_free_result`.
+#[no_mangle]
+pub extern "C" fn lb_screenshot_thumbnail(serial_ptr: *const c_char, max_edge_px: u32) -> LbResult {
+    ffi_guard(LbResult::empty(), || {
+        bytes_result(read_c_str(serial_ptr, "serial").and_then(|serial| screenshot_thumbnail(serial, max_edge_px)))
+    })
+}
+
+/// `lb_screenshot_thumbnail` that first checks `user_id` is what the screen shows: the
+/// current user or one of its running profiles (work-profile apps draw on the same
+/// screen). Otherwise fails with InvalidArgument rather than returning another user's screen.
+#[no_mangle]
+pub extern "C" fn lb_screenshot_thumbnail_for_user(
+    serial_ptr: *const c_char,
+    max_edge_px: u32,
+    user_id: u32,
+) -> LbResult {
+    ffi_guard(LbResult::empty(), || {
+        bytes_result(read_c_str(serial_ptr, "serial").and_then(|serial| {
+            users::require_visible_user(serial, user_id)?;
+            screenshot_thumbnail(serial, max_edge_px)
+        }))
+    })
+}
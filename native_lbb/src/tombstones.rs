@@ -0,0 +1,275 @@
+use std::fs;
+use std::os::raw::c_char;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::adb;
+use crate::error::{ErrorCode, LbError};
+use crate::failure_capture::path_component;
+use crate::json::{self, JsonValue};
+use crate::logging::{lb_log, Level};
+use crate::remote_fs::days_from_civil;
+use crate::root::adbd_uid;
+use crate::zip::{ZipArchive, ZipEntry};
+use crate::{ffi_guard, now_millis, read_c_str, string_result};
+
+/// Device directories collected, with the subdirectory their files land in locally.
+const CRASH_DIRS: [(&str, &str); 2] = [("/data/tombstones/", "tombstones"), ("/data/anr/", "anr")];
+/// Keys of files already collected into a directory, so repeated runs only add new ones.
+const MANIFEST_FILE: &str = ".lb_collected.json";
+
+/// A tombstone or ANR trace on the device (or inside a bugreport).
+struct CrashFile {
+    device_path: String,
+    kind: &'static str,
+    mtime_secs: u64,
+    size: u64,
+}
+
+impl CrashFile {
+    fn new(device_path: &str, mtime_secs: u64, size: u64) -> Option<CrashFile> {
+        let (_, kind) = CRASH_DIRS.iter().find(|(dir, _)| device_path.starts_with(dir))?;
+        let name = device_path.rsplit('/').next().unwrap_or_default();
+        // Android 12+ writes a protobuf twin of every text tombstone.
+        if name.is_empty() || name.ends_with(".pb") {
+            return None;
+        }
+        Some(CrashFile {
+            device_path: device_path.to_string(),
+            kind,
+            mtime_secs,
+            size,
+        })
+    }
+
+    /// Bugreport times have two-second resolution, so keys round to it; that way a file
+    /// pulled directly and later seen in a bugreport is still recognised.
+    fn key(&self, serial: &str) -> String {
+        format!("{}|{}|{}|{}", serial, self.device_path, self.mtime_secs & !1, self.size)
+    }
+
+    /// Tombstone names are reused (`tombstone_00`..`_31`), so the mtime keeps copies apart.
+    fn local_path(&self, serial_dir: &Path) -> PathBuf {
+        let name = self.device_path.rsplit('/').next().unwrap_or_default();
+        serial_dir.join(self.kind).join(format!("{}-{}", path_component(name), self.mtime_secs))
+    }
+}
+
+fn load_manifest(path: &Path) -> Vec<String> {
+    let Ok(text) = fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    json::parse(&text).ok().and_then(|keys| keys.as_string_array()).unwrap_or_else(|| {
+        lb_log!(Level::Warn, "crash", "Ignoring unreadable {}", path.display());
+        Vec::new()
+    })
+}
+
+fn save_manifest(path: &Path, keys: &[String]) -> Result<(), LbError> {
+    let staging = path.with_extension("tmp");
+    let keys: Vec<JsonValue> = keys.iter().map(JsonValue::from).collect();
+    fs::write(&staging, JsonValue::from(keys).to_string())
+        .and_then(|_| fs::rename(&staging, path))
+        .map_err(|err| LbError::io(format!("Failed to write {}: {}", path.display(), err)))
+}
+
+/// `stat -c '%Y %s %n'` lines for both directories; needs adbd running as root.
+fn list_device_files(serial: &str) -> Result<Vec<CrashFile>, LbError> {
+    let globs: Vec<String> = CRASH_DIRS.iter().map(|(dir, _)| format!("{}*", dir)).collect();
+    let output = adb::shell(serial, &format!("stat -c '%Y %s %n' {} 2>/dev/null; true", globs.join(" ")))?;
+    Ok(output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.trim().splitn(3, ' ');
+            let mtime = fields.next()?.parse().ok()?;
+            let size = fields.next()?.parse().ok()?;
+            CrashFile::new(fields.next()?, mtime, size)
+        })
+        .collect())
+}
+
+/// Device UTC offset in seconds from `date +%z` (`+0530`); zip times are local time.
+fn utc_offset_secs(serial: &str) -> i64 {
+    let output = adb::shell(serial, "date +%z").unwrap_or_default();
+    let value = output.trim();
+    let sign = if value.starts_with('-') { -1 } else { 1 };
+    let digits = value.trim_start_matches(['+', '-']);
+    let field = |range: std::ops::Range<usize>| digits.get(range).and_then(|part| part.parse::<i64>().ok());
+    match (field(0..2), field(2..4)) {
+        (Some(hours), Some(minutes)) => sign * (hours * 3600 + minutes * 60),
+        _ => 0,
+    }
+}
+
+fn dos_time_secs(entry: &ZipEntry, utc_offset: i64) -> u64 {
+    let (date, time) = (i64::from(entry.dos_date), i64::from(entry.dos_time));
+    let days = days_from_civil(1980 + (date >> 9), (date >> 5) & 0xf, date & 0x1f);
+    let seconds = days * 86_400 + (time >> 11) * 3600 + ((time >> 5) & 0x3f) * 60 + (time & 0x1f) * 2;
+    u64::try_from(seconds - utc_offset).unwrap_or(0)
+}
+
+struct Collected {
+    file: CrashFile,
+    local_path: PathBuf,
+}
+
+/// What one run gathered; `manifest` is extended with the new keys as files are written.
+struct Collection<'a> {
+    serial: &'a str,
+    serial_dir: PathBuf,
+    since_ms: u64,
+    manifest: Vec<String>,
+    collected: Vec<Collected>,
+    duplicates: usize,
+}
+
+impl Collection<'_> {
+    /// Where a wanted, not yet collected file should go; `None` to skip it.
+    fn claim(&mut self, file: &CrashFile) -> Result<Option<PathBuf>, LbError> {
+        if file.mtime_secs.saturating_mul(1000) < self.since_ms {
+            return Ok(None);
+        }
+        if self.manifest.contains(&file.key(self.serial)) {
+            self.duplicates += 1;
+            return Ok(None);
+        }
+        let local_path = file.local_path(&self.serial_dir);
+        if let Some(parent) = local_path.parent() {
+            fs::create_dir_all(parent).map_err(|err| LbError::io(format!("Failed to create {}: {}", parent.display(), err)))?;
+        }
+        Ok(Some(local_path))
+    }
+
+    fn record(&mut self, file: CrashFile, local_path: PathBuf) {
+        self.manifest.push(file.key(self.serial));
+        self.collected.push(Collected { file, local_path });
+    }
+
+    fn pull_direct(&mut self) -> Result<(), LbError> {
+        for file in list_device_files(self.serial)? {
+            if let Some(local_path) = self.claim(&file)? {
+                adb::adb_checked(Some(self.serial), &["pull", &file.device_path, &local_path.to_string_lossy()])?;
+                self.record(file, local_path);
+            }
+        }
+        Ok(())
+    }
+
+    fn extract_bugreport(&mut self, zip_path: &Path) -> Result<(), LbError> {
+        let utc_offset = utc_offset_secs(self.serial);
+        let mut archive = ZipArchive::open(zip_path)?;
+        for index in 0..archive.entries.len() {
+            let entry = &archive.entries[index];
+            let file = entry
+                .name
+                .strip_prefix("FS")
+                .filter(|_| !entry.is_dir())
+                .and_then(|path| CrashFile::new(path, dos_time_secs(entry, utc_offset), entry.size));
+            let Some(file) = file else {
+                continue;
+            };
+            if let Some(local_path) = self.claim(&file)? {
+                fs::write(&local_path, archive.read(index)?)
+                    .map_err(|err| LbError::io(format!("Failed to write {}: {}", local_path.display(), err)))?;
+                self.record(file, local_path);
+            }
+        }
+        Ok(())
+    }
+
+    /// Without root only dumpstate can read these directories; it copies them into the
+    /// bugreport zip under `FS/`.
+    fn via_bugreport(&mut self) -> Result<(), LbError> {
+        let zip_path = self.serial_dir.join(format!("bugreport-{}.zip", now_millis()));
+        lb_log!(Level::Info, "crash", "Taking a bugreport on {} to collect tombstones; this takes minutes", self.serial);
+        let output = adb::adb_checked(Some(self.serial), &["bugreport", &zip_path.to_string_lossy()]);
+        if let Err(err) = output {
+            let _ = fs::remove_file(&zip_path);
+            return Err(err.context("adb bugreport failed"));
+        }
+        if !zip_path.is_file() {
+            return Err(LbError::new(
+                ErrorCode::CommandFailed,
+                format!("adb bugreport on {} did not write a zip (needs Android 7+) and adbd is not root", self.serial),
+            )
+            .with_serial(self.serial));
+        }
+        let extracted = self.extract_bugreport(&zip_path);
+        let _ = fs::remove_file(&zip_path);
+        extracted
+    }
+}
+
+fn collect_native_crashes(serial: &str, since_ms: u64, local_dir: &str) -> Result<String, LbError> {
+    let started = Instant::now();
+    let local_dir = Path::new(local_dir);
+    let serial_dir = local_dir.join(path_component(serial));
+    fs::create_dir_all(&serial_dir)
+        .map_err(|err| LbError::io(format!("Failed to create {}: {}", serial_dir.display(), err)))?;
+    let manifest_path = local_dir.join(MANIFEST_FILE);
+    let mut collection = Collection {
+        serial,
+        serial_dir,
+        since_ms,
+        manifest: load_manifest(&manifest_path),
+        collected: Vec::new(),
+        duplicates: 0,
+    };
+    let rooted = adbd_uid(serial).as_deref() == Some("0");
+    let method = if rooted { "pull" } else { "bugreport" };
+    // Whatever was written before a failure stays recorded, so a retry does not redo it.
+    let gathered = if rooted { collection.pull_direct() } else { collection.via_bugreport() };
+    save_manifest(&manifest_path, &collection.manifest)?;
+    gathered?;
+    let Collection { collected, duplicates, .. } = collection;
+    lb_log!(
+        Level::Info,
+        "crash",
+        "Collected {} new crash files from {} via {} ({} already collected)",
+        collected.len(),
+        serial,
+        method,
+        duplicates
+    );
+    let files: Vec<JsonValue> = collected
+        .iter()
+        .map(|item| {
+            JsonValue::object(vec![
+                ("kind", item.file.kind.into()),
+                ("device_path", item.file.device_path.as_str().into()),
+                ("local_path", item.local_path.to_string_lossy().into_owned().into()),
+                ("mtime_ms", item.file.mtime_secs.saturating_mul(1000).into()),
+                ("bytes", item.file.size.into()),
+            ])
+        })
+        .collect();
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("method", method.into()),
+        ("files", files.into()),
+        ("already_collected", duplicates.into()),
+        ("duration_ms", (started.elapsed().as_millis() as u64).into()),
+    ])
+    .to_string())
+}
+
+/// Copies tombstones and ANR traces modified at or after `since_ms` (Unix milliseconds;
+/// 0 for all) into `<local_dir>/<serial>/{tombstones,anr}/`. With adbd running as root
+/// they are pulled directly; otherwise an `adb bugreport` is taken (minutes) and they are
+/// extracted from its `FS/` tree, after which the zip is deleted. Files already listed in
+/// `<local_dir>/.lb_collected.json` are skipped. Returns `{serial, method: pull | bugreport,
+/// files: [{kind, device_path, local_path, mtime_ms, bytes}], already_collected, duration_ms}`.
+#[no_mangle]
+pub extern "C" fn lb_collect_native_crashes(
+    serial_ptr: *const c_char,
+    since_ms: u64,
+    local_dir_ptr: *const c_char,
+) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(serial_ptr, "serial").and_then(|serial| {
+            read_c_str(local_dir_ptr, "local directory")
+                .and_then(|local_dir| collect_native_crashes(serial, since_ms, local_dir))
+        });
+        string_result(result, "crash collection")
+    })
+}
@@ -0,0 +1,334 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read};
+use std::os::raw::c_char;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::Instant;
+
+use crate::adb;
+use crate::cancel;
+use crate::error::{ErrorCode, LbError};
+use crate::json::JsonValue;
+use crate::lmk::matches_package;
+use crate::logging::{lb_log, Level};
+use crate::packages;
+use crate::transcript;
+use crate::{ffi_guard, now_millis, read_c_str, string_result};
+
+const MIN_INTERVAL_MS: u32 = 500;
+const MAX_SAMPLES: u32 = 3600;
+/// Without a package filter each sample keeps only the busiest processes.
+const MAX_UNFILTERED_PROCESSES: usize = 30;
+
+static NEXT_RUN_ID: AtomicU64 = AtomicU64::new(1);
+// Running `top` children, so `lb_shutdown` can stop a sampler that is still blocking.
+static RUNS: OnceLock<Mutex<HashMap<u64, Child>>> = OnceLock::new();
+
+fn run_registry() -> &'static Mutex<HashMap<u64, Child>> {
+    RUNS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Unregisters every running sampler and hands back its process, for `lb_shutdown` to stop.
+pub fn take_all() -> Vec<Child> {
+    run_registry()
+        .lock()
+        .map(|mut guard| guard.drain().map(|(_, child)| child).collect())
+        .unwrap_or_default()
+}
+
+/// Where the fields sit in a process row, from the header line of either top:
+/// toybox (8+) `  PID[%CPU]  RES ARGS` (the sort column is bracketed) or toolbox (7 and
+/// earlier) `  PID PR CPU% S  #THR     VSS     RSS PCY UID      Name`.
+struct Columns {
+    pid: usize,
+    cpu: usize,
+    rss: usize,
+    name: usize,
+    width: usize,
+}
+
+impl Columns {
+    fn from_header(line: &str) -> Option<Columns> {
+        let header: Vec<&str> = line.split(['[', ']', ' ', '\t']).filter(|token| !token.is_empty()).collect();
+        let find = |names: &[&str]| header.iter().position(|token| names.contains(token));
+        Some(Columns {
+            pid: find(&["PID"])?,
+            cpu: find(&["%CPU", "CPU%"])?,
+            rss: find(&["RES", "RSS"])?,
+            name: find(&["ARGS", "CMDLINE", "NAME", "Name", "CMD"])?,
+            width: header.len(),
+        })
+    }
+}
+
+/// toybox prints `153M` / `1.2G` / `912K` (plain numbers are bytes); toolbox `123456K`.
+fn size_kb(value: &str) -> Option<u64> {
+    let (number, scale) = match value.chars().last()? {
+        'K' | 'k' => (&value[..value.len() - 1], 1.0),
+        'M' | 'm' => (&value[..value.len() - 1], 1024.0),
+        'G' | 'g' => (&value[..value.len() - 1], 1024.0 * 1024.0),
+        'T' | 't' => (&value[..value.len() - 1], 1024.0 * 1024.0 * 1024.0),
+        _ => (value, 1.0 / 1024.0),
+    };
+    number.parse::<f64>().ok().map(|number| (number * scale).round() as u64)
+}
+
+struct Process {
+    pid: u32,
+    name: String,
+    /// Share of the whole device (all cores), 0-100.
+    cpu_percent: f64,
+    rss_kb: Option<u64>,
+}
+
+struct Sample {
+    timestamp_ms: u64,
+    device_cpu_percent: Option<f64>,
+    processes: Vec<Process>,
+}
+
+/// Splits `top -n` output into samples; each sample starts at its process header.
+struct TopParser<'a> {
+    filter: Option<&'a str>,
+    columns: Option<Columns>,
+    /// toybox reports `%CPU` per core and prints the total as `800%cpu`.
+    capacity: Option<f64>,
+    device_cpu: Option<f64>,
+    samples: Vec<Sample>,
+}
+
+impl TopParser<'_> {
+    fn line(&mut self, line: &str) {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            return;
+        }
+        if let Some(columns) = Columns::from_header(trimmed) {
+            self.columns = Some(columns);
+            self.samples.push(Sample {
+                timestamp_ms: now_millis(),
+                device_cpu_percent: self.device_cpu.take(),
+                processes: Vec::new(),
+            });
+            return;
+        }
+        if let Some(summary) = self.summary(trimmed) {
+            self.device_cpu = Some(summary);
+            return;
+        }
+        let (Some(columns), Some(sample)) = (&self.columns, self.samples.last_mut()) else {
+            return;
+        };
+        let fields: Vec<&str> = trimmed.split_whitespace().collect();
+        let Some(pid) = fields.get(columns.pid).and_then(|pid| pid.parse::<u32>().ok()) else {
+            return;
+        };
+        // toolbox leaves PCY blank for some processes; the name is then the last field.
+        let name = if fields.len() >= columns.width {
+            fields[columns.name.min(fields.len() - 1)..].join(" ")
+        } else {
+            fields.last().unwrap_or(&"").to_string()
+        };
+        if let Some(package) = self.filter {
+            let process = name.split_whitespace().next().unwrap_or_default();
+            if !matches_package(process, package) {
+                return;
+            }
+        }
+        let raw_cpu = fields
+            .get(columns.cpu)
+            .and_then(|cpu| cpu.trim_end_matches('%').parse::<f64>().ok())
+            .unwrap_or(0.0);
+        let cpu_percent = match self.capacity {
+            Some(capacity) if capacity > 0.0 => raw_cpu * 100.0 / capacity,
+            _ => raw_cpu,
+        };
+        sample.processes.push(Process {
+            pid,
+            name,
+            cpu_percent: (cpu_percent * 10.0).round() / 10.0,
+            rss_kb: fields.get(columns.rss).copied().and_then(size_kb),
+        });
+    }
+
+    /// Device-wide busy percentage from toybox `800%cpu 12%user ... 760%idle ...` or
+    /// toolbox `User 5%, System 3%, IOW 0%, IRQ 0%`.
+    fn summary(&mut self, line: &str) -> Option<f64> {
+        if line.starts_with("User ") && line.contains("System ") {
+            let busy: f64 = line
+                .split(',')
+                .filter_map(|part| part.split_whitespace().nth(1)?.trim_end_matches('%').parse::<f64>().ok())
+                .sum();
+            return Some(busy.min(100.0));
+        }
+        let percent = |suffix: &str| {
+            line.split_whitespace()
+                .find_map(|token| token.strip_suffix(suffix)?.parse::<f64>().ok())
+        };
+        let capacity = percent("%cpu")?;
+        self.capacity = Some(capacity);
+        let idle = percent("%idle")?;
+        (capacity > 0.0).then(|| ((capacity - idle) * 1000.0 / capacity).round() / 10.0)
+    }
+}
+
+fn sample_json(sample: &mut Sample, filtered: bool) -> JsonValue {
+    sample.processes.sort_by(|a, b| b.cpu_percent.total_cmp(&a.cpu_percent));
+    if !filtered {
+        sample.processes.truncate(MAX_UNFILTERED_PROCESSES);
+    }
+    let processes: Vec<JsonValue> = sample
+        .processes
+        .iter()
+        .map(|process| {
+            JsonValue::object(vec![
+                ("pid", process.pid.into()),
+                ("name", process.name.as_str().into()),
+                ("cpu_percent", process.cpu_percent.into()),
+                ("rss_kb", process.rss_kb.into()),
+            ])
+        })
+        .collect();
+    JsonValue::object(vec![
+        ("timestamp_ms", sample.timestamp_ms.into()),
+        ("device_cpu_percent", sample.device_cpu_percent.into()),
+        ("processes", processes.into()),
+    ])
+}
+
+fn top_sample(serial: &str, interval_ms: u32, count: u32, package: Option<&str>) -> Result<String, LbError> {
+    if interval_ms < MIN_INTERVAL_MS {
+        return Err(format!("Sampling interval must be at least {} ms", MIN_INTERVAL_MS).into());
+    }
+    if count == 0 || count > MAX_SAMPLES {
+        return Err(format!("Sample count must be between 1 and {}", MAX_SAMPLES).into());
+    }
+    if let Some(package) = package {
+        packages::validate_package(package)?;
+    }
+    // toolbox top has no -b or -o and rejects them, and takes whole seconds only.
+    let delay = format!("{}.{:03}", interval_ms / 1000, interval_ms % 1000);
+    let script = format!(
+        "top -b -d {} -n {} -o PID,%CPU,RES,ARGS 2>/dev/null || top -d {} -n {}",
+        delay,
+        count,
+        (interval_ms / 1000).max(1),
+        count
+    );
+    let argv = adb::adb_argv(Some(serial), &["shell", &script]);
+    let started = Instant::now();
+    let mut child = Command::new(&argv[0])
+        .args(&argv[1..])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| {
+            LbError::new(ErrorCode::SpawnFailed, format!("Failed to spawn {}: {}", argv[0], err))
+                .with_command(&argv)
+                .with_serial(serial)
+        })?;
+    let stderr = child.stderr.take().map(|mut stderr| {
+        thread::spawn(move || {
+            let mut text = String::new();
+            let _ = stderr.read_to_string(&mut text);
+            text
+        })
+    });
+    let stdout = child.stdout.take();
+    let id = NEXT_RUN_ID.fetch_add(1, Ordering::Relaxed);
+    run_registry()
+        .lock()
+        .map_err(|_| LbError::internal("Top registry poisoned"))?
+        .insert(id, child);
+    // Killed through the registry; once the child is taken out below it has closed its
+    // output and is exiting anyway.
+    let token = cancel::current();
+    let killed = Arc::new(AtomicBool::new(false));
+    let on_cancel = {
+        let killed = killed.clone();
+        token.on_cancel(move || {
+            if let Some(child) = run_registry().lock().ok().as_mut().and_then(|guard| guard.get_mut(&id)) {
+                killed.store(child.kill().is_ok(), Ordering::Relaxed);
+            }
+        })
+    };
+    let mut parser = TopParser {
+        filter: package,
+        columns: None,
+        capacity: None,
+        device_cpu: None,
+        samples: Vec::new(),
+    };
+    // Read as it arrives so each sample is stamped with when top printed it.
+    if let Some(stdout) = stdout {
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            parser.line(&line);
+        }
+    }
+    // Gone from the registry: `lb_shutdown` took the child and stops it.
+    let taken = run_registry().lock().ok().and_then(|mut guard| guard.remove(&id));
+    drop(on_cancel);
+    let Some(mut child) = taken else {
+        transcript::record_command(&argv, started.elapsed(), None);
+        return Err(LbError::new(ErrorCode::Cancelled, "top sampling was stopped by shutdown")
+            .with_serial(serial)
+            .with_command(&argv));
+    };
+    let status = child
+        .wait()
+        .map_err(|err| LbError::io(format!("Failed to wait for top: {}", err)))?;
+    transcript::record_command(&argv, started.elapsed(), status.code());
+    if killed.load(Ordering::Relaxed) {
+        let err = token.check().err().unwrap_or_else(|| LbError::new(ErrorCode::Cancelled, "Operation cancelled"));
+        return Err(err.with_serial(serial).with_command(&argv));
+    }
+    let errors = stderr.and_then(|reader| reader.join().ok()).unwrap_or_default();
+    if parser.samples.is_empty() {
+        let detail = errors.trim();
+        return Err(LbError::new(adb::classify_failure(detail), format!("top printed no samples: {}", detail))
+            .with_serial(serial)
+            .with_command(&argv));
+    }
+    lb_log!(Level::Debug, "exec", "Collected {} top samples from {}", parser.samples.len(), serial);
+    let samples: Vec<JsonValue> = parser
+        .samples
+        .iter_mut()
+        .map(|sample| sample_json(sample, package.is_some()))
+        .collect();
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("package", package.into()),
+        ("interval_ms", interval_ms.into()),
+        ("samples", samples.into()),
+    ])
+    .to_string())
+}
+
+/// Runs `top` for `count` samples `interval_ms` apart (at least 500; whole seconds on
+/// Android 7 and earlier) and returns `{serial, package, interval_ms, samples:
+/// [{timestamp_ms, device_cpu_percent, processes: [{pid, name, cpu_percent, rss_kb}]}]}`.
+/// CPU percentages are shares of the whole device on every top variant. With a
+/// `package_filter` only its processes are kept, otherwise the 30 busiest per sample.
+/// Blocks for the whole run.
+#[no_mangle]
+pub extern "C" fn lb_top_sample(
+    serial_ptr: *const c_char,
+    interval_ms: u32,
+    count: u32,
+    package_filter_ptr: *const c_char,
+) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let package = if package_filter_ptr.is_null() {
+            Ok(None)
+        } else {
+            read_c_str(package_filter_ptr, "package filter").map(Some)
+        };
+        let result = package.and_then(|package| {
+            read_c_str(serial_ptr, "serial").and_then(|serial| top_sample(serial, interval_ms, count, package))
+        });
+        string_result(result, "top samples")
+    })
+}
@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+use std::fs;
+use std::os::raw::c_char;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use crate::adb::shell_quote;
+use crate::error::LbError;
+use crate::history;
+use crate::json::JsonValue;
+use crate::{ffi_guard, now_millis, read_c_str, status_result};
+
+const MAX_TRANSCRIPT_ENTRIES: usize = 10_000;
+
+struct TranscriptEntry {
+    argv: Vec<String>,
+    started_at_ms: u64,
+    duration_ms: u64,
+    exit_code: Option<i32>,
+}
+
+#[derive(Default)]
+struct Transcript {
+    active: bool,
+    entries: Vec<TranscriptEntry>,
+    dropped: usize,
+}
+
+static TRANSCRIPTS: OnceLock<Mutex<HashMap<String, Transcript>>> = OnceLock::new();
+
+fn transcript_registry() -> &'static Mutex<HashMap<String, Transcript>> {
+    TRANSCRIPTS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Appends an executed command to every transcript that is currently capturing, and to
+/// the persistent command history. Secrets are redacted first (see `history::redacted`).
+pub fn record_command(argv: &[String], duration: Duration, exit_code: Option<i32>) {
+    let (argv, _) = history::redacted(argv);
+    record_redacted(&argv, duration, exit_code, None);
+}
+
+/// `record_command` for callers that captured the output, so history keeps its start.
+pub fn record_command_output(argv: &[String], duration: Duration, exit_code: Option<i32>, stdout: &str, stderr: &str) {
+    let (argv, hide_output) = history::redacted(argv);
+    let output = (!hide_output).then_some((stdout, stderr));
+    record_redacted(&argv, duration, exit_code, output);
+}
+
+/// `record_command` for callers that already ran `history::redacted` (to log the same
+/// line) and dropped the output it asked to hide.
+pub fn record_redacted(argv: &[String], duration: Duration, exit_code: Option<i32>, output: Option<(&str, &str)>) {
+    history::record(argv, duration, exit_code, output);
+    capture(argv, duration, exit_code);
+}
+
+fn capture(argv: &[String], duration: Duration, exit_code: Option<i32>) {
+    let Ok(mut guard) = transcript_registry().lock() else {
+        return;
+    };
+    let finished_at_ms = now_millis();
+    let duration_ms = duration.as_millis() as u64;
+    for transcript in guard.values_mut().filter(|transcript| transcript.active) {
+        if transcript.entries.len() >= MAX_TRANSCRIPT_ENTRIES {
+            transcript.dropped += 1;
+            continue;
+        }
+        transcript.entries.push(TranscriptEntry {
+            argv: argv.to_vec(),
+            started_at_ms: finished_at_ms.saturating_sub(duration_ms),
+            duration_ms,
+            exit_code,
+        });
+    }
+}
+
+fn render_script(session_id: &str, transcript: &Transcript) -> String {
+    let mut script = String::new();
+    script.push_str("#!/bin/sh\n");
+    script.push_str(&format!("# lazy_blacktea transcript for session {}\n", session_id));
+    script.push_str(&format!("# {} command(s)", transcript.entries.len()));
+    if transcript.dropped > 0 {
+        script.push_str(&format!(", {} dropped after the entry limit", transcript.dropped));
+    }
+    script.push_str("\n\n");
+    for entry in &transcript.entries {
+        let exit = entry
+            .exit_code
+            .map(|code| code.to_string())
+            .unwrap_or_else(|| "n/a".to_string());
+        script.push_str(&format!(
+            "# started_at_ms={} duration_ms={} exit={}\n",
+            entry.started_at_ms, entry.duration_ms, exit
+        ));
+        let quoted: Vec<String> = entry.argv.iter().map(|arg| shell_quote(arg)).collect();
+        script.push_str(&quoted.join(" "));
+        script.push('\n');
+    }
+    script
+}
+
+fn render_json(session_id: &str, transcript: &Transcript) -> JsonValue {
+    let commands = transcript
+        .entries
+        .iter()
+        .map(|entry| {
+            JsonValue::object(vec![
+                ("argv", entry.argv.iter().map(JsonValue::from).collect::<Vec<_>>().into()),
+                ("started_at_ms", entry.started_at_ms.into()),
+                ("duration_ms", entry.duration_ms.into()),
+                ("exit_code", entry.exit_code.into()),
+            ])
+        })
+        .collect::<Vec<_>>();
+    JsonValue::object(vec![
+        ("session_id", session_id.into()),
+        ("exported_at_ms", now_millis().into()),
+        ("dropped", transcript.dropped.into()),
+        ("commands", commands.into()),
+    ])
+}
+
+fn json_path_for(path: &Path) -> PathBuf {
+    let mut json_path = path.as_os_str().to_owned();
+    json_path.push(".json");
+    PathBuf::from(json_path)
+}
+
+#[cfg(unix)]
+fn mark_executable(path: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mut permissions = fs::metadata(path)?.permissions();
+    permissions.set_mode(permissions.mode() | 0o755);
+    fs::set_permissions(path, permissions)
+}
+
+#[cfg(not(unix))]
+fn mark_executable(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+fn export_transcript(session_id: &str, path: &str) -> Result<(), LbError> {
+    let guard = transcript_registry()
+        .lock()
+        .map_err(|_| LbError::internal("Transcript registry is unavailable"))?;
+    let transcript = guard
+        .get(session_id)
+        .ok_or_else(|| format!("Unknown transcript session: {}", session_id))?;
+
+    let script_path = Path::new(path);
+    fs::write(script_path, render_script(session_id, transcript))
+        .map_err(|err| LbError::io(format!("Failed to write transcript script: {}", err)))?;
+    mark_executable(script_path).map_err(|err| LbError::io(format!("Failed to mark transcript executable: {}", err)))?;
+    fs::write(json_path_for(script_path), render_json(session_id, transcript).to_string())
+        .map_err(|err| LbError::io(format!("Failed to write transcript JSON: {}", err)))
+}
+
+fn set_capturing(session_id: &str, active: bool) -> Result<(), LbError> {
+    let mut guard = transcript_registry()
+        .lock()
+        .map_err(|_| LbError::internal("Transcript registry is unavailable"))?;
+    if active {
+        guard.entry(session_id.to_string()).or_default().active = true;
+        return Ok(());
+    }
+    match guard.get_mut(session_id) {
+        Some(transcript) => {
+            transcript.active = false;
+            Ok(())
+        }
+        None => Err(LbError::not_found(format!("Unknown transcript session: {}", session_id))),
+    }
+}
+
+/// Starts (or resumes) capturing every command the library executes into `session_id`.
+#[no_mangle]
+pub extern "C" fn lb_transcript_start(session_id_ptr: *const c_char) -> i32 {
+    ffi_guard(0, || {
+        status_result(read_c_str(session_id_ptr, "session id").and_then(|id| set_capturing(id, true)))
+    })
+}
+
+/// Stops capturing; recorded commands stay available for export until discarded.
+#[no_mangle]
+pub extern "C" fn lb_transcript_stop(session_id_ptr: *const c_char) -> i32 {
+    ffi_guard(0, || {
+        status_result(read_c_str(session_id_ptr, "session id").and_then(|id| set_capturing(id, false)))
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn lb_transcript_discard(session_id_ptr: *const c_char) -> i32 {
+    ffi_guard(0, || {
+        let result = read_c_str(session_id_ptr, "session id").and_then(|id| {
+            transcript_registry()
+                .lock()
+                .map(|mut guard| {
+                    guard.remove(id);
+                })
+                .map_err(|_| LbError::internal("Transcript registry is unavailable"))
+        });
+        status_result(result)
+    })
+}
+
+/// Writes an executable shell script to `path` and its JSON twin to `<path>.json`.
+#[no_mangle]
+pub extern "C" fn lb_export_transcript(session_id_ptr: *const c_char, path_ptr: *const c_char) -> i32 {
+    ffi_guard(0, || {
+        let result = read_c_str(session_id_ptr, "session id")
+            .and_then(|id| read_c_str(path_ptr, "transcript path").and_then(|path| export_transcript(id, path)));
+        status_result(result)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exports_without_pairing_code() {
+        let session = "redaction-test";
+        set_capturing(session, true).unwrap();
+        let argv: Vec<String> = ["adb", "pair", "192.168.1.5:37000", "482913"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        record_command_output(&argv, Duration::from_millis(5), Some(0), "Successfully paired", "");
+        let path = std::env::temp_dir().join(format!("lb-transcript-{}.sh", std::process::id()));
+        export_transcript(session, path.to_str().unwrap()).unwrap();
+        let script = fs::read_to_string(&path).unwrap();
+        let json = fs::read_to_string(json_path_for(&path)).unwrap();
+        let _ = fs::remove_file(json_path_for(&path));
+        let _ = fs::remove_file(&path);
+        transcript_registry().lock().unwrap().remove(session);
+        assert!(script.contains("adb pair 192.168.1.5:37000"));
+        assert!(!script.contains("482913"));
+        assert!(!json.contains("482913"));
+    }
+}
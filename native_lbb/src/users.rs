@@ -0,0 +1,151 @@
+use std::os::raw::c_char;
+
+use crate::adb;
+use crate::error::{ErrorCode, LbError};
+use crate::json::JsonValue;
+use crate::{ffi_guard, read_c_str, string_result};
+
+const CURRENT_MARKER: &str = "__LB_CURRENT_USER__";
+
+/// `UserInfo` flag bits.
+const FLAG_PRIMARY: u32 = 0x1;
+const FLAG_ADMIN: u32 = 0x2;
+const FLAG_GUEST: u32 = 0x4;
+const FLAG_MANAGED_PROFILE: u32 = 0x20;
+const FLAG_DISABLED: u32 = 0x40;
+const FLAG_QUIET_MODE: u32 = 0x80;
+/// Android 11+; set on every kind of profile, not only work profiles.
+const FLAG_PROFILE: u32 = 0x1000;
+
+pub struct UserInfo {
+    pub id: u32,
+    pub name: String,
+    pub flags: u32,
+    pub running: bool,
+}
+
+impl UserInfo {
+    pub fn is_profile(&self) -> bool {
+        self.flags & (FLAG_MANAGED_PROFILE | FLAG_PROFILE) != 0
+    }
+
+    fn to_json(&self, current: Option<u32>) -> JsonValue {
+        let flag = |bit: u32| JsonValue::from(self.flags & bit != 0);
+        JsonValue::object(vec![
+            ("id", self.id.into()),
+            ("name", self.name.as_str().into()),
+            ("flags", self.flags.into()),
+            ("running", self.running.into()),
+            ("current", (current == Some(self.id)).into()),
+            ("primary", flag(FLAG_PRIMARY)),
+            ("admin", flag(FLAG_ADMIN)),
+            ("guest", flag(FLAG_GUEST)),
+            ("managed_profile", flag(FLAG_MANAGED_PROFILE)),
+            ("profile", self.is_profile().into()),
+            ("disabled", flag(FLAG_DISABLED)),
+            ("quiet_mode", flag(FLAG_QUIET_MODE)),
+        ])
+    }
+}
+
+/// `UserInfo{10:Work profile:1030} running` lines of `pm list users`; flags are hex and the
+/// name may itself contain colons.
+fn parse_users(output: &str) -> Vec<UserInfo> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            let (info, rest) = line.strip_prefix("UserInfo{")?.split_once('}')?;
+            let (id, info) = info.split_once(':')?;
+            let (name, flags) = info.rsplit_once(':')?;
+            Some(UserInfo {
+                id: id.parse().ok()?,
+                name: name.to_string(),
+                flags: u32::from_str_radix(flags, 16).unwrap_or(0),
+                running: rest.split_whitespace().any(|word| word == "running"),
+            })
+        })
+        .collect()
+}
+
+/// Users from `pm list users` and the foreground user from `am get-current-user` (absent
+/// before Android 8).
+pub fn list_users(serial: &str) -> Result<(Vec<UserInfo>, Option<u32>), LbError> {
+    let output = adb::shell(
+        serial,
+        &format!("pm list users; echo {}; am get-current-user 2>/dev/null; true", CURRENT_MARKER),
+    )?;
+    let (listing, current) = output.split_once(CURRENT_MARKER).unwrap_or((&output, ""));
+    let users = parse_users(listing);
+    if users.is_empty() {
+        return Err(LbError::parse(format!("pm list users listed no users: {}", listing.trim())).with_serial(serial));
+    }
+    Ok((users, current.trim().parse().ok()))
+}
+
+/// A user id from an FFI argument; negative means "the default user" and adds no `--user`.
+pub fn user_from_ffi(user: i32) -> Option<u32> {
+    u32::try_from(user).ok()
+}
+
+/// ` --user N` for pm/am command lines, or nothing for the default user.
+pub fn user_args(user: Option<u32>) -> String {
+    user.map(|user| format!(" --user {}", user)).unwrap_or_default()
+}
+
+/// Fails with NotFound unless `user` exists on the device.
+pub fn require_user(serial: &str, user: u32) -> Result<(), LbError> {
+    let (users, _) = list_users(serial)?;
+    users
+        .iter()
+        .any(|info| info.id == user)
+        .then_some(())
+        .ok_or_else(|| LbError::not_found(format!("User {} does not exist on {}", user, serial)).with_serial(serial))
+}
+
+/// Screens only show the foreground user and its running profiles, so capturing "as" any
+/// other user would silently return someone else's screen.
+pub fn require_visible_user(serial: &str, user: u32) -> Result<(), LbError> {
+    let (users, current) = list_users(serial)?;
+    let info = users
+        .iter()
+        .find(|info| info.id == user)
+        .ok_or_else(|| LbError::not_found(format!("User {} does not exist on {}", user, serial)).with_serial(serial))?;
+    let visible = current.map_or(user == 0, |current| current == user) || (info.is_profile() && info.running);
+    if visible {
+        Ok(())
+    } else {
+        Err(LbError::new(
+            ErrorCode::InvalidArgument,
+            format!(
+                "User {} is not on screen (current user {}); switch with `am switch-user {}` first",
+                user,
+                current.map_or("unknown".to_string(), |current| current.to_string()),
+                user
+            ),
+        )
+        .with_serial(serial))
+    }
+}
+
+fn users_json(serial: &str) -> Result<String, LbError> {
+    let (users, current) = list_users(serial)?;
+    let users: Vec<JsonValue> = users.iter().map(|info| info.to_json(current)).collect();
+    Ok(JsonValue::object(vec![
+        ("serial", serial.into()),
+        ("current_user", current.into()),
+        ("users", users.into()),
+    ])
+    .to_string())
+}
+
+/// Users and profiles on the device: `{serial, current_user, users: [{id, name, flags,
+/// running, current, primary, admin, guest, managed_profile, profile, disabled,
+/// quiet_mode}]}`. Work profiles are `managed_profile`; `current_user` is null before
+/// Android 8. Pass a user's `id` to the `_for_user` APIs and the `user` install/launch option.
+#[no_mangle]
+pub extern "C" fn lb_list_users(serial_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        string_result(read_c_str(serial_ptr, "serial").and_then(users_json), "user list")
+    })
+}
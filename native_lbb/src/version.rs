@@ -0,0 +1,131 @@
+use std::os::raw::c_char;
+
+use crate::json::JsonValue;
+use crate::{ffi_guard, string_result};
+
+/// Subsystems compiled into this library. Append a name whenever a module adds exports
+/// the host may want to gate on; names are never removed or renamed.
+const CAPABILITIES: [&str; 82] = [
+    "log-callback",
+    "file-logging",
+    "hierarchy-render",
+    "hierarchy-audit",
+    "view-hierarchy-protobuf",
+    "parallel-commands",
+    "screen-record",
+    "streams",
+    "kernel-log",
+    "lmk-watch",
+    "device-lock",
+    "transcript",
+    "fleet-reboot",
+    "cpu-control",
+    "benchmark-mode",
+    "failure-capture",
+    "battery-health",
+    "input-gesture",
+    "ime",
+    "dumpsys",
+    "presets",
+    "adb-server",
+    "shell-session",
+    "structured-commands",
+    "command-stream",
+    "retry-policy",
+    "device-batch",
+    "apk-install",
+    "packages",
+    "apk-pull",
+    "frame-metrics",
+    "health-monitor",
+    "perfetto",
+    "input-basic",
+    "input-macro",
+    "clipboard",
+    "wireless-adb",
+    "reboot",
+    "adb-root",
+    "fastboot",
+    "sideload",
+    "remote-fs",
+    "dir-transfer",
+    "checksum",
+    "screenshot-burst",
+    "screenshot-thumbnail",
+    "multi-capture",
+    "inspection-bundle",
+    "display-info",
+    "activity-stack",
+    "app-launch",
+    "intents",
+    "instrumentation",
+    "monkey",
+    "crash-watch",
+    "crash-collection",
+    "batterystats",
+    "thermal",
+    "top-sampler",
+    "network-stats",
+    "settings",
+    "dev-options",
+    "demo-mode",
+    "device-config",
+    "permissions",
+    "users",
+    "doze",
+    "backup",
+    "obb",
+    "emulator",
+    "avd",
+    "scheduler",
+    "history",
+    "device-meta",
+    "device-groups",
+    "rpc",
+    "control-server",
+    "script",
+    "cancellation",
+    "shutdown",
+    "recording-reconcile",
+    "recording-status",
+];
+
+/// Subsystems behind cargo features, reported only when compiled in.
+const OPTIONAL_CAPABILITIES: [(&str, bool); 3] = [
+    ("grpc", cfg!(feature = "grpc")),
+    ("python", cfg!(feature = "python")),
+    ("async-exec", cfg!(feature = "async-exec")),
+];
+
+pub fn version_json() -> JsonValue {
+    JsonValue::object(vec![
+        ("version", env!("CARGO_PKG_VERSION").into()),
+        ("git_hash", env!("LB_GIT_HASH").into()),
+        ("profile", env!("LB_BUILD_PROFILE").into()),
+    ])
+}
+
+pub fn capabilities() -> Vec<&'static str> {
+    let optional = OPTIONAL_CAPABILITIES
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| *name);
+    CAPABILITIES.iter().copied().chain(optional).collect()
+}
+
+/// Returns `{"version", "git_hash", "profile"}`: the crate semver, the short commit the
+/// library was built from (`unknown` outside a git checkout), and `debug` or `release`.
+#[no_mangle]
+pub extern "C" fn lb_version() -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || string_result(Ok(version_json().to_string()), "version"))
+}
+
+/// Returns a JSON array of the subsystem names compiled into this library, so hosts can
+/// hide features an older library lacks.
+#[no_mangle]
+pub extern "C" fn lb_capabilities() -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let names: Vec<JsonValue> = capabilities().into_iter().map(JsonValue::from).collect();
+        string_result(Ok(JsonValue::from(names).to_string()), "capabilities")
+    })
+}
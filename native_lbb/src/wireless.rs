@@ -0,0 +1,228 @@
+use std::fs;
+use std::os::raw::c_char;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::adb;
+use crate::error::LbError;
+use crate::json::{self, JsonValue};
+use crate::logging::{lb_log, Level};
+use crate::presets;
+use crate::{ffi_guard, now_millis, read_c_str, status_result, string_result};
+
+const ENDPOINTS_FILE: &str = "wireless_endpoints.json";
+
+/// Serializes read-modify-write of the endpoints file between host threads.
+static ENDPOINTS_LOCK: Mutex<()> = Mutex::new(());
+
+/// Known adb output fragments and the reason reported for them, checked in order.
+const CONNECT_REASONS: [(&str, &str); 7] = [
+    ("failed to authenticate", "unauthorized"),
+    ("Connection refused", "refused"),
+    ("No route to host", "unreachable"),
+    ("Network is unreachable", "unreachable"),
+    ("timed out", "timeout"),
+    ("unknown host", "unresolved"),
+    ("Name or service not known", "unresolved"),
+];
+const PAIR_REASONS: [(&str, &str); 4] = [
+    ("Wrong password", "wrong_code"),
+    ("Unable to start pairing client", "unreachable"),
+    ("unknown command", "unsupported"),
+    ("timed out", "timeout"),
+];
+
+/// `host:port`, where the host may be a bracketed IPv6 address.
+fn validate_host_port(host_port: &str) -> Result<(), LbError> {
+    let valid = host_port
+        .rsplit_once(':')
+        .filter(|(host, _)| !host.is_empty() && !host.contains(char::is_whitespace))
+        .and_then(|(_, port)| port.parse::<u16>().ok())
+        .is_some_and(|port| port != 0);
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("Expected host:port, got '{}'", host_port).into())
+    }
+}
+
+fn failure_reason(output: &str, reasons: &[(&str, &'static str)]) -> &'static str {
+    reasons
+        .iter()
+        .find(|(fragment, _)| output.contains(fragment))
+        .map_or("other", |(_, reason)| *reason)
+}
+
+/// `adb connect` / `pair` / `disconnect` exit 0 on many failures, so results come from the text.
+fn run_host_command(args: &[&str]) -> Result<String, LbError> {
+    let output = adb::run_adb(None, args)?;
+    Ok(format!("{}\n{}", output.stdout.trim(), output.stderr.trim()).trim().to_string())
+}
+
+fn outcome(host_port: &str, success: bool, reason: Option<&str>, message: &str, extra: Vec<(&str, JsonValue)>) -> String {
+    let mut fields = vec![
+        ("host_port", host_port.into()),
+        ("success", success.into()),
+        ("reason", reason.into()),
+        ("message", message.into()),
+    ];
+    fields.extend(extra);
+    JsonValue::object(fields).to_string()
+}
+
+fn pair(host_port: &str, code: &str) -> Result<String, LbError> {
+    validate_host_port(host_port)?;
+    if code.len() != 6 || !code.chars().all(|ch| ch.is_ascii_digit()) {
+        return Err("Pairing code must be the 6 digits shown on the device".into());
+    }
+    let output = run_host_command(&["pair", host_port, code])?;
+    // `Successfully paired to 192.168.1.5:37123 [guid=adb-R5CT-abc]`
+    let success = output.contains("Successfully paired");
+    let guid = output
+        .split_once("[guid=")
+        .and_then(|(_, rest)| rest.split_once(']'))
+        .map(|(guid, _)| guid.to_string());
+    lb_log!(Level::Info, "adb", "Pairing with {}: {}", host_port, output);
+    let reason = (!success).then(|| failure_reason(&output, &PAIR_REASONS));
+    Ok(outcome(host_port, success, reason, &output, vec![("guid", guid.into())]))
+}
+
+fn connect(host_port: &str) -> Result<String, LbError> {
+    validate_host_port(host_port)?;
+    let output = run_host_command(&["connect", host_port])?;
+    let already = output.contains("already connected to");
+    let success = output.contains("connected to");
+    lb_log!(Level::Info, "adb", "Connecting to {}: {}", host_port, output);
+    if success {
+        // The device is connected either way; a history write failure only costs the shortcut.
+        if let Err(err) = remember_endpoint(host_port) {
+            lb_log!(Level::Warn, "adb", "Could not save wireless endpoint {}: {}", host_port, err.message);
+        }
+    }
+    let reason = (!success).then(|| failure_reason(&output, &CONNECT_REASONS));
+    Ok(outcome(host_port, success, reason, &output, vec![("already_connected", already.into())]))
+}
+
+fn disconnect(host_port: &str) -> Result<String, LbError> {
+    validate_host_port(host_port)?;
+    let output = run_host_command(&["disconnect", host_port])?;
+    let success = output.starts_with("disconnected");
+    let reason = (!success).then_some(if output.contains("no such device") { "not_connected" } else { "other" });
+    lb_log!(Level::Info, "adb", "Disconnecting {}: {}", host_port, output);
+    Ok(outcome(host_port, success, reason, &output, Vec::new()))
+}
+
+fn endpoints_path() -> Result<PathBuf, LbError> {
+    Ok(presets::presets_dir()?.join(ENDPOINTS_FILE))
+}
+
+/// Entries as stored; an unreadable file is logged and treated as empty so reconnect
+/// history can never block a connect.
+fn load_endpoints(path: &Path) -> Vec<JsonValue> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(_) => return Vec::new(),
+    };
+    match json::parse(&text) {
+        Ok(JsonValue::Array(items)) => items
+            .into_iter()
+            .filter(|item| item.get("host_port").and_then(JsonValue::as_str).is_some())
+            .collect(),
+        _ => {
+            lb_log!(Level::Warn, "adb", "Ignoring unreadable {}", path.display());
+            Vec::new()
+        }
+    }
+}
+
+fn save_endpoints(path: &Path, endpoints: Vec<JsonValue>) -> Result<(), LbError> {
+    let dir = path.parent().ok_or_else(|| LbError::internal("Endpoints file has no directory"))?;
+    fs::create_dir_all(dir).map_err(|err| LbError::io(format!("Failed to create {}: {}", dir.display(), err)))?;
+    let staging = dir.join(format!(".{}.tmp", ENDPOINTS_FILE));
+    fs::write(&staging, JsonValue::from(endpoints).to_string())
+        .and_then(|_| fs::rename(&staging, path))
+        .map_err(|err| LbError::io(format!("Failed to write {}: {}", path.display(), err)))
+}
+
+fn remember_endpoint(host_port: &str) -> Result<(), LbError> {
+    let _guard = ENDPOINTS_LOCK.lock().map_err(|_| LbError::internal("Endpoints lock poisoned"))?;
+    let path = endpoints_path()?;
+    let mut endpoints = load_endpoints(&path);
+    let connect_count = endpoints
+        .iter()
+        .find(|item| item.get("host_port").and_then(JsonValue::as_str) == Some(host_port))
+        .and_then(|item| item.get("connect_count").and_then(JsonValue::as_u64))
+        .unwrap_or(0);
+    endpoints.retain(|item| item.get("host_port").and_then(JsonValue::as_str) != Some(host_port));
+    // Most recent first, which is the order the host offers reconnects in.
+    endpoints.insert(
+        0,
+        JsonValue::object(vec![
+            ("host_port", host_port.into()),
+            ("last_connected_ms", now_millis().into()),
+            ("connect_count", (connect_count + 1).into()),
+        ]),
+    );
+    save_endpoints(&path, endpoints)
+}
+
+fn forget_endpoint(host_port: &str) -> Result<(), LbError> {
+    let _guard = ENDPOINTS_LOCK.lock().map_err(|_| LbError::internal("Endpoints lock poisoned"))?;
+    let path = endpoints_path()?;
+    let mut endpoints = load_endpoints(&path);
+    let before = endpoints.len();
+    endpoints.retain(|item| item.get("host_port").and_then(JsonValue::as_str) != Some(host_port));
+    if endpoints.len() == before {
+        return Err(LbError::not_found(format!("No saved wireless endpoint {}", host_port)));
+    }
+    save_endpoints(&path, endpoints)
+}
+
+fn list_endpoints() -> Result<String, LbError> {
+    let _guard = ENDPOINTS_LOCK.lock().map_err(|_| LbError::internal("Endpoints lock poisoned"))?;
+    Ok(JsonValue::from(load_endpoints(&endpoints_path()?)).to_string())
+}
+
+/// Runs `adb pair host:port code` against the device's "Pair device with pairing code"
+/// endpoint. Returns `{host_port, success, reason, message, guid}`; `reason` is
+/// `wrong_code`, `unreachable`, `timeout`, `unsupported` (adb older than 30) or `other`.
+#[no_mangle]
+pub extern "C" fn lb_adb_pair(host_port_ptr: *const c_char, code_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        let result = read_c_str(host_port_ptr, "host_port")
+            .and_then(|host_port| read_c_str(code_ptr, "pairing code").and_then(|code| pair(host_port, code)));
+        string_result(result, "pair result")
+    })
+}
+
+/// Runs `adb connect host:port` and returns `{host_port, success, already_connected, reason,
+/// message}`; `reason` is `unauthorized` (pair first), `refused`, `unreachable`, `timeout`,
+/// `unresolved` or `other`. Successful endpoints are saved for `lb_wireless_endpoints`.
+#[no_mangle]
+pub extern "C" fn lb_adb_connect(host_port_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        string_result(read_c_str(host_port_ptr, "host_port").and_then(connect), "connect result")
+    })
+}
+
+/// Runs `adb disconnect host:port`; `{host_port, success, reason, message}` with reason
+/// `not_connected` or `other`. The endpoint stays saved.
+#[no_mangle]
+pub extern "C" fn lb_adb_disconnect(host_port_ptr: *const c_char) -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || {
+        string_result(read_c_str(host_port_ptr, "host_port").and_then(disconnect), "disconnect result")
+    })
+}
+
+/// Previously connected endpoints, most recent first: `[{host_port, last_connected_ms,
+/// connect_count}]`, stored as `wireless_endpoints.json` in the presets directory.
+#[no_mangle]
+pub extern "C" fn lb_wireless_endpoints() -> *mut c_char {
+    ffi_guard(std::ptr::null_mut(), || string_result(list_endpoints(), "wireless endpoints"))
+}
+
+/// Drops a saved endpoint; an unknown one is a NotFound error.
+#[no_mangle]
+pub extern "C" fn lb_forget_wireless_endpoint(host_port_ptr: *const c_char) -> i32 {
+    ffi_guard(0, || status_result(read_c_str(host_port_ptr, "host_port").and_then(forget_endpoint)))
+}
@@ -0,0 +1,407 @@
+//! The block-style YAML subset scripts are written in: nested mappings and `- ` sequences by
+//! indentation, plain/quoted scalars, `#` comments. Flow collections (`[...]`, `{...}`) fit on
+//! one line and take plain or quoted items (`[1, two, "three"]`, `{a: b}`), so JSON reads as
+//! is; anchors, tags, block scalars (`|`, `>`) and multi-document files are not supported and
+//! fail to parse rather than being misread.
+
+use crate::error::LbError;
+use crate::json::{self, JsonValue};
+
+struct Line {
+    number: usize,
+    indent: usize,
+    text: String,
+}
+
+/// Cuts a trailing `# comment` that is not inside quotes.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut previous = ' ';
+    for (index, ch) in line.char_indices() {
+        match quote {
+            Some(open) if ch == open => quote = None,
+            Some(_) => {}
+            None if ch == '"' || ch == '\'' => quote = Some(ch),
+            None if ch == '#' && previous.is_whitespace() => return &line[..index],
+            None => {}
+        }
+        previous = ch;
+    }
+    line
+}
+
+fn lines(source: &str) -> Result<Vec<Line>, LbError> {
+    let mut lines = Vec::new();
+    for (index, raw) in source.lines().enumerate() {
+        let number = index + 1;
+        let text = strip_comment(raw).trim_end();
+        if text.trim().is_empty() || text == "---" {
+            continue;
+        }
+        if text.starts_with('\t') || text.trim_start_matches(' ').starts_with('\t') {
+            return Err(LbError::parse(format!("YAML line {}: tabs are not allowed for indentation", number)));
+        }
+        let indent = text.len() - text.trim_start().len();
+        lines.push(Line {
+            number,
+            indent,
+            text: text.trim_start().to_string(),
+        });
+    }
+    Ok(lines)
+}
+
+/// `key: value` or `key:` split at the first `:` followed by a space or the line end,
+/// outside quotes.
+fn split_key(text: &str) -> Option<(String, &str)> {
+    if text.starts_with('{') || text.starts_with('[') {
+        return None;
+    }
+    if let Some(quote) = text.chars().next().filter(|ch| *ch == '"' || *ch == '\'') {
+        let close = text[1..].find(quote)? + 1;
+        let rest = text[close + 1..].strip_prefix(':')?;
+        if !(rest.is_empty() || rest.starts_with(' ')) {
+            return None;
+        }
+        return Some((text[1..close].to_string(), rest.trim()));
+    }
+    let bytes = text.as_bytes();
+    let position = (0..bytes.len()).find(|&index| bytes[index] == b':' && bytes.get(index + 1).is_none_or(|next| *next == b' '))?;
+    Some((text[..position].trim_end().to_string(), text[position + 1..].trim()))
+}
+
+fn is_sequence_item(text: &str) -> bool {
+    text == "-" || text.starts_with("- ")
+}
+
+fn scalar(text: &str, number: usize) -> Result<JsonValue, LbError> {
+    let context = || format!("YAML line {}", number);
+    match text {
+        "" | "~" | "null" | "Null" | "NULL" => return Ok(JsonValue::Null),
+        "true" | "True" | "TRUE" => return Ok(JsonValue::Bool(true)),
+        "false" | "False" | "FALSE" => return Ok(JsonValue::Bool(false)),
+        _ => {}
+    }
+    if text.starts_with('|') || text.starts_with('>') {
+        return Err(LbError::parse(format!("{}: block scalars are not supported", context())));
+    }
+    if text.starts_with('&') || text.starts_with('*') || text.starts_with('!') {
+        return Err(LbError::parse(format!("{}: anchors, aliases and tags are not supported", context())));
+    }
+    if text.starts_with('[') || text.starts_with('{') {
+        let mut flow = Flow { text, position: 0, number };
+        let value = flow.value()?;
+        flow.skip_spaces();
+        if flow.position < text.len() {
+            return Err(flow.error("unexpected text after the flow collection"));
+        }
+        return Ok(value);
+    }
+    if text.starts_with('"') {
+        return json::parse(text).map_err(|err| err.context(&context()));
+    }
+    if let Some(inner) = text.strip_prefix('\'') {
+        let inner = inner
+            .strip_suffix('\'')
+            .ok_or_else(|| LbError::parse(format!("{}: unterminated quoted string", context())))?;
+        return Ok(JsonValue::String(inner.replace("''", "'")));
+    }
+    let numeric = text.starts_with(|ch: char| ch.is_ascii_digit() || ch == '-' || ch == '.');
+    match json::parse(text) {
+        Ok(number @ JsonValue::Number(_)) if numeric => Ok(number),
+        _ => Ok(JsonValue::String(text.to_string())),
+    }
+}
+
+/// A one-line flow collection. Plain items end at `,`, `]` or `}`, and plain keys at `: `;
+/// each item is then read like a block scalar.
+struct Flow<'a> {
+    text: &'a str,
+    position: usize,
+    number: usize,
+}
+
+impl<'a> Flow<'a> {
+    fn error(&self, message: &str) -> LbError {
+        LbError::parse(format!("YAML line {}: {} at column {}", self.number, message, self.position + 1))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.position..].chars().next()
+    }
+
+    fn skip_spaces(&mut self) {
+        let rest = &self.text[self.position..];
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn value(&mut self) -> Result<JsonValue, LbError> {
+        self.skip_spaces();
+        match self.peek() {
+            Some('[') => self.sequence(),
+            Some('{') => self.mapping(),
+            Some('"') | Some('\'') => self.quoted(),
+            _ => {
+                let item = self.plain(&[',', ']', '}'])?;
+                scalar(item, self.number)
+            }
+        }
+    }
+
+    /// A quoted string, escapes and doubled `''` kept for `scalar` to decode.
+    fn quoted(&mut self) -> Result<JsonValue, LbError> {
+        let rest = &self.text[self.position..];
+        let bytes = rest.as_bytes();
+        let quote = bytes[0];
+        let mut index = 1;
+        loop {
+            match bytes.get(index) {
+                None => return Err(self.error("unterminated quoted string")),
+                Some(b'\\') if quote == b'"' => index += 2,
+                Some(b'\'') if quote == b'\'' && bytes.get(index + 1) == Some(&b'\'') => index += 2,
+                Some(&byte) if byte == quote => break,
+                Some(_) => index += 1,
+            }
+        }
+        self.position += index + 1;
+        scalar(&rest[..index + 1], self.number)
+    }
+
+    fn plain(&mut self, ends: &[char]) -> Result<&'a str, LbError> {
+        let rest: &'a str = &self.text[self.position..];
+        let length = rest.find(|ch| ends.contains(&ch)).unwrap_or(rest.len());
+        let item = rest[..length].trim();
+        if item.is_empty() {
+            return Err(self.error("expected a value"));
+        }
+        self.position += length;
+        Ok(item)
+    }
+
+    /// After an item: `,` (a trailing one is allowed) or the closing bracket.
+    fn next_item(&mut self, close: char) -> Result<bool, LbError> {
+        self.skip_spaces();
+        match self.peek() {
+            Some(',') => {
+                self.position += 1;
+                self.skip_spaces();
+                if self.peek() == Some(close) {
+                    self.position += 1;
+                    return Ok(false);
+                }
+                Ok(true)
+            }
+            Some(ch) if ch == close => {
+                self.position += 1;
+                Ok(false)
+            }
+            _ => Err(self.error(&format!("expected ',' or '{}'", close))),
+        }
+    }
+
+    fn sequence(&mut self) -> Result<JsonValue, LbError> {
+        self.position += 1;
+        self.skip_spaces();
+        let mut items = Vec::new();
+        if self.peek() == Some(']') {
+            self.position += 1;
+            return Ok(JsonValue::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            if !self.next_item(']')? {
+                return Ok(JsonValue::Array(items));
+            }
+        }
+    }
+
+    fn mapping(&mut self) -> Result<JsonValue, LbError> {
+        self.position += 1;
+        self.skip_spaces();
+        let mut pairs = Vec::new();
+        if self.peek() == Some('}') {
+            self.position += 1;
+            return Ok(JsonValue::Object(pairs));
+        }
+        loop {
+            let key = match self.peek() {
+                Some('"') | Some('\'') => match self.quoted()? {
+                    JsonValue::String(key) => key,
+                    _ => return Err(self.error("expected a key")),
+                },
+                _ => {
+                    let rest = &self.text[self.position..];
+                    let length = rest
+                        .match_indices(':')
+                        .map(|(index, _)| index)
+                        .find(|&index| rest[index + 1..].starts_with([' ', ',', '}']) || index + 1 == rest.len())
+                        .filter(|&index| !rest[..index].contains([',', '}']))
+                        .ok_or_else(|| self.error("expected 'key: value'"))?;
+                    self.position += length;
+                    rest[..length].trim().to_string()
+                }
+            };
+            self.skip_spaces();
+            if self.peek() != Some(':') {
+                return Err(self.error("expected ':' after the key"));
+            }
+            self.position += 1;
+            self.skip_spaces();
+            let value = if matches!(self.peek(), Some(',') | Some('}')) {
+                JsonValue::Null
+            } else {
+                self.value()?
+            };
+            pairs.push((key, value));
+            if !self.next_item('}')? {
+                return Ok(JsonValue::Object(pairs));
+            }
+        }
+    }
+}
+
+struct Parser {
+    lines: Vec<Line>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Line> {
+        self.lines.get(self.position)
+    }
+
+    /// The node whose lines start at the current position with exactly `indent` spaces.
+    fn block(&mut self, indent: usize) -> Result<JsonValue, LbError> {
+        match self.peek() {
+            Some(line) if is_sequence_item(&line.text) => self.sequence(indent),
+            Some(_) => self.mapping(indent),
+            None => Ok(JsonValue::Null),
+        }
+    }
+
+    /// The value after `key:` or `-` with nothing on the same line: a deeper block, a
+    /// sequence at the key's own indent (`key:\n- item`), or null.
+    fn nested(&mut self, indent: usize, allow_same_indent_sequence: bool) -> Result<JsonValue, LbError> {
+        match self.peek() {
+            Some(next) if next.indent > indent => {
+                let child = next.indent;
+                self.block(child)
+            }
+            Some(next) if allow_same_indent_sequence && next.indent == indent && is_sequence_item(&next.text) => {
+                self.sequence(indent)
+            }
+            _ => Ok(JsonValue::Null),
+        }
+    }
+
+    fn sequence(&mut self, indent: usize) -> Result<JsonValue, LbError> {
+        let mut items = Vec::new();
+        while let Some(line) = self.peek() {
+            if line.indent < indent || (line.indent == indent && !is_sequence_item(&line.text)) {
+                break;
+            }
+            if line.indent > indent {
+                return Err(LbError::parse(format!("YAML line {}: unexpected indentation", line.number)));
+            }
+            let rest = line.text[1..].trim_start().to_string();
+            let number = line.number;
+            if rest.is_empty() {
+                self.position += 1;
+                items.push(self.nested(indent, false)?);
+            } else if is_sequence_item(&rest) || split_key(&rest).is_some() {
+                // `- key: value` / `- - item`: the item is a block starting at the text's column.
+                let column = indent + (line.text.len() - rest.len());
+                self.lines[self.position] = Line {
+                    number,
+                    indent: column,
+                    text: rest,
+                };
+                items.push(self.block(column)?);
+            } else {
+                self.position += 1;
+                items.push(scalar(&rest, number)?);
+            }
+        }
+        Ok(JsonValue::Array(items))
+    }
+
+    fn mapping(&mut self, indent: usize) -> Result<JsonValue, LbError> {
+        let mut pairs: Vec<(String, JsonValue)> = Vec::new();
+        while let Some(line) = self.peek() {
+            if line.indent < indent {
+                break;
+            }
+            let number = line.number;
+            if line.indent > indent {
+                return Err(LbError::parse(format!("YAML line {}: unexpected indentation", number)));
+            }
+            if is_sequence_item(&line.text) {
+                break;
+            }
+            let (key, value) = split_key(&line.text)
+                .ok_or_else(|| LbError::parse(format!("YAML line {}: expected 'key: value'", number)))?;
+            let value = value.to_string();
+            if pairs.iter().any(|(existing, _)| *existing == key) {
+                return Err(LbError::parse(format!("YAML line {}: duplicate key '{}'", number, key)));
+            }
+            self.position += 1;
+            let value = if value.is_empty() {
+                self.nested(indent, true)?
+            } else {
+                scalar(&value, number)?
+            };
+            pairs.push((key, value));
+        }
+        Ok(JsonValue::Object(pairs))
+    }
+}
+
+pub fn parse(source: &str) -> Result<JsonValue, LbError> {
+    let lines = lines(source)?;
+    let Some(first) = lines.first() else {
+        return Ok(JsonValue::Null);
+    };
+    let indent = first.indent;
+    let mut parser = Parser { lines, position: 0 };
+    let value = if parser.lines.len() == 1 && !is_sequence_item(&parser.lines[0].text) && split_key(&parser.lines[0].text).is_none() {
+        parser.position = 1;
+        scalar(&parser.lines[0].text, parser.lines[0].number)?
+    } else {
+        parser.block(indent)?
+    };
+    if let Some(line) = parser.peek() {
+        return Err(LbError::parse(format!("YAML line {}: unexpected indentation", line.number)));
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn json(source: &str) -> String {
+        parse(source).unwrap().to_string()
+    }
+
+    #[test]
+    fn flow_plain_scalars() {
+        assert_eq!(json("c: [1, 2, three]"), r#"{"c":[1,2,"three"]}"#);
+        assert_eq!(json("m: {a: b, n: 1.5, off: false, none: }"), r#"{"m":{"a":"b","n":1.5,"off":false,"none":null}}"#);
+        assert_eq!(json("u: [http://host:8080/x, {k: [a, b]},]"), r#"{"u":["http://host:8080/x",{"k":["a","b"]}]}"#);
+    }
+
+    #[test]
+    fn flow_quoted_and_json() {
+        assert_eq!(json(r#"q: ["a, b", 'it''s', "say \"hi\""]"#), r#"{"q":["a, b","it's","say \"hi\""]}"#);
+        assert_eq!(json(r#"j: {"a": [1, {"b": null}], "c":"d"}"#), r#"{"j":{"a":[1,{"b":null}],"c":"d"}}"#);
+        assert_eq!(json("- []\n- {}"), "[[],{}]");
+    }
+
+    #[test]
+    fn flow_errors() {
+        for source in ["c: [1, 2", "c: [1,, 2]", "c: {a b}", "c: [1] x", "c: ['open]"] {
+            let err = parse(source).unwrap_err();
+            assert!(err.message.starts_with("YAML line 1:"), "{}: {}", source, err.message);
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::deflate;
+use crate::error::LbError;
+
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+const CENTRAL_DIRECTORY_ENTRY: u32 = 0x0201_4b50;
+const LOCAL_FILE_HEADER: u32 = 0x0403_4b50;
+/// Fixed end-of-central-directory record plus the longest possible archive comment.
+const MAX_EOCD_SEARCH: u64 = 22 + 0xffff;
+
+/// One central-directory entry. Times are the MS-DOS local date/time fields.
+pub struct ZipEntry {
+    pub name: String,
+    method: u16,
+    compressed_size: u64,
+    pub size: u64,
+    local_offset: u64,
+    pub dos_date: u16,
+    pub dos_time: u16,
+}
+
+impl ZipEntry {
+    pub fn is_dir(&self) -> bool {
+        self.name.ends_with('/')
+    }
+}
+
+fn corrupt(path: &Path, what: &str) -> LbError {
+    LbError::parse(format!("Corrupt zip {}: {}", path.display(), what))
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+/// A zip archive read lazily from disk; bugreports run to hundreds of megabytes, so only
+/// the central directory and the entries asked for are loaded. No zip64 or encryption.
+pub struct ZipArchive {
+    file: File,
+    path: PathBuf,
+    pub entries: Vec<ZipEntry>,
+}
+
+impl ZipArchive {
+    pub fn open(path: &Path) -> Result<ZipArchive, LbError> {
+        let io = |err: std::io::Error| LbError::io(format!("Failed to read {}: {}", path.display(), err));
+        let mut file = File::open(path).map_err(io)?;
+        let length = file.seek(SeekFrom::End(0)).map_err(io)?;
+        let tail_length = length.min(MAX_EOCD_SEARCH);
+        let mut tail = vec![0u8; tail_length as usize];
+        file.seek(SeekFrom::Start(length - tail_length)).map_err(io)?;
+        file.read_exact(&mut tail).map_err(io)?;
+        let eocd = (0..tail.len().saturating_sub(21))
+            .rev()
+            .find(|&offset| u32_at(&tail, offset) == END_OF_CENTRAL_DIRECTORY)
+            .ok_or_else(|| corrupt(path, "no end of central directory"))?;
+        let count = usize::from(u16_at(&tail, eocd + 10));
+        let directory_size = u32_at(&tail, eocd + 12);
+        let directory_offset = u32_at(&tail, eocd + 16);
+        if directory_size == u32::MAX || directory_offset == u32::MAX {
+            return Err(corrupt(path, "zip64 archives are not supported"));
+        }
+        let mut directory = vec![0u8; directory_size as usize];
+        file.seek(SeekFrom::Start(u64::from(directory_offset))).map_err(io)?;
+        file.read_exact(&mut directory).map_err(io)?;
+
+        let mut entries = Vec::with_capacity(count);
+        let mut offset = 0;
+        for _ in 0..count {
+            if offset + 46 > directory.len() || u32_at(&directory, offset) != CENTRAL_DIRECTORY_ENTRY {
+                return Err(corrupt(path, "bad central directory entry"));
+            }
+            let name_length = usize::from(u16_at(&directory, offset + 28));
+            let extra_length = usize::from(u16_at(&directory, offset + 30));
+            let comment_length = usize::from(u16_at(&directory, offset + 32));
+            let name = directory
+                .get(offset + 46..offset + 46 + name_length)
+                .ok_or_else(|| corrupt(path, "truncated entry name"))?;
+            entries.push(ZipEntry {
+                name: String::from_utf8_lossy(name).into_owned(),
+                method: u16_at(&directory, offset + 10),
+                dos_time: u16_at(&directory, offset + 12),
+                dos_date: u16_at(&directory, offset + 14),
+                compressed_size: u64::from(u32_at(&directory, offset + 20)),
+                size: u64::from(u32_at(&directory, offset + 24)),
+                local_offset: u64::from(u32_at(&directory, offset + 42)),
+            });
+            offset += 46 + name_length + extra_length + comment_length;
+        }
+        Ok(ZipArchive {
+            file,
+            path: path.to_path_buf(),
+            entries,
+        })
+    }
+
+    /// The decompressed contents of `entries[index]` (stored or deflated).
+    pub fn read(&mut self, index: usize) -> Result<Vec<u8>, LbError> {
+        let path = self.path.clone();
+        let io = |err: std::io::Error| LbError::io(format!("Failed to read {}: {}", path.display(), err));
+        let entry = self.entries.get(index).ok_or_else(|| corrupt(&path, "no such entry"))?;
+        let mut header = [0u8; 30];
+        self.file.seek(SeekFrom::Start(entry.local_offset)).map_err(io)?;
+        self.file.read_exact(&mut header).map_err(io)?;
+        if u32_at(&header, 0) != LOCAL_FILE_HEADER {
+            return Err(corrupt(&path, "bad local file header"));
+        }
+        // The local name and extra field can differ in length from the central copies.
+        let skip = i64::from(u16_at(&header, 26)) + i64::from(u16_at(&header, 28));
+        self.file.seek(SeekFrom::Current(skip)).map_err(io)?;
+        let mut data = vec![0u8; entry.compressed_size as usize];
+        self.file.read_exact(&mut data).map_err(io)?;
+        let contents = match entry.method {
+            0 => data,
+            8 => deflate::inflate(&data)?,
+            method => return Err(corrupt(&path, &format!("compression method {} is not supported", method))),
+        };
+        if contents.len() as u64 != entry.size {
+            return Err(corrupt(&path, &format!("{} has the wrong size", entry.name)));
+        }
+        Ok(contents)
+    }
+}
@@ -3,15 +3,22 @@
 from __future__ import annotations
 
 import ctypes
+import json
+import logging
 import os
 import pathlib
 import platform
 import sys
 import threading
-from typing import Iterable, List, Optional
+from typing import Any, Callable, Dict, FrozenSet, Iterable, List, Optional, Sequence, Union
 
 from utils import common
 
+try:  # PyO3 build of native_lbb (`--features python`); ctypes remains the fallback.
+    import lazy_blacktea_native as _native_ext
+except ImportError:
+    _native_ext = None
+
 logger = common.get_logger('native_bridge')
 
 _LIB_HANDLE: Optional[ctypes.CDLL] = None
@@ -29,11 +36,38 @@ _LIBRARY_NAME_BY_SYSTEM = {
 
 _LIBRARY_FILENAMES = tuple(dict.fromkeys(_LIBRARY_NAME_BY_SYSTEM.values()))
 
+_native_logger = common.get_logger('native_lbb')
+_NATIVE_LOG_LEVELS = {1: logging.ERROR, 2: logging.WARNING, 3: logging.INFO, 4: logging.DEBUG}
+_LogCallback = ctypes.CFUNCTYPE(None, ctypes.c_int, ctypes.c_char_p, ctypes.c_char_p)
+_LineCallback = ctypes.CFUNCTYPE(None, ctypes.c_void_p, ctypes.c_char_p)
+
 
 class NativeBridgeError(RuntimeError):
     """Raised when invoking the native library fails."""
 
 
+class _NativeResult(ctypes.Structure):
+    """Mirror of the native ``lb_result`` length-prefixed buffer."""
+
+    _fields_ = [('ptr', ctypes.c_void_p), ('len', ctypes.c_size_t)]
+
+
+def _forward_native_log(level: int, target: bytes, message: bytes) -> None:
+    _native_logger.log(
+        _NATIVE_LOG_LEVELS.get(level, logging.DEBUG),
+        '[%s] %s',
+        target.decode('utf-8', 'replace'),
+        message.decode('utf-8', 'replace'),
+    )
+
+
+# Kept at module level: the native side holds this pointer for the life of the process.
+_LOG_CALLBACK = _LogCallback(_forward_native_log)
+
+# Schedule callbacks outlive the call that registers them; dropped on cancel.
+_SCHEDULE_CALLBACKS: Dict[int, Any] = {}
+
+
 def _default_library_name() -> str:
     return _LIBRARY_NAME_BY_SYSTEM.get(platform.system(), _LIBRARY_FILENAMES[0])
 
@@ -81,10 +115,10 @@ def _load_library() -> Optional[ctypes.CDLL]:
                 logger.debug('Failed to load native library at %s: %s', path, exc)
                 continue
 
-            handle.lb_render_device_ui_html.argtypes = [ctypes.c_char_p]
-            handle.lb_render_device_ui_html.restype = ctypes.c_void_p
-            handle.lb_run_commands_parallel.argtypes = [ctypes.c_char_p]
-            handle.lb_run_commands_parallel.restype = ctypes.c_void_p
+            handle.lb_render_device_ui_html_buf.argtypes = [ctypes.c_char_p, ctypes.c_char_p]
+            handle.lb_render_device_ui_html_buf.restype = _NativeResult
+            handle.lb_run_commands_parallel_buf.argtypes = [ctypes.c_char_p]
+            handle.lb_run_commands_parallel_buf.restype = _NativeResult
             handle.lb_start_screen_record.argtypes = [ctypes.c_char_p, ctypes.c_char_p]
             handle.lb_start_screen_record.restype = ctypes.c_int
             handle.lb_stop_screen_record.argtypes = [ctypes.c_char_p]
@@ -93,6 +127,311 @@ def _load_library() -> Optional[ctypes.CDLL]:
             handle.lb_last_error.restype = ctypes.c_void_p
             handle.lb_free_string.argtypes = [ctypes.c_void_p]
             handle.lb_free_string.restype = None
+            handle.lb_free_result.argtypes = [_NativeResult]
+            handle.lb_free_result.restype = None
+            if hasattr(handle, 'lb_set_log_callback'):
+                handle.lb_set_log_callback.argtypes = [_LogCallback]
+                handle.lb_set_log_callback.restype = None
+                handle.lb_set_log_callback(_LOG_CALLBACK)
+            if hasattr(handle, 'lb_run_argv_parallel'):
+                handle.lb_run_argv_parallel.argtypes = [ctypes.c_char_p]
+                handle.lb_run_argv_parallel.restype = _NativeResult
+            if hasattr(handle, 'lb_run_commands_structured'):
+                handle.lb_run_commands_structured.argtypes = [ctypes.c_char_p]
+                handle.lb_run_commands_structured.restype = _NativeResult
+            if hasattr(handle, 'lb_run_on_devices'):
+                handle.lb_run_on_devices.argtypes = [ctypes.c_char_p, ctypes.c_char_p]
+                handle.lb_run_on_devices.restype = ctypes.c_void_p
+            if hasattr(handle, 'lb_get_clipboard'):
+                handle.lb_get_clipboard.argtypes = [ctypes.c_char_p]
+                handle.lb_get_clipboard.restype = ctypes.c_void_p
+                handle.lb_set_clipboard.argtypes = [ctypes.c_char_p, ctypes.c_char_p]
+                handle.lb_set_clipboard.restype = ctypes.c_int
+            if hasattr(handle, 'lb_adb_connect'):
+                for name in ('lb_adb_connect', 'lb_adb_disconnect'):
+                    getattr(handle, name).argtypes = [ctypes.c_char_p]
+                    getattr(handle, name).restype = ctypes.c_void_p
+                handle.lb_adb_pair.argtypes = [ctypes.c_char_p, ctypes.c_char_p]
+                handle.lb_adb_pair.restype = ctypes.c_void_p
+                handle.lb_wireless_endpoints.argtypes = []
+                handle.lb_wireless_endpoints.restype = ctypes.c_void_p
+            if hasattr(handle, 'lb_reboot'):
+                handle.lb_reboot.argtypes = [ctypes.c_char_p, ctypes.c_char_p]
+                handle.lb_reboot.restype = ctypes.c_int
+                handle.lb_wait_for_state.argtypes = [ctypes.c_char_p, ctypes.c_char_p, ctypes.c_uint64]
+                handle.lb_wait_for_state.restype = ctypes.c_int
+            if hasattr(handle, 'lb_wait_for_boot'):
+                handle.lb_wait_for_boot.argtypes = [ctypes.c_char_p, ctypes.c_uint64, ctypes.c_int]
+                handle.lb_wait_for_boot.restype = ctypes.c_void_p
+            if hasattr(handle, 'lb_adb_root'):
+                for name in ('lb_adb_root', 'lb_adb_unroot'):
+                    getattr(handle, name).argtypes = [ctypes.c_char_p]
+                    getattr(handle, name).restype = ctypes.c_int
+                handle.lb_remount.argtypes = [ctypes.c_char_p]
+                handle.lb_remount.restype = ctypes.c_void_p
+            if hasattr(handle, 'lb_file_checksum'):
+                handle.lb_file_checksum.argtypes = [ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p]
+                handle.lb_file_checksum.restype = ctypes.c_void_p
+            if hasattr(handle, 'lb_screenshot_thumbnail'):
+                handle.lb_screenshot_thumbnail.argtypes = [ctypes.c_char_p, ctypes.c_uint32]
+                handle.lb_screenshot_thumbnail.restype = _NativeResult
+            if hasattr(handle, 'lb_capture_all'):
+                handle.lb_capture_all.argtypes = [ctypes.c_char_p, ctypes.c_char_p]
+                handle.lb_capture_all.restype = ctypes.c_void_p
+            if hasattr(handle, 'lb_capture_inspection_bundle'):
+                handle.lb_capture_inspection_bundle.argtypes = [ctypes.c_char_p]
+                handle.lb_capture_inspection_bundle.restype = ctypes.c_void_p
+            if hasattr(handle, 'lb_display_info'):
+                handle.lb_display_info.argtypes = [ctypes.c_char_p]
+                handle.lb_display_info.restype = ctypes.c_void_p
+            if hasattr(handle, 'lb_activity_stack'):
+                handle.lb_activity_stack.argtypes = [ctypes.c_char_p]
+                handle.lb_activity_stack.restype = ctypes.c_void_p
+            if hasattr(handle, 'lb_launch_activity_with_options'):
+                handle.lb_launch_activity_with_options.argtypes = [ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p]
+                handle.lb_launch_activity_with_options.restype = ctypes.c_void_p
+            if hasattr(handle, 'lb_send_intent'):
+                handle.lb_send_intent.argtypes = [
+                    ctypes.c_char_p,
+                    ctypes.c_char_p,
+                    ctypes.c_char_p,
+                    ctypes.c_char_p,
+                    ctypes.c_uint32,
+                ]
+                handle.lb_send_intent.restype = ctypes.c_void_p
+            if hasattr(handle, 'lb_run_instrumentation'):
+                handle.lb_run_instrumentation.argtypes = [
+                    ctypes.c_char_p,
+                    ctypes.c_char_p,
+                    ctypes.c_char_p,
+                    _LineCallback,
+                    ctypes.c_void_p,
+                ]
+                handle.lb_run_instrumentation.restype = ctypes.c_void_p
+            if hasattr(handle, 'lb_monkey_start'):
+                handle.lb_monkey_start.argtypes = [
+                    ctypes.c_char_p,
+                    ctypes.c_char_p,
+                    ctypes.c_uint32,
+                    ctypes.c_uint32,
+                    ctypes.c_int64,
+                ]
+                handle.lb_monkey_start.restype = ctypes.c_int
+            for name in ('lb_monkey_status', 'lb_monkey_stop'):
+                if hasattr(handle, name):
+                    getattr(handle, name).argtypes = [ctypes.c_char_p]
+                    getattr(handle, name).restype = ctypes.c_void_p
+            if hasattr(handle, 'lb_collect_native_crashes'):
+                handle.lb_collect_native_crashes.argtypes = [ctypes.c_char_p, ctypes.c_uint64, ctypes.c_char_p]
+                handle.lb_collect_native_crashes.restype = ctypes.c_void_p
+            for name in ('lb_batterystats_reset', 'lb_batterystats_dump_json'):
+                if hasattr(handle, name):
+                    getattr(handle, name).argtypes = [ctypes.c_char_p]
+                    getattr(handle, name).restype = ctypes.c_int if name == 'lb_batterystats_reset' else ctypes.c_void_p
+            if hasattr(handle, 'lb_battery_set_unplugged'):
+                handle.lb_battery_set_unplugged.argtypes = [ctypes.c_char_p, ctypes.c_int]
+                handle.lb_battery_set_unplugged.restype = ctypes.c_int
+            if hasattr(handle, 'lb_thermal_status'):
+                handle.lb_thermal_status.argtypes = [ctypes.c_char_p]
+                handle.lb_thermal_status.restype = ctypes.c_void_p
+            if hasattr(handle, 'lb_top_sample'):
+                handle.lb_top_sample.argtypes = [ctypes.c_char_p, ctypes.c_uint32, ctypes.c_uint32, ctypes.c_char_p]
+                handle.lb_top_sample.restype = ctypes.c_void_p
+            if hasattr(handle, 'lb_network_stats'):
+                handle.lb_network_stats.argtypes = [ctypes.c_char_p, ctypes.c_char_p]
+                handle.lb_network_stats.restype = ctypes.c_void_p
+            settings_exports = {
+                'lb_settings_get': 3,
+                'lb_settings_set': 4,
+                'lb_settings_list': 2,
+                'lb_settings_snapshot': 2,
+                'lb_settings_restore': 2,
+            }
+            for name, arg_count in settings_exports.items():
+                if hasattr(handle, name):
+                    getattr(handle, name).argtypes = [ctypes.c_char_p] * arg_count
+                    getattr(handle, name).restype = ctypes.c_void_p
+            if hasattr(handle, 'lb_set_animation_scales'):
+                handle.lb_set_animation_scales.argtypes = [ctypes.c_char_p, ctypes.c_double]
+                handle.lb_set_animation_scales.restype = ctypes.c_void_p
+            if hasattr(handle, 'lb_get_dev_options'):
+                handle.lb_get_dev_options.argtypes = [ctypes.c_char_p]
+                handle.lb_get_dev_options.restype = ctypes.c_void_p
+            for name in _DEV_OPTION_EXPORTS.values():
+                if hasattr(handle, name):
+                    getattr(handle, name).argtypes = [ctypes.c_char_p, ctypes.c_int]
+                    getattr(handle, name).restype = ctypes.c_void_p
+            if hasattr(handle, 'lb_demo_mode_enable'):
+                handle.lb_demo_mode_enable.argtypes = [
+                    ctypes.c_char_p,
+                    ctypes.c_char_p,
+                    ctypes.c_int,
+                    ctypes.c_char_p,
+                ]
+                handle.lb_demo_mode_enable.restype = ctypes.c_void_p
+                handle.lb_demo_mode_disable.argtypes = [ctypes.c_char_p]
+                handle.lb_demo_mode_disable.restype = ctypes.c_int
+            if hasattr(handle, 'lb_set_display'):
+                handle.lb_set_display.argtypes = [ctypes.c_char_p, ctypes.c_char_p, ctypes.c_uint32]
+                handle.lb_set_dark_mode.argtypes = [ctypes.c_char_p, ctypes.c_int]
+                handle.lb_set_locale.argtypes = [ctypes.c_char_p, ctypes.c_char_p]
+                for name in ('lb_reset_display', 'lb_reset_dark_mode', 'lb_reset_locale'):
+                    getattr(handle, name).argtypes = [ctypes.c_char_p]
+                for name in (
+                    'lb_set_display',
+                    'lb_reset_display',
+                    'lb_set_dark_mode',
+                    'lb_reset_dark_mode',
+                    'lb_set_locale',
+                    'lb_reset_locale',
+                ):
+                    getattr(handle, name).restype = ctypes.c_void_p
+            if hasattr(handle, 'lb_set_permissions'):
+                handle.lb_set_permissions.argtypes = [ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p]
+                handle.lb_set_permissions.restype = ctypes.c_void_p
+            if hasattr(handle, 'lb_list_users'):
+                handle.lb_list_users.argtypes = [ctypes.c_char_p]
+                handle.lb_list_users.restype = ctypes.c_void_p
+                handle.lb_screenshot_thumbnail_for_user.argtypes = [ctypes.c_char_p, ctypes.c_uint32, ctypes.c_uint32]
+                handle.lb_screenshot_thumbnail_for_user.restype = _NativeResult
+            if hasattr(handle, 'lb_set_doze'):
+                handle.lb_set_doze.argtypes = [ctypes.c_char_p, ctypes.c_char_p]
+                handle.lb_set_doze.restype = ctypes.c_void_p
+                handle.lb_set_standby_bucket.argtypes = [ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p]
+                handle.lb_set_standby_bucket.restype = ctypes.c_void_p
+            if hasattr(handle, 'lb_app_backup'):
+                handle.lb_app_backup.argtypes = [
+                    ctypes.c_char_p,
+                    ctypes.c_char_p,
+                    ctypes.c_char_p,
+                    _LineCallback,
+                    ctypes.c_void_p,
+                ]
+                handle.lb_app_backup.restype = ctypes.c_void_p
+            if hasattr(handle, 'lb_app_restore'):
+                handle.lb_app_restore.argtypes = [
+                    ctypes.c_char_p,
+                    ctypes.c_char_p,
+                    _LineCallback,
+                    ctypes.c_void_p,
+                ]
+                handle.lb_app_restore.restype = ctypes.c_void_p
+            if hasattr(handle, 'lb_push_obb'):
+                handle.lb_push_obb.argtypes = [
+                    ctypes.c_char_p,
+                    ctypes.c_char_p,
+                    ctypes.c_char_p,
+                    _LineCallback,
+                    ctypes.c_void_p,
+                ]
+                handle.lb_push_obb.restype = ctypes.c_void_p
+            if hasattr(handle, 'lb_emulator_console'):
+                handle.lb_emulator_console.argtypes = [ctypes.c_char_p, ctypes.c_char_p]
+                handle.lb_emulator_console.restype = ctypes.c_void_p
+            if hasattr(handle, 'lb_emulator_geo_fix'):
+                handle.lb_emulator_geo_fix.argtypes = [
+                    ctypes.c_char_p,
+                    ctypes.c_double,
+                    ctypes.c_double,
+                    ctypes.c_double,
+                ]
+                handle.lb_emulator_geo_fix.restype = ctypes.c_int
+            if hasattr(handle, 'lb_emulator_sms'):
+                handle.lb_emulator_sms.argtypes = [ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p]
+                handle.lb_emulator_sms.restype = ctypes.c_int
+            if hasattr(handle, 'lb_emulator_call'):
+                handle.lb_emulator_call.argtypes = [ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p]
+                handle.lb_emulator_call.restype = ctypes.c_int
+            if hasattr(handle, 'lb_emulator_battery'):
+                handle.lb_emulator_battery.argtypes = [ctypes.c_char_p, ctypes.c_int, ctypes.c_int]
+                handle.lb_emulator_battery.restype = ctypes.c_int
+            if hasattr(handle, 'lb_emulator_network'):
+                handle.lb_emulator_network.argtypes = [ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p]
+                handle.lb_emulator_network.restype = ctypes.c_int
+            if hasattr(handle, 'lb_list_avds'):
+                handle.lb_list_avds.argtypes = []
+                handle.lb_list_avds.restype = ctypes.c_void_p
+            if hasattr(handle, 'lb_start_avd'):
+                handle.lb_start_avd.argtypes = [ctypes.c_char_p, ctypes.c_char_p]
+                handle.lb_start_avd.restype = ctypes.c_void_p
+            if hasattr(handle, 'lb_stop_avd'):
+                handle.lb_stop_avd.argtypes = [ctypes.c_char_p]
+                handle.lb_stop_avd.restype = ctypes.c_int
+            if hasattr(handle, 'lb_schedule_command'):
+                handle.lb_schedule_command.argtypes = [
+                    ctypes.c_char_p,
+                    ctypes.c_char_p,
+                    ctypes.c_char_p,
+                    _LineCallback,
+                    ctypes.c_void_p,
+                ]
+                handle.lb_schedule_command.restype = ctypes.c_uint64
+                handle.lb_schedule_list.argtypes = []
+                handle.lb_schedule_list.restype = ctypes.c_void_p
+                handle.lb_schedule_cancel.argtypes = [ctypes.c_uint64]
+                handle.lb_schedule_cancel.restype = ctypes.c_int
+            if hasattr(handle, 'lb_history_query'):
+                handle.lb_history_query.argtypes = [ctypes.c_char_p]
+                handle.lb_history_query.restype = ctypes.c_void_p
+                handle.lb_set_history_path.argtypes = [ctypes.c_char_p]
+                handle.lb_set_history_path.restype = ctypes.c_int
+                handle.lb_set_history_enabled.argtypes = [ctypes.c_int]
+                handle.lb_set_history_enabled.restype = None
+                handle.lb_history_clear.argtypes = []
+                handle.lb_history_clear.restype = ctypes.c_int
+            if hasattr(handle, 'lb_device_meta_set'):
+                handle.lb_device_meta_set.argtypes = [ctypes.c_char_p, ctypes.c_char_p]
+                handle.lb_device_meta_set.restype = ctypes.c_void_p
+                handle.lb_device_meta_get.argtypes = [ctypes.c_char_p]
+                handle.lb_device_meta_get.restype = ctypes.c_void_p
+                handle.lb_device_meta_list.argtypes = []
+                handle.lb_device_meta_list.restype = ctypes.c_void_p
+                handle.lb_list_devices_json.argtypes = []
+                handle.lb_list_devices_json.restype = ctypes.c_void_p
+                handle.lb_set_device_meta_path.argtypes = [ctypes.c_char_p]
+                handle.lb_set_device_meta_path.restype = ctypes.c_int
+            if hasattr(handle, 'lb_run_on_group'):
+                for name in ('lb_run_on_group', 'lb_capture_group', 'lb_start_group_recording'):
+                    getattr(handle, name).argtypes = [ctypes.c_char_p, ctypes.c_char_p]
+                    getattr(handle, name).restype = ctypes.c_void_p
+                handle.lb_install_on_group.argtypes = [ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p]
+                handle.lb_install_on_group.restype = ctypes.c_void_p
+                handle.lb_stop_group_recording.argtypes = [ctypes.c_char_p]
+                handle.lb_stop_group_recording.restype = ctypes.c_void_p
+            if hasattr(handle, 'lb_invoke'):
+                handle.lb_invoke.argtypes = [ctypes.c_char_p]
+                handle.lb_invoke.restype = ctypes.c_void_p
+            if hasattr(handle, 'lb_server_start'):
+                handle.lb_server_start.argtypes = [ctypes.c_char_p, ctypes.c_char_p]
+                handle.lb_server_start.restype = ctypes.c_void_p
+                handle.lb_server_stop.argtypes = [ctypes.c_uint64]
+                handle.lb_server_stop.restype = ctypes.c_int
+            if hasattr(handle, 'lb_grpc_start'):
+                handle.lb_grpc_start.argtypes = [ctypes.c_char_p]
+                handle.lb_grpc_start.restype = ctypes.c_void_p
+                handle.lb_grpc_stop.argtypes = [ctypes.c_uint64]
+                handle.lb_grpc_stop.restype = ctypes.c_int
+            if hasattr(handle, 'lb_run_script'):
+                handle.lb_run_script.argtypes = [ctypes.c_char_p, _LineCallback, ctypes.c_void_p]
+                handle.lb_run_script.restype = ctypes.c_void_p
+            if hasattr(handle, 'lb_cancel_token_new'):
+                handle.lb_cancel_token_new.argtypes = [ctypes.c_uint64]
+                handle.lb_cancel_token_new.restype = ctypes.c_void_p
+                for name in ('lb_cancel_token_cancel', 'lb_cancel_token_free', 'lb_set_thread_cancel_token'):
+                    getattr(handle, name).argtypes = [ctypes.c_uint64]
+                    getattr(handle, name).restype = ctypes.c_int
+            if hasattr(handle, 'lb_list_recordings'):
+                handle.lb_list_recordings.argtypes = []
+                handle.lb_list_recordings.restype = ctypes.c_void_p
+            if hasattr(handle, 'lb_reconcile_recordings'):
+                handle.lb_reconcile_recordings.argtypes = [ctypes.c_char_p, ctypes.c_char_p]
+                handle.lb_reconcile_recordings.restype = ctypes.c_void_p
+            if hasattr(handle, 'lb_shutdown'):
+                handle.lb_shutdown.argtypes = [ctypes.c_uint64]
+                handle.lb_shutdown.restype = ctypes.c_void_p
+            if hasattr(handle, 'lb_capabilities'):
+                handle.lb_capabilities.argtypes = []
+                handle.lb_capabilities.restype = ctypes.c_void_p
 
             _LIB_HANDLE = handle
             _HAS_NATIVE = True
@@ -123,6 +462,20 @@ def _read_and_free_string(ptr: int) -> str:
     return value
 
 
+def _read_and_free_result(result: '_NativeResult') -> Optional[str]:
+    """Decode an ``lb_result`` buffer; ``None`` signals a native error."""
+    if not result.ptr:
+        return None
+    handle = _load_library()
+    if handle is None:
+        return None
+    try:
+        value = ctypes.string_at(result.ptr, result.len).decode('utf-8')
+    finally:
+        handle.lb_free_result(result)
+    return value
+
+
 def _read_last_error() -> str:
     handle = _load_library()
     if handle is None:
@@ -131,6 +484,19 @@ def _read_last_error() -> str:
     return _read_and_free_string(err_ptr if err_ptr else 0)
 
 
+def capabilities() -> FrozenSet[str]:
+    """Return the subsystems the loaded native library supports; empty when it predates ``lb_capabilities``."""
+    handle = _load_library()
+    if handle is None or not hasattr(handle, 'lb_capabilities'):
+        return frozenset()
+    raw = _read_and_free_string(handle.lb_capabilities() or 0)
+    try:
+        return frozenset(json.loads(raw)) if raw else frozenset()
+    except ValueError:
+        logger.warning('Ignoring malformed native capabilities: %s', raw)
+        return frozenset()
+
+
 def render_device_ui_html(xml_content: str) -> str:
     """Render XML device UI dump into HTML using the native helper."""
     handle = _load_library()
@@ -138,11 +504,11 @@ def render_device_ui_html(xml_content: str) -> str:
         raise NativeBridgeError('Native library not available')
 
     xml_bytes = xml_content.encode('utf-8')
-    result_ptr = handle.lb_render_device_ui_html(ctypes.c_char_p(xml_bytes))
-    if not result_ptr:
+    html = _read_and_free_result(handle.lb_render_device_ui_html_buf(ctypes.c_char_p(xml_bytes), None))
+    if html is None:
         error_message = _read_last_error() or 'Unknown native rendering error'
         raise NativeBridgeError(error_message)
-    return _read_and_free_string(result_ptr)
+    return html
 
 
 def run_commands_parallel(commands: List[str]) -> List[List[str]]:
@@ -158,51 +524,1566 @@ def run_commands_parallel(commands: List[str]) -> List[List[str]]:
     payload_lines.extend(commands)
     payload = '\n'.join(payload_lines).encode('utf-8')
 
-    result_ptr = handle.lb_run_commands_parallel(ctypes.c_char_p(payload))
-    if not result_ptr:
+    raw_result = _read_and_free_result(handle.lb_run_commands_parallel_buf(ctypes.c_char_p(payload)))
+    if raw_result is None:
+        error_message = _read_last_error() or 'Unknown native command error'
+        raise NativeBridgeError(error_message)
+
+    return _split_parallel_result(raw_result, len(commands))
+
+
+def run_argv_parallel(argvs: List[List[str]]) -> List[List[str]]:
+    """Execute pre-tokenized argv lists in parallel, bypassing shell-style splitting."""
+    if not argvs:
+        return []
+
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_run_argv_parallel'):
+        raise NativeBridgeError('Native library does not support argv commands')
+
+    payload = json.dumps([list(argv) for argv in argvs]).encode('utf-8')
+    raw_result = _read_and_free_result(handle.lb_run_argv_parallel(ctypes.c_char_p(payload)))
+    if raw_result is None:
+        error_message = _read_last_error() or 'Unknown native command error'
+        raise NativeBridgeError(error_message)
+    return _split_parallel_result(raw_result, len(argvs))
+
+
+def run_commands_structured(commands: Sequence[Union[str, Sequence[str], Dict[str, Any]]]) -> List[Dict[str, Any]]:
+    """Execute commands in parallel and return per-command exit code, duration, pid, stdout and stderr.
+
+    A command may be a ``{'command': ..., 'stdin': '...'}`` dict to feed text to its stdin.
+    """
+    if not commands:
+        return []
+
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_run_commands_structured'):
+        raise NativeBridgeError('Native library does not support structured command results')
+
+    payload = json.dumps([command if isinstance(command, (str, dict)) else list(command) for command in commands])
+    raw_result = _read_and_free_result(handle.lb_run_commands_structured(ctypes.c_char_p(payload.encode('utf-8'))))
+    if raw_result is None:
         error_message = _read_last_error() or 'Unknown native command error'
         raise NativeBridgeError(error_message)
+    return json.loads(raw_result)
+
+
+def run_on_devices(serials: Sequence[str], command_template: str) -> Dict[str, Dict[str, Any]]:
+    """Run one command template on every serial and return structured results keyed by serial.
 
-    raw_result = _read_and_free_string(result_ptr)
+    ``{serial}`` in the template is substituted; otherwise an ``adb ...`` template gets ``-s <serial>``.
+    """
+    if not serials:
+        return {}
+
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_run_on_devices'):
+        raise NativeBridgeError('Native library does not support device batches')
+
+    payload = json.dumps(list(serials))
+    raw_result = _read_and_free_string(
+        handle.lb_run_on_devices(
+            ctypes.c_char_p(payload.encode('utf-8')),
+            ctypes.c_char_p(command_template.encode('utf-8')),
+        )
+        or 0
+    )
     if not raw_result:
-        return [[] for _ in commands]
+        error_message = _read_last_error() or 'Unknown native command error'
+        raise NativeBridgeError(error_message)
+    return json.loads(raw_result)
 
-    command_chunks = raw_result.split(_RECORD_SEPARATOR)
-    results: List[List[str]] = []
-    for chunk in command_chunks:
-        if chunk == '':
-            results.append([])
-            continue
-        lines = chunk.split(_LINE_SEPARATOR)
-        results.append(lines)
-    return results
 
+def capture_all(serials: Sequence[str], what: str = 'all') -> Dict[str, Dict[str, Any]]:
+    """Capture screenshots and/or UI dumps on every serial at the same moment, keyed by serial.
+
+    ``what`` is ``screenshot``, ``hierarchy`` or ``all``; screenshots come back as ``screenshot_png_base64``.
+    """
+    if not serials:
+        return {}
 
-def start_screen_record(serial: str, remote_path: str) -> None:
-    """Start an adb screenrecord session using the native helper."""
     handle = _load_library()
     if handle is None:
         raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_capture_all'):
+        raise NativeBridgeError('Native library does not support synchronized capture')
 
-    serial_bytes = serial.encode('utf-8')
-    remote_bytes = remote_path.encode('utf-8')
-    result = handle.lb_start_screen_record(ctypes.c_char_p(serial_bytes), ctypes.c_char_p(remote_bytes))
-    if result != 1:
-        error_message = _read_last_error() or f'Failed to start screenrecord for {serial}'
+    payload = json.dumps(list(serials))
+    raw_result = _read_and_free_string(
+        handle.lb_capture_all(ctypes.c_char_p(payload.encode('utf-8')), ctypes.c_char_p(what.encode('utf-8'))) or 0
+    )
+    if not raw_result:
+        error_message = _read_last_error() or 'Unknown native capture error'
         raise NativeBridgeError(error_message)
+    return json.loads(raw_result)
 
 
-def stop_screen_record(serial: str) -> None:
-    """Stop an adb screenrecord session using the native helper."""
+def capture_inspection_bundle(serial: str) -> Dict[str, Any]:
+    """Capture a screenshot and UI hierarchy taken at the same moment, plus display metrics."""
     handle = _load_library()
     if handle is None:
         raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_capture_inspection_bundle'):
+        raise NativeBridgeError('Native library does not support inspection bundles')
 
-    serial_bytes = serial.encode('utf-8')
-    result = handle.lb_stop_screen_record(ctypes.c_char_p(serial_bytes))
+    raw_result = _read_and_free_string(handle.lb_capture_inspection_bundle(ctypes.c_char_p(serial.encode('utf-8'))) or 0)
+    if not raw_result:
+        error_message = _read_last_error() or f'Failed to capture an inspection bundle from {serial}'
+        raise NativeBridgeError(error_message)
+    return json.loads(raw_result)
+
+
+def display_info(serial: str) -> Dict[str, Any]:
+    """Return physical/override resolution and density, rotation, and cutout insets of the default display."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_display_info'):
+        raise NativeBridgeError('Native library does not support display info')
+
+    raw_result = _read_and_free_string(handle.lb_display_info(ctypes.c_char_p(serial.encode('utf-8'))) or 0)
+    if not raw_result:
+        error_message = _read_last_error() or f'Failed to read display info from {serial}'
+        raise NativeBridgeError(error_message)
+    return json.loads(raw_result)
+
+
+def activity_stack(serial: str) -> Dict[str, Any]:
+    """Return the focused activity and the task stack (top to bottom) with each task's activities."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_activity_stack'):
+        raise NativeBridgeError('Native library does not support activity stacks')
+
+    raw_result = _read_and_free_string(handle.lb_activity_stack(ctypes.c_char_p(serial.encode('utf-8'))) or 0)
+    if not raw_result:
+        error_message = _read_last_error() or f'Failed to read the activity stack from {serial}'
+        raise NativeBridgeError(error_message)
+    return json.loads(raw_result)
+
+
+def launch_activity(
+    serial: str,
+    component: str,
+    extras: Optional[Dict[str, Any]] = None,
+    wait: bool = True,
+    force_stop: bool = False,
+    user: Optional[Union[int, str]] = None,
+) -> Dict[str, Any]:
+    """Start ``component`` via ``am start`` and return its launch timings (``force_stop`` for a cold start).
+
+    ``user`` is an ``list_users`` id (e.g. a work profile) or ``'current'``.
+    """
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_launch_activity_with_options'):
+        raise NativeBridgeError('Native library does not support activity launches')
+
+    options = json.dumps({'extras': extras or {}, 'wait': wait, 'force_stop': force_stop, 'user': user})
+    raw_result = _read_and_free_string(
+        handle.lb_launch_activity_with_options(
+            ctypes.c_char_p(serial.encode('utf-8')),
+            ctypes.c_char_p(component.encode('utf-8')),
+            ctypes.c_char_p(options.encode('utf-8')),
+        )
+        or 0
+    )
+    if not raw_result:
+        error_message = _read_last_error() or f'Failed to launch {component} on {serial}'
+        raise NativeBridgeError(error_message)
+    return json.loads(raw_result)
+
+
+def send_intent(
+    serial: str,
+    action: Optional[str],
+    data_uri: Optional[str] = None,
+    extras: Optional[Dict[str, Any]] = None,
+    broadcast: bool = False,
+    wait: bool = False,
+) -> Dict[str, Any]:
+    """Send an intent via ``am start`` (or ``am broadcast``) with typed, quoted extras."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_send_intent'):
+        raise NativeBridgeError('Native library does not support intents')
+
+    flags = (1 if broadcast else 0) | (2 if wait else 0)
+    raw_result = _read_and_free_string(
+        handle.lb_send_intent(
+            ctypes.c_char_p(serial.encode('utf-8')),
+            ctypes.c_char_p(action.encode('utf-8')) if action else None,
+            ctypes.c_char_p(data_uri.encode('utf-8')) if data_uri else None,
+            ctypes.c_char_p(json.dumps(extras).encode('utf-8')) if extras else None,
+            flags,
+        )
+        or 0
+    )
+    if not raw_result:
+        error_message = _read_last_error() or f'Failed to send intent on {serial}'
+        raise NativeBridgeError(error_message)
+    return json.loads(raw_result)
+
+
+def run_instrumentation(
+    serial: str,
+    runner: str,
+    args: Optional[Dict[str, Any]] = None,
+    on_event: Optional[Callable[[Dict[str, Any]], None]] = None,
+) -> Dict[str, Any]:
+    """Run ``am instrument -r -w`` for ``runner``, passing each test event to ``on_event``."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_run_instrumentation'):
+        raise NativeBridgeError('Native library does not support instrumentation runs')
+
+    def forward(_user_data: Optional[int], payload: bytes) -> None:
+        if on_event is not None:
+            on_event(json.loads(payload.decode('utf-8', 'replace')))
+
+    # The callback only runs during the call, so a local reference keeps it alive long enough.
+    callback = _LineCallback(forward)
+    raw_result = _read_and_free_string(
+        handle.lb_run_instrumentation(
+            ctypes.c_char_p(serial.encode('utf-8')),
+            ctypes.c_char_p(runner.encode('utf-8')),
+            ctypes.c_char_p(json.dumps(args).encode('utf-8')) if args else None,
+            callback,
+            None,
+        )
+        or 0
+    )
+    if not raw_result:
+        error_message = _read_last_error() or f'Failed to run {runner} on {serial}'
+        raise NativeBridgeError(error_message)
+    return json.loads(raw_result)
+
+
+def monkey_start(
+    serial: str,
+    package: str,
+    event_count: int,
+    throttle_ms: int = 0,
+    seed: Optional[int] = None,
+) -> None:
+    """Start a background monkey run against ``package``; ``seed=None`` lets monkey pick one."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_monkey_start'):
+        raise NativeBridgeError('Native library does not support monkey runs')
+
+    result = handle.lb_monkey_start(
+        ctypes.c_char_p(serial.encode('utf-8')),
+        ctypes.c_char_p(package.encode('utf-8')),
+        event_count,
+        throttle_ms,
+        -1 if seed is None else seed,
+    )
     if result != 1:
-        error_message = _read_last_error() or f'Failed to stop screenrecord for {serial}'
+        error_message = _read_last_error() or f'Failed to start monkey on {serial}'
         raise NativeBridgeError(error_message)
 
 
-__all__ = ['NativeBridgeError', 'is_available', 'render_device_ui_html', 'run_commands_parallel', 'start_screen_record', 'stop_screen_record']
+def _call_monkey(export: str, serial: str) -> Dict[str, Any]:
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, export):
+        raise NativeBridgeError('Native library does not support monkey runs')
+
+    raw_result = _read_and_free_string(getattr(handle, export)(ctypes.c_char_p(serial.encode('utf-8'))) or 0)
+    if not raw_result:
+        error_message = _read_last_error() or f'{export} failed for {serial}'
+        raise NativeBridgeError(error_message)
+    return json.loads(raw_result)
+
+
+def monkey_status(serial: str) -> Dict[str, Any]:
+    """Return the progress, crashes and ANRs of the device's monkey run."""
+    return _call_monkey('lb_monkey_status', serial)
+
+
+def monkey_stop(serial: str) -> Dict[str, Any]:
+    """Stop the device's monkey run (if still going) and return its final report."""
+    return _call_monkey('lb_monkey_stop', serial)
+
+
+def collect_native_crashes(serial: str, local_dir: str, since_ms: int = 0) -> Dict[str, Any]:
+    """Copy new tombstones and ANR traces into local_dir (via a bugreport when not rooted)."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_collect_native_crashes'):
+        raise NativeBridgeError('Native library does not support crash collection')
+
+    raw_result = _read_and_free_string(
+        handle.lb_collect_native_crashes(
+            ctypes.c_char_p(serial.encode('utf-8')),
+            ctypes.c_uint64(since_ms),
+            ctypes.c_char_p(local_dir.encode('utf-8')),
+        )
+        or 0
+    )
+    if not raw_result:
+        error_message = _read_last_error() or f'Failed to collect crashes from {serial}'
+        raise NativeBridgeError(error_message)
+    return json.loads(raw_result)
+
+
+def _batterystats_handle(export: str) -> ctypes.CDLL:
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, export):
+        raise NativeBridgeError('Native library does not support battery stats')
+    return handle
+
+
+def batterystats_reset(serial: str) -> None:
+    """Clear the device's battery stats before a power measurement."""
+    handle = _batterystats_handle('lb_batterystats_reset')
+    if handle.lb_batterystats_reset(ctypes.c_char_p(serial.encode('utf-8'))) != 1:
+        raise NativeBridgeError(_read_last_error() or f'Failed to reset battery stats on {serial}')
+
+
+def batterystats_dump(serial: str) -> Dict[str, Any]:
+    """Return per-UID power, CPU, network and wakelock usage since the last reset."""
+    handle = _batterystats_handle('lb_batterystats_dump_json')
+    raw_result = _read_and_free_string(
+        handle.lb_batterystats_dump_json(ctypes.c_char_p(serial.encode('utf-8'))) or 0
+    )
+    if not raw_result:
+        raise NativeBridgeError(_read_last_error() or f'Failed to read battery stats from {serial}')
+    return json.loads(raw_result)
+
+
+def battery_set_unplugged(serial: str, unplugged: bool = True) -> None:
+    """Make the framework treat the device as on battery, or restore the real state."""
+    handle = _batterystats_handle('lb_battery_set_unplugged')
+    if handle.lb_battery_set_unplugged(ctypes.c_char_p(serial.encode('utf-8')), 1 if unplugged else 0) != 1:
+        raise NativeBridgeError(_read_last_error() or f'Failed to change charging state on {serial}')
+
+
+def thermal_status(serial: str) -> Dict[str, Any]:
+    """Return sensor temperatures and the framework's thermal throttling status."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_thermal_status'):
+        raise NativeBridgeError('Native library does not support thermal status')
+
+    raw_result = _read_and_free_string(handle.lb_thermal_status(ctypes.c_char_p(serial.encode('utf-8'))) or 0)
+    if not raw_result:
+        raise NativeBridgeError(_read_last_error() or f'Failed to read thermal status from {serial}')
+    return json.loads(raw_result)
+
+
+def top_sample(
+    serial: str,
+    interval_ms: int = 1000,
+    count: int = 1,
+    package: Optional[str] = None,
+) -> Dict[str, Any]:
+    """Sample per-process CPU% and RSS with top; blocks for interval_ms * count."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_top_sample'):
+        raise NativeBridgeError('Native library does not support top sampling')
+
+    raw_result = _read_and_free_string(
+        handle.lb_top_sample(
+            ctypes.c_char_p(serial.encode('utf-8')),
+            interval_ms,
+            count,
+            ctypes.c_char_p(package.encode('utf-8')) if package else None,
+        )
+        or 0
+    )
+    if not raw_result:
+        raise NativeBridgeError(_read_last_error() or f'Failed to sample top on {serial}')
+    return json.loads(raw_result)
+
+
+def network_stats(serial: str, package: str) -> Dict[str, Any]:
+    """Return foreground/background and Wi-Fi/mobile rx/tx totals for the package's UID."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_network_stats'):
+        raise NativeBridgeError('Native library does not support network stats')
+
+    raw_result = _read_and_free_string(
+        handle.lb_network_stats(
+            ctypes.c_char_p(serial.encode('utf-8')),
+            ctypes.c_char_p(package.encode('utf-8')),
+        )
+        or 0
+    )
+    if not raw_result:
+        raise NativeBridgeError(_read_last_error() or f'Failed to read network stats for {package} on {serial}')
+    return json.loads(raw_result)
+
+
+def _call_settings(export: str, serial: str, *args: Optional[str]) -> Dict[str, Any]:
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, export):
+        raise NativeBridgeError('Native library does not support settings')
+
+    encoded = [ctypes.c_char_p(arg.encode('utf-8')) if arg is not None else None for arg in args]
+    raw_result = _read_and_free_string(
+        getattr(handle, export)(ctypes.c_char_p(serial.encode('utf-8')), *encoded) or 0
+    )
+    if not raw_result:
+        error_message = _read_last_error() or f'{export} failed for {serial}'
+        raise NativeBridgeError(error_message)
+    return json.loads(raw_result)
+
+
+def settings_get(serial: str, namespace: str, key: str) -> Dict[str, Any]:
+    """Read one system/secure/global setting; numeric values come back as numbers."""
+    return _call_settings('lb_settings_get', serial, namespace, key)
+
+
+def settings_set(serial: str, namespace: str, key: str, value: Optional[str]) -> Dict[str, Any]:
+    """Write (or delete, with ``value=None``) one setting and verify it stuck."""
+    return _call_settings('lb_settings_set', serial, namespace, key, value)
+
+
+def settings_list(serial: str, namespace: str) -> Dict[str, Any]:
+    """Return every key in one settings namespace."""
+    return _call_settings('lb_settings_list', serial, namespace)
+
+
+def settings_snapshot(serial: str, namespaces: Optional[List[str]] = None) -> Dict[str, Any]:
+    """Capture settings (all namespaces by default) for a later ``settings_restore``."""
+    return _call_settings(
+        'lb_settings_snapshot',
+        serial,
+        json.dumps(namespaces) if namespaces is not None else None,
+    )
+
+
+def settings_restore(serial: str, snapshot: Dict[str, Any]) -> Dict[str, Any]:
+    """Re-apply a snapshot's values that differ from the device."""
+    return _call_settings('lb_settings_restore', serial, json.dumps(snapshot))
+
+
+_DEV_OPTION_EXPORTS = {
+    'stay_awake': 'lb_set_stay_awake',
+    'pointer_location': 'lb_set_pointer_location',
+    'show_touches': 'lb_set_show_touches',
+    'gpu_profiling': 'lb_set_gpu_profiling',
+    'strict_mode': 'lb_set_strict_mode',
+}
+
+
+def _call_dev_options(export: str, serial: str, *args: Any) -> Dict[str, Any]:
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, export):
+        raise NativeBridgeError('Native library does not support developer options')
+
+    raw_result = _read_and_free_string(
+        getattr(handle, export)(ctypes.c_char_p(serial.encode('utf-8')), *args) or 0
+    )
+    if not raw_result:
+        error_message = _read_last_error() or f'{export} failed for {serial}'
+        raise NativeBridgeError(error_message)
+    return json.loads(raw_result)
+
+
+def set_animation_scales(serial: str, scale: float) -> Dict[str, Any]:
+    """Set window, transition and animator scales (0 disables animations)."""
+    return _call_dev_options('lb_set_animation_scales', serial, ctypes.c_double(scale))
+
+
+def get_dev_options(serial: str) -> Dict[str, Any]:
+    """Return animation scales and the state of each developer toggle."""
+    return _call_dev_options('lb_get_dev_options', serial)
+
+
+def set_dev_option(serial: str, option: str, enabled: bool) -> Dict[str, Any]:
+    """Flip one of ``stay_awake``, ``pointer_location``, ``show_touches``,
+    ``gpu_profiling`` or ``strict_mode`` and verify the device kept it."""
+    export = _DEV_OPTION_EXPORTS.get(option)
+    if export is None:
+        raise NativeBridgeError(f'Unknown developer option: {option}')
+    return _call_dev_options(export, serial, ctypes.c_int(1 if enabled else 0))
+
+
+def demo_mode_enable(
+    serial: str,
+    clock: Optional[str] = None,
+    battery: int = -1,
+    network: Optional[str] = None,
+) -> Dict[str, Any]:
+    """Freeze the status bar for screenshots (clock ``HHMM``, battery 0-100, network
+    ``wifi``/``mobile``/``both``/``none``); omitted values use 12:00, 100% and wifi."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_demo_mode_enable'):
+        raise NativeBridgeError('Native library does not support demo mode')
+
+    raw_result = _read_and_free_string(
+        handle.lb_demo_mode_enable(
+            serial.encode('utf-8'),
+            clock.encode('utf-8') if clock is not None else None,
+            battery,
+            network.encode('utf-8') if network is not None else None,
+        )
+        or 0
+    )
+    if not raw_result:
+        error_message = _read_last_error() or f'Failed to enter demo mode on {serial}'
+        raise NativeBridgeError(error_message)
+    return json.loads(raw_result)
+
+
+def demo_mode_disable(serial: str) -> None:
+    """Leave demo mode and restore the live status bar."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_demo_mode_disable'):
+        raise NativeBridgeError('Native library does not support demo mode')
+
+    if handle.lb_demo_mode_disable(serial.encode('utf-8')) != 1:
+        error_message = _read_last_error() or f'Failed to leave demo mode on {serial}'
+        raise NativeBridgeError(error_message)
+
+
+def _call_device_config(export: str, serial: str, *args: Any) -> Dict[str, Any]:
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, export):
+        raise NativeBridgeError('Native library does not support device configuration')
+
+    raw_result = _read_and_free_string(getattr(handle, export)(serial.encode('utf-8'), *args) or 0)
+    if not raw_result:
+        error_message = _read_last_error() or f'{export} failed for {serial}'
+        raise NativeBridgeError(error_message)
+    return json.loads(raw_result)
+
+
+def set_display(serial: str, size: Optional[str] = None, density: int = 0) -> Dict[str, Any]:
+    """Override display size (``'1080x2400'``) and/or density; ``None``/0 keeps the current one."""
+    return _call_device_config(
+        'lb_set_display',
+        serial,
+        size.encode('utf-8') if size is not None else None,
+        density,
+    )
+
+
+def reset_display(serial: str) -> Dict[str, Any]:
+    """Clear display size and density overrides."""
+    return _call_device_config('lb_reset_display', serial)
+
+
+def set_dark_mode(serial: str, enabled: bool) -> Dict[str, Any]:
+    """Force the dark or light theme (Android 10+)."""
+    return _call_device_config('lb_set_dark_mode', serial, 1 if enabled else 0)
+
+
+def reset_dark_mode(serial: str) -> Dict[str, Any]:
+    """Return night mode to ``auto``."""
+    return _call_device_config('lb_reset_dark_mode', serial)
+
+
+def set_locale(serial: str, tag: str) -> Dict[str, Any]:
+    """Switch the system locale; needs the ADB Change Language helper or root."""
+    return _call_device_config('lb_set_locale', serial, tag.encode('utf-8'))
+
+
+def reset_locale(serial: str) -> Dict[str, Any]:
+    """Switch back to the build's default locale."""
+    return _call_device_config('lb_reset_locale', serial)
+
+
+def set_permissions(serial: str, package: str, changes: List[Dict[str, Any]]) -> Dict[str, Any]:
+    """Apply ``{'permission', 'granted'}`` / ``{'appop', 'mode'}`` changes and report which took effect."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_set_permissions'):
+        raise NativeBridgeError('Native library does not support permission changes')
+
+    raw_result = _read_and_free_string(
+        handle.lb_set_permissions(
+            serial.encode('utf-8'),
+            package.encode('utf-8'),
+            json.dumps(changes).encode('utf-8'),
+        )
+        or 0
+    )
+    if not raw_result:
+        error_message = _read_last_error() or f'Failed to change permissions of {package} on {serial}'
+        raise NativeBridgeError(error_message)
+    return json.loads(raw_result)
+
+
+def list_users(serial: str) -> Dict[str, Any]:
+    """Return the device's users and profiles (work profiles have ``managed_profile``)."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_list_users'):
+        raise NativeBridgeError('Native library does not support listing users')
+
+    raw_result = _read_and_free_string(handle.lb_list_users(ctypes.c_char_p(serial.encode('utf-8'))) or 0)
+    if not raw_result:
+        error_message = _read_last_error() or f'Failed to list users on {serial}'
+        raise NativeBridgeError(error_message)
+    return json.loads(raw_result)
+
+
+def _call_doze(export: str, *args: str) -> Dict[str, Any]:
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, export):
+        raise NativeBridgeError('Native library does not support doze controls')
+
+    raw_result = _read_and_free_string(getattr(handle, export)(*(arg.encode('utf-8') for arg in args)) or 0)
+    if not raw_result:
+        error_message = _read_last_error() or f'{export} failed for {args[0]}'
+        raise NativeBridgeError(error_message)
+    return json.loads(raw_result)
+
+
+def set_doze(serial: str, mode: str) -> Dict[str, Any]:
+    """Force ``deep``/``light`` idle, ``step`` the deep state machine, or turn it ``off``."""
+    return _call_doze('lb_set_doze', serial, mode)
+
+
+def set_standby_bucket(serial: str, package: str, bucket: str) -> Dict[str, Any]:
+    """Move ``package`` to an App Standby bucket (``active`` ... ``restricted``)."""
+    return _call_doze('lb_set_standby_bucket', serial, package, bucket)
+
+
+def app_backup(
+    serial: str,
+    packages: List[str],
+    local_path: str,
+    on_progress: Optional[Callable[[Dict[str, Any]], None]] = None,
+) -> Dict[str, Any]:
+    """Run ``adb backup`` for ``packages`` into ``local_path``; blocks until the user answers the device prompt.
+
+    ``on_progress`` receives ``{stage, bytes, elapsed_ms}`` with stage ``starting``,
+    ``confirmation_pending``, ``running`` or ``complete``.
+    """
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_app_backup'):
+        raise NativeBridgeError('Native library does not support app backup')
+
+    def forward(_user_data: Optional[int], payload: bytes) -> None:
+        if on_progress is not None:
+            on_progress(json.loads(payload.decode('utf-8', 'replace')))
+
+    callback = _LineCallback(forward)
+    raw_result = _read_and_free_string(
+        handle.lb_app_backup(
+            ctypes.c_char_p(serial.encode('utf-8')),
+            ctypes.c_char_p(json.dumps(packages).encode('utf-8')),
+            ctypes.c_char_p(local_path.encode('utf-8')),
+            callback,
+            None,
+        )
+        or 0
+    )
+    if not raw_result:
+        error_message = _read_last_error() or f'Failed to back up apps on {serial}'
+        raise NativeBridgeError(error_message)
+    return json.loads(raw_result)
+
+
+def app_restore(
+    serial: str,
+    local_path: str,
+    on_progress: Optional[Callable[[Dict[str, Any]], None]] = None,
+) -> Dict[str, Any]:
+    """Run ``adb restore`` for ``local_path`` with the same progress stages as :func:`app_backup`."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_app_restore'):
+        raise NativeBridgeError('Native library does not support app restore')
+
+    def forward(_user_data: Optional[int], payload: bytes) -> None:
+        if on_progress is not None:
+            on_progress(json.loads(payload.decode('utf-8', 'replace')))
+
+    callback = _LineCallback(forward)
+    raw_result = _read_and_free_string(
+        handle.lb_app_restore(
+            ctypes.c_char_p(serial.encode('utf-8')),
+            ctypes.c_char_p(local_path.encode('utf-8')),
+            callback,
+            None,
+        )
+        or 0
+    )
+    if not raw_result:
+        error_message = _read_last_error() or f'Failed to restore {local_path} on {serial}'
+        raise NativeBridgeError(error_message)
+    return json.loads(raw_result)
+
+
+def push_obb(
+    serial: str,
+    package: str,
+    obb_path: str,
+    on_progress: Optional[Callable[[Dict[str, Any]], None]] = None,
+) -> Dict[str, Any]:
+    """Push ``obb_path`` into ``/sdcard/Android/obb/<package>/`` and verify its size on the device.
+
+    ``on_progress`` receives ``{file, bytes, total, percent}``.
+    """
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_push_obb'):
+        raise NativeBridgeError('Native library does not support OBB pushes')
+
+    def forward(_user_data: Optional[int], payload: bytes) -> None:
+        if on_progress is not None:
+            on_progress(json.loads(payload.decode('utf-8', 'replace')))
+
+    callback = _LineCallback(forward)
+    raw_result = _read_and_free_string(
+        handle.lb_push_obb(
+            ctypes.c_char_p(serial.encode('utf-8')),
+            ctypes.c_char_p(package.encode('utf-8')),
+            ctypes.c_char_p(obb_path.encode('utf-8')),
+            callback,
+            None,
+        )
+        or 0
+    )
+    if not raw_result:
+        error_message = _read_last_error() or f'Failed to push {obb_path} to {serial}'
+        raise NativeBridgeError(error_message)
+    return json.loads(raw_result)
+
+
+def emulator_console(serial: str, command: str) -> Dict[str, Any]:
+    """Send one raw command to the console of emulator ``serial`` and return its output."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_emulator_console'):
+        raise NativeBridgeError('Native library does not support the emulator console')
+    raw_result = _read_and_free_string(
+        handle.lb_emulator_console(
+            ctypes.c_char_p(serial.encode('utf-8')),
+            ctypes.c_char_p(command.encode('utf-8')),
+        )
+        or 0
+    )
+    if not raw_result:
+        error_message = _read_last_error() or f'Emulator console command failed on {serial}'
+        raise NativeBridgeError(error_message)
+    return json.loads(raw_result)
+
+
+def _call_emulator(export: str, serial: str, *args: Any) -> None:
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, export):
+        raise NativeBridgeError('Native library does not support emulator controls')
+    encoded = [arg.encode('utf-8') if isinstance(arg, str) else arg for arg in args]
+    if not getattr(handle, export)(ctypes.c_char_p(serial.encode('utf-8')), *encoded):
+        error_message = _read_last_error() or f'Emulator console command failed on {serial}'
+        raise NativeBridgeError(error_message)
+
+
+def emulator_geo_fix(serial: str, latitude: float, longitude: float, altitude: float = 0.0) -> None:
+    """Move the emulated GPS to ``latitude``/``longitude`` (degrees) at ``altitude`` metres."""
+    _call_emulator('lb_emulator_geo_fix', serial, latitude, longitude, altitude)
+
+
+def emulator_sms(serial: str, phone: str, text: str) -> None:
+    """Deliver an incoming SMS from ``phone``."""
+    _call_emulator('lb_emulator_sms', serial, phone, text)
+
+
+def emulator_call(serial: str, phone: str, action: str) -> None:
+    """Simulate a call: ``incoming``, ``accept``, ``busy``, ``hold`` or ``hangup``."""
+    _call_emulator('lb_emulator_call', serial, phone, action)
+
+
+def emulator_battery(serial: str, level: int, charging: Optional[bool] = None) -> None:
+    """Set the emulated battery level; ``charging=None`` leaves the charger state alone."""
+    _call_emulator('lb_emulator_battery', serial, level, -1 if charging is None else int(charging))
+
+
+def emulator_network(serial: str, speed: Optional[str] = None, delay: Optional[str] = None) -> None:
+    """Shape the emulated network by profile name (``edge``, ``lte``...) or ``<n>[:<m>]``."""
+    _call_emulator('lb_emulator_network', serial, speed, delay)
+
+
+def list_avds() -> Dict[str, Any]:
+    """List AVDs known to the SDK emulator and which of them are running."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_list_avds'):
+        raise NativeBridgeError('Native library does not support AVD management')
+    raw_result = _read_and_free_string(handle.lb_list_avds() or 0)
+    if not raw_result:
+        error_message = _read_last_error() or 'Failed to list AVDs'
+        raise NativeBridgeError(error_message)
+    return json.loads(raw_result)
+
+
+def start_avd(name: str, options: Optional[Dict[str, Any]] = None) -> Dict[str, Any]:
+    """Start AVD ``name`` and, unless ``options['wait_boot']`` is false, wait for it to boot."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_start_avd'):
+        raise NativeBridgeError('Native library does not support AVD management')
+    raw_result = _read_and_free_string(
+        handle.lb_start_avd(
+            ctypes.c_char_p(name.encode('utf-8')),
+            ctypes.c_char_p(json.dumps(options).encode('utf-8')) if options else None,
+        )
+        or 0
+    )
+    if not raw_result:
+        error_message = _read_last_error() or f'Failed to start AVD {name}'
+        raise NativeBridgeError(error_message)
+    return json.loads(raw_result)
+
+
+def stop_avd(serial: str) -> None:
+    """Stop emulator ``serial`` through its console."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_stop_avd'):
+        raise NativeBridgeError('Native library does not support AVD management')
+    if not handle.lb_stop_avd(ctypes.c_char_p(serial.encode('utf-8'))):
+        error_message = _read_last_error() or f'Failed to stop {serial}'
+        raise NativeBridgeError(error_message)
+
+
+def schedule_command(
+    serial: str,
+    command: str,
+    schedule: str,
+    on_result: Callable[[Dict[str, Any]], None],
+) -> int:
+    """Run ``adb shell command`` on ``schedule`` (milliseconds or a UTC cron expression).
+
+    ``on_result`` is called from a native thread after every run. Returns the schedule id.
+    """
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_schedule_command'):
+        raise NativeBridgeError('Native library does not support scheduled commands')
+
+    def forward(_user_data: Optional[int], payload: bytes) -> None:
+        on_result(json.loads(payload.decode('utf-8', 'replace')))
+
+    callback = _LineCallback(forward)
+    schedule_id = handle.lb_schedule_command(
+        ctypes.c_char_p(serial.encode('utf-8')),
+        ctypes.c_char_p(command.encode('utf-8')),
+        ctypes.c_char_p(str(schedule).encode('utf-8')),
+        callback,
+        None,
+    )
+    if not schedule_id:
+        error_message = _read_last_error() or f'Failed to schedule {command!r} on {serial}'
+        raise NativeBridgeError(error_message)
+    _SCHEDULE_CALLBACKS[schedule_id] = callback
+    return schedule_id
+
+
+def schedule_list() -> List[Dict[str, Any]]:
+    """Active schedules with run counts and next/last run times."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_schedule_list'):
+        raise NativeBridgeError('Native library does not support scheduled commands')
+    raw_result = _read_and_free_string(handle.lb_schedule_list() or 0)
+    if not raw_result:
+        error_message = _read_last_error() or 'Failed to list schedules'
+        raise NativeBridgeError(error_message)
+    return json.loads(raw_result)
+
+
+def schedule_cancel(schedule_id: int) -> None:
+    """Stop a schedule started by :func:`schedule_command`."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_schedule_cancel'):
+        raise NativeBridgeError('Native library does not support scheduled commands')
+    if not handle.lb_schedule_cancel(ctypes.c_uint64(schedule_id)):
+        error_message = _read_last_error() or f'Failed to cancel schedule {schedule_id}'
+        raise NativeBridgeError(error_message)
+    # No callback is made once cancel returns, so the reference can go.
+    _SCHEDULE_CALLBACKS.pop(schedule_id, None)
+
+
+def history_query(filters: Optional[Dict[str, Any]] = None) -> Dict[str, Any]:
+    """Search recorded commands, newest first.
+
+    ``filters`` may hold ``serial``, ``contains``, ``since_ms``, ``until_ms``, ``exit_code``,
+    ``failed`` and ``limit`` (default 100).
+    """
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_history_query'):
+        raise NativeBridgeError('Native library does not support command history')
+    raw_result = _read_and_free_string(
+        handle.lb_history_query(ctypes.c_char_p(json.dumps(filters).encode('utf-8')) if filters else None) or 0
+    )
+    if not raw_result:
+        error_message = _read_last_error() or 'Failed to query command history'
+        raise NativeBridgeError(error_message)
+    return json.loads(raw_result)
+
+
+def set_history_path(path: Optional[str]) -> None:
+    """Record command history in ``path``; ``None`` restores ``~/.lazy_blacktea_history.jsonl``."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_set_history_path'):
+        raise NativeBridgeError('Native library does not support command history')
+    if not handle.lb_set_history_path(ctypes.c_char_p(path.encode('utf-8')) if path is not None else None):
+        error_message = _read_last_error() or f'Failed to set history path to {path}'
+        raise NativeBridgeError(error_message)
+
+
+def set_history_enabled(enabled: bool) -> None:
+    """Turn command history recording on or off (off by default; secrets are redacted)."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_set_history_enabled'):
+        raise NativeBridgeError('Native library does not support command history')
+    handle.lb_set_history_enabled(1 if enabled else 0)
+
+
+def history_clear() -> None:
+    """Delete all recorded command history."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_history_clear'):
+        raise NativeBridgeError('Native library does not support command history')
+    if not handle.lb_history_clear():
+        error_message = _read_last_error() or 'Failed to clear command history'
+        raise NativeBridgeError(error_message)
+
+
+def device_meta_set(serial: str, meta: Optional[Dict[str, Any]]) -> Dict[str, Any]:
+    """Update ``alias``/``notes``/``tags``/``groups`` for ``serial``; ``None`` forgets the device."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_device_meta_set'):
+        raise NativeBridgeError('Native library does not support device metadata')
+    raw_result = _read_and_free_string(
+        handle.lb_device_meta_set(
+            ctypes.c_char_p(serial.encode('utf-8')),
+            ctypes.c_char_p(json.dumps(meta).encode('utf-8')) if meta is not None else None,
+        )
+        or 0
+    )
+    if not raw_result:
+        error_message = _read_last_error() or f'Failed to save metadata for {serial}'
+        raise NativeBridgeError(error_message)
+    return json.loads(raw_result)
+
+
+def device_meta_get(serial: str) -> Dict[str, Any]:
+    """Stored metadata for ``serial``, with empty defaults for unknown devices."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_device_meta_get'):
+        raise NativeBridgeError('Native library does not support device metadata')
+    raw_result = _read_and_free_string(handle.lb_device_meta_get(ctypes.c_char_p(serial.encode('utf-8'))) or 0)
+    if not raw_result:
+        error_message = _read_last_error() or f'Failed to read metadata for {serial}'
+        raise NativeBridgeError(error_message)
+    return json.loads(raw_result)
+
+
+def device_meta_list() -> List[Dict[str, Any]]:
+    """Every device with stored metadata, connected or not."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_device_meta_list'):
+        raise NativeBridgeError('Native library does not support device metadata')
+    raw_result = _read_and_free_string(handle.lb_device_meta_list() or 0)
+    if not raw_result:
+        error_message = _read_last_error() or 'Failed to list device metadata'
+        raise NativeBridgeError(error_message)
+    return json.loads(raw_result)
+
+
+def list_devices_json() -> List[Dict[str, Any]]:
+    """``adb devices -l`` with aliases, notes, tags and groups joined in."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_list_devices_json'):
+        raise NativeBridgeError('Native library does not support device metadata')
+    raw_result = _read_and_free_string(handle.lb_list_devices_json() or 0)
+    if not raw_result:
+        error_message = _read_last_error() or 'Failed to list devices'
+        raise NativeBridgeError(error_message)
+    return json.loads(raw_result)
+
+
+def set_device_meta_path(path: Optional[str]) -> None:
+    """Store device metadata in ``path``; ``None`` restores ``~/.lazy_blacktea_devices.json``."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_set_device_meta_path'):
+        raise NativeBridgeError('Native library does not support device metadata')
+    if not handle.lb_set_device_meta_path(ctypes.c_char_p(path.encode('utf-8')) if path is not None else None):
+        error_message = _read_last_error() or f'Failed to set device metadata path to {path}'
+        raise NativeBridgeError(error_message)
+
+
+def _call_group(export: str, group: str, *args: Optional[str]) -> Dict[str, Any]:
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, export):
+        raise NativeBridgeError('Native library does not support device group operations')
+    raw_result = _read_and_free_string(
+        getattr(handle, export)(
+            ctypes.c_char_p(group.encode('utf-8')),
+            *(ctypes.c_char_p(arg.encode('utf-8')) if arg is not None else None for arg in args),
+        )
+        or 0
+    )
+    if not raw_result:
+        error_message = _read_last_error() or f'Failed to run {export} on group {group}'
+        raise NativeBridgeError(error_message)
+    return json.loads(raw_result)
+
+
+def run_on_group(group: str, command_template: str) -> Dict[str, Any]:
+    """Run a command on every connected device in ``group``.
+
+    Returns ``{group, succeeded, failed, skipped, results}``; ``results`` is keyed by serial.
+    """
+    return _call_group('lb_run_on_group', group, command_template)
+
+
+def install_on_group(group: str, apk_path: str, options: Optional[Dict[str, Any]] = None) -> Dict[str, Any]:
+    """Install ``apk_path`` on every connected device in ``group``."""
+    return _call_group('lb_install_on_group', group, apk_path, json.dumps(options) if options else None)
+
+
+def capture_group(group: str, what: str = 'screenshot') -> Dict[str, Any]:
+    """Capture ``screenshot`` and/or ``hierarchy`` on every connected device in ``group`` at once."""
+    return _call_group('lb_capture_group', group, what)
+
+
+def start_group_recording(group: str, remote_path: str) -> Dict[str, Any]:
+    """Start ``screenrecord remote_path`` on every connected device in ``group``."""
+    return _call_group('lb_start_group_recording', group, remote_path)
+
+
+def stop_group_recording(group: str) -> Dict[str, Any]:
+    """Stop the recordings started by :func:`start_group_recording`."""
+    return _call_group('lb_stop_group_recording', group)
+
+
+def invoke(
+    method: str,
+    params: Optional[Dict[str, Any]] = None,
+    version: Optional[int] = None,
+    cancel_token: Optional[int] = None,
+    timeout_ms: Optional[int] = None,
+) -> Any:
+    """Call any native method by name through ``lb_invoke``.
+
+    ``method`` is the export name without ``lb_``; ``params`` maps argument names to JSON
+    values. Returns the method's ``result`` and raises :class:`NativeBridgeError` with the
+    native error message on failure. ``invoke('rpc.describe')`` lists every method.
+    ``cancel_token`` (from :func:`cancel_token_new`) and ``timeout_ms`` bound the call.
+    Uses the ``lazy_blacktea_native`` extension when it is importable and neither is given.
+    """
+    if _native_ext is not None and cancel_token is None and timeout_ms is None:
+        try:
+            return _native_ext.invoke(method, params or {}, version)
+        except _native_ext.NativeError as exc:
+            raise NativeBridgeError(str(exc)) from exc
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_invoke'):
+        raise NativeBridgeError('Native library does not support lb_invoke')
+    request: Dict[str, Any] = {'method': method, 'params': params or {}}
+    if version is not None:
+        request['version'] = version
+    if cancel_token is not None:
+        request['cancel_token'] = cancel_token
+    if timeout_ms is not None:
+        request['timeout_ms'] = timeout_ms
+    raw_result = _read_and_free_string(handle.lb_invoke(json.dumps(request).encode('utf-8')) or 0)
+    if not raw_result:
+        raise NativeBridgeError(_read_last_error() or f'Failed to invoke {method}')
+    response = json.loads(raw_result)
+    if 'error' in response:
+        raise NativeBridgeError(response['error'].get('message') or f'Failed to invoke {method}')
+    return response.get('result')
+
+
+def _cancel_library() -> ctypes.CDLL:
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_cancel_token_new'):
+        raise NativeBridgeError('Native library does not support cancellation')
+    return handle
+
+
+def cancel_token_new(timeout_ms: int = 0) -> int:
+    """Create a cancel token that also fires after ``timeout_ms`` (0 = never); returns its handle."""
+    handle = _cancel_library()
+    raw_result = _read_and_free_string(handle.lb_cancel_token_new(ctypes.c_uint64(timeout_ms)) or 0)
+    if not raw_result:
+        raise NativeBridgeError(_read_last_error() or 'Failed to create cancel token')
+    return int(json.loads(raw_result)['handle'])
+
+
+def _cancel_call(name: str, token: int) -> None:
+    handle = _cancel_library()
+    if not getattr(handle, name)(ctypes.c_uint64(token)):
+        raise NativeBridgeError(_read_last_error() or f'{name} failed for token {token}')
+
+
+def cancel_token_cancel(token: int) -> None:
+    """Fire ``token``: adb commands running under it are killed and raise Cancelled."""
+    _cancel_call('lb_cancel_token_cancel', token)
+
+
+def cancel_token_free(token: int) -> None:
+    """Forget ``token``; calls already running under it are unaffected."""
+    _cancel_call('lb_cancel_token_free', token)
+
+
+def set_thread_cancel_token(token: int) -> None:
+    """Run later native calls on this thread under ``token`` (0 restores the default)."""
+    _cancel_call('lb_set_thread_cancel_token', token)
+
+
+def reconcile_recordings(serials: List[str], action: Optional[str] = None) -> Dict[str, Any]:
+    """Find screenrecord processes left on devices by an earlier host process.
+
+    ``serials`` empty checks every connected device. ``action`` is ``'report'`` (default),
+    ``'adopt'`` or ``'stop'``. Returns a report per serial with the orphans and their files.
+    """
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_reconcile_recordings'):
+        raise NativeBridgeError('Native library does not support recording reconciliation')
+    raw_result = _read_and_free_string(
+        handle.lb_reconcile_recordings(
+            json.dumps(serials).encode('utf-8'),
+            action.encode('utf-8') if action is not None else None,
+        )
+        or 0
+    )
+    if not raw_result:
+        raise NativeBridgeError(_read_last_error() or 'Failed to reconcile recordings')
+    return json.loads(raw_result)
+
+
+def shutdown(deadline_ms: int = 0) -> Dict[str, Any]:
+    """Stop every recording, monkey run, stream, shell session, monitor and in-flight call before the host exits.
+
+    Children get SIGINT and up to ``deadline_ms`` (0 = 5000) to exit before being killed.
+    Returns ``{'recordings', 'monkeys', 'processes', 'force_killed', 'shell_sessions', 'monitors',
+    'schedules', 'duration_ms'}``.
+    """
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_shutdown'):
+        raise NativeBridgeError('Native library does not support shutdown')
+    raw_result = _read_and_free_string(handle.lb_shutdown(ctypes.c_uint64(deadline_ms)) or 0)
+    if not raw_result:
+        raise NativeBridgeError(_read_last_error() or 'Native shutdown failed')
+    return json.loads(raw_result)
+
+
+def server_start(bind_addr: str, auth_token: str) -> Dict[str, Any]:
+    """Serve the native API over HTTP and WebSocket on ``bind_addr``.
+
+    Every request must carry ``auth_token`` (``Authorization: Bearer`` or ``?token=``).
+    Returns ``{'handle', 'address'}``; ``address`` has the real port when ``bind_addr``
+    asks for port 0.
+    """
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_server_start'):
+        raise NativeBridgeError('Native library does not support the control server')
+    raw_result = _read_and_free_string(
+        handle.lb_server_start(bind_addr.encode('utf-8'), auth_token.encode('utf-8')) or 0
+    )
+    if not raw_result:
+        error_message = _read_last_error() or f'Failed to start control server on {bind_addr}'
+        raise NativeBridgeError(error_message)
+    return json.loads(raw_result)
+
+
+def server_stop(server_handle: int) -> None:
+    """Stop a server started by :func:`server_start`, closing its open connections."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_server_stop'):
+        raise NativeBridgeError('Native library does not support the control server')
+    if not handle.lb_server_stop(ctypes.c_uint64(server_handle)):
+        error_message = _read_last_error() or f'Failed to stop control server {server_handle}'
+        raise NativeBridgeError(error_message)
+
+
+def grpc_start(options: Dict[str, Any]) -> Dict[str, Any]:
+    """Serve the gRPC device farm API; needs a library built with ``--features grpc``.
+
+    ``options`` holds ``bind_addr`` and ``auth_token``, plus ``tls_cert_path`` and
+    ``tls_key_path`` (PEM) for TLS. Returns ``{'handle', 'address', 'tls'}``.
+    """
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_grpc_start'):
+        raise NativeBridgeError('Native library was built without the grpc feature')
+    raw_result = _read_and_free_string(handle.lb_grpc_start(json.dumps(options).encode('utf-8')) or 0)
+    if not raw_result:
+        error_message = _read_last_error() or 'Failed to start gRPC server'
+        raise NativeBridgeError(error_message)
+    return json.loads(raw_result)
+
+
+def grpc_stop(server_handle: int) -> None:
+    """Stop a server started by :func:`grpc_start`."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_grpc_stop'):
+        raise NativeBridgeError('Native library was built without the grpc feature')
+    if not handle.lb_grpc_stop(ctypes.c_uint64(server_handle)):
+        error_message = _read_last_error() or f'Failed to stop gRPC server {server_handle}'
+        raise NativeBridgeError(error_message)
+
+
+def run_script(
+    script: Union[str, Dict[str, Any]],
+    on_step: Optional[Callable[[Dict[str, Any]], None]] = None,
+) -> Dict[str, Any]:
+    """Run a device script (a dict, JSON text, or YAML text) and return its per-step results.
+
+    ``on_step`` receives each step result as it finishes. A failed step is reported in the
+    result (``success`` false, ``stopped_at``); only an invalid script raises.
+    """
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_run_script'):
+        raise NativeBridgeError('Native library does not support scripts')
+
+    def forward(_user_data: Optional[int], payload: bytes) -> None:
+        if on_step is not None:
+            on_step(json.loads(payload.decode('utf-8', 'replace')))
+
+    source = script if isinstance(script, str) else json.dumps(script)
+    callback = _LineCallback(forward)
+    raw_result = _read_and_free_string(handle.lb_run_script(source.encode('utf-8'), callback, None) or 0)
+    if not raw_result:
+        error_message = _read_last_error() or 'Failed to run script'
+        raise NativeBridgeError(error_message)
+    return json.loads(raw_result)
+
+
+def get_clipboard(serial: str) -> Optional[str]:
+    """Return the device's clipboard text, or ``None`` when it is empty."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_get_clipboard'):
+        raise NativeBridgeError('Native library does not support clipboard access')
+
+    raw_result = _read_and_free_string(handle.lb_get_clipboard(ctypes.c_char_p(serial.encode('utf-8'))) or 0)
+    if not raw_result:
+        error_message = _read_last_error() or f'Failed to read clipboard for {serial}'
+        raise NativeBridgeError(error_message)
+    return json.loads(raw_result).get('text')
+
+
+def set_clipboard(serial: str, text: str) -> None:
+    """Replace the device's clipboard text; quoting is handled natively."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_set_clipboard'):
+        raise NativeBridgeError('Native library does not support clipboard access')
+
+    result = handle.lb_set_clipboard(ctypes.c_char_p(serial.encode('utf-8')), ctypes.c_char_p(text.encode('utf-8')))
+    if result != 1:
+        error_message = _read_last_error() or f'Failed to set clipboard for {serial}'
+        raise NativeBridgeError(error_message)
+
+
+def _call_wireless(export: str, *args: str) -> Any:
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, export):
+        raise NativeBridgeError('Native library does not support wireless adb')
+
+    encoded = [ctypes.c_char_p(arg.encode('utf-8')) for arg in args]
+    raw_result = _read_and_free_string(getattr(handle, export)(*encoded) or 0)
+    if not raw_result:
+        error_message = _read_last_error() or f'{export} failed'
+        raise NativeBridgeError(error_message)
+    return json.loads(raw_result)
+
+
+def adb_pair(host_port: str, pairing_code: str) -> Dict[str, Any]:
+    """Pair with a device's wireless debugging endpoint; check ``success`` and ``reason``."""
+    return _call_wireless('lb_adb_pair', host_port, pairing_code)
+
+
+def adb_connect(host_port: str) -> Dict[str, Any]:
+    """Connect to a wireless device; successful endpoints are remembered for reconnects."""
+    return _call_wireless('lb_adb_connect', host_port)
+
+
+def adb_disconnect(host_port: str) -> Dict[str, Any]:
+    """Disconnect a wireless device."""
+    return _call_wireless('lb_adb_disconnect', host_port)
+
+
+def wireless_endpoints() -> List[Dict[str, Any]]:
+    """Previously connected ``host:port`` endpoints, most recent first."""
+    return _call_wireless('lb_wireless_endpoints')
+
+
+def reboot(serial: str, target: str = 'system') -> None:
+    """Reboot into ``system``, ``recovery``, ``bootloader`` or ``sideload``; returns once the device drops off."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_reboot'):
+        raise NativeBridgeError('Native library does not support reboots')
+
+    result = handle.lb_reboot(ctypes.c_char_p(serial.encode('utf-8')), ctypes.c_char_p(target.encode('utf-8')))
+    if result != 1:
+        error_message = _read_last_error() or f'Failed to reboot {serial}'
+        raise NativeBridgeError(error_message)
+
+
+def wait_for_state(serial: str, state: str, timeout_ms: int) -> None:
+    """Block until the device reaches ``state`` (e.g. ``booted``); raises on timeout."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_wait_for_state'):
+        raise NativeBridgeError('Native library does not support state waits')
+
+    result = handle.lb_wait_for_state(
+        ctypes.c_char_p(serial.encode('utf-8')),
+        ctypes.c_char_p(state.encode('utf-8')),
+        ctypes.c_uint64(timeout_ms),
+    )
+    if result != 1:
+        error_message = _read_last_error() or f'{serial} did not reach {state}'
+        raise NativeBridgeError(error_message)
+
+
+def wait_for_boot(serial: str, timeout_ms: int, wait_launcher: bool = False) -> Dict[str, Any]:
+    """Block until the device is booted and usable; returns the ms at which each stage was reached."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_wait_for_boot'):
+        raise NativeBridgeError('Native library does not support boot readiness waits')
+    raw_result = _read_and_free_string(
+        handle.lb_wait_for_boot(
+            ctypes.c_char_p(serial.encode('utf-8')),
+            ctypes.c_uint64(timeout_ms),
+            1 if wait_launcher else 0,
+        )
+        or 0
+    )
+    if not raw_result:
+        error_message = _read_last_error() or f'{serial} did not finish booting'
+        raise NativeBridgeError(error_message)
+    return json.loads(raw_result)
+
+
+def adb_root(serial: str, root: bool = True) -> None:
+    """Restart adbd as root (or unprivileged) and wait until the device confirms the uid."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_adb_root'):
+        raise NativeBridgeError('Native library does not support adb root')
+
+    export = handle.lb_adb_root if root else handle.lb_adb_unroot
+    if export(ctypes.c_char_p(serial.encode('utf-8'))) != 1:
+        error_message = _read_last_error() or f'Failed to switch adbd on {serial}'
+        raise NativeBridgeError(error_message)
+
+
+def remount(serial: str) -> Dict[str, Any]:
+    """Root adbd and remount partitions; check ``remounted`` and ``reason`` in the result."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_remount'):
+        raise NativeBridgeError('Native library does not support remount')
+
+    raw_result = _read_and_free_string(handle.lb_remount(ctypes.c_char_p(serial.encode('utf-8'))) or 0)
+    if not raw_result:
+        error_message = _read_last_error() or f'Failed to remount {serial}'
+        raise NativeBridgeError(error_message)
+    return json.loads(raw_result)
+
+
+def file_checksum(serial: str, path: str, algorithm: str = 'md5') -> str:
+    """Return the lowercase hex ``md5`` or ``sha256`` digest of a device file."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_file_checksum'):
+        raise NativeBridgeError('Native library does not support file checksums')
+
+    raw_result = _read_and_free_string(
+        handle.lb_file_checksum(
+            ctypes.c_char_p(serial.encode('utf-8')),
+            ctypes.c_char_p(path.encode('utf-8')),
+            ctypes.c_char_p(algorithm.encode('utf-8')),
+        )
+        or 0
+    )
+    if not raw_result:
+        error_message = _read_last_error() or f'Failed to checksum {path} on {serial}'
+        raise NativeBridgeError(error_message)
+    return json.loads(raw_result)['checksum']
+
+
+def screenshot_thumbnail(serial: str, max_edge_px: int = 160, user: Optional[int] = None) -> bytes:
+    """Return a PNG screenshot of the device scaled so its longer edge is at most ``max_edge_px``.
+
+    With ``user`` the capture fails unless that user (or work profile) is the one on screen.
+    """
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    export = 'lb_screenshot_thumbnail' if user is None else 'lb_screenshot_thumbnail_for_user'
+    if not hasattr(handle, export):
+        raise NativeBridgeError('Native library does not support screenshot thumbnails')
+
+    if user is None:
+        result = handle.lb_screenshot_thumbnail(ctypes.c_char_p(serial.encode('utf-8')), max_edge_px)
+    else:
+        result = handle.lb_screenshot_thumbnail_for_user(ctypes.c_char_p(serial.encode('utf-8')), max_edge_px, user)
+    if not result.ptr:
+        error_message = _read_last_error() or f'Failed to capture a thumbnail from {serial}'
+        raise NativeBridgeError(error_message)
+    try:
+        return ctypes.string_at(result.ptr, result.len)
+    finally:
+        handle.lb_free_result(result)
+
+
+def _split_parallel_result(raw_result: str, command_count: int) -> List[List[str]]:
+    if not raw_result:
+        return [[] for _ in range(command_count)]
+
+    command_chunks = raw_result.split(_RECORD_SEPARATOR)
+    results: List[List[str]] = []
+    for chunk in command_chunks:
+        if chunk == '':
+            results.append([])
+            continue
+        lines = chunk.split(_LINE_SEPARATOR)
+        results.append(lines)
+    return results
+
+
+def start_screen_record(serial: str, remote_path: str) -> None:
+    """Start an adb screenrecord session using the native helper."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+
+    serial_bytes = serial.encode('utf-8')
+    remote_bytes = remote_path.encode('utf-8')
+    result = handle.lb_start_screen_record(ctypes.c_char_p(serial_bytes), ctypes.c_char_p(remote_bytes))
+    if result != 1:
+        error_message = _read_last_error() or f'Failed to start screenrecord for {serial}'
+        raise NativeBridgeError(error_message)
+
+
+def stop_screen_record(serial: str) -> None:
+    """Stop an adb screenrecord session using the native helper."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+
+    serial_bytes = serial.encode('utf-8')
+    result = handle.lb_stop_screen_record(ctypes.c_char_p(serial_bytes))
+    if result != 1:
+        error_message = _read_last_error() or f'Failed to stop screenrecord for {serial}'
+        raise NativeBridgeError(error_message)
+
+
+
+def list_recordings() -> List[Dict[str, Any]]:
+    """Tracked screen recordings; ``state`` is ``'ended'`` for ones that stopped on their own."""
+    handle = _load_library()
+    if handle is None:
+        raise NativeBridgeError('Native library not available')
+    if not hasattr(handle, 'lb_list_recordings'):
+        raise NativeBridgeError('Native library does not support listing recordings')
+    raw_result = _read_and_free_string(handle.lb_list_recordings() or 0)
+    if not raw_result:
+        raise NativeBridgeError(_read_last_error() or 'Failed to list recordings')
+    return json.loads(raw_result)
+
+__all__ = ['NativeBridgeError', 'capabilities', 'is_available', 'render_device_ui_html', 'run_argv_parallel', 'run_commands_parallel', 'run_commands_structured', 'start_screen_record', 'stop_screen_record']